@@ -0,0 +1,50 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use wavebreaker::util::game_types::{
+    parse_separated_i32, split_x_separated, MAX_TRACK_SHAPE_ENTRIES, MAX_XSTATS_ENTRIES,
+};
+
+/// A track shape about as long as a long song's.
+fn track_shape() -> String {
+    (0..5000).map(|i| format!("{}x", (i * 37) % 1000)).collect()
+}
+
+fn xstats() -> String {
+    (0..24)
+        .map(|i| (i * 1234).to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn bench_track_shape(c: &mut Criterion) {
+    let track_shape = track_shape();
+
+    let mut group = c.benchmark_group("track_shape");
+    group.bench_function("split_x_separated", |b| {
+        b.iter(|| split_x_separated::<i32>(black_box(&track_shape)));
+    });
+    group.bench_function("parse_separated_i32", |b| {
+        b.iter(|| parse_separated_i32(black_box(&track_shape), b'x', MAX_TRACK_SHAPE_ENTRIES));
+    });
+    group.finish();
+}
+
+fn bench_xstats(c: &mut Criterion) {
+    let xstats = xstats();
+
+    let mut group = c.benchmark_group("xstats");
+    group.bench_function("split_collect", |b| {
+        b.iter(|| {
+            black_box(&xstats)
+                .split(',')
+                .map(str::parse::<i32>)
+                .collect::<Result<Vec<_>, _>>()
+        });
+    });
+    group.bench_function("parse_separated_i32", |b| {
+        b.iter(|| parse_separated_i32(black_box(&xstats), b',', MAX_XSTATS_ENTRIES));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_track_shape, bench_xstats);
+criterion_main!(benches);
@@ -0,0 +1,405 @@
+//! Benchmarks for the database side of the score pipeline.
+//!
+//! These run against a real Postgres and Redis, which get seeded with a few thousand players and scores first.
+//! **The seeding wipes the players, songs and scores tables and the skill point leaderboard,
+//! so never point this at a database you care about!**
+//!
+//! Set `WAVEBREAKER_BENCH_DATABASE` and `WAVEBREAKER_BENCH_REDIS` to the URLs of throwaway instances to run them,
+//! they're skipped otherwise. Parsing is covered by the `parsing` benchmarks, which don't need either.
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use deadpool_redis::Runtime as RedisRuntime;
+use diesel::prelude::*;
+use diesel_async::{
+    async_connection_wrapper::AsyncConnectionWrapper,
+    pooled_connection::{deadpool::Pool, AsyncDieselConnectionManager},
+    AsyncPgConnection, RunQueryDsl,
+};
+use steam_rs::steam_id::SteamId;
+use tokio::runtime::Runtime;
+use wavebreaker::{
+    models::{
+        extra_song_info::NewExtraSongInfo,
+        players::NewPlayer,
+        scores::{NewScore, Score},
+        songs::NewSong,
+    },
+    run_migrations,
+    schema::{extra_song_info, players, scores, song_aliases, songs},
+    util::{
+        game_types::{Character, League},
+        realm::MAIN_REALM,
+        redis_keys::SKILL_POINTS,
+    },
+};
+
+const PLAYER_COUNT: i32 = 2000;
+const SONG_COUNT: i32 = 500;
+/// How many songs every player has a score on.
+const SCORES_PER_PLAYER: i32 = 25;
+/// The song everyone has played, for the leaderboard benchmarks.
+const POPULAR_SONG: i32 = 1;
+
+struct BenchEnv {
+    rt: Runtime,
+    db: Pool<AsyncPgConnection>,
+    redis: deadpool_redis::Pool,
+}
+
+fn setup() -> Option<BenchEnv> {
+    let (Ok(db_url), Ok(redis_url)) = (
+        std::env::var("WAVEBREAKER_BENCH_DATABASE"),
+        std::env::var("WAVEBREAKER_BENCH_REDIS"),
+    ) else {
+        eprintln!(
+            "WAVEBREAKER_BENCH_DATABASE or WAVEBREAKER_BENCH_REDIS not set, skipping score pipeline benchmarks"
+        );
+        return None;
+    };
+
+    let rt = Runtime::new().expect("Failed to start Tokio runtime");
+
+    let migration_url = db_url.clone();
+    rt.block_on(tokio::task::spawn_blocking(move || {
+        let mut conn = AsyncConnectionWrapper::<AsyncPgConnection>::establish(&migration_url)
+            .expect("Failed to establish DB connection for migrations");
+        run_migrations(&mut conn).expect("Failed to run migrations");
+    }))
+    .expect("Migration task panicked");
+
+    let db = Pool::builder(AsyncDieselConnectionManager::<AsyncPgConnection>::new(
+        db_url,
+    ))
+    .build()
+    .expect("Failed to build DB pool");
+    let redis = deadpool_redis::Config::from_url(redis_url)
+        .create_pool(Some(RedisRuntime::Tokio1))
+        .expect("Failed to build Redis pool");
+
+    rt.block_on(seed(&db, &redis));
+
+    Some(BenchEnv { rt, db, redis })
+}
+
+fn song_title(n: i32) -> String {
+    format!("song {n}")
+}
+
+fn song_artist(n: i32) -> String {
+    format!("artist {}", n % 100)
+}
+
+/// Fills the database with players, songs (some with aliases) and their scores,
+/// roughly in the shape of a live instance.
+async fn seed(db: &Pool<AsyncPgConnection>, redis: &deadpool_redis::Pool) {
+    let mut conn = db.get().await.expect("Failed to get DB connection");
+    let mut redis_conn = redis.get().await.expect("Failed to get Redis connection");
+
+    diesel::sql_query("TRUNCATE players, songs RESTART IDENTITY CASCADE")
+        .execute(&mut conn)
+        .await
+        .expect("Failed to clear tables");
+    redis::cmd("DEL")
+        .arg(SKILL_POINTS)
+        .query_async::<()>(&mut redis_conn)
+        .await
+        .expect("Failed to clear skill points");
+
+    let usernames: Vec<String> = (1..=PLAYER_COUNT).map(|n| format!("player {n}")).collect();
+    let new_players: Vec<NewPlayer> = usernames
+        .iter()
+        .zip(1..)
+        .map(|(username, n)| {
+            NewPlayer::new(
+                username,
+                SteamId::from(76_561_197_960_265_728 + n as u64),
+                n,
+                "https://example.com/avatar.png",
+            )
+        })
+        .collect();
+    diesel::insert_into(players::table)
+        .values(&new_players)
+        .execute(&mut conn)
+        .await
+        .expect("Failed to seed players");
+
+    let titles: Vec<String> = (1..=SONG_COUNT).map(song_title).collect();
+    let artists: Vec<String> = (1..=SONG_COUNT).map(song_artist).collect();
+    let new_songs: Vec<NewSong> = titles
+        .iter()
+        .zip(&artists)
+        .map(|(title, artist)| NewSong::new(title, artist, None, MAIN_REALM))
+        .collect();
+    diesel::insert_into(songs::table)
+        .values(&new_songs)
+        .execute(&mut conn)
+        .await
+        .expect("Failed to seed songs");
+
+    // Every other song has MusicBrainz data and aliases, so the lookups have to go through them
+    let extra_infos: Vec<NewExtraSongInfo> = (1..=SONG_COUNT)
+        .step_by(2)
+        .map(|n| NewExtraSongInfo {
+            song_id: n,
+            musicbrainz_title: Some(format!("Song {n}")),
+            musicbrainz_artist: Some(format!("Artist {}", n % 100)),
+            lookup_title: Some(format!("song {n}")),
+            lookup_artist: Some(format!("artist {}", n % 100)),
+            ..Default::default()
+        })
+        .collect();
+    diesel::insert_into(extra_song_info::table)
+        .values(&extra_infos)
+        .execute(&mut conn)
+        .await
+        .expect("Failed to seed extra song info");
+    let aliases: Vec<_> = (1..=SONG_COUNT)
+        .step_by(2)
+        .flat_map(|n| {
+            [
+                (
+                    song_aliases::song_id.eq(n),
+                    song_aliases::kind.eq("title"),
+                    song_aliases::alias.eq(format!("song {n} (remastered)")),
+                ),
+                (
+                    song_aliases::song_id.eq(n),
+                    song_aliases::kind.eq("artist"),
+                    song_aliases::alias.eq(format!("the artist {}", n % 100)),
+                ),
+            ]
+        })
+        .collect();
+    diesel::insert_into(song_aliases::table)
+        .values(&aliases)
+        .execute(&mut conn)
+        .await
+        .expect("Failed to seed song aliases");
+
+    let track_shape: Vec<i32> = (0..2000).map(|i| (i * 37) % 1000).collect();
+    let xstats: Vec<i32> = (0..24).collect();
+    let feats = ["Stealth", "Clean Finish"];
+    for player in 1..=PLAYER_COUNT {
+        let new_scores: Vec<NewScore> = (0..SCORES_PER_PLAYER)
+            .map(|i| {
+                // Everyone has a score on the popular song, the rest are spread out
+                let song = if i == 0 {
+                    POPULAR_SONG
+                } else {
+                    (player * 7 + i * 13) % SONG_COUNT + 1
+                };
+                NewScore::new(
+                    player,
+                    song,
+                    MAIN_REALM,
+                    League::Casual,
+                    (player * 7919 + i * 104_729) % 1_000_000,
+                    &track_shape,
+                    &xstats,
+                    0,
+                    Character::Mono,
+                    &feats,
+                    180,
+                    500_000,
+                    0,
+                    0,
+                )
+            })
+            .collect();
+        diesel::insert_into(scores::table)
+            .values(&new_scores)
+            .on_conflict_do_nothing()
+            .execute(&mut conn)
+            .await
+            .expect("Failed to seed scores");
+    }
+
+    // Build the skill point leaderboard the same way refresh-skill-points does
+    let all_scores: Vec<Score> = Score::all()
+        .load(&mut conn)
+        .await
+        .expect("Failed to load scores");
+    let mut pipe = redis::pipe();
+    for score in &all_scores {
+        pipe.zincr(SKILL_POINTS, score.player_id, score.get_skill_points())
+            .ignore();
+    }
+    pipe.query_async::<()>(&mut redis_conn)
+        .await
+        .expect("Failed to seed skill points");
+}
+
+fn bench_find_or_create(c: &mut Criterion, env: &BenchEnv) {
+    let mut group = c.benchmark_group("find_or_create");
+
+    let title = song_title(SONG_COUNT / 2);
+    let artist = song_artist(SONG_COUNT / 2);
+    group.bench_function("existing", |b| {
+        b.to_async(&env.rt).iter(|| async {
+            let mut conn = env.db.get().await.unwrap();
+            black_box(
+                NewSong::new(&title, &artist, None, MAIN_REALM)
+                    .find_or_create(&mut conn)
+                    .await
+                    .unwrap(),
+            )
+        });
+    });
+
+    let alias_title = format!("{} (remastered)", song_title(1));
+    let alias_artist = format!("the {}", song_artist(1));
+    group.bench_function("alias", |b| {
+        b.to_async(&env.rt).iter(|| async {
+            let mut conn = env.db.get().await.unwrap();
+            black_box(
+                NewSong::new(&alias_title, &alias_artist, None, MAIN_REALM)
+                    .find_or_create(&mut conn)
+                    .await
+                    .unwrap(),
+            )
+        });
+    });
+
+    group.finish();
+}
+
+fn bench_leaderboard(c: &mut Criterion, env: &BenchEnv) {
+    let mut group = c.benchmark_group("leaderboard");
+
+    group.bench_function("top_page", |b| {
+        b.to_async(&env.rt).iter(|| async {
+            let mut conn = env.db.get().await.unwrap();
+            black_box(
+                Score::game_get_global(POPULAR_SONG, League::Casual, 0, &mut conn)
+                    .await
+                    .unwrap(),
+            )
+        });
+    });
+
+    group.bench_function("last_page", |b| {
+        b.to_async(&env.rt).iter(|| async {
+            let mut conn = env.db.get().await.unwrap();
+            black_box(
+                Score::game_get_global(POPULAR_SONG, League::Casual, 99, &mut conn)
+                    .await
+                    .unwrap(),
+            )
+        });
+    });
+
+    group.bench_function("skill_points_top_100", |b| {
+        b.to_async(&env.rt).iter(|| async {
+            let mut redis_conn = env.redis.get().await.unwrap();
+            black_box(
+                redis::cmd("ZRANGE")
+                    .arg(SKILL_POINTS)
+                    .arg(0)
+                    .arg(99)
+                    .arg("REV")
+                    .arg("WITHSCORES")
+                    .query_async::<Vec<(i32, i32)>>(&mut redis_conn)
+                    .await
+                    .unwrap(),
+            )
+        });
+    });
+
+    group.finish();
+}
+
+fn bench_score_submission(c: &mut Criterion, env: &BenchEnv) {
+    let track_shape: Vec<i32> = (0..2000).map(|i| (i * 37) % 1000).collect();
+    let xstats: Vec<i32> = (0..24).collect();
+    let feats = ["Stealth"];
+
+    let mut group = c.benchmark_group("score_submission");
+
+    // Always beats the stored score, so the score gets replaced and the skill points change every time
+    let mut next_score = 2_000_000;
+    group.bench_function("improved", |b| {
+        b.to_async(&env.rt).iter_batched(
+            || {
+                next_score += 1;
+                next_score
+            },
+            |new_score| {
+                let (track_shape, xstats, feats) = (&track_shape, &xstats, &feats);
+                async move {
+                    let mut conn = env.db.get().await.unwrap();
+                    let mut redis_conn = env.redis.get().await.unwrap();
+                    black_box(
+                        NewScore::new(
+                            1,
+                            POPULAR_SONG,
+                            MAIN_REALM,
+                            League::Pro,
+                            new_score,
+                            track_shape,
+                            xstats,
+                            0,
+                            Character::Mono,
+                            feats,
+                            180,
+                            500_000,
+                            0,
+                            0,
+                        )
+                        .create_or_update(&mut conn, &mut redis_conn)
+                        .await
+                        .unwrap(),
+                    )
+                }
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    // Doesn't beat the stored score, only the play count goes up
+    group.bench_function("not_improved", |b| {
+        b.to_async(&env.rt).iter(|| async {
+            let mut conn = env.db.get().await.unwrap();
+            let mut redis_conn = env.redis.get().await.unwrap();
+            black_box(
+                NewScore::new(
+                    2,
+                    POPULAR_SONG,
+                    MAIN_REALM,
+                    League::Casual,
+                    0,
+                    &track_shape,
+                    &xstats,
+                    0,
+                    Character::Mono,
+                    &feats,
+                    180,
+                    500_000,
+                    0,
+                    0,
+                )
+                .create_or_update(&mut conn, &mut redis_conn)
+                .await
+                .unwrap(),
+            )
+        });
+    });
+
+    group.finish();
+}
+
+fn score_pipeline(c: &mut Criterion) {
+    let Some(env) = setup() else {
+        return;
+    };
+
+    bench_find_or_create(c, &env);
+    bench_leaderboard(c, &env);
+    bench_score_submission(c, &env);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(50);
+    targets = score_pipeline
+}
+criterion_main!(benches);
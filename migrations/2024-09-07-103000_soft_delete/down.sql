@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM scores WHERE deleted_at IS NOT NULL;
+DELETE FROM songs WHERE deleted_at IS NOT NULL;
+
+DROP INDEX songs_unique_data;
+CREATE UNIQUE INDEX songs_unique_data ON songs (title, artist, modifiers);
+DROP INDEX scores_unique_compound;
+CREATE UNIQUE INDEX scores_unique_compound ON scores (player_id, song_id, league);
+
+ALTER TABLE songs DROP COLUMN deleted_at;
+ALTER TABLE scores DROP COLUMN deleted_at;
@@ -0,0 +1,8 @@
+ALTER TABLE songs ADD deleted_at TIMESTAMPTZ(3);
+ALTER TABLE scores ADD deleted_at TIMESTAMPTZ(3);
+
+-- Deleted rows shouldn't block new songs/scores with the same data
+DROP INDEX songs_unique_data;
+CREATE UNIQUE INDEX songs_unique_data ON songs (title, artist, modifiers) WHERE deleted_at IS NULL;
+DROP INDEX scores_unique_compound;
+CREATE UNIQUE INDEX scores_unique_compound ON scores (player_id, song_id, league) WHERE deleted_at IS NULL;
@@ -0,0 +1,10 @@
+-- Records what happened during song merges so they can be undone
+CREATE TABLE
+    merge_log (
+        id SERIAL PRIMARY KEY,
+        source_song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        target_song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        manifest JSONB NOT NULL,
+        merged_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        undone_at TIMESTAMPTZ(3)
+    );
@@ -0,0 +1,20 @@
+-- Queue for background work, see src/jobs
+CREATE TABLE
+    jobs (
+        id SERIAL PRIMARY KEY,
+        payload JSONB NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        max_attempts INTEGER NOT NULL DEFAULT 5,
+        run_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        locked_at TIMESTAMPTZ(3),
+        finished_at TIMESTAMPTZ(3),
+        failed_at TIMESTAMPTZ(3),
+        last_error TEXT,
+        created_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
+
+-- The worker only ever looks at jobs that haven't finished or failed yet
+CREATE INDEX jobs_pending ON jobs (run_at)
+WHERE
+    finished_at IS NULL
+    AND failed_at IS NULL;
@@ -0,0 +1,4 @@
+DROP INDEX extra_song_info_mb_title;
+DROP INDEX extra_song_info_mb_artist;
+DROP INDEX extra_song_info_aliases_title;
+DROP INDEX extra_song_info_aliases_artist;
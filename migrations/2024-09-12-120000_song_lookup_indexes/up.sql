@@ -0,0 +1,6 @@
+-- Song lookups match the game's (lowercase) title and artist against MusicBrainz data and aliases,
+-- see NewSong::find_or_create
+CREATE INDEX extra_song_info_mb_title ON extra_song_info (lower(musicbrainz_title));
+CREATE INDEX extra_song_info_mb_artist ON extra_song_info (lower(musicbrainz_artist));
+CREATE INDEX extra_song_info_aliases_title ON extra_song_info USING GIN (aliases_title);
+CREATE INDEX extra_song_info_aliases_artist ON extra_song_info USING GIN (aliases_artist);
@@ -0,0 +1,8 @@
+-- Everything outside the main realm would collide with it
+DELETE FROM songs WHERE realm <> 'main';
+
+DROP INDEX songs_unique_data;
+CREATE UNIQUE INDEX songs_unique_data ON songs (title, artist, modifiers) WHERE deleted_at IS NULL;
+
+ALTER TABLE scores DROP COLUMN realm;
+ALTER TABLE songs DROP COLUMN realm;
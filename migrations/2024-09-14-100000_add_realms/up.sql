@@ -0,0 +1,8 @@
+-- Realms are isolated instances living in the same database, see src/util/realm.rs
+-- Scores have a copy of their song's realm so rankings can be built without joining songs
+ALTER TABLE songs ADD COLUMN realm TEXT NOT NULL DEFAULT 'main';
+ALTER TABLE scores ADD COLUMN realm TEXT NOT NULL DEFAULT 'main';
+
+-- The same song can exist once per realm
+DROP INDEX songs_unique_data;
+CREATE UNIQUE INDEX songs_unique_data ON songs (realm, title, artist, modifiers) WHERE deleted_at IS NULL;
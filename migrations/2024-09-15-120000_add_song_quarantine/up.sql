@@ -0,0 +1,16 @@
+-- Song lookups that were refused because they're clearly not songs, kept for admins to review
+CREATE TABLE
+    song_quarantine (
+        id SERIAL PRIMARY KEY,
+        realm TEXT NOT NULL,
+        title TEXT NOT NULL,
+        artist TEXT NOT NULL,
+        reason TEXT NOT NULL,
+        first_player_id INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        times_seen INTEGER NOT NULL DEFAULT 1,
+        first_seen_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        last_seen_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
+
+-- The same tags are only recorded once, repeats bump times_seen
+CREATE UNIQUE INDEX song_quarantine_unique ON song_quarantine (realm, title, artist);
@@ -0,0 +1,2 @@
+-- Whether the player agreed to their recent rides being shown publicly, e.g. by Discord bots
+ALTER TABLE players ADD COLUMN share_activity BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,4 @@
+DROP TABLE shout_reports;
+
+ALTER TABLE shouts
+DROP COLUMN hidden_at;
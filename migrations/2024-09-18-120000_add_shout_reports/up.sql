@@ -0,0 +1,24 @@
+-- Hidden shouts stay in the database, the game just doesn't show them anymore
+ALTER TABLE shouts
+ADD COLUMN hidden_at TIMESTAMPTZ(3);
+
+-- Reports of abusive shouts, for moderators to resolve
+-- Reports outlive deleted shouts, so it's still visible how they were resolved
+CREATE TABLE
+    shout_reports (
+        id SERIAL PRIMARY KEY,
+        shout_id INTEGER REFERENCES shouts (id) ON DELETE SET NULL,
+        reporter_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        reason VARCHAR(240) NOT NULL,
+        reported_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        resolved_at TIMESTAMPTZ(3),
+        resolution TEXT,
+        resolved_by INTEGER REFERENCES players (id) ON DELETE SET NULL
+    );
+
+-- Players can only report a shout once
+CREATE UNIQUE INDEX shout_reports_unique ON shout_reports (shout_id, reporter_id);
+
+CREATE INDEX shout_reports_pending ON shout_reports (reported_at)
+WHERE
+    resolved_at IS NULL;
@@ -0,0 +1,28 @@
+-- Metadata fixes suggested by players, for moderators to approve or reject
+CREATE TABLE
+    metadata_suggestions (
+        id SERIAL PRIMARY KEY,
+        song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        mbid TEXT,
+        release_mbid TEXT,
+        title TEXT,
+        artist TEXT,
+        status TEXT NOT NULL DEFAULT 'pending',
+        suggested_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        resolved_at TIMESTAMPTZ(3),
+        resolved_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        -- A suggestion has to suggest *something*
+        CHECK (
+            mbid IS NOT NULL
+            OR (
+                title IS NOT NULL
+                AND artist IS NOT NULL
+            )
+        )
+    );
+
+-- Players can only have one pending suggestion per song
+CREATE UNIQUE INDEX metadata_suggestions_pending ON metadata_suggestions (song_id, player_id)
+WHERE
+    status = 'pending';
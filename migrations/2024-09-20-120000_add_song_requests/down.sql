@@ -0,0 +1,3 @@
+DROP TABLE song_request_votes;
+
+DROP TABLE song_requests;
@@ -0,0 +1,29 @@
+-- Songs players would like to see on the radio or in challenges
+CREATE TABLE
+    song_requests (
+        id SERIAL PRIMARY KEY,
+        title TEXT NOT NULL,
+        artist TEXT NOT NULL,
+        note VARCHAR(240),
+        requested_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        -- Kept up to date with song_request_votes, so requests can be sorted by it cheaply
+        votes INTEGER NOT NULL DEFAULT 0,
+        status TEXT NOT NULL DEFAULT 'open',
+        requested_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        resolved_at TIMESTAMPTZ(3),
+        resolved_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        admin_note VARCHAR(240)
+    );
+
+-- The same song can't be requested twice while a request for it is still open, players should upvote that one
+CREATE UNIQUE INDEX song_requests_open ON song_requests (lower(title), lower(artist))
+WHERE
+    status = 'open';
+
+CREATE TABLE
+    song_request_votes (
+        request_id INTEGER NOT NULL REFERENCES song_requests (id) ON DELETE CASCADE,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        voted_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        PRIMARY KEY (request_id, player_id)
+    );
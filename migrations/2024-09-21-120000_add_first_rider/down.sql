@@ -0,0 +1,2 @@
+ALTER TABLE songs
+DROP COLUMN first_rider_id;
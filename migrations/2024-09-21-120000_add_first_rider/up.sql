@@ -0,0 +1,24 @@
+-- The player who submitted the first score on the song, like the original game's discovery credit
+ALTER TABLE songs
+ADD COLUMN first_rider_id INTEGER REFERENCES players (id) ON DELETE SET NULL;
+
+CREATE INDEX songs_first_rider_id ON songs (first_rider_id);
+
+-- Scores don't remember when they were first submitted, only when they were last improved,
+-- so the oldest remaining score is the best guess for songs that already exist
+UPDATE songs
+SET
+    first_rider_id = first_scores.player_id
+FROM
+    (
+        SELECT DISTINCT
+            ON (song_id) song_id,
+            player_id
+        FROM
+            scores
+        ORDER BY
+            song_id,
+            submitted_at ASC
+    ) AS first_scores
+WHERE
+    songs.id = first_scores.song_id;
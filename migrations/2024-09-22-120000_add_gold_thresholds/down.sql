@@ -0,0 +1 @@
+DROP TABLE gold_thresholds;
@@ -0,0 +1,39 @@
+-- Every gold threshold the game reported for a song, per league and character, and how often it did.
+-- The one reported most often is taken as the song's real threshold.
+CREATE TABLE gold_thresholds (
+    song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+    league SMALLINT NOT NULL,
+    vehicle SMALLINT NOT NULL,
+    gold_threshold INTEGER NOT NULL,
+    submissions INTEGER NOT NULL DEFAULT 1,
+    last_submitted_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (song_id, league, vehicle, gold_threshold)
+);
+
+-- Scores only remember the threshold of their best ride, but that's a start
+INSERT INTO
+    gold_thresholds (
+        song_id,
+        league,
+        vehicle,
+        gold_threshold,
+        submissions,
+        last_submitted_at
+    )
+SELECT
+    song_id,
+    league,
+    vehicle,
+    gold_threshold,
+    COUNT(*),
+    MAX(submitted_at)
+FROM
+    scores
+WHERE
+    deleted_at IS NULL
+    AND gold_threshold > 0
+GROUP BY
+    song_id,
+    league,
+    vehicle,
+    gold_threshold;
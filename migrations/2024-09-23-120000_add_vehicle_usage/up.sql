@@ -0,0 +1,38 @@
+-- How often each player rides with each character, counted on every submission
+CREATE TABLE vehicle_usage (
+    player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+    vehicle SMALLINT NOT NULL,
+    rides INTEGER NOT NULL DEFAULT 0,
+    -- Sum of the scores of all rides, for averages
+    total_score BIGINT NOT NULL DEFAULT 0,
+    best_score INTEGER NOT NULL DEFAULT 0,
+    last_ridden_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (player_id, vehicle)
+);
+
+CREATE INDEX vehicle_usage_vehicle ON vehicle_usage (vehicle);
+
+-- Earlier rides only left their best score behind, so every score counts as one ride
+INSERT INTO
+    vehicle_usage (
+        player_id,
+        vehicle,
+        rides,
+        total_score,
+        best_score,
+        last_ridden_at
+    )
+SELECT
+    player_id,
+    vehicle,
+    COUNT(*),
+    SUM(score),
+    MAX(score),
+    MAX(submitted_at)
+FROM
+    scores
+WHERE
+    deleted_at IS NULL
+GROUP BY
+    player_id,
+    vehicle;
@@ -0,0 +1,10 @@
+-- Analytics events, see the events module. Only written to if [events] is enabled in the config.
+-- Nothing the server needs to run reads from here, so reports can be as heavy as they like.
+CREATE TABLE events (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX events_kind_created_at ON events (kind, created_at);
@@ -0,0 +1,50 @@
+DROP VIEW extra_song_info_with_aliases;
+
+ALTER TABLE extra_song_info
+ADD COLUMN aliases_artist TEXT[],
+ADD COLUMN aliases_title TEXT[];
+
+-- Songs that only had aliases get extra info again, like they used to
+INSERT INTO
+    extra_song_info (song_id)
+SELECT DISTINCT
+    song_id
+FROM
+    song_aliases
+WHERE
+    song_id NOT IN (
+        SELECT
+            song_id
+        FROM
+            extra_song_info
+    );
+
+UPDATE extra_song_info
+SET
+    aliases_artist = ARRAY(
+        SELECT
+            alias
+        FROM
+            song_aliases
+        WHERE
+            song_aliases.song_id = extra_song_info.song_id
+            AND kind = 'artist'
+        ORDER BY
+            id
+    ),
+    aliases_title = ARRAY(
+        SELECT
+            alias
+        FROM
+            song_aliases
+        WHERE
+            song_aliases.song_id = extra_song_info.song_id
+            AND kind = 'title'
+        ORDER BY
+            id
+    );
+
+CREATE INDEX extra_song_info_aliases_title ON extra_song_info USING GIN (aliases_title);
+CREATE INDEX extra_song_info_aliases_artist ON extra_song_info USING GIN (aliases_artist);
+
+DROP TABLE song_aliases;
@@ -0,0 +1,73 @@
+-- Alternative titles and artists the game can send for a song, one row per alias.
+-- They used to be arrays on extra_song_info, which made them hard to query and deduplicate,
+-- and a song needed extra info just to have an alias.
+CREATE TABLE song_aliases (
+    id SERIAL PRIMARY KEY,
+    song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+    -- 'title' or 'artist'
+    kind TEXT NOT NULL CHECK (kind IN ('title', 'artist')),
+    alias TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (song_id, kind, alias)
+);
+
+-- Song lookups look for songs by alias, see NewSong::find_or_create
+CREATE INDEX song_aliases_lookup ON song_aliases (kind, alias);
+
+INSERT INTO
+    song_aliases (song_id, kind, alias)
+SELECT
+    song_id,
+    'title',
+    alias
+FROM
+    extra_song_info,
+    unnest(aliases_title) AS alias
+WHERE
+    alias IS NOT NULL
+UNION
+SELECT
+    song_id,
+    'artist',
+    alias
+FROM
+    extra_song_info,
+    unnest(aliases_artist) AS alias
+WHERE
+    alias IS NOT NULL
+ON CONFLICT DO NOTHING;
+
+-- Drops their GIN indexes too
+ALTER TABLE extra_song_info
+DROP COLUMN aliases_title,
+DROP COLUMN aliases_artist;
+
+-- Extra info the way it looked with the arrays, for reports and tools that still read them.
+-- Only meant for the transition, new code should use song_aliases.
+CREATE VIEW extra_song_info_with_aliases AS
+SELECT
+    extra_song_info.*,
+    ARRAY(
+        SELECT
+            alias
+        FROM
+            song_aliases
+        WHERE
+            song_aliases.song_id = extra_song_info.song_id
+            AND kind = 'artist'
+        ORDER BY
+            id
+    ) AS aliases_artist,
+    ARRAY(
+        SELECT
+            alias
+        FROM
+            song_aliases
+        WHERE
+            song_aliases.song_id = extra_song_info.song_id
+            AND kind = 'title'
+        ORDER BY
+            id
+    ) AS aliases_title
+FROM
+    extra_song_info;
@@ -0,0 +1,9 @@
+DROP INDEX extra_song_info_lookup_title;
+DROP INDEX extra_song_info_lookup_artist;
+
+ALTER TABLE extra_song_info
+DROP COLUMN lookup_title,
+DROP COLUMN lookup_artist;
+
+CREATE INDEX extra_song_info_mb_title ON extra_song_info (lower(musicbrainz_title));
+CREATE INDEX extra_song_info_mb_artist ON extra_song_info (lower(musicbrainz_artist));
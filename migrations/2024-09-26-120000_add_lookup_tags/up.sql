@@ -0,0 +1,15 @@
+-- The MusicBrainz title and artist the way song lookups compare them, see util::normalize.
+-- Lowercasing is only a first guess, run the normalize-tags command to normalize existing rows properly.
+ALTER TABLE extra_song_info
+ADD COLUMN lookup_title TEXT,
+ADD COLUMN lookup_artist TEXT;
+
+UPDATE extra_song_info
+SET
+    lookup_title = lower(musicbrainz_title),
+    lookup_artist = lower(musicbrainz_artist);
+
+DROP INDEX extra_song_info_mb_title;
+DROP INDEX extra_song_info_mb_artist;
+CREATE INDEX extra_song_info_lookup_title ON extra_song_info (lookup_title);
+CREATE INDEX extra_song_info_lookup_artist ON extra_song_info (lookup_artist);
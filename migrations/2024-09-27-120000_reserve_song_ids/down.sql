@@ -0,0 +1 @@
+ALTER SEQUENCE songs_id_seq NO MAXVALUE;
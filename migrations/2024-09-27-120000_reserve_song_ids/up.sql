@@ -0,0 +1,3 @@
+-- Song IDs from 2000000000 up are reserved for built-in entries like radio songs, see util::reserved_songs.
+-- They're only ever inserted explicitly, the sequence must never get there.
+ALTER SEQUENCE songs_id_seq MAXVALUE 1999999999;
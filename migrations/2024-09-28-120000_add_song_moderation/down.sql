@@ -0,0 +1,3 @@
+ALTER TABLE songs
+DROP COLUMN locked,
+DROP COLUMN moderator_notes;
@@ -0,0 +1,5 @@
+-- Locked songs are left alone by automatic metadata lookups, merges and suggestions, e.g. after a manual fix.
+-- The notes are for moderators only, like why the song was locked.
+ALTER TABLE songs
+ADD COLUMN locked BOOLEAN NOT NULL DEFAULT FALSE,
+ADD COLUMN moderator_notes TEXT;
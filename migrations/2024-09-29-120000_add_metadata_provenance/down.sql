@@ -0,0 +1,4 @@
+ALTER TABLE song_aliases
+DROP COLUMN source;
+
+DROP TABLE metadata_provenance;
@@ -0,0 +1,18 @@
+-- Where each metadata field of a song's extra info came from, see models::metadata_provenance.
+-- Fields without a row predate this and count as automatic.
+CREATE TABLE metadata_provenance (
+    song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+    -- Column of extra_song_info
+    field TEXT NOT NULL,
+    source TEXT NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (song_id, field)
+);
+
+-- Where aliases came from. Existing ones were all added by merges or renames, which can't be told apart anymore.
+ALTER TABLE song_aliases
+ADD COLUMN source TEXT NOT NULL DEFAULT 'unknown';
+
+ALTER TABLE song_aliases
+ALTER COLUMN source
+DROP DEFAULT;
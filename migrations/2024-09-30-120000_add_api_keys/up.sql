@@ -0,0 +1,13 @@
+-- Keys for community sites using the API, each with its own quotas, see models::api_keys.
+-- Only a hash of the key is stored, the key itself is shown once when it's created.
+CREATE TABLE
+    api_keys (
+        id SERIAL PRIMARY KEY,
+        name TEXT NOT NULL,
+        key_hash TEXT NOT NULL UNIQUE,
+        requests_per_day INTEGER NOT NULL CHECK (requests_per_day > 0),
+        burst_per_minute INTEGER NOT NULL CHECK (burst_per_minute > 0),
+        created_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+        revoked_at TIMESTAMPTZ
+    );
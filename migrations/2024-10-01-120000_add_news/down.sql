@@ -0,0 +1,3 @@
+DROP TABLE player_messages;
+
+DROP TABLE news_items;
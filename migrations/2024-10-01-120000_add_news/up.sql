@@ -0,0 +1,26 @@
+-- Shown to every player in the game's news, see util::news
+CREATE TABLE
+    news_items (
+        id SERIAL PRIMARY KEY,
+        kind TEXT NOT NULL CHECK (kind IN ('announcement', 'challenge', 'maintenance')),
+        text VARCHAR(500) NOT NULL,
+        created_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+        -- Never expires if NULL
+        expires_at TIMESTAMPTZ
+    );
+
+-- Shown to a single player, once, the next time their game fetches the news
+CREATE TABLE
+    player_messages (
+        id SERIAL PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        text VARCHAR(500) NOT NULL,
+        created_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+        delivered_at TIMESTAMPTZ
+    );
+
+CREATE INDEX player_messages_pending ON player_messages (player_id)
+WHERE
+    delivered_at IS NULL;
@@ -0,0 +1,25 @@
+-- Players appealing the removal of one of their scores, for moderators to resolve
+-- Resolved appeals are the record of what was decided, so they outlive purged scores
+CREATE TABLE
+    score_appeals (
+        id SERIAL PRIMARY KEY,
+        score_id INTEGER REFERENCES scores (id) ON DELETE SET NULL,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        comment VARCHAR(1000) NOT NULL,
+        evidence_url TEXT,
+        appealed_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        resolved_at TIMESTAMPTZ(3),
+        resolution TEXT,
+        -- Told to the player
+        resolution_note TEXT,
+        resolved_by INTEGER REFERENCES players (id) ON DELETE SET NULL
+    );
+
+-- A score can only have one appeal open at a time
+CREATE UNIQUE INDEX score_appeals_one_pending ON score_appeals (score_id)
+WHERE
+    resolved_at IS NULL;
+
+CREATE INDEX score_appeals_pending ON score_appeals (appealed_at)
+WHERE
+    resolved_at IS NULL;
@@ -0,0 +1,13 @@
+-- The top of song leaderboards as they were at some point, for looking at them as of a past date
+-- All rows of one snapshot of a leaderboard share the same taken_at
+CREATE TABLE
+    leaderboard_snapshots (
+        id SERIAL PRIMARY KEY,
+        song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        league SMALLINT NOT NULL,
+        taken_at TIMESTAMPTZ(3) NOT NULL,
+        placement INTEGER NOT NULL CHECK (placement > 0),
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        score INTEGER NOT NULL,
+        UNIQUE (song_id, league, taken_at, placement)
+    );
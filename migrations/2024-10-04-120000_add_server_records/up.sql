@@ -0,0 +1,20 @@
+-- The best anyone ever did in a few categories, one row per realm and kind
+CREATE TABLE
+    server_records (
+        realm TEXT NOT NULL,
+        kind TEXT NOT NULL CHECK (
+            kind IN (
+                'highest_score',
+                'most_number_ones',
+                'longest_reign',
+                'most_plays_in_a_day'
+            )
+        ),
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        -- Only for records set on a song
+        song_id INTEGER REFERENCES songs (id) ON DELETE SET NULL,
+        league SMALLINT,
+        value BIGINT NOT NULL,
+        set_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        PRIMARY KEY (realm, kind)
+    );
@@ -0,0 +1,13 @@
+-- Players that were merged into another one, so links to them can still be followed
+-- The merged player itself is gone, so its ID isn't a foreign key
+CREATE TABLE
+    player_redirects (
+        player_id INTEGER PRIMARY KEY,
+        steam_id TEXT NOT NULL,
+        username VARCHAR(32) NOT NULL,
+        target_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        merged_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        merged_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
+
+CREATE INDEX player_redirects_target ON player_redirects (target_id);
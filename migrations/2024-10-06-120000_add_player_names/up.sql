@@ -0,0 +1,10 @@
+-- Names players went by before, recorded when their Steam name changes
+CREATE TABLE
+    player_names (
+        id SERIAL PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        username VARCHAR(32) NOT NULL,
+        used_until TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
+
+CREATE INDEX player_names_player ON player_names (player_id, used_until);
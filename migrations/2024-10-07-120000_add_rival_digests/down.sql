@@ -0,0 +1 @@
+DROP TABLE rival_digests;
@@ -0,0 +1,8 @@
+-- When players last looked at what their rivals did, and where they want a daily digest of it sent
+CREATE TABLE
+    rival_digests (
+        player_id INTEGER PRIMARY KEY REFERENCES players (id) ON DELETE CASCADE,
+        seen_at TIMESTAMPTZ(3),
+        webhook_url TEXT,
+        sent_at TIMESTAMPTZ(3)
+    );
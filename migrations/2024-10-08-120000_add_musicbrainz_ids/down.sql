@@ -0,0 +1,4 @@
+ALTER TABLE extra_song_info
+DROP COLUMN release_mbid,
+DROP COLUMN release_group_mbid,
+DROP COLUMN artist_mbids;
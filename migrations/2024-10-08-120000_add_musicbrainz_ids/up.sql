@@ -0,0 +1,7 @@
+-- More MusicBrainz IDs, so songs tagged differently can be found by their release group or artists
+ALTER TABLE extra_song_info
+ADD COLUMN release_mbid TEXT,
+ADD COLUMN release_group_mbid TEXT,
+ADD COLUMN artist_mbids TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX extra_song_info_artist_mbids ON extra_song_info USING GIN (artist_mbids);
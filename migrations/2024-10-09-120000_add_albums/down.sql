@@ -0,0 +1,5 @@
+DROP INDEX extra_song_info_release_group;
+
+ALTER TABLE extra_song_info
+DROP COLUMN release_group_title,
+DROP COLUMN release_track_count;
@@ -0,0 +1,6 @@
+-- Songs are grouped into albums by their release group
+ALTER TABLE extra_song_info
+ADD COLUMN release_group_title TEXT,
+ADD COLUMN release_track_count INTEGER;
+
+CREATE INDEX extra_song_info_release_group ON extra_song_info (release_group_mbid);
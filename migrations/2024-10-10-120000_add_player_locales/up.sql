@@ -0,0 +1,3 @@
+-- The locale players want the server's texts in, see util::i18n. NULL means it's picked per request.
+ALTER TABLE players
+ADD COLUMN locale VARCHAR(16);
@@ -0,0 +1,3 @@
+-- The time zone players want times shown in, like Europe/Berlin. Only for clients, see util::time_zone.
+ALTER TABLE players
+ADD COLUMN time_zone VARCHAR(64);
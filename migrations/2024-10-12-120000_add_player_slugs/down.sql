@@ -0,0 +1,4 @@
+DROP TABLE player_slugs;
+
+ALTER TABLE players
+DROP COLUMN slug;
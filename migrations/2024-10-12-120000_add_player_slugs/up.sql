@@ -0,0 +1,13 @@
+-- Slugs players picked for their profile links, lowercase. See models::player_slugs.
+ALTER TABLE players
+ADD COLUMN slug VARCHAR(32) UNIQUE;
+
+-- Slugs players had before, which keep leading to them and can't be claimed by anyone else
+CREATE TABLE
+    player_slugs (
+        slug VARCHAR(32) PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        used_until TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
+
+CREATE INDEX player_slugs_player ON player_slugs (player_id, used_until);
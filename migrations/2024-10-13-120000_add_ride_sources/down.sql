@@ -0,0 +1 @@
+DROP TABLE ride_sources;
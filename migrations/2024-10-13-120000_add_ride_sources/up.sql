@@ -0,0 +1,15 @@
+-- Which client each ride was submitted from, for debugging compatibility. See util::client_source.
+CREATE TABLE
+    ride_sources (
+        id SERIAL PRIMARY KEY,
+        score_id INTEGER NOT NULL REFERENCES scores (id) ON DELETE CASCADE,
+        user_agent VARCHAR(256),
+        fingerprint VARCHAR(16) NOT NULL,
+        http_version VARCHAR(8) NOT NULL,
+        region VARCHAR(8),
+        submitted_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
+
+CREATE INDEX ride_sources_score ON ride_sources (score_id);
+
+CREATE INDEX ride_sources_submitted_at ON ride_sources (submitted_at);
@@ -0,0 +1,13 @@
+-- Staff looking at the API as a player, for debugging what they see. See models::impersonations.
+CREATE TABLE
+    impersonations (
+        id SERIAL PRIMARY KEY,
+        staff_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        reason TEXT NOT NULL,
+        started_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        expires_at TIMESTAMPTZ(3) NOT NULL,
+        ended_at TIMESTAMPTZ(3)
+    );
+
+CREATE INDEX impersonations_started_at ON impersonations (started_at);
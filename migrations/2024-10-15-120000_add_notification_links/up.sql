@@ -0,0 +1,16 @@
+-- Email addresses and Discord accounts players get notifications on, see models::notification_links.
+CREATE TABLE
+    notification_links (
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        channel VARCHAR(16) NOT NULL,
+        address VARCHAR(320) NOT NULL,
+        -- Of the code sent to confirm the address, until it's confirmed
+        code_hash VARCHAR(64),
+        code_expires_at TIMESTAMPTZ(3),
+        code_attempts INTEGER NOT NULL DEFAULT 0,
+        verified_at TIMESTAMPTZ(3),
+        notify_dethrones BOOLEAN NOT NULL DEFAULT TRUE,
+        notify_moderation BOOLEAN NOT NULL DEFAULT TRUE,
+        created_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        PRIMARY KEY (player_id, channel)
+    );
@@ -0,0 +1,17 @@
+-- Players hiding, unhiding or deleting their own scores
+-- Kept as the record of what happened, so it outlives purged scores and has what the score was
+CREATE TABLE
+    score_removals (
+        id SERIAL PRIMARY KEY,
+        score_id INTEGER REFERENCES scores (id) ON DELETE SET NULL,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        song_id INTEGER NOT NULL,
+        league SMALLINT NOT NULL,
+        score INTEGER NOT NULL,
+        action VARCHAR(16) NOT NULL,
+        created_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
+
+CREATE INDEX score_removals_player ON score_removals (player_id, created_at);
+
+CREATE INDEX score_removals_score ON score_removals (score_id);
@@ -0,0 +1 @@
+DROP TABLE sandbagging_flags;
@@ -0,0 +1,19 @@
+-- Elite-level players that looked like they farm top spots in Casual, see util::sandbagging
+-- Cleared flags are kept, detection only looks at what the player did since it was cleared
+CREATE TABLE
+    sandbagging_flags (
+        id SERIAL PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        realm TEXT NOT NULL,
+        -- What the player looked like when flagged
+        elite_points BIGINT NOT NULL,
+        recent_scores INTEGER NOT NULL,
+        recent_casual_scores INTEGER NOT NULL,
+        casual_tops INTEGER NOT NULL,
+        -- Whether the player's Casual scores don't count towards the rankings while it's active
+        excluded BOOLEAN NOT NULL,
+        flagged_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        cleared_at TIMESTAMPTZ(3),
+        cleared_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        UNIQUE (player_id, realm)
+    );
@@ -0,0 +1,3 @@
+DROP TABLE daily_song_plays;
+
+DROP TABLE daily_stats;
@@ -0,0 +1,22 @@
+-- Daily aggregates rolled up by the job worker, so stats don't have to scan the scores of every ride
+-- Days are in the server's time zone
+CREATE TABLE
+    daily_stats (
+        day DATE PRIMARY KEY,
+        new_players INTEGER NOT NULL,
+        -- Players who submitted at least one ride
+        active_players INTEGER NOT NULL,
+        plays INTEGER NOT NULL,
+        casual_plays INTEGER NOT NULL,
+        pro_plays INTEGER NOT NULL,
+        elite_plays INTEGER NOT NULL,
+        rolled_up_at TIMESTAMPTZ(3) NOT NULL DEFAULT NOW()
+    );
+
+CREATE TABLE
+    daily_song_plays (
+        day DATE NOT NULL,
+        song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        plays INTEGER NOT NULL,
+        PRIMARY KEY (day, song_id)
+    );
@@ -0,0 +1,4 @@
+DROP TABLE daily_player_points;
+
+ALTER TABLE daily_stats
+DROP COLUMN new_songs;
@@ -0,0 +1,23 @@
+ALTER TABLE daily_stats ADD COLUMN new_songs INTEGER NOT NULL DEFAULT 0;
+
+-- Days rolled up already get their new songs too. The days are cut in the database's time zone here, which is the
+-- server's unless time_zone is configured differently, in which case songs near midnight can land a day off.
+UPDATE daily_stats
+SET
+    new_songs = (
+        SELECT
+            COUNT(*)
+        FROM
+            songs
+        WHERE
+            songs.created_at::DATE = daily_stats.day
+    );
+
+-- Players' skill points at the end of the days they changed, for charts of them over time
+CREATE TABLE
+    daily_player_points (
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        day DATE NOT NULL,
+        skill_points BIGINT NOT NULL,
+        PRIMARY KEY (player_id, day)
+    );
@@ -0,0 +1 @@
+DROP TABLE skill_point_ledger;
@@ -0,0 +1,17 @@
+-- Every change to a player's skill points, so the totals in Redis can be audited and rebuilt
+CREATE TABLE
+    skill_point_ledger (
+        id BIGSERIAL PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        realm TEXT NOT NULL,
+        -- The score that changed, NULL for adjustments and corrections, or once the score is gone
+        score_id INTEGER REFERENCES scores (id) ON DELETE SET NULL,
+        reason VARCHAR(16) NOT NULL,
+        delta INTEGER NOT NULL,
+        -- Why a moderator adjusted the skill points
+        note TEXT,
+        created_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        created_at TIMESTAMPTZ(3) NOT NULL DEFAULT NOW()
+    );
+
+CREATE INDEX skill_point_ledger_player ON skill_point_ledger (player_id, realm);
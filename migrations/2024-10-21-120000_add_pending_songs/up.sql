@@ -0,0 +1,18 @@
+-- Song lookups that would've created a song the player isn't allowed to create, waiting for a moderator
+CREATE TABLE
+    pending_songs (
+        id SERIAL PRIMARY KEY,
+        realm TEXT NOT NULL,
+        -- As the game sent them, modifiers included
+        title TEXT NOT NULL,
+        artist TEXT NOT NULL,
+        mbid TEXT,
+        release_mbid TEXT,
+        first_player_id INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        times_seen INTEGER NOT NULL DEFAULT 1,
+        first_seen_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        last_seen_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
+
+-- The same tags are only queued once, repeats bump times_seen
+CREATE UNIQUE INDEX pending_songs_unique ON pending_songs (realm, title, artist);
@@ -0,0 +1,3 @@
+ALTER TABLE extra_song_info
+DROP COLUMN cover_colors,
+DROP COLUMN cover_colors_source;
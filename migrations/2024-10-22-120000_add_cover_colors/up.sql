@@ -0,0 +1,4 @@
+-- The dominant colors of the cover as #rrggbb, most common first, and the cover they're from
+ALTER TABLE extra_song_info
+ADD COLUMN cover_colors TEXT[] NOT NULL DEFAULT '{}',
+ADD COLUMN cover_colors_source TEXT;
@@ -0,0 +1,5 @@
+DROP INDEX extra_song_info_preview_checked_at;
+
+ALTER TABLE extra_song_info
+DROP COLUMN preview_url,
+DROP COLUMN preview_checked_at;
@@ -0,0 +1,6 @@
+-- A 30 second preview of the song from Deezer, and when it was last looked up
+ALTER TABLE extra_song_info
+ADD COLUMN preview_url TEXT,
+ADD COLUMN preview_checked_at TIMESTAMPTZ(3);
+
+CREATE INDEX extra_song_info_preview_checked_at ON extra_song_info (preview_checked_at NULLS FIRST);
@@ -0,0 +1,12 @@
+-- Links moderators attached to a song, like its official video or a lyrics page
+CREATE TABLE
+    song_links (
+        id SERIAL PRIMARY KEY,
+        song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        -- 'officialVideo', 'bandcamp', 'lyrics' or 'other'
+        kind VARCHAR(16) NOT NULL,
+        url TEXT NOT NULL,
+        added_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        added_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        UNIQUE (song_id, kind, url)
+    );
@@ -0,0 +1,3 @@
+DROP TABLE traffic_flags;
+
+DROP TABLE traffic_densities;
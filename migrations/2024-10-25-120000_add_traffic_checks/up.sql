@@ -0,0 +1,65 @@
+-- Every traffic density the game reported for a song, per league and character, and how often it did.
+-- Like gold thresholds, the one reported most often is taken as the song's real density.
+CREATE TABLE traffic_densities (
+    song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+    league SMALLINT NOT NULL,
+    vehicle SMALLINT NOT NULL,
+    density INTEGER NOT NULL,
+    submissions INTEGER NOT NULL DEFAULT 1,
+    last_submitted_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (song_id, league, vehicle, density)
+);
+
+-- Scores only remember the density of their best ride, but that's a start
+INSERT INTO
+    traffic_densities (
+        song_id,
+        league,
+        vehicle,
+        density,
+        submissions,
+        last_submitted_at
+    )
+SELECT
+    song_id,
+    league,
+    vehicle,
+    density,
+    COUNT(*),
+    MAX(submitted_at)
+FROM
+    scores
+WHERE
+    deleted_at IS NULL
+    AND density > 0
+GROUP BY
+    song_id,
+    league,
+    vehicle,
+    density;
+
+-- Rides whose traffic doesn't add up, see util::traffic
+-- A player has one flag per leaderboard, a cleared one is replaced when they're flagged there again
+CREATE TABLE
+    traffic_flags (
+        id SERIAL PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        league SMALLINT NOT NULL,
+        vehicle SMALLINT NOT NULL,
+        reason VARCHAR(16) NOT NULL,
+        -- What the ride claimed
+        density INTEGER NOT NULL,
+        xstats INTEGER[] NOT NULL,
+        song_length INTEGER NOT NULL,
+        -- The consensus density of the song at the time, if there was a trusted one
+        expected_density INTEGER,
+        flagged_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        cleared_at TIMESTAMPTZ(3),
+        cleared_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        UNIQUE (player_id, song_id, league)
+    );
+
+CREATE INDEX traffic_flags_active_idx ON traffic_flags (flagged_at)
+WHERE
+    cleared_at IS NULL;
@@ -0,0 +1,44 @@
+DROP TRIGGER scores_delete_references ON scores;
+
+DROP FUNCTION scores_delete_references ();
+
+ALTER TABLE scores RENAME TO scores_partitioned;
+
+ALTER INDEX scores_pkey RENAME TO scores_partitioned_pkey;
+
+ALTER INDEX scores_unique_compound RENAME TO scores_partitioned_unique_compound;
+
+DROP INDEX scores_leaderboard;
+
+CREATE TABLE scores (LIKE scores_partitioned INCLUDING DEFAULTS);
+
+ALTER TABLE scores ADD PRIMARY KEY (id),
+ADD FOREIGN KEY (song_id) REFERENCES songs (id) ON DELETE CASCADE,
+ADD FOREIGN KEY (player_id) REFERENCES players (id) ON DELETE CASCADE;
+
+INSERT INTO scores SELECT * FROM scores_partitioned;
+
+ALTER SEQUENCE scores_id_seq OWNED BY scores.id;
+
+DROP TABLE scores_partitioned;
+
+CREATE UNIQUE INDEX scores_unique_compound ON scores (player_id, song_id, league)
+WHERE
+    deleted_at IS NULL;
+
+-- References to scores that are gone by now can't point at them anymore
+DELETE FROM ride_sources WHERE score_id NOT IN (SELECT id FROM scores);
+
+UPDATE score_appeals SET score_id = NULL WHERE score_id NOT IN (SELECT id FROM scores);
+
+UPDATE score_removals SET score_id = NULL WHERE score_id NOT IN (SELECT id FROM scores);
+
+UPDATE skill_point_ledger SET score_id = NULL WHERE score_id NOT IN (SELECT id FROM scores);
+
+ALTER TABLE ride_sources ADD FOREIGN KEY (score_id) REFERENCES scores (id) ON DELETE CASCADE;
+
+ALTER TABLE score_appeals ADD FOREIGN KEY (score_id) REFERENCES scores (id) ON DELETE SET NULL;
+
+ALTER TABLE score_removals ADD FOREIGN KEY (score_id) REFERENCES scores (id) ON DELETE SET NULL;
+
+ALTER TABLE skill_point_ledger ADD FOREIGN KEY (score_id) REFERENCES scores (id) ON DELETE SET NULL;
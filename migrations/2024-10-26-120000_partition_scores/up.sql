@@ -0,0 +1,72 @@
+-- Scores are split into partitions by song, so leaderboard queries (which are always for one song) only look at
+-- the partition that song's scores are in, however many scores there are in total.
+-- Rewrites the whole table, which takes a while on instances with millions of scores.
+
+-- Foreign keys can only point at a partitioned table by its whole primary key, which now includes the song.
+-- The trigger below does what they did when a score is deleted for good.
+ALTER TABLE ride_sources DROP CONSTRAINT ride_sources_score_id_fkey;
+
+ALTER TABLE score_appeals DROP CONSTRAINT score_appeals_score_id_fkey;
+
+ALTER TABLE score_removals DROP CONSTRAINT score_removals_score_id_fkey;
+
+ALTER TABLE skill_point_ledger DROP CONSTRAINT skill_point_ledger_score_id_fkey;
+
+ALTER TABLE scores RENAME TO scores_unpartitioned;
+
+ALTER INDEX scores_pkey RENAME TO scores_unpartitioned_pkey;
+
+ALTER INDEX scores_unique_compound RENAME TO scores_unpartitioned_unique_compound;
+
+CREATE TABLE scores (LIKE scores_unpartitioned INCLUDING DEFAULTS) PARTITION BY HASH (song_id);
+
+ALTER TABLE scores ADD PRIMARY KEY (id, song_id),
+ADD FOREIGN KEY (song_id) REFERENCES songs (id) ON DELETE CASCADE,
+ADD FOREIGN KEY (player_id) REFERENCES players (id) ON DELETE CASCADE;
+
+DO $$
+BEGIN
+    FOR remainder IN 0..15 LOOP
+        EXECUTE format(
+            'CREATE TABLE scores_p%s PARTITION OF scores FOR VALUES WITH (MODULUS 16, REMAINDER %s)',
+            remainder,
+            remainder
+        );
+    END LOOP;
+END $$;
+
+INSERT INTO scores SELECT * FROM scores_unpartitioned;
+
+-- The sequence would be dropped along with the old table otherwise
+ALTER SEQUENCE scores_id_seq OWNED BY scores.id;
+
+DROP TABLE scores_unpartitioned;
+
+CREATE UNIQUE INDEX scores_unique_compound ON scores (player_id, song_id, league)
+WHERE
+    deleted_at IS NULL;
+
+-- For the leaderboards, within a partition
+CREATE INDEX scores_leaderboard ON scores (song_id, league, score DESC)
+WHERE
+    deleted_at IS NULL;
+
+CREATE FUNCTION scores_delete_references () RETURNS trigger AS $$
+BEGIN
+    -- Moving a score to another song (when merging songs) moves it to another partition, which deletes it from the
+    -- old one. It's still there then.
+    IF EXISTS (SELECT 1 FROM scores WHERE id = OLD.id) THEN
+        RETURN OLD;
+    END IF;
+
+    DELETE FROM ride_sources WHERE score_id = OLD.id;
+    UPDATE score_appeals SET score_id = NULL WHERE score_id = OLD.id;
+    UPDATE score_removals SET score_id = NULL WHERE score_id = OLD.id;
+    UPDATE skill_point_ledger SET score_id = NULL WHERE score_id = OLD.id;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER scores_delete_references
+AFTER DELETE ON scores FOR EACH ROW
+EXECUTE FUNCTION scores_delete_references ();
@@ -0,0 +1,33 @@
+-- Archived scores go back, unless the player has a score on the same leaderboard again
+INSERT INTO scores (
+    id, song_id, player_id, league, submitted_at, play_count, score, track_shape, xstats, density, vehicle, feats,
+    song_length, gold_threshold, iss, isj, deleted_at, realm
+)
+SELECT
+    id, song_id, player_id, league, submitted_at, play_count, score, track_shape, xstats, density, vehicle, feats,
+    song_length, gold_threshold, iss, isj, deleted_at, realm
+FROM scores_archive
+ON CONFLICT DO NOTHING;
+
+-- Takes the references of the ones that couldn't go back with them
+DELETE FROM scores_archive;
+
+DROP TABLE scores_archive;
+
+ALTER TABLE songs DROP COLUMN scores_archived_at;
+
+CREATE OR REPLACE FUNCTION scores_delete_references () RETURNS trigger AS $$
+BEGIN
+    -- Moving a score to another song (when merging songs) moves it to another partition, which deletes it from the
+    -- old one. It's still there then.
+    IF EXISTS (SELECT 1 FROM scores WHERE id = OLD.id) THEN
+        RETURN OLD;
+    END IF;
+
+    DELETE FROM ride_sources WHERE score_id = OLD.id;
+    UPDATE score_appeals SET score_id = NULL WHERE score_id = OLD.id;
+    UPDATE score_removals SET score_id = NULL WHERE score_id = OLD.id;
+    UPDATE skill_point_ledger SET score_id = NULL WHERE score_id = OLD.id;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,40 @@
+-- Scores of songs nobody rode in years are moved here, out of the way of the leaderboards and rankings queries.
+-- They're moved back when the song is ridden again.
+CREATE TABLE scores_archive (LIKE scores INCLUDING DEFAULTS);
+
+-- Archived scores keep their IDs
+ALTER TABLE scores_archive ALTER COLUMN id DROP DEFAULT;
+
+ALTER TABLE scores_archive ADD COLUMN archived_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+ADD PRIMARY KEY (id),
+ADD FOREIGN KEY (song_id) REFERENCES songs (id) ON DELETE CASCADE,
+ADD FOREIGN KEY (player_id) REFERENCES players (id) ON DELETE CASCADE;
+
+CREATE INDEX scores_archive_leaderboard ON scores_archive (song_id, league, score DESC);
+
+CREATE INDEX scores_archive_player ON scores_archive (player_id);
+
+ALTER TABLE songs ADD COLUMN scores_archived_at TIMESTAMPTZ;
+
+-- Scores are inserted into the archive before they're deleted from the scores table and the other way around,
+-- so their references stay as long as they're in either of them
+CREATE OR REPLACE FUNCTION scores_delete_references () RETURNS trigger AS $$
+BEGIN
+    -- Moving a score to another song (when merging songs) moves it to another partition, which deletes it from the
+    -- old one. It's still there then.
+    IF EXISTS (SELECT 1 FROM scores WHERE id = OLD.id)
+        OR EXISTS (SELECT 1 FROM scores_archive WHERE id = OLD.id) THEN
+        RETURN OLD;
+    END IF;
+
+    DELETE FROM ride_sources WHERE score_id = OLD.id;
+    UPDATE score_appeals SET score_id = NULL WHERE score_id = OLD.id;
+    UPDATE score_removals SET score_id = NULL WHERE score_id = OLD.id;
+    UPDATE skill_point_ledger SET score_id = NULL WHERE score_id = OLD.id;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER scores_archive_delete_references
+AFTER DELETE ON scores_archive FOR EACH ROW
+EXECUTE FUNCTION scores_delete_references ();
@@ -0,0 +1,7 @@
+ALTER TABLE extra_song_info
+DROP COLUMN deezer_track_id,
+ADD COLUMN preview_url TEXT;
+
+UPDATE extra_song_info
+SET
+    preview_checked_at = NULL;
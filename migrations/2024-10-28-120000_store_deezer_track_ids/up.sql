@@ -0,0 +1,9 @@
+-- Deezer's preview links are signed and expire, so the track is stored and its preview looked up when it's played
+ALTER TABLE extra_song_info
+DROP COLUMN preview_url,
+ADD COLUMN deezer_track_id BIGINT;
+
+-- Looked up again to find the tracks
+UPDATE extra_song_info
+SET
+    preview_checked_at = NULL;
@@ -0,0 +1,7 @@
+DROP INDEX pending_songs_unique;
+
+ALTER TABLE pending_songs
+DROP COLUMN lookup_title,
+DROP COLUMN lookup_artist;
+
+CREATE UNIQUE INDEX pending_songs_unique ON pending_songs (realm, title, artist);
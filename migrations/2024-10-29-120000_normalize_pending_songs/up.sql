@@ -0,0 +1,52 @@
+-- Pending songs are keyed by their normalized tags, like song lookups, so the same song sent with different casing or
+-- spacing is only queued once. This is close to util::normalize, `wavebreaker normalize-tags` doesn't touch these.
+ALTER TABLE pending_songs
+ADD COLUMN lookup_title TEXT NOT NULL DEFAULT '',
+ADD COLUMN lookup_artist TEXT NOT NULL DEFAULT '';
+
+UPDATE pending_songs
+SET
+    lookup_title = regexp_replace(btrim(replace(lower(title), '&', ' and ')), '\s+', ' ', 'g'),
+    lookup_artist = regexp_replace(btrim(replace(lower(artist), '&', ' and ')), '\s+', ' ', 'g');
+
+-- Tags that turn out to be the same are merged into the entry seen first
+UPDATE pending_songs kept
+SET
+    times_seen = merged.times_seen,
+    first_seen_at = merged.first_seen_at,
+    last_seen_at = merged.last_seen_at
+FROM
+    (
+        SELECT
+            min(id) AS id,
+            sum(times_seen) AS times_seen,
+            min(first_seen_at) AS first_seen_at,
+            max(last_seen_at) AS last_seen_at
+        FROM
+            pending_songs
+        GROUP BY
+            realm,
+            lookup_title,
+            lookup_artist
+        HAVING
+            count(*) > 1
+    ) merged
+WHERE
+    kept.id = merged.id;
+
+DELETE FROM pending_songs duplicate USING pending_songs kept
+WHERE
+    duplicate.realm = kept.realm
+    AND duplicate.lookup_title = kept.lookup_title
+    AND duplicate.lookup_artist = kept.lookup_artist
+    AND duplicate.id > kept.id;
+
+ALTER TABLE pending_songs
+ALTER COLUMN lookup_title
+DROP DEFAULT,
+ALTER COLUMN lookup_artist
+DROP DEFAULT;
+
+DROP INDEX pending_songs_unique;
+
+CREATE UNIQUE INDEX pending_songs_unique ON pending_songs (realm, lookup_title, lookup_artist);
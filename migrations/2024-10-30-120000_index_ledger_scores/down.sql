@@ -0,0 +1 @@
+DROP INDEX skill_point_ledger_score;
@@ -0,0 +1,3 @@
+-- Deleting a score for good clears its references in the ledger (see scores_delete_references), which would go
+-- through the whole ledger for every score otherwise
+CREATE INDEX skill_point_ledger_score ON skill_point_ledger (score_id);
@@ -0,0 +1 @@
+DROP INDEX scores_feats;
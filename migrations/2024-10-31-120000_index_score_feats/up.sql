@@ -0,0 +1,2 @@
+-- For the feat leaderboards, which look for scores with `feats @> ARRAY[<feat>]`
+CREATE INDEX scores_feats ON scores USING GIN (feats);
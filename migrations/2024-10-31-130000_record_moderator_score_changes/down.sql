@@ -0,0 +1,6 @@
+DELETE FROM score_removals
+WHERE
+    moderator_id IS NOT NULL;
+
+ALTER TABLE score_removals
+DROP COLUMN moderator_id;
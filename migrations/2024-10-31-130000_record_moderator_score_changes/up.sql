@@ -0,0 +1,3 @@
+-- Moderators removing scores and restoring them on appeal are recorded along with what players do themselves
+ALTER TABLE score_removals
+ADD COLUMN moderator_id INTEGER REFERENCES players (id) ON DELETE SET NULL;
@@ -0,0 +1,3 @@
+DROP INDEX songs_normalized_artist;
+
+DROP FUNCTION normalize_tag (TEXT);
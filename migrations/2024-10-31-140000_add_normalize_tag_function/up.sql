@@ -0,0 +1,11 @@
+-- Same as `util::normalize::normalize_tag`, for comparing tags that aren't stored normalized, like the songs' own.
+-- Both have to be changed together.
+CREATE FUNCTION normalize_tag (tag TEXT) RETURNS TEXT AS $$
+    SELECT btrim(regexp_replace(
+        replace(translate(lower(tag), '‘’`´“”‐‑–—', '''''''''""----'), '&', ' and '),
+        '\s+', ' ', 'g'
+    ));
+$$ LANGUAGE SQL IMMUTABLE STRICT PARALLEL SAFE;
+
+-- For the songs of an artist, see `Song::by_artist_name`
+CREATE INDEX songs_normalized_artist ON songs (realm, normalize_tag (artist)) WHERE deleted_at IS NULL;
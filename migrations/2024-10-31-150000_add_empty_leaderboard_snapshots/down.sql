@@ -0,0 +1,7 @@
+DELETE FROM leaderboard_snapshots WHERE placement = 0;
+
+ALTER TABLE leaderboard_snapshots
+DROP CONSTRAINT leaderboard_snapshots_placement_check,
+ADD CONSTRAINT leaderboard_snapshots_placement_check CHECK (placement > 0),
+ALTER COLUMN player_id SET NOT NULL,
+ALTER COLUMN score SET NOT NULL;
@@ -0,0 +1,10 @@
+-- A leaderboard whose scores were all deleted is snapshotted as a single row with placement 0 and no player,
+-- otherwise its last snapshot would keep showing the scores that are gone
+ALTER TABLE leaderboard_snapshots
+ALTER COLUMN player_id DROP NOT NULL,
+ALTER COLUMN score DROP NOT NULL,
+DROP CONSTRAINT leaderboard_snapshots_placement_check,
+ADD CONSTRAINT leaderboard_snapshots_placement_check CHECK (
+    (placement > 0 AND player_id IS NOT NULL AND score IS NOT NULL)
+    OR (placement = 0 AND player_id IS NULL AND score IS NULL)
+);
@@ -0,0 +1,52 @@
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    util::{
+        activity::{recent_rides, RecentRide},
+        errors::RouteError,
+    },
+    AppState,
+};
+
+/// Most rides a request can get
+const MAX_LIMIT: usize = 100;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/", get(get_activity))
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct ActivityParams {
+    /// How many rides to get, at most [`MAX_LIMIT`]
+    limit: usize,
+}
+
+impl Default for ActivityParams {
+    fn default() -> Self {
+        Self { limit: 20 }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ActivityResponse {
+    rides: Vec<RecentRide>,
+}
+
+/// What players sharing their activity rode recently, newest first.
+async fn get_activity(
+    State(state): State<AppState>,
+    Query(params): Query<ActivityParams>,
+) -> Result<Json<ActivityResponse>, RouteError> {
+    let mut redis_conn = state.redis.get().await?;
+
+    let rides = recent_rides(params.limit.clamp(1, MAX_LIMIT), &mut redis_conn).await?;
+
+    Ok(Json(ActivityResponse { rides }))
+}
@@ -0,0 +1,1991 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use jsonwebtoken::{encode, Header};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{error, info};
+use validator::Validate;
+
+use crate::{
+    backup::{self, Manifest},
+    events::Event,
+    models::{
+        api_keys::ApiKey,
+        extra_song_info::{ExtraSongInfo, MetadataEdit},
+        impersonations::Impersonation,
+        jobs::QueuedJob,
+        merge_log::MergeLog,
+        metadata_provenance::{MetadataProvenance, MetadataSource},
+        metadata_suggestions::{MetadataSuggestion, SuggestionStatus},
+        news_items::{NewsItem, NewsKind},
+        notification_links::NotificationKind,
+        pending_songs::PendingSong,
+        player_messages::PlayerMessage,
+        player_names::PreviousName,
+        player_redirects::PlayerRedirect,
+        players::{Player, PlayerPublic},
+        ride_sources::{ClientSummary, RideSource},
+        sandbagging_flags::SandbaggingFlag,
+        score_appeals::{AppealResolution, ScoreAppeal},
+        score_removals::{ScoreRemoval, ScoreRemovalAction},
+        scores::Score,
+        shout_reports::{ReportResolution, ShoutReport},
+        shouts::Shout,
+        skill_point_ledger::LedgerEntry,
+        song_links::{LinkKind, SongLink},
+        song_quarantine::QuarantinedSong,
+        song_requests::{SongRequest, SongRequestStatus},
+        songs::{NewSong, Song},
+        traffic_flags::TrafficFlag,
+    },
+    util::{
+        cover_colors, doctor,
+        errors::{RouteError, WavebreakerError},
+        i18n::Text,
+        jwt::{AuthBody, Claims, ImpersonationClaim, StaffClaims},
+        metrics::{last_window, Histogram},
+        news, notify,
+        ranking_store::{DeferredRankingStore, RankingStore},
+        rankings::{self, RankingMode},
+        realm::MAIN_REALM,
+    },
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/merges", get(get_merges))
+        .route("/merges/:id/undo", post(undo_merge))
+        .route("/overview", get(get_overview))
+        .route("/jobs", get(get_jobs))
+        .route("/quarantine", get(get_quarantine))
+        .route("/quarantine/:id", delete(dismiss_quarantined))
+        .route("/pendingSongs", get(get_pending_songs))
+        .route("/pendingSongs/:id", delete(reject_pending_song))
+        .route("/pendingSongs/:id/approve", post(approve_pending_song))
+        .route("/suggestions", get(get_suggestions))
+        .route("/suggestions/:id/approve", post(approve_suggestion))
+        .route("/suggestions/:id/reject", post(reject_suggestion))
+        .route("/songRequests/:id/triage", post(triage_song_request))
+        .route(
+            "/songs/:id/moderation",
+            get(get_song_moderation).put(update_song_moderation),
+        )
+        .route("/songs/:id/metadata", put(edit_song_metadata))
+        .route("/songs/:id/links", post(add_song_link))
+        .route("/songLinks/:id", delete(remove_song_link))
+        .route("/shoutReports", get(get_shout_reports))
+        .route("/shouts/:id/resolveReports", post(resolve_shout_reports))
+        .route("/backups", post(make_backup))
+        .route("/doctor", post(run_doctor))
+        .route("/latency", get(get_latency))
+        .route("/news", get(get_news).post(post_news))
+        .route("/news/:id", delete(delete_news))
+        .route("/players/:id/messages", post(send_player_message))
+        .route("/players/:id/merge", post(merge_player))
+        .route("/players/:id/names", get(get_previous_names))
+        .route("/players/:id/scoreRemovals", get(get_score_removals))
+        .route(
+            "/players/:id/skillPoints",
+            get(get_skill_point_ledger).post(adjust_skill_points),
+        )
+        .route("/skillPointAdjustments", get(get_skill_point_adjustments))
+        .route("/players/:id/impersonate", post(impersonate_player))
+        .route("/impersonations", get(get_impersonations))
+        .route("/impersonations/:id", delete(end_impersonation))
+        .route("/scores/:id/remove", post(remove_score))
+        .route("/scores/:id/sources", get(get_ride_sources))
+        .route("/clients", get(get_clients))
+        .route("/appeals", get(get_appeals))
+        .route("/appeals/:id/resolve", post(resolve_appeal))
+        .route("/sandbagging", get(get_sandbagging_flags))
+        .route("/sandbagging/:id/clear", post(clear_sandbagging_flag))
+        .route("/traffic", get(get_traffic_flags))
+        .route("/traffic/:id/clear", post(clear_traffic_flag))
+        .route("/apiKeys", get(get_api_keys).post(create_api_key))
+        .route(
+            "/apiKeys/:id",
+            put(update_api_key_quotas).delete(revoke_api_key),
+        )
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MergesResponse {
+    merges: Vec<MergeLog>,
+}
+
+async fn get_merges(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<MergesResponse>, RouteError> {
+    use crate::schema::merge_log::dsl::*;
+
+    let mut conn = state.db.get().await?;
+
+    let merges: Vec<MergeLog> = merge_log
+        .order(merged_at.desc())
+        .limit(50)
+        .load::<MergeLog>(&mut conn)
+        .await?;
+
+    Ok(Json(MergesResponse { merges }))
+}
+
+async fn undo_merge(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<Json<MergeLog>, RouteError> {
+    use crate::schema::merge_log;
+
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    let merge: MergeLog = merge_log::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Merge"))?;
+
+    // Answered with a 409 if the merge was already undone
+    let undone = merge.undo(&mut conn, &mut redis_conn).await?;
+    info!(
+        "Merge {} of song {} into {} undone by player {}",
+        undone.id, undone.source_song_id, undone.target_song_id, claims.profile.id
+    );
+
+    Ok(Json(undone))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JobCounts {
+    /// Jobs waiting to be run, including ones waiting for a retry
+    pending: i64,
+    /// Jobs a worker is currently running
+    running: i64,
+    /// Jobs that ran out of attempts
+    failed: i64,
+}
+
+impl JobCounts {
+    async fn load(conn: &mut AsyncPgConnection) -> QueryResult<Self> {
+        use crate::schema::jobs::dsl::*;
+
+        let pending: i64 = QueuedJob::pending()
+            .filter(locked_at.is_null())
+            .count()
+            .get_result(conn)
+            .await?;
+        let running: i64 = QueuedJob::pending()
+            .filter(locked_at.is_not_null())
+            .count()
+            .get_result(conn)
+            .await?;
+        let failed: i64 = jobs
+            .filter(failed_at.is_not_null())
+            .count()
+            .get_result(conn)
+            .await?;
+
+        Ok(Self {
+            pending,
+            running,
+            failed,
+        })
+    }
+}
+
+async fn recent_job_failures(
+    limit: i64,
+    conn: &mut AsyncPgConnection,
+) -> QueryResult<Vec<QueuedJob>> {
+    use crate::schema::jobs::dsl::*;
+
+    jobs.filter(failed_at.is_not_null())
+        .order(failed_at.desc())
+        .limit(limit)
+        .load::<QueuedJob>(conn)
+        .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JobsResponse {
+    #[serde(flatten)]
+    counts: JobCounts,
+    recent_failures: Vec<QueuedJob>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Moderation {
+    /// Song tags that were refused, see `/quarantine`
+    quarantined_songs: i64,
+    /// New songs waiting for approval, see `/pendingSongs`
+    pending_songs: i64,
+    /// Shouts with unresolved reports, see `/shoutReports`
+    reported_shouts: i64,
+    /// Metadata suggestions from players, see `/suggestions`
+    pending_suggestions: i64,
+    /// Song requests waiting for an admin, see `/api/songRequests`
+    open_song_requests: i64,
+    /// Players suspected of sandbagging that no moderator cleared yet, see `/sandbagging`
+    sandbagging_flags: i64,
+    /// Rides with traffic that didn't add up that no moderator cleared yet, see `/traffic`
+    traffic_flags: i64,
+}
+
+/// Activity since midnight, in the server's time zone
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Traffic {
+    /// Scores submitted or improved
+    scores: i64,
+    new_players: i64,
+    new_songs: i64,
+    shouts: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OverviewResponse {
+    moderation: Moderation,
+    jobs: JobCounts,
+    recent_job_failures: Vec<QueuedJob>,
+    today: Traffic,
+    /// Requests handled in the last finished latency window, see `/latency`
+    requests_last_window: u64,
+    /// Saved rides waiting for the rest of their processing, see `ride_queue` in the config
+    rides_queued: usize,
+}
+
+/// Everything an admin dashboard shows at a glance, so it doesn't need a request for each.
+async fn get_overview(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<OverviewResponse>, RouteError> {
+    use diesel::dsl::count_distinct;
+
+    use crate::schema::{
+        pending_songs, players, sandbagging_flags, scores, shout_reports, shouts, song_quarantine,
+        song_requests, songs, traffic_flags,
+    };
+
+    let mut conn = state.db.get().await?;
+    let midnight = state
+        .config
+        .time_zone
+        .start_of_today(OffsetDateTime::now_utc());
+
+    let quarantined_songs: i64 = song_quarantine::table.count().get_result(&mut conn).await?;
+    let pending_songs: i64 = pending_songs::table.count().get_result(&mut conn).await?;
+    let pending_suggestions: i64 = MetadataSuggestion::pending()
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    let open_song_requests: i64 = song_requests::table
+        .filter(song_requests::status.eq(SongRequestStatus::Open.as_str()))
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    let reported_shouts: i64 = ShoutReport::pending()
+        .select(count_distinct(shout_reports::shout_id))
+        .get_result(&mut conn)
+        .await?;
+    let sandbagging_flags: i64 = sandbagging_flags::table
+        .filter(sandbagging_flags::cleared_at.is_null())
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    let traffic_flags: i64 = traffic_flags::table
+        .filter(traffic_flags::cleared_at.is_null())
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    let today = Traffic {
+        scores: Score::all()
+            .filter(scores::submitted_at.ge(midnight))
+            .count()
+            .get_result(&mut conn)
+            .await?,
+        new_players: players::table
+            .filter(players::joined_at.ge(midnight))
+            .count()
+            .get_result(&mut conn)
+            .await?,
+        new_songs: Song::all()
+            .filter(songs::created_at.ge(midnight))
+            .count()
+            .get_result(&mut conn)
+            .await?,
+        shouts: shouts::table
+            .filter(shouts::posted_at.ge(midnight))
+            .count()
+            .get_result(&mut conn)
+            .await?,
+    };
+
+    Ok(Json(OverviewResponse {
+        moderation: Moderation {
+            quarantined_songs,
+            pending_songs,
+            reported_shouts,
+            pending_suggestions,
+            open_song_requests,
+            sandbagging_flags,
+            traffic_flags,
+        },
+        jobs: JobCounts::load(&mut conn).await?,
+        recent_job_failures: recent_job_failures(5, &mut conn).await?,
+        today,
+        requests_last_window: last_window(
+            state.config.latency_alerts.window_secs,
+            &mut state.redis.get().await?,
+        )
+        .await?
+        .values()
+        .map(Histogram::count)
+        .sum(),
+        rides_queued: state.rides.waiting(),
+    }))
+}
+
+async fn get_jobs(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<JobsResponse>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(JobsResponse {
+        counts: JobCounts::load(&mut conn).await?,
+        recent_failures: recent_job_failures(20, &mut conn).await?,
+    }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QuarantineResponse {
+    songs: Vec<QuarantinedSong>,
+}
+
+async fn get_quarantine(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<QuarantineResponse>, RouteError> {
+    use crate::schema::song_quarantine::dsl::*;
+
+    let mut conn = state.db.get().await?;
+
+    let songs: Vec<QuarantinedSong> = song_quarantine
+        .order(last_seen_at.desc())
+        .limit(100)
+        .load::<QuarantinedSong>(&mut conn)
+        .await?;
+
+    Ok(Json(QuarantineResponse { songs }))
+}
+
+async fn dismiss_quarantined(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<(), RouteError> {
+    use crate::schema::song_quarantine;
+
+    let mut conn = state.db.get().await?;
+
+    let quarantined: QuarantinedSong = song_quarantine::table
+        .find(id)
+        .first::<QuarantinedSong>(&mut conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Quarantined song"))?;
+    quarantined.delete(&mut conn).await?;
+
+    info!(
+        "Quarantined song {} dismissed by player {}",
+        quarantined.id, claims.profile.id
+    );
+
+    Ok(())
+}
+
+/// New songs players looked up but weren't allowed to create, the most looked up first.
+/// See [`crate::util::song_creation`].
+async fn get_pending_songs(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<Vec<PendingSong>>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(PendingSong::queue(100, &mut conn).await?))
+}
+
+async fn find_pending_song(
+    id: i32,
+    conn: &mut AsyncPgConnection,
+) -> Result<PendingSong, RouteError> {
+    use crate::schema::pending_songs;
+
+    Ok(pending_songs::table
+        .find(id)
+        .select(PendingSong::as_select())
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Pending song"))?)
+}
+
+/// Creates the song, the next lookup of its tags finds it.
+/// If it was created in the meantime (or its tags match a song already), that one is returned.
+/// Nothing is kept if a step fails, so the pending song can be approved again.
+async fn approve_pending_song(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<Json<Song>, RouteError> {
+    use crate::{
+        schema::pending_songs,
+        util::modifiers::{parse_from_title, remove_from_title},
+    };
+
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    let (pending, song, created) = conn
+        .transaction::<_, WavebreakerError, _>(|conn| {
+            let redis_conn = &mut redis_conn;
+            async move {
+                // Two moderators approving at once would both create the song otherwise
+                let pending = pending_songs::table
+                    .find(id)
+                    .select(PendingSong::as_select())
+                    .for_update()
+                    .first(conn)
+                    .await
+                    .optional()?
+                    .ok_or(WavebreakerError::NotFound("Pending song"))?;
+                let title = remove_from_title(&pending.title);
+                let (song, created) = NewSong::new(
+                    &title,
+                    &pending.artist,
+                    parse_from_title(&pending.title),
+                    &pending.realm,
+                )
+                .find_or_create_cached(conn, redis_conn)
+                .await?;
+                if let (Some(mbid), false) = (&pending.mbid, song.locked) {
+                    song.add_metadata_mbid(
+                        mbid,
+                        pending.release_mbid.as_deref(),
+                        MetadataSource::GameMbid,
+                        false,
+                        conn,
+                        redis_conn,
+                    )
+                    .await?;
+                    cover_colors::queue(song.id, conn).await;
+                }
+                pending.delete(conn).await?;
+                Ok((pending, song, created))
+            }
+            .scope_boxed()
+        })
+        .await?;
+    if created {
+        state.events.emit(Event::SongCreated {
+            song_id: song.id,
+            realm: song.realm.clone(),
+        });
+    }
+
+    info!(
+        "Pending song {} approved as song {} by player {}",
+        pending.id, song.id, claims.profile.id
+    );
+
+    Ok(Json(song))
+}
+
+/// Drops the pending song. If its tags are looked up again, they're queued again.
+async fn reject_pending_song(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<(), RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let pending = find_pending_song(id, &mut conn).await?;
+    pending.delete(&mut conn).await?;
+
+    info!(
+        "Pending song {} rejected by player {}",
+        pending.id, claims.profile.id
+    );
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SuggestionWithSong {
+    suggestion: MetadataSuggestion,
+    song: Song,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SuggestionsResponse {
+    suggestions: Vec<SuggestionWithSong>,
+}
+
+/// Pending metadata suggestions, oldest first.
+async fn get_suggestions(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<SuggestionsResponse>, RouteError> {
+    use crate::schema::{metadata_suggestions, songs};
+
+    let mut conn = state.db.get().await?;
+
+    let suggestions: Vec<(MetadataSuggestion, Song)> = MetadataSuggestion::pending()
+        .inner_join(songs::table)
+        .filter(songs::deleted_at.is_null())
+        .order(metadata_suggestions::suggested_at.asc())
+        .limit(100)
+        .select((MetadataSuggestion::as_select(), Song::as_select()))
+        .load(&mut conn)
+        .await?;
+
+    Ok(Json(SuggestionsResponse {
+        suggestions: suggestions
+            .into_iter()
+            .map(|(suggestion, song)| SuggestionWithSong { suggestion, song })
+            .collect(),
+    }))
+}
+
+async fn find_pending_suggestion(
+    id: i32,
+    conn: &mut AsyncPgConnection,
+) -> Result<MetadataSuggestion, WavebreakerError> {
+    MetadataSuggestion::pending()
+        .find(id)
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Pending suggestion"))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ApproveSuggestionParams {
+    /// Also overwrite the metadata fields that were edited by hand
+    force: bool,
+}
+
+/// Applies a metadata suggestion: the title and artist are changed first, then the metadata of the MBID is added.
+/// Fields edited by hand are kept unless it's forced, see [`MetadataSource::precedence`].
+async fn approve_suggestion(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Query(params): Query<ApproveSuggestionParams>,
+) -> Result<Json<MetadataSuggestion>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let suggestion = find_pending_suggestion(id, &mut conn).await?;
+    let song: Song = Song::all()
+        .find(suggestion.song_id)
+        .first(&mut conn)
+        .await?;
+    // Unlock the song first to apply it anyway
+    if song.locked {
+        return Err(WavebreakerError::SongLocked(song.id).into());
+    }
+
+    // A suggestion is applied completely or not at all
+    let moderator_id = claims.profile.id;
+    let force = params.force;
+    let (suggestion, store) = conn
+        .transaction::<_, WavebreakerError, _>(|conn| {
+            async move {
+                let mut store = DeferredRankingStore::default();
+                let mut song = song;
+                if let (Some(title), Some(artist)) = (&suggestion.title, &suggestion.artist) {
+                    song = song.rename(title, artist, conn, &mut store).await?;
+                }
+                if let Some(mbid) = &suggestion.mbid {
+                    song.add_metadata_mbid(
+                        mbid,
+                        suggestion.release_mbid.as_deref(),
+                        MetadataSource::ManualMbid,
+                        force,
+                        conn,
+                        &mut store,
+                    )
+                    .await?;
+                }
+                let suggestion = suggestion
+                    .resolve(SuggestionStatus::Approved, moderator_id, conn)
+                    .await?;
+                Ok((suggestion, store))
+            }
+            .scope_boxed()
+        })
+        .await?;
+    store.apply(&mut state.redis.get().await?).await?;
+    if suggestion.mbid.is_some() {
+        cover_colors::queue(suggestion.song_id, &mut conn).await;
+    }
+    info!(
+        "Suggestion {} for song {} approved by player {}{}",
+        suggestion.id,
+        suggestion.song_id,
+        claims.profile.id,
+        if force { ", forced" } else { "" }
+    );
+
+    Ok(Json(suggestion))
+}
+
+async fn reject_suggestion(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<Json<MetadataSuggestion>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let suggestion = find_pending_suggestion(id, &mut conn)
+        .await?
+        .resolve(SuggestionStatus::Rejected, claims.profile.id, &mut conn)
+        .await?;
+    info!(
+        "Suggestion {} for song {} rejected by player {}",
+        suggestion.id, suggestion.song_id, claims.profile.id
+    );
+
+    Ok(Json(suggestion))
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct TriageRequest {
+    status: SongRequestStatus,
+    /// Shown to players, e.g. why the request was rejected
+    #[validate(length(max = 500))]
+    note: Option<String>,
+}
+
+/// Accepts, rejects, finishes or reopens a song request.
+async fn triage_song_request(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Json(payload): Json<TriageRequest>,
+) -> Result<Json<SongRequest>, RouteError> {
+    use crate::schema::song_requests;
+
+    if payload.validate().is_err() {
+        return Err(RouteError::new_bad_request().set_public_error_message("Note is too long"));
+    }
+
+    let mut conn = state.db.get().await?;
+
+    let request: SongRequest = song_requests::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Song request"))?;
+    // Reopening a request can collide with a newer open one for the same song, that's a 409
+    let request = request
+        .triage(
+            payload.status,
+            payload.note.as_deref(),
+            claims.profile.id,
+            &mut conn,
+        )
+        .await?;
+    info!(
+        "Song request {} set to {} by player {}",
+        request.id, request.status, claims.profile.id
+    );
+
+    Ok(Json(request))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SongModeration {
+    #[serde(flatten)]
+    song: Song,
+    moderator_notes: Option<String>,
+    /// Where the fields of the song's extra info came from
+    provenance: Vec<MetadataProvenance>,
+}
+
+impl SongModeration {
+    async fn load(mut song: Song, conn: &mut AsyncPgConnection) -> Result<Self, RouteError> {
+        Ok(Self {
+            moderator_notes: song.moderator_notes.take(),
+            provenance: MetadataProvenance::for_song(song.id, conn).await?,
+            song,
+        })
+    }
+}
+
+/// A song with the parts only moderators get to see.
+async fn get_song_moderation(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<Json<SongModeration>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let song: Song = Song::all().find(id).first(&mut conn).await?;
+
+    Ok(Json(SongModeration::load(song, &mut conn).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SongModerationRequest {
+    locked: bool,
+    /// Replaces the old notes, leave it out to remove them
+    notes: Option<String>,
+}
+
+/// Locks or unlocks a song and sets its moderator notes.
+async fn update_song_moderation(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Json(payload): Json<SongModerationRequest>,
+) -> Result<Json<SongModeration>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let notes = payload
+        .notes
+        .as_deref()
+        .map(str::trim)
+        .filter(|notes| !notes.is_empty());
+    let song: Song = Song::all()
+        .find(id)
+        .first::<Song>(&mut conn)
+        .await?
+        .set_moderation(payload.locked, notes, &mut conn)
+        .await?;
+    info!(
+        "Song {} {} by player {}",
+        song.id,
+        if song.locked { "locked" } else { "unlocked" },
+        claims.profile.id
+    );
+
+    Ok(Json(SongModeration::load(song, &mut conn).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MetadataEditRequest {
+    title: Option<String>,
+    artist: Option<String>,
+    cover_url: Option<String>,
+    cover_url_small: Option<String>,
+}
+
+/// Changes a song's metadata by hand. Only the fields that are given are changed.
+/// Changed fields aren't overwritten by automatic lookups anymore, see `GET /songs/:id/moderation` for where
+/// each field came from.
+async fn edit_song_metadata(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Json(payload): Json<MetadataEditRequest>,
+) -> Result<Json<ExtraSongInfo>, RouteError> {
+    let trimmed = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+    };
+    let edit = MetadataEdit::new(
+        trimmed(payload.title),
+        trimmed(payload.artist),
+        trimmed(payload.cover_url),
+        trimmed(payload.cover_url_small),
+    );
+    if edit.fields().is_empty() {
+        return Err(RouteError::new_bad_request().set_public_error_message("Nothing to change"));
+    }
+
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    let song: Song = Song::all().find(id).first(&mut conn).await?;
+    let extra_info = song
+        .edit_metadata(&edit, &mut conn, &mut redis_conn)
+        .await?;
+    if edit.cover_url.is_some() || edit.cover_url_small.is_some() {
+        cover_colors::queue(song.id, &mut conn).await;
+    }
+    info!(
+        "Metadata of song {} ({}) edited by player {}",
+        song.id,
+        edit.fields().join(", "),
+        claims.profile.id
+    );
+
+    Ok(Json(extra_info))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SongLinkRequest {
+    kind: LinkKind,
+    url: String,
+}
+
+/// Attaches a link to a song, like its official video or a lyrics page. Answered with a 409 if it has it already.
+async fn add_song_link(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Json(payload): Json<SongLinkRequest>,
+) -> Result<Json<SongLink>, RouteError> {
+    let url = payload.url.trim();
+    if let Some(problem) = SongLink::check_url(payload.kind, url) {
+        return Err(RouteError::new_bad_request().set_public_error_message(problem));
+    }
+
+    let mut conn = state.db.get().await?;
+
+    let song: Song = Song::all().find(id).first(&mut conn).await?;
+    let link = SongLink::add(song.id, payload.kind, url, claims.profile.id, &mut conn)
+        .await?
+        .ok_or_else(|| {
+            RouteError::new_conflict().set_public_error_message("The song has this link already")
+        })?;
+    info!(
+        "Link {} ({}) added to song {} by player {}",
+        link.id, link.kind, song.id, claims.profile.id
+    );
+
+    Ok(Json(link))
+}
+
+async fn remove_song_link(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<(), RouteError> {
+    use crate::schema::song_links;
+
+    let mut conn = state.db.get().await?;
+
+    let link: SongLink = song_links::table
+        .find(id)
+        .select(SongLink::as_select())
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Song link"))?;
+    link.delete(&mut conn).await?;
+    info!(
+        "Link {} removed from song {} by player {}",
+        link.id, link.song_id, claims.profile.id
+    );
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportedShout {
+    report: ShoutReport,
+    shout: Shout,
+    author: PlayerPublic,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ShoutReportsResponse {
+    reports: Vec<ReportedShout>,
+}
+
+/// Unresolved shout reports, oldest first so nothing waits forever.
+async fn get_shout_reports(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<ShoutReportsResponse>, RouteError> {
+    use crate::schema::{players, shout_reports, shouts};
+
+    let mut conn = state.db.get().await?;
+
+    let reports: Vec<(ShoutReport, Shout, Player)> = ShoutReport::pending()
+        .inner_join(shouts::table.inner_join(players::table))
+        .order(shout_reports::reported_at.asc())
+        .limit(100)
+        .select((
+            ShoutReport::as_select(),
+            Shout::as_select(),
+            Player::as_select(),
+        ))
+        .load(&mut conn)
+        .await?;
+
+    Ok(Json(ShoutReportsResponse {
+        reports: reports
+            .into_iter()
+            .map(|(report, shout, author)| ReportedShout {
+                report,
+                shout,
+                author: author.into(),
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResolveReportsRequest {
+    action: ReportResolution,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResolveReportsResponse {
+    resolved_reports: usize,
+}
+
+/// Hides or deletes a reported shout, or dismisses its reports. All of its pending reports are resolved.
+async fn resolve_shout_reports(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Json(payload): Json<ResolveReportsRequest>,
+) -> Result<Json<ResolveReportsResponse>, RouteError> {
+    use crate::schema::shouts;
+
+    let mut conn = state.db.get().await?;
+
+    let shout: Shout = shouts::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Shout"))?;
+
+    let resolved_reports = conn
+        .transaction::<_, WavebreakerError, _>(|conn| {
+            let shout = &shout;
+            let moderator_id = claims.profile.id;
+            async move {
+                let resolved =
+                    ShoutReport::resolve_all(shout.id, payload.action, moderator_id, conn).await?;
+                match payload.action {
+                    ReportResolution::Hide => shout.hide(conn).await?,
+                    ReportResolution::Delete => shout.delete(conn).await?,
+                    ReportResolution::Dismiss => {}
+                }
+                Ok(resolved)
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    info!(
+        "Reports of shout {} resolved by player {} ({}), {resolved_reports} report(s) closed",
+        shout.id,
+        claims.profile.id,
+        payload.action.as_str()
+    );
+
+    Ok(Json(ResolveReportsResponse { resolved_reports }))
+}
+
+/// Makes a backup right away. This can take a while on big databases.
+async fn make_backup(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+) -> Result<Json<Manifest>, RouteError> {
+    info!("Backup requested by player {}", claims.profile.id);
+    let manifest = backup::run(&state).await?;
+
+    Ok(Json(manifest))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DoctorRequest {
+    #[serde(default)]
+    fix: bool,
+}
+
+/// Looks for data that doesn't add up, see [`doctor`].
+async fn run_doctor(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Json(payload): Json<DoctorRequest>,
+) -> Result<Json<doctor::Report>, RouteError> {
+    info!(
+        "Doctor requested by player {}, fix: {}",
+        claims.profile.id, payload.fix
+    );
+    let report = doctor::run(&state, payload.fix).await?;
+
+    Ok(Json(report))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RouteLatency {
+    route: String,
+    requests: u64,
+    p50_ms: Option<u64>,
+    p90_ms: Option<u64>,
+    p99_ms: Option<u64>,
+}
+
+/// Latencies per route over the last finished window of every instance, see `latency_alerts.window_secs` in the config.
+async fn get_latency(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<Vec<RouteLatency>>, RouteError> {
+    let mut redis_conn = state.redis.get().await?;
+    let mut routes: Vec<RouteLatency> =
+        last_window(state.config.latency_alerts.window_secs, &mut redis_conn)
+            .await?
+            .into_iter()
+            .map(|(route, histogram)| RouteLatency {
+                route,
+                requests: histogram.count(),
+                p50_ms: histogram.percentile(50),
+                p90_ms: histogram.percentile(90),
+                p99_ms: histogram.percentile(99),
+            })
+            .collect();
+    routes.sort_unstable_by(|a, b| a.route.cmp(&b.route));
+
+    Ok(Json(routes))
+}
+
+/// Longest text a news item or player message can have, as stored in the database.
+const NEWS_TEXT_MAX_CHARS: usize = 500;
+
+fn check_news_text(text: &str) -> Result<&str, RouteError> {
+    let text = text.trim();
+    if text.is_empty() || text.chars().count() > NEWS_TEXT_MAX_CHARS {
+        return Err(
+            RouteError::new_bad_request().set_public_error_message(&format!(
+                "Text has to be between 1 and {NEWS_TEXT_MAX_CHARS} characters"
+            )),
+        );
+    }
+    Ok(text)
+}
+
+/// The latest news items, including expired ones.
+async fn get_news(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<Vec<NewsItem>>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(NewsItem::recent(50, &mut conn).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NewsRequest {
+    kind: NewsKind,
+    text: String,
+    /// Leave it out to keep the item until it's deleted
+    #[serde(default, with = "time::serde::iso8601::option")]
+    expires_at: Option<OffsetDateTime>,
+}
+
+/// Posts a news item, the game shows it from its next news fetch on.
+async fn post_news(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Json(payload): Json<NewsRequest>,
+) -> Result<Json<NewsItem>, RouteError> {
+    let text = check_news_text(&payload.text)?;
+
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    let item = NewsItem::create(
+        payload.kind,
+        text,
+        payload.expires_at,
+        claims.profile.id,
+        &mut conn,
+    )
+    .await?;
+    news::mark_dirty(&mut redis_conn).await?;
+    info!(
+        "News item {} ({}) posted by player {}",
+        item.id, item.kind, claims.profile.id
+    );
+
+    Ok(Json(item))
+}
+
+/// Deletes a news item, the game stops showing it from its next news fetch on.
+async fn delete_news(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<(), RouteError> {
+    use crate::schema::news_items;
+
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    let deleted = diesel::delete(news_items::table.find(id))
+        .execute(&mut conn)
+        .await?;
+    if deleted == 0 {
+        return Err(WavebreakerError::NotFound("News item").into());
+    }
+    news::mark_dirty(&mut redis_conn).await?;
+    info!("News item {id} deleted by player {}", claims.profile.id);
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlayerMessageRequest {
+    text: String,
+}
+
+/// Sends a message to a player, shown the next time their game fetches the news.
+async fn send_player_message(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Json(payload): Json<PlayerMessageRequest>,
+) -> Result<Json<PlayerMessage>, RouteError> {
+    use crate::schema::players;
+
+    let text = check_news_text(&payload.text)?;
+
+    let mut conn = state.db.get().await?;
+
+    let player: Player = players::table.find(id).first(&mut conn).await?;
+    let message = PlayerMessage::send(player.id, text, Some(claims.profile.id), &mut conn).await?;
+    notify_moderation(player.id, text, &mut conn).await;
+    info!(
+        "Message {} sent to player {} by player {}",
+        message.id, player.id, claims.profile.id
+    );
+
+    Ok(Json(message))
+}
+
+/// Sends a message from staff to where the player gets notifications, on top of the game's news.
+/// The message is already sent, so failing to do so is only logged.
+async fn notify_moderation(player_id: i32, text: &str, conn: &mut AsyncPgConnection) {
+    if let Err(e) = notify::queue(player_id, NotificationKind::Moderation, text, conn).await {
+        error!("Failed to notify player {player_id} of a message from staff: {e:?}");
+    }
+}
+
+/// How long an impersonation token works, it's for a debugging session and nothing more
+const IMPERSONATION_MINUTES: i64 = 30;
+
+#[derive(Deserialize)]
+struct ImpersonateRequest {
+    /// Why the player's account is looked at, kept with the impersonation
+    reason: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ImpersonateResponse {
+    impersonation: Impersonation,
+    token: AuthBody,
+}
+
+/// Hands out a token to use the API as the player, to see what they see.
+/// It only allows reading and is recorded with the reason, see [`Impersonation`].
+async fn impersonate_player(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Json(payload): Json<ImpersonateRequest>,
+) -> Result<Json<ImpersonateResponse>, RouteError> {
+    use crate::schema::players;
+
+    let reason = payload.reason.trim();
+    if reason.is_empty() {
+        return Err(RouteError::new_bad_request().set_public_error_message("Reason is required"));
+    }
+
+    let mut conn = state.db.get().await?;
+
+    let player: Player = players::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Player"))?;
+    let expires_at = OffsetDateTime::now_utc() + time::Duration::minutes(IMPERSONATION_MINUTES);
+    let impersonation =
+        Impersonation::start(claims.profile.id, player.id, reason, expires_at, &mut conn).await?;
+    info!(
+        "Player {} started impersonating player {} (impersonation {}): {}",
+        claims.profile.id, player.id, impersonation.id, reason
+    );
+
+    let token_claims = Claims {
+        profile: player,
+        exp: expires_at.unix_timestamp(),
+        impersonation: Some(ImpersonationClaim {
+            id: impersonation.id,
+            staff_id: claims.profile.id,
+        }),
+    };
+    let token = encode(&Header::default(), &token_claims, &state.jwt_keys.encoding)?;
+
+    Ok(Json(ImpersonateResponse {
+        impersonation,
+        token: AuthBody::new(token),
+    }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ImpersonationsResponse {
+    /// Most recent first
+    impersonations: Vec<Impersonation>,
+}
+
+async fn get_impersonations(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<ImpersonationsResponse>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(ImpersonationsResponse {
+        impersonations: Impersonation::recent(100, &mut conn).await?,
+    }))
+}
+
+/// Ends an impersonation before it expires, its token stops working right away.
+async fn end_impersonation(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<Json<Impersonation>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let impersonation = Impersonation::end(id, &mut conn)
+        .await?
+        .ok_or(WavebreakerError::NotFound("Impersonation"))?;
+    info!(
+        "Impersonation {} of player {} ended by player {}",
+        impersonation.id, impersonation.player_id, claims.profile.id
+    );
+
+    Ok(Json(impersonation))
+}
+
+/// Players flagged for sandbagging that no moderator cleared yet, see [`crate::util::sandbagging`].
+async fn get_sandbagging_flags(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<Vec<SandbaggingFlag>>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(SandbaggingFlag::active(&mut conn).await?))
+}
+
+/// Decides a flagged player isn't sandbagging. Their Casual scores count towards the rankings again.
+async fn clear_sandbagging_flag(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<Json<SandbaggingFlag>, RouteError> {
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    let flag = SandbaggingFlag::clear(id, claims.profile.id, &mut conn)
+        .await?
+        .ok_or(WavebreakerError::NotFound("Sandbagging flag"))?;
+    if flag.excluded {
+        rankings::refresh_player(flag.player_id, &flag.realm, &mut conn, &mut redis_conn).await?;
+    }
+    info!(
+        "Sandbagging flag {} of player {} cleared by player {}",
+        flag.id, flag.player_id, claims.profile.id
+    );
+
+    Ok(Json(flag))
+}
+
+/// Rides whose traffic didn't add up that no moderator cleared yet, see [`crate::util::traffic`].
+async fn get_traffic_flags(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<Vec<TrafficFlag>>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(TrafficFlag::active(100, &mut conn).await?))
+}
+
+/// Decides a flagged ride is fine.
+async fn clear_traffic_flag(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<Json<TrafficFlag>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let flag = TrafficFlag::clear(id, claims.profile.id, &mut conn)
+        .await?
+        .ok_or(WavebreakerError::NotFound("Traffic flag"))?;
+    info!(
+        "Traffic flag {} of player {} on song {} cleared by player {}",
+        flag.id, flag.player_id, flag.song_id, claims.profile.id
+    );
+
+    Ok(Json(flag))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PreviousNamesResponse {
+    username: String,
+    /// Most recent first
+    previous_names: Vec<PreviousName>,
+}
+
+async fn get_previous_names(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<Json<PreviousNamesResponse>, RouteError> {
+    use crate::schema::players;
+
+    let mut conn = state.db.get().await?;
+
+    let player: Player = players::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Player"))?;
+    let previous_names = PreviousName::for_player(player.id, &mut conn).await?;
+
+    Ok(Json(PreviousNamesResponse {
+        username: player.username,
+        previous_names,
+    }))
+}
+
+/// The scores a player hid, unhid or deleted themselves, newest first.
+async fn get_score_removals(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<ScoreRemoval>>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(ScoreRemoval::for_player(id, &mut conn).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LedgerParams {
+    /// Defaults to the main realm
+    realm: Option<String>,
+}
+
+impl LedgerParams {
+    fn realm(&self, state: &AppState) -> Result<String, RouteError> {
+        let Some(realm) = &self.realm else {
+            return Ok(MAIN_REALM.to_owned());
+        };
+        let known = realm == MAIN_REALM
+            || state
+                .config
+                .main
+                .realms
+                .iter()
+                .any(|known| known.name() == realm);
+        if !known {
+            return Err(RouteError::new_bad_request().set_public_error_message("Unknown realm"));
+        }
+        Ok(realm.clone())
+    }
+}
+
+/// Every change to the player's skill points in the realm, newest first, see [`LedgerEntry`].
+async fn get_skill_point_ledger(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+    Path(id): Path<i32>,
+    Query(params): Query<LedgerParams>,
+) -> Result<Json<Vec<LedgerEntry>>, RouteError> {
+    let realm = params.realm(&state)?;
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(LedgerEntry::for_player(id, &realm, &mut conn).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SkillPointAdjustment {
+    /// Negative to take points away, like for a cheat penalty
+    delta: i32,
+    /// Why, like the event the points are a reward for. Kept in the ledger.
+    /// Clients written before it was renamed still send it as `note`.
+    #[serde(alias = "note")]
+    reason: String,
+}
+
+/// Longest reason an adjustment can be given
+const ADJUSTMENT_REASON_MAX_LENGTH: usize = 500;
+
+/// Adds skill points to the player by hand, or takes them away. They count on top of the player's scores, also
+/// when the rankings are computed again.
+///
+/// The rankings are changed in the same transaction as the ledger, right before it's committed: if they can't be
+/// changed, the entry isn't kept. Only if the commit itself fails afterwards do the rankings have points the ledger
+/// doesn't, until they're computed again from the ledger.
+async fn adjust_skill_points(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Query(params): Query<LedgerParams>,
+    Json(payload): Json<SkillPointAdjustment>,
+) -> Result<Json<LedgerEntry>, RouteError> {
+    use crate::schema::players;
+
+    let realm = params.realm(&state)?;
+    if payload.delta == 0 {
+        return Err(RouteError::new_bad_request()
+            .set_public_error_message("An adjustment has to change the skill points"));
+    }
+    let reason = payload.reason.trim().to_owned();
+    if reason.is_empty() {
+        return Err(RouteError::new_bad_request().set_public_error_message("Reason is required"));
+    }
+    if reason.chars().count() > ADJUSTMENT_REASON_MAX_LENGTH {
+        return Err(RouteError::new_bad_request().set_public_error_message("Reason is too long"));
+    }
+
+    let mut conn = state.db.get().await?;
+
+    let player: Player = players::table.find(id).first(&mut conn).await?;
+    let player_id = player.id;
+    let moderator_id = claims.profile.id;
+    let delta = payload.delta;
+    let mut redis_conn = state.redis.get().await?;
+    let entry = conn
+        .transaction::<_, WavebreakerError, _>(|conn| {
+            let realm = &realm;
+            let reason = &reason;
+            let redis_conn = &mut redis_conn;
+            async move {
+                let entry =
+                    LedgerEntry::adjust(player_id, realm, delta, reason, moderator_id, conn)
+                        .await?;
+                // Last, so the entry is rolled back if the rankings can't be changed
+                redis_conn
+                    .add_points(
+                        player_id,
+                        vec![(RankingMode::SkillPoints.key(realm), i64::from(delta))],
+                    )
+                    .await?;
+                Ok(entry)
+            }
+            .scope_boxed()
+        })
+        .await?;
+    info!(
+        "Skill points of player {player_id} in realm {realm} adjusted by {delta} by player {moderator_id}: {reason}"
+    );
+
+    Ok(Json(entry))
+}
+
+/// The latest adjustments by moderators of anyone's skill points, newest first.
+async fn get_skill_point_adjustments(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<Vec<LedgerEntry>>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(LedgerEntry::recent_adjustments(100, &mut conn).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MergePlayerRequest {
+    target_id: i32,
+}
+
+async fn merge_player(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Json(payload): Json<MergePlayerRequest>,
+) -> Result<Json<PlayerRedirect>, RouteError> {
+    use crate::schema::players;
+
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    let player: Player = players::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Player"))?;
+    let redirect = player
+        .merge_into(
+            payload.target_id,
+            Some(claims.profile.id),
+            &mut conn,
+            &mut redis_conn,
+        )
+        .await?;
+    info!(
+        "Player {} merged into {} by player {}",
+        redirect.player_id, redirect.target_id, claims.profile.id
+    );
+
+    Ok(Json(redirect))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RemoveScoreRequest {
+    /// Told to the player
+    reason: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RideSourcesResponse {
+    /// Most recent first
+    sources: Vec<RideSource>,
+}
+
+async fn get_ride_sources(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<Json<RideSourcesResponse>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(RideSourcesResponse {
+        sources: RideSource::for_score(id, &mut conn).await?,
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct ClientsParams {
+    /// How far back rides are counted
+    days: i64,
+}
+
+impl Default for ClientsParams {
+    fn default() -> Self {
+        Self { days: 7 }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ClientsResponse {
+    /// Most rides first
+    clients: Vec<ClientSummary>,
+}
+
+/// Which clients rides were submitted from lately, see [`crate::util::client_source`].
+async fn get_clients(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+    Query(params): Query<ClientsParams>,
+) -> Result<Json<ClientsResponse>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let since = OffsetDateTime::now_utc() - time::Duration::days(params.days.clamp(1, 365));
+    Ok(Json(ClientsResponse {
+        clients: RideSource::summarize(since, 100, &mut conn).await?,
+    }))
+}
+
+/// Deletes a score and tells the player about it the next time their game fetches the news.
+async fn remove_score(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Json(payload): Json<RemoveScoreRequest>,
+) -> Result<(), RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let score: Score = Score::all().find(id).first(&mut conn).await?;
+    // Scores of deleted songs are deleted too, so the song is always there
+    let song: Song = Song::all().find(score.song_id).first(&mut conn).await?;
+
+    let i18n = &state.config.i18n;
+    let locale = Player::locale_of(score.player_id, i18n, &mut conn).await?;
+    let league = format!("{:?}", score.league);
+    let mut text = i18n.text(
+        &locale,
+        Text::ScoreRemoved,
+        &[
+            ("score", &score.score),
+            ("artist", &song.artist),
+            ("title", &song.title),
+            ("league", &league),
+        ],
+    );
+    if let Some(reason) = payload
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty())
+    {
+        let reason = i18n.text(&locale, Text::RemovalReason, &[("reason", &reason)]);
+        text = format!("{text}\n{reason}");
+    }
+    let text = check_news_text(&text)?;
+
+    let player_id = score.player_id;
+    let moderator_id = claims.profile.id;
+    // The rankings are only changed once the transaction is committed
+    let store = conn
+        .transaction::<_, WavebreakerError, _>(|conn| {
+            async move {
+                let mut store = DeferredRankingStore::default();
+                ScoreRemoval::record_moderation(
+                    &score,
+                    ScoreRemovalAction::Remove,
+                    moderator_id,
+                    conn,
+                )
+                .await?;
+                score.delete(conn, &mut store).await?;
+                PlayerMessage::send(player_id, text, Some(moderator_id), conn).await?;
+                Ok(store)
+            }
+            .scope_boxed()
+        })
+        .await?;
+    store.apply(&mut state.redis.get().await?).await?;
+    notify_moderation(player_id, text, &mut conn).await;
+    info!("Score {id} removed by player {}", claims.profile.id);
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingAppeal {
+    appeal: ScoreAppeal,
+    /// `None` if the score was purged before the appeal was filed, which shouldn't happen
+    score: Option<Score>,
+    player: PlayerPublic,
+}
+
+/// Unresolved score appeals, oldest first so nothing waits forever.
+async fn get_appeals(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<Vec<PendingAppeal>>, RouteError> {
+    use crate::schema::{players, score_appeals, scores};
+
+    let mut conn = state.db.get().await?;
+
+    let appeals: Vec<(ScoreAppeal, Option<Score>, Player)> = ScoreAppeal::pending()
+        .left_join(scores::table)
+        .inner_join(players::table)
+        .order(score_appeals::appealed_at.asc())
+        .select((
+            ScoreAppeal::as_select(),
+            Option::<Score>::as_select(),
+            Player::as_select(),
+        ))
+        .load(&mut conn)
+        .await?;
+
+    Ok(Json(
+        appeals
+            .into_iter()
+            .map(|(appeal, score, player)| PendingAppeal {
+                appeal,
+                score,
+                player: player.into(),
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResolveAppealRequest {
+    action: AppealResolution,
+    /// Told to the player
+    note: Option<String>,
+}
+
+/// Accepts an appeal, restoring the score, or rejects it. Either way, the player is told the next time their
+/// game fetches the news. Accepting fails with a 409 if the player has set a new score on the same song and league
+/// since the score was removed.
+async fn resolve_appeal(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Json(payload): Json<ResolveAppealRequest>,
+) -> Result<Json<ScoreAppeal>, RouteError> {
+    use crate::schema::{scores, songs};
+
+    let mut conn = state.db.get().await?;
+
+    let appeal: ScoreAppeal = ScoreAppeal::pending()
+        .find(id)
+        .select(ScoreAppeal::as_select())
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Pending appeal"))?;
+    let score: Option<Score> = match appeal.score_id {
+        Some(score_id) => scores::table
+            .find(score_id)
+            .first(&mut conn)
+            .await
+            .optional()?,
+        None => None,
+    };
+    let song: Option<Song> = match &score {
+        Some(score) => songs::table
+            .find(score.song_id)
+            .first(&mut conn)
+            .await
+            .optional()?,
+        None => None,
+    };
+    // The scores of a deleted song are only brought back with it
+    if payload.action == AppealResolution::Accept
+        && song.as_ref().is_some_and(|song| song.deleted_at.is_some())
+    {
+        return Err(RouteError::new_conflict()
+            .set_public_error_message("The score's song was deleted, restore the song first"));
+    }
+
+    let note = payload
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|note| !note.is_empty());
+    let i18n = &state.config.i18n;
+    let locale = Player::locale_of(appeal.player_id, i18n, &mut conn).await?;
+    let appealed = match (&score, &song) {
+        (Some(score), Some(song)) => i18n.text(
+            &locale,
+            Text::AppealedScore,
+            &[
+                ("score", &score.score),
+                ("artist", &song.artist),
+                ("title", &song.title),
+                ("league", &format!("{:?}", score.league)),
+            ],
+        ),
+        _ => i18n.text(&locale, Text::AppealedScoreGone, &[]),
+    };
+    let resolution = match payload.action {
+        AppealResolution::Accept => Text::AppealAccepted,
+        AppealResolution::Reject => Text::AppealRejected,
+    };
+    let mut text = i18n.text(&locale, resolution, &[("appealed", &appealed)]);
+    if let Some(note) = note {
+        text = format!("{text}\n{note}");
+    }
+    let text = check_news_text(&text)?;
+
+    let moderator_id = claims.profile.id;
+    let (resolved, store) = conn
+        .transaction::<_, WavebreakerError, _>(|conn| {
+            let (appeal, score) = (&appeal, &score);
+            async move {
+                let mut store = DeferredRankingStore::default();
+                if payload.action == AppealResolution::Accept {
+                    let score = score.as_ref().ok_or(WavebreakerError::NotFound("Score"))?;
+                    score.restore(conn, &mut store).await?;
+                    ScoreRemoval::record_moderation(
+                        score,
+                        ScoreRemovalAction::Restore,
+                        moderator_id,
+                        conn,
+                    )
+                    .await?;
+                }
+                let resolved = appeal
+                    .resolve(payload.action, note, moderator_id, conn)
+                    .await?;
+                PlayerMessage::send(appeal.player_id, text, Some(moderator_id), conn).await?;
+                Ok((resolved, store))
+            }
+            .scope_boxed()
+        })
+        .await?;
+    store.apply(&mut state.redis.get().await?).await?;
+    notify_moderation(appeal.player_id, text, &mut conn).await;
+    info!(
+        "Appeal {} {} by player {}",
+        resolved.id,
+        payload.action.as_str(),
+        moderator_id
+    );
+
+    Ok(Json(resolved))
+}
+
+/// All API keys, including revoked ones.
+async fn get_api_keys(
+    State(state): State<AppState>,
+    _claims: StaffClaims,
+) -> Result<Json<Vec<ApiKey>>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(ApiKey::all(&mut conn).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiKeyQuotas {
+    requests_per_day: i32,
+    burst_per_minute: i32,
+}
+
+impl ApiKeyQuotas {
+    fn check(&self) -> Result<(), RouteError> {
+        if self.requests_per_day < 1 || self.burst_per_minute < 1 {
+            return Err(RouteError::new_bad_request()
+                .set_public_error_message("Quotas have to allow at least one request"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateApiKeyRequest {
+    name: String,
+    #[serde(flatten)]
+    quotas: ApiKeyQuotas,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreatedApiKey {
+    api_key: ApiKey,
+    /// Only shown this one time, it can't be looked up later
+    key: String,
+}
+
+/// Creates an API key for a community site, see [`crate::util::api_quota`].
+async fn create_api_key(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<Json<CreatedApiKey>, RouteError> {
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(RouteError::new_bad_request().set_public_error_message("Name is missing"));
+    }
+    payload.quotas.check()?;
+
+    let mut conn = state.db.get().await?;
+
+    let (api_key, key) = ApiKey::create(
+        name,
+        payload.quotas.requests_per_day,
+        payload.quotas.burst_per_minute,
+        claims.profile.id,
+        &mut conn,
+    )
+    .await?;
+    info!(
+        "API key {} ({}) created by player {}",
+        api_key.id, api_key.name, claims.profile.id
+    );
+
+    Ok(Json(CreatedApiKey { api_key, key }))
+}
+
+/// Changes the quotas of an API key.
+async fn update_api_key_quotas(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+    Json(payload): Json<ApiKeyQuotas>,
+) -> Result<Json<ApiKey>, RouteError> {
+    use crate::schema::api_keys;
+
+    payload.check()?;
+
+    let mut conn = state.db.get().await?;
+
+    let api_key: ApiKey = api_keys::table
+        .find(id)
+        .select(ApiKey::as_select())
+        .first(&mut conn)
+        .await?;
+    let api_key = api_key
+        .set_quotas(
+            payload.requests_per_day,
+            payload.burst_per_minute,
+            &mut conn,
+        )
+        .await?;
+    info!(
+        "Quotas of API key {} set to {}/day, {}/minute by player {}",
+        api_key.id, api_key.requests_per_day, api_key.burst_per_minute, claims.profile.id
+    );
+
+    Ok(Json(api_key))
+}
+
+/// Revokes an API key. It's kept, so it's still visible who had access.
+async fn revoke_api_key(
+    State(state): State<AppState>,
+    StaffClaims(claims): StaffClaims,
+    Path(id): Path<i32>,
+) -> Result<Json<ApiKey>, RouteError> {
+    use crate::schema::api_keys;
+
+    let mut conn = state.db.get().await?;
+
+    let api_key: ApiKey = api_keys::table
+        .find(id)
+        .select(ApiKey::as_select())
+        .first(&mut conn)
+        .await?;
+    let api_key = api_key.revoke(&mut conn).await?;
+    info!(
+        "API key {} revoked by player {}",
+        api_key.id, claims.profile.id
+    );
+
+    Ok(Json(api_key))
+}
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Path, State},
+    routing::get,
+    Json, Router,
+};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+
+use crate::{
+    models::{
+        extra_song_info::{AlbumProgress, ExtraSongInfo},
+        scores::Score,
+        songs::Song,
+    },
+    util::{
+        errors::{RouteError, WavebreakerError},
+        jwt::Claims,
+        realm::MAIN_REALM,
+    },
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/:mbid", get(get_album))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AlbumResponse {
+    mbid: String,
+    title: Option<String>,
+    /// How many tracks the album has, going by its smallest edition. `None` if `MusicBrainz` didn't say.
+    track_count: Option<i32>,
+    songs: Vec<AlbumSong>,
+    /// How much of the album the player rode, only there with a token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<AlbumProgress>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AlbumSong {
+    #[serde(flatten)]
+    song: Song,
+    extra_info: ExtraSongInfo,
+    /// Whether the player rode the song, only there with a token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ridden: Option<bool>,
+}
+
+/// The songs of an album in the main realm, a `MusicBrainz` release group.
+/// With a token, also how much of it the player rode, see [`AlbumProgress`].
+async fn get_album(
+    State(state): State<AppState>,
+    claims: Option<Claims>,
+    Path(mbid): Path<String>,
+) -> Result<Json<AlbumResponse>, RouteError> {
+    use crate::schema::scores::dsl::{player_id, song_id};
+
+    let mut conn = state.db_read.get().await?;
+
+    let songs = ExtraSongInfo::songs_on_album(&mbid, MAIN_REALM, &mut conn).await?;
+    if songs.is_empty() {
+        return Err(WavebreakerError::NotFound("Album").into());
+    }
+    let title = songs
+        .iter()
+        .find_map(|(_, info)| info.release_group_title.clone());
+    let track_count = songs
+        .iter()
+        .filter_map(|(_, info)| info.release_track_count)
+        .min();
+
+    let ridden: Option<HashSet<i32>> = match &claims {
+        Some(claims) => Some(
+            Score::all()
+                .filter(player_id.eq(claims.profile.id))
+                .filter(song_id.eq_any(songs.iter().map(|(song, _)| song.id)))
+                .select(song_id)
+                .load::<i32>(&mut conn)
+                .await?
+                .into_iter()
+                .collect(),
+        ),
+        None => None,
+    };
+
+    let progress = ridden.as_ref().map(|ridden| {
+        AlbumProgress::from_songs(
+            mbid.clone(),
+            title.clone(),
+            songs
+                .iter()
+                .map(|(song, info)| (info, ridden.contains(&song.id))),
+        )
+    });
+
+    Ok(Json(AlbumResponse {
+        mbid,
+        title,
+        track_count,
+        songs: songs
+            .into_iter()
+            .map(|(song, extra_info)| AlbumSong {
+                ridden: ridden.as_ref().map(|ridden| ridden.contains(&song.id)),
+                song,
+                extra_info,
+            })
+            .collect(),
+        progress,
+    }))
+}
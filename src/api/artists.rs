@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
+
+use crate::{
+    models::{extra_song_info::ExtraSongInfo, players::PlayerPublic, scores::Score, songs::Song},
+    util::{
+        errors::{RouteError, WavebreakerError},
+        game_types::League,
+        musicbrainz::is_mbid,
+        realm::MAIN_REALM,
+    },
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/:artist", get(get_artist))
+}
+
+/// Most songs listed for one artist.
+const MAX_ARTIST_SONGS: i64 = 500;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ArtistResponse {
+    /// The `MusicBrainz` ID of the artist, if that's what was asked for
+    mbid: Option<String>,
+    /// The artist's name from `MusicBrainz`, or the name that was asked for
+    name: Option<String>,
+    /// Plays of all the songs together
+    total_plays: i64,
+    songs: Vec<ArtistSong>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ArtistSong {
+    #[serde(flatten)]
+    song: Song,
+    /// How often the song was played, in every league
+    plays: i64,
+    /// The best score in each league anyone rode the song in
+    top_scores: Vec<TopScore>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TopScore {
+    league: League,
+    score_id: i32,
+    score: i32,
+    player: PlayerPublic,
+}
+
+/// The songs of an artist in the main realm, for artist pages.
+/// The artist is either a MusicBrainz artist ID or a name, see [`Song::by_artist_name`].
+#[allow(clippy::doc_markdown)]
+async fn get_artist(
+    State(state): State<AppState>,
+    Path(artist): Path<String>,
+) -> Result<Json<ArtistResponse>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let (mbid, name, songs) = if is_mbid(&artist) {
+        let songs =
+            ExtraSongInfo::songs_by_artist(&artist, MAIN_REALM, MAX_ARTIST_SONGS, &mut conn)
+                .await?;
+        // Songs crediting only this artist have just their name, others have everyone's
+        let name = songs
+            .iter()
+            .find(|(_, info)| info.artist_mbids.len() == 1)
+            .or_else(|| songs.first())
+            .and_then(|(_, info)| info.musicbrainz_artist.clone());
+        let songs = songs.into_iter().map(|(song, _)| song).collect();
+        (Some(artist), name, songs)
+    } else {
+        let name = artist.trim().to_owned();
+        let songs = Song::by_artist_name(&name, MAIN_REALM, MAX_ARTIST_SONGS, &mut conn).await?;
+        (None, Some(name), songs)
+    };
+    if songs.is_empty() {
+        return Err(WavebreakerError::NotFound("Artist").into());
+    }
+
+    let song_ids: Vec<i32> = songs.iter().map(|song| song.id).collect();
+    let plays: HashMap<i32, i64> = Score::plays_of_songs(&song_ids, &mut conn)
+        .await?
+        .into_iter()
+        .collect();
+    let mut top_scores: HashMap<i32, Vec<TopScore>> = HashMap::new();
+    for (score, player) in Score::tops_of_songs(&song_ids, &mut conn).await? {
+        top_scores.entry(score.song_id).or_default().push(TopScore {
+            league: score.league,
+            score_id: score.id,
+            score: score.score,
+            player,
+        });
+    }
+
+    Ok(Json(ArtistResponse {
+        mbid,
+        name,
+        total_plays: plays.values().sum(),
+        songs: songs
+            .into_iter()
+            .map(|song| ArtistSong {
+                plays: plays.get(&song.id).copied().unwrap_or_default(),
+                top_scores: top_scores.remove(&song.id).unwrap_or_default(),
+                song,
+            })
+            .collect(),
+    }))
+}
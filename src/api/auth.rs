@@ -60,6 +60,7 @@ async fn auth_return(
     let claims = Claims {
         profile: player,
         exp,
+        impersonation: None,
     };
     // Create the authorization token
     let token = encode(&Header::default(), &claims, &state.jwt_keys.encoding)?;
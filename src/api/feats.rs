@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query, State},
+    routing::get,
+    Json, Router,
+};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::{
+        players::{Player, PlayerPublic},
+        scores::FeatLeader,
+        songs::Song,
+    },
+    util::{errors::RouteError, game_types::Feat},
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/:feat", get(get_feat_leaderboard))
+}
+
+const FEAT_PAGE_SIZE: i64 = 50;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct FeatParams {
+    #[serde(default)]
+    page: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct FeatPlace {
+    /// 1 is the top
+    rank: i64,
+    #[serde(flatten)]
+    player: PlayerPublic,
+    /// How many of the player's scores have the feat
+    scores: i64,
+}
+
+/// Ranks the players by how many of their scores in the main realm have the feat.
+async fn get_feat_leaderboard(
+    State(state): State<AppState>,
+    Path(feat): Path<Feat>,
+    Query(params): Query<FeatParams>,
+) -> Result<Json<Vec<FeatPlace>>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    Ok(Json(feat_places(feat, None, params, &mut conn).await?))
+}
+
+/// Ranks the players by how many of their scores on the song have the feat, one per league at most.
+pub(super) async fn get_song_feat_leaderboard(
+    State(state): State<AppState>,
+    Path((id, feat)): Path<(i32, Feat)>,
+    Query(params): Query<FeatParams>,
+) -> Result<Json<Vec<FeatPlace>>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let song: Song = Song::all().find(id).first(&mut conn).await?;
+
+    Ok(Json(
+        feat_places(feat, Some(song.id), params, &mut conn).await?,
+    ))
+}
+
+async fn feat_places(
+    feat: Feat,
+    song_id: Option<i32>,
+    params: FeatParams,
+    conn: &mut AsyncPgConnection,
+) -> Result<Vec<FeatPlace>, RouteError> {
+    use crate::schema::players::dsl::*;
+
+    let page = params.page.max(0);
+    let leaders = FeatLeader::load(feat, song_id, page, FEAT_PAGE_SIZE, conn).await?;
+
+    let leader_ids: Vec<i32> = leaders.iter().map(|leader| leader.player_id).collect();
+    let mut found: HashMap<i32, Player> = Player::all()
+        .filter(id.eq_any(&leader_ids))
+        .load::<Player>(conn)
+        .await?
+        .into_iter()
+        .map(|player| (player.id, player))
+        .collect();
+
+    Ok(leaders
+        .into_iter()
+        .zip(1..)
+        .filter_map(|(leader, place)| {
+            found.remove(&leader.player_id).map(|player| FeatPlace {
+                rank: page * FEAT_PAGE_SIZE + place,
+                player: player.into(),
+                scores: leader.scores,
+            })
+        })
+        .collect())
+}
@@ -2,22 +2,45 @@ use axum::{routing::get, Json, Router};
 use serde::Serialize;
 
 use crate::{
-    util::{errors::RouteError, radio::get_radio_songs},
+    util::{clock, errors::RouteError, radio::get_radio_songs},
     AppState,
 };
 
+mod activity;
+mod admin;
+mod albums;
+mod artists;
 mod auth;
+mod feats;
 mod players;
+mod records;
 mod rivals;
+mod scores;
+mod shouts;
+mod song_requests;
 mod songs;
+mod stats;
+mod vehicles;
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/healthCheck", get(health_check))
+        .route("/time", get(get_time))
         .nest("/songs", songs::routes())
+        .nest("/artists", artists::routes())
+        .nest("/albums", albums::routes())
         .nest("/players", players::routes())
+        .nest("/records", records::routes())
         .nest("/auth", auth::routes())
         .nest("/rivals", rivals::routes())
+        .nest("/scores", scores::routes())
+        .nest("/shouts", shouts::routes())
+        .nest("/songRequests", song_requests::routes())
+        .nest("/admin", admin::routes())
+        .nest("/activity", activity::routes())
+        .nest("/vehicles", vehicles::routes())
+        .nest("/feats", feats::routes())
+        .nest("/stats", stats::routes())
 }
 
 #[derive(Serialize)]
@@ -43,3 +66,21 @@ async fn health_check() -> Result<Json<HealthCheck>, RouteError> {
         radio_status,
     }))
 }
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerTime {
+    /// Unix seconds, the same time the game is sent
+    unix_time: i64,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    time: time::OffsetDateTime,
+}
+
+/// The server time, see [`clock`]. For clients counting down to something, like the end of an event.
+async fn get_time() -> Json<ServerTime> {
+    let now = clock::now();
+    Json(ServerTime {
+        unix_time: now.unix_timestamp(),
+        time: now,
+    })
+}
@@ -1,20 +1,62 @@
+use std::collections::HashMap;
+
 use axum::{
-    extract::{Path, State},
-    routing::get,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post, put},
     Json, Router,
 };
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
-use serde::Serialize;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tracing::info;
 
 use crate::{
-    models::players::{Player, PlayerPublic},
-    util::errors::RouteError,
+    jobs::Job,
+    models::{
+        extra_song_info::AlbumProgress,
+        notification_links::{NotificationChannel, NotificationLink},
+        player_names::PreviousName,
+        player_redirects::PlayerRedirect,
+        player_slugs::PreviousSlug,
+        players::{Player, PlayerPublic, SteamIdWrapper},
+        scores::FeatCount,
+        songs::Song,
+        vehicle_usage::VehicleUsage,
+    },
+    util::{
+        activity::forget_player, errors::RouteError, jwt::Claims, notify, rankings::RankingMode,
+        realm::MAIN_REALM, redis_keys, redis_ops, slug::parse_slug, time_zone::is_time_zone_name,
+    },
     AppState,
 };
 
 pub fn routes() -> Router<AppState> {
-    Router::new().route("/:id", get(get_player))
+    Router::new()
+        .route("/:id", get(get_player))
+        .route("/:id/albums", get(get_player_albums))
+        .route("/by-slug/:slug", get(get_player_by_slug))
+        .route("/lookup", post(lookup_players))
+        .route("/rankings", get(get_rankings))
+        .route("/self/shareActivity", put(set_share_activity))
+        .route("/self/locale", put(set_locale))
+        .route("/self/timeZone", put(set_time_zone))
+        .route("/self/slug", put(set_slug))
+        .route("/self/notifications", get(get_notification_links))
+        .route(
+            "/self/notifications/:channel",
+            put(link_notifications).delete(unlink_notifications),
+        )
+        .route(
+            "/self/notifications/:channel/verify",
+            post(verify_notifications),
+        )
+        .route(
+            "/self/notifications/:channel/preferences",
+            put(set_notification_preferences),
+        )
 }
 
 #[derive(Serialize)]
@@ -22,19 +64,663 @@ pub fn routes() -> Router<AppState> {
 struct PlayerResponse {
     #[serde(flatten)]
     player: PlayerPublic,
+    /// How many songs the player was the first to ride
+    songs_discovered: i64,
+    /// Characters the player rode with, most ridden first
+    vehicles: Vec<VehicleUsage>,
+    /// How many of the player's scores have each feat
+    feats: Vec<FeatCount>,
+    /// Names the player went by before, most recent first. Only there if `profiles.show_previous_names` is on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_names: Option<Vec<PreviousName>>,
 }
 
+/// Players that were merged into another one redirect to it, see [`Player::merge_into`].
 async fn get_player(
     State(state): State<AppState>,
     Path(id): Path<i32>,
-) -> Result<Json<PlayerResponse>, RouteError> {
+) -> Result<Response, RouteError> {
     use crate::schema::players;
 
-    let mut conn = state.db.get().await?;
+    let mut conn = state.db_read.get().await?;
 
-    let player: Player = players::table.find(id).first(&mut conn).await?;
+    let Some(player) = players::table
+        .find(id)
+        .first::<Player>(&mut conn)
+        .await
+        .optional()?
+    else {
+        let target = PlayerRedirect::target_of(id, &mut conn)
+            .await?
+            .ok_or_else(RouteError::new_not_found)?;
+        // Relative, so it works under any prefix
+        return Ok(Redirect::permanent(&target.to_string()).into_response());
+    };
+
+    Ok(Json(player_response(&state, player, &mut conn).await?).into_response())
+}
+
+/// Old slugs redirect to the player's current one, or their ID if they don't have one anymore.
+async fn get_player_by_slug(
+    State(state): State<AppState>,
+    Path(requested_slug): Path<String>,
+) -> Result<Response, RouteError> {
+    use crate::schema::players::dsl::*;
+
+    let requested_slug = requested_slug.to_ascii_lowercase();
+    let mut conn = state.db_read.get().await?;
+
+    let Some(player) = Player::all()
+        .filter(slug.eq(&requested_slug))
+        .first(&mut conn)
+        .await
+        .optional()?
+    else {
+        let owner = PreviousSlug::owner_of(&requested_slug, &mut conn)
+            .await?
+            .ok_or_else(RouteError::new_not_found)?;
+        let current_slug: Option<String> =
+            players.find(owner).select(slug).first(&mut conn).await?;
+        // Relative, so it works under any prefix
+        let target = current_slug.unwrap_or_else(|| format!("../{owner}"));
+        return Ok(Redirect::permanent(&target).into_response());
+    };
+
+    Ok(Json(player_response(&state, player, &mut conn).await?).into_response())
+}
 
-    Ok(Json(PlayerResponse {
+/// The public profile of a player, for [`get_player`] and [`get_player_by_slug`].
+async fn player_response(
+    state: &AppState,
+    player: Player,
+    conn: &mut diesel_async::AsyncPgConnection,
+) -> Result<PlayerResponse, RouteError> {
+    use crate::schema::songs;
+
+    let songs_discovered: i64 = Song::all()
+        .filter(songs::first_rider_id.eq(player.id))
+        .count()
+        .get_result(conn)
+        .await?;
+    let vehicles = VehicleUsage::for_player(player.id, conn).await?;
+    let feats = FeatCount::for_player(player.id, conn).await?;
+    let previous_names = if state.config.profiles.show_previous_names {
+        Some(PreviousName::for_player(player.id, conn).await?)
+    } else {
+        None
+    };
+
+    Ok(PlayerResponse {
         player: player.into(),
+        songs_discovered,
+        vehicles,
+        feats,
+        previous_names,
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ShareActivityRequest {
+    share_activity: bool,
+}
+
+/// Lets players decide whether their recent rides are shown publicly, see [`crate::util::activity`].
+async fn set_share_activity(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(payload): Json<ShareActivityRequest>,
+) -> Result<(), RouteError> {
+    use crate::schema::players::dsl::*;
+
+    let mut conn = state.db.get().await?;
+
+    let player: Player = diesel::update(players.find(claims.profile.id))
+        .set(share_activity.eq(payload.share_activity))
+        .get_result(&mut conn)
+        .await?;
+
+    let mut redis_conn = state.redis.get().await?;
+    // send_ride has to see the change right away
+    Player::forget_cached(player.steam_id.0, &mut redis_conn).await?;
+    if !payload.share_activity {
+        forget_player(claims.profile.id, &mut redis_conn).await?;
+    }
+
+    info!(
+        "Player {} set activity sharing to {}",
+        claims.profile.id, payload.share_activity
+    );
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LocaleRequest {
+    /// `None` to have it picked per request again
+    locale: Option<String>,
+}
+
+/// Sets the locale the server's texts are written in for the player, see [`crate::util::i18n`].
+async fn set_locale(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(payload): Json<LocaleRequest>,
+) -> Result<(), RouteError> {
+    use crate::schema::players::dsl::*;
+
+    let new_locale = payload
+        .locale
+        .as_deref()
+        .map(|new_locale| new_locale.trim().to_ascii_lowercase());
+    if let Some(new_locale) = &new_locale {
+        if !state.config.i18n.is_available(new_locale) {
+            return Err(RouteError::new_bad_request()
+                .set_public_error_message(&format!("Locale {new_locale} isn't available")));
+        }
+    }
+
+    let mut conn = state.db.get().await?;
+
+    let player: Player = diesel::update(players.find(claims.profile.id))
+        .set(locale.eq(&new_locale))
+        .get_result(&mut conn)
+        .await?;
+
+    let mut redis_conn = state.redis.get().await?;
+    Player::forget_cached(player.steam_id.0, &mut redis_conn).await?;
+
+    info!(
+        "Player {} set their locale to {:?}",
+        claims.profile.id, new_locale
+    );
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TimeZoneRequest {
+    /// Like `Europe/Berlin`, `None` to let clients pick again
+    time_zone: Option<String>,
+}
+
+/// Stores the time zone the player wants times shown in, see [`crate::util::time_zone`].
+async fn set_time_zone(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(payload): Json<TimeZoneRequest>,
+) -> Result<(), RouteError> {
+    use crate::schema::players::dsl::*;
+
+    if let Some(new_time_zone) = &payload.time_zone {
+        if !is_time_zone_name(new_time_zone) {
+            return Err(RouteError::new_bad_request()
+                .set_public_error_message(&format!("{new_time_zone} isn't a time zone")));
+        }
+    }
+
+    let mut conn = state.db.get().await?;
+
+    let player: Player = diesel::update(players.find(claims.profile.id))
+        .set(time_zone.eq(&payload.time_zone))
+        .get_result(&mut conn)
+        .await?;
+
+    let mut redis_conn = state.redis.get().await?;
+    Player::forget_cached(player.steam_id.0, &mut redis_conn).await?;
+
+    info!(
+        "Player {} set their time zone to {:?}",
+        claims.profile.id, payload.time_zone
+    );
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SlugRequest {
+    /// `None` to remove it, so the profile is only linked by ID
+    slug: Option<String>,
+}
+
+/// Claims a slug for the player's profile links, see [`crate::util::slug`].
+async fn set_slug(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(payload): Json<SlugRequest>,
+) -> Result<(), RouteError> {
+    let new_slug = match payload.slug.as_deref() {
+        Some(new_slug) => {
+            let Some(new_slug) = parse_slug(new_slug) else {
+                return Err(RouteError::new_bad_request().set_public_error_message(&format!(
+                    "Slugs are {}-{} letters, digits, - and _, start and end with a letter or digit and aren't only digits",
+                    crate::util::slug::MIN_LENGTH,
+                    crate::util::slug::MAX_LENGTH
+                )));
+            };
+            if state.config.text_filter.check(&new_slug).is_some() {
+                return Err(RouteError::new_bad_request()
+                    .set_public_error_message(&format!("The slug {new_slug} isn't allowed")));
+            }
+            Some(new_slug)
+        }
+        None => None,
+    };
+
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    // The player in the token might have an outdated slug
+    let player = Player::all()
+        .find(claims.profile.id)
+        .first::<Player>(&mut conn)
+        .await?;
+    player
+        .set_slug(
+            new_slug.as_deref(),
+            time::Duration::days(state.config.profiles.slug_change_days),
+            &mut conn,
+            &mut redis_conn,
+        )
+        .await?;
+
+    info!(
+        "Player {} set their slug to {:?}",
+        claims.profile.id, new_slug
+    );
+
+    Ok(())
+}
+
+/// Lists the email addresses and Discord accounts the player linked for notifications, see [`crate::util::notify`].
+async fn get_notification_links(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<NotificationLink>>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    Ok(Json(
+        NotificationLink::for_player(claims.profile.id, &mut conn).await?,
+    ))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LinkNotificationsRequest {
+    /// The email address, or the ID of the Discord account
+    address: String,
+}
+
+/// Links an email address or Discord account to get notifications on, and sends a code to it to confirm it's theirs.
+/// Linking again replaces the address and sends a new code.
+async fn link_notifications(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(channel): Path<NotificationChannel>,
+    Json(payload): Json<LinkNotificationsRequest>,
+) -> Result<Json<NotificationLink>, RouteError> {
+    let settings = &state.config.notifications;
+    if !notify::is_available(settings, channel) {
+        return Err(
+            RouteError::new_bad_request().set_public_error_message(&format!(
+                "This server doesn't send notifications on {}",
+                channel.as_str()
+            )),
+        );
+    }
+    let Some(address) = notify::parse_address(channel, &payload.address) else {
+        return Err(
+            RouteError::new_bad_request().set_public_error_message(&format!(
+                "That isn't an address notifications can be sent to on {}",
+                channel.as_str()
+            )),
+        );
+    };
+
+    // Every code is a message to an address that might not be theirs
+    let mut redis_conn = state.redis.get().await?;
+    let key = redis_keys::notification_codes(claims.profile.id);
+    let count = redis_ops::incr_in_window(&key, 60 * 60, &mut redis_conn)
+        .await?
+        .count;
+    if count > i64::from(settings.codes_per_hour) {
+        return Err(RouteError::from_status(StatusCode::TOO_MANY_REQUESTS)
+            .set_public_error_message("Too many codes were sent, try again later"));
+    }
+
+    let mut conn = state.db.get().await?;
+
+    let link = NotificationLink::link(claims.profile.id, channel, &address, &mut conn).await?;
+    Job::SendNotificationCode {
+        player_id: claims.profile.id,
+        channel,
+    }
+    .enqueue(&mut conn)
+    .await?;
+
+    info!(
+        "Player {} linked {} for notifications",
+        claims.profile.id,
+        channel.as_str()
+    );
+
+    Ok(Json(link))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyNotificationsRequest {
+    code: String,
+}
+
+/// Confirms a linked address with the code that was sent to it, notifications are only sent after that.
+async fn verify_notifications(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(channel): Path<NotificationChannel>,
+    Json(payload): Json<VerifyNotificationsRequest>,
+) -> Result<Json<NotificationLink>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let link = NotificationLink::find(claims.profile.id, channel, &mut conn)
+        .await?
+        .ok_or_else(RouteError::new_not_found)?;
+    let Some(link) = link.verify(&payload.code, &mut conn).await? else {
+        return Err(RouteError::new_bad_request().set_public_error_message(
+            "The code is wrong or expired, link the address again for a new one",
+        ));
+    };
+
+    info!(
+        "Player {} confirmed {} for notifications",
+        claims.profile.id,
+        channel.as_str()
+    );
+
+    Ok(Json(link))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NotificationPreferencesRequest {
+    /// Whether to be notified when someone takes the top spot on a leaderboard from the player
+    dethrones: bool,
+    /// Whether to be notified of messages from moderators
+    moderation: bool,
+}
+
+/// Picks which notifications the player gets on a linked channel.
+async fn set_notification_preferences(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(channel): Path<NotificationChannel>,
+    Json(payload): Json<NotificationPreferencesRequest>,
+) -> Result<Json<NotificationLink>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let link = NotificationLink::set_preferences(
+        claims.profile.id,
+        channel,
+        payload.dethrones,
+        payload.moderation,
+        &mut conn,
+    )
+    .await?
+    .ok_or_else(RouteError::new_not_found)?;
+
+    Ok(Json(link))
+}
+
+/// Stops notifications on a channel and forgets the address.
+async fn unlink_notifications(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(channel): Path<NotificationChannel>,
+) -> Result<(), RouteError> {
+    let mut conn = state.db.get().await?;
+
+    if !NotificationLink::unlink(claims.profile.id, channel, &mut conn).await? {
+        return Err(RouteError::new_not_found());
+    }
+
+    info!(
+        "Player {} unlinked {} from notifications",
+        claims.profile.id,
+        channel.as_str()
+    );
+
+    Ok(())
+}
+
+/// How many Steam IDs can be looked up at once.
+const MAX_LOOKUP_IDS: usize = 100;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LookupRequest {
+    /// 64-bit Steam IDs, as strings since they don't fit into a JavaScript number
+    steam_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LookedUpPlayer {
+    steam_id: String,
+    #[serde(flatten)]
+    player: PlayerPublic,
+    /// Rank in the main realm's skill point rankings, 1 is the top. `None` if the player isn't ranked.
+    rank: Option<i64>,
+    skill_points: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LookupResponse {
+    players: Vec<LookedUpPlayer>,
+    /// Steam IDs that don't belong to anyone on this server
+    not_found: Vec<String>,
+}
+
+/// Resolves many Steam IDs at once, for companion tools like overlays and Discord bots.
+async fn lookup_players(
+    State(state): State<AppState>,
+    Json(payload): Json<LookupRequest>,
+) -> Result<Json<LookupResponse>, RouteError> {
+    use crate::schema::players::dsl::*;
+
+    if payload.steam_ids.len() > MAX_LOOKUP_IDS {
+        return Err(
+            RouteError::new_bad_request().set_public_error_message(&format!(
+                "At most {MAX_LOOKUP_IDS} Steam IDs can be looked up at once"
+            )),
+        );
+    }
+    let mut wrapped_ids = Vec::with_capacity(payload.steam_ids.len());
+    for requested in &payload.steam_ids {
+        let Ok(parsed) = requested.parse::<u64>() else {
+            return Err(RouteError::new_bad_request()
+                .set_public_error_message(&format!("{requested} isn't a Steam ID")));
+        };
+        wrapped_ids.push(SteamIdWrapper(parsed.into()));
+    }
+
+    let mut conn = state.db_read.get().await?;
+    let found: Vec<Player> = Player::all()
+        .filter(steam_id.eq_any(wrapped_ids))
+        .load(&mut conn)
+        .await?;
+
+    let mut pipe = redis::pipe();
+    for player in &found {
+        pipe.zrevrank(redis_keys::skill_points(MAIN_REALM), player.id)
+            .zscore(redis_keys::skill_points(MAIN_REALM), player.id);
+    }
+    let mut redis_conn = state.redis.get().await?;
+    let rankings: Vec<(Option<i64>, Option<i64>)> = if found.is_empty() {
+        vec![]
+    } else {
+        let flat: Vec<Option<i64>> = pipe.query_async(&mut redis_conn).await?;
+        flat.chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect()
+    };
+
+    let found_ids: Vec<String> = found.iter().map(|p| p.steam_id.0.to_string()).collect();
+    let not_found = payload
+        .steam_ids
+        .into_iter()
+        .filter(|requested| !found_ids.contains(requested))
+        .collect();
+    let looked_up = found
+        .into_iter()
+        .zip(found_ids)
+        .zip(rankings)
+        .map(
+            |((player, player_steam_id), (rank, skill_points))| LookedUpPlayer {
+                steam_id: player_steam_id,
+                player: player.into(),
+                // Ranks in Redis start at 0
+                rank: rank.map(|rank| rank + 1),
+                skill_points,
+            },
+        )
+        .collect();
+
+    Ok(Json(LookupResponse {
+        players: looked_up,
+        not_found,
+    }))
+}
+
+/// How many players are on one page of the rankings.
+const RANKINGS_PER_PAGE: isize = 50;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RankingsParams {
+    #[serde(default)]
+    mode: RankingMode,
+    #[serde(default)]
+    page: isize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RankedPlayer {
+    /// 1 is the top
+    rank: isize,
+    #[serde(flatten)]
+    player: PlayerPublic,
+    /// Points in the requested mode, see [`RankingMode`]
+    points: i64,
+    /// Points in every league's pool, which add up to the player's skill points
+    league_points: LeaguePoints,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct LeaguePoints {
+    casual: i64,
+    pro: i64,
+    elite: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RankingsResponse {
+    mode: RankingMode,
+    players: Vec<RankedPlayer>,
+}
+
+/// Lists the main realm's global rankings in the requested mode, best first.
+/// Players without any points in that mode aren't listed.
+async fn get_rankings(
+    State(state): State<AppState>,
+    Query(params): Query<RankingsParams>,
+) -> Result<Json<RankingsResponse>, RouteError> {
+    use crate::schema::players::dsl::*;
+
+    let offset = params.page.max(0) * RANKINGS_PER_PAGE;
+    let mut redis_conn = state.redis.get().await?;
+    let ranked: Vec<(i32, i64)> = redis_conn
+        .zrevrangebyscore_limit_withscores(
+            params.mode.key(MAIN_REALM),
+            "+inf",
+            "(0",
+            offset,
+            RANKINGS_PER_PAGE,
+        )
+        .await?;
+
+    let ranked_ids: Vec<i32> = ranked.iter().map(|(player, _)| *player).collect();
+    let mut pipe = redis::pipe();
+    for player in &ranked_ids {
+        for pool in RankingMode::LEAGUE_POOLS {
+            pipe.zscore(pool.key(MAIN_REALM), player);
+        }
+    }
+    let mut league_points: HashMap<i32, LeaguePoints> = if ranked_ids.is_empty() {
+        HashMap::new()
+    } else {
+        let flat: Vec<Option<i64>> = pipe.query_async(&mut redis_conn).await?;
+        ranked_ids
+            .iter()
+            .zip(flat.chunks_exact(RankingMode::LEAGUE_POOLS.len()))
+            .map(|(player, pools)| {
+                let points = LeaguePoints {
+                    casual: pools[0].unwrap_or_default(),
+                    pro: pools[1].unwrap_or_default(),
+                    elite: pools[2].unwrap_or_default(),
+                };
+                (*player, points)
+            })
+            .collect()
+    };
+
+    let mut conn = state.db_read.get().await?;
+    let mut found: HashMap<i32, Player> = Player::all()
+        .filter(id.eq_any(&ranked_ids))
+        .load::<Player>(&mut conn)
+        .await?
+        .into_iter()
+        .map(|player| (player.id, player))
+        .collect();
+
+    let ranked_players = ranked
+        .into_iter()
+        .zip(1..)
+        // Ranked players that don't exist anymore are skipped, the doctor cleans them up
+        .filter_map(|((player, points), place)| {
+            found.remove(&player).map(|found_player| RankedPlayer {
+                rank: offset + place,
+                player: found_player.into(),
+                points,
+                league_points: league_points.remove(&player).unwrap_or_default(),
+            })
+        })
+        .collect();
+
+    Ok(Json(RankingsResponse {
+        mode: params.mode,
+        players: ranked_players,
     }))
 }
+
+/// The albums in the main realm the player rode songs of and how much of each, the most ridden first.
+async fn get_player_albums(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<AlbumProgress>>, RouteError> {
+    use crate::schema::players;
+
+    let mut conn = state.db_read.get().await?;
+
+    let player: Player = players::table.find(id).first(&mut conn).await?;
+
+    Ok(Json(
+        AlbumProgress::for_player(player.id, MAIN_REALM, &mut conn).await?,
+    ))
+}
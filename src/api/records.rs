@@ -0,0 +1,36 @@
+use axum::{extract::State, routing::get, Json, Router};
+use serde::Serialize;
+
+use crate::{
+    models::{players::PlayerPublic, server_records::ServerRecord},
+    util::{errors::RouteError, realm::MAIN_REALM},
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/", get(get_records))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordResponse {
+    #[serde(flatten)]
+    record: ServerRecord,
+    holder: PlayerPublic,
+}
+
+/// Lists the main realm's server records, see [`crate::records`].
+async fn get_records(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<RecordResponse>>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let records = ServerRecord::for_realm(MAIN_REALM, &mut conn).await?;
+
+    Ok(Json(
+        records
+            .into_iter()
+            .map(|(record, holder)| RecordResponse { record, holder })
+            .collect(),
+    ))
+}
@@ -1,16 +1,33 @@
-use axum::{extract::State, routing::get, Json, Router};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post, put},
+    Json, Router,
+};
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
 
 use crate::{
-    models::{players::Player, rivalries::RivalryView},
+    models::{
+        players::Player,
+        rival_digests::{RivalActivity, RivalDigest},
+        rivalries::{NewRivalry, Rivalry, RivalryView},
+    },
+    records,
     util::{errors::RouteError, jwt::Claims},
     AppState,
 };
 
 pub fn routes() -> Router<AppState> {
-    Router::new().route("/own", get(get_own_rivals))
+    Router::new()
+        .route("/own", get(get_own_rivals))
+        .route("/own/mutual", get(get_own_mutual_rivals))
+        .route("/own/digest", get(get_own_digest))
+        .route("/own/digest/seen", post(mark_digest_seen))
+        .route("/own/digest/webhook", put(set_digest_webhook))
+        .route("/own/:id", put(add_rival).delete(remove_rival))
 }
 
 #[derive(Serialize)]
@@ -23,12 +40,171 @@ async fn get_own_rivals(
     State(state): State<AppState>,
     claims: Claims,
 ) -> Result<Json<RivalryResponse>, RouteError> {
-    use crate::schema::players::dsl::*;
+    let mut conn = state.db.get().await?;
+
+    let rivalries = Rivalry::all_for_player(claims.profile.id, &mut conn).await?;
+
+    Ok(Json(RivalryResponse { rivalries }))
+}
 
+/// Only the rivals who added the player back, which the game considers friends.
+async fn get_own_mutual_rivals(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<RivalryResponse>, RouteError> {
     let mut conn = state.db.get().await?;
 
-    let player: Player = players.find(claims.profile.id).first(&mut conn).await?;
-    let rivalries: Vec<RivalryView> = player.get_rivalry_views(&mut conn).await?;
+    let rivalries = Rivalry::mutual_rivals_of(claims.profile.id, &mut conn).await?;
 
     Ok(Json(RivalryResponse { rivalries }))
 }
+
+/// Adds a rival, within the limits set by `rivals` in the config.
+/// Answered with a 409 when the player has too many rivals, and a 429 when the rival was removed too recently.
+async fn add_rival(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, RouteError> {
+    use crate::schema::players;
+
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    let rival: Player = players::table.find(id).first(&mut conn).await?;
+    let created = NewRivalry::new(claims.profile.id, rival.id)
+        .create(&state.config.rivals, &mut conn, &mut redis_conn)
+        .await?;
+
+    Ok(if created.is_some() {
+        StatusCode::CREATED
+    } else {
+        StatusCode::NO_CONTENT
+    })
+}
+
+/// Removes a rival. Answered with a 429 when the rival was added too recently.
+async fn remove_rival(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, RouteError> {
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    let removed = Rivalry::remove(
+        claims.profile.id,
+        id,
+        &state.config.rivals,
+        &mut conn,
+        &mut redis_conn,
+    )
+    .await?;
+
+    if removed {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(RouteError::new_not_found())
+    }
+}
+
+/// How far back the digest goes for players who never looked at it before.
+const FIRST_DIGEST_DAYS: i64 = 7;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DigestResponse {
+    /// When the player last looked at the digest, the activities are from after that
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    since: OffsetDateTime,
+    /// When the digest was put together, to mark it as seen with
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    until: OffsetDateTime,
+    new_scores: usize,
+    /// How many of the new scores beat the player's own
+    beat_you: usize,
+    /// How many of the new scores are on top of their leaderboard
+    number_ones: usize,
+    /// Most recent first
+    activities: Vec<RivalActivity>,
+}
+
+/// What the player's rivals did since the player last marked the digest as seen, see [`mark_digest_seen`].
+async fn get_own_digest(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<DigestResponse>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let until = OffsetDateTime::now_utc();
+    let since = RivalDigest::seen_at_of(claims.profile.id, &mut conn)
+        .await?
+        .unwrap_or_else(|| until - Duration::days(FIRST_DIGEST_DAYS));
+    let activities = RivalActivity::since(claims.profile.id, since, &mut conn).await?;
+
+    Ok(Json(DigestResponse {
+        since,
+        until,
+        new_scores: activities.len(),
+        beat_you: activities
+            .iter()
+            .filter(|activity| activity.beat_you)
+            .count(),
+        number_ones: activities
+            .iter()
+            .filter(|activity| activity.number_one)
+            .count(),
+        activities,
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DigestSeenRequest {
+    /// The `until` of the digest the player saw, so what happened after it still shows up next time.
+    /// Everything until now if left out.
+    #[serde(default, with = "time::serde::iso8601::option")]
+    until: Option<OffsetDateTime>,
+}
+
+/// Marks the rivals' activity as seen, so the next digest starts after it.
+async fn mark_digest_seen(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(payload): Json<DigestSeenRequest>,
+) -> Result<StatusCode, RouteError> {
+    let now = OffsetDateTime::now_utc();
+    let until = payload.until.map_or(now, |until| until.min(now));
+
+    let mut conn = state.db.get().await?;
+
+    RivalDigest::mark_seen(claims.profile.id, until, &mut conn).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DigestWebhookRequest {
+    /// `None` to stop sending the digest
+    webhook_url: Option<String>,
+}
+
+/// Sets where the player's daily digest is sent, see `digests.daily` in the config.
+async fn set_digest_webhook(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(payload): Json<DigestWebhookRequest>,
+) -> Result<StatusCode, RouteError> {
+    let webhook_url = payload.webhook_url.as_deref().map(str::trim);
+    if webhook_url.is_some_and(|webhook_url| !records::is_discord_webhook(webhook_url)) {
+        return Err(RouteError::new_bad_request()
+            .set_public_error_message("The webhook URL has to be a Discord webhook"));
+    }
+
+    let mut conn = state.db.get().await?;
+
+    RivalDigest::set_webhook(claims.profile.id, webhook_url, &mut conn).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,736 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tracing::info;
+use url::Url;
+use validator::Validate;
+
+use crate::{
+    models::{
+        gold_thresholds::GoldThreshold,
+        players::{Player, PlayerPublic},
+        sandbagging_flags::SandbaggingFlag,
+        score_appeals::ScoreAppeal,
+        score_removals::{ScoreRemoval, ScoreRemovalAction},
+        scores::Score,
+        songs::Song,
+        traffic_flags::TrafficFlag,
+    },
+    schema::{players, songs},
+    util::{
+        errors::{RouteError, WavebreakerError},
+        game_types::{
+            validate_track_shape, validate_xstats, Character, League, MAX_TRACK_SHAPE_ENTRIES,
+            MAX_XSTATS_ENTRIES,
+        },
+        jwt::Claims,
+        rank_cache,
+        ranking_store::DeferredRankingStore,
+        redis_keys, redis_ops,
+    },
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/dryRun", post(dry_run))
+        .route("/appeals", get(get_own_appeals))
+        .route("/:id", get(get_score).delete(delete_score))
+        .route("/:id/compare/:other_id", get(compare_scores))
+        .route("/:id/ghost", get(get_score_ghost))
+        .route("/:id/appeal", post(appeal_score))
+        .route("/:id/hide", post(hide_score))
+        .route("/:id/unhide", post(unhide_score))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScoreDetail {
+    #[serde(flatten)]
+    score: Score,
+    player: PlayerPublic,
+    song: Song,
+    skill_points: i32,
+    /// 1 is the top score on the song and league
+    rank: i64,
+    /// How many scores there are on the song and league
+    ranked_scores: i64,
+    /// Percentage of the other scores on the song and league this one beats or ties
+    percentile: f64,
+    /// Whether the score is archived, its placement is among the archived scores then
+    archived: bool,
+}
+
+impl ScoreDetail {
+    /// Loads the score, falling back to the archive with `include_archived`.
+    async fn load(
+        id: i32,
+        include_archived: bool,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<Self, RouteError> {
+        let mut score: Option<Score> = Score::all().find(id).first(conn).await.optional()?;
+        let archived = score.is_none() && include_archived;
+        if archived {
+            score = Score::find_archived(id, conn).await?;
+        }
+        let score = score.ok_or(WavebreakerError::NotFound("Score"))?;
+        // Scores of deleted songs are deleted too, so the song is always there
+        let song: Song = songs::table.find(score.song_id).first(conn).await?;
+        let player: Player = players::table.find(score.player_id).first(conn).await?;
+        let (rank, ranked_scores) = if archived {
+            score.archived_placement(conn).await?
+        } else {
+            let (better, total) =
+                rank_cache::standing(score.song_id, score.league, score.score, conn, redis_conn)
+                    .await?;
+            (better + 1, total)
+        };
+
+        #[allow(clippy::cast_precision_loss)]
+        let percentile = if ranked_scores > 1 {
+            (ranked_scores - rank) as f64 * 100.0 / (ranked_scores - 1) as f64
+        } else {
+            100.0
+        };
+
+        Ok(Self {
+            skill_points: score.get_skill_points(),
+            score,
+            player: player.into(),
+            song,
+            rank,
+            ranked_scores,
+            percentile,
+            archived,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetScoreParams {
+    /// Also looks for the score in the archive, see [`crate::models::scores_archive`]
+    #[serde(default)]
+    include_archived: bool,
+}
+
+/// A score with everything needed to show it on its own page.
+async fn get_score(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(params): Query<GetScoreParams>,
+) -> Result<Json<ScoreDetail>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    Ok(Json(
+        ScoreDetail::load(id, params.include_archived, &mut conn, &mut redis_conn).await?,
+    ))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ComparisonResponse {
+    score: ScoreDetail,
+    other: ScoreDetail,
+    /// Positive if the first score is the better one
+    score_difference: i32,
+    skill_point_difference: i32,
+    same_song: bool,
+    /// Extended stats only mean the same thing for the same character, see `Score::xstats`
+    same_vehicle: bool,
+    /// Feats only one of the two scores got
+    only_in_score: Vec<String>,
+    only_in_other: Vec<String>,
+}
+
+/// Compares two scores, e.g. for "compare my run to yours" links.
+/// The scores don't have to be on the same song, though that's what makes sense most of the time.
+async fn compare_scores(
+    State(state): State<AppState>,
+    Path((id, other_id)): Path<(i32, i32)>,
+) -> Result<Json<ComparisonResponse>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    let score = ScoreDetail::load(id, false, &mut conn, &mut redis_conn).await?;
+    let other = ScoreDetail::load(other_id, false, &mut conn, &mut redis_conn).await?;
+
+    Ok(Json(ComparisonResponse {
+        score_difference: score.score.score - other.score.score,
+        skill_point_difference: score.skill_points - other.skill_points,
+        same_song: score.score.song_id == other.score.song_id,
+        same_vehicle: score.score.vehicle == other.score.vehicle,
+        only_in_score: feats_missing_from(&score.score, &other.score),
+        only_in_other: feats_missing_from(&other.score, &score.score),
+        score,
+        other,
+    }))
+}
+
+fn feats_missing_from(score: &Score, other: &Score) -> Vec<String> {
+    score
+        .feats
+        .iter()
+        .flatten()
+        .filter(|feat| !other.feats.iter().flatten().any(|f| f == *feat))
+        .cloned()
+        .collect()
+}
+
+/// Version of the [`GhostBundle`] format, bumped when fields are removed or change meaning.
+const GHOST_FORMAT: u32 = 1;
+/// How long clients may keep a ghost without asking again.
+/// A ghost only changes when its player beats the score, and clients can revalidate with the `ETag`.
+const GHOST_MAX_AGE_SECS: u32 = 300;
+
+/// Everything stored about the run behind a score, for client mods to race against it as a "ghost".
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GhostBundle<'a> {
+    format: u32,
+    score_id: i32,
+    song_id: i32,
+    player_id: i32,
+    league: League,
+    vehicle: Character,
+    score: i32,
+    /// In centiseconds
+    song_length: i32,
+    density: i32,
+    gold_threshold: i32,
+    /// The track's elevation at evenly spaced points, as the game sends it
+    track_shape: Vec<i32>,
+    /// The extended stats, meaning depends on the vehicle, see `Score::xstats`
+    xstats: Vec<i32>,
+    feats: Vec<&'a str>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    submitted_at: OffsetDateTime,
+}
+
+impl<'a> From<&'a Score> for GhostBundle<'a> {
+    fn from(score: &'a Score) -> Self {
+        // Submissions are limited the same way, only scores from before that can be longer
+        Self {
+            format: GHOST_FORMAT,
+            score_id: score.id,
+            song_id: score.song_id,
+            player_id: score.player_id,
+            league: score.league,
+            vehicle: score.vehicle,
+            score: score.score,
+            song_length: score.song_length,
+            density: score.density,
+            gold_threshold: score.gold_threshold,
+            track_shape: score
+                .track_shape
+                .iter()
+                .flatten()
+                .copied()
+                .take(MAX_TRACK_SHAPE_ENTRIES)
+                .collect(),
+            xstats: score
+                .xstats
+                .iter()
+                .flatten()
+                .copied()
+                .take(MAX_XSTATS_ENTRIES)
+                .collect(),
+            feats: score.feats.iter().flatten().map(String::as_str).collect(),
+            submitted_at: score.submitted_at,
+        }
+    }
+}
+
+/// The run behind a score, see [`GhostBundle`].
+///
+/// Answered with an `ETag`, a request with a matching `If-None-Match` gets a 304 without the bundle.
+async fn get_score_ghost(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    headers: HeaderMap,
+) -> Result<Response, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let score: Score = Score::all().find(id).first(&mut conn).await?;
+    let body = serde_json::to_vec(&GhostBundle::from(&score))?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(&body);
+    let etag = format!("\"{:x}\"", hasher.finalize());
+    let cache_headers = [
+        (ETAG, etag.clone()),
+        (
+            CACHE_CONTROL,
+            format!("public, max-age={GHOST_MAX_AGE_SECS}"),
+        ),
+    ];
+
+    let unchanged = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+    if unchanged {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    Ok((
+        cache_headers,
+        [(CONTENT_TYPE, "application/json".to_owned())],
+        body,
+    )
+        .into_response())
+}
+
+/// A score submission like the game would send it, with the same rules.
+/// Track shape, extended stats and feats are in the game's format too, so payloads can be copied over.
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct DryRunRequest {
+    song_id: i32,
+    #[validate(range(min = 0))]
+    score: i32,
+    vehicle: Character,
+    league: League,
+    #[serde(default)]
+    #[validate(length(max = 1024))]
+    feats: String,
+    /// In centiseconds
+    #[validate(range(min = 1, max = 720_000))]
+    song_length: i32,
+    #[serde(default)]
+    #[validate(custom(function = "validate_track_shape"))]
+    track_shape: String,
+    #[validate(range(min = 0))]
+    density: i32,
+    #[serde(default)]
+    #[validate(custom(function = "validate_xstats"))]
+    xstats: String,
+    /// Skill points are worked out relative to it, so unlike the game this has to be above 0,
+    /// same as for the consensus in `gold_thresholds`
+    #[validate(range(min = 1))]
+    gold_threshold: i32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TopScore {
+    player: PlayerPublic,
+    score: i32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DryRunResponse {
+    /// Why the game's submission would be refused, as `field: reason`. Empty if it would be accepted.
+    validation_errors: Vec<String>,
+    /// Left out if the submission would be refused
+    #[serde(flatten)]
+    outcome: Option<DryRunOutcome>,
+}
+
+/// What an accepted submission would do.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DryRunOutcome {
+    /// Whether the gold threshold is close to what others reported for the song, league and character.
+    /// `None` if not enough others did.
+    gold_threshold_plausible: Option<bool>,
+    /// The player's current score on the song and league
+    personal_best: Option<i32>,
+    new_personal_best: bool,
+    /// Where the player would stand afterwards, 1 is the top score
+    rank: i64,
+    /// How many scores there would be on the song and league afterwards
+    ranked_scores: i64,
+    /// The best score of anyone else on the song and league
+    current_top: Option<TopScore>,
+    /// Whether the submission would take the top spot from `current_top`
+    dethrone: bool,
+    /// Skill points of the submission
+    skill_points: i32,
+    /// How many skill points the player would gain, 0 unless it's a new personal best
+    skill_point_gain: i32,
+}
+
+/// Tells what submitting a score would do, without saving anything.
+/// Meant for overlay tools and for trying out what gets flagged.
+async fn dry_run(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(payload): Json<DryRunRequest>,
+) -> Result<Json<DryRunResponse>, RouteError> {
+    use crate::schema::scores::dsl::*;
+
+    let mut validation_errors: Vec<String> = match payload.validate() {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors
+            .field_errors()
+            .into_iter()
+            .flat_map(|(field, field_errors)| {
+                field_errors
+                    .iter()
+                    .map(move |error| format!("{field}: {}", error.code))
+            })
+            .collect(),
+    };
+    validation_errors.sort_unstable();
+    if !validation_errors.is_empty() {
+        return Ok(Json(DryRunResponse {
+            validation_errors,
+            outcome: None,
+        }));
+    }
+
+    let mut conn = state.db_read.get().await?;
+
+    let song: Song = Song::all().find(payload.song_id).first(&mut conn).await?;
+    let on_leaderboard = || {
+        Score::all()
+            .filter(song_id.eq(song.id))
+            .filter(league.eq(payload.league))
+    };
+
+    let previous: Option<Score> = on_leaderboard()
+        .filter(player_id.eq(claims.profile.id))
+        .first(&mut conn)
+        .await
+        .optional()?;
+    let current_top: Option<(Score, Player)> = on_leaderboard()
+        .filter(player_id.ne(claims.profile.id))
+        .inner_join(players::table)
+        .order(score.desc())
+        .first(&mut conn)
+        .await
+        .optional()?;
+
+    let new_personal_best = previous
+        .as_ref()
+        .is_none_or(|previous| payload.score > previous.score);
+    let kept_score = previous
+        .as_ref()
+        .map_or(payload.score, |previous| previous.score.max(payload.score));
+    // The player's own score is never better than the one they'd keep
+    let mut redis_conn = state.redis.get().await?;
+    let (better, total) = rank_cache::standing(
+        song.id,
+        payload.league,
+        kept_score,
+        &mut conn,
+        &mut redis_conn,
+    )
+    .await?;
+    let others = total - i64::from(previous.is_some());
+
+    let gold_threshold_plausible =
+        GoldThreshold::consensus(song.id, payload.league, payload.vehicle, &mut conn)
+            .await?
+            .map(|consensus| consensus.is_plausible(payload.gold_threshold));
+
+    let submitted_skill_points =
+        Score::skill_points_for(payload.league, payload.score, payload.gold_threshold);
+    let skill_point_gain = match &previous {
+        Some(previous) if new_personal_best => submitted_skill_points - previous.get_skill_points(),
+        Some(_) => 0,
+        None => submitted_skill_points,
+    };
+
+    Ok(Json(DryRunResponse {
+        validation_errors,
+        outcome: Some(DryRunOutcome {
+            gold_threshold_plausible,
+            personal_best: previous.map(|previous| previous.score),
+            new_personal_best,
+            rank: better + 1,
+            ranked_scores: others + 1,
+            dethrone: current_top
+                .as_ref()
+                .is_some_and(|(top, _)| top.score < payload.score),
+            current_top: current_top.map(|(top, top_player)| TopScore {
+                player: top_player.into(),
+                score: top.score,
+            }),
+            skill_points: submitted_skill_points,
+            skill_point_gain,
+        }),
+    }))
+}
+
+/// Same limit as the `score_appeals.comment` column
+const MAX_APPEAL_COMMENT_LENGTH: usize = 1000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AppealRequest {
+    comment: String,
+    evidence_url: Option<String>,
+}
+
+/// Appeals the removal of one of the player's scores. Moderators resolve it, see `/api/admin/appeals`.
+/// Answered with a 409 if the score already has a pending appeal.
+async fn appeal_score(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<i32>,
+    Json(payload): Json<AppealRequest>,
+) -> Result<Json<ScoreAppeal>, RouteError> {
+    use crate::schema::scores;
+
+    let comment = payload.comment.trim();
+    if comment.is_empty() || comment.chars().count() > MAX_APPEAL_COMMENT_LENGTH {
+        return Err(
+            RouteError::new_bad_request().set_public_error_message(&format!(
+                "Comment must be between 1 and {MAX_APPEAL_COMMENT_LENGTH} characters long"
+            )),
+        );
+    }
+    let evidence_url = payload
+        .evidence_url
+        .as_deref()
+        .map(str::trim)
+        .filter(|evidence_url| !evidence_url.is_empty());
+    if evidence_url.is_some_and(|evidence_url| {
+        !Url::parse(evidence_url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+    }) {
+        return Err(RouteError::new_bad_request()
+            .set_public_error_message("Evidence has to be an HTTP(S) link"));
+    }
+
+    let mut conn = state.db.get().await?;
+
+    // Deleted scores are what can be appealed, so not `Score::all`
+    let score: Score = scores::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .filter(|score: &Score| score.player_id == claims.profile.id)
+        .ok_or(WavebreakerError::NotFound("Score"))?;
+    if score.deleted_at.is_none() {
+        return Err(RouteError::new_bad_request()
+            .set_public_error_message("Only removed scores can be appealed"));
+    }
+    if ScoreRemoval::is_hidden_by_player(&score, &mut conn).await? {
+        return Err(RouteError::new_bad_request()
+            .set_public_error_message("Scores you hid yourself can be unhidden instead"));
+    }
+
+    let appeal = ScoreAppeal::create(
+        score.id,
+        claims.profile.id,
+        comment,
+        evidence_url,
+        &mut conn,
+    )
+    .await?;
+    info!(
+        "Removal of score {} appealed by player {}",
+        score.id, claims.profile.id
+    );
+
+    Ok(Json(appeal))
+}
+
+/// The player's own appeals and what became of them, newest first.
+async fn get_own_appeals(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<ScoreAppeal>>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    Ok(Json(
+        ScoreAppeal::for_player(claims.profile.id, &mut conn).await?,
+    ))
+}
+
+/// Gets one of the player's own scores, deleted or not.
+async fn own_score(
+    id: i32,
+    player: i32,
+    conn: &mut AsyncPgConnection,
+) -> Result<Score, RouteError> {
+    use crate::schema::scores;
+
+    Ok(scores::table
+        .find(id)
+        .first(conn)
+        .await
+        .optional()?
+        .filter(|score: &Score| score.player_id == player)
+        .ok_or(WavebreakerError::NotFound("Score"))?)
+}
+
+/// Refuses a hide, unhide or delete once the player reached their daily limit, so the rankings aren't churned by
+/// it. Only the ones that went through count, see [`count_removal`].
+async fn check_removal_rate(state: &AppState, player: i32) -> Result<(), RouteError> {
+    let mut redis_conn = state.redis.get().await?;
+    let count: Option<i64> = redis_conn.get(redis_keys::score_removals(player)).await?;
+    if count.unwrap_or(0) >= i64::from(state.config.profiles.score_removals_per_day) {
+        return Err(RouteError::from_status(StatusCode::TOO_MANY_REQUESTS)
+            .set_public_error_message(
+                "You changed too many of your scores today, try again tomorrow",
+            ));
+    }
+    Ok(())
+}
+
+/// Counts a hide, unhide or delete that went through towards the player's daily limit.
+async fn count_removal(
+    player: i32,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> Result<(), RouteError> {
+    redis_ops::incr_in_window(
+        &redis_keys::score_removals(player),
+        24 * 60 * 60,
+        redis_conn,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Takes one of the player's own scores off the leaderboards and the rankings.
+/// It can be brought back with `/unhide` until deleted scores are purged.
+async fn hide_score(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> Result<(), RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let score = own_score(id, claims.profile.id, &mut conn).await?;
+    if score.deleted_at.is_some() {
+        return Err(RouteError::new_bad_request()
+            .set_public_error_message("The score is already hidden or removed"));
+    }
+    check_removal_rate(&state, claims.profile.id).await?;
+
+    // The rankings are only changed once the transaction is committed
+    let store = conn
+        .transaction::<_, WavebreakerError, _>(|conn| {
+            let score = &score;
+            async move {
+                let mut store = DeferredRankingStore::default();
+                ScoreRemoval::record(score, ScoreRemovalAction::Hide, conn).await?;
+                score.delete(conn, &mut store).await?;
+                Ok(store)
+            }
+            .scope_boxed()
+        })
+        .await?;
+    let mut redis_conn = state.redis.get().await?;
+    store.apply(&mut redis_conn).await?;
+    count_removal(claims.profile.id, &mut redis_conn).await?;
+    info!("Score {id} hidden by player {}", claims.profile.id);
+
+    Ok(())
+}
+
+/// Brings back a score the player hid. Scores removed by moderators have to be appealed instead.
+/// Fails if the player set a new score on the same song and league since hiding it.
+async fn unhide_score(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> Result<(), RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let score = own_score(id, claims.profile.id, &mut conn).await?;
+    if !ScoreRemoval::is_hidden_by_player(&score, &mut conn).await? {
+        return Err(RouteError::new_bad_request()
+            .set_public_error_message("Only scores you hid yourself can be unhidden"));
+    }
+    // The scores of a deleted song are only brought back with it
+    if Song::all()
+        .find(score.song_id)
+        .first::<Song>(&mut conn)
+        .await
+        .optional()?
+        .is_none()
+    {
+        return Err(
+            RouteError::new_conflict().set_public_error_message("The score's song was deleted")
+        );
+    }
+    check_removal_rate(&state, claims.profile.id).await?;
+
+    let store = conn
+        .transaction::<_, WavebreakerError, _>(|conn| {
+            let score = &score;
+            async move {
+                let mut store = DeferredRankingStore::default();
+                score.restore(conn, &mut store).await?;
+                ScoreRemoval::record(score, ScoreRemovalAction::Unhide, conn).await?;
+                Ok(store)
+            }
+            .scope_boxed()
+        })
+        .await?;
+    let mut redis_conn = state.redis.get().await?;
+    store.apply(&mut redis_conn).await?;
+    count_removal(claims.profile.id, &mut redis_conn).await?;
+    info!("Score {id} unhidden by player {}", claims.profile.id);
+
+    Ok(())
+}
+
+/// Deletes one of the player's own scores for good, whether it's hidden or not.
+/// Scores removed by moderators are left alone, they're kept until purged in case of an appeal.
+async fn delete_score(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> Result<(), RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let score = own_score(id, claims.profile.id, &mut conn).await?;
+    if score.deleted_at.is_some() && !ScoreRemoval::is_hidden_by_player(&score, &mut conn).await? {
+        return Err(RouteError::new_bad_request()
+            .set_public_error_message("Removed scores can only be appealed"));
+    }
+    // Deleting would take the evidence with it, hiding is fine while a moderator looks into it
+    if TrafficFlag::is_active_on(score.player_id, score.song_id, score.league, &mut conn).await?
+        || SandbaggingFlag::is_active(score.player_id, &score.realm, &mut conn).await?
+    {
+        return Err(
+            RouteError::from_status(StatusCode::CONFLICT).set_public_error_message(
+                "Scores can't be deleted while a moderator looks into them, hide them instead",
+            ),
+        );
+    }
+    check_removal_rate(&state, claims.profile.id).await?;
+
+    let store = conn
+        .transaction::<_, WavebreakerError, _>(|conn| {
+            let score = &score;
+            async move {
+                let mut store = DeferredRankingStore::default();
+                ScoreRemoval::record(score, ScoreRemovalAction::Delete, conn).await?;
+                score.delete_permanently(conn, &mut store).await?;
+                Ok(store)
+            }
+            .scope_boxed()
+        })
+        .await?;
+    let mut redis_conn = state.redis.get().await?;
+    store.apply(&mut redis_conn).await?;
+    count_removal(claims.profile.id, &mut redis_conn).await?;
+    info!("Score {id} deleted by player {}", claims.profile.id);
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use axum::{
+    extract::{Path, State},
+    routing::post,
+    Json, Router,
+};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    models::{
+        shout_reports::{NewShoutReport, ShoutReport},
+        shouts::Shout,
+    },
+    util::{
+        errors::{RouteError, WavebreakerError},
+        jwt::Claims,
+    },
+    AppState,
+};
+
+/// Same limit as the `shout_reports.reason` column
+const MAX_REASON_LENGTH: usize = 240;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/:id/report", post(report_shout))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportRequest {
+    reason: String,
+}
+
+/// Reports a shout to the moderators. Answered with a 409 if the player already reported it.
+async fn report_shout(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<i32>,
+    Json(payload): Json<ReportRequest>,
+) -> Result<Json<ShoutReport>, RouteError> {
+    use crate::schema::shouts;
+
+    let reason = payload.reason.trim();
+    if reason.is_empty() || reason.chars().count() > MAX_REASON_LENGTH {
+        return Err(
+            RouteError::new_bad_request().set_public_error_message(&format!(
+                "Reason must be between 1 and {MAX_REASON_LENGTH} characters long"
+            )),
+        );
+    }
+
+    let mut conn = state.db.get().await?;
+
+    let shout: Shout = shouts::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Shout"))?;
+    let report = NewShoutReport::new(shout.id, claims.profile.id, reason)
+        .insert(&mut conn)
+        .await?;
+
+    info!(
+        "Shout {} reported by player {}",
+        shout.id, claims.profile.id
+    );
+
+    Ok(Json(report))
+}
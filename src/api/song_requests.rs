@@ -0,0 +1,170 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    models::song_requests::{NewSongRequest, SongRequest, SongRequestStatus},
+    util::{
+        errors::{RouteError, WavebreakerError},
+        jwt::Claims,
+    },
+    AppState,
+};
+
+/// Same limit as the `song_requests.note` column
+const MAX_NOTE_LENGTH: usize = 240;
+const REQUESTS_PER_PAGE: i64 = 50;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(get_song_requests).post(request_song))
+        .route("/:id/vote", post(vote).delete(unvote))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SongRequestsParams {
+    #[serde(default = "default_status")]
+    status: SongRequestStatus,
+    #[serde(default)]
+    page: i64,
+}
+
+const fn default_status() -> SongRequestStatus {
+    SongRequestStatus::Open
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SongRequestsResponse {
+    requests: Vec<SongRequest>,
+}
+
+/// Lists song requests with the given status, most voted first.
+async fn get_song_requests(
+    State(state): State<AppState>,
+    Query(params): Query<SongRequestsParams>,
+) -> Result<Json<SongRequestsResponse>, RouteError> {
+    use crate::schema::song_requests::dsl::*;
+
+    let mut conn = state.db_read.get().await?;
+
+    let requests: Vec<SongRequest> = song_requests
+        .filter(status.eq(params.status.as_str()))
+        .order((votes.desc(), requested_at.asc()))
+        .limit(REQUESTS_PER_PAGE)
+        .offset(params.page.max(0) * REQUESTS_PER_PAGE)
+        .load(&mut conn)
+        .await?;
+
+    Ok(Json(SongRequestsResponse { requests }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SongRequestRequest {
+    title: String,
+    artist: String,
+    note: Option<String>,
+}
+
+/// Requests a song. Answered with a 409 if it's already requested, players should vote for that request instead.
+async fn request_song(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(payload): Json<SongRequestRequest>,
+) -> Result<Json<SongRequest>, RouteError> {
+    let bad_request =
+        |message: &str| RouteError::new_bad_request().set_public_error_message(message);
+
+    let (title, artist) = (payload.title.trim(), payload.artist.trim());
+    let note = payload
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty());
+    if title.is_empty() || artist.is_empty() {
+        return Err(bad_request("Title and artist can't be empty"));
+    }
+    if note.is_some_and(|note| note.chars().count() > MAX_NOTE_LENGTH) {
+        return Err(bad_request(&format!(
+            "Note can't be longer than {MAX_NOTE_LENGTH} characters"
+        )));
+    }
+    let rules = &state.config.text_filter;
+    if [Some(title), Some(artist), note]
+        .into_iter()
+        .flatten()
+        .any(|text| rules.check(text).is_some())
+    {
+        return Err(bad_request("Request contains text that isn't allowed"));
+    }
+
+    let mut conn = state.db.get().await?;
+
+    let request = NewSongRequest {
+        title,
+        artist,
+        note,
+        requested_by: claims.profile.id,
+    }
+    .insert(&mut conn)
+    .await?;
+    info!(
+        "Player {} requested song {} - {} (request {})",
+        claims.profile.id, request.artist, request.title, request.id
+    );
+
+    Ok(Json(request))
+}
+
+async fn find_open_request(
+    request_id: i32,
+    conn: &mut AsyncPgConnection,
+) -> Result<SongRequest, WavebreakerError> {
+    use crate::schema::song_requests::dsl::*;
+
+    song_requests
+        .find(request_id)
+        .filter(status.eq(SongRequestStatus::Open.as_str()))
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or(WavebreakerError::NotFound("Open song request"))
+}
+
+async fn vote(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> Result<Json<SongRequest>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let request = find_open_request(id, &mut conn)
+        .await?
+        .vote(claims.profile.id, &mut conn)
+        .await?;
+
+    Ok(Json(request))
+}
+
+async fn unvote(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> Result<Json<SongRequest>, RouteError> {
+    let mut conn = state.db.get().await?;
+
+    let request = find_open_request(id, &mut conn)
+        .await?
+        .unvote(claims.profile.id, &mut conn)
+        .await?;
+
+    Ok(Json(request))
+}
@@ -1,20 +1,50 @@
 use axum::{
     extract::{Path, Query, State},
-    routing::get,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Redirect,
+    },
+    routing::{get, post},
     Json, Router,
 };
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
 use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
+use tracing::info;
 
+use super::feats::get_song_feat_leaderboard;
 use crate::{
-    models::{extra_song_info::ExtraSongInfo, songs::Song},
-    util::errors::RouteError,
+    models::{
+        extra_song_info::ExtraSongInfo,
+        gold_thresholds::GoldThreshold,
+        leaderboard_snapshots::LeaderboardSnapshot,
+        metadata_suggestions::{MetadataSuggestion, NewMetadataSuggestion},
+        players::{Player, PlayerPublic},
+        scores::Score,
+        song_aliases::{AliasKind, SongAlias},
+        song_links::SongLink,
+        songs::Song,
+    },
+    schema::{extra_song_info, players, songs},
+    util::{errors::RouteError, game_types::League, jwt::Claims, previews},
     AppState,
 };
 
 pub fn routes() -> Router<AppState> {
-    Router::new().route("/:id", get(get_song))
+    Router::new()
+        .route("/:id", get(get_song))
+        .route("/:id/suggestions", post(suggest_metadata))
+        .route("/:id/preview", get(get_preview))
+        .route("/:id/leaderboard", get(get_leaderboard))
+        .route("/:id/leaderboard/stream", get(stream_leaderboard))
+        .route("/:id/history", get(get_leaderboard_history))
+        .route("/:id/records", get(get_record_history))
+        .route("/:id/feats/:feat", get(get_song_feat_leaderboard))
 }
 
 #[derive(Serialize)]
@@ -23,7 +53,23 @@ struct SongResponse {
     #[serde(flatten)]
     song: Song,
     #[serde(skip_serializing_if = "Option::is_none")]
-    extra_info: Option<ExtraSongInfo>,
+    extra_info: Option<ExtraInfoResponse>,
+    /// The player who discovered the song by riding it first
+    first_rider: Option<PlayerPublic>,
+    /// The gold medal cutoff per league and character, as reported by the game most often
+    gold_thresholds: Vec<GoldThreshold>,
+    /// Links moderators attached, like the official video
+    links: Vec<SongLink>,
+}
+
+/// Extra info with the song's aliases, the way it looked before they got their own table
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExtraInfoResponse {
+    #[serde(flatten)]
+    info: Option<ExtraSongInfo>,
+    aliases_artist: Vec<String>,
+    aliases_title: Vec<String>,
 }
 
 #[derive(Deserialize)]
@@ -38,21 +84,365 @@ async fn get_song(
     Path(id): Path<i32>,
     query: Query<GetSongParams>,
 ) -> Result<Json<SongResponse>, RouteError> {
-    use crate::schema::songs;
+    let mut conn = state.db_read.get().await?;
 
-    let mut conn = state.db.get().await?;
-
-    let song: Song = songs::table.find(id).first(&mut conn).await?;
+    let song: Song = Song::all().find(id).first(&mut conn).await?;
+    let first_rider = match song.first_rider_id {
+        Some(first_rider_id) => players::table
+            .find(first_rider_id)
+            .first::<Player>(&mut conn)
+            .await
+            .optional()?
+            .map(PlayerPublic::from),
+        None => None,
+    };
+    let gold_thresholds = GoldThreshold::consensus_for_song(song.id, &mut conn).await?;
+    let links = SongLink::for_song(song.id, &mut conn).await?;
     if query.with_extra_info {
-        let extra_info: Option<ExtraSongInfo> = ExtraSongInfo::belonging_to(&song)
+        let info: Option<ExtraSongInfo> = ExtraSongInfo::belonging_to(&song)
             .first(&mut conn)
             .await
             .optional()?;
-        return Ok(Json(SongResponse { song, extra_info }));
+        let (titles, artists): (Vec<SongAlias>, Vec<SongAlias>) =
+            SongAlias::for_song(song.id, &mut conn)
+                .await?
+                .into_iter()
+                .partition(|alias| alias.kind == AliasKind::Title.as_str());
+        let extra_info = (info.is_some() || !titles.is_empty() || !artists.is_empty()).then(|| {
+            ExtraInfoResponse {
+                info,
+                aliases_artist: artists.into_iter().map(|alias| alias.alias).collect(),
+                aliases_title: titles.into_iter().map(|alias| alias.alias).collect(),
+            }
+        });
+        return Ok(Json(SongResponse {
+            song,
+            extra_info,
+            first_rider,
+            gold_thresholds,
+            links,
+        }));
     }
 
     Ok(Json(SongResponse {
         song,
         extra_info: None,
+        first_rider,
+        gold_thresholds,
+        links,
     }))
 }
+
+/// Sends the player to a link to the song's 30 second preview, which only works for a while, so it's only cached for
+/// a few minutes, see [`crate::util::previews`].
+async fn get_preview(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Redirect, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let track_id: Option<i64> = extra_song_info::table
+        .filter(extra_song_info::song_id.eq(id))
+        .select(extra_song_info::deezer_track_id)
+        .first::<Option<i64>>(&mut conn)
+        .await
+        .optional()?
+        .flatten();
+    let no_preview =
+        || RouteError::new_not_found().set_public_error_message("The song doesn't have a preview");
+    let Some(track_id) = track_id else {
+        return Err(no_preview());
+    };
+    // Asking Deezer can take a while, nobody else should wait for the connection meanwhile
+    drop(conn);
+
+    let url = previews::cached_preview_url(track_id, &mut state.redis.get().await?)
+        .await?
+        .ok_or_else(no_preview)?;
+    Ok(Redirect::temporary(&url))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SuggestionRequest {
+    mbid: Option<String>,
+    release_mbid: Option<String>,
+    title: Option<String>,
+    artist: Option<String>,
+}
+
+/// Lets players suggest metadata fixes for a song, which moderators can then approve.
+/// Answered with a 409 if the player already has a pending suggestion for the song.
+async fn suggest_metadata(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<i32>,
+    Json(payload): Json<SuggestionRequest>,
+) -> Result<Json<MetadataSuggestion>, RouteError> {
+    let bad_request =
+        |message: &str| RouteError::new_bad_request().set_public_error_message(message);
+
+    let mbid = payload.mbid.as_deref().map(str::trim);
+    let release_mbid = payload.release_mbid.as_deref().map(str::trim);
+    if !mbid.into_iter().chain(release_mbid).all(looks_like_mbid) {
+        return Err(bad_request("Invalid MBID"));
+    }
+    if release_mbid.is_some() && mbid.is_none() {
+        return Err(bad_request("A release MBID needs a recording MBID"));
+    }
+
+    let title = payload.title.as_deref().map(str::trim);
+    let artist = payload.artist.as_deref().map(str::trim);
+    match (title, artist) {
+        (Some(title), Some(artist)) => {
+            if title.is_empty() || artist.is_empty() {
+                return Err(bad_request("Title and artist can't be empty"));
+            }
+            let rules = &state.config.text_filter;
+            if rules.check(title).is_some() || rules.check(artist).is_some() {
+                return Err(bad_request("Title or artist isn't allowed"));
+            }
+        }
+        (None, None) if mbid.is_some() => {}
+        (None, None) => return Err(bad_request("Suggest an MBID or a title and artist")),
+        _ => {
+            return Err(bad_request(
+                "Title and artist have to be suggested together",
+            ))
+        }
+    }
+
+    let mut conn = state.db.get().await?;
+
+    let song: Song = Song::all().find(id).first(&mut conn).await?;
+    let suggestion = NewMetadataSuggestion {
+        song_id: song.id,
+        player_id: claims.profile.id,
+        mbid,
+        release_mbid,
+        title,
+        artist,
+    }
+    .insert(&mut conn)
+    .await?;
+
+    info!(
+        "Player {} suggested metadata for song {} (suggestion {})",
+        claims.profile.id, song.id, suggestion.id
+    );
+
+    Ok(Json(suggestion))
+}
+
+/// How many scores are on one page of a song's leaderboard.
+const LEADERBOARD_PAGE_SIZE: i64 = 50;
+
+/// Whose scores are on a leaderboard.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+enum LeaderboardMode {
+    /// Everyone's
+    #[default]
+    Global,
+    /// Only the requesting player's and their mutual rivals', needs a token
+    MutualRivals,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LeaderboardParams {
+    /// `None` for the leaderboard across all leagues, with every player's best score in whichever league it is
+    league: Option<League>,
+    #[serde(default)]
+    mode: LeaderboardMode,
+    #[serde(default)]
+    page: i64,
+    /// Shows the leaderboard the song had when its scores were archived, if they were
+    #[serde(default)]
+    include_archived: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LeaderboardPlace {
+    #[serde(flatten)]
+    score: Score,
+    player: PlayerPublic,
+    skill_points: i32,
+}
+
+/// Shows a page of a song's leaderboard in one league, or across all of them, best first.
+/// Every place has the league its score is in.
+async fn get_leaderboard(
+    State(state): State<AppState>,
+    claims: Option<Claims>,
+    Path(id): Path<i32>,
+    Query(params): Query<LeaderboardParams>,
+) -> Result<Json<Vec<LeaderboardPlace>>, RouteError> {
+    let mutual_rivals_of = match params.mode {
+        LeaderboardMode::Global => None,
+        LeaderboardMode::MutualRivals => {
+            Some(claims.ok_or_else(RouteError::new_unauthorized)?.profile.id)
+        }
+    };
+
+    let mut conn = state.db_read.get().await?;
+
+    let song: Song = Song::all().find(id).first(&mut conn).await?;
+    let places = if params.include_archived && song.scores_archived_at.is_some() {
+        Score::archived_leaderboard(
+            song.id,
+            params.league,
+            mutual_rivals_of,
+            params.page.max(0),
+            LEADERBOARD_PAGE_SIZE,
+            &mut conn,
+        )
+        .await?
+    } else {
+        Score::leaderboard(
+            song.id,
+            params.league,
+            mutual_rivals_of,
+            params.page.max(0),
+            LEADERBOARD_PAGE_SIZE,
+            &mut conn,
+        )
+        .await?
+    };
+
+    Ok(Json(
+        places
+            .into_iter()
+            .map(|(score, player)| LeaderboardPlace {
+                skill_points: score.get_skill_points(),
+                score,
+                player,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StreamParams {
+    /// `None` for updates in every league
+    league: Option<League>,
+}
+
+/// Streams the changes of the song's leaderboard as server-sent events, see [`crate::util::live_leaderboards`].
+///
+/// Every score that changes it is sent as a `ride` event. A client that falls behind gets a `resync` event with how
+/// many updates it missed, and should load the leaderboard again.
+async fn stream_leaderboard(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(params): Query<StreamParams>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+    let song_id: i32 = Song::all()
+        .find(id)
+        .select(songs::id)
+        .first(&mut conn)
+        .await?;
+    drop(conn);
+
+    let updates =
+        BroadcastStream::new(state.live_leaderboards.subscribe()).filter_map(move |update| {
+            match update {
+                Ok(update) => (update.song_id == song_id
+                    && params.league.is_none_or(|league| league == update.league))
+                .then(|| Event::default().event("ride").json_data(&*update)),
+                Err(BroadcastStreamRecvError::Lagged(missed)) => Some(Ok(Event::default()
+                    .event("resync")
+                    .data(missed.to_string()))),
+            }
+        });
+    Ok(Sse::new(updates).keep_alive(KeepAlive::default()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryParams {
+    league: League,
+    /// Defaults to now, which gets the latest snapshot
+    #[serde(default, with = "time::serde::iso8601::option")]
+    as_of: Option<OffsetDateTime>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SnapshotPlace {
+    #[serde(flatten)]
+    place: LeaderboardSnapshot,
+    player: PlayerPublic,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LeaderboardHistoryResponse {
+    /// When the snapshot shown was taken. `None` if there's none that old.
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    taken_at: Option<OffsetDateTime>,
+    places: Vec<SnapshotPlace>,
+}
+
+/// Shows the top of a song's leaderboard as it was at a past point in time, from the daily snapshots.
+async fn get_leaderboard_history(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<LeaderboardHistoryResponse>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let song: Song = Song::all().find(id).first(&mut conn).await?;
+    let (taken_at, places) = LeaderboardSnapshot::as_of(
+        song.id,
+        params.league,
+        params.as_of.unwrap_or_else(OffsetDateTime::now_utc),
+        &mut conn,
+    )
+    .await?;
+
+    Ok(Json(LeaderboardHistoryResponse {
+        taken_at,
+        places: places
+            .into_iter()
+            .map(|(place, player)| SnapshotPlace { place, player })
+            .collect(),
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordsParams {
+    league: League,
+}
+
+/// Shows who held the record of a song over time, from the daily snapshots.
+async fn get_record_history(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(params): Query<RecordsParams>,
+) -> Result<Json<Vec<SnapshotPlace>>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let song: Song = Song::all().find(id).first(&mut conn).await?;
+    let records = LeaderboardSnapshot::records(song.id, params.league, &mut conn).await?;
+
+    Ok(Json(
+        records
+            .into_iter()
+            .map(|(place, player)| SnapshotPlace { place, player })
+            .collect(),
+    ))
+}
+
+/// MBIDs are UUIDs, like `c5a22ed3-4ff4-4ee0-8b6f-3a2a6b8b3c1c`.
+fn looks_like_mbid(mbid: &str) -> bool {
+    mbid.len() == 36
+        && mbid.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
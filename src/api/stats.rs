@@ -0,0 +1,165 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::get,
+    Json, Router,
+};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Deserialize;
+use time::{Date, Duration, OffsetDateTime};
+
+use crate::{
+    models::{
+        daily_stats::{DailyPlayerPoints, DailyStats, SongPlays},
+        players::Player,
+    },
+    util::{
+        errors::RouteError,
+        timeseries::{self, Bucket, Series},
+    },
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/daily", get(get_daily_stats))
+        .route("/songs", get(get_most_played_songs))
+        .route("/timeseries/plays", get(get_plays_series))
+        .route("/timeseries/newSongs", get(get_new_songs_series))
+        .route(
+            "/timeseries/players/:id/skillPoints",
+            get(get_skill_points_series),
+        )
+}
+
+/// Most days that can be asked for at once
+const MAX_DAYS: i64 = 366;
+/// Most songs listed as most played
+const MOST_PLAYED_LIMIT: i64 = 50;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StatsParams {
+    /// How many days back, up to [`MAX_DAYS`]
+    days: Option<i64>,
+}
+
+impl StatsParams {
+    /// The first day asked for, in the server's time zone.
+    fn since(&self, state: &AppState, default_days: i64) -> Date {
+        let days = self.days.unwrap_or(default_days).clamp(1, MAX_DAYS);
+        state.config.time_zone.today(OffsetDateTime::now_utc()) - Duration::days(days)
+    }
+}
+
+/// The last day that's rolled up, if the job worker keeps up.
+fn yesterday(state: &AppState) -> Date {
+    state.config.time_zone.today(OffsetDateTime::now_utc()) - Duration::days(1)
+}
+
+/// The rolled up stats of every day in the last 30 days (or `days`), oldest first, for charts.
+/// Today isn't rolled up until it's over.
+async fn get_daily_stats(
+    State(state): State<AppState>,
+    Query(params): Query<StatsParams>,
+) -> Result<Json<Vec<DailyStats>>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let since = params.since(&state, 30);
+
+    Ok(Json(DailyStats::since(since, &mut conn).await?))
+}
+
+/// The songs played most in the last 7 days (or `days`), most first.
+async fn get_most_played_songs(
+    State(state): State<AppState>,
+    Query(params): Query<StatsParams>,
+) -> Result<Json<Vec<SongPlays>>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let since = params.since(&state, 7);
+
+    Ok(Json(
+        DailyStats::most_played(since, MOST_PLAYED_LIMIT, &mut conn).await?,
+    ))
+}
+
+/// Plays per day in the last 30 days (or `days`).
+async fn get_plays_series(
+    State(state): State<AppState>,
+    Query(params): Query<StatsParams>,
+) -> Result<Json<Series>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let since = params.since(&state, 30);
+    let plays: Vec<(Date, i64)> = DailyStats::since(since, &mut conn)
+        .await?
+        .into_iter()
+        .map(|stats| (stats.day, stats.plays.into()))
+        .collect();
+
+    Ok(Json(timeseries::sum_counts(
+        Bucket::Day,
+        since,
+        yesterday(&state),
+        &plays,
+    )))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WeeksParams {
+    /// How many weeks back, up to a year
+    weeks: Option<i64>,
+}
+
+/// New songs per week in the last 12 weeks (or `weeks`), the current one included.
+async fn get_new_songs_series(
+    State(state): State<AppState>,
+    Query(params): Query<WeeksParams>,
+) -> Result<Json<Series>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let weeks = params.weeks.unwrap_or(12).clamp(1, MAX_DAYS / 7);
+    let until = yesterday(&state);
+    let since = timeseries::bucket_start(Bucket::Week, until - Duration::weeks(weeks - 1));
+    let new_songs: Vec<(Date, i64)> = DailyStats::since(since, &mut conn)
+        .await?
+        .into_iter()
+        .map(|stats| (stats.day, stats.new_songs.into()))
+        .collect();
+
+    Ok(Json(timeseries::sum_counts(
+        Bucket::Week,
+        since,
+        until,
+        &new_songs,
+    )))
+}
+
+/// The player's skill points in the main realm at the end of every day in the last 90 days (or `days`).
+/// Only goes back to when the job worker started recording them, it's 0 before that.
+async fn get_skill_points_series(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(params): Query<StatsParams>,
+) -> Result<Json<Series>, RouteError> {
+    use crate::schema::players;
+
+    let mut conn = state.db_read.get().await?;
+
+    let player: Player = players::table.find(id).first(&mut conn).await?;
+    let since = params.since(&state, 90);
+    let changes: Vec<(Date, i64)> = DailyPlayerPoints::since(player.id, since, &mut conn)
+        .await?
+        .into_iter()
+        .map(|points| (points.day, points.skill_points))
+        .collect();
+
+    Ok(Json(timeseries::carry_levels(
+        Bucket::Day,
+        since,
+        yesterday(&state),
+        &changes,
+    )))
+}
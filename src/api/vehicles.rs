@@ -0,0 +1,23 @@
+use axum::{extract::State, routing::get, Json, Router};
+use serde::Serialize;
+
+use crate::{models::vehicle_usage::VehicleMeta, util::errors::RouteError, AppState};
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/meta", get(get_meta))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MetaResponse {
+    vehicles: Vec<VehicleMeta>,
+}
+
+/// Which characters are popular, and how well people do with them.
+async fn get_meta(State(state): State<AppState>) -> Result<Json<MetaResponse>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+
+    let vehicles = VehicleMeta::load(&mut conn).await?;
+
+    Ok(Json(MetaResponse { vehicles }))
+}
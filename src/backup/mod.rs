@@ -0,0 +1,507 @@
+//! Logical backups of the database and the rankings in Redis.
+//!
+//! Every backup is stored under `backups/{name}/` in the blob storage (see [`crate::storage`]),
+//! named after the time it was made (like `20240917T031500.123Z`). It contains:
+//! - one [JSON Lines](https://jsonlines.org) file per table, every row as produced by Postgres' `row_to_json`
+//! - `rankings.json`, the skill point rankings of every realm
+//! - `manifest.json`, saying what's in the backup
+//!
+//! All tables are read in one repeatable read transaction, so they're consistent with each other.
+//! Rankings can be rebuilt from the scores with `refresh-skill-points`, they're only included so restoring is quicker.
+//! The files are written to a local staging directory first and stored afterwards, the manifest last:
+//! a backup without one is unfinished.
+//!
+//! Backups are made with the `backup` command, `POST /api/admin/backups` or daily by the job worker,
+//! see the `backup` section of the config.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use diesel::{sql_query, sql_types::Text, QueryableByName};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncPgConnection, RunQueryDsl};
+use redis::AsyncCommands;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::{
+    fs::{self, File},
+    io::{AsyncWriteExt, BufWriter},
+};
+use tracing::{info, warn};
+
+use crate::{
+    storage::BlobStorage,
+    util::{realm::MAIN_REALM, redis_keys},
+    AppState,
+};
+
+/// Tables that end up in a backup.
+/// `jobs` is left out, it's only interesting while the server is running.
+/// `events` is left out too, it's analytics that can get huge and isn't needed to restore anything.
+const TABLES: &[&str] = &[
+    "players",
+    "songs",
+    "extra_song_info",
+    "song_aliases",
+    "song_links",
+    "metadata_provenance",
+    "scores",
+    "scores_archive",
+    "score_appeals",
+    "score_removals",
+    "sandbagging_flags",
+    "ride_sources",
+    "skill_point_ledger",
+    "leaderboard_snapshots",
+    "daily_stats",
+    "daily_song_plays",
+    "daily_player_points",
+    "server_records",
+    "gold_thresholds",
+    "traffic_densities",
+    "traffic_flags",
+    "vehicle_usage",
+    "shouts",
+    "shout_reports",
+    "rivalries",
+    "rival_digests",
+    "merge_log",
+    "metadata_suggestions",
+    "song_requests",
+    "song_request_votes",
+    "pending_songs",
+    "song_quarantine",
+    "news_items",
+    "player_messages",
+    "player_names",
+    "player_redirects",
+    "player_slugs",
+    "notification_links",
+    "api_keys",
+    "impersonations",
+];
+/// Where backups are stored, see [`crate::storage`].
+const BACKUPS_PREFIX: &str = "backups/";
+/// Written last, see the module documentation.
+const MANIFEST_FILE: &str = "manifest.json";
+/// How many rows are fetched from the database at once while exporting a table.
+const FETCH_SIZE: usize = 1000;
+/// Staging directories are named this plus the name of the backup.
+const STAGING_PREFIX: &str = "wavebreaker-backup-";
+/// Staging directories this old are left over from a backup that crashed, see [`remove_stale_staging`].
+const STALE_STAGING_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Describes a finished backup, written to its `manifest.json`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub name: String,
+    #[serde(with = "time::serde::iso8601")]
+    pub created_at: OffsetDateTime,
+    /// Number of rows exported per table
+    pub tables: BTreeMap<&'static str, usize>,
+    /// Number of ranked players per realm
+    pub rankings: BTreeMap<String, usize>,
+}
+
+#[derive(QueryableByName)]
+struct JsonRow {
+    #[diesel(sql_type = Text)]
+    row: String,
+}
+
+/// Makes a backup, stores it and deletes old backups afterwards.
+///
+/// # Errors
+/// Fails if the database or Redis can't be read, or the backup can't be written or stored.
+pub async fn run(state: &AppState) -> anyhow::Result<Manifest> {
+    remove_stale_staging().await;
+
+    let created_at = OffsetDateTime::now_utc();
+    let name = backup_name(created_at);
+    let staging = std::env::temp_dir().join(format!("{STAGING_PREFIX}{name}"));
+    // Not `create_dir_all`, two backups must never share a staging directory
+    fs::create_dir(&staging)
+        .await
+        .with_context(|| format!("Failed to create staging directory {}", staging.display()))?;
+
+    let result = export(state, name, created_at, &staging).await;
+    let result = match result {
+        Ok(manifest) => store(&state.storage, &manifest.name, &staging)
+            .await
+            .map(|()| manifest),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = fs::remove_dir_all(&staging).await {
+        warn!(
+            "Failed to clean up staging directory {}: {e}",
+            staging.display()
+        );
+    }
+    let manifest = result?;
+    info!(
+        "Backup {} stored in {}",
+        manifest.name,
+        state.storage.describe()
+    );
+
+    prune(&state.storage, state.config.backup.keep).await?;
+
+    Ok(manifest)
+}
+
+/// Writes every file of the backup into the staging directory.
+async fn export(
+    state: &AppState,
+    name: String,
+    created_at: OffsetDateTime,
+    staging: &Path,
+) -> anyhow::Result<Manifest> {
+    let mut conn = state.db.get().await?;
+    let tables = conn
+        .build_transaction()
+        .repeatable_read()
+        .read_only()
+        .run(|conn| {
+            async move {
+                let mut tables = BTreeMap::new();
+                for table in TABLES {
+                    let rows = export_table(table, &staging.join(format!("{table}.jsonl")), conn)
+                        .await
+                        .with_context(|| format!("Failed to export table {table}"))?;
+                    tables.insert(*table, rows);
+                }
+                Ok::<_, anyhow::Error>(tables)
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    let mut realms = vec![MAIN_REALM.to_owned()];
+    realms.extend(
+        state
+            .config
+            .main
+            .realms
+            .iter()
+            .map(|realm| realm.name().to_owned()),
+    );
+    let mut redis_conn = state.redis.get().await?;
+    let rankings = export_rankings(&realms, &staging.join("rankings.json"), &mut redis_conn)
+        .await
+        .context("Failed to export rankings")?;
+
+    let manifest = Manifest {
+        name,
+        created_at,
+        tables,
+        rankings,
+    };
+    write_json(&staging.join(MANIFEST_FILE), &manifest).await?;
+
+    Ok(manifest)
+}
+
+/// Stores every file in the staging directory as part of the backup, the manifest last.
+async fn store(storage: &BlobStorage, name: &str, staging: &Path) -> anyhow::Result<()> {
+    let mut files = Vec::new();
+    let mut entries = fs::read_dir(staging).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Ok(file) = entry.file_name().into_string() {
+            if file != MANIFEST_FILE {
+                files.push(file);
+            }
+        }
+    }
+    files.sort_unstable();
+    files.push(MANIFEST_FILE.to_owned());
+
+    for file in files {
+        storage
+            .put_file(
+                &format!("{BACKUPS_PREFIX}{name}/{file}"),
+                &staging.join(&file),
+            )
+            .await
+            .with_context(|| format!("Failed to store {file} of backup {name}"))?;
+    }
+
+    Ok(())
+}
+
+/// Writes every row of the table to `path`, one JSON object per line.
+/// A cursor is used so big tables don't have to fit into memory, which needs a transaction.
+///
+/// # Returns
+/// How many rows were written.
+async fn export_table(
+    table: &str,
+    path: &Path,
+    conn: &mut AsyncPgConnection,
+) -> anyhow::Result<usize> {
+    let mut file = BufWriter::new(File::create(path).await?);
+
+    // Table names come from `TABLES`, never from anyone else
+    sql_query(format!(
+        "DECLARE backup_cursor NO SCROLL CURSOR FOR SELECT row_to_json(t)::text AS row FROM {table} t"
+    ))
+    .execute(conn)
+    .await?;
+
+    let mut count = 0;
+    loop {
+        let rows: Vec<JsonRow> = sql_query(format!("FETCH {FETCH_SIZE} FROM backup_cursor"))
+            .load(conn)
+            .await?;
+        for row in &rows {
+            file.write_all(row.row.as_bytes()).await?;
+            file.write_all(b"\n").await?;
+        }
+        count += rows.len();
+        if rows.len() < FETCH_SIZE {
+            break;
+        }
+    }
+
+    sql_query("CLOSE backup_cursor").execute(conn).await?;
+    file.flush().await?;
+
+    Ok(count)
+}
+
+/// Writes the skill point rankings of the realms to `path`, as `[player ID, skill points]` pairs per realm.
+///
+/// # Returns
+/// How many players are ranked in each realm.
+async fn export_rankings(
+    realms: &[String],
+    path: &Path,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> anyhow::Result<BTreeMap<String, usize>> {
+    let mut rankings: BTreeMap<&str, Vec<(i32, i64)>> = BTreeMap::new();
+    for realm in realms {
+        let ranking: Vec<(i32, i64)> = redis_conn
+            .zrange_withscores(redis_keys::skill_points(realm), 0, -1)
+            .await?;
+        rankings.insert(realm, ranking);
+    }
+    write_json(path, &rankings).await?;
+
+    Ok(rankings
+        .into_iter()
+        .map(|(realm, ranking)| (realm.to_owned(), ranking.len()))
+        .collect())
+}
+
+async fn write_json(path: &Path, value: &impl Serialize) -> anyhow::Result<()> {
+    fs::write(path, serde_json::to_vec_pretty(value)?).await?;
+    Ok(())
+}
+
+/// Removes the staging directories of backups that crashed before they could clean up after themselves.
+/// Only old ones, so a backup running next to this one keeps its directory. Failing to is only logged.
+async fn remove_stale_staging() {
+    let Ok(mut entries) = fs::read_dir(std::env::temp_dir()).await else {
+        return;
+    };
+    let mut stale: Vec<PathBuf> = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let is_staging = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|file| file.starts_with(STAGING_PREFIX));
+        let is_old = entry
+            .metadata()
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > STALE_STAGING_AGE);
+        if is_staging && is_old {
+            stale.push(entry.path());
+        }
+    }
+
+    for directory in stale {
+        match fs::remove_dir_all(&directory).await {
+            Ok(()) => info!("Removed leftover staging directory {}", directory.display()),
+            Err(e) => warn!(
+                "Failed to remove leftover staging directory {}: {e}",
+                directory.display()
+            ),
+        }
+    }
+}
+
+/// Deletes all but the newest `keep` finished backups in the storage, and unfinished ones that are older than a
+/// day, see [`outdated`]. Anything else in there is left alone.
+async fn prune(storage: &BlobStorage, keep: usize) -> anyhow::Result<()> {
+    let mut backups = Vec::new();
+    for name in storage.list(BACKUPS_PREFIX).await? {
+        if is_backup_name(&name) {
+            let finished = storage
+                .list(&format!("{BACKUPS_PREFIX}{name}/"))
+                .await?
+                .iter()
+                .any(|file| file == MANIFEST_FILE);
+            backups.push((name, finished));
+        }
+    }
+
+    let stale_before = backup_name(OffsetDateTime::now_utc() - STALE_STAGING_AGE);
+    for name in outdated(backups, keep, &stale_before) {
+        match storage
+            .delete_all(&format!("{BACKUPS_PREFIX}{name}/"))
+            .await
+        {
+            Ok(()) => info!("Deleted old backup {name}"),
+            Err(e) => warn!("Failed to delete old backup {name}: {e:?}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Picks the backups [`prune`] deletes from their names and whether they're finished: all finished ones but the
+/// newest `keep`, and unfinished ones made before `stale_before` (a backup name), which crashed. Newer unfinished
+/// ones might still be running.
+fn outdated(mut backups: Vec<(String, bool)>, keep: usize, stale_before: &str) -> Vec<String> {
+    // Names sort the same way as the times they're made from
+    backups.sort_unstable();
+
+    // Never delete the backup that was just made
+    let mut finished_to_delete = backups
+        .iter()
+        .filter(|(_, finished)| *finished)
+        .count()
+        .saturating_sub(keep.max(1));
+    backups
+        .into_iter()
+        .filter(|(name, finished)| {
+            if *finished {
+                let delete = finished_to_delete > 0;
+                finished_to_delete = finished_to_delete.saturating_sub(1);
+                delete
+            } else {
+                name.as_str() < stale_before
+            }
+        })
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Names are precise to the millisecond, so backups made right after one another don't end up in the same place.
+fn backup_name(time: OffsetDateTime) -> String {
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}.{:03}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+        time.millisecond()
+    )
+}
+
+/// Whether the name looks like one made by [`backup_name`].
+/// Backups made before names had milliseconds (like `20240917T031500Z`) count too.
+fn is_backup_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    let seconds_end = match bytes.len() {
+        16 => 15,
+        20 if bytes[15] == b'.' && bytes[16..19].iter().all(u8::is_ascii_digit) => 19,
+        _ => return false,
+    };
+    bytes[8] == b'T'
+        && bytes[seconds_end] == b'Z'
+        && bytes[..8].iter().all(u8::is_ascii_digit)
+        && bytes[9..15].iter().all(u8::is_ascii_digit)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use time::{Date, Month};
+
+    use super::*;
+
+    fn at(second: u8, millisecond: u16) -> OffsetDateTime {
+        Date::from_calendar_date(2024, Month::September, 17)
+            .unwrap()
+            .with_hms_milli(3, 15, second, millisecond)
+            .unwrap()
+            .assume_utc()
+    }
+
+    /// Tables left out of backups on purpose, see [`TABLES`].
+    const NOT_BACKED_UP: [&str; 2] = ["events", "jobs"];
+
+    #[test]
+    fn test_tables_cover_schema() {
+        let schema = include_str!("../schema.rs");
+        let mut lines = schema.lines();
+        let mut missing = Vec::new();
+        while let Some(line) = lines.next() {
+            if line.trim() != "diesel::table! {" {
+                continue;
+            }
+            let table = lines
+                .next()
+                .and_then(|line| line.split_whitespace().next())
+                .unwrap();
+            if !TABLES.contains(&table) && !NOT_BACKED_UP.contains(&table) {
+                missing.push(table);
+            }
+        }
+        assert!(
+            missing.is_empty(),
+            "Tables missing from backups: {missing:?}"
+        );
+    }
+
+    #[test]
+    fn test_outdated() {
+        let backups = vec![
+            (backup_name(at(4, 0)), true),
+            (backup_name(at(1, 0)), true),
+            (backup_name(at(2, 0)), false),
+            (backup_name(at(3, 0)), true),
+            (backup_name(at(5, 0)), false),
+        ];
+        // Unfinished backups don't count towards the ones kept
+        assert_eq!(
+            outdated(backups.clone(), 2, &backup_name(at(0, 0))),
+            vec![backup_name(at(1, 0))]
+        );
+        // Only unfinished backups older than the cutoff are deleted
+        assert_eq!(
+            outdated(backups.clone(), 3, &backup_name(at(3, 0))),
+            vec![backup_name(at(2, 0))]
+        );
+        assert_eq!(
+            outdated(backups, 0, &backup_name(at(6, 0))),
+            vec![
+                backup_name(at(1, 0)),
+                backup_name(at(2, 0)),
+                backup_name(at(3, 0)),
+                backup_name(at(5, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backup_name() {
+        let name = backup_name(at(0, 123));
+        assert_eq!(name, "20240917T031500.123Z");
+        assert!(is_backup_name(&name));
+        assert!(is_backup_name("20240917T031500Z"));
+        assert!(!is_backup_name("20240917T031500.12Z"));
+        assert!(!is_backup_name("notes.txt"));
+        // Sorting by name is sorting by time
+        assert!(backup_name(at(0, 999)) < backup_name(at(1, 0)));
+    }
+}
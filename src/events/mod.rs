@@ -0,0 +1,211 @@
+//! Analytics events, kept apart from the tables the game is served from.
+//!
+//! Gameplay code only [`EventSink::emit`]s events, which never waits: they're handed to a background writer
+//! through a bounded channel and dropped if it can't keep up, analytics aren't worth slowing down a ride for.
+//! The writer stores them in batches, either in the `events` table or by sending them to a webhook.
+//!
+//! Events are anonymous, they say what happened to which song but never who did it.
+//! Everything is off unless `events.enabled` is set in the config.
+
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use axum::http::header::CONTENT_TYPE;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{error, info, warn};
+
+use crate::{
+    util::game_types::{Character, League},
+    AppState,
+};
+
+/// How many events can wait for the writer before new ones are dropped.
+const CHANNEL_CAPACITY: usize = 10_000;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    /// A lookup created a new song
+    #[serde(rename_all = "camelCase")]
+    SongCreated { song_id: i32, realm: String },
+    /// A score was submitted, whether it beat the player's old one or not
+    #[serde(rename_all = "camelCase")]
+    ScoreSubmitted {
+        song_id: i32,
+        realm: String,
+        league: League,
+        vehicle: Character,
+        score: i32,
+    },
+    /// A score took the top spot of a song's leaderboard from another player
+    #[serde(rename_all = "camelCase")]
+    Dethrone {
+        song_id: i32,
+        realm: String,
+        league: League,
+    },
+}
+
+impl Event {
+    /// Stored in the `kind` column, same as the `kind` field of the JSON.
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::SongCreated { .. } => "song_created",
+            Self::ScoreSubmitted { .. } => "score_submitted",
+            Self::Dethrone { .. } => "dethrone",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TimedEvent {
+    #[serde(flatten)]
+    event: Event,
+    #[serde(with = "time::serde::iso8601")]
+    created_at: OffsetDateTime,
+}
+
+/// Where events are emitted to. Does nothing if events are disabled.
+#[derive(Clone)]
+pub struct EventSink {
+    sender: Option<mpsc::Sender<TimedEvent>>,
+    /// Taken by [`run_writer`] when it starts
+    receiver: Arc<Mutex<Option<mpsc::Receiver<TimedEvent>>>>,
+}
+
+impl EventSink {
+    #[must_use]
+    pub fn new(enabled: bool) -> Self {
+        if !enabled {
+            return Self {
+                sender: None,
+                receiver: Arc::new(Mutex::new(None)),
+            };
+        }
+
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        Self {
+            sender: Some(sender),
+            receiver: Arc::new(Mutex::new(Some(receiver))),
+        }
+    }
+
+    /// Hands the event to the writer, without waiting for it to be written.
+    pub fn emit(&self, event: Event) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+
+        let timed = TimedEvent {
+            event,
+            created_at: OffsetDateTime::now_utc(),
+        };
+        // Closed only happens if the writer isn't running, e.g. in manager commands
+        if let Err(TrySendError::Full(dropped)) = sender.try_send(timed) {
+            warn!(
+                "Event writer can't keep up, dropped {} event",
+                dropped.event.kind()
+            );
+        }
+    }
+}
+
+/// Writes emitted events in batches until the server stops. Meant to be spawned as a task next to the server.
+pub async fn run_writer(state: AppState) {
+    let receiver = state
+        .events
+        .receiver
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    let Some(mut receiver) = receiver else {
+        return;
+    };
+    let config = &state.config.events;
+    info!("Event writer started");
+
+    let mut batch = Vec::with_capacity(config.batch_size);
+    loop {
+        // Waits for the first event of the batch, then collects more for a while
+        if receiver.recv_many(&mut batch, config.batch_size).await == 0 {
+            break;
+        }
+        let deadline = tokio::time::sleep(Duration::from_secs(config.flush_interval_secs));
+        tokio::pin!(deadline);
+        while batch.len() < config.batch_size {
+            let missing = config.batch_size - batch.len();
+            tokio::select! {
+                () = &mut deadline => break,
+                more = receiver.recv_many(&mut batch, missing) => {
+                    if more == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = write_batch(&state, &batch).await {
+            error!("Failed to write {} event(s): {e:?}", batch.len());
+        }
+        batch.clear();
+    }
+}
+
+async fn write_batch(state: &AppState, batch: &[TimedEvent]) -> anyhow::Result<()> {
+    if let Some(webhook_url) = &state.config.events.webhook_url {
+        reqwest::Client::new()
+            .post(webhook_url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(batch)?)
+            .send()
+            .await?
+            .error_for_status()?;
+    } else {
+        insert_batch(state, batch).await?;
+    }
+
+    Ok(())
+}
+
+async fn insert_batch(state: &AppState, batch: &[TimedEvent]) -> anyhow::Result<()> {
+    use crate::schema::events::dsl::*;
+
+    let rows = batch
+        .iter()
+        .map(|timed| {
+            Ok((
+                kind.eq(timed.event.kind()),
+                payload.eq(serde_json::to_value(&timed.event)?),
+                created_at.eq(timed.created_at),
+            ))
+        })
+        .collect::<Result<Vec<_>, serde_json::Error>>()?;
+
+    let mut conn = state.db.get().await?;
+    diesel::insert_into(events)
+        .values(rows)
+        .execute(&mut conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Permanently deletes all events stored before `before`.
+///
+/// # Returns
+/// The number of events that were purged.
+pub async fn purge(before: OffsetDateTime, conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+    use crate::schema::events::dsl::*;
+
+    diesel::delete(events.filter(created_at.lt(before)))
+        .execute(conn)
+        .await
+}
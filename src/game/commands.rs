@@ -0,0 +1,77 @@
+//! Tag commands, a way for players to talk to the server from inside the game.
+//!
+//! The original server let players type commands into a song's tags, e.g. to get sent to a specific leaderboard.
+//! The game sends those tags like any other song, so they're recognized in `fetch_song_id`
+//! before they can end up in the database as junk songs. Which commands exist is up to the config.
+
+use crate::TagCommand;
+
+/// Finds the command the title and artist sent by the game belong to, if any.
+/// Case and surrounding whitespace are ignored, since players type these by hand.
+pub fn find_tag_command<'a>(
+    commands: &'a [TagCommand],
+    title: &str,
+    artist: &str,
+) -> Option<&'a TagCommand> {
+    let (title, artist) = (title.trim(), artist.trim());
+    commands.iter().find(|command| {
+        command.title.trim().eq_ignore_ascii_case(title)
+            && command.artist.trim().eq_ignore_ascii_case(artist)
+    })
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands() -> Vec<TagCommand> {
+        vec![
+            TagCommand {
+                name: "weekly".to_owned(),
+                title: "Weekly Challenge".to_owned(),
+                artist: "Wavebreaker".to_owned(),
+                song_id: Some(42),
+            },
+            TagCommand {
+                name: "ping".to_owned(),
+                title: " Ping ".to_owned(),
+                artist: "Server".to_owned(),
+                song_id: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_find_tag_command() {
+        let commands = commands();
+
+        // Case and surrounding whitespace don't matter, on either side
+        for (title, artist) in [
+            ("Weekly Challenge", "Wavebreaker"),
+            ("weekly challenge", "WAVEBREAKER"),
+            ("  Weekly Challenge\t", " Wavebreaker "),
+        ] {
+            let command = find_tag_command(&commands, title, artist).unwrap();
+            assert_eq!(command.name, "weekly");
+            assert_eq!(command.song_id, Some(42));
+        }
+        assert_eq!(
+            find_tag_command(&commands, "ping", "server").unwrap().name,
+            "ping"
+        );
+
+        // Both have to match, and nothing but whitespace is ignored
+        for (title, artist) in [
+            ("Weekly Challenge", "Server"),
+            ("Ping", "Wavebreaker"),
+            ("Weekly  Challenge", "Wavebreaker"),
+            ("Weekly Challenge!", "Wavebreaker"),
+            ("Weekly", "Wavebreaker"),
+            ("", ""),
+        ] {
+            assert!(find_tag_command(&commands, title, artist).is_none());
+        }
+        assert!(find_tag_command(&[], "Weekly Challenge", "Wavebreaker").is_none());
+    }
+}
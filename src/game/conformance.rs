@@ -0,0 +1,276 @@
+//! Pins down the protocol Wavebreaker speaks with the game, so changing it by accident fails a test.
+//!
+//! The exchanges in `fixtures/` were written by hand after what the handlers parse and send, with the field names
+//! and values the game uses. They aren't recordings of the original Audiosurf server, so they only catch changes
+//! to what Wavebreaker does, not places where it already differs from the original.
+//!
+//! This is not a replay of the original server: no handler runs, so nothing here compares what a handler answers
+//! with a fixture. That needs captured exchanges, which there are none of yet, and handlers that can run against a
+//! stand-in for Postgres, Redis and Steam, which they can't. Until then, a response fixture only pins down the shape
+//! of the handler's response type.
+//!
+//! Every fixture is a pair: `<name>.request` is the form body the game sends (with the Wavebreaker mod, so with its
+//! extra fields), `<name>.xml` a response to it. The request has to parse (and pass validation) with the extractor
+//! the handler uses. The response is read into the handler's response type and serialized again, which has to give
+//! the same XML, apart from whitespace, attribute order and empty elements being self-closing. Renaming, adding or
+//! dropping an element of a response fails this.
+//!
+//! Requests are also sent through the router itself, with [`crate::test_state`]. The handlers need Postgres, Redis
+//! and Steam, which the tests don't have, so those only cover what happens before a handler runs: that the routes
+//! are where the game looks for them, their bodies are parsed as forms and oversized ones are refused in XML.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::FromRequest,
+    http::{header::CONTENT_TYPE, Method, Request, StatusCode},
+    response::Response,
+    Form,
+};
+use quick_xml::{events::Event, Reader};
+use serde::{de::DeserializeOwned, Serialize};
+use tower::ServiceExt;
+use validator::Validate;
+
+use super::{
+    gameplay::{
+        GetRidesRequest, GetRidesResponse, SendRideRequest, SendRideResponse, SongIdRequest,
+        SongIdResponse,
+    },
+    misc::{CustomNewsRequest, CustomNewsResponse},
+    user::{LoginSteamRequest, LoginSteamResponse, SteamSyncRequest, SteamSyncResponse},
+};
+use crate::{make_router, test_state};
+
+/// Where the game sends the requests of the fixtures, in the same order as [`FIXTURES`].
+const FIXTURE_ROUTES: [&str; 6] = [
+    "/as_steamlogin/game_AttemptLoginSteamVerified.php",
+    "/as_steamlogin/game_SteamSyncSteamVerified.php",
+    "/as_steamlogin/game_fetchsongid_unicode.php",
+    "/as_steamlogin/game_SendRideSteamVerified.php",
+    "/as_steamlogin/game_GetRidesSteamVerified.php",
+    "//as_steamlogin/game_CustomNews.php",
+];
+const FIXTURES: [&str; 6] = [
+    "login",
+    "steamsync",
+    "fetchsongid",
+    "sendride",
+    "getrides",
+    "customnews",
+];
+/// The game's other routes, which don't have fixtures
+const OTHER_ROUTES: [&str; 4] = [
+    "/as_steamlogin/game_fetchshouts_unicode.php",
+    "/as_steamlogin/game_sendShoutSteamVerified.php",
+    "/as/game_fetchtrackshape2.php",
+    "/as/asradio/game_asradiolist5.php",
+];
+
+/// XML with everything that doesn't change its meaning to the game taken out.
+#[derive(Debug, PartialEq, Eq)]
+enum Node {
+    Element {
+        name: String,
+        attributes: BTreeMap<String, String>,
+        children: Vec<Node>,
+    },
+    Text(String),
+}
+
+fn parse_xml(xml: &str) -> Node {
+    fn element(start: &quick_xml::events::BytesStart) -> Node {
+        let attributes = start
+            .attributes()
+            .map(|attribute| {
+                let attribute = attribute.expect("attribute should be well-formed");
+                (
+                    String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+                    attribute
+                        .unescape_value()
+                        .expect("attribute value should be well-formed")
+                        .into_owned(),
+                )
+            })
+            .collect();
+        Node::Element {
+            name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+            attributes,
+            children: vec![],
+        }
+    }
+
+    fn push(stack: &mut [Node], node: Node) {
+        if let Some(Node::Element { children, .. }) = stack.last_mut() {
+            children.push(node);
+        }
+    }
+
+    let mut reader = Reader::from_str(xml);
+    // The document node, holding the root element
+    let mut stack = vec![Node::Element {
+        name: String::new(),
+        attributes: BTreeMap::new(),
+        children: vec![],
+    }];
+    loop {
+        match reader.read_event().expect("XML should be well-formed") {
+            Event::Start(start) => stack.push(element(&start)),
+            Event::Empty(start) => push(&mut stack, element(&start)),
+            Event::End(_) => {
+                let node = stack.pop().expect("elements should be balanced");
+                push(&mut stack, node);
+            }
+            Event::Text(text) => {
+                let text = text.unescape().expect("text should be well-formed");
+                if !text.trim().is_empty() {
+                    push(&mut stack, Node::Text(text.trim().to_owned()));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    match stack.pop() {
+        Some(Node::Element { mut children, .. }) if stack.is_empty() && children.len() == 1 => {
+            children.remove(0)
+        }
+        _ => panic!("XML should have a single root element"),
+    }
+}
+
+/// Builds a request the way the game sends it.
+fn game_request(method: Method, path: &str, body: impl Into<Body>) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(path)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(body.into())
+        .expect("request should build")
+}
+
+/// Sends the request through the router, see the module documentation.
+async fn send(request: Request<Body>) -> Response {
+    make_router(test_state())
+        .oneshot(request)
+        .await
+        .expect("the router never fails")
+}
+
+/// Parses the request with the extractor the handler uses.
+async fn parse_request<T: DeserializeOwned>(fixture: &str, body: &'static str) -> T {
+    let Form(payload) = Form::<T>::from_request(game_request(Method::POST, "/", body), &())
+        .await
+        .unwrap_or_else(|e| panic!("{fixture}.request should parse: {}", e.body_text()));
+    payload
+}
+
+/// Reads the fixture's response into `T` and checks that serializing it again gives the same XML.
+fn check_response<T: Serialize + DeserializeOwned>(fixture: &str, xml: &str) {
+    let response: T = quick_xml::de::from_str(xml)
+        .unwrap_or_else(|e| panic!("{fixture}.xml should deserialize: {e}"));
+    let serialized = quick_xml::se::to_string(&response)
+        .unwrap_or_else(|e| panic!("{fixture}.xml should serialize again: {e}"));
+
+    assert_eq!(
+        parse_xml(&serialized),
+        parse_xml(xml),
+        "{fixture}.xml isn't what Wavebreaker sends: {serialized}"
+    );
+}
+
+#[tokio::test]
+async fn test_login() {
+    parse_request::<LoginSteamRequest>("login", include_str!("fixtures/login.request")).await;
+    check_response::<LoginSteamResponse>("login", include_str!("fixtures/login.xml"));
+}
+
+#[tokio::test]
+async fn test_steam_sync() {
+    parse_request::<SteamSyncRequest>("steamsync", include_str!("fixtures/steamsync.request"))
+        .await;
+    check_response::<SteamSyncResponse>("steamsync", include_str!("fixtures/steamsync.xml"));
+}
+
+#[tokio::test]
+async fn test_fetch_song_id() {
+    let request =
+        parse_request::<SongIdRequest>("fetchsongid", include_str!("fixtures/fetchsongid.request"))
+            .await;
+    assert!(request.validate().is_ok());
+    check_response::<SongIdResponse>("fetchsongid", include_str!("fixtures/fetchsongid.xml"));
+}
+
+#[tokio::test]
+async fn test_send_ride() {
+    let request =
+        parse_request::<SendRideRequest>("sendride", include_str!("fixtures/sendride.request"))
+            .await;
+    assert!(request.validate().is_ok());
+    check_response::<SendRideResponse>("sendride", include_str!("fixtures/sendride.xml"));
+}
+
+#[tokio::test]
+async fn test_get_rides() {
+    parse_request::<GetRidesRequest>("getrides", include_str!("fixtures/getrides.request")).await;
+    check_response::<GetRidesResponse>("getrides", include_str!("fixtures/getrides.xml"));
+}
+
+#[tokio::test]
+async fn test_custom_news() {
+    parse_request::<CustomNewsRequest>("customnews", include_str!("fixtures/customnews.request"))
+        .await;
+    check_response::<CustomNewsResponse>("customnews", include_str!("fixtures/customnews.xml"));
+}
+
+#[tokio::test]
+async fn test_routes_exist() {
+    // The game only ever posts, so anything else being refused means the route is there
+    for path in FIXTURE_ROUTES.iter().chain(&OTHER_ROUTES) {
+        let response = send(game_request(Method::GET, path, Body::empty())).await;
+        assert_eq!(
+            response.status(),
+            StatusCode::METHOD_NOT_ALLOWED,
+            "{path} should be routed"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_routes_parse_forms() {
+    // Missing every field, so it's refused while parsing, before the handler needs anything
+    for (path, fixture) in FIXTURE_ROUTES.iter().zip(FIXTURES) {
+        let response = send(game_request(Method::POST, path, "unrelated=1")).await;
+        assert_eq!(
+            response.status(),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "{path} should parse the body of {fixture}.request as a form"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_oversized_body() {
+    let body = format!("ticket={}", "A".repeat(128 * 1024));
+    let response = send(game_request(Method::POST, FIXTURE_ROUTES[0], body)).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body should be readable");
+    assert_eq!(
+        parse_xml(&String::from_utf8_lossy(&body)),
+        parse_xml(r#"<RESULT status="failed"/>"#)
+    );
+}
+
+#[test]
+fn test_parse_xml() {
+    assert_eq!(
+        parse_xml(r#"<A b="1" c="2"><D/>  <E> text </E></A>"#),
+        parse_xml("<A c=\"2\" b=\"1\">\n    <D></D>\n    <E>text</E>\n</A>")
+    );
+    assert_ne!(parse_xml("<A><D/></A>"), parse_xml("<A><E/></A>"));
+    assert_ne!(parse_xml(r#"<A b="1"/>"#), parse_xml(r#"<A b="2"/>"#));
+}
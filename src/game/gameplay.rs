@@ -1,37 +1,79 @@
-use axum::{extract::State, http::StatusCode, Form};
-use axum_serde::Xml;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode, Version},
+    Extension, Form,
+};
 use diesel::{associations::HasTable, prelude::*};
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use steam_rs::steam_id::SteamId;
 use time::OffsetDateTime;
 use tokio::try_join;
-use tracing::{error, info, instrument};
+use tracing::{error, field, info, instrument, warn, Span};
+use validator::Validate;
 
-use super::helpers::ticket_auth;
+use super::{
+    commands::find_tag_command,
+    helpers::{ticket_auth, validate_payload},
+    protocol::{ascii_text, ClientVersion, GameResponse, GameXml},
+};
 use crate::{
+    events::Event,
+    jobs::Job,
     models::{
         extra_song_info::ExtraSongInfo,
+        gold_thresholds::GoldThreshold,
+        metadata_provenance::MetadataSource,
+        notification_links::NotificationKind,
+        pending_songs::{NewPendingSong, PendingSong},
         players::Player,
+        ride_sources::RideSource,
         rivalries::Rivalry,
-        scores::{NewScore, Score, ScoreWithPlayer},
+        scores::{NewScore, Score, ScoreWithPlayer, GAME_MAX_PAGE},
+        song_quarantine::NewQuarantinedSong,
         songs::{NewSong, Song},
+        traffic_densities::TrafficDensity,
+        traffic_flags::{FlaggedRide, TrafficFlag},
+        vehicle_usage::VehicleUsage,
     },
+    records,
     util::{
+        activity::{self, RecentRide},
+        bogus_songs::{check_song_tags, BogusSongReason},
+        client_source::ClientSource,
+        clock, cover_colors,
         errors::{IntoRouteError, RouteError},
-        game_types::{split_x_separated, Character, Leaderboard, League},
+        game_types::{
+            parse_separated_i32, validate_track_shape, validate_xstats, Character, Leaderboard,
+            League, MAX_TRACK_SHAPE_ENTRIES, MAX_XSTATS_ENTRIES,
+        },
+        i18n::Text,
+        live_leaderboards::{self, LeaderboardUpdate},
+        notify,
+        radio::cached_radio_songs,
+        rank_cache,
+        realm::{Realm, MAIN_REALM},
+        redis_keys, redis_ops,
+        reserved_songs::find_reserved_radio_song,
     },
     AppState,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct SongIdRequest {
+    #[validate(length(min = 1, max = 512))]
     artist: String,
+    #[validate(length(min = 1, max = 512))]
     song: String,
     league: League,
     //Wavebreaker-specific
     ticket: String,
+    #[validate(length(equal = 36))]
     mbid: Option<String>,
     #[serde(rename = "releasembid")]
+    #[validate(length(equal = 36))]
     release_mbid: Option<String>,
 }
 
@@ -44,34 +86,102 @@ pub struct SongIdResponse {
     song_id: i32,
 }
 
+impl SongIdResponse {
+    fn found(song_id: i32) -> Self {
+        Self {
+            status: "allgood".to_owned(),
+            song_id,
+        }
+    }
+
+    /// The game won't play the song and shows an error.
+    fn failed() -> Self {
+        Self {
+            status: "failed".to_owned(),
+            song_id: 0,
+        }
+    }
+}
+
+impl GameResponse for SongIdResponse {}
+
 /// Attempts to get a song ID from the server.
 /// If the song isn't registered on the server yet, it will be created.
+/// Titles and artists that are tag commands get the command's response instead, see [`super::commands`].
+/// Tags that clearly aren't a song are refused and put into quarantine.
+/// Tags of radio songs with a reserved ID get that song, see [`crate::util::reserved_songs`].
+/// New songs are only created if the player is allowed to, otherwise they're queued as pending songs, see
+/// [`crate::util::song_creation`].
 ///
 /// # Errors
 ///
 /// This fails if:
 /// - The response fails to serialize
 /// - The song fails to be created/retrieved
-#[instrument(skip_all)]
+#[instrument(skip_all, fields(steam_id = field::Empty))]
+#[allow(clippy::too_many_lines)]
 pub async fn fetch_song_id(
     State(state): State<AppState>,
+    Extension(realm): Extension<Realm>,
+    Extension(version): Extension<ClientVersion>,
     Form(payload): Form<SongIdRequest>,
-) -> Result<Xml<SongIdResponse>, RouteError> {
+) -> Result<GameXml<SongIdResponse>, RouteError> {
     use crate::{
-        schema::{extra_song_info::dsl::*, songs::dsl::*},
+        schema::{
+            extra_song_info::dsl::*,
+            songs::dsl::{modifiers, realm as song_realm},
+        },
         util::modifiers::{parse_from_title, remove_from_title},
     };
 
+    validate_payload(&payload, "FetchSongId")?;
+
     let steam_player = ticket_auth(&payload.ticket, &state.steam_api).await?;
 
+    if let Some(command) =
+        find_tag_command(&state.config.tag_commands, &payload.song, &payload.artist)
+    {
+        info!(
+            "Player {} (Steam) used tag command {}",
+            steam_player, command.name
+        );
+        return Ok(GameXml(
+            version,
+            command
+                .song_id
+                .map_or_else(SongIdResponse::failed, SongIdResponse::found),
+        ));
+    }
+
     let mut conn = state.db.get().await?;
+
+    if let Some(reason) = check_song_tags(&payload.song, &payload.artist) {
+        info!(
+            "Song {} - {} looked up by {} (Steam) refused, reason {:?}",
+            payload.artist, payload.song, steam_player, reason
+        );
+        quarantine_song(&payload, &realm, steam_player, reason, &mut conn).await?;
+
+        return Ok(GameXml(version, SongIdResponse::failed()));
+    }
+
+    if let Some(song) = find_reserved_song(&payload, &realm, &mut conn).await? {
+        info!(
+            "Song {} - {} looked up by {} (Steam), reserved ID {}",
+            song.artist, song.title, steam_player, song.id
+        );
+        return Ok(GameXml(version, SongIdResponse::found(song.id)));
+    }
+
+    let mut redis_conn = state.redis.get().await?;
     let parsed_modifiers = parse_from_title(&payload.song);
 
     // if recording MBID is provided, look it up using that + modifiers from the title
     // else, look up the song by title and artist
     if let Some(recording_mbid) = &payload.mbid {
-        let song = songs
+        let song = Song::all()
             .inner_join(extra_song_info)
+            .filter(song_realm.eq(realm.name()))
             .filter(
                 mbid.eq(recording_mbid)
                     .and(modifiers.is_not_distinct_from(&parsed_modifiers)),
@@ -88,40 +198,67 @@ pub async fn fetch_song_id(
                 song.artist, song.title, steam_player, payload.league, payload.mbid, payload.release_mbid
             );
 
-            Ok(Xml(SongIdResponse {
-                status: "allgood".to_owned(),
-                song_id: song.id,
-            }))
+            Ok(GameXml(version, SongIdResponse::found(song.id)))
         } else {
             info!(
                 "Song {} - {} looked up by {} (Steam), league {:?}, MBID {:?}, release MBID {:?} (new MBID lookup)",
                 payload.artist, payload.song, steam_player, payload.league, payload.mbid, payload.release_mbid
             );
 
-            let song = NewSong::new(
+            let new_song = NewSong::new(
                 &remove_from_title(&payload.song),
                 &payload.artist,
                 parsed_modifiers,
+                realm.name(),
+            );
+            let Some(song) = find_or_create_song(
+                &state,
+                &new_song,
+                &payload,
+                steam_player,
+                &mut conn,
+                &mut redis_conn,
             )
-            .find_or_create(&mut conn)
-            .await?;
+            .await?
+            else {
+                return Ok(GameXml(version, SongIdResponse::failed()));
+            };
 
-            song.add_metadata_mbid(recording_mbid, payload.release_mbid.as_deref(), &mut conn)
+            // The lookup may have found a song that was fixed by hand, the game doesn't get to change it
+            if !song.locked {
+                song.add_metadata_mbid(
+                    recording_mbid,
+                    payload.release_mbid.as_deref(),
+                    MetadataSource::GameMbid,
+                    false,
+                    &mut conn,
+                    &mut redis_conn,
+                )
                 .await?;
+                cover_colors::queue(song.id, &mut conn).await;
+            }
 
-            Ok(Xml(SongIdResponse {
-                status: "allgood".to_owned(),
-                song_id: song.id,
-            }))
+            Ok(GameXml(version, SongIdResponse::found(song.id)))
         }
     } else {
-        let song = NewSong::new(
+        let new_song = NewSong::new(
             &remove_from_title(&payload.song),
             &payload.artist,
             parsed_modifiers,
+            realm.name(),
+        );
+        let Some(song) = find_or_create_song(
+            &state,
+            &new_song,
+            &payload,
+            steam_player,
+            &mut conn,
+            &mut redis_conn,
         )
-        .find_or_create(&mut conn)
-        .await?;
+        .await?
+        else {
+            return Ok(GameXml(version, SongIdResponse::failed()));
+        };
 
         info!(
             "Song {} - {} looked up by {} (Steam), league {:?}, MBID {:?}, release MBID {:?}",
@@ -133,35 +270,193 @@ pub async fn fetch_song_id(
             payload.release_mbid
         );
 
-        Ok(Xml(SongIdResponse {
-            status: "allgood".to_owned(),
-            song_id: song.id,
-        }))
+        Ok(GameXml(version, SongIdResponse::found(song.id)))
     }
 }
 
-#[derive(Deserialize)]
+/// Finds the built-in song the tags belong to, if they're the tags of a radio song with a reserved ID.
+/// Built-in songs only exist in the main realm, see [`crate::util::reserved_songs`].
+async fn find_reserved_song(
+    payload: &SongIdRequest,
+    realm: &Realm,
+    conn: &mut AsyncPgConnection,
+) -> Result<Option<Song>, RouteError> {
+    if realm.name() != MAIN_REALM {
+        return Ok(None);
+    }
+    // Only read again once the file changed, so changes still apply right away
+    let radio_songs = match cached_radio_songs().await {
+        Ok(Some(radio_songs)) => radio_songs,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            warn!("Failed to get radio songs for reserved song IDs: {e:#}");
+            return Ok(None);
+        }
+    };
+    let Some((reserved_id, radio_song)) =
+        find_reserved_radio_song(&radio_songs, &payload.song, &payload.artist)
+    else {
+        return Ok(None);
+    };
+
+    let song =
+        Song::find_or_create_reserved(reserved_id, &radio_song.title, &radio_song.artist, conn)
+            .await?;
+    if song.is_none() {
+        warn!(
+            "Radio song {} - {} can't have reserved ID {reserved_id}, another song has its tags or it was deleted",
+            radio_song.artist, radio_song.title
+        );
+    }
+    Ok(song)
+}
+
+/// Finds the song, creating it if it doesn't exist and the player is allowed to, see
+/// [`crate::util::song_creation`]. If they aren't, the tags are queued as a pending song instead, unless the player
+/// is unknown or queued too many this hour.
+///
+/// # Returns
+/// `None` if the song doesn't exist and the player can't create it.
+async fn find_or_create_song(
+    state: &AppState,
+    new_song: &NewSong<'_>,
+    payload: &SongIdRequest,
+    steam_player: SteamId,
+    conn: &mut AsyncPgConnection,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> Result<Option<Song>, RouteError> {
+    use crate::schema::scores::dsl::player_id;
+
+    let policy = &state.config.song_creation;
+    if !policy.is_open() {
+        if let Some(song) = new_song.find_cached(conn, redis_conn).await? {
+            return Ok(Some(song));
+        }
+
+        let Some(player) = Player::find_by_steam_id(steam_player)
+            .first::<Player>(conn)
+            .await
+            .optional()?
+        else {
+            // Anyone can send any Steam ID, only players who logged in once get to queue songs
+            info!(
+                "Song {} - {} looked up by unknown player {} (Steam) can't be created, not queued",
+                payload.artist, payload.song, steam_player
+            );
+            return Ok(None);
+        };
+        let scores: i64 = Score::all()
+            .filter(player_id.eq(player.id))
+            .count()
+            .get_result(conn)
+            .await?;
+        if !policy.allows(scores, player.is_staff()) {
+            let key = redis_keys::pending_songs(player.id);
+            let queued = redis_ops::incr_in_window(&key, 60 * 60, redis_conn)
+                .await?
+                .count;
+            if queued > i64::from(policy.pending_songs_per_hour) {
+                info!(
+                    "Song {} - {} looked up by player {} can't be created by them, not queued since they queued too many",
+                    payload.artist, payload.song, player.id
+                );
+                return Ok(None);
+            }
+
+            let pending = NewPendingSong {
+                realm: new_song.realm,
+                title: &payload.song,
+                artist: &payload.artist,
+                mbid: payload.mbid.as_deref(),
+                release_mbid: payload.release_mbid.as_deref(),
+                first_player_id: player.id,
+            }
+            .insert(conn)
+            .await?;
+            info!(
+                "Song {} - {} looked up by player {} can't be created by them, pending song {}",
+                payload.artist, payload.song, player.id, pending.id
+            );
+            return Ok(None);
+        }
+    }
+
+    let (song, created) = new_song.find_or_create_cached(conn, redis_conn).await?;
+    if created {
+        emit_song_created(state, &song);
+        if !policy.is_open() {
+            PendingSong::resolve(new_song.realm, &payload.song, &payload.artist, conn).await?;
+        }
+    }
+    Ok(Some(song))
+}
+
+fn emit_song_created(state: &AppState, song: &Song) {
+    state.events.emit(Event::SongCreated {
+        song_id: song.id,
+        realm: song.realm.clone(),
+    });
+}
+
+/// Records tags refused by [`fetch_song_id`] in the quarantine, so admins can review them.
+async fn quarantine_song(
+    payload: &SongIdRequest,
+    realm: &Realm,
+    steam_player: SteamId,
+    reason: BogusSongReason,
+    conn: &mut AsyncPgConnection,
+) -> Result<(), RouteError> {
+    let player_id = Player::find_by_steam_id(steam_player)
+        .select(crate::schema::players::id)
+        .first::<i32>(conn)
+        .await
+        .optional()?;
+
+    NewQuarantinedSong::new(
+        realm.name(),
+        &payload.song,
+        &payload.artist,
+        reason,
+        player_id,
+    )
+    .insert(conn)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize, Validate)]
 pub struct SendRideRequest {
     ticket: String,
     #[serde(rename = "songid")]
     song_id: i32,
+    #[validate(range(min = 0))]
     score: i32,
     vehicle: Character,
     league: League,
+    #[validate(length(max = 1024))]
     feats: String,
+    /// In centiseconds. Capped at two hours, anything longer isn't a song anymore.
     #[serde(rename = "songlength")]
+    #[validate(range(min = 1, max = 720_000))]
     song_length: i32,
     #[serde(rename = "trackshape")]
+    #[validate(custom(function = "validate_track_shape"))]
     track_shape: String,
+    #[validate(range(min = 0))]
     density: i32,
+    #[validate(custom(function = "validate_xstats"))]
     xstats: String,
     #[serde(rename = "goldthreshold")]
+    #[validate(range(min = 0))]
     gold_threshold: i32,
     iss: i32,
     isj: i32,
     //Wavebreaker-specific
+    #[validate(length(equal = 36))]
     mbid: Option<String>,
     #[serde(rename = "releasembid")]
+    #[validate(length(equal = 36))]
     release_mbid: Option<String>,
 }
 
@@ -191,21 +486,59 @@ struct BeatScore {
     my_score: i32,
     #[serde(rename = "reignseconds")]
     reign_seconds: i64,
+    /// ID of the player with the top score before this one, not sent to the game
+    #[serde(skip)]
+    rival_id: Option<i32>,
 }
 
+impl GameResponse for SendRideResponse {
+    fn into_legacy(mut self) -> Self {
+        self.beat_score.rival_name = ascii_text(&self.beat_score.rival_name);
+        self
+    }
+}
+
+impl SendRideRequest {
+    /// Identifies the submission, so the game retrying it can be told apart from a new one.
+    /// The ticket changes between sessions, so the same score on the same song later on hashes differently.
+    fn submission_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.ticket.as_bytes());
+        hasher.update(self.song_id.to_le_bytes());
+        hasher.update(i16::from(self.league).to_le_bytes());
+        hasher.update(self.score.to_le_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// How long a score submission is remembered (in seconds), so the game's automatic retries of it aren't counted again.
+const RIDE_RETRY_WINDOW: u64 = 600;
+/// Value of a submission's Redis key while it's still being processed.
+const RIDE_PENDING: &str = "pending";
+
 /// Accepts score submissions by the client.
 ///
+/// The game retries submissions on slow connections, so every submission is remembered in Redis for a while.
+/// A retry gets the response of the original submission instead of being counted again.
+///
 /// # Errors
 /// This fails if:
 /// - The response fails to serialize
 /// - Authenticating with Steam fails
 /// - The score fails to be inserted
-#[instrument(skip_all)]
+/// - The same submission is still being processed
+#[instrument(skip_all, fields(steam_id = field::Empty, song_id = field::Empty))]
 pub async fn send_ride(
     State(state): State<AppState>,
+    Extension(realm): Extension<Realm>,
+    Extension(client_version): Extension<ClientVersion>,
+    http_version: Version,
+    headers: HeaderMap,
     Form(payload): Form<SendRideRequest>,
-) -> Result<Xml<SendRideResponse>, RouteError> {
-    use crate::schema::{players::dsl::*, rivalries::dsl::*, scores::dsl::*, songs::dsl::songs};
+) -> Result<GameXml<SendRideResponse>, RouteError> {
+    Span::current().record("song_id", payload.song_id);
+    // Before the Steam auth request, no need to spend one on garbage
+    validate_payload(&payload, "SendRide")?;
 
     let steam_player = ticket_auth(&payload.ticket, &state.steam_api).await?;
 
@@ -215,30 +548,538 @@ pub async fn send_ride(
     );
 
     let mut redis_conn = state.redis.get().await?;
-    let mut conn = state.db.get().await?;
-    let player: Player = Player::find_by_steam_id(steam_player)
-        .first::<Player>(&mut conn)
+    let submission_key = redis_keys::ride_submission(&payload.submission_hash());
+
+    if let Some(previous_response) =
+        check_retry(&submission_key, steam_player, &mut redis_conn).await?
+    {
+        return Ok(GameXml(client_version, previous_response));
+    }
+
+    let settings = &state.config.ride_sources;
+    let source = settings.enabled.then(|| {
+        ClientSource::from_request(http_version, &headers, settings.region_header.as_deref())
+    });
+
+    match save_ride(
+        &state,
+        &realm,
+        &payload,
+        steam_player,
+        source,
+        &mut redis_conn,
+    )
+    .await
+    {
+        Ok(response) => {
+            redis_conn
+                .set_options::<_, _, ()>(
+                    &submission_key,
+                    serde_json::to_string(&response)?,
+                    SetOptions::default()
+                        .conditional_set(ExistenceCheck::XX)
+                        .with_expiration(SetExpiry::KEEPTTL),
+                )
+                .await?;
+            Ok(GameXml(client_version, response))
+        }
+        Err(e) => {
+            // Let the game's retry have another go
+            redis_conn.del::<_, ()>(&submission_key).await?;
+            Err(e)
+        }
+    }
+}
+
+/// Claims a submission's Redis key. If it was already claimed, the submission is a retry.
+///
+/// # Returns
+/// `None` if the submission is new, the response to the original submission if it's a retry.
+///
+/// # Errors
+/// Fails if the original submission is still being processed, or if something is wrong with Redis.
+async fn check_retry(
+    submission_key: &str,
+    steam_player: SteamId,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> Result<Option<SendRideResponse>, RouteError> {
+    let claimed: bool = redis_conn
+        .set_options(
+            submission_key,
+            RIDE_PENDING,
+            SetOptions::default()
+                .conditional_set(ExistenceCheck::NX)
+                .with_expiration(SetExpiry::EX(RIDE_RETRY_WINDOW)),
+        )
         .await?;
+    if claimed {
+        return Ok(None);
+    }
+
+    info!("Submission from {steam_player} (Steam) is a retry");
+    let previous: Option<String> = redis_conn.get(submission_key).await?;
+    match previous.as_deref() {
+        Some(RIDE_PENDING) => Err(RouteError::new_conflict()
+            .set_public_error_message("This score is already being submitted")),
+        Some(previous) => Ok(Some(serde_json::from_str(previous)?)),
+        // Expired right in between, extremely unlikely, but the retry window is over anyway
+        None => Err(RouteError::new_conflict()
+            .set_public_error_message("Please try submitting the score again")),
+    }
+}
 
-    let song = songs
+/// Does the actual work of [`send_ride`]: saves the score and figures out who got dethroned.
+/// Everything the response doesn't depend on is queued, see [`super::ride_queue`].
+async fn save_ride(
+    state: &AppState,
+    realm: &Realm,
+    payload: &SendRideRequest,
+    steam_player: SteamId,
+    source: Option<ClientSource>,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> Result<SendRideResponse, RouteError> {
+    let mut conn = state.db.get().await?;
+    let player = Player::find_by_steam_id_cached(steam_player, &mut conn, redis_conn).await?;
+
+    // Songs from other realms are treated like they don't exist
+    let song = Song::all()
         .find(payload.song_id)
+        .filter(crate::schema::songs::realm.eq(realm.name()))
         .first::<Song>(&mut conn)
         .await
         .http_error("Song not found", StatusCode::NOT_FOUND)?;
+    // The ride has to count against the old scores, so they're out of the archive for good
+    if song.scores_archived_at.is_some() {
+        Score::restore_archived(song.id, &mut conn, redis_conn).await?;
+    }
+
+    let beat_score = get_beat_score(&player, steam_player, payload, &mut conn).await?;
+
+    let xstats = parse_separated_i32(&payload.xstats, b',', MAX_XSTATS_ENTRIES)
+        .http_status_error(StatusCode::BAD_REQUEST)?;
+    let new_score = NewScore::new(
+        player.id,
+        song.id,
+        &song.realm,
+        payload.league,
+        payload.score,
+        &parse_separated_i32(&payload.track_shape, b'x', MAX_TRACK_SHAPE_ENTRIES)
+            .http_status_error(StatusCode::BAD_REQUEST)?,
+        &xstats,
+        payload.density,
+        payload.vehicle,
+        &payload.feats.split(", ").collect::<Vec<&str>>(),
+        payload.song_length,
+        payload.gold_threshold,
+        payload.iss,
+        payload.isj,
+    )
+    .create_or_update(&mut conn, redis_conn)
+    .await?;
+
+    if song.first_rider_id.is_none() {
+        song.credit_first_rider(player.id, &mut conn).await?;
+    }
+    emit_ride_events(state, &song, payload, &beat_score);
+    // The connection isn't needed anymore, so it's back in the pool if the queue makes us wait
+    drop(conn);
+
+    let song_id = new_score.song_id;
+    let saved = SavedRide {
+        steam_player,
+        league: payload.league,
+        score: payload.score,
+        vehicle: payload.vehicle,
+        gold_threshold: payload.gold_threshold,
+        density: payload.density,
+        xstats,
+        song_length: payload.song_length,
+        // Without anyone else on the leaderboard, the player's score is always on top
+        on_top: beat_score.dethroned || beat_score.rival_id.is_none(),
+        dethroned: beat_score
+            .rival_id
+            .filter(|_| beat_score.dethroned)
+            .map(|rival_id| {
+                (
+                    beat_score.rival_name.clone(),
+                    rival_id,
+                    beat_score.reign_seconds,
+                )
+            }),
+        ridden_at: OffsetDateTime::now_utc(),
+        source,
+        player,
+        song,
+        new_score,
+    };
+    state.rides.push(state, saved).await;
+
+    Ok(SendRideResponse {
+        status: "allgood".to_owned(),
+        song_id,
+        beat_score,
+    })
+}
+
+/// What the work after saving a ride needs to know about it, see [`super::ride_queue`].
+pub(super) struct SavedRide {
+    player: Player,
+    song: Song,
+    steam_player: SteamId,
+    /// The player's score on the song after the ride, which is only this ride's if it beat the old one
+    new_score: Score,
+    league: League,
+    score: i32,
+    vehicle: Character,
+    gold_threshold: i32,
+    density: i32,
+    xstats: Vec<i32>,
+    /// In centiseconds
+    song_length: i32,
+    /// Whether the ride is on top of the song's leaderboard now
+    on_top: bool,
+    /// Name, ID and reign (in seconds) of the player who lost the top spot to the ride
+    dethroned: Option<(String, i32, i64)>,
+    ridden_at: OffsetDateTime,
+    /// `None` if `ride_sources.enabled` is off
+    source: Option<ClientSource>,
+}
+
+impl SavedRide {
+    /// Does the work the game doesn't wait for. None of it is worth failing the submission over,
+    /// so anything going wrong in there is only logged.
+    ///
+    /// # Errors
+    /// Fails if there's no connection to the DB or Redis to be had.
+    pub(super) async fn process(self, state: &AppState) -> anyhow::Result<()> {
+        let mut conn = state.db.get().await?;
+        let mut redis_conn = state.redis.get().await?;
+
+        track_gold_threshold(&self, &mut conn).await;
+        if state.config.traffic_checks.enabled {
+            track_traffic(state, &self, &mut conn).await;
+        }
+        record_vehicle_usage(&self, &mut conn).await;
+        record_source(&self, &mut conn).await;
+        if self.player.share_activity {
+            record_activity(&self, &mut redis_conn).await;
+        }
+        check_records(state, &self, &mut conn, &mut redis_conn).await;
+        publish_leaderboard_update(&self, &mut conn, &mut redis_conn).await;
+        if let Some((_, rival_id, _)) = &self.dethroned {
+            notify_dethroned(state, &self, *rival_id, &mut conn).await;
+        }
+
+        // Add MusicBrainz metadata in the background, if no extra metadata exists already
+        // we're doing this here because we need the song length to search for the recording
+        if let Err(e) = queue_metadata_lookup(&self.song, self.song_length * 10, &mut conn).await {
+            error!(
+                "Failed to queue metadata lookup for song {}: {}",
+                self.song.id, e
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Shows the ride in the player's recent activity. Failing to do so isn't worth failing the submission over.
+async fn record_activity(ride: &SavedRide, redis_conn: &mut deadpool_redis::Connection) {
+    let recent = RecentRide {
+        player_id: ride.player.id,
+        username: ride.player.username.clone(),
+        realm: ride.song.realm.clone(),
+        song_id: ride.song.id,
+        title: ride.song.title.clone(),
+        artist: ride.song.artist.clone(),
+        league: ride.league,
+        score: ride.score,
+        ridden_at: ride.ridden_at,
+    };
+    if let Err(e) = activity::record_ride(&recent, redis_conn).await {
+        error!(
+            "Failed to record recent ride of player {}: {}",
+            ride.player.id, e
+        );
+    }
+}
+
+/// Checks whether the ride broke any server records. Failing to do so isn't worth failing the submission over.
+async fn check_records(
+    state: &AppState,
+    ride: &SavedRide,
+    conn: &mut diesel_async::AsyncPgConnection,
+    redis_conn: &mut deadpool_redis::Connection,
+) {
+    let submission = records::Submission {
+        player: &ride.player,
+        song: &ride.song,
+        score: &ride.new_score,
+        on_top: ride.on_top,
+        dethroned: ride
+            .dethroned
+            .as_ref()
+            .map(|(name, rival_id, reign_seconds)| (name.as_str(), *rival_id, *reign_seconds)),
+    };
+    if let Err(e) = records::check_submission(state, &submission, conn, redis_conn).await {
+        error!("Failed to check records for song {}: {e:?}", ride.song.id);
+    }
+}
+
+/// Tells the streams of the song's leaderboard about the ride, if it changed the leaderboard.
+/// Failing to do so isn't worth failing the submission over.
+async fn publish_leaderboard_update(
+    ride: &SavedRide,
+    conn: &mut AsyncPgConnection,
+    redis_conn: &mut deadpool_redis::Connection,
+) {
+    // Rides that didn't beat the player's old score left the leaderboard as it was
+    if ride.new_score.score != ride.score {
+        return;
+    }
+
+    let result = async {
+        let (better, _) = rank_cache::standing(
+            ride.song.id,
+            ride.league,
+            ride.new_score.score,
+            conn,
+            redis_conn,
+        )
+        .await?;
+        let update = LeaderboardUpdate {
+            song_id: ride.song.id,
+            league: ride.league,
+            score_id: ride.new_score.id,
+            player_id: ride.player.id,
+            username: ride.player.username.clone(),
+            score: ride.new_score.score,
+            rank: better + 1,
+            dethroned: ride.dethroned.as_ref().map(|(_, rival_id, _)| *rival_id),
+            ridden_at: ride.ridden_at,
+        };
+        live_leaderboards::publish(&update, redis_conn).await
+    }
+    .await;
+    if let Err(e) = result {
+        error!(
+            "Failed to publish leaderboard update for song {}: {e:?}",
+            ride.song.id
+        );
+    }
+}
+
+/// Lets the player who lost the top spot know, if they linked anywhere to be notified on.
+/// Failing to do so isn't worth failing the submission over.
+async fn notify_dethroned(
+    state: &AppState,
+    ride: &SavedRide,
+    rival_id: i32,
+    conn: &mut AsyncPgConnection,
+) {
+    let i18n = &state.config.i18n;
+    let result = async {
+        let locale = Player::locale_of(rival_id, i18n, conn).await?;
+        let league = format!("{:?}", ride.league);
+        let text = i18n.text(
+            &locale,
+            Text::NotifyDethroned,
+            &[
+                ("player", &ride.player.username),
+                ("title", &ride.song.title),
+                ("artist", &ride.song.artist),
+                ("league", &league),
+                ("score", &ride.score),
+            ],
+        );
+        notify::queue(rival_id, NotificationKind::Dethrone, &text, conn).await
+    }
+    .await;
+    if let Err(e) = result {
+        error!(
+            "Failed to notify player {rival_id} of being dethroned on song {}: {e:?}",
+            ride.song.id
+        );
+    }
+}
+
+fn emit_ride_events(
+    state: &AppState,
+    song: &Song,
+    payload: &SendRideRequest,
+    beat_score: &BeatScore,
+) {
+    state.events.emit(Event::ScoreSubmitted {
+        song_id: song.id,
+        realm: song.realm.clone(),
+        league: payload.league,
+        vehicle: payload.vehicle,
+        score: payload.score,
+    });
+    if beat_score.dethroned {
+        state.events.emit(Event::Dethrone {
+            song_id: song.id,
+            realm: song.realm.clone(),
+            league: payload.league,
+        });
+    }
+}
+
+/// Remembers which client the ride came from. Failing to do so isn't worth failing the submission over.
+async fn record_source(ride: &SavedRide, conn: &mut AsyncPgConnection) {
+    let Some(source) = &ride.source else {
+        return;
+    };
+    if let Err(e) = RideSource::record(ride.new_score.id, source, conn).await {
+        error!(
+            "Failed to record the source of a ride of player {}: {}",
+            ride.player.id, e
+        );
+    }
+}
+
+/// Counts the ride towards the player's character stats. Failing to do so isn't worth failing the submission over.
+async fn record_vehicle_usage(ride: &SavedRide, conn: &mut AsyncPgConnection) {
+    if let Err(e) = VehicleUsage::record(ride.player.id, ride.vehicle, ride.score, conn).await {
+        error!(
+            "Failed to record vehicle usage of player {}: {}",
+            ride.player.id, e
+        );
+    }
+}
+
+/// Checks the reported gold threshold against what others reported for the song, then counts it.
+/// Failing to do either isn't worth failing the submission over.
+async fn track_gold_threshold(ride: &SavedRide, conn: &mut AsyncPgConnection) {
+    if let Err(e) = check_gold_threshold(ride, conn).await {
+        error!(
+            "Failed to check gold threshold for song {}: {}",
+            ride.song.id, e
+        );
+    }
+
+    if let Err(e) = GoldThreshold::record(
+        ride.song.id,
+        ride.league,
+        ride.vehicle,
+        ride.gold_threshold,
+        conn,
+    )
+    .await
+    {
+        error!(
+            "Failed to record gold threshold for song {}: {}",
+            ride.song.id, e
+        );
+    }
+}
+
+/// Logs gold thresholds that are far off from the consensus, the score is saved anyway for now.
+async fn check_gold_threshold(ride: &SavedRide, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+    let Some(consensus) =
+        GoldThreshold::consensus(ride.song.id, ride.league, ride.vehicle, conn).await?
+    else {
+        return Ok(());
+    };
+
+    if !consensus.is_plausible(ride.gold_threshold) {
+        warn!(
+            "Gold threshold {} reported by {} (Steam) for song {} ({:?}, {:?}) is far off from the usual {}",
+            ride.gold_threshold,
+            ride.steam_player,
+            ride.song.id,
+            ride.league,
+            ride.vehicle,
+            consensus.gold_threshold
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks the ride's traffic against the song's length and what others reported for it, then counts its density.
+/// Failing to do either isn't worth failing the submission over.
+async fn track_traffic(state: &AppState, ride: &SavedRide, conn: &mut AsyncPgConnection) {
+    if let Err(e) = check_traffic(state, ride, conn).await {
+        error!("Failed to check traffic for song {}: {}", ride.song.id, e);
+    }
+
+    if let Err(e) =
+        TrafficDensity::record(ride.song.id, ride.league, ride.vehicle, ride.density, conn).await
+    {
+        error!(
+            "Failed to record traffic density for song {}: {}",
+            ride.song.id, e
+        );
+    }
+}
+
+/// Flags rides whose traffic doesn't add up for moderators, the score is saved anyway.
+async fn check_traffic(
+    state: &AppState,
+    ride: &SavedRide,
+    conn: &mut AsyncPgConnection,
+) -> QueryResult<()> {
+    let consensus =
+        TrafficDensity::consensus(ride.song.id, ride.league, ride.vehicle, conn).await?;
+    let Some(problem) = state.config.traffic_checks.check(
+        ride.density,
+        &ride.xstats,
+        ride.song_length,
+        consensus.as_ref(),
+    ) else {
+        return Ok(());
+    };
+
+    let flagged = FlaggedRide {
+        player_id: ride.player.id,
+        song_id: ride.song.id,
+        league: ride.league,
+        vehicle: ride.vehicle,
+        density: ride.density,
+        xstats: &ride.xstats,
+        song_length: ride.song_length,
+        expected_density: consensus
+            .filter(TrafficDensity::is_trusted)
+            .map(|consensus| consensus.density),
+    };
+    let flag = TrafficFlag::flag(&flagged, problem, conn).await?;
+    warn!(
+        "Traffic of a ride by {} (Steam) on song {} ({:?}, {:?}) doesn't add up ({}), flagged as {}",
+        ride.steam_player,
+        ride.song.id,
+        ride.league,
+        ride.vehicle,
+        problem.as_str(),
+        flag.id
+    );
+
+    Ok(())
+}
+
+/// Checks if the submission beats another player's top score on the song.
+/// This is the part of [`send_ride`]'s response that's for dethroning.
+async fn get_beat_score(
+    player: &Player,
+    steam_player: SteamId,
+    payload: &SendRideRequest,
+    conn: &mut diesel_async::AsyncPgConnection,
+) -> Result<BeatScore, RouteError> {
+    use crate::schema::{players::dsl::*, rivalries::dsl::*, scores::dsl::*};
 
     // Check the song for a top score by another player
-    let current_top: Option<(Score, Player)> = scores
+    let current_top: Option<(Score, Player)> = Score::all()
         .inner_join(players::table())
         .filter(song_id.eq(payload.song_id))
         .filter(league.eq(payload.league))
         .filter(player_id.ne(player.id))
         .order(score.desc())
-        .first::<(Score, Player)>(&mut conn)
+        .first::<(Score, Player)>(conn)
         .await
         .optional()?;
 
     // construct part of the response that's for dethroning
-    let beat_score = if let Some(current_top) = current_top {
+    if let Some(current_top) = current_top {
         // Check if the player dethroned the current top score
         if current_top.0.score < payload.score {
             info!(
@@ -248,80 +1089,73 @@ pub async fn send_ride(
         }
 
         // Calculate how long the current top score has been at the top before being mercilessly dethroned (part of the Brutus achievement condition!)
-        let reign_duration = OffsetDateTime::now_utc() - current_top.0.submitted_at;
+        let reign_duration = clock::now() - current_top.0.submitted_at;
 
         // Check if the player has a rivalry with the top score holder (part of the Brutus achievement condition!)
         let rivalry = rivalries
             .find((player.id, current_top.1.id))
-            .first::<Rivalry>(&mut conn)
+            .first::<Rivalry>(conn)
             .await;
         // If rivalry exists, check if rivalry is mutual (we consider mutual rivalries to be friends)
         let mutual = if let Ok(rivalry) = rivalry {
-            rivalry.is_mutual(&mut conn).await
+            rivalry.is_mutual(conn).await
         } else {
             false
         };
 
-        BeatScore {
+        Ok(BeatScore {
             dethroned: current_top.0.score < payload.score,
             friend: mutual,
             rival_name: current_top.1.username,
             rival_score: current_top.0.score,
             my_score: payload.score,
             reign_seconds: reign_duration.whole_seconds(),
-        }
+            rival_id: Some(current_top.1.id),
+        })
     } else {
         info!(
             "Player {} (Steam) got a new top score of {}",
             steam_player, payload.score
         );
-        BeatScore {
+        Ok(BeatScore {
             dethroned: false,
             friend: false,
             rival_name: "No one".to_owned(),
             rival_score: 143,
             my_score: 0,
             reign_seconds: 0,
-        }
-    };
-
-    let new_score = NewScore::new(
-        player.id,
-        song.id,
-        payload.league,
-        payload.score,
-        &split_x_separated::<i32>(&payload.track_shape)?,
-        &payload
-            .xstats
-            .split(',')
-            .map(str::parse)
-            .collect::<Result<Vec<_>, _>>()?,
-        payload.density,
-        payload.vehicle,
-        &payload.feats.split(", ").collect::<Vec<&str>>(),
-        payload.song_length,
-        payload.gold_threshold,
-        payload.iss,
-        payload.isj,
-    )
-    .create_or_update(&mut conn, &mut redis_conn)
-    .await?;
+            rival_id: None,
+        })
+    }
+}
 
-    // Add MusicBrainz metadata, if no extra metadata exists already
-    // we're doing this here because we need the song length to search for the recording
-    if let Err(e) = song
-        .auto_add_metadata(payload.song_length * 10, &mut conn)
+#[allow(clippy::doc_markdown)]
+/// Queues a background MusicBrainz lookup for the song, unless it already has extra metadata
+/// or a lookup is already waiting in the queue.
+async fn queue_metadata_lookup(
+    song: &Song,
+    duration: i32,
+    conn: &mut diesel_async::AsyncPgConnection,
+) -> anyhow::Result<()> {
+    let has_extra_info = ExtraSongInfo::belonging_to(song)
+        .select(ExtraSongInfo::as_select())
+        .first::<ExtraSongInfo>(conn)
         .await
-    {
-        error!("Failed to add metadata for song {}: {}", song.id, e);
+        .optional()?
+        .is_some();
+    if has_extra_info {
+        return Ok(());
     }
 
-    // TODO: Implement dethrone notifications
-    Ok(Xml(SendRideResponse {
-        status: "allgood".to_owned(),
-        song_id: new_score.song_id,
-        beat_score,
-    }))
+    let job = Job::AddMetadata {
+        song_id: song.id,
+        duration,
+    };
+    if !job.is_queued(conn).await? {
+        job.enqueue(conn).await?;
+    }
+
+    Ok(())
 }
 
 #[derive(Deserialize)]
@@ -329,6 +1163,16 @@ pub struct GetRidesRequest {
     #[serde(rename = "songid")]
     song_id: i32,
     ticket: String,
+    //Wavebreaker-specific
+    /// Which page of the leaderboards to get, for "load more" in the client.
+    #[serde(default)]
+    page: i64,
+    /// Only show mutual rivals (friends) on the rival leaderboard, instead of everyone the player added.
+    #[serde(default, rename = "mutualrivals")]
+    mutual_rivals: bool,
+    /// Also send the leaderboard across all leagues, see [`Leaderboard::Combined`].
+    #[serde(default)]
+    combined: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -337,8 +1181,9 @@ pub struct GetRidesResponse {
     #[serde(rename = "@status")]
     status: String,
     scores: Vec<ResponseScore>,
+    /// Unix seconds, the game works out the age of the rides with it. See [`clock`].
     #[serde(rename = "servertime")]
-    server_time: u64,
+    server_time: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -352,6 +1197,8 @@ struct ResponseScore {
 struct LeagueRides {
     #[serde(rename = "@leagueid")]
     league_id: League,
+    /// Empty leagues have no `ride` elements at all
+    #[serde(default)]
     ride: Vec<Ride>,
 }
 
@@ -361,6 +1208,7 @@ struct Ride {
     score: i32,
     #[serde(rename = "vehicleid")]
     vehicle_id: Character,
+    /// Unix seconds, see [`clock::ride_time`]
     #[serde(rename = "ridetime")]
     time: i64,
     feats: String,
@@ -369,9 +1217,32 @@ struct Ride {
     song_length: i32,
     #[serde(rename = "trafficcount")]
     traffic_count: i32,
+    /// The league the ride is in, only sent on [`Leaderboard::Combined`] where leagues are mixed
+    #[serde(default, rename = "leagueid", skip_serializing_if = "Option::is_none")]
+    league_id: Option<League>,
+}
+
+impl GameResponse for GetRidesResponse {
+    fn into_legacy(mut self) -> Self {
+        let rides = self
+            .scores
+            .iter_mut()
+            .flat_map(|score| &mut score.league)
+            .flat_map(|league| &mut league.ride);
+        for ride in rides {
+            ride.username = ascii_text(&ride.username);
+            ride.feats = ascii_text(&ride.feats);
+        }
+        self
+    }
 }
 
-fn create_league_rides(league: League, scores: Vec<ScoreWithPlayer>) -> LeagueRides {
+/// `server_time` is the one sent along in the response, see [`clock::ride_time`].
+fn create_league_rides(
+    league: League,
+    scores: Vec<ScoreWithPlayer>,
+    server_time: i64,
+) -> LeagueRides {
     let mut league_rides = LeagueRides {
         league_id: league,
         ride: vec![],
@@ -382,7 +1253,7 @@ fn create_league_rides(league: League, scores: Vec<ScoreWithPlayer>) -> LeagueRi
             username: with_player.player.username,
             score: with_player.score.score,
             vehicle_id: with_player.score.vehicle,
-            time: with_player.score.submitted_at.unix_timestamp(),
+            time: clock::ride_time(with_player.score.submitted_at, server_time),
             feats: with_player
                 .score
                 .feats
@@ -392,83 +1263,148 @@ fn create_league_rides(league: League, scores: Vec<ScoreWithPlayer>) -> LeagueRi
                 .join(", "),
             song_length: with_player.score.song_length,
             traffic_count: with_player.score.id,
+            league_id: None,
         });
     }
 
     league_rides
 }
 
+/// The game shows leaderboards per league, so the combined one is sent as the same rides in every league,
+/// each with the league it's really from.
+fn create_combined_rides(scores: Vec<ScoreWithPlayer>, server_time: i64) -> Vec<LeagueRides> {
+    let rides: Vec<Ride> = scores
+        .into_iter()
+        .map(|with_player| {
+            let league = with_player.score.league;
+            let mut rides = create_league_rides(league, vec![with_player], server_time).ride;
+            let mut ride = rides.remove(0);
+            ride.league_id = Some(league);
+            ride
+        })
+        .collect();
+
+    [League::Casual, League::Pro, League::Elite]
+        .into_iter()
+        .map(|league| LeagueRides {
+            league_id: league,
+            ride: rides.clone(),
+        })
+        .collect()
+}
+
 /// Returns scores for a given song.
 ///
 /// # Errors
 /// This fails if:
 /// - The response fails to serialize
 /// - Authenticating with Steam fails
-#[instrument(skip_all)]
+#[instrument(skip_all, fields(steam_id = field::Empty, song_id = field::Empty))]
 pub async fn get_rides(
     State(state): State<AppState>,
+    Extension(realm): Extension<Realm>,
+    Extension(version): Extension<ClientVersion>,
     Form(payload): Form<GetRidesRequest>,
-) -> Result<Xml<GetRidesResponse>, RouteError> {
+) -> Result<GameXml<GetRidesResponse>, RouteError> {
     const ALL_LEAGUES: [League; 3] = [League::Casual, League::Pro, League::Elite];
+    Span::current().record("song_id", payload.song_id);
 
     let steam_player = ticket_auth(&payload.ticket, &state.steam_api).await?;
+    // Out of range pages are clamped instead of rejected, the client won't understand an error here
+    let page = payload.page.clamp(0, GAME_MAX_PAGE);
     info!(
-        "Player {} (Steam) requesting rides of song {}",
-        steam_player, payload.song_id
+        "Player {} (Steam) requesting rides of song {}, page {}",
+        steam_player, payload.song_id, page
     );
 
-    let mut conn = state.db.get().await?;
+    // Only reads from here on, so the replica can take them
+    let mut conn = state.db_read.get().await?;
+    let mut redis_conn = state.redis.get().await?;
 
-    let player: Player = Player::find_by_steam_id(steam_player)
-        .first::<Player>(&mut conn)
-        .await?;
+    let player = Player::find_by_steam_id_cached(steam_player, &mut conn, &mut redis_conn).await?;
+    // Songs from other realms are treated like they don't exist, so their leaderboards are empty
+    let in_realm: bool = diesel::select(diesel::dsl::exists(
+        Song::all()
+            .find(payload.song_id)
+            .filter(crate::schema::songs::realm.eq(realm.name())),
+    ))
+    .get_result(&mut conn)
+    .await?;
 
-    let mut rival_ids: Vec<i32> = player
-        .get_rivals(&mut conn)
+    let mut rival_ids: Vec<i32> = Rivalry::all_for_player(player.id, &mut conn)
         .await?
-        .iter()
-        .map(|r| r.id)
+        .into_iter()
+        .filter(|view| view.mutual || !payload.mutual_rivals)
+        .map(|view| view.rival.id)
         .collect();
     rival_ids.push(player.id); // So the player can see themself in rival scores
 
+    // Read once, so every ride's age is relative to the same time
+    let server_time = clock::unix_time();
+
     let mut global_rides: Vec<LeagueRides> = vec![];
     let mut rival_rides: Vec<LeagueRides> = vec![];
     let mut nearby_rides: Vec<LeagueRides> = vec![];
 
     for league in ALL_LEAGUES {
         // can't borrow that one connection as mutable multiple times, so we need more
-        let mut conn1 = state.db.get().await?;
-        let mut conn2 = state.db.get().await?;
+        let mut conn1 = state.db_read.get().await?;
+        let mut conn2 = state.db_read.get().await?;
 
-        let global_future = Score::game_get_global(payload.song_id, league, &mut conn);
-        let rival_future = Score::game_get_rivals(payload.song_id, league, &rival_ids, &mut conn1);
-        let nearby_future =
-            Score::game_get_nearby(payload.song_id, league, player.location_id, &mut conn2);
+        let global_future = Score::game_get_global(payload.song_id, league, page, &mut conn);
+        let rival_future =
+            Score::game_get_rivals(payload.song_id, league, &rival_ids, page, &mut conn1);
+        let nearby_future = Score::game_get_nearby(
+            payload.song_id,
+            league,
+            player.location_id,
+            page,
+            &mut conn2,
+        );
 
-        let (global_scores, rival_scores, nearby_scores) =
-            try_join!(global_future, rival_future, nearby_future)?;
+        let (global_scores, rival_scores, nearby_scores) = if in_realm {
+            try_join!(global_future, rival_future, nearby_future)?
+        } else {
+            Default::default()
+        };
 
-        global_rides.push(create_league_rides(league, global_scores));
-        rival_rides.push(create_league_rides(league, rival_scores));
-        nearby_rides.push(create_league_rides(league, nearby_scores));
+        global_rides.push(create_league_rides(league, global_scores, server_time));
+        rival_rides.push(create_league_rides(league, rival_scores, server_time));
+        nearby_rides.push(create_league_rides(league, nearby_scores, server_time));
     }
 
-    Ok(Xml(GetRidesResponse {
-        status: "allgood".to_owned(),
-        scores: vec![
-            ResponseScore {
-                score_type: Leaderboard::Global,
-                league: global_rides,
-            },
-            ResponseScore {
-                score_type: Leaderboard::Friend,
-                league: rival_rides,
-            },
-            ResponseScore {
-                score_type: Leaderboard::Nearby,
-                league: nearby_rides,
-            },
-        ],
-        server_time: 143,
-    }))
+    let mut scores = vec![
+        ResponseScore {
+            score_type: Leaderboard::Global,
+            league: global_rides,
+        },
+        ResponseScore {
+            score_type: Leaderboard::Friend,
+            league: rival_rides,
+        },
+        ResponseScore {
+            score_type: Leaderboard::Nearby,
+            league: nearby_rides,
+        },
+    ];
+    if payload.combined {
+        let combined_scores = if in_realm {
+            Score::game_get_combined(payload.song_id, page, &mut conn).await?
+        } else {
+            vec![]
+        };
+        scores.push(ResponseScore {
+            score_type: Leaderboard::Combined,
+            league: create_combined_rides(combined_scores, server_time),
+        });
+    }
+
+    Ok(GameXml(
+        version,
+        GetRidesResponse {
+            status: "allgood".to_owned(),
+            scores,
+            server_time,
+        },
+    ))
 }
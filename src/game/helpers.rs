@@ -1,7 +1,18 @@
 use anyhow::{Context, Error};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use axum_serde::Xml;
+use serde::Serialize;
 use steam_rs::{steam_id::SteamId, Steam};
+use tracing::{warn, Span};
+use validator::Validate;
+
+use crate::util::errors::RouteError;
 
 /// Validates Steam game auth tickets. Returns a `SteamId` struct representing for user who the ticket belongs to.
+/// The Steam ID is recorded in the current span's `steam_id` field, if it has one.
 ///
 /// # Errors
 /// This function will return an error if it fails to authenticate with Steam.
@@ -10,5 +21,47 @@ pub async fn ticket_auth(ticket: &str, steam: &Steam) -> Result<SteamId, Error>
         .authenticate_user_ticket(12900, ticket)
         .await
         .context("Failed to authenticate with Steam")?;
-    Ok(SteamId::from(steam_result.steam_id))
+    let steam_id = SteamId::from(steam_result.steam_id);
+    Span::current().record("steam_id", steam_id.into_u64());
+    Ok(steam_id)
+}
+
+/// Validates a request payload from the game.
+/// Rejections are logged with the reason, since the game won't tell anyone why its request failed.
+///
+/// # Errors
+/// Returns a bad request error if the payload is invalid.
+pub fn validate_payload<T: Validate>(payload: &T, endpoint: &str) -> Result<(), RouteError> {
+    payload.validate().map_err(|e| {
+        warn!("Rejected {endpoint} request: {e}");
+        RouteError::new_bad_request()
+            .set_public_error_message("Invalid request")
+            .set_error(e.into())
+    })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename = "RESULT")]
+struct GameErrorResponse {
+    #[serde(rename = "@status")]
+    status: String,
+}
+
+/// Turns the plain text response axum sends when a request body is over the size limit
+/// into a response the game understands.
+///
+/// The limit is enforced while the body is read, so an oversized body is never buffered completely.
+pub async fn payload_too_large_to_xml(response: Response) -> Response {
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE {
+        return response;
+    }
+
+    warn!("Rejected game request, body is over the size limit");
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Xml(GameErrorResponse {
+            status: "failed".to_owned(),
+        }),
+    )
+        .into_response()
 }
@@ -1,19 +1,29 @@
-use axum::{extract::State, Form};
+use axum::{extract::State, http::HeaderMap, Extension, Form};
 use axum_extra::extract::Form as ExtraForm;
-use axum_serde::Xml;
-use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use serde::{Deserialize, Serialize};
-use tracing::instrument;
+use tracing::{field, info, instrument, Span};
+use validator::Validate;
 
-use super::helpers::ticket_auth;
+use super::{
+    helpers::{ticket_auth, validate_payload},
+    protocol::{ascii_text, ClientVersion, GameResponse, GameText, GameXml},
+};
 use crate::{
     models::{
+        player_messages::PlayerMessage,
         players::Player,
         scores::Score,
         shouts::{NewShout, Shout},
     },
-    util::{errors::RouteError, game_types::join_x_separated},
+    util::{
+        errors::RouteError,
+        game_types::join_x_separated,
+        i18n::{accept_language, Localization, Text},
+        news, redis_keys, redis_ops,
+        text_filter::{FilterReason, TextFilterRules},
+    },
     AppState,
 };
 
@@ -29,29 +39,56 @@ pub struct CustomNewsResponse {
     text: String,
 }
 
-/// Sends text to the game, shown before playing a song
+impl GameResponse for CustomNewsResponse {
+    fn into_legacy(mut self) -> Self {
+        self.text = ascii_text(&self.text);
+        self
+    }
+}
+
+/// Sends text to the game, shown before playing a song.
+/// Includes messages for the player and the current news items, see [`news`].
 ///
 /// # Errors
-/// This fails if the response fails to serialize
-#[instrument(skip_all)]
+/// This fails if the response fails to serialize, or something goes wrong with the database or Redis
+#[instrument(skip_all, fields(steam_id = field::Empty))]
 pub async fn get_custom_news(
     State(state): State<AppState>,
+    Extension(version): Extension<ClientVersion>,
+    headers: HeaderMap,
     Form(payload): Form<CustomNewsRequest>,
-) -> Result<Xml<CustomNewsResponse>, RouteError> {
+) -> Result<GameXml<CustomNewsResponse>, RouteError> {
     let steam_player = ticket_auth(&payload.ticket, &state.steam_api).await?;
 
     let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
 
     let player: Player = Player::find_by_steam_id(steam_player)
         .first::<Player>(&mut conn)
         .await?;
 
-    Ok(Xml(CustomNewsResponse {
-        text: format!(
-            "Hi, {}!\n\nWelcome to wavebreaker-rs,\nthe next generation of Wavebreaker!",
-            player.username
-        ),
-    }))
+    let messages: Vec<String> = PlayerMessage::take_pending(player.id, &mut conn)
+        .await?
+        .into_iter()
+        .map(|message| message.text)
+        .collect();
+    if !messages.is_empty() {
+        info!(
+            "Delivered {} message(s) to player {}",
+            messages.len(),
+            player.id
+        );
+    }
+    let i18n = &state.config.i18n;
+    let locale = i18n.negotiate(player.locale.as_deref(), accept_language(&headers));
+    let items = news::current_items(i18n, &locale, &mut conn, &mut redis_conn).await?;
+
+    Ok(GameXml(
+        version,
+        CustomNewsResponse {
+            text: news::compose(i18n, &locale, &player.username, &messages, &items),
+        },
+    ))
 }
 
 #[derive(Deserialize)]
@@ -71,11 +108,12 @@ pub async fn fetch_track_shape(
     State(state): State<AppState>,
     Form(payload): Form<GetShoutsRequest>,
 ) -> Result<String, RouteError> {
-    use crate::schema::scores::dsl::*;
-
     let mut conn = state.db.get().await?;
 
-    let ride = scores.find(payload.ridd).first::<Score>(&mut conn).await?;
+    let ride = Score::all()
+        .find(payload.ridd)
+        .first::<Score>(&mut conn)
+        .await?;
     let track_shape_string =
         join_x_separated(&ride.track_shape.into_iter().flatten().collect::<Vec<i32>>());
 
@@ -94,6 +132,8 @@ where
 }
 
 async fn shouts_to_string(
+    i18n: &Localization,
+    locale: &str,
     conn: &mut AsyncPgConnection,
     target_song_id: i32,
 ) -> diesel::QueryResult<String> {
@@ -106,10 +146,7 @@ async fn shouts_to_string(
         .load::<(Shout, Player)>(conn)
         .await?;
     if shouts_with_player.is_empty() {
-        return Ok(
-            "This song has no shouts yet. Let's change that!\n'Cause we're gonna shout it loud!"
-                .to_owned(),
-        );
+        return Ok(i18n.text(locale, Text::NoShouts, &[]));
     }
 
     let mut shout_string = String::new();
@@ -133,21 +170,32 @@ pub struct FetchShoutsRequest {
 ///
 /// # Errors
 /// This fails if the response can't serialize or something is wrong with the database
-#[instrument(skip_all)]
+#[instrument(skip_all, fields(song_id = field::Empty))]
 pub async fn fetch_shouts(
     State(state): State<AppState>,
+    Extension(version): Extension<ClientVersion>,
+    headers: HeaderMap,
     ExtraForm(payload): ExtraForm<FetchShoutsRequest>,
-) -> Result<String, RouteError> {
+) -> Result<GameText, RouteError> {
+    Span::current().record("song_id", payload.song_id);
     let mut conn = state.db.get().await?;
 
-    Ok(shouts_to_string(&mut conn, payload.song_id).await?)
+    // Sent without a ticket, so the player isn't known
+    let i18n = &state.config.i18n;
+    let locale = i18n.negotiate(None, accept_language(&headers));
+    Ok(GameText(
+        version,
+        shouts_to_string(i18n, &locale, &mut conn, payload.song_id).await?,
+    ))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct SendShoutRequest {
     ticket: String,
     #[serde(rename = "songid")]
     song_id: i32,
+    /// Same limit as the `shouts.content` column
+    #[validate(length(min = 1, max = 240))]
     shout: String,
 }
 
@@ -157,21 +205,116 @@ pub struct SendShoutRequest {
 /// This fails if:
 /// - The response fails to serialize
 /// - Something is wrong with the database
-#[instrument(skip_all)]
+#[instrument(skip_all, fields(steam_id = field::Empty, song_id = field::Empty))]
 pub async fn send_shout(
     State(state): State<AppState>,
+    Extension(version): Extension<ClientVersion>,
+    headers: HeaderMap,
     Form(payload): Form<SendShoutRequest>,
-) -> Result<String, RouteError> {
+) -> Result<GameText, RouteError> {
+    Span::current().record("song_id", payload.song_id);
+    validate_payload(&payload, "SendShout")?;
+
     let steam_player = ticket_auth(&payload.ticket, &state.steam_api).await?;
 
     let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
 
-    let player: Player = Player::find_by_steam_id(steam_player)
-        .first::<Player>(&mut conn)
-        .await?;
+    let player = Player::find_by_steam_id_cached(steam_player, &mut conn, &mut redis_conn).await?;
+    let i18n = &state.config.i18n;
+    let locale = i18n.negotiate(player.locale.as_deref(), accept_language(&headers));
+    if let Some(reason) = filter_shout(
+        &state.config.text_filter,
+        player.id,
+        &payload.shout,
+        &mut conn,
+        &mut redis_conn,
+    )
+    .await?
+    {
+        info!(
+            "Shout on song {} by {} (Steam) refused, reason {:?}",
+            payload.song_id, steam_player, reason
+        );
+        let shouts = shouts_to_string(i18n, &locale, &mut conn, payload.song_id).await?;
+        // The game can't show an error here, but it shows whatever comes back as the song's shouts
+        return Ok(GameText(
+            version,
+            match refusal_notice(&state.config.text_filter, i18n, &locale, reason) {
+                Some(notice) => format!("{notice}\n\n{shouts}"),
+                None => shouts,
+            },
+        ));
+    }
 
     let shout = NewShout::new(payload.song_id, player.id, &payload.shout);
     shout.insert(&mut conn).await?;
 
-    Ok(shouts_to_string(&mut conn, payload.song_id).await?)
+    Ok(GameText(
+        version,
+        shouts_to_string(i18n, &locale, &mut conn, payload.song_id).await?,
+    ))
+}
+
+/// Tells the player why their shout was refused, so they don't just see it missing.
+///
+/// # Returns
+/// `None` if there's nothing to tell, like for duplicates, whose original is still there.
+fn refusal_notice(
+    rules: &TextFilterRules,
+    i18n: &Localization,
+    locale: &str,
+    reason: FilterReason,
+) -> Option<String> {
+    match reason {
+        FilterReason::Duplicate => None,
+        FilterReason::RateLimited => Some(i18n.text(
+            locale,
+            Text::ShoutRateLimited,
+            &[("limit", &rules.shouts_per_minute.unwrap_or_default())],
+        )),
+        FilterReason::BlockedWord | FilterReason::Link | FilterReason::RepeatedCharacters => {
+            Some(i18n.text(locale, Text::ShoutRefused, &[]))
+        }
+    }
+}
+
+/// Runs a shout through the text filter, see [`crate::util::text_filter`].
+///
+/// # Returns
+/// `None` if the shout can be posted, the reason it can't otherwise.
+async fn filter_shout(
+    rules: &TextFilterRules,
+    player_id: i32,
+    text: &str,
+    conn: &mut AsyncPgConnection,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> Result<Option<FilterReason>, RouteError> {
+    use crate::schema::shouts::dsl::*;
+
+    if let Some(reason) = rules.check(text) {
+        return Ok(Some(reason));
+    }
+
+    if let Some(limit) = rules.shouts_per_minute {
+        let key = redis_keys::shout_rate(player_id);
+        // The window starts with the first shout, so it's a fixed minute and not a sliding one
+        let count = redis_ops::incr_in_window(&key, 60, redis_conn).await?.count;
+        if count > i64::from(limit) {
+            return Ok(Some(FilterReason::RateLimited));
+        }
+    }
+
+    let previous: Option<String> = shouts
+        .filter(author_id.eq(player_id))
+        .order(posted_at.desc())
+        .select(content)
+        .first(conn)
+        .await
+        .optional()?;
+    if previous.is_some_and(|previous| previous.trim() == text.trim()) {
+        return Ok(Some(FilterReason::Duplicate));
+    }
+
+    Ok(None)
 }
@@ -1,43 +1,72 @@
+mod commands;
+#[cfg(test)]
+mod conformance;
 mod gameplay;
 mod helpers;
 mod misc;
+mod protocol;
 mod radio;
+mod ride_queue;
 mod user;
 
-use axum::{routing::post, Router};
+use std::sync::Arc;
+
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware::{from_fn_with_state, map_response},
+    routing::post,
+    Extension, Router,
+};
 use tower_http::services::ServeDir;
 
+pub use self::ride_queue::{run_workers as run_ride_workers, RideQueue};
 use self::{
     gameplay::{fetch_song_id, get_rides, send_ride},
+    helpers::payload_too_large_to_xml,
     misc::{fetch_shouts, fetch_track_shape, get_custom_news, send_shout},
+    protocol::negotiate,
     radio::get_radio_list,
     user::{login_steam, steam_sync},
 };
-use crate::AppState;
+use crate::{util::realm::Realm, AppState, Limits, Protocol};
 
-/// Returns all routes used for everything under ``/as_steamlogin``
-pub fn routes_steam() -> Router<AppState> {
+/// Returns all routes used for everything under ``/as_steamlogin``, for the given realm
+pub fn routes_steam(limits: &Limits, protocol: &Protocol, realm: Realm) -> Router<AppState> {
     Router::new()
         .route("/game_AttemptLoginSteamVerified.php", post(login_steam))
         .route("/game_SteamSyncSteamVerified.php", post(steam_sync))
         .route("/game_fetchsongid_unicode.php", post(fetch_song_id))
-        .route("/game_SendRideSteamVerified.php", post(send_ride))
+        // Track shapes make these a lot bigger than everything else
+        .route(
+            "/game_SendRideSteamVerified.php",
+            post(send_ride).layer(DefaultBodyLimit::max(limits.send_ride_body_bytes)),
+        )
         .route("/game_GetRidesSteamVerified.php", post(get_rides))
         .route("/game_fetchshouts_unicode.php", post(fetch_shouts))
         .route("/game_sendShoutSteamVerified.php", post(send_shout))
+        .layer(Extension(realm))
+        .layer(from_fn_with_state(Arc::new(protocol.clone()), negotiate))
+        .layer(DefaultBodyLimit::max(limits.game_body_bytes))
+        .layer(map_response(payload_too_large_to_xml))
 }
 
 /// Returns all routes used for everything under ``//as_steamlogin``
 ///
 /// **beware the double slash**
-pub fn routes_steam_doubleslash() -> Router<AppState> {
-    Router::new().route("/game_CustomNews.php", post(get_custom_news))
+pub fn routes_steam_doubleslash(limits: &Limits, protocol: &Protocol) -> Router<AppState> {
+    Router::new()
+        .route("/game_CustomNews.php", post(get_custom_news))
+        .layer(from_fn_with_state(Arc::new(protocol.clone()), negotiate))
+        .layer(DefaultBodyLimit::max(limits.game_body_bytes))
+        .layer(map_response(payload_too_large_to_xml))
 }
 
 /// Returns all routes used for everything under ``/as``
-pub fn routes_as(cgr_path: &str) -> Router<AppState> {
+pub fn routes_as(cgr_path: &str, limits: &Limits) -> Router<AppState> {
     Router::new()
         .route("/game_fetchtrackshape2.php", post(fetch_track_shape))
         .route("/asradio/game_asradiolist5.php", post(get_radio_list))
         .nest_service("/asradio", ServeDir::new(cgr_path))
+        .layer(DefaultBodyLimit::max(limits.game_body_bytes))
+        .layer(map_response(payload_too_large_to_xml))
 }
@@ -0,0 +1,201 @@
+//! Which version of the game protocol a request speaks, so responses can be shaped for it.
+//!
+//! Every request to the `as_steamlogin` routes goes through [`negotiate`], which works out the [`ClientVersion`]
+//! from the user agent and the endpoint, as configured in `[protocol]`, and adds it to the request as an extension.
+//! Handlers wrap their responses in [`GameXml`] (or [`GameText`] for plain text), which only keep the text in them
+//! that version can show, see [`GameResponse`].
+//!
+//! Anything that isn't recognized as an older build is taken as the current retail one, so the defaults change nothing.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header::USER_AGENT, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_serde::Xml;
+use serde::Serialize;
+use tracing::debug;
+
+use crate::Protocol;
+
+/// Version of the game a request comes from, see the module documentation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClientVersion {
+    /// The current Steam release
+    #[default]
+    Retail,
+    /// Older builds, which can't show text outside of ASCII
+    Legacy,
+}
+
+impl ClientVersion {
+    /// Works out the version from the user agent and `path`, the endpoint within the game routes.
+    #[must_use]
+    pub fn detect(path: &str, headers: &HeaderMap, settings: &Protocol) -> Self {
+        let endpoint = path.rsplit('/').next().unwrap_or(path);
+        if settings
+            .legacy_endpoints
+            .iter()
+            .any(|legacy| legacy == endpoint)
+        {
+            return Self::Legacy;
+        }
+
+        let user_agent = headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if settings
+            .legacy_user_agents
+            .iter()
+            .any(|prefix| user_agent.starts_with(prefix.as_str()))
+        {
+            return Self::Legacy;
+        }
+
+        Self::Retail
+    }
+}
+
+/// Adds the [`ClientVersion`] of the request as an extension, see the module documentation.
+pub async fn negotiate(
+    State(settings): State<Arc<Protocol>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let version = ClientVersion::detect(request.uri().path(), request.headers(), &settings);
+    if version != ClientVersion::Retail {
+        debug!("Serving {} as {:?}", request.uri().path(), version);
+    }
+    request.extensions_mut().insert(version);
+
+    next.run(request).await
+}
+
+/// A response to the game whose text is shown differently depending on the version of the client.
+/// Older builds take the same shape, they only can't show all of the text, see [`ClientVersion::Legacy`].
+pub trait GameResponse: Serialize + Sized {
+    /// Makes the text the player sees showable by [`ClientVersion::Legacy`] clients, with [`ascii_text`].
+    /// Responses without any such text are left as they are.
+    #[must_use]
+    fn into_legacy(self) -> Self {
+        self
+    }
+}
+
+/// Serializes a [`GameResponse`] as XML, with the text the client version can show.
+pub struct GameXml<T>(pub ClientVersion, pub T);
+
+impl<T: GameResponse> IntoResponse for GameXml<T> {
+    fn into_response(self) -> Response {
+        let Self(version, response) = self;
+        match version {
+            ClientVersion::Retail => Xml(response).into_response(),
+            ClientVersion::Legacy => Xml(response.into_legacy()).into_response(),
+        }
+    }
+}
+
+/// A plain text response to the game, like the shouts of a song, with the text the client version can show.
+pub struct GameText(pub ClientVersion, pub String);
+
+impl IntoResponse for GameText {
+    fn into_response(self) -> Response {
+        let Self(version, text) = self;
+        match version {
+            ClientVersion::Retail => text.into_response(),
+            ClientVersion::Legacy => ascii_text(&text).into_response(),
+        }
+    }
+}
+
+/// Replaces everything outside of ASCII with `?` for [`ClientVersion::Legacy`] clients,
+/// after turning typographic quotes and dashes into their plain versions.
+#[must_use]
+pub fn ascii_text(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' => '\'',
+            '\u{201c}' | '\u{201d}' => '"',
+            '\u{2010}' | '\u{2011}' | '\u{2013}' | '\u{2014}' => '-',
+            c if c.is_ascii() => c,
+            _ => '?',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn settings() -> Protocol {
+        Protocol {
+            legacy_user_agents: vec!["Audiosurf/0.".to_owned()],
+            legacy_endpoints: vec!["game_fetchsongid.php".to_owned()],
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        let mut old_build = HeaderMap::new();
+        old_build.insert(USER_AGENT, HeaderValue::from_static("Audiosurf/0.9"));
+        let mut retail = HeaderMap::new();
+        retail.insert(USER_AGENT, HeaderValue::from_static("Audiosurf/1.0"));
+
+        assert_eq!(
+            ClientVersion::detect("/game_GetRidesSteamVerified.php", &old_build, &settings()),
+            ClientVersion::Legacy
+        );
+        assert_eq!(
+            ClientVersion::detect("/game_GetRidesSteamVerified.php", &retail, &settings()),
+            ClientVersion::Retail
+        );
+        assert_eq!(
+            ClientVersion::detect("/game_fetchsongid.php", &retail, &settings()),
+            ClientVersion::Legacy
+        );
+        assert_eq!(
+            ClientVersion::detect("/game_fetchsongid.php", &HeaderMap::new(), &settings()),
+            ClientVersion::Legacy
+        );
+        assert_eq!(
+            ClientVersion::detect(
+                "/game_GetRidesSteamVerified.php",
+                &HeaderMap::new(),
+                &Protocol::default()
+            ),
+            ClientVersion::Retail
+        );
+    }
+
+    #[test]
+    fn test_ascii_text() {
+        assert_eq!(ascii_text("Daft Punk"), "Daft Punk");
+        assert_eq!(ascii_text("Don\u{2019}t \u{2013} Stop"), "Don't - Stop");
+        assert_eq!(ascii_text("Émilie"), "?milie");
+        assert_eq!(ascii_text("日本"), "??");
+    }
+
+    #[tokio::test]
+    async fn test_game_text() {
+        let body = |response: Response| async move {
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        };
+        let text = "Émilie (at 2024-10-01): Très bien\n".to_owned();
+
+        let retail = GameText(ClientVersion::Retail, text.clone()).into_response();
+        assert_eq!(body(retail).await.ok().as_deref(), Some(text.as_str()));
+        let legacy = GameText(ClientVersion::Legacy, text).into_response();
+        assert_eq!(
+            body(legacy).await.ok().as_deref(),
+            Some("?milie (at 2024-10-01): Tr?s bien\n")
+        );
+    }
+}
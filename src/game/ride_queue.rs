@@ -0,0 +1,115 @@
+//! The work after a ride was saved that the game doesn't have to wait for.
+//!
+//! `send_ride` only does what its response depends on: saving the score, updating the rankings (their delta is only
+//! right under the lock the score is saved with) and working out who got dethroned. The rest (gold thresholds,
+//! character stats, recent activity, server records and their webhook, queueing the metadata lookup) is handed to
+//! workers through a bounded queue, so a traffic spike doesn't make every submission wait for all of it.
+//!
+//! When the queue is full, submissions wait for room instead of dropping the work. That way a spike only slows down
+//! responses once the workers are `ride_queue.capacity` rides behind, and never loses anything while the server runs.
+//! The queue is kept in memory though, whatever is still in it when the server stops is lost.
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Semaphore,
+};
+use tracing::{error, info, warn, Instrument};
+
+use super::gameplay::SavedRide;
+use crate::AppState;
+
+/// Where saved rides are queued for the workers.
+#[derive(Clone)]
+pub struct RideQueue {
+    sender: mpsc::Sender<SavedRide>,
+    /// Taken by [`run_workers`] when they start
+    receiver: Arc<Mutex<Option<mpsc::Receiver<SavedRide>>>>,
+}
+
+impl RideQueue {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        Self {
+            sender,
+            receiver: Arc::new(Mutex::new(Some(receiver))),
+        }
+    }
+
+    /// Hands the ride to the workers, waiting for room if the queue is full.
+    /// Without workers (e.g. in tests) it's processed right away.
+    pub(super) async fn push(&self, state: &AppState, ride: SavedRide) {
+        let workers_running = self
+            .receiver
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_none();
+        let ride = if workers_running {
+            match self.sender.try_send(ride) {
+                Ok(()) => return,
+                Err(TrySendError::Full(ride)) => {
+                    warn!("Ride queue is full, submissions wait for room");
+                    match self.sender.send(ride).await {
+                        Ok(()) => return,
+                        Err(mpsc::error::SendError(ride)) => ride,
+                    }
+                }
+                // The workers stopped, which only happens when the server does
+                Err(TrySendError::Closed(ride)) => ride,
+            }
+        } else {
+            ride
+        };
+
+        process(state, ride).await;
+    }
+
+    /// How many rides are waiting for a worker.
+    #[must_use]
+    pub fn waiting(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+}
+
+async fn process(state: &AppState, ride: SavedRide) {
+    if let Err(e) = ride.process(state).await {
+        error!("Failed to process a saved ride: {e:?}");
+    }
+}
+
+/// Processes queued rides until the server stops, `ride_queue.workers` at a time.
+/// Meant to be spawned as a task next to the server.
+pub async fn run_workers(state: AppState) {
+    let receiver = state
+        .rides
+        .receiver
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    let Some(mut receiver) = receiver else {
+        return;
+    };
+    let workers = state.config.ride_queue.workers.max(1);
+    let permits = Arc::new(Semaphore::new(workers));
+    info!("Ride queue started with {workers} worker(s)");
+
+    loop {
+        // Rides stay in the queue until a worker is free, so a full queue really means that many are waiting
+        let Ok(permit) = Arc::clone(&permits).acquire_owned().await else {
+            break;
+        };
+        let Some(ride) = receiver.recv().await else {
+            break;
+        };
+        let state = state.clone();
+        tokio::spawn(
+            async move {
+                process(&state, ride).await;
+                drop(permit);
+            }
+            .in_current_span(),
+        );
+    }
+}
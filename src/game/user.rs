@@ -1,18 +1,24 @@
-use axum::{extract::State, Form};
-use axum_serde::Xml;
+use axum::{extract::State, Extension, Form};
 use diesel::{ExpressionMethods, QueryDsl};
 use diesel_async::RunQueryDsl;
 use serde::{Deserialize, Serialize};
-use tracing::{info, instrument};
+use tracing::{field, info, instrument};
 
 #[allow(clippy::wildcard_imports)]
 use crate::schema::players::dsl::*;
 use crate::{
-    game::helpers::ticket_auth,
-    models::players::{NewPlayer, Player},
+    game::{
+        helpers::ticket_auth,
+        protocol::{ascii_text, ClientVersion, GameResponse, GameXml},
+    },
+    models::{
+        players::{NewPlayer, Player},
+        rivalries::NewRivalry,
+    },
     util::{
-        errors::{IntoRouteError, RouteError},
+        errors::{IntoRouteError, RouteError, WavebreakerError},
         game_types::split_x_separated,
+        realm::Realm,
     },
     AppState,
 };
@@ -38,6 +44,13 @@ pub struct LoginSteamResponse {
     steam_id: i32,
 }
 
+impl GameResponse for LoginSteamResponse {
+    fn into_legacy(mut self) -> Self {
+        self.username = ascii_text(&self.username);
+        self
+    }
+}
+
 /// Attempts to authenticate a user through Steam.
 ///
 /// # Errors
@@ -45,11 +58,13 @@ pub struct LoginSteamResponse {
 /// - The response fails to serialize
 /// - Authenticating with Steam fails
 /// - Something goes wrong with the database
-#[instrument(skip_all)]
+#[instrument(skip_all, fields(steam_id = field::Empty))]
 pub async fn login_steam(
     State(state): State<AppState>,
+    Extension(realm): Extension<Realm>,
+    Extension(version): Extension<ClientVersion>,
     Form(payload): Form<LoginSteamRequest>,
-) -> Result<Xml<LoginSteamResponse>, RouteError> {
+) -> Result<GameXml<LoginSteamResponse>, RouteError> {
     let steam_player = ticket_auth(&payload.ticket, &state.steam_api)
         .await
         .http_internal_error("Failed to authenticate with Steam")?;
@@ -67,22 +82,26 @@ pub async fn login_steam(
     let mut conn = state.db.get().await?;
     let mut redis_conn = state.redis.get().await?;
 
+    // Steam names can't be refused, so anything blocked is masked instead
     let player = NewPlayer::new(
-        &summary[0].persona_name,
+        &state.config.text_filter.mask(&summary[0].persona_name),
         steam_player,
         i32::try_from(steam_player.get_account_id())?,
         &summary[0].avatar_full,
     )
-    .create_or_update(&mut conn, &mut redis_conn)
+    .create_or_update(realm.name(), &mut conn, &mut redis_conn)
     .await?;
 
-    Ok(Xml(LoginSteamResponse {
-        status: "allgood".to_owned(),
-        user_id: player.id,
-        username: player.username,
-        location_id: player.location_id,
-        steam_id: player.steam_account_num,
-    }))
+    Ok(GameXml(
+        version,
+        LoginSteamResponse {
+            status: "allgood".to_owned(),
+            user_id: player.id,
+            username: player.username,
+            location_id: player.location_id,
+            steam_id: player.steam_account_num,
+        },
+    ))
 }
 
 #[derive(Deserialize)]
@@ -98,6 +117,8 @@ pub struct SteamSyncResponse {
     status: String,
 }
 
+impl GameResponse for SteamSyncResponse {}
+
 /// Attempts to sync rivals with user's Steam friends.
 ///
 /// # Errors
@@ -105,11 +126,12 @@ pub struct SteamSyncResponse {
 /// - The response fails to serialize
 /// - Authenticating with Steam fails
 /// - Something goes wrong with the database
-#[instrument(skip_all)]
+#[instrument(skip_all, fields(steam_id = field::Empty))]
 pub async fn steam_sync(
     State(state): State<AppState>,
+    Extension(version): Extension<ClientVersion>,
     Form(payload): Form<SteamSyncRequest>,
-) -> Result<Xml<SteamSyncResponse>, RouteError> {
+) -> Result<GameXml<SteamSyncResponse>, RouteError> {
     //Split the string of steam account numbers into a vector
     //Validating before the steam auth request, because if this is invalid anyway then we don't care about the request
     //This way we have one less Steam API request on the daily limit
@@ -120,6 +142,7 @@ pub async fn steam_sync(
         .await
         .http_internal_error("Failed to authenticate with Steam")?;
     let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
 
     let player: Player = Player::find_by_steam_id(steam_player)
         .first::<Player>(&mut conn)
@@ -131,20 +154,25 @@ pub async fn steam_sync(
         .load::<Player>(&mut conn)
         .await?;
 
-    //Add new rivalry for each friend
+    //Add new rivalry for each friend, as far as the limits allow
+    let mut added = 0;
     for friend in &friends {
-        diesel::insert_into(crate::schema::rivalries::table)
-            .values((
-                crate::schema::rivalries::challenger_id.eq(player.id),
-                crate::schema::rivalries::rival_id.eq(friend.id),
-            ))
-            .on_conflict_do_nothing()
-            .execute(&mut conn)
-            .await?;
+        match NewRivalry::new(player.id, friend.id)
+            .create_synced(&state.config.rivals, &mut conn, &mut redis_conn)
+            .await
+        {
+            Ok(rivalry) => added += usize::from(rivalry.is_some()),
+            Err(WavebreakerError::TooManyRivals(_)) => break,
+            // Removed recently, the player has to wait before it's added again
+            Err(WavebreakerError::RivalryCooldown | WavebreakerError::SelfRivalry) => {}
+            Err(e) => return Err(e.into()),
+        }
     }
 
-    Ok(Xml(SteamSyncResponse {
-        //This technically doesn't return the number of friends added
-        status: format!("added {} of {} friends", friends.len(), friend_nums.len()),
-    }))
+    Ok(GameXml(
+        version,
+        SteamSyncResponse {
+            status: format!("added {} of {} friends", added, friend_nums.len()),
+        },
+    ))
 }
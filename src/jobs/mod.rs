@@ -0,0 +1,523 @@
+use diesel::{prelude::*, sql_types::BigInt};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    models::{
+        daily_stats::{DailyPlayerPoints, DailyStats},
+        jobs::{NewJob, QueuedJob},
+        leaderboard_snapshots::LeaderboardSnapshot,
+        metadata_suggestions::MetadataSuggestion,
+        notification_links::{NotificationChannel, NotificationLink},
+        players::Player,
+        ride_sources::RideSource,
+        rival_digests::{RivalActivity, RivalDigest},
+        scores::Score,
+        shout_reports::ShoutReport,
+        song_quarantine::QuarantinedSong,
+        songs::Song,
+    },
+    util::{
+        cover_colors,
+        i18n::{Localization, Text},
+        instance::STARTUP_LOCK,
+        notify, previews, sandbagging,
+        time_zone::TimeZone,
+    },
+    AppState,
+};
+
+/// Most activities listed in one rival digest, so it fits in a Discord message.
+const DIGEST_MAX_LINES: usize = 15;
+
+/// Work that's done in the background by the job worker, instead of while a player is waiting for a response.
+/// Jobs are stored in the `jobs` table as JSON, so they survive restarts.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Job {
+    #[allow(clippy::doc_markdown)]
+    /// Looks up the song on MusicBrainz and adds the metadata, if the song doesn't have any yet.
+    #[serde(rename_all = "camelCase")]
+    AddMetadata { song_id: i32, duration: i32 },
+    /// Extracts the dominant colors of the song's cover, see [`cover_colors`].
+    #[serde(rename_all = "camelCase")]
+    ExtractCoverColors { song_id: i32 },
+    /// Purges deleted songs/scores and old finished jobs, see `jobs.purge_deleted_after_days` in the config.
+    PurgeDeleted,
+    /// Archives the scores of songs nobody rode in a long time, see `jobs.archive_scores_after_years` in the config.
+    ArchiveScores,
+    /// Makes a backup, see `backup.daily` in the config.
+    Backup,
+    /// Prunes old events, resolved moderation items and the like, see `retention` in the config.
+    Prune,
+    /// Snapshots the top of the song leaderboards, see `snapshots.daily` in the config.
+    SnapshotLeaderboards,
+    /// Sends players what their rivals did to their webhooks, see `digests.daily` in the config.
+    SendRivalDigests,
+    /// Rolls up the stats of the days that are over, see `stats.daily` in the config.
+    RollUpStats,
+    /// Looks up song previews that are missing or old, see `previews` in the config.
+    RefreshPreviews,
+    /// Looks for elite-level players farming Casual, see `sandbagging` in the config.
+    DetectSandbagging,
+    /// Sends a notification to the email address or Discord account the player linked, see [`notify`].
+    #[serde(rename_all = "camelCase")]
+    Notify {
+        player_id: i32,
+        channel: NotificationChannel,
+        text: String,
+    },
+    /// Sends a new code confirming the link to the address the player linked, see [`notify`].
+    /// The code is made when the job runs, so it's never part of the queue.
+    #[serde(rename_all = "camelCase")]
+    SendNotificationCode {
+        player_id: i32,
+        channel: NotificationChannel,
+    },
+}
+
+impl Job {
+    /// When the job runs again after a run that ended at `now`, if it's a recurring one.
+    /// Recurring jobs schedule their next run themselves once they're done. They all run daily, at midnight in the
+    /// server's time zone.
+    #[must_use]
+    pub fn next_run(&self, time_zone: TimeZone, now: OffsetDateTime) -> Option<OffsetDateTime> {
+        match self {
+            Self::PurgeDeleted
+            | Self::ArchiveScores
+            | Self::Backup
+            | Self::Prune
+            | Self::SnapshotLeaderboards
+            | Self::SendRivalDigests
+            | Self::RollUpStats
+            | Self::RefreshPreviews
+            | Self::DetectSandbagging => Some(time_zone.next_midnight(now)),
+            Self::AddMetadata { .. }
+            | Self::ExtractCoverColors { .. }
+            | Self::Notify { .. }
+            | Self::SendNotificationCode { .. } => None,
+        }
+    }
+
+    /// Puts the job in the queue, to be run as soon as possible.
+    pub async fn enqueue(&self, conn: &mut AsyncPgConnection) -> anyhow::Result<QueuedJob> {
+        self.schedule(OffsetDateTime::now_utc(), conn).await
+    }
+
+    /// Puts the job in the queue, to be run at `run_at` or later.
+    pub async fn schedule(
+        &self,
+        run_at: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> anyhow::Result<QueuedJob> {
+        let new_job = NewJob {
+            payload: serde_json::to_value(self)?,
+            run_at,
+        };
+        Ok(new_job.insert(conn).await?)
+    }
+
+    /// Whether an identical job is already waiting in the queue.
+    pub async fn is_queued(&self, conn: &mut AsyncPgConnection) -> anyhow::Result<bool> {
+        use crate::schema::jobs::dsl::*;
+
+        let count: i64 = QueuedJob::pending()
+            .filter(payload.eq(serde_json::to_value(self)?))
+            .count()
+            .get_result(conn)
+            .await?;
+        Ok(count > 0)
+    }
+
+    /// Does the actual work.
+    async fn run(&self, state: &AppState) -> anyhow::Result<()> {
+        let mut conn = state.db.get().await?;
+
+        match self {
+            Self::AddMetadata { song_id, duration } => {
+                // The song might have been deleted (or merged) in the meantime, nothing to do then
+                let song = Song::all()
+                    .find(song_id)
+                    .first::<Song>(&mut conn)
+                    .await
+                    .optional()?;
+                if let Some(song) = song {
+                    song.auto_add_metadata(*duration, &mut conn).await?;
+                    cover_colors::queue(song.id, &mut conn).await;
+                    // The daily job tries again, Deezer being down isn't worth looking up the metadata again
+                    if state.config.previews.enabled {
+                        if let Err(e) = previews::find(&song, &mut conn).await {
+                            warn!("Failed to look up the preview of song {}: {e:#}", song.id);
+                        }
+                    }
+                }
+            }
+            Self::ExtractCoverColors { song_id } => {
+                cover_colors::extract(*song_id, &mut conn).await?;
+            }
+            Self::PurgeDeleted => purge_deleted(state, &mut conn).await?,
+            Self::ArchiveScores => archive_scores(state, &mut conn).await?,
+            Self::Backup => {
+                if !state.config.backup.daily {
+                    return Ok(());
+                }
+
+                crate::backup::run(state).await?;
+            }
+            Self::Prune => prune(state, &mut conn).await?,
+            Self::SnapshotLeaderboards => {
+                if !state.config.snapshots.daily {
+                    return Ok(());
+                }
+
+                let places =
+                    LeaderboardSnapshot::take(state.config.snapshots.top.max(1), &mut conn).await?;
+                info!("Snapshotted {places} leaderboard place(s)");
+            }
+            Self::SendRivalDigests => send_rival_digests(state, &mut conn).await?,
+            Self::RollUpStats => {
+                if !state.config.stats.daily {
+                    return Ok(());
+                }
+
+                let days = DailyStats::roll_up_missing(
+                    state.config.time_zone,
+                    state.config.stats.backfill_days,
+                    &mut conn,
+                )
+                .await?;
+                info!("Rolled up the stats of {} day(s)", days.len());
+
+                for day in days {
+                    let players =
+                        DailyPlayerPoints::record(day, state.config.time_zone, &mut conn).await?;
+                    info!("Recorded the skill points of {players} player(s) on {day}");
+                }
+            }
+            Self::RefreshPreviews => {
+                let config = &state.config.previews;
+                if !config.enabled {
+                    return Ok(());
+                }
+
+                let (looked_up, found, failed) =
+                    previews::refresh(config.refresh_days, config.per_day, &mut conn).await?;
+                info!(
+                    "Looked up the previews of {looked_up} song(s), found {found}, {failed} failed"
+                );
+            }
+            Self::DetectSandbagging => {
+                if !state.config.sandbagging.enabled {
+                    return Ok(());
+                }
+
+                let mut redis_conn = state.redis.get().await?;
+                sandbagging::detect(state, &mut conn, &mut redis_conn).await?;
+            }
+            Self::Notify {
+                player_id,
+                channel,
+                text,
+            } => {
+                // The player might have unlinked it or linked something else in the meantime
+                let Some(link) = NotificationLink::find(*player_id, *channel, &mut conn).await?
+                else {
+                    return Ok(());
+                };
+                if link.verified_at.is_none() {
+                    return Ok(());
+                }
+                notify::deliver(&state.config.notifications, *channel, &link.address, text).await?;
+            }
+            Self::SendNotificationCode { player_id, channel } => {
+                let Some(link) = NotificationLink::find(*player_id, *channel, &mut conn).await?
+                else {
+                    return Ok(());
+                };
+                if link.verified_at.is_some() {
+                    return Ok(());
+                }
+
+                let settings = &state.config.notifications;
+                let code = notify::new_code();
+                let valid_until =
+                    OffsetDateTime::now_utc() + Duration::minutes(settings.code_valid_minutes);
+                if !link.set_code(&code, valid_until, &mut conn).await? {
+                    return Ok(());
+                }
+
+                let i18n = &state.config.i18n;
+                let locale = Player::locale_of(*player_id, i18n, &mut conn).await?;
+                let text = i18n.text(
+                    &locale,
+                    Text::NotifyCode,
+                    &[("code", &code), ("minutes", &settings.code_valid_minutes)],
+                );
+                notify::deliver(settings, *channel, &link.address, &text).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Purges soft-deleted songs and scores, and finished jobs, once they're old enough.
+async fn purge_deleted(state: &AppState, conn: &mut AsyncPgConnection) -> anyhow::Result<()> {
+    let Some(older_than_days) = state.config.jobs.purge_deleted_after_days else {
+        return Ok(());
+    };
+
+    // Whole days, so it doesn't matter when in the day the job runs
+    let cutoff = state
+        .config
+        .time_zone
+        .start_of_today(OffsetDateTime::now_utc())
+        - Duration::days(older_than_days);
+    let purged_scores = Score::purge_deleted(cutoff, conn).await?;
+    let purged_songs = Song::purge_deleted(cutoff, conn).await?;
+    let purged_jobs = QueuedJob::purge_finished(cutoff, conn).await?;
+    info!(
+        "Purged {purged_songs} song(s), {purged_scores} score(s) and {purged_jobs} finished job(s) from before {cutoff}"
+    );
+    Ok(())
+}
+
+/// Archives the scores of songs nobody rode in the configured number of years.
+async fn archive_scores(state: &AppState, conn: &mut AsyncPgConnection) -> anyhow::Result<()> {
+    let Some(years) = state.config.jobs.archive_scores_after_years else {
+        return Ok(());
+    };
+
+    // Whole days, so it doesn't matter when in the day the job runs
+    let cutoff = state
+        .config
+        .time_zone
+        .start_of_today(OffsetDateTime::now_utc())
+        - Duration::days(365 * years);
+    let mut redis_conn = state.redis.get().await?;
+    let (songs, scores) = Score::archive_untouched(cutoff, conn, &mut redis_conn).await?;
+    info!("Archived {scores} score(s) of {songs} song(s) nobody rode since {cutoff}");
+    Ok(())
+}
+
+/// Prunes everything that has a retention period set in the config.
+async fn prune(state: &AppState, conn: &mut AsyncPgConnection) -> anyhow::Result<()> {
+    let retention = &state.config.retention;
+    let today = state
+        .config
+        .time_zone
+        .start_of_today(OffsetDateTime::now_utc());
+    let cutoff = |days: i64| today - Duration::days(days);
+    let mut pruned = Vec::new();
+
+    if let Some(days) = retention.events_days {
+        let events = crate::events::purge(cutoff(days), conn).await?;
+        pruned.push(format!("{events} event(s)"));
+    }
+    if let Some(days) = retention.resolved_moderation_days {
+        let reports = ShoutReport::purge_resolved(cutoff(days), conn).await?;
+        let suggestions = MetadataSuggestion::purge_resolved(cutoff(days), conn).await?;
+        pruned.push(format!(
+            "{reports} shout report(s), {suggestions} metadata suggestion(s)"
+        ));
+    }
+    if let Some(days) = retention.quarantine_days {
+        let quarantined = QuarantinedSong::purge_unseen(cutoff(days), conn).await?;
+        pruned.push(format!("{quarantined} quarantined song(s)"));
+    }
+    if retention.ride_sources_days > 0 {
+        let sources = RideSource::purge(cutoff(retention.ride_sources_days), conn).await?;
+        pruned.push(format!("{sources} ride source(s)"));
+    }
+
+    info!("Pruned {}", pruned.join(", "));
+    Ok(())
+}
+
+/// Sends every player who set a webhook what their rivals did since the last digest.
+/// A webhook that doesn't work only skips that player, it's tried again the next day.
+async fn send_rival_digests(state: &AppState, conn: &mut AsyncPgConnection) -> anyhow::Result<()> {
+    if !state.config.digests.daily {
+        return Ok(());
+    }
+
+    let mut sent = 0;
+    for digest in RivalDigest::with_webhooks(conn).await? {
+        let Some(webhook_url) = &digest.webhook_url else {
+            continue;
+        };
+        // Set before webhooks had to be Discord's
+        if !crate::records::is_discord_webhook(webhook_url) {
+            warn!(
+                "Not sending the rival digest of player {}, its webhook isn't a Discord webhook",
+                digest.player_id
+            );
+            continue;
+        }
+        let now = OffsetDateTime::now_utc();
+        let since = digest.sent_at.unwrap_or(now - Duration::days(1));
+        let activities = RivalActivity::since(digest.player_id, since, conn).await?;
+
+        if !activities.is_empty() {
+            let locale = Player::locale_of(digest.player_id, &state.config.i18n, conn).await?;
+            let message = digest_message(&activities, &state.config.i18n, &locale);
+            if let Err(e) = crate::records::announce(webhook_url, &message).await {
+                warn!(
+                    "Failed to send the rival digest of player {}: {e:?}",
+                    digest.player_id
+                );
+                continue;
+            }
+            sent += 1;
+        }
+        digest.mark_sent(now, conn).await?;
+    }
+
+    info!("Sent {sent} rival digest(s)");
+    Ok(())
+}
+
+/// Lists the activities in a message, the most recent ones if there's too many.
+fn digest_message(activities: &[RivalActivity], i18n: &Localization, locale: &str) -> String {
+    let mut message = i18n.text(locale, Text::DigestHeader, &[]);
+    // Discord doesn't take messages over 2000 characters
+    for activity in activities.iter().take(DIGEST_MAX_LINES) {
+        message.push_str("\n- ");
+        message.push_str(&activity.describe(i18n, locale));
+    }
+    if activities.len() > DIGEST_MAX_LINES {
+        let more = activities.len() - DIGEST_MAX_LINES;
+        message.push('\n');
+        message.push_str(&i18n.text(locale, Text::DigestMore, &[("count", &more)]));
+    }
+    message
+}
+
+/// Runs the job worker forever. Meant to be spawned as a task next to the server.
+pub async fn run_worker(state: AppState) {
+    let poll_interval = std::time::Duration::from_secs(state.config.jobs.poll_interval_secs);
+
+    if let Err(e) = schedule_recurring(&state).await {
+        error!("Failed to schedule recurring jobs: {e:?}");
+    }
+
+    info!("Job worker started");
+    loop {
+        let worked = work_next(&state).await.unwrap_or_else(|e| {
+            error!("Job worker error: {e:?}");
+            false
+        });
+        // If a job was run there might be more, so don't wait
+        if !worked {
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+}
+
+/// Makes sure every recurring job that's enabled in the config has a run queued.
+async fn schedule_recurring(state: &AppState) -> anyhow::Result<()> {
+    let mut conn = state.db.get().await?;
+
+    conn.transaction::<_, anyhow::Error, _>(|conn| {
+        async move {
+            // Instances starting at the same time would otherwise both see nothing queued and queue the jobs twice
+            diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+                .bind::<BigInt, _>(STARTUP_LOCK)
+                .execute(conn)
+                .await?;
+            enqueue_missing_recurring(state, conn).await
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+async fn enqueue_missing_recurring(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+) -> anyhow::Result<()> {
+    if state.config.jobs.purge_deleted_after_days.is_some()
+        && !Job::PurgeDeleted.is_queued(conn).await?
+    {
+        Job::PurgeDeleted.enqueue(conn).await?;
+    }
+    if state.config.jobs.archive_scores_after_years.is_some()
+        && !Job::ArchiveScores.is_queued(conn).await?
+    {
+        Job::ArchiveScores.enqueue(conn).await?;
+    }
+    if state.config.backup.daily && !Job::Backup.is_queued(conn).await? {
+        Job::Backup.enqueue(conn).await?;
+    }
+    if state.config.retention.is_enabled() && !Job::Prune.is_queued(conn).await? {
+        Job::Prune.enqueue(conn).await?;
+    }
+    if state.config.snapshots.daily && !Job::SnapshotLeaderboards.is_queued(conn).await? {
+        Job::SnapshotLeaderboards.enqueue(conn).await?;
+    }
+    if state.config.digests.daily && !Job::SendRivalDigests.is_queued(conn).await? {
+        Job::SendRivalDigests.enqueue(conn).await?;
+    }
+    if state.config.stats.daily && !Job::RollUpStats.is_queued(conn).await? {
+        Job::RollUpStats.enqueue(conn).await?;
+    }
+    if state.config.previews.enabled && !Job::RefreshPreviews.is_queued(conn).await? {
+        Job::RefreshPreviews.enqueue(conn).await?;
+    }
+    if state.config.sandbagging.enabled && !Job::DetectSandbagging.is_queued(conn).await? {
+        Job::DetectSandbagging.enqueue(conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Claims and runs the next due job, if there is one.
+///
+/// # Returns
+/// Whether a job was run.
+#[instrument(skip(state))]
+async fn work_next(state: &AppState) -> anyhow::Result<bool> {
+    let mut conn = state.db.get().await?;
+
+    let Some(queued) = QueuedJob::claim_next(&mut conn).await? else {
+        return Ok(false);
+    };
+
+    let job = match serde_json::from_value::<Job>(queued.payload.clone()) {
+        Ok(job) => job,
+        Err(e) => {
+            // Retrying won't make the payload any more valid
+            error!("Job {} has an invalid payload: {e}", queued.id);
+            diesel::update(&queued)
+                .set(crate::schema::jobs::failed_at.eq(OffsetDateTime::now_utc()))
+                .execute(&mut conn)
+                .await?;
+            return Ok(true);
+        }
+    };
+
+    let result = job.run(state).await;
+    let updated = match result {
+        Ok(()) => queued.finish(&mut conn).await?,
+        Err(e) => {
+            warn!(
+                "Job {} ({job:?}) failed on attempt {}/{}: {e:?}",
+                queued.id, queued.attempts, queued.max_attempts
+            );
+            queued.fail(&format!("{e:?}"), &mut conn).await?
+        }
+    };
+
+    // Once a recurring job is done for good, queue its next run
+    if updated.finished_at.is_some() || updated.failed_at.is_some() {
+        if let Some(next_run) = job.next_run(state.config.time_zone, OffsetDateTime::now_utc()) {
+            job.schedule(next_run, &mut conn).await?;
+        }
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,910 @@
+#![warn(
+    clippy::pedantic,
+    clippy::nursery,
+    clippy::correctness,
+    clippy::style,
+    clippy::perf,
+    clippy::complexity,
+    clippy::cognitive_complexity,
+    clippy::double_parens,
+    clippy::len_zero,
+    clippy::question_mark,
+    clippy::suspicious,
+    clippy::todo
+)]
+#![allow(clippy::wildcard_imports)]
+// because every time I leave out error documentation, it's because it's EXTREMELY obvious.
+// I don't need to tell people that a function which returns something from the database will fail if it can't connect to the database!
+// I'm not kidding, the error descriptions were almost always "This function will fail if something is wrong with the database". That's not helpful.
+#![allow(clippy::missing_errors_doc)]
+// The library is only split out of the binary so benchmarks can use it, it's not meant to be used by anyone else.
+// These lints are about making a nice public API, which is a lot of noise for no benefit here.
+#![allow(
+    clippy::must_use_candidate,
+    clippy::return_self_not_must_use,
+    clippy::missing_panics_doc
+)]
+
+mod api;
+pub mod backup;
+mod events;
+mod game;
+mod jobs;
+pub mod manager;
+pub mod models;
+mod records;
+pub mod schema;
+pub mod storage;
+pub mod util;
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware,
+    response::Response,
+    Router,
+};
+use deadpool_redis::Runtime;
+use diesel::pg::Pg;
+use diesel_async::{
+    async_connection_wrapper::AsyncConnectionWrapper,
+    pooled_connection::{deadpool::Pool, AsyncDieselConnectionManager},
+};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use figment::{
+    providers::{Env, Format, Toml},
+    Figment,
+};
+use serde::Deserialize;
+use steam_rs::Steam;
+use tower_http::trace::TraceLayer;
+use tracing::{info, Instrument, Span};
+
+use crate::{
+    api::routes,
+    game::{routes_as, routes_steam, routes_steam_doubleslash},
+    models::rivalries::RivalryLimits,
+    util::{
+        i18n::Localization, realm::Realm, sandbagging::SandbaggingRules, scoring::ScoringPolicy,
+        song_creation::SongCreationPolicy, text_filter::TextFilterRules, time_zone::TimeZone,
+        traffic::TrafficRules,
+    },
+};
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+
+#[derive(Deserialize, Clone)]
+struct Config {
+    main: Main,
+    radio: Radio,
+    external: External,
+    #[serde(default)]
+    jobs: Jobs,
+    #[serde(default)]
+    limits: Limits,
+    #[serde(default)]
+    tag_commands: Vec<TagCommand>,
+    #[serde(default)]
+    text_filter: TextFilterRules,
+    #[serde(default)]
+    rivals: RivalryLimits,
+    #[serde(default)]
+    scoring: ScoringPolicy,
+    #[serde(default)]
+    sandbagging: SandbaggingRules,
+    #[serde(default)]
+    song_creation: SongCreationPolicy,
+    #[serde(default)]
+    traffic_checks: TrafficRules,
+    #[serde(default)]
+    storage: Storage,
+    #[serde(default)]
+    backup: Backup,
+    #[serde(default)]
+    snapshots: Snapshots,
+    #[serde(default)]
+    stats: Stats,
+    #[serde(default)]
+    previews: Previews,
+    #[serde(default)]
+    records: Records,
+    #[serde(default)]
+    digests: Digests,
+    #[serde(default)]
+    profiles: Profiles,
+    #[serde(default)]
+    i18n: Localization,
+    #[serde(default)]
+    time_zone: TimeZone,
+    #[serde(default)]
+    latency_alerts: LatencyAlerts,
+    #[serde(default)]
+    events: Events,
+    #[serde(default)]
+    retention: Retention,
+    #[serde(default)]
+    ride_queue: RideQueueSettings,
+    #[serde(default)]
+    ride_sources: RideSources,
+    #[serde(default)]
+    protocol: Protocol,
+    #[serde(default)]
+    notifications: Notifications,
+    #[serde(default)]
+    tls: Tls,
+    /// Already read by [`log_format`] before anything else, it's only here so mistakes in it are reported
+    #[allow(dead_code)]
+    #[serde(default)]
+    logging: Logging,
+}
+
+#[derive(Deserialize, Clone)]
+struct Main {
+    address: String,
+    database: String,
+    /// Read-only replica used for leaderboards and other heavy reads. Everything goes to `database` if unset.
+    database_replica: Option<String>,
+    redis: String,
+    jwt_secret: String,
+    /// Realms served in addition to the main one, see [`util::realm`]
+    #[serde(default)]
+    realms: Vec<Realm>,
+    /// Tells this instance apart in the logs when several run behind a load balancer, see [`util::instance`]
+    instance_id: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+struct Radio {
+    cgr_location: String,
+}
+
+#[derive(Deserialize, Clone)]
+struct External {
+    steam_key: String,
+    steam_realm: String,
+    steam_return_path: String,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct Jobs {
+    /// How long the job worker waits before checking the queue again when it's empty
+    poll_interval_secs: u64,
+    /// If set, deleted songs and scores are purged automatically once they've been deleted for this many days
+    purge_deleted_after_days: Option<i64>,
+    /// If set, the scores of songs nobody rode for this many years are archived daily, see [`models::scores_archive`]
+    archive_scores_after_years: Option<i64>,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: 5,
+            purge_deleted_after_days: None,
+            archive_scores_after_years: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct Limits {
+    /// Largest request body (in bytes) the game routes accept
+    game_body_bytes: usize,
+    /// Largest request body (in bytes) for score submissions, which carry the whole track shape
+    send_ride_body_bytes: usize,
+    /// Requests to `/api` without an API key one client can make in a minute, 0 for no limit, see `util::api_quota`
+    anonymous_api_per_minute: u32,
+    /// Header with the client's IP address, like `X-Forwarded-For` behind a proxy. The connection's address is used
+    /// if unset. Only the last address in it is used, the one the proxy in front of the server added, so only set it
+    /// if that proxy always adds one. Clients could pick their own address otherwise.
+    client_ip_header: Option<String>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            game_body_bytes: 64 * 1024,
+            send_ride_body_bytes: 512 * 1024,
+            anonymous_api_per_minute: 300,
+            client_ip_header: None,
+        }
+    }
+}
+
+/// How log lines are written, to the console and the log files alike.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, for shipping logs to something like Loki or Elasticsearch
+    Json,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+struct Logging {
+    format: LogFormat,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct LatencyAlerts {
+    /// Alert when the p99 latency of `send_ride` goes over this many milliseconds. No alerts if unset.
+    send_ride_p99_ms: Option<u64>,
+    /// How long a window latencies are measured over is, in seconds
+    window_secs: u64,
+    /// Alerts are sent here as JSON in a POST request. They're only logged if unset.
+    webhook_url: Option<String>,
+}
+
+impl Default for LatencyAlerts {
+    fn default() -> Self {
+        Self {
+            send_ride_p99_ms: None,
+            window_secs: 300,
+            webhook_url: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct Events {
+    /// Whether analytics events are recorded at all, see [`events`]
+    enabled: bool,
+    /// Events are sent here as a JSON array in a POST request instead of being stored in the `events` table, if set
+    webhook_url: Option<String>,
+    /// Most events written at once
+    batch_size: usize,
+    /// How long (in seconds) the writer waits for a batch to fill up before writing it anyway
+    flush_interval_secs: u64,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            webhook_url: None,
+            batch_size: 500,
+            flush_interval_secs: 10,
+        }
+    }
+}
+
+/// How the work after saving a ride is queued, see [`game::RideQueue`].
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct RideQueueSettings {
+    /// Most rides waiting for a worker before submissions have to wait for room
+    capacity: usize,
+    /// How many rides are processed at once
+    workers: usize,
+}
+
+impl Default for RideQueueSettings {
+    fn default() -> Self {
+        Self {
+            capacity: 1000,
+            workers: 4,
+        }
+    }
+}
+
+/// Recording which clients rides are submitted from, see [`util::client_source`].
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct RideSources {
+    enabled: bool,
+    /// Header with the client's region, like `CF-IPCountry` behind Cloudflare. No region is recorded if unset.
+    region_header: Option<String>,
+}
+
+impl Default for RideSources {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            region_header: None,
+        }
+    }
+}
+
+/// Telling older builds of the game apart, see `game::protocol`.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+struct Protocol {
+    /// User agents (prefixes) of builds that get the legacy response shapes
+    legacy_user_agents: Vec<String>,
+    /// Endpoints (like `game_CustomNews.php`) that always get the legacy response shapes, for ones only older builds call
+    legacy_endpoints: Vec<String>,
+}
+
+/// Notifications outside of the game, see [`util::notify`].
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct Notifications {
+    /// Token of the bot sending Discord direct messages. Players can't link Discord accounts if unset.
+    discord_bot_token: Option<String>,
+    /// SMTP server emails are sent through, with STARTTLS. Players can't link email addresses if unset.
+    /// Needs the `smtp` feature.
+    smtp_host: Option<String>,
+    smtp_port: u16,
+    smtp_username: String,
+    smtp_password: String,
+    /// Sender of the emails, like `Wavebreaker <noreply@example.com>`
+    email_from: String,
+    /// How long the code confirming a link works, in minutes
+    code_valid_minutes: i64,
+    /// Most codes a player can have sent in an hour
+    codes_per_hour: u32,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self {
+            discord_bot_token: None,
+            smtp_host: None,
+            smtp_port: 587,
+            smtp_username: String::new(),
+            smtp_password: String::new(),
+            email_from: String::new(),
+            code_valid_minutes: 30,
+            codes_per_hour: 5,
+        }
+    }
+}
+
+/// Serving HTTPS next to plain HTTP, see `util::tls`. Needs the `tls` feature.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct Tls {
+    /// Where HTTPS is served, it's off if unset
+    address: Option<String>,
+    /// PEM file with the certificate chain, leaf first
+    cert_path: String,
+    /// PEM file with the private key
+    key_path: String,
+    /// How often the files are checked for a new certificate
+    reload_interval_secs: u64,
+}
+
+impl Default for Tls {
+    fn default() -> Self {
+        Self {
+            address: None,
+            cert_path: "cert.pem".to_owned(),
+            key_path: "key.pem".to_owned(),
+            reload_interval_secs: 3600,
+        }
+    }
+}
+
+/// How long things that pile up are kept, in days. Nothing is pruned for settings that are unset.
+/// Pruning is done daily by the job worker.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+#[allow(clippy::struct_field_names)]
+struct Retention {
+    /// Analytics events, see [`events`]
+    events_days: Option<i64>,
+    /// Shout reports and metadata suggestions, counted from when a moderator resolved them
+    resolved_moderation_days: Option<i64>,
+    /// Quarantined song tags, counted from when they were last seen
+    quarantine_days: Option<i64>,
+    /// Which clients rides were submitted from, see [`util::client_source`].
+    /// Recorded for every ride, so they're pruned by default. 0 keeps them forever.
+    ride_sources_days: i64,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            events_days: None,
+            resolved_moderation_days: None,
+            quarantine_days: None,
+            ride_sources_days: 90,
+        }
+    }
+}
+
+impl Retention {
+    const fn is_enabled(&self) -> bool {
+        self.events_days.is_some()
+            || self.resolved_moderation_days.is_some()
+            || self.quarantine_days.is_some()
+            || self.ride_sources_days > 0
+    }
+}
+
+/// Where blobs like backups are kept, see [`storage`].
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum StorageBackend {
+    /// Files below `storage.directory`
+    #[default]
+    Local,
+    /// Objects in `storage.s3_bucket`. Needs the `s3` feature.
+    S3,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct Storage {
+    backend: StorageBackend,
+    /// Root of the local storage, blobs are stored in subdirectories of it (like `backups`)
+    directory: String,
+    s3_bucket: Option<String>,
+    /// Prepended to the keys of everything stored in S3
+    s3_prefix: String,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::Local,
+            directory: ".".to_owned(),
+            s3_bucket: None,
+            s3_prefix: String::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct Backup {
+    /// How many backups are kept around, older ones are deleted
+    keep: usize,
+    /// Whether the job worker makes a backup every day
+    daily: bool,
+}
+
+impl Default for Backup {
+    fn default() -> Self {
+        Self {
+            keep: 7,
+            daily: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct Snapshots {
+    /// Whether the job worker snapshots the song leaderboards every day, see `LeaderboardSnapshot::take`
+    daily: bool,
+    /// How many places of each leaderboard are kept
+    top: i32,
+}
+
+impl Default for Snapshots {
+    fn default() -> Self {
+        Self {
+            daily: false,
+            top: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct Stats {
+    /// Whether the job worker rolls up the stats of the day before every day, see `DailyStats::roll_up_missing`
+    daily: bool,
+    /// How many past days are rolled up, days missing among them are filled in
+    backfill_days: i64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            daily: false,
+            backfill_days: 30,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+struct Records {
+    /// Broken records are announced to this Discord webhook, see [`records`]
+    discord_webhook_url: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+struct Digests {
+    /// Whether the job worker sends players a daily digest of what their rivals did, to the webhook they set
+    daily: bool,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct Profiles {
+    /// Whether public profiles list the names the player went by before, see `PreviousName`.
+    /// Moderators can always see them.
+    show_previous_names: bool,
+    /// How long players have to wait before changing their slug again, see [`util::slug`]
+    slug_change_days: i64,
+    /// How often a player can hide, unhide or delete their own scores in a day, see `api::scores`
+    score_removals_per_day: u32,
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Self {
+            show_previous_names: false,
+            slug_change_days: 30,
+            score_removals_per_day: 10,
+        }
+    }
+}
+
+/// A title and artist players can tag a song with to talk to the server, instead of playing a song.
+/// See `game::commands`.
+#[derive(Deserialize, Clone)]
+struct TagCommand {
+    /// Only used for logging
+    name: String,
+    title: String,
+    artist: String,
+    /// Song whose leaderboards the game is sent to. If unset, the game is just told the lookup failed.
+    song_id: Option<i32>,
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    steam_api: Arc<Steam>,
+    config: Arc<Config>,
+    db: Pool<diesel_async::AsyncPgConnection>,
+    /// Pool for the read replica, if there is one. Otherwise, it's the same pool as `db`.
+    /// Replication lag means this might not have the latest writes yet, so never use it for anything that writes
+    /// or needs to see something that was just written!
+    db_read: Pool<diesel_async::AsyncPgConnection>,
+    redis: deadpool_redis::Pool,
+    jwt_keys: util::jwt::Keys,
+    latencies: util::metrics::RouteLatencies,
+    events: events::EventSink,
+    rides: game::RideQueue,
+    /// See [`util::live_leaderboards`]
+    live_leaderboards: util::live_leaderboards::LiveLeaderboards,
+    storage: Arc<storage::BlobStorage>,
+    /// See [`util::instance::resolve_id`]
+    instance_id: Arc<str>,
+}
+
+pub fn run_migrations(
+    connection: &mut impl MigrationHarness<Pg>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    // This will run the necessary migrations.
+    //
+    // See the documentation for `MigrationHarness` for
+    // all available methods.
+    connection.run_pending_migrations(MIGRATIONS)?;
+
+    Ok(())
+}
+
+fn config_figment() -> Figment {
+    Figment::new()
+        .merge(Toml::file("Wavebreaker.toml"))
+        .merge(Env::prefixed("WAVEBREAKER_"))
+}
+
+/// Checks what the types of the config can't express, so a bad value is refused on startup instead of misbehaving later.
+fn validate_config(config: &Config) -> anyhow::Result<()> {
+    config.sandbagging.validate()?;
+    config.i18n.validate()?;
+    if config
+        .jobs
+        .archive_scores_after_years
+        .is_some_and(|years| years < 1)
+    {
+        anyhow::bail!("jobs.archive_scores_after_years has to be at least 1");
+    }
+    Ok(())
+}
+
+/// Settings that were moved elsewhere, with where they are now.
+/// Starting with one of them set is refused, the server would quietly do something else with it otherwise.
+const MOVED_SETTINGS: [(&str, &str); 3] = [
+    ("backup.directory", "storage.directory"),
+    ("backup.s3_bucket", "storage.s3_bucket"),
+    ("backup.s3_prefix", "storage.s3_prefix"),
+];
+
+/// Refuses the config if it sets one of [`MOVED_SETTINGS`].
+fn check_moved_settings(figment: &Figment) -> anyhow::Result<()> {
+    for (old, new) in MOVED_SETTINGS {
+        if figment.find_value(old).is_ok() {
+            anyhow::bail!("{old} was replaced by {new} (see the storage section of the README)");
+        }
+    }
+    Ok(())
+}
+
+/// Reads only the log format from the config, since logging has to be set up before anything else.
+///
+/// Falls back to the default if the config can't be read, [`init_state`] reports what's wrong with it afterwards.
+#[must_use]
+pub fn log_format() -> LogFormat {
+    config_figment()
+        .extract_inner::<Logging>("logging")
+        .map(|logging| logging.format)
+        .unwrap_or_default()
+}
+
+/// Reads the config, initializes database connections and the Steam API client
+///
+/// # Returns
+/// An `AppState` struct with all the necessary members
+///
+/// # Errors
+/// This function can fail if the config file is missing or invalid, the connection to Postgres or Redis fails, or the Steam API key is invalid
+pub async fn init_state() -> anyhow::Result<AppState> {
+    let figment = config_figment();
+    check_moved_settings(&figment).context("Config should be valid!")?;
+    let wavebreaker_config: Config = figment.extract().context("Config should be valid!")?;
+    validate_config(&wavebreaker_config).context("Config should be valid!")?;
+
+    util::scoring::install(wavebreaker_config.scoring.clone());
+
+    let diesel_manager = AsyncDieselConnectionManager::<diesel_async::AsyncPgConnection>::new(
+        &wavebreaker_config.main.database,
+    );
+    let pool = Pool::builder(diesel_manager)
+        .build()
+        .context("Failed to build DB pool!")?;
+    let read_pool = match &wavebreaker_config.main.database_replica {
+        Some(replica_url) => {
+            info!("Using read replica for leaderboards");
+            Pool::builder(AsyncDieselConnectionManager::<
+                diesel_async::AsyncPgConnection,
+            >::new(replica_url))
+            .build()
+            .context("Failed to build replica DB pool!")?
+        }
+        None => pool.clone(),
+    };
+
+    // clone the url because moving the value will screw things up
+    let pg_url = wavebreaker_config.main.database.clone();
+    tokio::task::spawn_blocking(move || {
+        use diesel::prelude::Connection;
+        use diesel_async::pg::AsyncPgConnection;
+        let mut conn = AsyncConnectionWrapper::<AsyncPgConnection>::establish(&pg_url)
+            .expect("Failed to establish DB connection for migrations!");
+
+        // Released when the connection is closed, right after
+        diesel::RunQueryDsl::execute(
+            diesel::sql_query("SELECT pg_advisory_lock($1)")
+                .bind::<diesel::sql_types::BigInt, _>(util::instance::STARTUP_LOCK),
+            &mut conn,
+        )
+        .expect("Failed to wait for other instances migrating!");
+        run_migrations(&mut conn).expect("Failed to run migrations!");
+    })
+    .await?;
+
+    let redis_cfg = deadpool_redis::Config::from_url(&wavebreaker_config.main.redis);
+    let redis_pool = redis_cfg
+        .create_pool(Some(Runtime::Tokio1))
+        .context("Failed to build Redis pool!")?;
+
+    // Set global user agent so MusicBrainz can contact us if we're messing up
+    musicbrainz_rs::config::set_user_agent(
+        "wavebreaker-rs/0.1.0 (https://github.com/AudiosurfResearch/wavebreaker-rs)",
+    );
+
+    let blob_storage = storage::BlobStorage::from_config(&wavebreaker_config.storage)
+        .await
+        .context("Failed to set up storage!")?;
+    info!("Storing blobs in {}", blob_storage.describe());
+
+    Ok(AppState {
+        steam_api: Arc::new(Steam::new(&wavebreaker_config.external.steam_key)),
+        db: pool,
+        db_read: read_pool,
+        redis: redis_pool,
+        jwt_keys: util::jwt::Keys::new(wavebreaker_config.main.jwt_secret.as_bytes()),
+        events: events::EventSink::new(wavebreaker_config.events.enabled),
+        rides: game::RideQueue::new(wavebreaker_config.ride_queue.capacity),
+        live_leaderboards: util::live_leaderboards::LiveLeaderboards::default(),
+        instance_id: util::instance::resolve_id(wavebreaker_config.main.instance_id.as_deref())
+            .into(),
+        config: Arc::new(wavebreaker_config),
+        latencies: util::metrics::RouteLatencies::default(),
+        storage: Arc::new(blob_storage),
+    })
+}
+
+/// A state for driving the routes in tests without Postgres, Redis or Steam.
+/// Nothing connects until a handler asks for a connection, which then fails, so only what happens before that
+/// (routing, parsing bodies, body limits) can be tested with it.
+#[cfg(test)]
+pub(crate) fn test_state() -> AppState {
+    let config: Config = Figment::new()
+        .merge(Toml::string(
+            r#"
+            [main]
+            address = "127.0.0.1:0"
+            database = "postgres://wavebreaker@127.0.0.1:1/wavebreaker"
+            redis = "redis://127.0.0.1:1"
+            jwt_secret = "test"
+
+            [radio]
+            cgr_location = "radio"
+
+            [external]
+            steam_key = "test"
+            steam_realm = "http://localhost"
+            steam_return_path = "/"
+            "#,
+        ))
+        .extract()
+        .expect("test config should be valid");
+
+    let pool = Pool::builder(AsyncDieselConnectionManager::<
+        diesel_async::AsyncPgConnection,
+    >::new(&config.main.database))
+    .build()
+    .expect("test DB pool should build");
+    let redis_pool = deadpool_redis::Config::from_url(&config.main.redis)
+        .create_pool(Some(Runtime::Tokio1))
+        .expect("test Redis pool should build");
+
+    AppState {
+        steam_api: Arc::new(Steam::new(&config.external.steam_key)),
+        db: pool.clone(),
+        db_read: pool,
+        redis: redis_pool,
+        jwt_keys: util::jwt::Keys::new(config.main.jwt_secret.as_bytes()),
+        events: events::EventSink::new(false),
+        rides: game::RideQueue::new(config.ride_queue.capacity),
+        live_leaderboards: util::live_leaderboards::LiveLeaderboards::default(),
+        instance_id: "test".into(),
+        storage: Arc::new(storage::BlobStorage::Local(std::env::temp_dir())),
+        latencies: util::metrics::RouteLatencies::default(),
+        config: Arc::new(config),
+    }
+}
+
+pub fn make_router(state: AppState) -> Router {
+    let instance_id = Arc::clone(&state.instance_id);
+    let mut router = Router::new()
+        .nest(
+            "/as_steamlogin",
+            routes_steam(&state.config.limits, &state.config.protocol, Realm::main()),
+        )
+        .nest(
+            "//as_steamlogin",
+            routes_steam_doubleslash(&state.config.limits, &state.config.protocol),
+        ) // for that one edge case
+        .nest(
+            "/as",
+            routes_as(&state.config.radio.cgr_location, &state.config.limits),
+        );
+
+    // Other realms get the same game routes under their own prefix
+    for realm in &state.config.main.realms {
+        let prefix = format!("/realms/{}", realm.name());
+        info!("Serving realm {} under {prefix}", realm.name());
+        router = router
+            .nest(
+                &format!("{prefix}/as_steamlogin"),
+                routes_steam(&state.config.limits, &state.config.protocol, realm.clone()),
+            )
+            .nest(
+                &format!("{prefix}//as_steamlogin"),
+                routes_steam_doubleslash(&state.config.limits, &state.config.protocol),
+            )
+            .nest(
+                &format!("{prefix}/as"),
+                routes_as(&state.config.radio.cgr_location, &state.config.limits),
+            );
+    }
+
+    router
+        .nest(
+            "/api",
+            routes().layer(middleware::from_fn_with_state(
+                state.clone(),
+                util::api_quota::enforce,
+            )),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            util::metrics::track_latency,
+        ))
+        .layer(
+            // TAKEN FROM: https://github.com/tokio-rs/axum/blob/d1fb14ead1063efe31ae3202e947ffd569875c0b/examples/error-handling/src/main.rs#L60-L77
+            TraceLayer::new_for_http() // Create our own span for the request and include the matched path. The matched
+                // path is useful for figuring out which handler the request was routed to.
+                .make_span_with(move |req: &Request| {
+                    let method = req.method();
+                    let uri = req.uri();
+
+                    // axum automatically adds this extension.
+                    let matched_path = req
+                        .extensions()
+                        .get::<MatchedPath>()
+                        .map(axum::extract::MatchedPath::as_str);
+
+                    tracing::info_span!(
+                        "request",
+                        %method,
+                        %uri,
+                        route = matched_path,
+                        instance = %instance_id
+                    )
+                })
+                .on_response(|response: &Response, latency: Duration, _span: &Span| {
+                    info!(
+                        status = response.status().as_u16(),
+                        latency_ms = latency.as_millis(),
+                        "Request finished"
+                    );
+                })
+                // By default `TraceLayer` will log 5xx responses but we're doing our specific
+                // logging of errors so disable that
+                .on_failure(()),
+        )
+        .with_state(state)
+}
+
+/// Runs the self-check, then starts the job worker and serves the API and game routes until the server stops.
+///
+/// # Errors
+/// Fails if the self-check fails, the address can't be bound or the server stops with an error.
+pub async fn run_server(state: AppState) -> anyhow::Result<()> {
+    util::self_check::run(&state)
+        .await
+        .context("Self-check failed, refusing to start")?;
+
+    let listener = tokio::net::TcpListener::bind(&state.config.main.address)
+        .await
+        .context("Listener should always be able to listen!")?;
+    info!(
+        "Listening on {} as instance {}",
+        &state.config.main.address, &state.instance_id
+    );
+
+    // Log lines of the background tasks say which instance they're from, like the ones of requests
+    let span = tracing::info_span!("background", instance = %state.instance_id);
+    tokio::spawn(jobs::run_worker(state.clone()).instrument(span.clone()));
+    tokio::spawn(util::metrics::run_windows(state.clone()).instrument(span.clone()));
+    tokio::spawn(events::run_writer(state.clone()).instrument(span.clone()));
+    tokio::spawn(util::live_leaderboards::run_relay(state.clone()).instrument(span.clone()));
+    tokio::spawn(game::run_ride_workers(state.clone()).instrument(span));
+
+    let tls = state.config.tls.clone();
+    let app = make_router(state);
+
+    let plain = axum::serve(
+        listener,
+        app.clone()
+            .into_make_service_with_connect_info::<SocketAddr>(),
+    );
+    let plain = async {
+        plain
+            .await
+            .context("Server should be able to... well, serve!")
+    };
+    tokio::try_join!(plain, serve_tls(tls, app)).map(|_| ())
+}
+
+#[cfg(feature = "tls")]
+async fn serve_tls(config: Tls, app: Router) -> anyhow::Result<()> {
+    util::tls::serve(config, app).await
+}
+
+#[cfg(not(feature = "tls"))]
+#[allow(clippy::unused_async)]
+async fn serve_tls(config: Tls, _app: Router) -> anyhow::Result<()> {
+    if config.address.is_some() {
+        anyhow::bail!("tls.address is set, but Wavebreaker was built without the tls feature");
+    }
+    Ok(())
+}
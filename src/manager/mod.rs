@@ -1,10 +1,13 @@
 use clap::{ArgAction, Parser, Subcommand};
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
-use redis::AsyncCommands;
-use tracing::instrument;
+use time::{Duration, OffsetDateTime};
+use tracing::{info, instrument};
 
-use crate::AppState;
+use crate::{
+    util::{realm::MAIN_REALM, redis_keys},
+    AppState,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -21,19 +24,68 @@ pub enum Command {
         #[clap(action=ArgAction::Set)]
         new_alias: bool,
     },
+    /// Undoes a song merge, using the ID from the merge log
+    UndoMerge {
+        merge_id: i32,
+    },
+    /// Merges a player into another one, keeping the higher score where both have one
+    MergePlayers {
+        id_to_merge: i32,
+        target: i32,
+    },
     DeleteSong {
         id_to_delete: i32,
     },
     DeleteScore {
         id_to_delete: i32,
     },
+    RestoreSong {
+        id_to_restore: i32,
+    },
+    RestoreScore {
+        id_to_restore: i32,
+    },
+    /// Permanently deletes songs and scores that were deleted more than `older_than_days` days ago
+    PurgeDeleted {
+        #[clap(default_value_t = 30)]
+        older_than_days: i64,
+    },
     RefreshSkillPoints {
         player_to_refresh: i32,
     },
+    /// Recalculates the skill points of every player in every realm, after the `scoring` section of the config
+    /// changed. Best done while the server is stopped.
+    RecalculateSkillPoints,
+    /// Rewrites the data in Redis to the layout this version of Wavebreaker expects
+    MigrateRedis,
+    /// Normalizes the metadata tags and aliases song lookups compare with again, see `util::normalize`
+    NormalizeTags,
+    /// Looks up MusicBrainz metadata for every song that doesn't have any yet, see `util::metadata_backfill`.
+    /// Picks up where the last run left off if it was stopped.
+    BackfillMetadata {
+        /// How many lookups run at once
+        #[clap(long, default_value_t = 4)]
+        workers: usize,
+        /// Milliseconds between starting two lookups
+        #[clap(long, default_value_t = 3000)]
+        interval_ms: u64,
+        /// Start over instead of picking up where the last run left off
+        #[clap(long)]
+        restart: bool,
+    },
+    /// Makes a backup of the database and rankings, see the `backup` section of the config
+    Backup,
+    /// Looks for data that doesn't add up, like rankings of players that don't exist
+    Doctor {
+        /// Fix what was found, instead of only reporting it
+        #[clap(long)]
+        fix: bool,
+    },
 }
 
 //skip state because it has members that don't implement Debug
 #[instrument(name = "cli_command", skip(state))]
+#[allow(clippy::too_many_lines)]
 pub async fn parse_command(command: &Command, state: AppState) -> anyhow::Result<()> {
     match command {
         Command::MergeSongs {
@@ -41,39 +93,126 @@ pub async fn parse_command(command: &Command, state: AppState) -> anyhow::Result
             target,
             new_alias,
         } => {
-            use crate::{models::songs::Song, schema::songs::dsl::*};
+            use crate::models::songs::Song;
 
             let mut conn = state.db.get().await?;
             let mut redis_conn = state.redis.get().await?;
 
-            let to_merge = songs.find(*id_to_merge).first::<Song>(&mut conn).await?;
-            to_merge
+            let to_merge = Song::all()
+                .find(*id_to_merge)
+                .first::<Song>(&mut conn)
+                .await?;
+            let merge = to_merge
                 .merge_into(*target, *new_alias, &mut conn, &mut redis_conn)
-                .await
+                .await?;
+            info!(
+                "Merged song {} into {}, merge ID is {}",
+                merge.source_song_id, merge.target_song_id, merge.id
+            );
+
+            Ok(())
+        }
+        Command::UndoMerge { merge_id } => {
+            use crate::{models::merge_log::MergeLog, schema::merge_log::dsl::*};
+
+            let mut conn = state.db.get().await?;
+            let mut redis_conn = state.redis.get().await?;
+
+            let merge = merge_log
+                .find(*merge_id)
+                .first::<MergeLog>(&mut conn)
+                .await?;
+            merge.undo(&mut conn, &mut redis_conn).await?;
+
+            Ok(())
+        }
+        Command::MergePlayers {
+            id_to_merge,
+            target,
+        } => {
+            use crate::models::players::Player;
+
+            let mut conn = state.db.get().await?;
+            let mut redis_conn = state.redis.get().await?;
+
+            let to_merge = Player::all()
+                .find(*id_to_merge)
+                .first::<Player>(&mut conn)
+                .await?;
+            let redirect = to_merge
+                .merge_into(*target, None, &mut conn, &mut redis_conn)
+                .await?;
+            info!(
+                "Merged player {} into {}",
+                redirect.player_id, redirect.target_id
+            );
+
+            Ok(())
         }
         Command::DeleteSong { id_to_delete } => {
-            use crate::schema::songs::dsl::*;
+            use crate::models::songs::Song;
 
             let mut conn = state.db.get().await?;
             let mut redis_conn = state.redis.get().await?;
 
-            let song = songs
+            let song = Song::all()
                 .find(*id_to_delete)
-                .first::<crate::models::songs::Song>(&mut conn)
+                .first::<Song>(&mut conn)
                 .await?;
-            song.delete(&mut conn, &mut redis_conn).await
+            song.delete(&mut conn, &mut redis_conn).await?;
+
+            Ok(())
         }
         Command::DeleteScore { id_to_delete } => {
-            use crate::schema::scores::dsl::*;
+            use crate::models::scores::Score;
 
             let mut conn = state.db.get().await?;
             let mut redis_conn = state.redis.get().await?;
 
-            let score_to_delete = scores
+            let score_to_delete = Score::all()
                 .find(*id_to_delete)
-                .first::<crate::models::scores::Score>(&mut conn)
+                .first::<Score>(&mut conn)
                 .await?;
-            score_to_delete.delete(&mut conn, &mut redis_conn).await
+            score_to_delete.delete(&mut conn, &mut redis_conn).await?;
+
+            Ok(())
+        }
+        Command::RestoreSong { id_to_restore } => {
+            use crate::{models::songs::Song, schema::songs::dsl::*};
+
+            let mut conn = state.db.get().await?;
+            let mut redis_conn = state.redis.get().await?;
+
+            let song = songs.find(*id_to_restore).first::<Song>(&mut conn).await?;
+            song.restore(&mut conn, &mut redis_conn).await?;
+
+            Ok(())
+        }
+        Command::RestoreScore { id_to_restore } => {
+            use crate::{models::scores::Score, schema::scores::dsl::*};
+
+            let mut conn = state.db.get().await?;
+            let mut redis_conn = state.redis.get().await?;
+
+            let score_to_restore = scores
+                .find(*id_to_restore)
+                .first::<Score>(&mut conn)
+                .await?;
+            score_to_restore.restore(&mut conn, &mut redis_conn).await?;
+
+            Ok(())
+        }
+        Command::PurgeDeleted { older_than_days } => {
+            use crate::models::{scores::Score, songs::Song};
+
+            let mut conn = state.db.get().await?;
+
+            let cutoff = OffsetDateTime::now_utc() - Duration::days(*older_than_days);
+            let purged_scores = Score::purge_deleted(cutoff, &mut conn).await?;
+            let purged_songs = Song::purge_deleted(cutoff, &mut conn).await?;
+            info!("Purged {purged_songs} song(s) and {purged_scores} score(s) deleted before {cutoff}");
+
+            Ok(())
         }
         Command::RefreshSkillPoints { player_to_refresh } => {
             use crate::{models::scores::Score, schema::scores::dsl::*};
@@ -81,16 +220,116 @@ pub async fn parse_command(command: &Command, state: AppState) -> anyhow::Result
             let mut conn = state.db.get().await?;
             let mut redis_conn = state.redis.get().await?;
 
-            let all_player_scores: Vec<Score> = scores
+            //Every realm has its own rankings
+            //The main realm is always refreshed, even if the player has no scores there
+            let mut player_realms: Vec<String> = Score::all()
                 .filter(player_id.eq(player_to_refresh))
-                .load::<Score>(&mut conn)
+                .select(realm)
+                .distinct()
+                .load(&mut conn)
                 .await?;
-
-            //Add skill points of all scores
-            let skill_points: i32 = all_player_scores.iter().map(Score::get_skill_points).sum();
-            redis_conn
-                .zadd::<_, _, _, ()>("leaderboard", player_to_refresh, skill_points)
+            if !player_realms
+                .iter()
+                .any(|score_realm| score_realm == MAIN_REALM)
+            {
+                player_realms.push(MAIN_REALM.to_owned());
+            }
+            for score_realm in player_realms {
+                crate::util::rankings::refresh_player(
+                    *player_to_refresh,
+                    &score_realm,
+                    &mut conn,
+                    &mut redis_conn,
+                )
                 .await?;
+            }
+
+            Ok(())
+        }
+        Command::RecalculateSkillPoints => {
+            let mut conn = state.db.get().await?;
+            let mut redis_conn = state.redis.get().await?;
+
+            let mut realms = vec![MAIN_REALM.to_owned()];
+            realms.extend(
+                state
+                    .config
+                    .main
+                    .realms
+                    .iter()
+                    .map(|realm| realm.name().to_owned()),
+            );
+            let ranked =
+                crate::util::scoring::recalculate_rankings(&realms, &mut conn, &mut redis_conn)
+                    .await?;
+            for (realm, players) in ranked {
+                info!("Realm {realm}: {players} player(s) ranked");
+            }
+
+            Ok(())
+        }
+        Command::NormalizeTags => {
+            use crate::models::{extra_song_info::ExtraSongInfo, song_aliases::SongAlias};
+
+            let mut conn = state.db.get().await?;
+
+            let extra_infos = ExtraSongInfo::normalize_all(&mut conn).await?;
+            let aliases = SongAlias::normalize_all(&mut conn).await?;
+            // Lookups that are cached already keep resolving like before until they expire
+            info!("Normalized {extra_infos} extra info row(s) and {aliases} alias(es)");
+
+            Ok(())
+        }
+        Command::BackfillMetadata {
+            workers,
+            interval_ms,
+            restart,
+        } => {
+            let summary = crate::util::metadata_backfill::run(
+                &state,
+                *workers,
+                std::time::Duration::from_millis(*interval_ms),
+                *restart,
+            )
+            .await?;
+            info!(
+                "Backfill done: {} song(s) matched, {} unmatched and {} skipped for not having scores",
+                summary.matched, summary.unmatched, summary.skipped
+            );
+
+            Ok(())
+        }
+        Command::Backup => {
+            let manifest = crate::backup::run(&state).await?;
+            info!(
+                "Backup {} done, {} table(s) exported",
+                manifest.name,
+                manifest.tables.len()
+            );
+
+            Ok(())
+        }
+        Command::Doctor { fix } => {
+            let report = crate::util::doctor::run(&state, *fix).await?;
+            info!("{}", serde_json::to_string_pretty(&report)?);
+            if report.problems() > 0 && !fix {
+                info!("Run doctor --fix to fix these problems");
+            }
+
+            Ok(())
+        }
+        Command::MigrateRedis => {
+            let mut redis_conn = state.redis.get().await?;
+
+            let old_version = redis_keys::migrate(&mut redis_conn).await?;
+            if old_version == redis_keys::SCHEMA_VERSION {
+                info!("Redis data is already in schema version {old_version}, nothing to do");
+            } else {
+                info!(
+                    "Migrated Redis data from schema version {old_version} to {}",
+                    redis_keys::SCHEMA_VERSION
+                );
+            }
 
             Ok(())
         }
@@ -0,0 +1,138 @@
+use std::fmt::Write;
+
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use crate::schema::api_keys;
+
+/// Every key starts with this, so leaked keys are easy to recognize.
+const KEY_PREFIX: &str = "wbk_";
+
+/// A key a community site uses to access the API with its own quotas, see [`crate::util::api_quota`].
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = api_keys, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKey {
+    pub id: i32,
+    /// Who the key belongs to, e.g. the name of the site
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub requests_per_day: i32,
+    /// Requests allowed within a single minute
+    pub burst_per_minute: i32,
+    /// The moderator who created the key. `None` if they don't exist anymore.
+    pub created_by: Option<i32>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: OffsetDateTime,
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub revoked_at: Option<OffsetDateTime>,
+}
+
+impl ApiKey {
+    /// How a key is stored, so a leaked database doesn't leak working keys.
+    #[must_use]
+    pub fn hash(key: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(key.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Creates a new key with the given quotas.
+    ///
+    /// # Returns
+    /// The key and its plain text, which isn't stored anywhere and can't be shown again.
+    pub async fn create(
+        key_name: &str,
+        per_day: i32,
+        per_minute: i32,
+        creator: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<(Self, String)> {
+        use crate::schema::api_keys::dsl::*;
+
+        let mut bytes = [0u8; 24];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let plain = bytes.iter().fold(KEY_PREFIX.to_owned(), |mut plain, byte| {
+            // Writing into a String can't fail
+            let _ = write!(plain, "{byte:02x}");
+            plain
+        });
+
+        let created = diesel::insert_into(api_keys)
+            .values((
+                name.eq(key_name),
+                key_hash.eq(Self::hash(&plain)),
+                requests_per_day.eq(per_day),
+                burst_per_minute.eq(per_minute),
+                created_by.eq(creator),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await?;
+
+        Ok((created, plain))
+    }
+
+    /// Finds the key with that plain text, unless it was revoked.
+    pub async fn find_active(
+        plain: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::api_keys::dsl::*;
+
+        api_keys
+            .filter(key_hash.eq(Self::hash(plain)))
+            .filter(revoked_at.is_null())
+            .select(Self::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Gets all keys, including revoked ones, newest first.
+    pub async fn all(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::api_keys::dsl::*;
+
+        api_keys
+            .order(id.desc())
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Changes the quotas of the key. They apply from the next request on.
+    pub async fn set_quotas(
+        &self,
+        per_day: i32,
+        per_minute: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::api_keys::dsl::*;
+
+        diesel::update(self)
+            .set((
+                requests_per_day.eq(per_day),
+                burst_per_minute.eq(per_minute),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// Revokes the key, requests using it are rejected from now on. Revoking it again changes nothing.
+    pub async fn revoke(&self, conn: &mut AsyncPgConnection) -> QueryResult<Self> {
+        use crate::schema::api_keys::dsl::*;
+
+        diesel::update(self)
+            .set(revoked_at.eq(self.revoked_at.unwrap_or_else(OffsetDateTime::now_utc)))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+    }
+}
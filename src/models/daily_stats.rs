@@ -0,0 +1,259 @@
+use diesel::{
+    dsl,
+    prelude::*,
+    sql_query,
+    sql_types::{Bool, Date as SqlDate, SmallInt, Text, Timestamptz},
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::Serialize;
+use time::{Date, Duration, OffsetDateTime};
+
+use crate::{
+    models::songs::Song,
+    schema::{daily_player_points, daily_song_plays, daily_stats, songs},
+    util::{game_types::League, realm::MAIN_REALM, time_zone::TimeZone, timeseries},
+};
+
+/// What happened on the server on one day, in its time zone. Rolled up by the job worker once the day is over,
+/// see [`DailyStats::roll_up_missing`].
+///
+/// Plays are counted from the clients rides were submitted from, see [`crate::util::client_source`], so there
+/// aren't any while `ride_sources.enabled` is off. The rollups stay when old ride sources are pruned.
+#[derive(Queryable, Selectable, Debug, Serialize)]
+#[diesel(table_name = daily_stats, check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct DailyStats {
+    #[serde(serialize_with = "timeseries::serialize_day")]
+    pub day: Date,
+    pub new_players: i32,
+    /// Players who submitted at least one ride
+    pub active_players: i32,
+    pub plays: i32,
+    pub casual_plays: i32,
+    pub pro_plays: i32,
+    pub elite_plays: i32,
+    #[serde(skip_serializing)]
+    pub rolled_up_at: OffsetDateTime,
+    /// Songs that were new to the server
+    pub new_songs: i32,
+}
+
+/// How often a song was played over some days, see [`DailyStats::most_played`].
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SongPlays {
+    pub song: Song,
+    pub plays: i64,
+}
+
+/// A player's skill points in the main realm at the end of a day they changed on, see
+/// [`DailyPlayerPoints::record`].
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = daily_player_points, check_for_backend(diesel::pg::Pg))]
+pub struct DailyPlayerPoints {
+    pub player_id: i32,
+    pub day: Date,
+    pub skill_points: i64,
+}
+
+impl DailyStats {
+    /// Rolls up every day of the last `backfill_days` days that's over and wasn't rolled up yet, oldest first.
+    /// Days missing in between, like when the job worker was down, are filled in too.
+    ///
+    /// # Returns
+    /// The days that were rolled up, oldest first.
+    pub async fn roll_up_missing(
+        time_zone: TimeZone,
+        backfill_days: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Date>> {
+        use crate::schema::daily_stats::dsl::*;
+
+        let today = time_zone.today(OffsetDateTime::now_utc());
+        let since = today - Duration::days(backfill_days.max(1));
+        let rolled_up: Vec<Date> = daily_stats
+            .filter(day.ge(since))
+            .select(day)
+            .load(conn)
+            .await?;
+
+        let missing = timeseries::missing_days(since, today, &rolled_up);
+        for missing_day in &missing {
+            Self::roll_up(*missing_day, time_zone, conn).await?;
+        }
+        Ok(missing)
+    }
+
+    /// Rolls up one day, replacing what was rolled up for it before.
+    pub async fn roll_up(
+        rolled_day: Date,
+        time_zone: TimeZone,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        let start = time_zone.start_of(rolled_day);
+        let end = time_zone.end_of(rolled_day);
+
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                sql_query(
+                    "INSERT INTO daily_stats \
+                     (day, new_players, new_songs, active_players, plays, casual_plays, pro_plays, elite_plays) \
+                     SELECT $1, \
+                     (SELECT COUNT(*) FROM players WHERE joined_at >= $2 AND joined_at < $3)::INTEGER, \
+                     (SELECT COUNT(*) FROM songs WHERE created_at >= $2 AND created_at < $3)::INTEGER, \
+                     COUNT(DISTINCT scores.player_id)::INTEGER, COUNT(*)::INTEGER, \
+                     (COUNT(*) FILTER (WHERE scores.league = $4))::INTEGER, \
+                     (COUNT(*) FILTER (WHERE scores.league = $5))::INTEGER, \
+                     (COUNT(*) FILTER (WHERE scores.league = $6))::INTEGER \
+                     FROM ride_sources JOIN scores ON scores.id = ride_sources.score_id \
+                     WHERE ride_sources.submitted_at >= $2 AND ride_sources.submitted_at < $3 \
+                     ON CONFLICT (day) DO UPDATE SET new_players = EXCLUDED.new_players, \
+                     new_songs = EXCLUDED.new_songs, \
+                     active_players = EXCLUDED.active_players, plays = EXCLUDED.plays, \
+                     casual_plays = EXCLUDED.casual_plays, pro_plays = EXCLUDED.pro_plays, \
+                     elite_plays = EXCLUDED.elite_plays, rolled_up_at = NOW()",
+                )
+                .bind::<SqlDate, _>(rolled_day)
+                .bind::<Timestamptz, _>(start)
+                .bind::<Timestamptz, _>(end)
+                .bind::<SmallInt, _>(League::Casual)
+                .bind::<SmallInt, _>(League::Pro)
+                .bind::<SmallInt, _>(League::Elite)
+                .execute(conn)
+                .await?;
+
+                diesel::delete(daily_song_plays::table.filter(daily_song_plays::day.eq(rolled_day)))
+                    .execute(conn)
+                    .await?;
+                sql_query(
+                    "INSERT INTO daily_song_plays (day, song_id, plays) \
+                     SELECT $1, scores.song_id, COUNT(*)::INTEGER \
+                     FROM ride_sources JOIN scores ON scores.id = ride_sources.score_id \
+                     WHERE ride_sources.submitted_at >= $2 AND ride_sources.submitted_at < $3 \
+                     GROUP BY scores.song_id",
+                )
+                .bind::<SqlDate, _>(rolled_day)
+                .bind::<Timestamptz, _>(start)
+                .bind::<Timestamptz, _>(end)
+                .execute(conn)
+                .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Gets the rollups of the days since `since`, oldest first. Days that weren't rolled up are missing.
+    pub async fn since(since: Date, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::daily_stats::dsl::*;
+
+        daily_stats
+            .filter(day.ge(since))
+            .order(day)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Gets the songs played most since `since`, most first.
+    pub async fn most_played(
+        since: Date,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<SongPlays>> {
+        let plays = dsl::sum(daily_song_plays::plays);
+        let most_played: Vec<(Song, Option<i64>)> = Song::all()
+            .inner_join(daily_song_plays::table)
+            .filter(daily_song_plays::day.ge(since))
+            .group_by(songs::id)
+            .select((Song::as_select(), plays))
+            .order((plays.desc(), songs::id))
+            .limit(limit)
+            .load(conn)
+            .await?;
+
+        Ok(most_played
+            .into_iter()
+            .map(|(song, plays)| SongPlays {
+                song,
+                plays: plays.unwrap_or_default(),
+            })
+            .collect())
+    }
+}
+
+impl DailyPlayerPoints {
+    /// Records the skill points of the players whose points changed on the day, adding up their entries in the
+    /// skill point ledger (see [`crate::models::skill_point_ledger`]) up to the end of it, so any day that's over can
+    /// be recorded, not only the one that just ended. The first time, everyone with skill points is recorded, so
+    /// there's something to start from.
+    ///
+    /// # Returns
+    /// How many players were recorded.
+    pub async fn record(
+        recorded_day: Date,
+        time_zone: TimeZone,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        use crate::schema::daily_player_points::dsl::*;
+
+        let start = time_zone.start_of(recorded_day);
+        let end = time_zone.end_of(recorded_day);
+
+        let first_time = daily_player_points
+            .select(player_id)
+            .first::<i32>(conn)
+            .await
+            .optional()?
+            .is_none();
+        sql_query(
+            "INSERT INTO daily_player_points (player_id, day, skill_points) \
+             SELECT player_id, $1, SUM(delta) FROM skill_point_ledger \
+             WHERE realm = $2 AND created_at < $4 \
+             GROUP BY player_id \
+             HAVING $5 OR BOOL_OR(created_at >= $3) \
+             ON CONFLICT (player_id, day) DO UPDATE SET skill_points = EXCLUDED.skill_points",
+        )
+        .bind::<SqlDate, _>(recorded_day)
+        .bind::<Text, _>(MAIN_REALM)
+        .bind::<Timestamptz, _>(start)
+        .bind::<Timestamptz, _>(end)
+        .bind::<Bool, _>(first_time)
+        .execute(conn)
+        .await
+    }
+
+    /// Gets the player's skill points on the days since `since` they changed on, oldest first, along with the
+    /// last ones before that.
+    pub async fn since(
+        player: i32,
+        since: Date,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        use crate::schema::daily_player_points::dsl::*;
+
+        let before: Option<Self> = daily_player_points
+            .filter(player_id.eq(player))
+            .filter(day.lt(since))
+            .order(day.desc())
+            .select(Self::as_select())
+            .first(conn)
+            .await
+            .optional()?;
+        let mut points: Vec<Self> = before.into_iter().collect();
+        points.extend(
+            daily_player_points
+                .filter(player_id.eq(player))
+                .filter(day.ge(since))
+                .order(day)
+                .select(Self::as_select())
+                .load::<Self>(conn)
+                .await?,
+        );
+        Ok(points)
+    }
+}
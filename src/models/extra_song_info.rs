@@ -1,8 +1,22 @@
-use diesel::prelude::*;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+};
+
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Bool, Integer, Nullable, Text},
+};
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use serde::Serialize;
+use time::OffsetDateTime;
 
-use crate::schema::extra_song_info;
+use super::songs::Song;
+use crate::{
+    schema::{extra_song_info, songs},
+    util::normalize::normalize_tag,
+};
 
 /// Used for storing additional metadata from [MusicBrainz](https://musicbrainz.org).
 /// This lets us display fancy stuff™ on the song page.
@@ -34,10 +48,155 @@ pub struct ExtraSongInfo {
     /// For songs that have been mistagged by the automatic lookup
     /// A value of `true`, prevents any new metadata lookups by title
     pub mistag_lock: bool,
-    /// Alternative artist tags that can be matched to this song
-    pub aliases_artist: Option<Vec<Option<String>>>,
-    /// Alternative title tags that can be matched to this song
-    pub aliases_title: Option<Vec<Option<String>>>,
+    /// `musicbrainz_title` normalized for song lookups, see [`crate::util::normalize`]
+    #[serde(skip_serializing)]
+    pub lookup_title: Option<String>,
+    /// `musicbrainz_artist` normalized for song lookups, see [`crate::util::normalize`]
+    #[serde(skip_serializing)]
+    pub lookup_artist: Option<String>,
+    /// The release the cover is from
+    pub release_mbid: Option<String>,
+    /// The album the song is on, see [`AlbumProgress`]
+    pub release_group_mbid: Option<String>,
+    /// Every artist credited on the recording, see [`ExtraSongInfo::songs_by_artist`]
+    pub artist_mbids: Vec<Option<String>>,
+    pub release_group_title: Option<String>,
+    /// How many tracks the release has, on all its media
+    pub release_track_count: Option<i32>,
+    /// The dominant colors of the cover as `#rrggbb`, most common first, see [`crate::util::cover_colors`].
+    /// Empty until they're extracted.
+    pub cover_colors: Vec<Option<String>>,
+    /// The cover the colors are from
+    #[serde(skip_serializing)]
+    pub cover_colors_source: Option<String>,
+    /// When the preview was last looked up, `None` if it never was
+    #[serde(skip_serializing)]
+    pub preview_checked_at: Option<OffsetDateTime>,
+    /// The track on Deezer with a 30 second preview of the song, see [`crate::util::previews`]
+    pub deezer_track_id: Option<i64>,
+}
+
+impl ExtraSongInfo {
+    /// Gets the songs in the realm a [MusicBrainz](https://musicbrainz.org) artist is credited on,
+    /// however they were tagged. Only songs with metadata from MusicBrainz can be found, ordered by title.
+    #[allow(clippy::doc_markdown)]
+    pub async fn songs_by_artist(
+        artist_mbid: &str,
+        in_realm: &str,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(Song, Self)>> {
+        use crate::schema::extra_song_info::dsl::*;
+
+        Song::all()
+            .inner_join(extra_song_info)
+            .filter(songs::realm.eq(in_realm))
+            .filter(artist_mbids.contains(vec![Some(artist_mbid)]))
+            .order((songs::title, songs::id))
+            .limit(limit)
+            .select((Song::as_select(), Self::as_select()))
+            .load(conn)
+            .await
+    }
+
+    /// Gets the songs in the realm that are on an album, a [MusicBrainz](https://musicbrainz.org) release group.
+    /// Ordered by title, as track numbers aren't stored.
+    #[allow(clippy::doc_markdown)]
+    pub async fn songs_on_album(
+        album_mbid: &str,
+        in_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(Song, Self)>> {
+        use crate::schema::extra_song_info::dsl::*;
+
+        Song::all()
+            .inner_join(extra_song_info)
+            .filter(songs::realm.eq(in_realm))
+            .filter(release_group_mbid.eq(album_mbid))
+            .order((songs::title, songs::id))
+            .select((Song::as_select(), Self::as_select()))
+            .load(conn)
+            .await
+    }
+
+    /// Normalizes the lookup title and artist of all extra info again, e.g. after the rules changed.
+    ///
+    /// # Returns
+    /// How many rows were changed.
+    pub async fn normalize_all(conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        use crate::schema::extra_song_info::dsl::*;
+
+        let all: Vec<Self> = extra_song_info.select(Self::as_select()).load(conn).await?;
+
+        let mut changed = 0;
+        for info in all {
+            let new_title = info.musicbrainz_title.as_deref().map(normalize_tag);
+            let new_artist = info.musicbrainz_artist.as_deref().map(normalize_tag);
+            if new_title == info.lookup_title && new_artist == info.lookup_artist {
+                continue;
+            }
+
+            diesel::update(&info)
+                .set((lookup_title.eq(new_title), lookup_artist.eq(new_artist)))
+                .execute(conn)
+                .await?;
+            changed += 1;
+        }
+
+        Ok(changed)
+    }
+
+    /// Deletes this `ExtraSongInfo` record from the database.
+    pub async fn delete(&self, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        diesel::delete(self).execute(conn).await?;
+        Ok(())
+    }
+}
+
+/// Metadata a moderator sets by hand, fields that are `None` are left as they are.
+#[derive(AsChangeset, Debug, Default)]
+#[diesel(table_name = extra_song_info)]
+pub struct MetadataEdit {
+    pub musicbrainz_title: Option<String>,
+    pub musicbrainz_artist: Option<String>,
+    pub cover_url: Option<String>,
+    pub cover_url_small: Option<String>,
+    lookup_title: Option<String>,
+    lookup_artist: Option<String>,
+}
+
+impl MetadataEdit {
+    /// The lookup title and artist are normalized from the given ones.
+    #[must_use]
+    pub fn new(
+        musicbrainz_title: Option<String>,
+        musicbrainz_artist: Option<String>,
+        cover_url: Option<String>,
+        cover_url_small: Option<String>,
+    ) -> Self {
+        Self {
+            lookup_title: musicbrainz_title.as_deref().map(normalize_tag),
+            lookup_artist: musicbrainz_artist.as_deref().map(normalize_tag),
+            musicbrainz_title,
+            musicbrainz_artist,
+            cover_url,
+            cover_url_small,
+        }
+    }
+
+    /// The columns the edit changes.
+    #[must_use]
+    pub fn fields(&self) -> Vec<&'static str> {
+        [
+            ("musicbrainz_title", self.musicbrainz_title.is_some()),
+            ("musicbrainz_artist", self.musicbrainz_artist.is_some()),
+            ("cover_url", self.cover_url.is_some()),
+            ("cover_url_small", self.cover_url_small.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(field, set)| set.then_some(field))
+        .collect()
+    }
 }
 
 /// Used for inserting additional metadata from [MusicBrainz](https://musicbrainz.org).
@@ -52,14 +211,15 @@ pub struct NewExtraSongInfo {
     pub musicbrainz_title: Option<String>,
     pub musicbrainz_artist: Option<String>,
     pub musicbrainz_length: Option<i32>,
-    pub aliases_title: Option<Vec<String>>,
-    pub aliases_artist: Option<Vec<String>>,
+    pub lookup_title: Option<String>,
+    pub lookup_artist: Option<String>,
 }
 
 impl NewExtraSongInfo {
+    /// The lookup title and artist are normalized from the ones from [MusicBrainz](https://musicbrainz.org).
     #[must_use]
     #[allow(clippy::too_many_arguments)] // Too bad, I don't care!
-    pub const fn new(
+    pub fn new(
         song_id: i32,
         cover_url: Option<String>,
         cover_url_small: Option<String>,
@@ -67,19 +227,17 @@ impl NewExtraSongInfo {
         musicbrainz_title: Option<String>,
         musicbrainz_artist: Option<String>,
         musicbrainz_length: Option<i32>,
-        aliases_title: Option<Vec<String>>,
-        aliases_artist: Option<Vec<String>>,
     ) -> Self {
         Self {
             song_id,
             cover_url,
             cover_url_small,
             mbid,
+            lookup_title: musicbrainz_title.as_deref().map(normalize_tag),
+            lookup_artist: musicbrainz_artist.as_deref().map(normalize_tag),
             musicbrainz_title,
             musicbrainz_artist,
             musicbrainz_length,
-            aliases_title,
-            aliases_artist,
         }
     }
 
@@ -97,3 +255,188 @@ impl NewExtraSongInfo {
             .await
     }
 }
+
+/// How much of an album a player rode.
+///
+/// Albums are [MusicBrainz](https://musicbrainz.org) release groups with one or more editions (releases), like a deluxe
+/// edition with bonus tracks. Progress goes by the edition the player is closest to completing, so bonus tracks don't
+/// count towards the standard edition. Tracks are told apart by their recording MBID, riding a song steep counts for
+/// its track too, and riding a track counts for every edition it's known to be on.
+#[derive(QueryableByName, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[allow(clippy::doc_markdown)]
+pub struct AlbumProgress {
+    /// The MBID of the release group
+    #[diesel(sql_type = Text)]
+    pub mbid: String,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub title: Option<String>,
+    /// How many tracks the edition has. `None` if MusicBrainz didn't say.
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub track_count: Option<i32>,
+    /// How many of the album's tracks are known to the server, on all editions
+    #[diesel(sql_type = BigInt)]
+    pub known_tracks: i64,
+    /// How many of the edition's tracks the player rode
+    #[diesel(sql_type = BigInt)]
+    pub ridden_tracks: i64,
+    /// Whether the player rode every track of the edition, never if the track count is unknown
+    #[diesel(sql_type = Bool)]
+    pub complete: bool,
+}
+
+impl AlbumProgress {
+    /// Gets the albums in the realm the player rode a song of, the most ridden first.
+    pub async fn for_player(
+        player: i32,
+        in_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        sql_query(
+            "WITH ridden AS ( \
+                 SELECT DISTINCT info.mbid, info.release_group_mbid FROM scores \
+                 JOIN songs ON songs.id = scores.song_id \
+                 JOIN extra_song_info info ON info.song_id = songs.id \
+                 WHERE scores.player_id = $1 AND scores.deleted_at IS NULL \
+                 AND songs.deleted_at IS NULL AND songs.realm = $2 \
+                 AND info.mbid IS NOT NULL AND info.release_group_mbid IS NOT NULL \
+             ), editions AS ( \
+                 SELECT info.release_group_mbid AS mbid, \
+                 min(min(info.release_group_title)) OVER (PARTITION BY info.release_group_mbid) AS title, \
+                 min(info.release_track_count) AS track_count, \
+                 count(DISTINCT info.mbid) FILTER (WHERE info.mbid IN (SELECT mbid FROM ridden)) AS ridden_tracks \
+                 FROM extra_song_info info \
+                 JOIN songs ON songs.id = info.song_id \
+                 WHERE songs.deleted_at IS NULL AND songs.realm = $2 \
+                 AND info.release_group_mbid IN (SELECT release_group_mbid FROM ridden) \
+                 GROUP BY info.release_group_mbid, info.release_mbid \
+             ) \
+             SELECT album.mbid, album.title, album.track_count, album.ridden_tracks, album.complete, \
+             (SELECT count(DISTINCT known.mbid) FROM extra_song_info known \
+              JOIN songs ON songs.id = known.song_id \
+              WHERE known.release_group_mbid = album.mbid AND songs.deleted_at IS NULL AND songs.realm = $2 \
+             ) AS known_tracks \
+             FROM ( \
+                 SELECT DISTINCT ON (mbid) mbid, title, track_count, ridden_tracks, \
+                 COALESCE(ridden_tracks >= track_count, false) AS complete \
+                 FROM editions WHERE ridden_tracks > 0 \
+                 ORDER BY mbid, complete DESC, ridden_tracks DESC, track_count NULLS LAST \
+             ) album \
+             ORDER BY album.ridden_tracks DESC, album.mbid",
+        )
+        .bind::<Integer, _>(player)
+        .bind::<Text, _>(in_realm)
+        .load(conn)
+        .await
+    }
+
+    /// Works out the progress like [`AlbumProgress::for_player`] does, from the metadata of the album's songs and
+    /// whether the player rode them.
+    pub fn from_songs<'a>(
+        mbid: String,
+        title: Option<String>,
+        songs: impl IntoIterator<Item = (&'a ExtraSongInfo, bool)> + Clone,
+    ) -> Self {
+        let ridden: HashSet<&str> = songs
+            .clone()
+            .into_iter()
+            .filter(|(_, ridden)| *ridden)
+            .filter_map(|(info, _)| info.mbid.as_deref())
+            .collect();
+        let mut known: HashSet<&str> = HashSet::new();
+        // Track count and ridden tracks of every edition
+        let mut editions: HashMap<Option<&str>, (Option<i32>, HashSet<&str>)> = HashMap::new();
+        for (info, _) in songs {
+            let Some(recording) = info.mbid.as_deref() else {
+                continue;
+            };
+            known.insert(recording);
+            let (track_count, ridden_tracks) =
+                editions.entry(info.release_mbid.as_deref()).or_default();
+            *track_count = track_count
+                .into_iter()
+                .chain(info.release_track_count)
+                .min();
+            if ridden.contains(recording) {
+                ridden_tracks.insert(recording);
+            }
+        }
+
+        let (track_count, ridden_tracks) = editions
+            .into_values()
+            .map(|(track_count, ridden_tracks)| {
+                (
+                    track_count,
+                    i64::try_from(ridden_tracks.len()).unwrap_or_default(),
+                )
+            })
+            .max_by_key(|&(track_count, ridden_tracks)| {
+                (
+                    is_complete(track_count, ridden_tracks),
+                    ridden_tracks,
+                    Reverse(track_count.unwrap_or(i32::MAX)),
+                )
+            })
+            .unwrap_or_default();
+        Self {
+            mbid,
+            title,
+            track_count,
+            known_tracks: i64::try_from(known.len()).unwrap_or_default(),
+            ridden_tracks,
+            complete: is_complete(track_count, ridden_tracks),
+        }
+    }
+}
+
+fn is_complete(track_count: Option<i32>, ridden_tracks: i64) -> bool {
+    track_count.is_some_and(|track_count| ridden_tracks >= i64::from(track_count))
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(recording: &str, release: &str, track_count: i32) -> ExtraSongInfo {
+        ExtraSongInfo {
+            mbid: Some(recording.to_owned()),
+            release_mbid: Some(release.to_owned()),
+            release_track_count: Some(track_count),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_album_progress_from_songs() {
+        // Two tracks on the standard edition, the deluxe edition has them and a bonus track
+        let songs = [
+            (track("a", "standard", 2), true),
+            (track("b", "standard", 2), true),
+            (track("a", "deluxe", 3), false),
+            (track("c", "deluxe", 3), true),
+        ];
+        let progress = AlbumProgress::from_songs(
+            "album".to_owned(),
+            None,
+            songs.iter().map(|(info, ridden)| (info, *ridden)),
+        );
+        // Riding "a" as the standard edition's song counts for the deluxe edition too
+        assert_eq!(progress.track_count, Some(2));
+        assert_eq!(progress.ridden_tracks, 2);
+        assert_eq!(progress.known_tracks, 3);
+        assert!(progress.complete);
+
+        // The bonus track alone doesn't complete anything
+        let progress = AlbumProgress::from_songs(
+            "album".to_owned(),
+            None,
+            songs
+                .iter()
+                .map(|(info, ridden)| (info, *ridden && info.mbid.as_deref() == Some("c"))),
+        );
+        assert_eq!(progress.track_count, Some(3));
+        assert_eq!(progress.ridden_tracks, 1);
+        assert!(!progress.complete);
+    }
+}
@@ -0,0 +1,114 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{
+    schema::gold_thresholds,
+    util::game_types::{Character, League},
+};
+
+/// Thresholds reported fewer times than this aren't trusted enough to check submissions against.
+const MIN_TRUSTED_SUBMISSIONS: i32 = 3;
+/// How far (in percent) a reported threshold may be off from the trusted one.
+/// The same song can be analyzed slightly differently depending on the file.
+const TOLERANCE_PERCENT: i64 = 10;
+
+/// A gold threshold the game reported for a song, league and character, and how often it did.
+/// The game calculates it from the song, so everyone riding the same song should report the same one.
+#[derive(Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = gold_thresholds, check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct GoldThreshold {
+    #[serde(skip_serializing)]
+    pub song_id: i32,
+    pub league: League,
+    pub vehicle: Character,
+    pub gold_threshold: i32,
+    pub submissions: i32,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub last_submitted_at: OffsetDateTime,
+}
+
+impl GoldThreshold {
+    /// Counts a threshold reported by the game.
+    pub async fn record(
+        song: i32,
+        ridden_league: League,
+        character: Character,
+        threshold: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::gold_thresholds::dsl::*;
+
+        diesel::insert_into(gold_thresholds)
+            .values((
+                song_id.eq(song),
+                league.eq(ridden_league),
+                vehicle.eq(character),
+                gold_threshold.eq(threshold),
+            ))
+            .on_conflict((song_id, league, vehicle, gold_threshold))
+            .do_update()
+            .set((
+                submissions.eq(submissions + 1),
+                last_submitted_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gets the consensus threshold of every league and character the song was ridden with,
+    /// which is the one reported most often.
+    pub async fn consensus_for_song(
+        song: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        use crate::schema::gold_thresholds::dsl::*;
+
+        gold_thresholds
+            .filter(song_id.eq(song))
+            .distinct_on((league, vehicle))
+            .order_by((
+                league,
+                vehicle,
+                submissions.desc(),
+                last_submitted_at.desc(),
+            ))
+            .load(conn)
+            .await
+    }
+
+    /// Gets the consensus threshold of the song for one league and character, see [`Self::consensus_for_song`].
+    pub async fn consensus(
+        song: i32,
+        ridden_league: League,
+        character: Character,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::gold_thresholds::dsl::*;
+
+        gold_thresholds
+            .filter(song_id.eq(song))
+            .filter(league.eq(ridden_league))
+            .filter(vehicle.eq(character))
+            .order_by((submissions.desc(), last_submitted_at.desc()))
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Whether a reported threshold is believable, going by this consensus.
+    /// Always true if the consensus isn't backed by enough submissions yet.
+    #[must_use]
+    pub fn is_plausible(&self, reported: i32) -> bool {
+        if self.submissions < MIN_TRUSTED_SUBMISSIONS {
+            return true;
+        }
+
+        let difference = (i64::from(reported) - i64::from(self.gold_threshold)).abs();
+        difference * 100 <= i64::from(self.gold_threshold) * TOLERANCE_PERCENT
+    }
+}
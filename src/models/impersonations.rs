@@ -0,0 +1,98 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::schema::impersonations;
+
+/// A staff member using the API as a player, to see what the player sees when debugging their issues.
+///
+/// Every one is kept, so it's clear who looked at whose account when and why.
+/// The token handed out for it only works while the impersonation is active, see [`crate::util::jwt::Claims`].
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = impersonations, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct Impersonation {
+    pub id: i32,
+    pub staff_id: i32,
+    pub player_id: i32,
+    pub reason: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub started_at: OffsetDateTime,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub expires_at: OffsetDateTime,
+    /// When it was ended early by staff, if it was
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub ended_at: Option<OffsetDateTime>,
+}
+
+impl Impersonation {
+    /// Records that `staff` impersonates `player` from now until `until`.
+    pub async fn start(
+        staff: i32,
+        player: i32,
+        why: &str,
+        until: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::impersonations::dsl::*;
+
+        diesel::insert_into(impersonations)
+            .values((
+                staff_id.eq(staff),
+                player_id.eq(player),
+                reason.eq(why),
+                expires_at.eq(until),
+            ))
+            .returning(Self::as_select())
+            .get_result(conn)
+            .await
+    }
+
+    /// Whether the impersonation hasn't expired or been ended yet.
+    pub async fn is_active(id: i32, conn: &mut AsyncPgConnection) -> QueryResult<bool> {
+        use crate::schema::impersonations::dsl::{ended_at, expires_at, impersonations};
+
+        diesel::select(diesel::dsl::exists(
+            impersonations
+                .find(id)
+                .filter(ended_at.is_null())
+                .filter(expires_at.gt(OffsetDateTime::now_utc())),
+        ))
+        .get_result(conn)
+        .await
+    }
+
+    /// Ends an active impersonation early, its token stops working right away.
+    ///
+    /// # Returns
+    /// `None` if there's no active impersonation with that ID.
+    pub async fn end(id: i32, conn: &mut AsyncPgConnection) -> QueryResult<Option<Self>> {
+        use crate::schema::impersonations::dsl::{ended_at, expires_at, impersonations};
+
+        diesel::update(
+            impersonations
+                .find(id)
+                .filter(ended_at.is_null())
+                .filter(expires_at.gt(OffsetDateTime::now_utc())),
+        )
+        .set(ended_at.eq(OffsetDateTime::now_utc()))
+        .returning(Self::as_select())
+        .get_result(conn)
+        .await
+        .optional()
+    }
+
+    /// Gets the most recent impersonations, the newest first.
+    pub async fn recent(limit: i64, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::impersonations::dsl::*;
+
+        impersonations
+            .order(started_at.desc())
+            .limit(limit)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+}
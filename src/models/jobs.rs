@@ -0,0 +1,177 @@
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::Serialize;
+use time::{Duration, OffsetDateTime};
+
+use crate::schema::jobs;
+
+/// Jobs that have been locked for longer than this are assumed to belong to a worker that died
+/// and are picked up again.
+const STALE_LOCK_TIMEOUT: Duration = Duration::minutes(15);
+/// Delay before the first retry of a failed job. Doubles with every attempt.
+const RETRY_BASE_DELAY: Duration = Duration::seconds(30);
+/// Upper bound for the delay between retries.
+const RETRY_MAX_DELAY: Duration = Duration::hours(1);
+
+/// A job in the queue, as stored in the database.
+/// The actual work is described by the payload, which is a serialized [`crate::jobs::Job`].
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = jobs, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedJob {
+    pub id: i32,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub run_at: OffsetDateTime,
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub locked_at: Option<OffsetDateTime>,
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub finished_at: Option<OffsetDateTime>,
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub failed_at: Option<OffsetDateTime>,
+    pub last_error: Option<String>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: OffsetDateTime,
+}
+
+type Pending = diesel::dsl::Filter<
+    diesel::dsl::Filter<jobs::table, diesel::dsl::IsNull<jobs::finished_at>>,
+    diesel::dsl::IsNull<jobs::failed_at>,
+>;
+
+impl QueuedJob {
+    /// Returns a query fragment that selects all jobs that haven't finished or failed for good yet.
+    #[must_use]
+    pub fn pending() -> Pending {
+        jobs::table
+            .filter(jobs::finished_at.is_null())
+            .filter(jobs::failed_at.is_null())
+    }
+
+    /// Takes the next job that's due and locks it, so no other worker picks it up.
+    /// Uses `SKIP LOCKED`, so multiple workers can safely poll the queue at the same time.
+    ///
+    /// # Returns
+    /// The claimed job with its attempt count already increased, or `None` if nothing is due.
+    pub async fn claim_next(conn: &mut AsyncPgConnection) -> QueryResult<Option<Self>> {
+        use crate::schema::jobs::dsl::*;
+
+        conn.transaction(|conn| {
+            async move {
+                let current_time = OffsetDateTime::now_utc();
+
+                let next_job = Self::pending()
+                    .filter(run_at.le(current_time))
+                    .filter(
+                        locked_at
+                            .is_null()
+                            .or(locked_at.lt(current_time - STALE_LOCK_TIMEOUT)),
+                    )
+                    .order(run_at.asc())
+                    .for_update()
+                    .skip_locked()
+                    .first::<Self>(conn)
+                    .await
+                    .optional()?;
+
+                match next_job {
+                    Some(next_job) => diesel::update(&next_job)
+                        .set((locked_at.eq(current_time), attempts.eq(attempts + 1)))
+                        .get_result::<Self>(conn)
+                        .await
+                        .map(Some),
+                    None => Ok(None),
+                }
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Marks the job as successfully finished.
+    pub async fn finish(&self, conn: &mut AsyncPgConnection) -> QueryResult<Self> {
+        use crate::schema::jobs::dsl::*;
+
+        diesel::update(self)
+            .set((
+                finished_at.eq(OffsetDateTime::now_utc()),
+                locked_at.eq(None::<OffsetDateTime>),
+            ))
+            .get_result(conn)
+            .await
+    }
+
+    /// Records a failed attempt. The job is retried later with exponential backoff,
+    /// unless it has run out of attempts, in which case it's marked as failed for good.
+    pub async fn fail(&self, error: &str, conn: &mut AsyncPgConnection) -> QueryResult<Self> {
+        use crate::schema::jobs::dsl::*;
+
+        let current_time = OffsetDateTime::now_utc();
+
+        if self.attempts >= self.max_attempts {
+            diesel::update(self)
+                .set((
+                    failed_at.eq(current_time),
+                    locked_at.eq(None::<OffsetDateTime>),
+                    last_error.eq(error),
+                ))
+                .get_result(conn)
+                .await
+        } else {
+            diesel::update(self)
+                .set((
+                    run_at.eq(current_time + self.retry_delay()),
+                    locked_at.eq(None::<OffsetDateTime>),
+                    last_error.eq(error),
+                ))
+                .get_result(conn)
+                .await
+        }
+    }
+
+    /// How long to wait before trying the job again, based on how many attempts it had so far.
+    fn retry_delay(&self) -> Duration {
+        let exponent = u32::try_from(self.attempts.saturating_sub(1))
+            .unwrap_or(0)
+            .min(16);
+        (RETRY_BASE_DELAY * 2_i32.pow(exponent)).min(RETRY_MAX_DELAY)
+    }
+
+    /// Permanently deletes all jobs that finished before `before`.
+    /// Failed jobs are kept around so they can still be looked at.
+    ///
+    /// # Returns
+    /// The number of jobs that were purged.
+    pub async fn purge_finished(
+        before: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        use crate::schema::jobs::dsl::*;
+
+        diesel::delete(jobs.filter(finished_at.lt(before)))
+            .execute(conn)
+            .await
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = jobs)]
+pub struct NewJob {
+    pub payload: serde_json::Value,
+    pub run_at: OffsetDateTime,
+}
+
+impl NewJob {
+    /// Puts the job in the queue.
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<QueuedJob> {
+        diesel::insert_into(jobs::table)
+            .values(self)
+            .get_result(conn)
+            .await
+    }
+}
@@ -0,0 +1,165 @@
+use diesel::{
+    dsl,
+    prelude::*,
+    sql_query,
+    sql_types::{Integer, Timestamptz},
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{
+    models::players::PlayerPublic,
+    schema::{leaderboard_snapshots, players},
+    util::game_types::League,
+};
+
+/// One place on a song's leaderboard as it was when the snapshot was taken, see [`LeaderboardSnapshot::take`].
+#[derive(Queryable, Selectable, Debug, Serialize)]
+#[diesel(table_name = leaderboard_snapshots, check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardSnapshot {
+    #[serde(skip_serializing)]
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub song_id: i32,
+    pub league: League,
+    /// Shared by all places of the same snapshot
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub taken_at: OffsetDateTime,
+    /// 1 is the top. Tied scores are ordered by who got there first.
+    /// 0 if the leaderboard was empty, that's the only row of the snapshot then.
+    pub placement: i32,
+    /// `None` if the leaderboard was empty
+    #[serde(skip_serializing)]
+    pub player_id: Option<i32>,
+    /// `None` if the leaderboard was empty
+    pub score: Option<i32>,
+}
+
+impl LeaderboardSnapshot {
+    /// Snapshots the top `top` places of every leaderboard of the songs that got new or deleted scores since
+    /// the last snapshot. The other leaderboards didn't change, so their last snapshot still shows them.
+    /// Leaderboards that lost all their scores get an empty snapshot, see [`LeaderboardSnapshot::placement`].
+    ///
+    /// # Returns
+    /// How many places were snapshotted, not counting empty snapshots.
+    pub async fn take(top: i32, conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        use crate::schema::leaderboard_snapshots::dsl::*;
+
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let last_taken: Option<OffsetDateTime> = leaderboard_snapshots
+                    .select(dsl::max(taken_at))
+                    .first(conn)
+                    .await?;
+                let last_taken = last_taken.unwrap_or(OffsetDateTime::UNIX_EPOCH);
+                let now = OffsetDateTime::now_utc();
+
+                sql_query(
+                    "INSERT INTO leaderboard_snapshots (song_id, league, taken_at, placement, player_id, score) \
+                     SELECT DISTINCT scores.song_id, scores.league, $1, 0, NULL::INTEGER, NULL::INTEGER FROM scores \
+                     JOIN songs ON songs.id = scores.song_id \
+                     WHERE songs.deleted_at IS NULL AND scores.deleted_at > $2 AND NOT EXISTS ( \
+                         SELECT 1 FROM scores kept WHERE kept.song_id = scores.song_id \
+                         AND kept.league = scores.league AND kept.deleted_at IS NULL \
+                     )",
+                )
+                .bind::<Timestamptz, _>(now)
+                .bind::<Timestamptz, _>(last_taken)
+                .execute(conn)
+                .await?;
+
+                sql_query(
+            "INSERT INTO leaderboard_snapshots (song_id, league, taken_at, placement, player_id, score) \
+             SELECT song_id, league, $1, placement, player_id, score FROM ( \
+                 SELECT song_id, league, player_id, score, \
+                 ROW_NUMBER() OVER (PARTITION BY song_id, league ORDER BY score DESC, submitted_at) AS placement \
+                 FROM scores WHERE deleted_at IS NULL AND song_id IN ( \
+                     SELECT scores.song_id FROM scores JOIN songs ON songs.id = scores.song_id \
+                     WHERE songs.deleted_at IS NULL AND (scores.submitted_at > $2 OR scores.deleted_at > $2) \
+                 ) \
+             ) ranked WHERE placement <= $3",
+        )
+                .bind::<Timestamptz, _>(now)
+                .bind::<Timestamptz, _>(last_taken)
+                .bind::<Integer, _>(top)
+                .execute(conn)
+                .await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Gets the leaderboard of the song and league as it was at `as_of`, from the last snapshot taken until then.
+    ///
+    /// # Returns
+    /// When that snapshot was taken, `None` if there's none that old, and its places, best first.
+    pub async fn as_of(
+        song: i32,
+        score_league: League,
+        as_of: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<(Option<OffsetDateTime>, Vec<(Self, PlayerPublic)>)> {
+        use crate::schema::leaderboard_snapshots::dsl::*;
+
+        let snapshot_time: Option<OffsetDateTime> = leaderboard_snapshots
+            .filter(song_id.eq(song))
+            .filter(league.eq(score_league))
+            .filter(taken_at.le(as_of))
+            .select(dsl::max(taken_at))
+            .first(conn)
+            .await?;
+        let Some(snapshot_time) = snapshot_time else {
+            return Ok((None, vec![]));
+        };
+
+        // Empty snapshots have no player, so they have no places here
+        let places = leaderboard_snapshots
+            .inner_join(players::table)
+            .filter(song_id.eq(song))
+            .filter(league.eq(score_league))
+            .filter(taken_at.eq(snapshot_time))
+            .order(placement)
+            .select((Self::as_select(), PlayerPublic::as_select()))
+            .load(conn)
+            .await?;
+
+        Ok((Some(snapshot_time), places))
+    }
+
+    /// Gets the record of the song and league whenever it changed, oldest first.
+    /// Only as precise as the snapshots, records that were beaten between two of them are missing.
+    pub async fn records(
+        song: i32,
+        score_league: League,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(Self, PlayerPublic)>> {
+        use crate::schema::leaderboard_snapshots::dsl::*;
+
+        let tops: Vec<(Self, PlayerPublic)> = leaderboard_snapshots
+            .inner_join(players::table)
+            .filter(song_id.eq(song))
+            .filter(league.eq(score_league))
+            .filter(placement.eq(1))
+            .order(taken_at)
+            .select((Self::as_select(), PlayerPublic::as_select()))
+            .load(conn)
+            .await?;
+
+        let mut records: Vec<(Self, PlayerPublic)> = Vec::new();
+        for (top, holder) in tops {
+            let unchanged = records.last().is_some_and(|(record, _)| {
+                record.player_id == top.player_id && record.score == top.score
+            });
+            if !unchanged {
+                records.push((top, holder));
+            }
+        }
+
+        Ok(records)
+    }
+}
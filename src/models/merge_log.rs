@@ -0,0 +1,252 @@
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    models::{
+        extra_song_info::ExtraSongInfo,
+        scores::Score,
+        song_aliases::{AliasKind, SongAlias},
+        songs::Song,
+    },
+    schema::{extra_song_info, merge_log},
+    util::{
+        errors::WavebreakerError,
+        ranking_store::{DeferredRankingStore, RankingStore},
+        rankings,
+    },
+};
+
+/// What happened to one of the merged song's scores.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum ScoreMergeAction {
+    /// The score was moved to the target song.
+    /// If it beat the player's score on the target song, that one got deleted and its plays were absorbed.
+    #[serde(rename_all = "camelCase")]
+    Moved {
+        score_id: i32,
+        replaced_score_id: Option<i32>,
+        absorbed_plays: i32,
+    },
+    /// The player's score on the target song was higher, so this one got deleted
+    /// and its plays were added to the other one.
+    #[serde(rename_all = "camelCase")]
+    Absorbed {
+        score_id: i32,
+        into_score_id: i32,
+        plays: i32,
+    },
+}
+
+/// Everything that was changed by a song merge. Used to undo it.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeManifest {
+    pub scores: Vec<ScoreMergeAction>,
+    /// Artist alias that was added to the target song, if it didn't have it already
+    pub added_artist_alias: Option<String>,
+    /// Title alias that was added to the target song, if it didn't have it already
+    pub added_title_alias: Option<String>,
+    /// If the target song didn't have an `ExtraSongInfo` before and one had to be created for the aliases.
+    /// Only happened before aliases had their own table, kept so those merges can still be undone.
+    #[serde(default)]
+    pub created_extra_info: bool,
+}
+
+/// A record of one song being merged into another.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = merge_log, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct MergeLog {
+    pub id: i32,
+    pub source_song_id: i32,
+    pub target_song_id: i32,
+    /// A [`MergeManifest`], as JSON
+    pub manifest: serde_json::Value,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub merged_at: OffsetDateTime,
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub undone_at: Option<OffsetDateTime>,
+}
+
+impl MergeLog {
+    /// Undoes the merge, using the manifest to restore the original state as well as possible:
+    /// The merged song is restored, moved scores go back to it, deleted scores are restored,
+    /// absorbed plays are taken away again and the aliases added to the target song are removed.
+    ///
+    /// Scores that were improved on the target song after the merge keep their improvements
+    /// when they're moved back. It all happens in one transaction, so it's undone completely or not at all.
+    ///
+    /// # Errors
+    /// Fails if the merge was already undone, the manifest is invalid or something is wrong with the DB or Redis.
+    pub async fn undo(
+        &self,
+        conn: &mut AsyncPgConnection,
+        store: &mut impl RankingStore,
+    ) -> Result<Self, WavebreakerError> {
+        use crate::schema::{merge_log::dsl::undone_at, scores::dsl::*, songs::dsl::songs};
+
+        if self.undone_at.is_some() {
+            return Err(WavebreakerError::MergeAlreadyUndone(self.id));
+        }
+
+        let manifest: MergeManifest = serde_json::from_value(self.manifest.clone())?;
+
+        // Rankings and cached lookups are only changed once everything is committed
+        let (undone, deferred) = conn
+            .transaction::<_, WavebreakerError, _>(|conn| {
+                let manifest = &manifest;
+                async move {
+                    let mut deferred = DeferredRankingStore::default();
+                    let source = songs.find(self.source_song_id).first::<Song>(conn).await?;
+                    source.restore(conn, &mut deferred).await?;
+
+                    // Move scores back before restoring the ones they replaced, otherwise they'd collide
+                    for action in &manifest.scores {
+                        match action {
+                            ScoreMergeAction::Moved {
+                                score_id,
+                                replaced_score_id,
+                                absorbed_plays,
+                            } => {
+                                // Scores are partitioned by song, so the song is always given along with the ID
+                                diesel::update(
+                                    scores
+                                        .find(score_id)
+                                        .filter(song_id.eq(self.target_song_id)),
+                                )
+                                .set((
+                                    song_id.eq(self.source_song_id),
+                                    play_count.eq(play_count - absorbed_plays),
+                                ))
+                                .execute(conn)
+                                .await?;
+                                let moved = scores
+                                    .find(score_id)
+                                    .filter(song_id.eq(self.source_song_id))
+                                    .first::<Score>(conn)
+                                    .await?;
+                                rankings::refresh_player(
+                                    moved.player_id,
+                                    &moved.realm,
+                                    conn,
+                                    &mut deferred,
+                                )
+                                .await?;
+
+                                if let Some(replaced_score_id) = replaced_score_id {
+                                    let replaced = scores
+                                        .find(replaced_score_id)
+                                        .filter(song_id.eq(self.target_song_id))
+                                        .first::<Score>(conn)
+                                        .await?;
+                                    replaced.restore(conn, &mut deferred).await?;
+                                }
+                            }
+                            ScoreMergeAction::Absorbed {
+                                score_id,
+                                into_score_id,
+                                plays,
+                            } => {
+                                diesel::update(
+                                    scores
+                                        .find(into_score_id)
+                                        .filter(song_id.eq(self.target_song_id)),
+                                )
+                                .set(play_count.eq(play_count - plays))
+                                .execute(conn)
+                                .await?;
+
+                                let absorbed = scores
+                                    .find(score_id)
+                                    .filter(song_id.eq(self.source_song_id))
+                                    .first::<Score>(conn)
+                                    .await?;
+                                absorbed.restore(conn, &mut deferred).await?;
+                            }
+                        }
+                    }
+
+                    if let Some(title_alias) = &manifest.added_title_alias {
+                        SongAlias::remove(self.target_song_id, AliasKind::Title, title_alias, conn)
+                            .await?;
+                    }
+                    if let Some(artist_alias) = &manifest.added_artist_alias {
+                        SongAlias::remove(
+                            self.target_song_id,
+                            AliasKind::Artist,
+                            artist_alias,
+                            conn,
+                        )
+                        .await?;
+                    }
+                    if manifest.created_extra_info {
+                        // Merges from before aliases had their own table needed extra info to hold them
+                        let target_extra_info: Option<ExtraSongInfo> = extra_song_info::table
+                            .filter(extra_song_info::song_id.eq(self.target_song_id))
+                            .select(ExtraSongInfo::as_select())
+                            .first::<ExtraSongInfo>(conn)
+                            .await
+                            .optional()?;
+                        if let Some(target_extra_info) =
+                            target_extra_info.filter(|info| info.mbid.is_none())
+                        {
+                            target_extra_info.delete(conn).await?;
+                        }
+                    }
+                    // Lookups of the restored song were resolving to the target through the aliases
+                    Song::invalidate_lookups(self.target_song_id, &mut deferred).await?;
+
+                    let undone = diesel::update(self)
+                        .set(undone_at.eq(OffsetDateTime::now_utc()))
+                        .get_result::<Self>(conn)
+                        .await?;
+                    Ok((undone, deferred))
+                }
+                .scope_boxed()
+            })
+            .await?;
+        deferred.apply(store).await?;
+
+        Ok(undone)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = merge_log)]
+pub struct NewMergeLog {
+    pub source_song_id: i32,
+    pub target_song_id: i32,
+    pub manifest: serde_json::Value,
+}
+
+impl NewMergeLog {
+    /// Creates a new merge log entry from a manifest.
+    ///
+    /// # Errors
+    /// Fails if the manifest can't be serialized.
+    pub fn new(
+        source_song_id: i32,
+        target_song_id: i32,
+        manifest: &MergeManifest,
+    ) -> serde_json::Result<Self> {
+        Ok(Self {
+            source_song_id,
+            target_song_id,
+            manifest: serde_json::to_value(manifest)?,
+        })
+    }
+
+    /// Inserts the merge log entry into the database.
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<MergeLog> {
+        diesel::insert_into(merge_log::table)
+            .values(self)
+            .get_result(conn)
+            .await
+    }
+}
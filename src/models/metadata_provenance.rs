@@ -0,0 +1,157 @@
+use diesel::{prelude::*, upsert::excluded};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::schema::metadata_provenance;
+
+/// The fields of `ExtraSongInfo` that come from [MusicBrainz](https://musicbrainz.org), all set at once by a lookup.
+pub const MUSICBRAINZ_FIELDS: [&str; 11] = [
+    "mbid",
+    "release_mbid",
+    "release_group_mbid",
+    "release_group_title",
+    "release_track_count",
+    "artist_mbids",
+    "musicbrainz_title",
+    "musicbrainz_artist",
+    "musicbrainz_length",
+    "cover_url",
+    "cover_url_small",
+];
+
+/// Where a piece of metadata came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataSource {
+    /// Found by searching for the song's tags, see `Song::auto_add_metadata`
+    AutoMatch,
+    /// The MBID the game sent when looking up the song
+    GameMbid,
+    /// An MBID a player suggested and a moderator approved
+    ManualMbid,
+    /// Set by a moderator by hand
+    AdminEdit,
+    /// Old tags of a song that was merged into this one
+    MergeAlias,
+    /// Old tags of this song from before it was renamed
+    RenameAlias,
+}
+
+impl MetadataSource {
+    const ALL: [Self; 6] = [
+        Self::AutoMatch,
+        Self::GameMbid,
+        Self::ManualMbid,
+        Self::AdminEdit,
+        Self::MergeAlias,
+        Self::RenameAlias,
+    ];
+
+    /// How the source is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::AutoMatch => "auto_match",
+            Self::GameMbid => "game_mbid",
+            Self::ManualMbid => "manual_mbid",
+            Self::AdminEdit => "admin_edit",
+            Self::MergeAlias => "merge_alias",
+            Self::RenameAlias => "rename_alias",
+        }
+    }
+
+    /// How much metadata from the source counts over others'. A field is only overwritten by sources with at least
+    /// the precedence of the one it came from, unless a moderator forces it. Fields edited by hand come first, then
+    /// MBIDs a moderator approved, then everything automatic.
+    #[must_use]
+    pub const fn precedence(self) -> u8 {
+        match self {
+            Self::AdminEdit => 2,
+            Self::ManualMbid => 1,
+            Self::AutoMatch | Self::GameMbid | Self::MergeAlias | Self::RenameAlias => 0,
+        }
+    }
+}
+
+/// Where one field of a song's extra info came from, and when it was set.
+#[derive(Queryable, Selectable, Debug, Serialize)]
+#[diesel(table_name = metadata_provenance, check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataProvenance {
+    #[serde(skip_serializing)]
+    pub song_id: i32,
+    pub field: String,
+    /// See [`MetadataSource`]
+    pub source: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub recorded_at: OffsetDateTime,
+}
+
+impl MetadataProvenance {
+    /// Records that the fields of the song's extra info were just set from `from`.
+    pub async fn record(
+        song: i32,
+        fields: &[&str],
+        from: MetadataSource,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::metadata_provenance::dsl::*;
+
+        let rows: Vec<_> = fields
+            .iter()
+            .map(|updated_field| {
+                (
+                    song_id.eq(song),
+                    field.eq(*updated_field),
+                    source.eq(from.as_str()),
+                )
+            })
+            .collect();
+        diesel::insert_into(metadata_provenance)
+            .values(rows)
+            .on_conflict((song_id, field))
+            .do_update()
+            .set((
+                source.eq(excluded(source)),
+                recorded_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gets the fields of the song that `from` doesn't get to overwrite, because they came from a source with a
+    /// higher [`MetadataSource::precedence`].
+    pub async fn outranking(
+        song: i32,
+        from: MetadataSource,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<String>> {
+        use crate::schema::metadata_provenance::dsl::*;
+
+        let higher: Vec<&str> = MetadataSource::ALL
+            .into_iter()
+            .filter(|other| other.precedence() > from.precedence())
+            .map(MetadataSource::as_str)
+            .collect();
+        metadata_provenance
+            .filter(song_id.eq(song))
+            .filter(source.eq_any(higher))
+            .select(field)
+            .load(conn)
+            .await
+    }
+
+    /// Gets the provenance of all fields of the song that have one.
+    pub async fn for_song(song: i32, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::metadata_provenance::dsl::*;
+
+        metadata_provenance
+            .filter(song_id.eq(song))
+            .order(field)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+}
@@ -0,0 +1,120 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::schema::metadata_suggestions;
+
+/// Where a suggestion is at, stored as [`SuggestionStatus::as_str`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuggestionStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl SuggestionStatus {
+    /// How the status is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Rejected => "rejected",
+        }
+    }
+}
+
+/// A player's suggestion for fixing a song's metadata.
+/// Either the MBID of the recording to take the metadata from, a corrected title and artist, or both.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = metadata_suggestions, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataSuggestion {
+    pub id: i32,
+    pub song_id: i32,
+    pub player_id: i32,
+    pub mbid: Option<String>,
+    pub release_mbid: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    /// A [`SuggestionStatus`], see [`SuggestionStatus::as_str`]
+    pub status: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub suggested_at: OffsetDateTime,
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub resolved_at: Option<OffsetDateTime>,
+    /// The moderator who approved or rejected the suggestion. `None` if they don't exist anymore.
+    pub resolved_by: Option<i32>,
+}
+
+type Pending = diesel::dsl::Filter<
+    metadata_suggestions::table,
+    diesel::dsl::Eq<metadata_suggestions::status, &'static str>,
+>;
+
+impl MetadataSuggestion {
+    /// Suggestions no moderator has looked at yet.
+    #[must_use]
+    pub fn pending() -> Pending {
+        metadata_suggestions::table
+            .filter(metadata_suggestions::status.eq(SuggestionStatus::Pending.as_str()))
+    }
+
+    /// Permanently deletes all suggestions that were approved or rejected before `before`.
+    ///
+    /// # Returns
+    /// The number of suggestions that were purged.
+    pub async fn purge_resolved(
+        before: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        use crate::schema::metadata_suggestions::dsl::*;
+
+        diesel::delete(metadata_suggestions.filter(resolved_at.lt(before)))
+            .execute(conn)
+            .await
+    }
+
+    /// Marks the suggestion as approved or rejected.
+    pub async fn resolve(
+        &self,
+        new_status: SuggestionStatus,
+        moderator_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::metadata_suggestions::dsl::*;
+
+        diesel::update(self)
+            .set((
+                status.eq(new_status.as_str()),
+                resolved_at.eq(OffsetDateTime::now_utc()),
+                resolved_by.eq(moderator_id),
+            ))
+            .get_result(conn)
+            .await
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = metadata_suggestions)]
+pub struct NewMetadataSuggestion<'a> {
+    pub song_id: i32,
+    pub player_id: i32,
+    pub mbid: Option<&'a str>,
+    pub release_mbid: Option<&'a str>,
+    pub title: Option<&'a str>,
+    pub artist: Option<&'a str>,
+}
+
+impl NewMetadataSuggestion<'_> {
+    /// Stores the suggestion. A player suggesting something for a song they already have a pending suggestion for
+    /// is a unique violation.
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<MetadataSuggestion> {
+        diesel::insert_into(metadata_suggestions::table)
+            .values(self)
+            .get_result(conn)
+            .await
+    }
+}
@@ -1,6 +1,38 @@
+pub mod api_keys;
+pub mod daily_stats;
 pub mod extra_song_info;
+pub mod gold_thresholds;
+pub mod impersonations;
+pub mod jobs;
+pub mod leaderboard_snapshots;
+pub mod merge_log;
+pub mod metadata_provenance;
+pub mod metadata_suggestions;
+pub mod news_items;
+pub mod notification_links;
+pub mod pending_songs;
+pub mod player_messages;
+pub mod player_names;
+pub mod player_redirects;
+pub mod player_slugs;
 pub mod players;
+pub mod ride_sources;
+pub mod rival_digests;
 pub mod rivalries;
+pub mod sandbagging_flags;
+pub mod score_appeals;
+pub mod score_removals;
 pub mod scores;
+pub mod scores_archive;
+pub mod server_records;
+pub mod shout_reports;
 pub mod shouts;
+pub mod skill_point_ledger;
+pub mod song_aliases;
+pub mod song_links;
+pub mod song_quarantine;
+pub mod song_requests;
 pub mod songs;
+pub mod traffic_densities;
+pub mod traffic_flags;
+pub mod vehicle_usage;
@@ -0,0 +1,103 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::schema::news_items;
+
+/// What a news item is about. Decides where it goes in the news, see [`crate::util::news`].
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum NewsKind {
+    /// The server is going down soon, shown first
+    Maintenance,
+    /// Something for players to go for, like a song of the week
+    Challenge,
+    Announcement,
+}
+
+impl NewsKind {
+    /// In the order they're shown in.
+    pub const ALL: [Self; 3] = [Self::Maintenance, Self::Challenge, Self::Announcement];
+
+    /// How the kind is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Maintenance => "maintenance",
+            Self::Challenge => "challenge",
+            Self::Announcement => "announcement",
+        }
+    }
+}
+
+/// Something every player sees in the game's news until it expires.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = news_items, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct NewsItem {
+    pub id: i32,
+    /// See [`NewsKind::as_str`]
+    pub kind: String,
+    pub text: String,
+    /// The moderator who posted it. `None` if they don't exist anymore.
+    pub created_by: Option<i32>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: OffsetDateTime,
+    /// `None` if it stays until it's deleted
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+impl NewsItem {
+    /// Posts a news item. The news have to be marked as dirty afterwards, see [`crate::util::news::mark_dirty`].
+    pub async fn create(
+        news_kind: NewsKind,
+        news_text: &str,
+        expires: Option<OffsetDateTime>,
+        creator: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::news_items::dsl::*;
+
+        diesel::insert_into(news_items)
+            .values((
+                kind.eq(news_kind.as_str()),
+                text.eq(news_text),
+                expires_at.eq(expires),
+                created_by.eq(creator),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// Items that haven't expired yet, newest first.
+    pub async fn active(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::news_items::dsl::*;
+
+        news_items
+            .filter(
+                expires_at
+                    .is_null()
+                    .or(expires_at.gt(OffsetDateTime::now_utc())),
+            )
+            .order(id.desc())
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// The latest items, including expired ones, newest first.
+    pub async fn recent(limit: i64, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::news_items::dsl::*;
+
+        news_items
+            .order(id.desc())
+            .limit(limit)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+}
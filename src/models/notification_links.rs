@@ -0,0 +1,277 @@
+use diesel::{prelude::*, upsert::excluded};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use crate::schema::notification_links;
+
+/// Wrong guesses of a code before it stops working, so it can't be guessed by trying them all.
+const MAX_CODE_ATTEMPTS: i32 = 5;
+
+/// Where notifications go, see [`crate::util::notify`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationChannel {
+    Email,
+    /// Direct messages from the server's bot
+    Discord,
+}
+
+impl NotificationChannel {
+    pub const ALL: [Self; 2] = [Self::Email, Self::Discord];
+
+    /// How the channel is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Discord => "discord",
+        }
+    }
+}
+
+/// What a notification is about. Players pick which ones they want per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationKind {
+    /// Someone took the top spot on a leaderboard from the player
+    Dethrone,
+    /// Messages from moderators, like a removed score or an appeal that was decided
+    Moderation,
+}
+
+/// An email address or Discord account a player gets notifications on, once they confirmed it with a code.
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug, Serialize)]
+#[diesel(belongs_to(super::players::Player))]
+#[diesel(table_name = notification_links, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(player_id, channel))]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationLink {
+    #[serde(skip_serializing)]
+    pub player_id: i32,
+    /// See [`NotificationChannel::as_str`]
+    pub channel: String,
+    /// The email address, or the ID of the Discord account
+    pub address: String,
+    #[serde(skip_serializing)]
+    pub code_hash: Option<String>,
+    #[serde(skip_serializing)]
+    pub code_expires_at: Option<OffsetDateTime>,
+    #[serde(skip_serializing)]
+    pub code_attempts: i32,
+    /// `None` until the player entered the code, nothing but the code is sent until then
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub verified_at: Option<OffsetDateTime>,
+    pub notify_dethrones: bool,
+    pub notify_moderation: bool,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: OffsetDateTime,
+}
+
+impl NotificationLink {
+    /// How a code is stored. Codes are short, so the hash only keeps them from being read off the database.
+    #[must_use]
+    pub fn hash_code(player: i32, code: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(player.to_le_bytes());
+        hasher.update(code.trim().as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Links the address to the player, replacing what they had linked on the channel before.
+    /// It has to be confirmed with a code before anything but the code is sent to it, see [`Self::set_code`].
+    pub async fn link(
+        player: i32,
+        on: NotificationChannel,
+        to: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::notification_links::dsl::*;
+
+        diesel::insert_into(notification_links)
+            .values((
+                player_id.eq(player),
+                channel.eq(on.as_str()),
+                address.eq(to),
+            ))
+            .on_conflict((player_id, channel))
+            .do_update()
+            .set((
+                address.eq(excluded(address)),
+                code_hash.eq(None::<String>),
+                code_expires_at.eq(None::<OffsetDateTime>),
+                code_attempts.eq(0),
+                verified_at.eq(None::<OffsetDateTime>),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// Makes `code` the one confirming the link, replacing any code sent before.
+    /// Codes are only made right before they're sent, so they're never stored anywhere but as a hash.
+    ///
+    /// # Returns
+    /// Whether the link still waits for a code, it might have been confirmed, unlinked or pointed elsewhere since.
+    pub async fn set_code(
+        &self,
+        code: &str,
+        valid_until: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<bool> {
+        use crate::schema::notification_links::dsl::*;
+
+        let updated = diesel::update(notification_links.find((self.player_id, &self.channel)))
+            .filter(address.eq(&self.address))
+            .filter(verified_at.is_null())
+            .set((
+                code_hash.eq(Self::hash_code(self.player_id, code)),
+                code_expires_at.eq(valid_until),
+                code_attempts.eq(0),
+            ))
+            .execute(conn)
+            .await?;
+        Ok(updated > 0)
+    }
+
+    /// Gets what the player linked, confirmed or not.
+    pub async fn for_player(player: i32, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::notification_links::dsl::*;
+
+        notification_links
+            .filter(player_id.eq(player))
+            .order(channel.asc())
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    pub async fn find(
+        player: i32,
+        on: NotificationChannel,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::notification_links::dsl::*;
+
+        notification_links
+            .find((player, on.as_str()))
+            .select(Self::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Confirms the link if `code` is the one that was sent, and it's still valid.
+    ///
+    /// # Returns
+    /// The confirmed link, `None` if the code is wrong, expired or was guessed at too often.
+    pub async fn verify(
+        &self,
+        code: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::notification_links::dsl::*;
+
+        // Counting the attempt and checking there are some left is one statement, so guesses made at the same time
+        // can't all get through before the count goes up
+        let expected = diesel::update(notification_links.find((self.player_id, &self.channel)))
+            .filter(code_hash.is_not_null())
+            .filter(code_expires_at.gt(OffsetDateTime::now_utc()))
+            .filter(code_attempts.lt(MAX_CODE_ATTEMPTS))
+            .set(code_attempts.eq(code_attempts + 1))
+            .returning(code_hash)
+            .get_result::<Option<String>>(conn)
+            .await
+            .optional()?
+            .flatten();
+        let Some(expected) = expected else {
+            return Ok(None);
+        };
+        if expected != Self::hash_code(self.player_id, code) {
+            return Ok(None);
+        }
+
+        diesel::update(notification_links.find((self.player_id, &self.channel)))
+            .filter(code_hash.eq(&expected))
+            .set((
+                verified_at.eq(OffsetDateTime::now_utc()),
+                code_hash.eq(None::<String>),
+                code_expires_at.eq(None::<OffsetDateTime>),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+            .optional()
+    }
+
+    /// Picks the notifications the player wants on the channel.
+    ///
+    /// # Returns
+    /// `None` if nothing is linked on the channel.
+    pub async fn set_preferences(
+        player: i32,
+        on: NotificationChannel,
+        dethrones: bool,
+        moderation: bool,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::notification_links::dsl::*;
+
+        diesel::update(notification_links.find((player, on.as_str())))
+            .set((
+                notify_dethrones.eq(dethrones),
+                notify_moderation.eq(moderation),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+            .optional()
+    }
+
+    /// Removes what the player linked on the channel.
+    ///
+    /// # Returns
+    /// Whether anything was linked.
+    pub async fn unlink(
+        player: i32,
+        on: NotificationChannel,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<bool> {
+        use crate::schema::notification_links::dsl::*;
+
+        let deleted = diesel::delete(notification_links.find((player, on.as_str())))
+            .execute(conn)
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    /// Gets the channels the player confirmed and wants this kind of notification on.
+    pub async fn channels_wanting(
+        player: i32,
+        kind: NotificationKind,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<NotificationChannel>> {
+        use crate::schema::notification_links::dsl::*;
+
+        let query = notification_links
+            .filter(player_id.eq(player))
+            .filter(verified_at.is_not_null())
+            .select(channel)
+            .into_boxed();
+        let query = match kind {
+            NotificationKind::Dethrone => query.filter(notify_dethrones),
+            NotificationKind::Moderation => query.filter(notify_moderation),
+        };
+
+        Ok(query
+            .load::<String>(conn)
+            .await?
+            .iter()
+            .filter_map(|name| {
+                NotificationChannel::ALL
+                    .into_iter()
+                    .find(|linked| linked.as_str() == name)
+            })
+            .collect())
+    }
+}
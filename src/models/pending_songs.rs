@@ -0,0 +1,107 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{schema::pending_songs, util::normalize::normalize_tag};
+
+/// Song tags a player looked up but wasn't allowed to create a song for, waiting for a moderator to approve them.
+/// See [`crate::util::song_creation`]. Tags are queued once per realm by their normalized form (see
+/// [`normalize_tag`]), so the same song sent with different casing or spacing is counted as seen again.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = pending_songs, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct PendingSong {
+    pub id: i32,
+    pub realm: String,
+    /// As the game sent it, modifiers included
+    pub title: String,
+    pub artist: String,
+    /// The MusicBrainz recording the game matched, attached to the song once it's approved
+    pub mbid: Option<String>,
+    pub release_mbid: Option<String>,
+    /// The player who looked these tags up first. `None` if they don't exist anymore.
+    pub first_player_id: Option<i32>,
+    pub times_seen: i32,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub first_seen_at: OffsetDateTime,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub last_seen_at: OffsetDateTime,
+}
+
+impl PendingSong {
+    /// Gets the pending songs, the most looked up first.
+    pub async fn queue(limit: i64, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::pending_songs::dsl::*;
+
+        pending_songs
+            .order((times_seen.desc(), first_seen_at))
+            .limit(limit)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Removes the entry, once the song is approved or rejected.
+    pub async fn delete(&self, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        diesel::delete(self).execute(conn).await?;
+        Ok(())
+    }
+
+    /// Removes the entry for the tags (compared normalized), if there is one, because someone allowed to create the song
+    /// just did.
+    pub async fn resolve(
+        in_realm: &str,
+        song_title: &str,
+        song_artist: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::pending_songs::dsl::*;
+
+        diesel::delete(
+            pending_songs
+                .filter(realm.eq(in_realm))
+                .filter(lookup_title.eq(normalize_tag(song_title)))
+                .filter(lookup_artist.eq(normalize_tag(song_artist))),
+        )
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = pending_songs)]
+pub struct NewPendingSong<'a> {
+    pub realm: &'a str,
+    pub title: &'a str,
+    pub artist: &'a str,
+    pub mbid: Option<&'a str>,
+    pub release_mbid: Option<&'a str>,
+    pub first_player_id: i32,
+}
+
+impl NewPendingSong<'_> {
+    /// Queues the tags for moderators.
+    /// If they're queued already (or tags that are the same once normalized), they're only counted as seen again.
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<PendingSong> {
+        use crate::schema::pending_songs::dsl::*;
+
+        diesel::insert_into(pending_songs)
+            .values((
+                self,
+                lookup_title.eq(normalize_tag(self.title)),
+                lookup_artist.eq(normalize_tag(self.artist)),
+            ))
+            .on_conflict((realm, lookup_title, lookup_artist))
+            .do_update()
+            .set((
+                times_seen.eq(times_seen + 1),
+                last_seen_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .returning(PendingSong::as_returning())
+            .get_result(conn)
+            .await
+    }
+}
@@ -0,0 +1,69 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::schema::player_messages;
+
+/// A message for a single player, shown in their game's news once, see [`crate::util::news`].
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug, Serialize)]
+#[diesel(belongs_to(super::players::Player))]
+#[diesel(table_name = player_messages, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerMessage {
+    pub id: i32,
+    pub player_id: i32,
+    pub text: String,
+    /// The moderator who sent it. `None` if the server sent it on its own, or they don't exist anymore.
+    pub created_by: Option<i32>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: OffsetDateTime,
+    /// When the player's game fetched it. `None` while it's still waiting.
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub delivered_at: Option<OffsetDateTime>,
+}
+
+impl PlayerMessage {
+    /// Queues a message for the player, it's shown the next time their game fetches the news.
+    pub async fn send(
+        player: i32,
+        message: &str,
+        sender: Option<i32>,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::player_messages::dsl::*;
+
+        diesel::insert_into(player_messages)
+            .values((
+                player_id.eq(player),
+                text.eq(message),
+                created_by.eq(sender),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// Marks all messages still waiting for the player as delivered.
+    ///
+    /// # Returns
+    /// The messages, oldest first.
+    pub async fn take_pending(player: i32, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::player_messages::dsl::*;
+
+        let mut taken: Vec<Self> = diesel::update(
+            player_messages
+                .filter(player_id.eq(player))
+                .filter(delivered_at.is_null()),
+        )
+        .set(delivered_at.eq(OffsetDateTime::now_utc()))
+        .returning(Self::as_returning())
+        .get_results(conn)
+        .await?;
+        // RETURNING has no order
+        taken.sort_unstable_by_key(|message| message.id);
+
+        Ok(taken)
+    }
+}
@@ -0,0 +1,50 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::schema::player_names;
+
+/// A name a player went by before. Steam names can be changed any time, these keep leaderboards traceable.
+///
+/// Recorded by [`crate::models::players::NewPlayer::create_or_update`] when the name changes on login.
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug, Serialize)]
+#[diesel(belongs_to(super::players::Player))]
+#[diesel(table_name = player_names, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct PreviousName {
+    #[serde(skip_serializing)]
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub player_id: i32,
+    pub username: String,
+    /// When the player changed it
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub used_until: OffsetDateTime,
+}
+
+impl PreviousName {
+    /// Remembers that the player stopped going by `name` just now.
+    pub async fn record(player: i32, name: &str, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        use crate::schema::player_names::dsl::*;
+
+        diesel::insert_into(player_names)
+            .values((player_id.eq(player), username.eq(name)))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Gets the names the player went by before, the most recent first.
+    pub async fn for_player(player: i32, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::player_names::dsl::*;
+
+        player_names
+            .filter(player_id.eq(player))
+            .order(used_until.desc())
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+}
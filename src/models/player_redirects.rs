@@ -0,0 +1,43 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{models::players::SteamIdWrapper, schema::player_redirects};
+
+/// A player that was merged into another one, see [`crate::models::players::Player::merge_into`].
+#[derive(Queryable, Selectable, Insertable, Debug, Serialize)]
+#[diesel(table_name = player_redirects, check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerRedirect {
+    /// The ID the merged player had
+    pub player_id: i32,
+    pub steam_id: SteamIdWrapper,
+    pub username: String,
+    /// The player it was merged into
+    pub target_id: i32,
+    /// The moderator who merged it, `None` if it was merged from the command line
+    pub merged_by: Option<i32>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub merged_at: OffsetDateTime,
+}
+
+impl PlayerRedirect {
+    /// Finds out which player a merged player went to.
+    ///
+    /// # Returns
+    /// `None` if no player with that ID was ever merged.
+    pub async fn target_of(
+        merged_player: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<i32>> {
+        use crate::schema::player_redirects::dsl::*;
+
+        player_redirects
+            .find(merged_player)
+            .select(target_id)
+            .first(conn)
+            .await
+            .optional()
+    }
+}
@@ -0,0 +1,126 @@
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::Serialize;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    models::players::Player,
+    schema::{player_slugs, players},
+    util::errors::WavebreakerError,
+};
+
+/// A slug a player had before, see [`crate::util::slug`].
+///
+/// Old slugs keep leading to the player, so links shared before a change still work,
+/// and nobody else can claim them. The player can take one of their own back.
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug, Serialize)]
+#[diesel(belongs_to(super::players::Player))]
+#[diesel(table_name = player_slugs, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(slug))]
+#[serde(rename_all = "camelCase")]
+pub struct PreviousSlug {
+    pub slug: String,
+    #[serde(skip_serializing)]
+    pub player_id: i32,
+    /// When the player changed it
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub used_until: OffsetDateTime,
+}
+
+impl PreviousSlug {
+    /// Finds out which player had the slug before.
+    ///
+    /// # Returns
+    /// `None` if no player had it before.
+    pub async fn owner_of(
+        old_slug: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<i32>> {
+        use crate::schema::player_slugs::dsl::*;
+
+        player_slugs
+            .find(old_slug)
+            .select(player_id)
+            .first(conn)
+            .await
+            .optional()
+    }
+}
+
+impl Player {
+    /// Changes the player's slug, `None` removes it. The old one is kept as a [`PreviousSlug`].
+    /// `new_slug` has to be in the form [`crate::util::slug::parse_slug`] brings it in.
+    /// The cached player is dropped, so their profile links change right away.
+    ///
+    /// # Errors
+    /// Fails if another player has or had the slug, the player changed theirs less than `cooldown` ago,
+    /// or something is wrong with the DB or Redis.
+    pub async fn set_slug(
+        &self,
+        new_slug: Option<&str>,
+        cooldown: Duration,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<(), WavebreakerError> {
+        if self.slug.as_deref() == new_slug {
+            return Ok(());
+        }
+
+        conn.transaction::<_, WavebreakerError, _>(|conn| {
+            async move {
+                let last_change: Option<OffsetDateTime> = player_slugs::table
+                    .filter(player_slugs::player_id.eq(self.id))
+                    .select(diesel::dsl::max(player_slugs::used_until))
+                    .first(conn)
+                    .await?;
+                if last_change.is_some_and(|at| at + cooldown > OffsetDateTime::now_utc()) {
+                    return Err(WavebreakerError::SlugCooldown);
+                }
+
+                if let Some(new_slug) = new_slug {
+                    let current_owner: Option<i32> = players::table
+                        .filter(players::slug.eq(new_slug))
+                        .select(players::id)
+                        .first(conn)
+                        .await
+                        .optional()?;
+                    let previous_owner = PreviousSlug::owner_of(new_slug, conn).await?;
+                    if current_owner
+                        .into_iter()
+                        .chain(previous_owner)
+                        .any(|owner| owner != self.id)
+                    {
+                        return Err(WavebreakerError::SlugTaken(new_slug.to_owned()));
+                    }
+                    // Taking an old one back
+                    diesel::delete(player_slugs::table.find(new_slug))
+                        .execute(conn)
+                        .await?;
+                }
+
+                if let Some(old_slug) = &self.slug {
+                    diesel::insert_into(player_slugs::table)
+                        .values((
+                            player_slugs::slug.eq(old_slug),
+                            player_slugs::player_id.eq(self.id),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+                // Two players claiming the same slug at once end up with a unique violation here
+                diesel::update(players::table.find(self.id))
+                    .set(players::slug.eq(new_slug))
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        Self::forget_cached(self.steam_id.0, redis_conn).await
+    }
+}
@@ -5,18 +5,36 @@ use diesel::{
     pg::Pg,
     prelude::*,
     serialize::{self, Output, ToSql},
-    sql_types::{SmallInt, Text},
+    sql_query,
+    sql_types::{Integer, SmallInt, Text},
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
 };
-use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use steam_rs::steam_id::SteamId;
+use tracing::{debug, warn};
 
 use super::rivalries::RivalryView;
 use crate::{
-    models::{rivalries::Rivalry, scores::Score},
-    schema::players,
+    models::{
+        player_names::PreviousName, player_redirects::PlayerRedirect, rivalries::Rivalry,
+        scores::Score,
+    },
+    schema::{player_redirects, players, scores},
+    util::{
+        activity,
+        errors::WavebreakerError,
+        game_types::League,
+        i18n::Localization,
+        ranking_store::RankingStore,
+        rankings::{self, RankingMode},
+        realm::MAIN_REALM,
+        redis_keys,
+    },
 };
 
 #[derive(Serialize, Deserialize, AsExpression, FromSqlRow, Debug, PartialEq, Eq)]
@@ -102,19 +120,175 @@ pub struct Player {
     #[serde(deserialize_with = "time::serde::iso8601::deserialize")]
     pub joined_at: time::OffsetDateTime,
     pub avatar_url: String,
+    /// Whether the player's recent rides may be shown publicly, see [`crate::util::activity`].
+    /// Defaulted so tokens issued before this existed can still be read.
+    #[serde(default)]
+    pub share_activity: bool,
+    /// The locale the player picked for the server's texts, see [`crate::util::i18n`].
+    /// `None` if they didn't, then it's picked per request.
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// The time zone the player wants times shown in, see [`crate::util::time_zone`]. Only for clients.
+    #[serde(default)]
+    pub time_zone: Option<String>,
+    /// For profile links, see [`crate::util::slug`]
+    #[serde(default)]
+    pub slug: Option<String>,
 }
 
+/// How long (in seconds) a player stays cached, see [`Player::find_by_steam_id_cached`].
+const PLAYER_CACHE_TTL: u64 = 60 * 10;
+
+/// Moves everything of a merged player over to the player it's merged into, see [`Player::merge_into`].
+/// `$1` is the merged player, `$2` the one it's merged into.
+/// Whatever would clash with what the target already has is left behind, and deleted along with the merged player.
+const MERGE_STATEMENTS: [&str; 42] = [
+    // Of two scores on the same leaderboard, the lower one is deleted and its plays are added to the other one.
+    // On a tie the target's score stays.
+    "UPDATE scores kept SET play_count = kept.play_count + dropped.play_count FROM scores dropped \
+     WHERE kept.player_id = $2 AND dropped.player_id = $1 AND kept.song_id = dropped.song_id \
+     AND kept.league = dropped.league AND kept.deleted_at IS NULL AND dropped.deleted_at IS NULL \
+     AND kept.score >= dropped.score",
+    "UPDATE scores dropped SET deleted_at = now() FROM scores kept \
+     WHERE kept.player_id = $2 AND dropped.player_id = $1 AND kept.song_id = dropped.song_id \
+     AND kept.league = dropped.league AND kept.deleted_at IS NULL AND dropped.deleted_at IS NULL \
+     AND kept.score >= dropped.score",
+    "UPDATE scores kept SET play_count = kept.play_count + dropped.play_count FROM scores dropped \
+     WHERE kept.player_id = $1 AND dropped.player_id = $2 AND kept.song_id = dropped.song_id \
+     AND kept.league = dropped.league AND kept.deleted_at IS NULL AND dropped.deleted_at IS NULL \
+     AND kept.score > dropped.score",
+    "UPDATE scores dropped SET deleted_at = now() FROM scores kept \
+     WHERE kept.player_id = $1 AND dropped.player_id = $2 AND kept.song_id = dropped.song_id \
+     AND kept.league = dropped.league AND kept.deleted_at IS NULL AND dropped.deleted_at IS NULL \
+     AND kept.score > dropped.score",
+    // Deleted scores go along, so they can still be restored
+    "UPDATE scores SET player_id = $2 WHERE player_id = $1",
+    // Archived scores are merged like the live ones above, but the lower one is gone for good.
+    // On a tie the target's score stays.
+    "UPDATE scores_archive kept SET play_count = kept.play_count + dropped.play_count FROM scores_archive dropped \
+     WHERE kept.player_id = $2 AND dropped.player_id = $1 AND kept.song_id = dropped.song_id \
+     AND kept.league = dropped.league AND kept.score >= dropped.score",
+    "UPDATE scores_archive kept SET play_count = kept.play_count + dropped.play_count FROM scores_archive dropped \
+     WHERE kept.player_id = $1 AND dropped.player_id = $2 AND kept.song_id = dropped.song_id \
+     AND kept.league = dropped.league AND kept.score > dropped.score",
+    "DELETE FROM scores_archive dropped USING scores_archive kept \
+     WHERE kept.player_id = $1 AND dropped.player_id = $2 AND kept.song_id = dropped.song_id \
+     AND kept.league = dropped.league AND kept.score > dropped.score",
+    // The lower ones of the merged player are left behind
+    "UPDATE scores_archive mine SET player_id = $2 WHERE player_id = $1 AND NOT EXISTS ( \
+         SELECT 1 FROM scores_archive theirs \
+         WHERE theirs.player_id = $2 AND theirs.song_id = mine.song_id AND theirs.league = mine.league \
+     )",
+    "INSERT INTO rivalries (challenger_id, rival_id, established_at) \
+     SELECT $2, rival_id, established_at FROM rivalries WHERE challenger_id = $1 AND rival_id <> $2 \
+     ON CONFLICT DO NOTHING",
+    "INSERT INTO rivalries (challenger_id, rival_id, established_at) \
+     SELECT challenger_id, $2, established_at FROM rivalries WHERE rival_id = $1 AND challenger_id <> $2 \
+     ON CONFLICT DO NOTHING",
+    "UPDATE shouts SET author_id = $2 WHERE author_id = $1",
+    // The target's digest settings stay if they have any
+    "UPDATE rival_digests SET player_id = $2 WHERE player_id = $1 AND NOT EXISTS ( \
+         SELECT 1 FROM rival_digests theirs WHERE theirs.player_id = $2 \
+     )",
+    "UPDATE shout_reports mine SET reporter_id = $2 WHERE reporter_id = $1 AND NOT EXISTS ( \
+         SELECT 1 FROM shout_reports theirs WHERE theirs.reporter_id = $2 AND theirs.shout_id = mine.shout_id \
+     )",
+    "UPDATE metadata_suggestions mine SET player_id = $2 WHERE player_id = $1 AND NOT (status = 'pending' AND EXISTS ( \
+         SELECT 1 FROM metadata_suggestions theirs \
+         WHERE theirs.player_id = $2 AND theirs.song_id = mine.song_id AND theirs.status = 'pending' \
+     ))",
+    // Requests both of them voted for lose the second vote
+    "UPDATE song_requests SET votes = votes - 1 WHERE id IN ( \
+         SELECT request_id FROM song_request_votes WHERE player_id = $1 \
+         INTERSECT SELECT request_id FROM song_request_votes WHERE player_id = $2 \
+     )",
+    "UPDATE song_request_votes SET player_id = $2 WHERE player_id = $1 AND request_id NOT IN ( \
+         SELECT request_id FROM song_request_votes WHERE player_id = $2 \
+     )",
+    "UPDATE song_requests SET requested_by = $2 WHERE requested_by = $1",
+    "INSERT INTO vehicle_usage (player_id, vehicle, rides, total_score, best_score, last_ridden_at) \
+     SELECT $2, vehicle, rides, total_score, best_score, last_ridden_at FROM vehicle_usage WHERE player_id = $1 \
+     ON CONFLICT (player_id, vehicle) DO UPDATE SET \
+     rides = vehicle_usage.rides + excluded.rides, \
+     total_score = vehicle_usage.total_score + excluded.total_score, \
+     best_score = GREATEST(vehicle_usage.best_score, excluded.best_score), \
+     last_ridden_at = GREATEST(vehicle_usage.last_ridden_at, excluded.last_ridden_at)",
+    "UPDATE songs SET first_rider_id = $2 WHERE first_rider_id = $1",
+    "UPDATE song_quarantine SET first_player_id = $2 WHERE first_player_id = $1",
+    "UPDATE pending_songs SET first_player_id = $2 WHERE first_player_id = $1",
+    "UPDATE song_links SET added_by = $2 WHERE added_by = $1",
+    "UPDATE player_messages SET player_id = $2 WHERE player_id = $1",
+    "UPDATE player_names SET player_id = $2 WHERE player_id = $1",
+    // The merged player's slug keeps leading to them, like the slugs they had before
+    "INSERT INTO player_slugs (slug, player_id) SELECT slug, $2 FROM players WHERE id = $1 AND slug IS NOT NULL",
+    "UPDATE player_slugs SET player_id = $2 WHERE player_id = $1",
+    "UPDATE score_appeals SET player_id = $2 WHERE player_id = $1",
+    "UPDATE score_removals SET player_id = $2 WHERE player_id = $1",
+    "UPDATE score_removals SET moderator_id = $2 WHERE moderator_id = $1",
+    "UPDATE impersonations SET player_id = $2 WHERE player_id = $1",
+    "UPDATE impersonations SET staff_id = $2 WHERE staff_id = $1",
+    // Of two flags in the same realm, the target's one stays
+    "UPDATE sandbagging_flags mine SET player_id = $2 WHERE player_id = $1 AND NOT EXISTS ( \
+         SELECT 1 FROM sandbagging_flags theirs WHERE theirs.player_id = $2 AND theirs.realm = mine.realm \
+     )",
+    "UPDATE sandbagging_flags SET cleared_by = $2 WHERE cleared_by = $1",
+    // Of two flags on the same leaderboard, the target's one stays
+    "UPDATE traffic_flags mine SET player_id = $2 WHERE player_id = $1 AND NOT EXISTS ( \
+         SELECT 1 FROM traffic_flags theirs \
+         WHERE theirs.player_id = $2 AND theirs.song_id = mine.song_id AND theirs.league = mine.league \
+     )",
+    "UPDATE traffic_flags SET cleared_by = $2 WHERE cleared_by = $1",
+    "UPDATE notification_links mine SET player_id = $2 WHERE player_id = $1 AND NOT EXISTS ( \
+         SELECT 1 FROM notification_links theirs WHERE theirs.player_id = $2 AND theirs.channel = mine.channel \
+     )",
+    // The target's rankings are computed again afterwards, which records the difference
+    "UPDATE skill_point_ledger SET player_id = $2 WHERE player_id = $1",
+    "UPDATE leaderboard_snapshots SET player_id = $2 WHERE player_id = $1",
+    // Past days aren't computed again, of two on the same day the higher one stays
+    "INSERT INTO daily_player_points (player_id, day, skill_points) \
+     SELECT $2, day, skill_points FROM daily_player_points WHERE player_id = $1 \
+     ON CONFLICT (player_id, day) DO UPDATE SET \
+     skill_points = GREATEST(daily_player_points.skill_points, excluded.skill_points)",
+    "UPDATE server_records SET player_id = $2 WHERE player_id = $1",
+    // Players that were merged into this one before now lead to the target as well
+    "UPDATE player_redirects SET target_id = $2 WHERE target_id = $1",
+];
+
 // Types for use with functions that return reusable query fragments
 type All = diesel::dsl::Select<players::table, diesel::dsl::AsSelect<Player, diesel::pg::Pg>>;
 type WithSteamId = diesel::dsl::Eq<players::steam_id, SteamIdWrapper>;
 type BySteamId = diesel::dsl::Filter<All, WithSteamId>;
 
 impl Player {
-    /// Returns the total skill points a player has earned with their scores.
+    /// Picks the locale for texts written for the player outside of a request, like messages from moderators.
+    pub async fn locale_of(
+        player: i32,
+        i18n: &Localization,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<String> {
+        use crate::schema::players::dsl::*;
+
+        let preferred: Option<String> = players
+            .find(player)
+            .select(locale)
+            .first(conn)
+            .await
+            .optional()?
+            .flatten();
+        Ok(i18n.negotiate(preferred.as_deref(), None))
+    }
+
+    /// Whether the player is a moderator or a member of the Wavebreaker team.
+    #[must_use]
+    pub fn is_staff(&self) -> bool {
+        self.account_type == AccountType::Moderator || self.account_type == AccountType::Team
+    }
+
+    /// Returns the total skill points a player has earned with their scores, in all realms combined.
     pub async fn get_skill_points(&self, conn: &mut AsyncPgConnection) -> QueryResult<i32> {
-        use crate::schema::scores::dsl::*;
+        use crate::schema::scores::dsl::player_id;
 
-        let player_scores = scores
+        let player_scores = Score::all()
             .filter(player_id.eq(self.id))
             .load::<Score>(conn)
             .await?;
@@ -138,6 +312,52 @@ impl Player {
         Self::all().filter(steam_id.eq(SteamIdWrapper(id_to_find)))
     }
 
+    /// Finds a player by their Steam ID, going through a cache in Redis first.
+    /// Meant for the game routes, which all need the player and are called a lot.
+    ///
+    /// Anything that changes a player has to call [`Player::forget_cached`] afterwards,
+    /// otherwise the old version is used until the cache expires.
+    ///
+    /// # Errors
+    /// Fails if the player doesn't exist or something is wrong with the DB or Redis.
+    pub async fn find_by_steam_id_cached(
+        id_to_find: SteamId,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<Self, WavebreakerError> {
+        let cache_key = redis_keys::player(id_to_find);
+        let cached: Option<String> = redis_conn.get(&cache_key).await?;
+        // Entries that don't deserialize anymore are from an older version, they're just replaced
+        if let Some(player) = cached.and_then(|cached| serde_json::from_str(&cached).ok()) {
+            return Ok(player);
+        }
+
+        let player: Self = Self::find_by_steam_id(id_to_find).first(conn).await?;
+        redis_conn
+            .set_ex::<_, _, ()>(
+                &cache_key,
+                serde_json::to_string(&player)?,
+                PLAYER_CACHE_TTL,
+            )
+            .await?;
+
+        Ok(player)
+    }
+
+    /// Drops the player from the cache, see [`Player::find_by_steam_id_cached`].
+    ///
+    /// # Errors
+    /// Fails if something is wrong with Redis.
+    pub async fn forget_cached(
+        steam_id: SteamId,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<(), WavebreakerError> {
+        redis_conn
+            .del::<_, ()>(redis_keys::player(steam_id))
+            .await?;
+        Ok(())
+    }
+
     /// Returns a query fragment that selects all players.
     #[must_use]
     pub fn all() -> All {
@@ -162,22 +382,128 @@ impl Player {
             .await
     }
 
-    /// Retrieves rivalries, with the date they were established, and the profiles of the rivals.
+    /// Retrieves rivalries, with the date they were established, the profiles of the rivals and whether they're mutual.
     /// This is **not** like `get_rivals`, which only returns a `Vec<Player>` of the rivals and nothing else.
+    /// See [`Rivalry::all_for_player`].
     pub async fn get_rivalry_views(
         &self,
         conn: &mut AsyncPgConnection,
     ) -> QueryResult<Vec<RivalryView>> {
-        use crate::schema::rivalries::dsl::*;
+        Rivalry::all_for_player(self.id, conn).await
+    }
 
-        rivalries
-            .inner_join(
-                crate::schema::players::table.on(rival_id.eq(crate::schema::players::dsl::id)),
-            )
-            .filter(challenger_id.eq(self.id))
-            .select((established_at, PlayerPublic::as_select()))
-            .load::<RivalryView>(conn)
+    /// Merges this player into another one, for people who ended up with two accounts. `self` is deleted when it's done.
+    /// A [`PlayerRedirect`] is left behind, so links to the merged player still lead somewhere.
+    ///
+    /// Their scores, rivalries, shouts etc. go to the target. Where both have a score on the same leaderboard,
+    /// the higher one is kept, see [`MERGE_STATEMENTS`]. The rankings of the target are computed again afterwards.
+    ///
+    /// # Errors
+    /// Fails if the target doesn't exist, is the same player, or something is wrong with the DB or Redis.
+    pub async fn merge_into(
+        &self,
+        target: i32,
+        moderator: Option<i32>,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<PlayerRedirect, WavebreakerError> {
+        if target == self.id {
+            return Err(WavebreakerError::SelfMerge);
+        }
+        let target = Self::all()
+            .find(target)
+            .first::<Self>(conn)
             .await
+            .optional()?
+            .ok_or(WavebreakerError::NotFound("Merge target player"))?;
+
+        let mut realms: Vec<String> = scores::table
+            .filter(scores::player_id.eq_any([self.id, target.id]))
+            .select(scores::realm)
+            .distinct()
+            .load(conn)
+            .await?;
+        if !realms.iter().any(|realm| realm == MAIN_REALM) {
+            realms.push(MAIN_REALM.to_owned());
+        }
+        // The leaderboards the merged player is on are the ones that change
+        let leaderboards: Vec<(i32, League)> = scores::table
+            .filter(scores::player_id.eq(self.id))
+            .select((scores::song_id, scores::league))
+            .distinct()
+            .load(conn)
+            .await?;
+
+        debug!("Merging player {} into {}", self.id, target.id);
+
+        let redirect = conn
+            .transaction(|conn| {
+                async move {
+                    for statement in MERGE_STATEMENTS {
+                        sql_query(statement)
+                            .bind::<Integer, _>(self.id)
+                            .bind::<Integer, _>(target.id)
+                            .execute(conn)
+                            .await?;
+                    }
+
+                    let redirect = diesel::insert_into(player_redirects::table)
+                        .values((
+                            player_redirects::player_id.eq(self.id),
+                            player_redirects::steam_id.eq(&self.steam_id),
+                            player_redirects::username.eq(&self.username),
+                            player_redirects::target_id.eq(target.id),
+                            player_redirects::merged_by.eq(moderator),
+                        ))
+                        .returning(PlayerRedirect::as_returning())
+                        .get_result(conn)
+                        .await?;
+                    diesel::delete(players::table.find(self.id))
+                        .execute(conn)
+                        .await?;
+
+                    Ok::<_, WavebreakerError>(redirect)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        self.forget_merged(&target, &realms, &leaderboards, conn, redis_conn)
+            .await?;
+
+        Ok(redirect)
+    }
+
+    /// Updates what's in Redis after the player was merged into `target`, see [`Player::merge_into`].
+    async fn forget_merged(
+        &self,
+        target: &Self,
+        realms: &[String],
+        leaderboards: &[(i32, League)],
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<(), WavebreakerError> {
+        for (song_id, league) in leaderboards {
+            redis_conn.invalidate_rank_index(*song_id, *league).await?;
+        }
+        for realm in realms {
+            rankings::refresh_player(target.id, realm, conn, redis_conn).await?;
+            let mut pipe = redis::pipe();
+            for mode in RankingMode::ALL {
+                pipe.zrem(mode.key(realm), self.id).ignore();
+            }
+            pipe.query_async::<()>(redis_conn).await?;
+        }
+        Self::forget_cached(self.steam_id.0, redis_conn).await?;
+        Self::forget_cached(target.steam_id.0, redis_conn).await?;
+        if let Err(e) = activity::forget_player(self.id, redis_conn).await {
+            warn!(
+                "Failed to forget the activity of merged player {}: {e:?}",
+                self.id
+            );
+        }
+
+        Ok(())
     }
 }
 
@@ -221,15 +547,25 @@ impl<'a> NewPlayer<'a> {
     }
 
     /// Creates or updates the player in the database.
+    /// The player is added to the rankings of `realm` if they aren't in there yet.
+    /// If their name changed, the old one is kept as a [`PreviousName`].
     ///
     /// # Errors
     /// This fails if:
     /// - The player fails to be inserted/updated in the database
     pub async fn create_or_update(
         &self,
+        realm: &str,
         conn: &mut AsyncPgConnection,
         redis_conn: &mut deadpool_redis::Connection,
-    ) -> anyhow::Result<Player> {
+    ) -> Result<Player, WavebreakerError> {
+        let previous_name: Option<String> = players::table
+            .filter(players::steam_account_num.eq(self.steam_account_num))
+            .select(players::username)
+            .first(conn)
+            .await
+            .optional()?;
+
         // Register player
         // Update info if already registered
         let player_result = diesel::insert_into(players::table)
@@ -242,10 +578,14 @@ impl<'a> NewPlayer<'a> {
             ))
             .get_result::<Player>(conn)
             .await?;
+        if let Some(previous_name) = previous_name.filter(|name| *name != player_result.username) {
+            PreviousName::record(player_result.id, &previous_name, conn).await?;
+        }
+        Player::forget_cached(self.steam_id.0, redis_conn).await?;
 
         // If the player doesn't exist in the Redis sorted set, add them with a score of 0
         redis::cmd("ZADD")
-            .arg("leaderboard")
+            .arg(redis_keys::skill_points(realm))
             .arg("NX")
             .arg(0i32)
             .arg(player_result.id)
@@ -266,6 +606,8 @@ pub struct PlayerPublic {
     #[serde(serialize_with = "time::serde::iso8601::serialize")]
     pub joined_at: time::OffsetDateTime,
     pub avatar_url: String,
+    /// For profile links, see [`crate::util::slug`]
+    pub slug: Option<String>,
 }
 
 impl From<Player> for PlayerPublic {
@@ -276,6 +618,7 @@ impl From<Player> for PlayerPublic {
             account_type: player.account_type,
             joined_at: player.joined_at,
             avatar_url: player.avatar_url,
+            slug: player.slug,
         }
     }
 }
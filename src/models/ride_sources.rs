@@ -0,0 +1,109 @@
+use diesel::{dsl::count_star, prelude::*};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{schema::ride_sources, util::client_source::ClientSource};
+
+/// The client a ride was submitted from, see [`crate::util::client_source`].
+/// One is kept for every ride, under the player's score on the leaderboard it was ridden on,
+/// whether the ride beat that score or not.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = ride_sources, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct RideSource {
+    pub id: i32,
+    pub score_id: i32,
+    pub user_agent: Option<String>,
+    pub fingerprint: String,
+    pub http_version: String,
+    pub region: Option<String>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub submitted_at: OffsetDateTime,
+}
+
+/// How many rides came from one kind of client, see [`RideSource::summarize`].
+#[derive(Queryable, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientSummary {
+    pub user_agent: Option<String>,
+    pub fingerprint: String,
+    pub http_version: String,
+    pub region: Option<String>,
+    pub rides: i64,
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub last_seen_at: Option<OffsetDateTime>,
+}
+
+impl RideSource {
+    /// Remembers which client a ride on the score's leaderboard was submitted from.
+    pub async fn record(
+        score: i32,
+        source: &ClientSource,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::ride_sources::dsl::*;
+
+        diesel::insert_into(ride_sources)
+            .values((
+                score_id.eq(score),
+                user_agent.eq(&source.user_agent),
+                fingerprint.eq(&source.fingerprint),
+                http_version.eq(source.http_version),
+                region.eq(&source.region),
+            ))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Gets the clients the rides on a score's leaderboard were submitted from, the most recent first.
+    pub async fn for_score(score: i32, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::ride_sources::dsl::*;
+
+        ride_sources
+            .filter(score_id.eq(score))
+            .order(submitted_at.desc())
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Counts the rides per kind of client since `since`, the most common first.
+    pub async fn summarize(
+        since: OffsetDateTime,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<ClientSummary>> {
+        use crate::schema::ride_sources::dsl::*;
+
+        ride_sources
+            .filter(submitted_at.ge(since))
+            .group_by((user_agent, fingerprint, http_version, region))
+            .select((
+                user_agent,
+                fingerprint,
+                http_version,
+                region,
+                count_star(),
+                diesel::dsl::max(submitted_at),
+            ))
+            .order(count_star().desc())
+            .limit(limit)
+            .load(conn)
+            .await
+    }
+
+    /// Permanently deletes everything recorded before `before`.
+    ///
+    /// # Returns
+    /// The number of entries that were purged.
+    pub async fn purge(before: OffsetDateTime, conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        use crate::schema::ride_sources::dsl::*;
+
+        diesel::delete(ride_sources.filter(submitted_at.lt(before)))
+            .execute(conn)
+            .await
+    }
+}
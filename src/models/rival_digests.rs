@@ -0,0 +1,215 @@
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Bool, Integer, Nullable, SmallInt, Text, Timestamptz},
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{
+    schema::rival_digests,
+    util::{
+        game_types::League,
+        i18n::{self, Localization},
+    },
+};
+
+/// Most activities a digest lists, the most recent ones.
+const MAX_ACTIVITIES: i64 = 100;
+
+/// Where a player is in catching up with their rivals, see [`RivalActivity`].
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = rival_digests, check_for_backend(diesel::pg::Pg))]
+pub struct RivalDigest {
+    pub player_id: i32,
+    /// When the player last looked at their rivals' activity. `None` if they never did.
+    pub seen_at: Option<OffsetDateTime>,
+    /// Where the daily digest is sent, a Discord webhook (see [`crate::records::is_discord_webhook`])
+    pub webhook_url: Option<String>,
+    /// When the last daily digest was sent
+    pub sent_at: Option<OffsetDateTime>,
+}
+
+impl RivalDigest {
+    /// Gets when the player last looked at their rivals' activity, `None` if they never did.
+    pub async fn seen_at_of(
+        player: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<OffsetDateTime>> {
+        use crate::schema::rival_digests::dsl::*;
+
+        Ok(rival_digests
+            .find(player)
+            .select(seen_at)
+            .first::<Option<OffsetDateTime>>(conn)
+            .await
+            .optional()?
+            .flatten())
+    }
+
+    /// Marks everything the player's rivals did until `until` as seen.
+    pub async fn mark_seen(
+        player: i32,
+        until: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::rival_digests::dsl::*;
+
+        diesel::insert_into(rival_digests)
+            .values((player_id.eq(player), seen_at.eq(until)))
+            .on_conflict(player_id)
+            .do_update()
+            .set(seen_at.eq(until))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Sets where the player's daily digest is sent, `None` to stop sending it.
+    pub async fn set_webhook(
+        player: i32,
+        url: Option<&str>,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::rival_digests::dsl::*;
+
+        diesel::insert_into(rival_digests)
+            .values((player_id.eq(player), webhook_url.eq(url)))
+            .on_conflict(player_id)
+            .do_update()
+            .set(webhook_url.eq(url))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Gets the digests of everyone who wants them sent.
+    pub async fn with_webhooks(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::rival_digests::dsl::*;
+
+        rival_digests
+            .filter(webhook_url.is_not_null())
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Remembers that the digest was sent, covering everything until `sent`.
+    pub async fn mark_sent(
+        &self,
+        sent: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::rival_digests::dsl::*;
+
+        diesel::update(rival_digests.find(self.player_id))
+            .set(sent_at.eq(sent))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+}
+
+/// A score one of the player's rivals submitted: on a song the player rode too, or one that's on top of its leaderboard.
+#[derive(QueryableByName, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RivalActivity {
+    #[diesel(sql_type = Integer)]
+    pub rival_id: i32,
+    #[diesel(sql_type = Text)]
+    pub rival_name: String,
+    #[diesel(sql_type = Integer)]
+    pub score_id: i32,
+    #[diesel(sql_type = Integer)]
+    pub song_id: i32,
+    #[diesel(sql_type = Text)]
+    pub title: String,
+    #[diesel(sql_type = Text)]
+    pub artist: String,
+    #[diesel(sql_type = SmallInt)]
+    pub league: League,
+    #[diesel(sql_type = Integer)]
+    pub score: i32,
+    #[diesel(sql_type = Timestamptz)]
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub submitted_at: OffsetDateTime,
+    /// The player's own score on the song and league, if they have one
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub your_score: Option<i32>,
+    /// Whether the rival's score is better than the player's own
+    #[diesel(sql_type = Bool)]
+    pub beat_you: bool,
+    /// Whether the rival's score is the best on its leaderboard now
+    #[diesel(sql_type = Bool)]
+    pub number_one: bool,
+}
+
+impl RivalActivity {
+    /// Gets what the rivals of the player did since `since`, the most recent first.
+    /// Only the rivals the player added count, whether or not they added the player back.
+    pub async fn since(
+        player: i32,
+        since: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        sql_query(
+            "SELECT * FROM ( \
+                 SELECT theirs.player_id AS rival_id, players.username AS rival_name, theirs.id AS score_id, \
+                 theirs.song_id, songs.title, songs.artist, theirs.league, theirs.score, theirs.submitted_at, \
+                 mine.score AS your_score, COALESCE(mine.score < theirs.score, false) AS beat_you, \
+                 NOT EXISTS ( \
+                     SELECT 1 FROM scores better \
+                     WHERE better.song_id = theirs.song_id AND better.league = theirs.league \
+                     AND better.deleted_at IS NULL AND better.score > theirs.score \
+                 ) AS number_one, \
+                 EXISTS ( \
+                     SELECT 1 FROM scores ridden \
+                     WHERE ridden.player_id = $1 AND ridden.song_id = theirs.song_id AND ridden.deleted_at IS NULL \
+                 ) AS shared \
+                 FROM rivalries \
+                 JOIN scores theirs ON theirs.player_id = rivalries.rival_id \
+                 JOIN players ON players.id = theirs.player_id \
+                 JOIN songs ON songs.id = theirs.song_id \
+                 LEFT JOIN scores mine ON mine.player_id = $1 AND mine.song_id = theirs.song_id \
+                 AND mine.league = theirs.league AND mine.deleted_at IS NULL \
+                 WHERE rivalries.challenger_id = $1 AND theirs.submitted_at > $2 \
+                 AND theirs.deleted_at IS NULL AND songs.deleted_at IS NULL \
+             ) activity WHERE shared OR number_one \
+             ORDER BY submitted_at DESC LIMIT $3",
+        )
+        .bind::<Integer, _>(player)
+        .bind::<Timestamptz, _>(since)
+        .bind::<BigInt, _>(MAX_ACTIVITIES)
+        .load(conn)
+        .await
+    }
+
+    /// Describes the activity in a sentence, for the digests sent to webhooks.
+    #[must_use]
+    pub fn describe(&self, i18n: &Localization, locale: &str) -> String {
+        let league = format!("{:?}", self.league);
+        let mut description = i18n.text(
+            locale,
+            i18n::Text::DigestActivity,
+            &[
+                ("rival", &self.rival_name),
+                ("score", &self.score),
+                ("title", &self.title),
+                ("artist", &self.artist),
+                ("league", &league),
+            ],
+        );
+        if self.number_one {
+            description.push_str(&i18n.text(locale, i18n::Text::DigestNumberOne, &[]));
+        }
+        if let Some(your_score) = self.your_score.filter(|_| self.beat_you) {
+            description.push_str(&i18n.text(
+                locale,
+                i18n::Text::DigestBeatYou,
+                &[("score", &your_score)],
+            ));
+        }
+        description
+    }
+}
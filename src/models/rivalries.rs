@@ -1,9 +1,69 @@
 use diesel::prelude::*;
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 
 use super::players::PlayerPublic;
-use crate::{models::players::Player, schema::rivalries};
+use crate::{
+    models::players::Player,
+    schema::{players, rivalries},
+    util::{errors::WavebreakerError, redis_keys},
+};
+
+/// Keeps players from griefing others with mass rival declarations, see `rivals` in the config.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RivalryLimits {
+    /// How many rivals a player can have at most
+    pub max_rivals: i64,
+    /// How long a player has to wait before adding or removing the same rival again, in seconds
+    pub change_cooldown_secs: u64,
+}
+
+impl Default for RivalryLimits {
+    fn default() -> Self {
+        Self {
+            max_rivals: 200,
+            change_cooldown_secs: 60 * 60,
+        }
+    }
+}
+
+impl RivalryLimits {
+    /// Fails if the rivalry between the two players changed too recently.
+    async fn check_cooldown(
+        challenger: i32,
+        rival: i32,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<(), WavebreakerError> {
+        let cooling_down: bool = redis_conn
+            .exists(redis_keys::rivalry_change(challenger, rival))
+            .await?;
+        if cooling_down {
+            return Err(WavebreakerError::RivalryCooldown);
+        }
+        Ok(())
+    }
+
+    /// Starts the cooldown after the rivalry between the two players changed.
+    async fn start_cooldown(
+        &self,
+        challenger: i32,
+        rival: i32,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<(), WavebreakerError> {
+        if self.change_cooldown_secs > 0 {
+            redis_conn
+                .set_ex::<_, _, ()>(
+                    redis_keys::rivalry_change(challenger, rival),
+                    1,
+                    self.change_cooldown_secs,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Identifiable, Selectable, Queryable, Associations, Debug)]
 #[diesel(belongs_to(Player, foreign_key = challenger_id))]
@@ -19,6 +79,71 @@ pub struct Rivalry {
 }
 
 impl Rivalry {
+    /// Gets every rivalry the player started, along with the rival's profile and whether it's mutual,
+    /// in a single query. Oldest first.
+    pub async fn all_for_player(
+        player: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<RivalryView>> {
+        use crate::schema::rivalries::dsl::*;
+
+        let reverse = diesel::alias!(crate::schema::rivalries as reverse);
+        rivalries
+            .inner_join(players::table.on(rival_id.eq(players::id)))
+            .left_join(
+                reverse.on(reverse
+                    .field(challenger_id)
+                    .eq(rival_id)
+                    .and(reverse.field(rival_id).eq(challenger_id))),
+            )
+            .filter(challenger_id.eq(player))
+            .order(established_at)
+            .select((
+                established_at,
+                PlayerPublic::as_select(),
+                reverse.field(challenger_id).nullable().is_not_null(),
+            ))
+            .load::<RivalryView>(conn)
+            .await
+    }
+
+    /// Gets the player's mutual rivalries, the ones [`Rivalry::is_mutual`] is true for, in a single query.
+    /// Oldest first.
+    pub async fn mutual_rivals_of(
+        player: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<RivalryView>> {
+        let mut views = Self::all_for_player(player, conn).await?;
+        views.retain(|view| view.mutual);
+        Ok(views)
+    }
+
+    /// Ends the rivalry, unless it changed too recently.
+    ///
+    /// # Returns
+    /// Whether there was a rivalry to end.
+    ///
+    /// # Errors
+    /// Fails if the rivalry is still cooling down, or something is wrong with the DB or Redis.
+    pub async fn remove(
+        challenger: i32,
+        rival: i32,
+        limits: &RivalryLimits,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<bool, WavebreakerError> {
+        RivalryLimits::check_cooldown(challenger, rival, redis_conn).await?;
+
+        let removed = diesel::delete(rivalries::table.find((challenger, rival)))
+            .execute(conn)
+            .await?;
+        if removed > 0 {
+            limits.start_cooldown(challenger, rival, redis_conn).await?;
+        }
+
+        Ok(removed > 0)
+    }
+
     /// Find out if the players added *each others* as rivals.
     pub async fn is_mutual(&self, conn: &mut AsyncPgConnection) -> bool {
         use crate::schema::rivalries::dsl::*;
@@ -54,18 +179,78 @@ impl NewRivalry {
         }
     }
 
-    /// Creates a new rivalry in the database.
-    ///
-    /// # Arguments
-    /// * `conn` - A mutable reference to the database connection.
+    /// Creates the rivalry in the database, if the challenger is within the [`RivalryLimits`].
     ///
     /// # Returns
-    /// A `QueryResult` containing the created `Rivalry` instance.
-    pub async fn create(&self, conn: &mut AsyncPgConnection) -> QueryResult<Rivalry> {
-        diesel::insert_into(rivalries::table)
+    /// The new rivalry, `None` if it existed already.
+    ///
+    /// # Errors
+    /// Fails if the challenger has too many rivals already, the rivalry changed too recently,
+    /// or something is wrong with the DB or Redis.
+    pub async fn create(
+        &self,
+        limits: &RivalryLimits,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<Option<Rivalry>, WavebreakerError> {
+        self.insert(limits, true, conn, redis_conn).await
+    }
+
+    /// Like [`NewRivalry::create`], for rivals added by syncing the player's Steam friends.
+    /// The player didn't add them by hand, so they can remove them right away.
+    pub async fn create_synced(
+        &self,
+        limits: &RivalryLimits,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<Option<Rivalry>, WavebreakerError> {
+        self.insert(limits, false, conn, redis_conn).await
+    }
+
+    async fn insert(
+        &self,
+        limits: &RivalryLimits,
+        cool_down: bool,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<Option<Rivalry>, WavebreakerError> {
+        use crate::schema::rivalries::dsl::*;
+
+        if self.challenger_id == self.rival_id {
+            return Err(WavebreakerError::SelfRivalry);
+        }
+        let existing: Option<Rivalry> = rivalries
+            .find((self.challenger_id, self.rival_id))
+            .first(conn)
+            .await
+            .optional()?;
+        if existing.is_some() {
+            return Ok(None);
+        }
+
+        let rival_count: i64 = rivalries
+            .filter(challenger_id.eq(self.challenger_id))
+            .count()
+            .get_result(conn)
+            .await?;
+        if rival_count >= limits.max_rivals {
+            return Err(WavebreakerError::TooManyRivals(limits.max_rivals));
+        }
+        RivalryLimits::check_cooldown(self.challenger_id, self.rival_id, redis_conn).await?;
+
+        let rivalry = diesel::insert_into(rivalries)
             .values(self)
+            .on_conflict_do_nothing()
             .get_result(conn)
             .await
+            .optional()?;
+        if rivalry.is_some() && cool_down {
+            limits
+                .start_cooldown(self.challenger_id, self.rival_id, redis_conn)
+                .await?;
+        }
+
+        Ok(rivalry)
     }
 }
 
@@ -78,4 +263,7 @@ pub struct RivalryView {
     pub established_at: time::OffsetDateTime,
     #[diesel(embed)]
     pub rival: PlayerPublic,
+    /// Whether the rival added the player back, see [`Rivalry::is_mutual`]
+    #[serde(default)]
+    pub mutual: bool,
 }
@@ -0,0 +1,184 @@
+use diesel::{prelude::*, upsert};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{schema::sandbagging_flags, util::sandbagging::LeagueActivity};
+
+/// An elite-level player who looked like they farm top spots in Casual, see [`crate::util::sandbagging`].
+///
+/// Flags stay around once a moderator cleared them, so the player is only flagged again for what they did since.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = sandbagging_flags, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct SandbaggingFlag {
+    pub id: i32,
+    pub player_id: i32,
+    pub realm: String,
+    /// The player's points in the `elite` rankings when they were flagged
+    pub elite_points: i64,
+    /// Scores the player submitted during the detection window
+    pub recent_scores: i32,
+    /// How many of those were in Casual
+    pub recent_casual_scores: i32,
+    /// How many of those are on top of their Casual leaderboard
+    pub casual_tops: i32,
+    /// Whether the player's Casual scores don't count towards the rankings while the flag is active
+    pub excluded: bool,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub flagged_at: OffsetDateTime,
+    /// When a moderator decided the player isn't sandbagging, `None` while the flag is active
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub cleared_at: Option<OffsetDateTime>,
+    /// The moderator who cleared the flag. `None` if they don't exist anymore.
+    pub cleared_by: Option<i32>,
+}
+
+impl SandbaggingFlag {
+    /// Flags the player in the realm, unless they're flagged there already.
+    ///
+    /// # Returns
+    /// The new flag, `None` if there already was an active one.
+    pub async fn flag(
+        player: i32,
+        in_realm: &str,
+        points: i64,
+        activity: &LeagueActivity,
+        exclude: bool,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::sandbagging_flags::dsl::*;
+
+        // Only a cleared flag is replaced, an active one stays as it was
+        diesel::insert_into(sandbagging_flags)
+            .values((
+                player_id.eq(player),
+                realm.eq(in_realm),
+                elite_points.eq(points),
+                recent_scores.eq(activity.scores),
+                recent_casual_scores.eq(activity.casual_scores),
+                casual_tops.eq(activity.casual_tops),
+                excluded.eq(exclude),
+            ))
+            .on_conflict((player_id, realm))
+            .do_update()
+            .set((
+                elite_points.eq(upsert::excluded(elite_points)),
+                recent_scores.eq(upsert::excluded(recent_scores)),
+                recent_casual_scores.eq(upsert::excluded(recent_casual_scores)),
+                casual_tops.eq(upsert::excluded(casual_tops)),
+                excluded.eq(upsert::excluded(excluded)),
+                flagged_at.eq(OffsetDateTime::now_utc()),
+                cleared_at.eq(None::<OffsetDateTime>),
+                cleared_by.eq(None::<i32>),
+            ))
+            .filter(cleared_at.is_not_null())
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+            .optional()
+    }
+
+    /// Flags no moderator cleared yet, the newest first.
+    pub async fn active(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::sandbagging_flags::dsl::*;
+
+        sandbagging_flags
+            .filter(cleared_at.is_null())
+            .order(flagged_at.desc())
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// When the player's last flag in the realm was cleared, if it was.
+    /// Detection doesn't look at anything they did before that.
+    pub async fn cleared_at_of(
+        player: i32,
+        in_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<OffsetDateTime>> {
+        use crate::schema::sandbagging_flags::dsl::*;
+
+        Ok(sandbagging_flags
+            .filter(player_id.eq(player))
+            .filter(realm.eq(in_realm))
+            .select(cleared_at)
+            .first::<Option<OffsetDateTime>>(conn)
+            .await
+            .optional()?
+            .flatten())
+    }
+
+    /// Whether the player has an active flag in the realm, excluded or not.
+    pub async fn is_active(
+        player: i32,
+        in_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<bool> {
+        use crate::schema::sandbagging_flags::dsl::*;
+
+        diesel::select(diesel::dsl::exists(
+            sandbagging_flags
+                .filter(player_id.eq(player))
+                .filter(realm.eq(in_realm))
+                .filter(cleared_at.is_null()),
+        ))
+        .get_result(conn)
+        .await
+    }
+
+    /// Whether the player's Casual scores in the realm are kept out of the rankings.
+    pub async fn is_excluded(
+        player: i32,
+        in_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<bool> {
+        use crate::schema::sandbagging_flags::dsl::*;
+
+        diesel::select(diesel::dsl::exists(
+            sandbagging_flags
+                .filter(player_id.eq(player))
+                .filter(realm.eq(in_realm))
+                .filter(excluded)
+                .filter(cleared_at.is_null()),
+        ))
+        .get_result(conn)
+        .await
+    }
+
+    /// Every player and realm whose Casual scores are kept out of the rankings.
+    pub async fn all_excluded(conn: &mut AsyncPgConnection) -> QueryResult<Vec<(i32, String)>> {
+        use crate::schema::sandbagging_flags::dsl::*;
+
+        sandbagging_flags
+            .filter(excluded)
+            .filter(cleared_at.is_null())
+            .select((player_id, realm))
+            .load(conn)
+            .await
+    }
+
+    /// Clears an active flag, the player's Casual scores count again.
+    ///
+    /// # Returns
+    /// `None` if there's no active flag with that ID.
+    pub async fn clear(
+        flag: i32,
+        moderator_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::sandbagging_flags::dsl::*;
+
+        diesel::update(sandbagging_flags.find(flag).filter(cleared_at.is_null()))
+            .set((
+                cleared_at.eq(OffsetDateTime::now_utc()),
+                cleared_by.eq(moderator_id),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+            .optional()
+    }
+}
@@ -0,0 +1,121 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::schema::score_appeals;
+
+/// What a moderator decided about an appeal.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AppealResolution {
+    /// The score is restored
+    Accept,
+    /// The score stays removed
+    Reject,
+}
+
+impl AppealResolution {
+    /// How the resolution is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Accept => "accepted",
+            Self::Reject => "rejected",
+        }
+    }
+}
+
+/// A player's appeal against the removal of one of their scores.
+///
+/// Resolved appeals are kept as the record of what was decided, by whom and why.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = score_appeals, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreAppeal {
+    pub id: i32,
+    /// `None` if the score was purged
+    pub score_id: Option<i32>,
+    pub player_id: i32,
+    pub comment: String,
+    /// A link to a video or screenshot of the ride, if the player has one
+    pub evidence_url: Option<String>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub appealed_at: OffsetDateTime,
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub resolved_at: Option<OffsetDateTime>,
+    /// An [`AppealResolution`], see [`AppealResolution::as_str`]
+    pub resolution: Option<String>,
+    /// Why it was decided that way, told to the player
+    pub resolution_note: Option<String>,
+    /// The moderator who resolved the appeal. `None` if they don't exist anymore.
+    pub resolved_by: Option<i32>,
+}
+
+type Pending =
+    diesel::dsl::Filter<score_appeals::table, diesel::dsl::IsNull<score_appeals::resolved_at>>;
+
+impl ScoreAppeal {
+    /// Appeals no moderator has resolved yet.
+    #[must_use]
+    pub fn pending() -> Pending {
+        score_appeals::table.filter(score_appeals::resolved_at.is_null())
+    }
+
+    /// Files an appeal. Appealing a score that already has a pending appeal is a unique violation.
+    pub async fn create(
+        appealed_score: i32,
+        appellant: i32,
+        appeal_comment: &str,
+        evidence: Option<&str>,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::score_appeals::dsl::*;
+
+        diesel::insert_into(score_appeals)
+            .values((
+                score_id.eq(appealed_score),
+                player_id.eq(appellant),
+                comment.eq(appeal_comment),
+                evidence_url.eq(evidence),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// All appeals of the player, newest first.
+    pub async fn for_player(player: i32, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::score_appeals::dsl::*;
+
+        score_appeals
+            .filter(player_id.eq(player))
+            .order(id.desc())
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Marks the appeal as resolved. Doesn't do anything about the score itself.
+    pub async fn resolve(
+        &self,
+        resolution_taken: AppealResolution,
+        note: Option<&str>,
+        moderator_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::score_appeals::dsl::*;
+
+        diesel::update(self)
+            .set((
+                resolved_at.eq(OffsetDateTime::now_utc()),
+                resolution.eq(resolution_taken.as_str()),
+                resolution_note.eq(note),
+                resolved_by.eq(moderator_id),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+    }
+}
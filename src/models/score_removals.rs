@@ -0,0 +1,136 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use super::scores::Score;
+use crate::{schema::score_removals, util::game_types::League};
+
+/// What a player did to one of their own scores, or a moderator did to someone's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScoreRemovalAction {
+    /// Taken off the leaderboards, the player can bring it back until it's purged
+    Hide,
+    /// Brought back after being hidden
+    Unhide,
+    /// Gone for good
+    Delete,
+    /// Removed by a moderator, the player can appeal it
+    Remove,
+    /// Brought back by a moderator accepting an appeal
+    Restore,
+}
+
+impl ScoreRemovalAction {
+    /// How the action is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Hide => "hide",
+            Self::Unhide => "unhide",
+            Self::Delete => "delete",
+            Self::Remove => "remove",
+            Self::Restore => "restore",
+        }
+    }
+}
+
+/// A player hiding, unhiding or deleting one of their own scores, or a moderator removing or restoring one.
+///
+/// Every one is kept along with what the score was, so moderators can tell what happened to it after it's gone.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = score_removals, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreRemoval {
+    pub id: i32,
+    /// `None` if the score was deleted or purged
+    pub score_id: Option<i32>,
+    pub player_id: i32,
+    pub song_id: i32,
+    pub league: League,
+    pub score: i32,
+    /// A [`ScoreRemovalAction`], see [`ScoreRemovalAction::as_str`]
+    pub action: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: OffsetDateTime,
+    /// The moderator who did it, `None` if it was the player (or the moderator doesn't exist anymore)
+    pub moderator_id: Option<i32>,
+}
+
+impl ScoreRemoval {
+    /// Records what the player did to the score.
+    pub async fn record(
+        removed: &Score,
+        taken: ScoreRemovalAction,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        Self::record_by(removed, taken, None, conn).await
+    }
+
+    /// Records what a moderator did to the score.
+    pub async fn record_moderation(
+        removed: &Score,
+        taken: ScoreRemovalAction,
+        moderator: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        Self::record_by(removed, taken, Some(moderator), conn).await
+    }
+
+    async fn record_by(
+        removed: &Score,
+        taken: ScoreRemovalAction,
+        moderator: Option<i32>,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::score_removals::dsl::*;
+
+        diesel::insert_into(score_removals)
+            .values((
+                score_id.eq(removed.id),
+                player_id.eq(removed.player_id),
+                song_id.eq(removed.song_id),
+                league.eq(removed.league),
+                score.eq(removed.score),
+                action.eq(taken.as_str()),
+                moderator_id.eq(moderator),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// Everything the player and moderators did to the player's scores, newest first.
+    pub async fn for_player(player: i32, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::score_removals::dsl::*;
+
+        score_removals
+            .filter(player_id.eq(player))
+            .order(id.desc())
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Whether the score is deleted because its player hid it, and not because a moderator removed it.
+    pub async fn is_hidden_by_player(
+        removed: &Score,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<bool> {
+        use crate::schema::score_removals::dsl::*;
+
+        if removed.deleted_at.is_none() {
+            return Ok(false);
+        }
+        let last_action: Option<String> = score_removals
+            .filter(score_id.eq(removed.id))
+            .order(id.desc())
+            .select(action)
+            .first(conn)
+            .await
+            .optional()?;
+        // Moderators can only remove scores that aren't deleted, so they can't have removed it since
+        Ok(last_action.as_deref() == Some(ScoreRemovalAction::Hide.as_str()))
+    }
+}
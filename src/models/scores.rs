@@ -1,25 +1,48 @@
-use anyhow::Context;
 use diesel::{
     associations::HasTable,
     backend::Backend,
     deserialize::{self, FromSql},
+    dsl::not,
     pg::Pg,
     prelude::*,
+    query_builder::{BoxedSqlQuery, SqlQuery},
     serialize,
     serialize::{Output, ToSql},
-    sql_types::SmallInt,
+    sql_query,
+    sql_types::{Array, BigInt, Integer, Nullable, SmallInt, Text},
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
 };
-use diesel_async::{AsyncPgConnection, RunQueryDsl};
-use redis::AsyncCommands;
 use serde::Serialize;
 use time::OffsetDateTime;
 
 use crate::{
-    models::{players::Player, songs::Song},
-    schema::scores,
-    util::game_types::{Character, League},
+    models::{
+        merge_log::ScoreMergeAction,
+        players::{Player, PlayerPublic},
+        rivalries::Rivalry,
+        score_appeals::ScoreAppeal,
+        skill_point_ledger::LedgerReason,
+        songs::Song,
+    },
+    schema::{players, score_appeals, scores},
+    util::{
+        errors::WavebreakerError,
+        game_types::{Character, Feat, League},
+        ranking_store::RankingStore,
+        rankings,
+        realm::MAIN_REALM,
+        scoring,
+    },
 };
 
+/// How many scores per league are sent to the game in one leaderboard page.
+pub const GAME_PAGE_SIZE: i64 = 11;
+/// The last leaderboard page the game is allowed to request.
+/// Keeps clients from paging through an entire song's scores.
+pub const GAME_MAX_PAGE: i64 = 100;
+
 impl ToSql<SmallInt, Pg> for League
 where
     i16: ToSql<SmallInt, Pg>,
@@ -73,6 +96,8 @@ pub struct Score {
     pub player_id: i32,
     pub league: League,
     pub submitted_at: time::OffsetDateTime,
+    /// How often the player has played the song in this league.
+    /// Counted on every submission, not just the ones that improved the score.
     pub play_count: i32,
     pub score: i32,
     pub track_shape: Vec<Option<i32>>,
@@ -84,64 +109,400 @@ pub struct Score {
     pub feats: Vec<Option<String>>,
     pub song_length: i32,
     pub gold_threshold: i32,
+    /// Sent by the game with every ride, meaning unknown. Stored as sent and shown in the API as is.
+    /// Steep and ironmode rides don't need them, they're separate songs through their title modifiers.
     pub iss: i32,
+    /// See [`Score::iss`].
     pub isj: i32,
+    /// When the score was (soft-)deleted. Deleted scores are hidden by [`Score::all`].
+    #[serde(skip_serializing)]
+    pub deleted_at: Option<time::OffsetDateTime>,
+    /// Always the realm of the song, kept here so the rankings don't need the song.
+    pub realm: String,
 }
 
+// Types for use with functions that return reusable query fragments
+type All = diesel::dsl::Filter<scores::table, diesel::dsl::IsNull<scores::deleted_at>>;
+type BestPerPlayer = diesel::dsl::Select<
+    diesel::dsl::Order<
+        diesel::dsl::DistinctOn<
+            diesel::dsl::Filter<All, diesel::dsl::Eq<scores::song_id, i32>>,
+            scores::player_id,
+        >,
+        (
+            scores::player_id,
+            diesel::dsl::Desc<scores::score>,
+            scores::submitted_at,
+        ),
+    >,
+    scores::id,
+>;
+type ByKey = diesel::dsl::Filter<
+    diesel::dsl::Find<scores::table, i32>,
+    diesel::dsl::Eq<scores::song_id, i32>,
+>;
+
 impl Score {
+    /// Returns a query fragment that selects all scores that haven't been deleted.
+    /// Use this instead of `scores::table` unless you *really* want deleted scores, too.
+    #[must_use]
+    pub fn all() -> All {
+        scores::table.filter(scores::deleted_at.is_null())
+    }
+
+    /// Returns a query fragment that selects this score, deleted or not, by its song too.
+    /// Scores are partitioned by song, so Postgres only has to look in one partition for it.
+    /// Use this instead of `diesel::update(self)` or `scores::table.find(id)` when the song is known.
+    #[must_use]
+    pub fn by_key(&self) -> ByKey {
+        scores::table
+            .find(self.id)
+            .filter(scores::song_id.eq(self.song_id))
+    }
+
     /// Calculates and returns the skill points the player earned for this score.
     #[must_use]
-    #[allow(clippy::cast_possible_truncation)]
     pub fn get_skill_points(&self) -> i32 {
-        let multiplier = (self.league as u32 + 1) * 100;
-        ((f64::from(self.score) / f64::from(self.gold_threshold)) * f64::from(multiplier)).round()
-            as i32
+        Self::skill_points_for(self.league, self.score, self.gold_threshold)
+    }
+
+    /// Calculates the skill points a score would be worth, for scores that aren't stored (yet).
+    /// Uses the installed formula, see [`scoring`].
+    #[must_use]
+    pub fn skill_points_for(score_league: League, points: i32, gold: i32) -> i32 {
+        scoring::policy().skill_points(score_league, points, gold)
     }
 
-    /// Deletes the score from the database.
+    /// Adds plays to the score's play count, without touching anything else.
+    ///
+    /// # Errors
+    /// This fails if the database query fails.
+    pub async fn add_plays(&self, plays: i32, conn: &mut AsyncPgConnection) -> QueryResult<Self> {
+        use crate::schema::scores::dsl::*;
+
+        diesel::update(self.by_key())
+            .set(play_count.eq(play_count + plays))
+            .get_result::<Self>(conn)
+            .await
+    }
+
+    /// Moves the score to another song, used when merging songs.
+    /// If the player already has a score in the same league on the target song (`target_score`),
+    /// only the higher one survives and gets the plays of the other one.
+    /// Scores are unique per player, song and league, so play counts never mix leagues.
+    ///
+    /// # Returns
+    /// What happened to the score, for the merge log.
+    ///
+    /// # Errors
+    /// This fails if the database query fails or something goes wrong with Redis.
+    pub async fn move_to_song(
+        &self,
+        target_song_id: i32,
+        target_score: Option<&Self>,
+        conn: &mut AsyncPgConnection,
+        store: &mut impl RankingStore,
+    ) -> Result<ScoreMergeAction, WavebreakerError> {
+        use crate::schema::scores::dsl::*;
+
+        let action = match target_score {
+            // If the score on the song we want to merge into is lower, we delete that score
+            // then, we add our song's score to the merge target song
+            Some(target_score) if target_score.score < self.score => {
+                target_score.delete(conn, store).await?;
+                diesel::update(self.by_key())
+                    .set((
+                        song_id.eq(target_song_id),
+                        play_count.eq(play_count + target_score.play_count),
+                    ))
+                    .execute(conn)
+                    .await?;
+                // Which of the player's scores is the best of a song changed for both songs
+                rankings::refresh_player(self.player_id, &self.realm, conn, store).await?;
+
+                ScoreMergeAction::Moved {
+                    score_id: self.id,
+                    replaced_score_id: Some(target_score.id),
+                    absorbed_plays: target_score.play_count,
+                }
+            }
+            Some(target_score) => {
+                target_score.add_plays(self.play_count, conn).await?;
+                self.delete(conn, store).await?;
+
+                ScoreMergeAction::Absorbed {
+                    score_id: self.id,
+                    into_score_id: target_score.id,
+                    plays: self.play_count,
+                }
+            }
+            None => {
+                diesel::update(self.by_key())
+                    .set(song_id.eq(target_song_id))
+                    .execute(conn)
+                    .await?;
+                rankings::refresh_player(self.player_id, &self.realm, conn, store).await?;
+
+                ScoreMergeAction::Moved {
+                    score_id: self.id,
+                    replaced_score_id: None,
+                    absorbed_plays: 0,
+                }
+            }
+        };
+        store
+            .invalidate_rank_index(self.song_id, self.league)
+            .await?;
+        store
+            .invalidate_rank_index(target_song_id, self.league)
+            .await?;
+
+        Ok(action)
+    }
+
+    /// Deletes the score. This is a soft delete, the score can be brought back with [`Score::restore`]
+    /// until it's purged with [`Score::purge_deleted`].
     ///
     /// # Errors
     /// This fails if the database query fails or something goes wrong with Redis.
     pub async fn delete(
         &self,
         conn: &mut AsyncPgConnection,
-        redis_conn: &mut deadpool_redis::Connection,
-    ) -> anyhow::Result<()> {
+        store: &mut impl RankingStore,
+    ) -> Result<(), WavebreakerError> {
+        self.delete_at(OffsetDateTime::now_utc(), conn, store).await
+    }
+
+    /// Deletes the score like [`Score::delete`], but with a specific deletion time.
+    ///
+    /// # Errors
+    /// This fails if the database query fails or something goes wrong with Redis.
+    pub async fn delete_at(
+        &self,
+        deletion_time: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+        store: &mut impl RankingStore,
+    ) -> Result<(), WavebreakerError> {
         use crate::schema::scores::dsl::*;
 
-        // Subtract the skill points from the player on Redis
-        let sub_amount = 0 - self.get_skill_points();
-        redis_conn
-            .zincr::<&str, i32, i32, i32>("leaderboard", self.player_id, sub_amount)
+        let deleted_rows = diesel::update(self.by_key().filter(deleted_at.is_null()))
+            .set(deleted_at.eq(deletion_time))
+            .execute(conn)
+            .await?;
+
+        // Take the skill points away from the player in the rankings
+        // unless the score was already deleted, then they're already gone
+        if deleted_rows > 0 {
+            store
+                .invalidate_rank_index(self.song_id, self.league)
+                .await?;
+            rankings::record_change(
+                self,
+                LedgerReason::Deletion,
+                self.get_skill_points(),
+                0,
+                conn,
+                store,
+            )
             .await?;
+        }
 
-        diesel::delete(scores.filter(id.eq(self.id)))
+        Ok(())
+    }
+
+    /// Deletes the score for good, taking its skill points away first if it wasn't deleted already.
+    ///
+    /// # Errors
+    /// This fails if the database query fails or something goes wrong with Redis.
+    pub async fn delete_permanently(
+        &self,
+        conn: &mut AsyncPgConnection,
+        store: &mut impl RankingStore,
+    ) -> Result<(), WavebreakerError> {
+        self.delete(conn, store).await?;
+        diesel::delete(self.by_key()).execute(conn).await?;
+
+        Ok(())
+    }
+
+    /// Restores a deleted score and gives the player their skill points back.
+    ///
+    /// # Errors
+    /// This fails if the database query fails or something goes wrong with Redis.
+    /// Also fails if the player has set a new score on the same song and league since this one was deleted.
+    pub async fn restore(
+        &self,
+        conn: &mut AsyncPgConnection,
+        store: &mut impl RankingStore,
+    ) -> Result<(), WavebreakerError> {
+        use crate::schema::scores::dsl::*;
+
+        let restored_rows = diesel::update(self.by_key().filter(deleted_at.is_not_null()))
+            .set(deleted_at.eq(None::<OffsetDateTime>))
             .execute(conn)
             .await?;
+
+        if restored_rows > 0 {
+            store
+                .invalidate_rank_index(self.song_id, self.league)
+                .await?;
+            rankings::record_change(
+                self,
+                LedgerReason::Restore,
+                0,
+                self.get_skill_points(),
+                conn,
+                store,
+            )
+            .await?;
+        }
+
         Ok(())
     }
 
+    /// Permanently deletes all scores that were deleted before `before`.
+    /// Scores with a pending appeal are kept until it's resolved.
+    ///
+    /// # Returns
+    /// The number of scores that were purged.
+    pub async fn purge_deleted(
+        before: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        use crate::schema::scores::dsl::*;
+
+        let appealed = ScoreAppeal::pending()
+            .filter(score_appeals::score_id.is_not_null())
+            .select(score_appeals::score_id.assume_not_null());
+        diesel::delete(
+            scores
+                .filter(deleted_at.lt(before))
+                .filter(not(id.eq_any(appealed))),
+        )
+        .execute(conn)
+        .await
+    }
+
+    /// Returns a query fragment that selects the IDs of every player's best score on the song, in whichever league
+    /// it is. The earlier one wins a tie.
+    #[must_use]
+    pub fn best_per_player(find_song_id: i32) -> BestPerPlayer {
+        Self::all()
+            .filter(scores::song_id.eq(find_song_id))
+            .distinct_on(scores::player_id)
+            .order((
+                scores::player_id,
+                scores::score.desc(),
+                scores::submitted_at,
+            ))
+            .select(scores::id)
+    }
+
+    /// Gets a page of a song's leaderboard in one league, best first, for the API.
+    /// With `find_league` set to `None`, it's the leaderboard across all leagues: every player's best score,
+    /// in whichever league it is.
+    /// With `mutual_rivals_of`, only that player and the players with a mutual rivalry with them are on it,
+    /// see [`Rivalry::mutual_rivals_of`].
+    pub async fn leaderboard(
+        find_song_id: i32,
+        find_league: Option<League>,
+        mutual_rivals_of: Option<i32>,
+        page: i64,
+        page_size: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(Self, PlayerPublic)>> {
+        use crate::schema::scores::dsl::*;
+
+        let mut query = Self::all()
+            .inner_join(players::table)
+            .filter(song_id.eq(find_song_id))
+            .select((Self::as_select(), PlayerPublic::as_select()))
+            .into_boxed();
+        if let Some(find_league) = find_league {
+            query = query.filter(league.eq(find_league));
+        } else {
+            query = query.filter(id.eq_any(Self::best_per_player(find_song_id)));
+        }
+        if let Some(player) = mutual_rivals_of {
+            let mut player_ids: Vec<i32> = Rivalry::mutual_rivals_of(player, conn)
+                .await?
+                .into_iter()
+                .map(|view| view.rival.id)
+                .collect();
+            player_ids.push(player);
+            query = query.filter(player_id.eq_any(player_ids));
+        }
+        query
+            .order((score.desc(), submitted_at))
+            .limit(page_size)
+            .offset(page.saturating_mul(page_size))
+            .load(conn)
+            .await
+    }
+
+    /// Gets the best score on each league of the songs, for pages showing many songs at once.
+    pub async fn tops_of_songs(
+        song_ids: &[i32],
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(Self, PlayerPublic)>> {
+        use crate::schema::scores::dsl::*;
+
+        Self::all()
+            .inner_join(players::table)
+            .filter(song_id.eq_any(song_ids))
+            .distinct_on((song_id, league))
+            .order((song_id, league, score.desc(), submitted_at))
+            .select((Self::as_select(), PlayerPublic::as_select()))
+            .load(conn)
+            .await
+    }
+
+    /// Adds up how often each of the songs was played, in every league.
+    ///
+    /// # Returns
+    /// The song IDs with their plays, songs nobody played are left out.
+    pub async fn plays_of_songs(
+        song_ids: &[i32],
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(i32, i64)>> {
+        use crate::schema::scores::dsl::*;
+
+        let plays: Vec<(i32, Option<i64>)> = Self::all()
+            .filter(song_id.eq_any(song_ids))
+            .group_by(song_id)
+            .select((song_id, diesel::dsl::sum(play_count)))
+            .load(conn)
+            .await?;
+        Ok(plays
+            .into_iter()
+            .map(|(song, total)| (song, total.unwrap_or_default()))
+            .collect())
+    }
+
     /// Retrieves the scores for a specific song and league, for display in-game.
     /// **ALL OF THE `game_get_*` FUNCTIONS ARE ONLY FOR IN-GAME LEADERBOARDS.**
-    ///  Therefore, the score count is limited to 11.
+    ///  Therefore, the score count is limited to [`GAME_PAGE_SIZE`] per page.
     ///
     /// # Arguments
     /// * `find_song_id` - The ID of the song to find scores for.
     /// * `find_league` - The league to filter scores by.
+    /// * `page` - The page of the leaderboard, starting at 0.
     /// * `conn` - The database connection.
     pub async fn game_get_global(
         find_song_id: i32,
         find_league: League,
+        page: i64,
         conn: &mut AsyncPgConnection,
     ) -> QueryResult<Vec<ScoreWithPlayer>> {
         use crate::schema::{players::dsl::*, scores::dsl::*};
 
-        Ok(scores
+        Ok(Self::all()
             .inner_join(players::table())
             .filter(song_id.eq(find_song_id))
             .filter(league.eq(find_league))
             .order(score.desc())
-            .limit(11)
+            .limit(GAME_PAGE_SIZE)
+            .offset(page.saturating_mul(GAME_PAGE_SIZE))
             .load::<(Self, Player)>(conn)
             .await?
             .into_iter()
@@ -152,28 +513,62 @@ impl Score {
             .collect::<Vec<ScoreWithPlayer>>())
     }
 
+    /// Retrieves every player's best score on a song, in whichever league it is, for display in-game.
+    /// The game has no such leaderboard, it's for clients asking for it, see `get_rides`.
+    ///
+    /// # Arguments
+    /// * `find_song_id` - The ID of the song to find scores for.
+    /// * `page` - The page of the leaderboard, starting at 0.
+    /// * `conn` - The database connection.
+    pub async fn game_get_combined(
+        find_song_id: i32,
+        page: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<ScoreWithPlayer>> {
+        use crate::schema::{players::dsl::*, scores::dsl::*};
+
+        Ok(Self::all()
+            .inner_join(players::table())
+            .filter(song_id.eq(find_song_id))
+            .filter(crate::schema::scores::id.eq_any(Self::best_per_player(find_song_id)))
+            .order((score.desc(), submitted_at))
+            .limit(GAME_PAGE_SIZE)
+            .offset(page.saturating_mul(GAME_PAGE_SIZE))
+            .load::<(Self, Player)>(conn)
+            .await?
+            .into_iter()
+            .map(|(curr_score, player)| ScoreWithPlayer {
+                score: curr_score,
+                player,
+            })
+            .collect())
+    }
+
     /// Gets all rivals' scores for a specific song and league, for display in-game.
     ///
     /// # Arguments
     /// * `find_song_id` - The ID of the song to find scores for.
     /// * `find_league` - The league to filter scores by.
     /// *  `rival_ids` - The IDs of the rivals to filter scores by.
+    /// * `page` - The page of the leaderboard, starting at 0.
     /// * `conn` - The database connection.
     pub async fn game_get_rivals(
         find_song_id: i32,
         find_league: League,
         rival_ids: &Vec<i32>,
+        page: i64,
         conn: &mut AsyncPgConnection,
     ) -> QueryResult<Vec<ScoreWithPlayer>> {
         use crate::schema::{players::dsl::*, scores::dsl::*};
 
-        Ok(scores
+        Ok(Self::all()
             .inner_join(players::table())
             .filter(song_id.eq(find_song_id))
             .filter(league.eq(find_league))
             .filter(player_id.eq_any(rival_ids))
             .order(score.desc())
-            .limit(11)
+            .limit(GAME_PAGE_SIZE)
+            .offset(page.saturating_mul(GAME_PAGE_SIZE))
             .load::<(Self, Player)>(conn)
             .await?
             .into_iter()
@@ -190,22 +585,26 @@ impl Score {
     /// # Arguments
     /// * `find_song_id` - The ID of the song to find scores for.
     /// * `find_league` - The league to filter scores by.
+    /// * `find_location_id` - The location to filter players by.
+    /// * `page` - The page of the leaderboard, starting at 0.
     /// * `conn` - The database connection.
     pub async fn game_get_nearby(
         find_song_id: i32,
         find_league: League,
         find_location_id: i32,
+        page: i64,
         conn: &mut AsyncPgConnection,
     ) -> QueryResult<Vec<ScoreWithPlayer>> {
         use crate::schema::{players::dsl::*, scores::dsl::*};
 
-        Ok(scores
+        Ok(Self::all()
             .inner_join(players::table())
             .filter(song_id.eq(find_song_id))
             .filter(league.eq(find_league))
             .filter(location_id.eq(find_location_id))
             .order(score.desc())
-            .limit(11)
+            .limit(GAME_PAGE_SIZE)
+            .offset(page.saturating_mul(GAME_PAGE_SIZE))
             .load::<(Self, Player)>(conn)
             .await?
             .into_iter()
@@ -224,11 +623,107 @@ pub struct ScoreWithPlayer {
     pub player: Player,
 }
 
+/// A player and how many of their scores have a feat, see [`FeatLeader::load`].
+#[derive(QueryableByName, Debug)]
+pub struct FeatLeader {
+    #[diesel(sql_type = Integer)]
+    pub player_id: i32,
+    #[diesel(sql_type = BigInt)]
+    pub scores: i64,
+}
+
+impl FeatLeader {
+    /// Gets a page of the players with the most scores with the feat, most first.
+    /// With `find_song_id`, only that song's scores count, otherwise the main realm's.
+    pub async fn load(
+        feat: Feat,
+        find_song_id: Option<i32>,
+        page: i64,
+        page_size: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        Self::query(feat, find_song_id, page, page_size)
+            .load(conn)
+            .await
+    }
+
+    fn query(
+        feat: Feat,
+        find_song_id: Option<i32>,
+        page: i64,
+        page_size: i64,
+    ) -> BoxedSqlQuery<'static, Pg, SqlQuery> {
+        // `@>` rather than `= ANY`, so the `scores_feats` index is used
+        sql_query(
+            "SELECT player_id, COUNT(*) AS scores FROM scores \
+             WHERE deleted_at IS NULL AND feats @> ARRAY[$1] \
+             AND (song_id = $2 OR ($2 IS NULL AND realm = $3)) \
+             GROUP BY player_id ORDER BY scores DESC, player_id LIMIT $4 OFFSET $5",
+        )
+        .into_boxed()
+        .bind::<Text, _>(feat.as_str())
+        .bind::<Nullable<Integer>, _>(find_song_id)
+        .bind::<Text, _>(MAIN_REALM)
+        .bind::<BigInt, _>(page_size)
+        .bind::<BigInt, _>(page.saturating_mul(page_size))
+    }
+}
+
+/// How many of a player's scores have a feat.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatCount {
+    pub feat: Feat,
+    pub scores: i64,
+}
+
+/// How many of a player's scores have a feat, by its name in the game, see [`FeatCount::for_player`].
+#[derive(QueryableByName, Debug)]
+struct FeatTally {
+    #[diesel(sql_type = Text)]
+    feat: String,
+    #[diesel(sql_type = BigInt)]
+    scores: i64,
+}
+
+impl FeatCount {
+    /// Counts the player's scores with every known feat, in the order of [`Feat::ALL`], including the ones they
+    /// never got.
+    pub async fn for_player(player: i32, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        let names: Vec<&str> = Feat::ALL.iter().map(|feat| feat.as_str()).collect();
+        let tallies: Vec<FeatTally> = sql_query(
+            "SELECT feat, COUNT(DISTINCT id) AS scores FROM scores, unnest(feats) AS feat \
+             WHERE deleted_at IS NULL AND player_id = $1 AND feat = ANY($2) GROUP BY feat",
+        )
+        .bind::<Integer, _>(player)
+        .bind::<Array<Text>, _>(names)
+        .load(conn)
+        .await?;
+        Ok(Self::count(tallies))
+    }
+
+    fn count(tallies: Vec<FeatTally>) -> Vec<Self> {
+        let mut counts: Vec<Self> = Feat::ALL
+            .into_iter()
+            .map(|feat| Self { feat, scores: 0 })
+            .collect();
+        for tally in tallies {
+            if let Some(count) = Feat::from_game(&tally.feat)
+                .and_then(|feat| counts.iter_mut().find(|count| count.feat == feat))
+            {
+                count.scores = tally.scores;
+            }
+        }
+        counts
+    }
+}
+
 #[derive(Insertable)]
 #[diesel(table_name = scores)]
 pub struct NewScore<'a> {
     pub player_id: i32,
     pub song_id: i32,
+    pub realm: &'a str,
     pub league: League,
     pub score: i32,
     pub track_shape: &'a [i32],
@@ -248,6 +743,7 @@ impl<'a> NewScore<'a> {
     /// # Arguments
     /// * `player_id` - The ID of the player.
     /// * `song_id` - The ID of the song.
+    /// * `realm` - The realm of the song.
     /// * `league` - The league (Casual, Pro, Elite) the score was set in.
     /// * `score` - The score.
     /// * `track_shape` - Contains the track's elevation at various points.
@@ -264,6 +760,7 @@ impl<'a> NewScore<'a> {
     pub const fn new(
         player_id: i32,
         song_id: i32,
+        realm: &'a str,
         league: League,
         score: i32,
         track_shape: &'a [i32],
@@ -279,6 +776,7 @@ impl<'a> NewScore<'a> {
         Self {
             player_id,
             song_id,
+            realm,
             league,
             score,
             track_shape,
@@ -295,6 +793,11 @@ impl<'a> NewScore<'a> {
 
     /// Creates or updates a score entry in the database.
     ///
+    /// This is an upsert on the player/song/league index, so two submissions arriving at the same time
+    /// can't end up as two rows, and a lower score can never overwrite a higher one.
+    /// Submissions for the same player and song are serialized with an advisory lock,
+    /// which keeps the Redis skill point delta consistent with what ended up in the DB.
+    ///
     /// # Arguments
     /// * `conn` - The database connection.
     ///
@@ -308,80 +811,158 @@ impl<'a> NewScore<'a> {
     pub async fn create_or_update(
         &self,
         conn: &mut AsyncPgConnection,
-        redis_conn: &mut deadpool_redis::Connection,
-    ) -> anyhow::Result<Score> {
+        store: &mut impl RankingStore,
+    ) -> Result<Score, WavebreakerError> {
+        use diesel::{dsl::case_when, sql_types::Integer, upsert::excluded};
+
         use crate::schema::scores::dsl::*;
 
-        let existing_score = scores
-            .filter(player_id.eq(self.player_id))
-            .filter(song_id.eq(self.song_id))
-            .filter(league.eq(self.league))
-            .first::<Score>(conn)
-            .await
-            .optional()?;
-
-        if let Some(existing_score) = existing_score {
-            if existing_score.score < self.score {
-                // Subtract the skill points of the old score from the Redis leaderboard
-                let sub_amount = 0 - existing_score.get_skill_points();
-                redis_conn
-                    .zincr::<&str, i32, i32, i32>(
-                        "leaderboard",
-                        existing_score.player_id,
-                        sub_amount,
-                    )
-                    .await?;
+        let (previous_score, new_score) = conn
+            .transaction::<_, WavebreakerError, _>(|conn| {
+                async move {
+                    // Held until the transaction ends
+                    diesel::sql_query("SELECT pg_advisory_xact_lock($1, $2)")
+                        .bind::<Integer, _>(self.player_id)
+                        .bind::<Integer, _>(self.song_id)
+                        .execute(conn)
+                        .await?;
 
-                let updated_score = diesel::update(scores)
-                    .filter(player_id.eq(self.player_id))
-                    .filter(song_id.eq(self.song_id))
-                    .filter(league.eq(self.league))
-                    .set((
-                        score.eq(self.score),
-                        track_shape.eq(self.track_shape),
-                        xstats.eq(self.xstats),
-                        density.eq(self.density),
-                        vehicle.eq(self.vehicle),
-                        feats.eq(self.feats),
-                        song_length.eq(self.song_length),
-                        gold_threshold.eq(self.gold_threshold),
-                        iss.eq(self.iss),
-                        isj.eq(self.isj),
-                        play_count.eq(play_count + 1),
-                        submitted_at.eq(OffsetDateTime::now_utc()),
-                    ))
-                    .get_result::<Score>(conn)
-                    .await
-                    .context("Failed to update score")?;
-
-                // Add the skill points of the new score to the Redis leaderboard
-                let add_amount = updated_score.get_skill_points();
-                redis_conn
-                    .zincr::<&str, i32, i32, i32>(
-                        "leaderboard",
-                        updated_score.player_id,
-                        add_amount,
-                    )
-                    .await?;
+                    let previous_score = Score::all()
+                        .filter(player_id.eq(self.player_id))
+                        .filter(song_id.eq(self.song_id))
+                        .filter(league.eq(self.league))
+                        .first::<Score>(conn)
+                        .await
+                        .optional()?;
 
-                Ok(updated_score)
-            } else {
-                Ok(existing_score)
-            }
-        } else {
-            let new_score = diesel::insert_into(scores)
-                .values(self)
-                .get_result::<Score>(conn)
-                .await
-                .context("Failed to insert score")?;
-
-            // Add the skill points of the new score to the Redis leaderboard
-            let add_amount = new_score.get_skill_points();
-            redis_conn
-                .zincr::<&str, i32, i32, i32>("leaderboard", new_score.player_id, add_amount)
+                    // Only take the new values if the submission beats the stored score,
+                    // every submission counts as a play either way
+                    let new_score =
+                        diesel::insert_into(scores)
+                            .values(self)
+                            .on_conflict((player_id, song_id, league))
+                            .filter_target(deleted_at.is_null())
+                            .do_update()
+                            .set((
+                                score.eq(case_when(excluded(score).gt(score), excluded(score))
+                                    .otherwise(score)),
+                                track_shape.eq(case_when(
+                                    excluded(score).gt(score),
+                                    excluded(track_shape),
+                                )
+                                .otherwise(track_shape)),
+                                xstats.eq(case_when(excluded(score).gt(score), excluded(xstats))
+                                    .otherwise(xstats)),
+                                density.eq(case_when(excluded(score).gt(score), excluded(density))
+                                    .otherwise(density)),
+                                vehicle.eq(case_when(excluded(score).gt(score), excluded(vehicle))
+                                    .otherwise(vehicle)),
+                                feats.eq(case_when(excluded(score).gt(score), excluded(feats))
+                                    .otherwise(feats)),
+                                song_length.eq(case_when(
+                                    excluded(score).gt(score),
+                                    excluded(song_length),
+                                )
+                                .otherwise(song_length)),
+                                gold_threshold.eq(case_when(
+                                    excluded(score).gt(score),
+                                    excluded(gold_threshold),
+                                )
+                                .otherwise(gold_threshold)),
+                                iss.eq(case_when(excluded(score).gt(score), excluded(iss))
+                                    .otherwise(iss)),
+                                isj.eq(case_when(excluded(score).gt(score), excluded(isj))
+                                    .otherwise(isj)),
+                                play_count.eq(play_count + 1),
+                                submitted_at.eq(case_when(
+                                    excluded(score).gt(score),
+                                    excluded(submitted_at),
+                                )
+                                .otherwise(submitted_at)),
+                            ))
+                            .get_result::<Score>(conn)
+                            .await?;
+
+                    Ok((previous_score, new_score))
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        // Only the score matters to the rank index, not the plays
+        if previous_score
+            .as_ref()
+            .is_none_or(|previous| previous.score != new_score.score)
+        {
+            store
+                .invalidate_rank_index(new_score.song_id, new_score.league)
                 .await?;
+        }
 
-            Ok(new_score)
+        // Swap the old score's skill points for the new ones in the rankings
+        let previous_skill_points = previous_score.as_ref().map_or(0, Score::get_skill_points);
+        if new_score.get_skill_points() != previous_skill_points {
+            rankings::record_change(
+                &new_score,
+                LedgerReason::Submission,
+                previous_skill_points,
+                new_score.get_skill_points(),
+                conn,
+                store,
+            )
+            .await?;
         }
+
+        Ok(new_score)
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feat_count() {
+        let counts = FeatCount::count(vec![
+            FeatTally {
+                feat: "Stealth".to_owned(),
+                scores: 3,
+            },
+            // Feats that aren't known here are left out
+            FeatTally {
+                feat: "Ninja".to_owned(),
+                scores: 7,
+            },
+        ]);
+        assert_eq!(
+            counts,
+            vec![
+                FeatCount {
+                    feat: Feat::CleanFinish,
+                    scores: 0,
+                },
+                FeatCount {
+                    feat: Feat::Stealth,
+                    scores: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_feat_leader_query() {
+        let query = FeatLeader::query(Feat::CleanFinish, Some(12), 2, 50);
+        let sql = diesel::debug_query::<Pg, _>(&query).to_string();
+        assert!(sql.contains("feats @> ARRAY[$1]"));
+        assert!(sql.contains(r#"binds: ["Clean Finish", Some(12), "main", 50, 100]"#));
+
+        // Pages far out don't overflow
+        let query = FeatLeader::query(Feat::Stealth, None, i64::MAX, 50);
+        let sql = diesel::debug_query::<Pg, _>(&query).to_string();
+        assert!(sql.contains(&format!(
+            r#"binds: ["Stealth", None, "main", 50, {}]"#,
+            i64::MAX
+        )));
     }
 }
@@ -0,0 +1,325 @@
+//! Scores of songs nobody rode in a long time, kept in the `scores_archive` table instead of `scores`.
+//!
+//! The job worker archives them daily, see `jobs.archive_scores_after_years` in the config. Archived scores are
+//! left out of everything that goes through [`Score::all`], like the leaderboards, but they keep counting towards
+//! their players' skill points, and the API shows them when asked to (`includeArchived`). Riding the song again
+//! brings them back, see [`Score::restore_archived`].
+
+use diesel::{
+    dsl::{exists, not},
+    prelude::*,
+    sql_query,
+    sql_types::{Array, Integer},
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use time::OffsetDateTime;
+
+use crate::{
+    models::{players::PlayerPublic, rivalries::Rivalry, scores::Score, songs::Song},
+    schema::{gold_thresholds, players, scores, scores_archive, songs},
+    util::{errors::WavebreakerError, game_types::League, rank_cache, ranking_store::RankingStore},
+};
+
+/// Songs archived in one transaction
+const BATCH_SIZE: i64 = 100;
+
+/// Moves the scores of the songs (`$1`) that aren't deleted into the archive. Deleted scores stay, to be purged.
+/// They're inserted before they're deleted, so the `scores_delete_references` trigger keeps their references.
+const ARCHIVE_STATEMENTS: [&str; 3] = [
+    "INSERT INTO scores_archive ( \
+         id, song_id, player_id, league, submitted_at, play_count, score, track_shape, xstats, density, vehicle, \
+         feats, song_length, gold_threshold, iss, isj, deleted_at, realm \
+     ) \
+     SELECT id, song_id, player_id, league, submitted_at, play_count, score, track_shape, xstats, density, vehicle, \
+         feats, song_length, gold_threshold, iss, isj, deleted_at, realm \
+     FROM scores WHERE song_id = ANY($1) AND deleted_at IS NULL",
+    "DELETE FROM scores WHERE song_id = ANY($1) AND deleted_at IS NULL",
+    "UPDATE songs SET scores_archived_at = now() WHERE id = ANY($1)",
+];
+
+/// Moves the archived scores of a song (`$1`) back, see [`ARCHIVE_STATEMENTS`].
+///
+/// A score can be on the same leaderboard as an archived one if it was unhidden in the meantime. Like when merging
+/// players, the higher one stays (the live one on a tie) and gets the plays of both, the other one comes back deleted,
+/// so it can be restored. Only the archived scores that made it back are deleted from the archive.
+const RESTORE_STATEMENTS: [&str; 6] = [
+    "UPDATE scores live SET play_count = live.play_count + archived.play_count FROM scores_archive archived \
+     WHERE archived.song_id = $1 AND live.song_id = $1 AND live.player_id = archived.player_id \
+     AND live.league = archived.league AND live.deleted_at IS NULL AND archived.deleted_at IS NULL \
+     AND live.score >= archived.score",
+    "UPDATE scores_archive archived SET play_count = archived.play_count + live.play_count FROM scores live \
+     WHERE archived.song_id = $1 AND live.song_id = $1 AND live.player_id = archived.player_id \
+     AND live.league = archived.league AND live.deleted_at IS NULL AND archived.deleted_at IS NULL \
+     AND archived.score > live.score",
+    "UPDATE scores live SET deleted_at = now() FROM scores_archive archived \
+     WHERE archived.song_id = $1 AND live.song_id = $1 AND live.player_id = archived.player_id \
+     AND live.league = archived.league AND live.deleted_at IS NULL AND archived.deleted_at IS NULL \
+     AND archived.score > live.score",
+    "INSERT INTO scores ( \
+         id, song_id, player_id, league, submitted_at, play_count, score, track_shape, xstats, density, vehicle, \
+         feats, song_length, gold_threshold, iss, isj, deleted_at, realm \
+     ) \
+     SELECT id, song_id, player_id, league, submitted_at, play_count, score, track_shape, xstats, density, vehicle, \
+         feats, song_length, gold_threshold, iss, isj, \
+         CASE WHEN EXISTS ( \
+             SELECT 1 FROM scores live \
+             WHERE live.song_id = $1 AND live.player_id = archived.player_id AND live.league = archived.league \
+             AND live.deleted_at IS NULL \
+         ) THEN COALESCE(deleted_at, now()) ELSE deleted_at END, \
+         realm \
+     FROM scores_archive archived WHERE song_id = $1 \
+     ON CONFLICT DO NOTHING",
+    "DELETE FROM scores_archive WHERE song_id = $1 AND id IN (SELECT id FROM scores WHERE song_id = $1)",
+    "UPDATE songs SET scores_archived_at = NULL WHERE id = $1",
+];
+
+/// The statement of [`RESTORE_STATEMENTS`] moving the scores back
+const RESTORE_MOVING_STATEMENT: usize = 3;
+
+/// The columns of `scores_archive` that make up a [`Score`], in its order.
+type ScoreColumns = (
+    scores_archive::id,
+    scores_archive::song_id,
+    scores_archive::player_id,
+    scores_archive::league,
+    scores_archive::submitted_at,
+    scores_archive::play_count,
+    scores_archive::score,
+    scores_archive::track_shape,
+    scores_archive::xstats,
+    scores_archive::density,
+    scores_archive::vehicle,
+    scores_archive::feats,
+    scores_archive::song_length,
+    scores_archive::gold_threshold,
+    scores_archive::iss,
+    scores_archive::isj,
+    scores_archive::deleted_at,
+    scores_archive::realm,
+);
+
+/// Selects an archived score as a [`Score`].
+const AS_SCORE: ScoreColumns = (
+    scores_archive::id,
+    scores_archive::song_id,
+    scores_archive::player_id,
+    scores_archive::league,
+    scores_archive::submitted_at,
+    scores_archive::play_count,
+    scores_archive::score,
+    scores_archive::track_shape,
+    scores_archive::xstats,
+    scores_archive::density,
+    scores_archive::vehicle,
+    scores_archive::feats,
+    scores_archive::song_length,
+    scores_archive::gold_threshold,
+    scores_archive::iss,
+    scores_archive::isj,
+    scores_archive::deleted_at,
+    scores_archive::realm,
+);
+
+impl Score {
+    /// Archives the scores of every song nobody rode since `cutoff`. Songs that are newer than that are left alone.
+    /// A ride counts even if it didn't improve a score, going by the gold thresholds the game reported with it.
+    ///
+    /// # Returns
+    /// How many songs and scores were archived.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB or Redis.
+    pub async fn archive_untouched(
+        cutoff: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+        store: &mut impl RankingStore,
+    ) -> Result<(usize, usize), WavebreakerError> {
+        let mut archived_songs = 0;
+        let mut archived_scores = 0;
+
+        loop {
+            let untouched: Vec<i32> = Song::all()
+                .filter(songs::scores_archived_at.is_null())
+                .filter(songs::created_at.lt(cutoff))
+                .filter(exists(Self::all().filter(scores::song_id.eq(songs::id))))
+                .filter(not(exists(
+                    scores::table
+                        .filter(scores::song_id.eq(songs::id))
+                        .filter(scores::submitted_at.ge(cutoff)),
+                )))
+                .filter(not(exists(
+                    gold_thresholds::table
+                        .filter(gold_thresholds::song_id.eq(songs::id))
+                        .filter(gold_thresholds::last_submitted_at.ge(cutoff)),
+                )))
+                .select(songs::id)
+                .order(songs::id)
+                .limit(BATCH_SIZE)
+                .load(conn)
+                .await?;
+            if untouched.is_empty() {
+                break;
+            }
+
+            archived_scores += conn
+                .transaction(|conn| {
+                    let untouched = &untouched;
+                    async move {
+                        // The first statement is the one moving them
+                        let mut moved = None;
+                        for statement in ARCHIVE_STATEMENTS {
+                            let rows = sql_query(statement)
+                                .bind::<Array<Integer>, _>(untouched)
+                                .execute(conn)
+                                .await?;
+                            moved.get_or_insert(rows);
+                        }
+                        Ok::<_, diesel::result::Error>(moved.unwrap_or_default())
+                    }
+                    .scope_boxed()
+                })
+                .await?;
+            rank_cache::invalidate(&untouched, store).await?;
+            archived_songs += untouched.len();
+        }
+
+        Ok((archived_songs, archived_scores))
+    }
+
+    /// Brings the archived scores of a song back, when it's ridden again or merged.
+    /// Has to be called before anything looks at the song's scores, if the song's scores are archived.
+    ///
+    /// # Returns
+    /// How many scores were brought back.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB or Redis.
+    pub async fn restore_archived(
+        song: i32,
+        conn: &mut AsyncPgConnection,
+        store: &mut impl RankingStore,
+    ) -> Result<usize, WavebreakerError> {
+        let restored = conn
+            .transaction(|conn| {
+                async move {
+                    let mut restored = 0;
+                    for (i, statement) in RESTORE_STATEMENTS.into_iter().enumerate() {
+                        let rows = sql_query(statement)
+                            .bind::<Integer, _>(song)
+                            .execute(conn)
+                            .await?;
+                        if i == RESTORE_MOVING_STATEMENT {
+                            restored = rows;
+                        }
+                    }
+                    Ok::<_, diesel::result::Error>(restored)
+                }
+                .scope_boxed()
+            })
+            .await?;
+        rank_cache::invalidate(&[song], store).await?;
+
+        Ok(restored)
+    }
+
+    /// Gets an archived score by its ID.
+    pub async fn find_archived(
+        find_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        scores_archive::table
+            .find(find_id)
+            .select(AS_SCORE)
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Gets the archived scores of a player in a realm, for computing their skill points.
+    pub async fn archived_of(
+        player: i32,
+        in_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        scores_archive::table
+            .filter(scores_archive::player_id.eq(player))
+            .filter(scores_archive::realm.eq(in_realm))
+            .select(AS_SCORE)
+            .load(conn)
+            .await
+    }
+
+    /// Gets where an archived score stands among the archived scores on its song and league.
+    ///
+    /// # Returns
+    /// The score's rank (1 is the top score) and how many scores there are in total.
+    pub async fn archived_placement(
+        &self,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<(i64, i64)> {
+        use crate::schema::scores_archive::dsl::*;
+
+        let on_leaderboard = || {
+            scores_archive
+                .filter(song_id.eq(self.song_id))
+                .filter(league.eq(self.league))
+        };
+        let better: i64 = on_leaderboard()
+            .filter(score.gt(self.score))
+            .count()
+            .get_result(conn)
+            .await?;
+        let total: i64 = on_leaderboard().count().get_result(conn).await?;
+
+        Ok((better + 1, total))
+    }
+
+    /// Gets a page of the leaderboard a song had when its scores were archived, like [`Score::leaderboard`].
+    pub async fn archived_leaderboard(
+        find_song_id: i32,
+        find_league: Option<League>,
+        mutual_rivals_of: Option<i32>,
+        page: i64,
+        page_size: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(Self, PlayerPublic)>> {
+        use crate::schema::scores_archive::dsl::*;
+
+        let mut query = scores_archive
+            .inner_join(players::table)
+            .filter(song_id.eq(find_song_id))
+            .select((AS_SCORE, PlayerPublic::as_select()))
+            .into_boxed();
+        if let Some(find_league) = find_league {
+            query = query.filter(league.eq(find_league));
+        } else {
+            // Every player's best archived score, the earlier one wins a tie
+            query = query.filter(
+                id.eq_any(
+                    scores_archive
+                        .filter(song_id.eq(find_song_id))
+                        .distinct_on(player_id)
+                        .order((player_id, score.desc(), submitted_at))
+                        .select(id),
+                ),
+            );
+        }
+        if let Some(player) = mutual_rivals_of {
+            let mut player_ids: Vec<i32> = Rivalry::mutual_rivals_of(player, conn)
+                .await?
+                .into_iter()
+                .map(|view| view.rival.id)
+                .collect();
+            player_ids.push(player);
+            query = query.filter(player_id.eq_any(player_ids));
+        }
+        query
+            .order((score.desc(), submitted_at))
+            .limit(page_size)
+            .offset(page.saturating_mul(page_size))
+            .load(conn)
+            .await
+    }
+}
@@ -0,0 +1,149 @@
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{
+    models::players::PlayerPublic,
+    schema::{players, server_records},
+    util::game_types::League,
+};
+
+/// What a server record is about, see [`crate::records`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// The highest score anyone submitted, on any song
+    HighestScore,
+    /// The most leaderboards a player was on top of at once
+    MostNumberOnes,
+    /// The longest a score stayed on top of a leaderboard before it was beaten, in seconds
+    LongestReign,
+    /// The most scores a player submitted on a single day (UTC)
+    MostPlaysInADay,
+}
+
+impl RecordKind {
+    pub const ALL: [Self; 4] = [
+        Self::HighestScore,
+        Self::MostNumberOnes,
+        Self::LongestReign,
+        Self::MostPlaysInADay,
+    ];
+
+    /// Stored in the `kind` column.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::HighestScore => "highest_score",
+            Self::MostNumberOnes => "most_number_ones",
+            Self::LongestReign => "longest_reign",
+            Self::MostPlaysInADay => "most_plays_in_a_day",
+        }
+    }
+}
+
+/// The best anyone did in one of the [`RecordKind`]s in a realm.
+#[derive(Queryable, Selectable, Debug, Serialize)]
+#[diesel(table_name = server_records, check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct ServerRecord {
+    #[serde(skip_serializing)]
+    pub realm: String,
+    /// See [`RecordKind::as_str`]
+    pub kind: String,
+    pub player_id: i32,
+    /// The song the record was set on, for records that are about one
+    pub song_id: Option<i32>,
+    pub league: Option<League>,
+    pub value: i64,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub set_at: OffsetDateTime,
+}
+
+/// An attempt at a record, see [`NewServerRecord::try_break`].
+#[derive(Insertable, Debug)]
+#[diesel(table_name = server_records)]
+pub struct NewServerRecord<'a> {
+    pub realm: &'a str,
+    pub kind: &'a str,
+    pub player_id: i32,
+    pub song_id: Option<i32>,
+    pub league: Option<League>,
+    pub value: i64,
+}
+
+impl NewServerRecord<'_> {
+    /// Makes this the record if it beats the current one, or if there's none yet.
+    ///
+    /// # Returns
+    /// The new record and the one it beat, `None` if it didn't beat anything.
+    pub async fn try_break(
+        &self,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<(ServerRecord, Option<ServerRecord>)>> {
+        use crate::schema::server_records::dsl::*;
+
+        conn.transaction(|conn| {
+            async move {
+                let current: Option<ServerRecord> = server_records
+                    .find((self.realm, self.kind))
+                    .select(ServerRecord::as_select())
+                    .for_update()
+                    .first(conn)
+                    .await
+                    .optional()?;
+                if current
+                    .as_ref()
+                    .is_some_and(|current| current.value >= self.value)
+                {
+                    return Ok(None);
+                }
+
+                let record = diesel::insert_into(server_records)
+                    .values(self)
+                    .on_conflict((realm, kind))
+                    .do_update()
+                    .set((
+                        player_id.eq(self.player_id),
+                        song_id.eq(self.song_id),
+                        league.eq(self.league),
+                        value.eq(self.value),
+                        set_at.eq(OffsetDateTime::now_utc()),
+                    ))
+                    .returning(ServerRecord::as_returning())
+                    .get_result(conn)
+                    .await?;
+
+                Ok(Some((record, current)))
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+}
+
+impl ServerRecord {
+    /// Gets the records of the realm along with who holds them, in the order of [`RecordKind::ALL`].
+    pub async fn for_realm(
+        record_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(Self, PlayerPublic)>> {
+        use crate::schema::server_records::dsl::*;
+
+        let mut records: Vec<(Self, PlayerPublic)> = server_records
+            .inner_join(players::table)
+            .filter(realm.eq(record_realm))
+            .select((Self::as_select(), PlayerPublic::as_select()))
+            .load(conn)
+            .await?;
+        records.sort_by_key(|(record, _)| {
+            RecordKind::ALL
+                .iter()
+                .position(|record_kind| record_kind.as_str() == record.kind)
+        });
+
+        Ok(records)
+    }
+}
@@ -0,0 +1,127 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::schema::shout_reports;
+
+/// What a moderator did about a reported shout.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportResolution {
+    /// The shout was hidden from the game, but kept
+    Hide,
+    /// The shout was deleted
+    Delete,
+    /// The report was unfounded, the shout stays
+    Dismiss,
+}
+
+impl ReportResolution {
+    /// How the resolution is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Hide => "hidden",
+            Self::Delete => "deleted",
+            Self::Dismiss => "dismissed",
+        }
+    }
+}
+
+/// A player's report of a shout they think is abusive.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = shout_reports, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct ShoutReport {
+    pub id: i32,
+    /// `None` if the shout was deleted
+    pub shout_id: Option<i32>,
+    pub reporter_id: i32,
+    pub reason: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub reported_at: OffsetDateTime,
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub resolved_at: Option<OffsetDateTime>,
+    /// A [`ReportResolution`], see [`ReportResolution::as_str`]
+    pub resolution: Option<String>,
+    /// The moderator who resolved the report. `None` if they don't exist anymore.
+    pub resolved_by: Option<i32>,
+}
+
+type Pending =
+    diesel::dsl::Filter<shout_reports::table, diesel::dsl::IsNull<shout_reports::resolved_at>>;
+
+impl ShoutReport {
+    /// Reports no moderator has resolved yet.
+    #[must_use]
+    pub fn pending() -> Pending {
+        shout_reports::table.filter(shout_reports::resolved_at.is_null())
+    }
+
+    /// Permanently deletes all reports that were resolved before `before`.
+    ///
+    /// # Returns
+    /// The number of reports that were purged.
+    pub async fn purge_resolved(
+        before: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        use crate::schema::shout_reports::dsl::*;
+
+        diesel::delete(shout_reports.filter(resolved_at.lt(before)))
+            .execute(conn)
+            .await
+    }
+
+    /// Resolves every pending report of the shout at once, since they're all about the same thing.
+    /// Has to happen before the shout is deleted, its reports can't be found afterwards.
+    ///
+    /// # Returns
+    /// How many reports were resolved.
+    pub async fn resolve_all(
+        target_shout_id: i32,
+        resolution_taken: ReportResolution,
+        moderator_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        use crate::schema::shout_reports::dsl::*;
+
+        diesel::update(Self::pending().filter(shout_id.eq(target_shout_id)))
+            .set((
+                resolved_at.eq(OffsetDateTime::now_utc()),
+                resolution.eq(resolution_taken.as_str()),
+                resolved_by.eq(moderator_id),
+            ))
+            .execute(conn)
+            .await
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = shout_reports)]
+pub struct NewShoutReport<'a> {
+    pub shout_id: i32,
+    pub reporter_id: i32,
+    pub reason: &'a str,
+}
+
+impl<'a> NewShoutReport<'a> {
+    #[must_use]
+    pub const fn new(shout_id: i32, reporter_id: i32, reason: &'a str) -> Self {
+        Self {
+            shout_id,
+            reporter_id,
+            reason,
+        }
+    }
+
+    /// Files the report. A player reporting the same shout twice is a unique violation.
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<ShoutReport> {
+        diesel::insert_into(shout_reports::table)
+            .values(self)
+            .get_result(conn)
+            .await
+    }
+}
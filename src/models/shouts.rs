@@ -16,13 +16,35 @@ pub struct Shout {
     pub author_id: i32,
     pub posted_at: time::OffsetDateTime,
     pub content: String,
+    /// Set when a moderator hid the shout, see [`super::shout_reports`]
+    pub hidden_at: Option<time::OffsetDateTime>,
 }
 
 impl Shout {
+    /// Finds the song's shouts. Hidden ones are left out.
     #[must_use]
     pub fn find_by_song_id(target_id: i32) -> shouts::BoxedQuery<'static, diesel::pg::Pg> {
         use crate::schema::shouts::dsl::*;
-        shouts.filter(song_id.eq(target_id)).into_boxed()
+        shouts
+            .filter(song_id.eq(target_id))
+            .filter(hidden_at.is_null())
+            .into_boxed()
+    }
+
+    /// Hides the shout from the game without deleting it.
+    pub async fn hide(&self, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        use crate::schema::shouts::dsl::*;
+        diesel::update(self)
+            .set(hidden_at.eq(time::OffsetDateTime::now_utc()))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the shout for good. Its reports are kept.
+    pub async fn delete(&self, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        diesel::delete(self).execute(conn).await?;
+        Ok(())
     }
 }
 
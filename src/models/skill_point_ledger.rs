@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+
+use diesel::{dsl::sum, prelude::*};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use super::scores::Score;
+use crate::schema::skill_point_ledger;
+
+/// Why a player's skill points changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerReason {
+    /// A new or improved score
+    Submission,
+    /// A score was deleted, by its player, a moderator or a merge
+    Deletion,
+    /// A deleted score was brought back
+    Restore,
+    /// The player's points were computed again from their scores, after a merge or a sandbagging flag
+    Refresh,
+    /// Everyone's points were computed again, like after installing a new scoring formula
+    Recalculation,
+    /// A moderator added or took away points by hand
+    Adjustment,
+}
+
+impl LedgerReason {
+    /// How the reason is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Submission => "submission",
+            Self::Deletion => "deletion",
+            Self::Restore => "restore",
+            Self::Refresh => "refresh",
+            Self::Recalculation => "recalculation",
+            Self::Adjustment => "adjustment",
+        }
+    }
+}
+
+/// One change to a player's skill points in a realm, see [`crate::util::rankings`].
+///
+/// The deltas of a player add up to their skill points in the rankings, so they can be audited (see
+/// [`crate::util::doctor`]) and charted. Adjustments by moderators aren't in the scores, so the rankings are rebuilt
+/// from the scores plus the adjustments here.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = skill_point_ledger, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct LedgerEntry {
+    pub id: i64,
+    pub player_id: i32,
+    pub realm: String,
+    /// `None` for refreshes, recalculations and adjustments, or once the score is purged
+    pub score_id: Option<i32>,
+    /// A [`LedgerReason`], see [`LedgerReason::as_str`]
+    pub reason: String,
+    pub delta: i32,
+    /// Why a moderator adjusted the points, always set for adjustments
+    pub note: Option<String>,
+    /// The moderator who adjusted the points. `None` for everything else, or if they don't exist anymore.
+    pub created_by: Option<i32>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: OffsetDateTime,
+}
+
+/// Most entries inserted at once, Postgres takes at most 65535 parameters per statement.
+const INSERT_CHUNK_SIZE: usize = 10_000;
+
+impl LedgerEntry {
+    /// Records how the skill points of the score's player changed because of the score.
+    /// Nothing is recorded if they didn't.
+    pub async fn record_score(
+        changed: &Score,
+        changed_because: LedgerReason,
+        change: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::skill_point_ledger::dsl::*;
+
+        if change == 0 {
+            return Ok(());
+        }
+        diesel::insert_into(skill_point_ledger)
+            .values((
+                player_id.eq(changed.player_id),
+                realm.eq(&changed.realm),
+                score_id.eq(changed.id),
+                reason.eq(changed_because.as_str()),
+                delta.eq(change),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records how much the skill points of players in the realm changed when they were computed again,
+    /// given as `(player ID, change)`. Players whose points didn't change are left out.
+    pub async fn record_corrections(
+        in_realm: &str,
+        changed_because: LedgerReason,
+        changes: &[(i32, i32)],
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::skill_point_ledger::dsl::*;
+
+        let changes: Vec<(i32, i32)> = changes
+            .iter()
+            .copied()
+            .filter(|(_, change)| *change != 0)
+            .collect();
+        for chunk in changes.chunks(INSERT_CHUNK_SIZE) {
+            let rows: Vec<_> = chunk
+                .iter()
+                .map(|(player, change)| {
+                    (
+                        player_id.eq(player),
+                        realm.eq(in_realm),
+                        reason.eq(changed_because.as_str()),
+                        delta.eq(change),
+                    )
+                })
+                .collect();
+            diesel::insert_into(skill_point_ledger)
+                .values(rows)
+                .execute(conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Records a moderator adding (or with a negative `change`, taking away) skill points by hand.
+    pub async fn adjust(
+        player: i32,
+        in_realm: &str,
+        change: i32,
+        adjustment_note: &str,
+        moderator_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::skill_point_ledger::dsl::*;
+
+        diesel::insert_into(skill_point_ledger)
+            .values((
+                player_id.eq(player),
+                realm.eq(in_realm),
+                reason.eq(LedgerReason::Adjustment.as_str()),
+                delta.eq(change),
+                note.eq(adjustment_note),
+                created_by.eq(moderator_id),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// The player's entries in the realm, newest first.
+    pub async fn for_player(
+        player: i32,
+        in_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        use crate::schema::skill_point_ledger::dsl::*;
+
+        skill_point_ledger
+            .filter(player_id.eq(player))
+            .filter(realm.eq(in_realm))
+            .order(id.desc())
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// The latest adjustments by moderators in every realm, newest first, to audit them.
+    pub async fn recent_adjustments(
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        use crate::schema::skill_point_ledger::dsl::*;
+
+        skill_point_ledger
+            .filter(reason.eq(LedgerReason::Adjustment.as_str()))
+            .order(id.desc())
+            .limit(limit)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// How many points moderators gave the player in the realm, in total.
+    pub async fn adjustments_of(
+        player: i32,
+        in_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<i64> {
+        use crate::schema::skill_point_ledger::dsl::*;
+
+        Ok(skill_point_ledger
+            .filter(player_id.eq(player))
+            .filter(realm.eq(in_realm))
+            .filter(reason.eq(LedgerReason::Adjustment.as_str()))
+            .select(sum(delta))
+            .first::<Option<i64>>(conn)
+            .await?
+            .unwrap_or_default())
+    }
+
+    /// How many points moderators gave every player in the realm, in total. Players without any are left out.
+    pub async fn all_adjustments(
+        in_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<HashMap<i32, i64>> {
+        use crate::schema::skill_point_ledger::dsl::*;
+
+        Ok(skill_point_ledger
+            .filter(realm.eq(in_realm))
+            .filter(reason.eq(LedgerReason::Adjustment.as_str()))
+            .group_by(player_id)
+            .select((player_id, sum(delta)))
+            .load::<(i32, Option<i64>)>(conn)
+            .await?
+            .into_iter()
+            .map(|(player, total)| (player, total.unwrap_or_default()))
+            .collect())
+    }
+
+    /// What the player's entries in the realm add up to, which should be their skill points.
+    pub async fn total_of(
+        player: i32,
+        in_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<i64> {
+        use crate::schema::skill_point_ledger::dsl::*;
+
+        Ok(skill_point_ledger
+            .filter(player_id.eq(player))
+            .filter(realm.eq(in_realm))
+            .select(sum(delta))
+            .first::<Option<i64>>(conn)
+            .await?
+            .unwrap_or_default())
+    }
+
+    /// What the entries of every player in the realm add up to, which should be their skill points.
+    pub async fn totals(
+        in_realm: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<HashMap<i32, i64>> {
+        use crate::schema::skill_point_ledger::dsl::*;
+
+        Ok(skill_point_ledger
+            .filter(realm.eq(in_realm))
+            .group_by(player_id)
+            .select((player_id, sum(delta)))
+            .load::<(i32, Option<i64>)>(conn)
+            .await?
+            .into_iter()
+            .map(|(player, total)| (player, total.unwrap_or_default()))
+            .collect())
+    }
+}
@@ -0,0 +1,154 @@
+use diesel::{dsl::exists, prelude::*};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{
+    models::metadata_provenance::MetadataSource, schema::song_aliases,
+    util::normalize::normalize_tag,
+};
+
+/// Which tag of the song an alias stands in for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AliasKind {
+    Title,
+    Artist,
+}
+
+impl AliasKind {
+    /// Stored in the `kind` column.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Title => "title",
+            Self::Artist => "artist",
+        }
+    }
+}
+
+/// An alternative title or artist tag that can be matched to a song,
+/// e.g. the old tags of a song that was renamed or merged into another one.
+///
+/// Aliases are stored normalized, see [`crate::util::normalize`].
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, Serialize)]
+#[diesel(belongs_to(super::songs::Song))]
+#[diesel(table_name = song_aliases, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct SongAlias {
+    pub id: i32,
+    pub song_id: i32,
+    /// `title` or `artist`, see [`AliasKind`]
+    pub kind: String,
+    pub alias: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: OffsetDateTime,
+    /// Where the alias came from, see [`MetadataSource`]
+    pub source: String,
+}
+
+impl SongAlias {
+    /// Adds an alias to the song, unless it has it already. The alias is normalized first.
+    ///
+    /// # Returns
+    /// The normalized alias if it was actually added.
+    pub async fn add(
+        song: i32,
+        alias_kind: AliasKind,
+        new_alias: &str,
+        from: MetadataSource,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<String>> {
+        use crate::schema::song_aliases::dsl::*;
+
+        let normalized = normalize_tag(new_alias);
+        let inserted = diesel::insert_into(song_aliases)
+            .values((
+                song_id.eq(song),
+                kind.eq(alias_kind.as_str()),
+                alias.eq(&normalized),
+                source.eq(from.as_str()),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+
+        Ok((inserted > 0).then_some(normalized))
+    }
+
+    /// Removes an alias from the song, if it has it.
+    pub async fn remove(
+        song: i32,
+        alias_kind: AliasKind,
+        old_alias: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::song_aliases::dsl::*;
+
+        diesel::delete(
+            song_aliases
+                .filter(song_id.eq(song))
+                .filter(kind.eq(alias_kind.as_str()))
+                .filter(alias.eq(normalize_tag(old_alias))),
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Normalizes all aliases again, e.g. after the rules changed.
+    /// Aliases that turn into one the song already has are deleted.
+    ///
+    /// # Returns
+    /// How many aliases were changed or deleted.
+    pub async fn normalize_all(conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        use crate::schema::song_aliases::dsl::*;
+
+        let all: Vec<Self> = song_aliases
+            .order(id)
+            .select(Self::as_select())
+            .load(conn)
+            .await?;
+
+        let mut changed = 0;
+        for old in all {
+            let normalized = normalize_tag(&old.alias);
+            if normalized == old.alias {
+                continue;
+            }
+
+            let duplicate = diesel::select(exists(
+                song_aliases
+                    .filter(song_id.eq(old.song_id))
+                    .filter(kind.eq(&old.kind))
+                    .filter(alias.eq(&normalized)),
+            ))
+            .get_result::<bool>(conn)
+            .await?;
+            if duplicate {
+                diesel::delete(&old).execute(conn).await?;
+            } else {
+                diesel::update(&old)
+                    .set(alias.eq(&normalized))
+                    .execute(conn)
+                    .await?;
+            }
+            changed += 1;
+        }
+
+        Ok(changed)
+    }
+
+    /// Gets all aliases of the song, oldest first.
+    pub async fn for_song(song: i32, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::song_aliases::dsl::*;
+
+        song_aliases
+            .filter(song_id.eq(song))
+            .order(id)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+}
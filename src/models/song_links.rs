@@ -0,0 +1,212 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::schema::song_links;
+
+/// Longest URL a link can have
+pub const MAX_URL_LENGTH: usize = 2000;
+
+/// What a link of a song leads to.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LinkKind {
+    OfficialVideo,
+    /// The song on Bandcamp, to buy it
+    Bandcamp,
+    Lyrics,
+    Other,
+}
+
+impl LinkKind {
+    /// In the order they're listed in.
+    pub const ALL: [Self; 4] = [
+        Self::OfficialVideo,
+        Self::Bandcamp,
+        Self::Lyrics,
+        Self::Other,
+    ];
+
+    /// How the kind is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::OfficialVideo => "officialVideo",
+            Self::Bandcamp => "bandcamp",
+            Self::Lyrics => "lyrics",
+            Self::Other => "other",
+        }
+    }
+
+    /// The sites links of the kind have to be on, including their subdomains. Empty if they can be anywhere.
+    #[must_use]
+    pub const fn hosts(self) -> &'static [&'static str] {
+        match self {
+            Self::OfficialVideo => &["youtube.com", "youtu.be", "vimeo.com"],
+            Self::Bandcamp => &["bandcamp.com"],
+            Self::Lyrics | Self::Other => &[],
+        }
+    }
+
+    /// Whether a link of the kind can be on the host, see [`LinkKind::hosts`].
+    fn allows_host(self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.hosts().is_empty()
+            || self.hosts().iter().any(|site| {
+                host == *site
+                    || host
+                        .strip_suffix(site)
+                        .is_some_and(|subdomain| subdomain.ends_with('.'))
+            })
+    }
+}
+
+/// A link a moderator attached to a song, listed on the song's page.
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug, Serialize)]
+#[diesel(belongs_to(super::songs::Song))]
+#[diesel(table_name = song_links, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct SongLink {
+    pub id: i32,
+    pub song_id: i32,
+    /// See [`LinkKind::as_str`]
+    pub kind: String,
+    pub url: String,
+    /// The moderator who added it. `None` if they don't exist anymore.
+    #[serde(skip_serializing)]
+    pub added_by: Option<i32>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub added_at: OffsetDateTime,
+}
+
+impl SongLink {
+    /// Attaches a link to the song. The URL has to be checked with [`SongLink::check_url`] first.
+    ///
+    /// # Returns
+    /// `None` if the song has the link already.
+    pub async fn add(
+        song: i32,
+        link_kind: LinkKind,
+        link_url: &str,
+        moderator_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::song_links::dsl::*;
+
+        diesel::insert_into(song_links)
+            .values((
+                song_id.eq(song),
+                kind.eq(link_kind.as_str()),
+                url.eq(link_url),
+                added_by.eq(moderator_id),
+            ))
+            .on_conflict_do_nothing()
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+            .optional()
+    }
+
+    /// The song's links, by kind (see [`LinkKind::ALL`]) and then in the order they were added.
+    pub async fn for_song(song: i32, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::song_links::dsl::*;
+
+        let mut links: Vec<Self> = song_links
+            .filter(song_id.eq(song))
+            .order(id)
+            .select(Self::as_select())
+            .load(conn)
+            .await?;
+        links.sort_by_key(|link| {
+            LinkKind::ALL
+                .iter()
+                .position(|link_kind| link_kind.as_str() == link.kind)
+        });
+        Ok(links)
+    }
+
+    /// Removes the link from its song.
+    pub async fn delete(&self, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        diesel::delete(self).execute(conn).await?;
+        Ok(())
+    }
+
+    /// Checks that the URL is a web link that's not too long, on a site the kind of link can be on.
+    ///
+    /// # Returns
+    /// What's wrong with it, if anything.
+    #[must_use]
+    pub fn check_url(link_kind: LinkKind, link_url: &str) -> Option<&'static str> {
+        if link_url.len() > MAX_URL_LENGTH {
+            return Some("The URL is too long");
+        }
+        let Some(parsed) = Url::parse(link_url)
+            .ok()
+            .filter(|parsed| matches!(parsed.scheme(), "https" | "http"))
+        else {
+            return Some("The URL has to be an http:// or https:// URL");
+        };
+        match parsed.host_str() {
+            None => Some("The URL has to be an http:// or https:// URL"),
+            Some(host) if !link_kind.allows_host(host) => {
+                Some("The URL isn't on a site for this kind of link")
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_url() {
+        assert_eq!(
+            SongLink::check_url(LinkKind::Lyrics, "https://example.com/lyrics"),
+            None
+        );
+        assert_eq!(
+            SongLink::check_url(LinkKind::Other, "http://example.com"),
+            None
+        );
+        assert!(SongLink::check_url(LinkKind::Other, "ftp://example.com/song.mp3").is_some());
+        assert!(SongLink::check_url(LinkKind::Other, "javascript:alert(1)").is_some());
+        assert!(SongLink::check_url(LinkKind::Other, "example.com").is_some());
+        assert!(SongLink::check_url(LinkKind::Other, "https://").is_some());
+        assert_eq!(
+            SongLink::check_url(
+                LinkKind::Other,
+                &format!("https://example.com/{}", "a".repeat(MAX_URL_LENGTH))
+            ),
+            Some("The URL is too long")
+        );
+    }
+
+    #[test]
+    fn test_check_url_host() {
+        assert_eq!(
+            SongLink::check_url(LinkKind::OfficialVideo, "https://www.youtube.com/watch?v=x"),
+            None
+        );
+        assert_eq!(
+            SongLink::check_url(LinkKind::OfficialVideo, "https://youtu.be/x"),
+            None
+        );
+        assert_eq!(
+            SongLink::check_url(LinkKind::Bandcamp, "https://artist.Bandcamp.com/track/song"),
+            None
+        );
+        assert!(
+            SongLink::check_url(LinkKind::Bandcamp, "https://example.com/bandcamp.com").is_some()
+        );
+        assert!(SongLink::check_url(LinkKind::Bandcamp, "https://notbandcamp.com").is_some());
+        assert!(
+            SongLink::check_url(LinkKind::OfficialVideo, "https://youtube.com.example.com")
+                .is_some()
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{schema::song_quarantine, util::bogus_songs::BogusSongReason};
+
+/// Song tags that were refused because they're clearly not a song.
+/// Kept so admins can check nothing legitimate is being refused.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = song_quarantine, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantinedSong {
+    pub id: i32,
+    pub realm: String,
+    pub title: String,
+    pub artist: String,
+    /// A [`BogusSongReason`], see [`BogusSongReason::as_str`]
+    pub reason: String,
+    /// The player who sent these tags first. `None` if they don't exist anymore.
+    pub first_player_id: Option<i32>,
+    pub times_seen: i32,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub first_seen_at: OffsetDateTime,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub last_seen_at: OffsetDateTime,
+}
+
+impl QuarantinedSong {
+    /// Removes the entry, e.g. after an admin has looked at it.
+    pub async fn delete(&self, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        diesel::delete(self).execute(conn).await?;
+        Ok(())
+    }
+
+    /// Permanently deletes all entries that weren't seen since `before`.
+    ///
+    /// # Returns
+    /// The number of entries that were purged.
+    pub async fn purge_unseen(
+        before: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        use crate::schema::song_quarantine::dsl::*;
+
+        diesel::delete(song_quarantine.filter(last_seen_at.lt(before)))
+            .execute(conn)
+            .await
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = song_quarantine)]
+pub struct NewQuarantinedSong<'a> {
+    pub realm: &'a str,
+    pub title: &'a str,
+    pub artist: &'a str,
+    pub reason: &'a str,
+    pub first_player_id: Option<i32>,
+}
+
+impl<'a> NewQuarantinedSong<'a> {
+    #[must_use]
+    pub const fn new(
+        realm: &'a str,
+        title: &'a str,
+        artist: &'a str,
+        reason: BogusSongReason,
+        first_player_id: Option<i32>,
+    ) -> Self {
+        Self {
+            realm,
+            title,
+            artist,
+            reason: reason.as_str(),
+            first_player_id,
+        }
+    }
+
+    /// Puts the tags into quarantine.
+    /// If they're in there already, they're only counted as seen again.
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<QuarantinedSong> {
+        use crate::schema::song_quarantine::dsl::*;
+
+        diesel::insert_into(song_quarantine)
+            .values(self)
+            .on_conflict((realm, title, artist))
+            .do_update()
+            .set((
+                times_seen.eq(times_seen + 1),
+                last_seen_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .get_result(conn)
+            .await
+    }
+}
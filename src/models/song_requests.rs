@@ -0,0 +1,158 @@
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::schema::{song_request_votes, song_requests};
+
+/// Where a song request is at, stored as [`SongRequestStatus::as_str`].
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SongRequestStatus {
+    /// Waiting for an admin, players can still vote on it
+    Open,
+    /// An admin is going to add the song
+    Accepted,
+    /// The song was added
+    Done,
+    Rejected,
+}
+
+impl SongRequestStatus {
+    /// How the status is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Accepted => "accepted",
+            Self::Done => "done",
+            Self::Rejected => "rejected",
+        }
+    }
+}
+
+/// A song players would like to see added, e.g. to the radio.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = song_requests, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct SongRequest {
+    pub id: i32,
+    pub title: String,
+    pub artist: String,
+    pub note: Option<String>,
+    /// `None` if the player doesn't exist anymore
+    pub requested_by: Option<i32>,
+    pub votes: i32,
+    /// A [`SongRequestStatus`], see [`SongRequestStatus::as_str`]
+    pub status: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub requested_at: OffsetDateTime,
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub resolved_at: Option<OffsetDateTime>,
+    pub resolved_by: Option<i32>,
+    /// Shown to players, e.g. why the request was rejected
+    pub admin_note: Option<String>,
+}
+
+impl SongRequest {
+    /// Adds the player's vote to the request. Voting twice doesn't count twice.
+    ///
+    /// # Returns
+    /// The request with its new vote count.
+    pub async fn vote(&self, player_id: i32, conn: &mut AsyncPgConnection) -> QueryResult<Self> {
+        conn.transaction(|conn| {
+            async move {
+                let added = diesel::insert_into(song_request_votes::table)
+                    .values((
+                        song_request_votes::request_id.eq(self.id),
+                        song_request_votes::player_id.eq(player_id),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .await?;
+                self.add_votes(i32::from(added > 0), conn).await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Takes the player's vote back, if they voted.
+    ///
+    /// # Returns
+    /// The request with its new vote count.
+    pub async fn unvote(&self, player_id: i32, conn: &mut AsyncPgConnection) -> QueryResult<Self> {
+        conn.transaction(|conn| {
+            async move {
+                let removed = diesel::delete(song_request_votes::table.find((self.id, player_id)))
+                    .execute(conn)
+                    .await?;
+                self.add_votes(-i32::from(removed > 0), conn).await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    async fn add_votes(&self, change: i32, conn: &mut AsyncPgConnection) -> QueryResult<Self> {
+        use crate::schema::song_requests::dsl::*;
+
+        diesel::update(self)
+            .set(votes.eq(votes + change))
+            .get_result(conn)
+            .await
+    }
+
+    /// Sets the request's status, as decided by an admin.
+    pub async fn triage(
+        &self,
+        new_status: SongRequestStatus,
+        note_for_players: Option<&str>,
+        admin_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::song_requests::dsl::*;
+
+        // Reopening a request makes it open again for good, it wasn't resolved after all
+        let resolved = (new_status != SongRequestStatus::Open).then(OffsetDateTime::now_utc);
+        diesel::update(self)
+            .set((
+                status.eq(new_status.as_str()),
+                resolved_at.eq(resolved),
+                resolved_by.eq(resolved.map(|_| admin_id)),
+                admin_note.eq(note_for_players),
+            ))
+            .get_result(conn)
+            .await
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = song_requests)]
+pub struct NewSongRequest<'a> {
+    pub title: &'a str,
+    pub artist: &'a str,
+    pub note: Option<&'a str>,
+    pub requested_by: i32,
+}
+
+impl NewSongRequest<'_> {
+    /// Stores the request, with the requester's vote already counted.
+    /// If there's an open request for the same song already, this is a unique violation.
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<SongRequest> {
+        conn.transaction(|conn| {
+            async move {
+                let request: SongRequest = diesel::insert_into(song_requests::table)
+                    .values(self)
+                    .get_result(conn)
+                    .await?;
+                request.vote(self.requested_by, conn).await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+}
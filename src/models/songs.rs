@@ -1,17 +1,35 @@
 use diesel::prelude::*;
-use diesel_async::{AsyncPgConnection, RunQueryDsl, SaveChangesDsl};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use redis::AsyncCommands;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
 use tracing::debug;
 
 use crate::{
     models::{
-        extra_song_info::{ExtraSongInfo, NewExtraSongInfo},
-        players::{AccountType, Player},
+        extra_song_info::{ExtraSongInfo, MetadataEdit, NewExtraSongInfo},
+        merge_log::{MergeLog, MergeManifest, NewMergeLog},
+        metadata_provenance::{MetadataProvenance, MetadataSource, MUSICBRAINZ_FIELDS},
+        players::Player,
         scores::Score,
+        song_aliases::{AliasKind, SongAlias},
     },
     schema::{extra_song_info, songs},
+    util::{
+        errors::WavebreakerError,
+        normalize::normalize_tag,
+        ranking_store::{DeferredRankingStore, RankingStore},
+        realm::MAIN_REALM,
+        redis_keys,
+    },
 };
 
+/// How long (in seconds) a song lookup stays cached, see [`NewSong::find_or_create_cached`].
+const LOOKUP_CACHE_TTL: u64 = 60 * 60 * 24;
+
 #[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
 #[diesel(table_name = songs, check_for_backend(diesel::pg::Pg))]
 #[diesel(primary_key(id))]
@@ -24,140 +42,291 @@ pub struct Song {
     #[serde(serialize_with = "time::serde::iso8601::serialize")]
     pub created_at: time::OffsetDateTime,
     pub modifiers: Option<Vec<Option<String>>>,
+    /// When the song was (soft-)deleted. Deleted songs are hidden by [`Song::all`].
+    #[serde(skip_serializing)]
+    pub deleted_at: Option<time::OffsetDateTime>,
+    /// The realm the song belongs to, see [`crate::util::realm`].
+    pub realm: String,
+    /// The player who submitted the first score on the song. `None` if nobody did yet or they don't exist anymore.
+    pub first_rider_id: Option<i32>,
+    /// Locked songs are left alone by automatic metadata lookups, merges and metadata suggestions.
+    pub locked: bool,
+    /// Only for moderators, e.g. why the song was locked
+    #[serde(skip_serializing)]
+    pub moderator_notes: Option<String>,
+    /// When the song's scores were archived for nobody riding it in a long time, see [`crate::models::scores_archive`].
+    /// `None` if they weren't, or the song was ridden again since.
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub scores_archived_at: Option<time::OffsetDateTime>,
 }
 
+// Types for use with functions that return reusable query fragments
+type All = diesel::dsl::Filter<songs::table, diesel::dsl::IsNull<songs::deleted_at>>;
+
+diesel::define_sql_function!(
+    /// The database's copy of [`normalize_tag`], for tags that aren't stored normalized
+    #[sql_name = "normalize_tag"]
+    fn normalize_tag_sql(x: diesel::sql_types::Text) -> diesel::sql_types::Text
+);
+
 impl Song {
-    /// Deletes the song from the database.
+    /// Returns a query fragment that selects all songs that haven't been deleted.
+    /// Use this instead of `songs::table` unless you *really* want deleted songs, too.
+    #[must_use]
+    pub fn all() -> All {
+        songs::table.filter(songs::deleted_at.is_null())
+    }
+
+    /// Gets the songs of an artist in the realm by name, ordered by title.
+    /// Matches songs whose tags, metadata or artist aliases have the name,
+    /// compared the way song lookups do, see [`crate::util::normalize`].
+    pub async fn by_artist_name(
+        name: &str,
+        in_realm: &str,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        use diesel::dsl::exists;
+
+        use crate::schema::{
+            extra_song_info::dsl::lookup_artist,
+            song_aliases::dsl::{alias, kind, song_aliases, song_id},
+            songs::dsl::{artist, id, realm, title},
+        };
+
+        let normalized_name = normalize_tag(name);
+        let artist_alias = exists(
+            song_aliases
+                .filter(song_id.eq(id))
+                .filter(kind.eq(AliasKind::Artist.as_str()))
+                .filter(alias.eq(&normalized_name)),
+        );
+
+        Self::all()
+            .left_join(extra_song_info::table)
+            .filter(realm.eq(in_realm))
+            .filter(
+                normalize_tag_sql(artist)
+                    .eq(&normalized_name)
+                    .or(lookup_artist.eq(&normalized_name))
+                    .or(artist_alias),
+            )
+            .select(Self::as_select())
+            .order((title, id))
+            .limit(limit)
+            .load(conn)
+            .await
+    }
+
+    /// Drops every cached lookup that resolved to the song, see [`NewSong::find_or_create_cached`].
+    /// Has to be called whenever the song changes in a way that could make lookups resolve differently,
+    /// like it being deleted or losing aliases.
+    pub async fn invalidate_lookups(
+        song_id: i32,
+        store: &mut impl RankingStore,
+    ) -> Result<(), WavebreakerError> {
+        Ok(store.forget_song_lookups(song_id).await?)
+    }
+
+    /// Deletes the song. This is a soft delete, the song and its scores can be brought back with [`Song::restore`]
+    /// until they're purged with [`Song::purge_deleted`].
     ///
     /// # Errors
     /// Fails if something is wrong with the DB or with Redis.
     pub async fn delete(
         &self,
         conn: &mut AsyncPgConnection,
-        redis_conn: &mut deadpool_redis::Connection,
-    ) -> anyhow::Result<()> {
+        store: &mut impl RankingStore,
+    ) -> Result<(), WavebreakerError> {
         use crate::schema::{
-            scores::dsl::{scores, song_id},
-            songs::dsl::{id, songs},
+            scores::dsl::song_id,
+            songs::dsl::{deleted_at, id, songs},
         };
 
+        // Scores deleted along with the song get the same timestamp as the song
+        // so we know which ones to bring back when restoring it
+        let deletion_time = OffsetDateTime::now_utc();
+
         // Manually delete all of the song's scores with our own Score::delete().
         // Necessary because we have to subtract the skill points from Redis
         // Diesel doesn't provide hooks to do it automatically
-        let ass_scores: Vec<Score> = scores
+        let ass_scores: Vec<Score> = Score::all()
             .filter(song_id.eq(self.id))
             .load::<Score>(conn)
             .await?;
         for score in ass_scores {
-            score.delete(conn, redis_conn).await?;
+            score.delete_at(deletion_time, conn, store).await?;
         }
 
-        diesel::delete(songs.filter(id.eq(self.id)))
+        diesel::update(songs.filter(id.eq(self.id)))
+            .set(deleted_at.eq(deletion_time))
+            .execute(conn)
+            .await?;
+        Self::invalidate_lookups(self.id, store).await?;
+        Ok(())
+    }
+
+    /// Restores a deleted song, along with the scores that were deleted with it.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB or with Redis,
+    /// or if a song with the same title, artist and modifiers has been created in the meantime.
+    pub async fn restore(
+        &self,
+        conn: &mut AsyncPgConnection,
+        store: &mut impl RankingStore,
+    ) -> Result<(), WavebreakerError> {
+        use crate::schema::{
+            scores::dsl::{deleted_at as score_deleted_at, scores, song_id},
+            songs::dsl::{deleted_at, id, songs},
+        };
+
+        let Some(deletion_time) = self.deleted_at else {
+            return Ok(());
+        };
+
+        diesel::update(songs.filter(id.eq(self.id)))
+            .set(deleted_at.eq(None::<OffsetDateTime>))
             .execute(conn)
             .await?;
+
+        let ass_scores: Vec<Score> = scores
+            .filter(song_id.eq(self.id))
+            .filter(score_deleted_at.eq(deletion_time))
+            .load::<Score>(conn)
+            .await?;
+        for score in ass_scores {
+            score.restore(conn, store).await?;
+        }
+
         Ok(())
     }
 
+    /// Permanently deletes all songs that were deleted before `before`.
+    /// Their scores, metadata, shouts, etc. go with them.
+    ///
+    /// # Returns
+    /// The number of songs that were purged.
+    pub async fn purge_deleted(
+        before: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        use crate::schema::songs::dsl::*;
+
+        diesel::delete(songs.filter(deleted_at.lt(before)))
+            .execute(conn)
+            .await
+    }
+
     /// Merges this song into another one. `self` will be deleted when it's done.
+    /// Everything the merge changes is recorded in the merge log, so it can be undone with [`MergeLog::undo`].
+    /// Archived scores of either song are brought back first, so they're merged too.
+    /// It all happens in one transaction, a merge that fails halfway leaves both songs as they were.
     ///
     /// # Errors
-    /// When the merge fails or something is wrong with the database, this fails.
+    /// When the merge fails, either song is locked or something is wrong with the database, this fails.
     pub async fn merge_into(
         &self,
         target: i32,
         should_alias: bool,
         conn: &mut AsyncPgConnection,
-        redis_conn: &mut deadpool_redis::Connection,
-    ) -> anyhow::Result<()> {
-        use crate::schema::{scores::dsl::*, songs::dsl::*};
+        store: &mut impl RankingStore,
+    ) -> Result<MergeLog, WavebreakerError> {
+        use crate::schema::scores::dsl::deleted_at;
 
-        let target = songs.find(target).first::<Self>(conn).await?;
-        let mut target_scores: Vec<Score> = Score::belonging_to(&target)
-            .select(Score::as_select())
-            .load::<Score>(conn)
-            .await?;
-        let own_scores: Vec<Score> = Score::belonging_to(&self)
-            .select(Score::as_select())
-            .load::<Score>(conn)
-            .await?;
+        let target = Self::all()
+            .find(target)
+            .first::<Self>(conn)
+            .await
+            .optional()?
+            .ok_or(WavebreakerError::NotFound("Merge target song"))?;
+        if target.realm != self.realm {
+            return Err(WavebreakerError::CrossRealmMerge);
+        }
+        if let Some(locked) = [self, &target].into_iter().find(|song| song.locked) {
+            return Err(WavebreakerError::SongLocked(locked.id));
+        }
+        // Rankings and cached lookups are only changed once everything is committed
+        let (merge, deferred) = conn
+            .transaction::<_, WavebreakerError, _>(|conn| {
+                let target = &target;
+                async move {
+                    let mut deferred = DeferredRankingStore::default();
+                    for song in [self, target] {
+                        if song.scores_archived_at.is_some() {
+                            Score::restore_archived(song.id, conn, &mut deferred).await?;
+                        }
+                    }
+                    let target_scores: Vec<Score> = Score::belonging_to(target)
+                        .filter(deleted_at.is_null())
+                        .select(Score::as_select())
+                        .load::<Score>(conn)
+                        .await?;
+                    let own_scores: Vec<Score> = Score::belonging_to(&self)
+                        .filter(deleted_at.is_null())
+                        .select(Score::as_select())
+                        .load::<Score>(conn)
+                        .await?;
 
-        debug!("Merging song {} into {}", self.id, target.id);
-
-        for mut own_score in own_scores {
-            // Find score with same player and league in the target song
-            match target_scores.iter_mut().find(|found_score| {
-                found_score.player_id == own_score.player_id
-                    && found_score.league == own_score.league
-            }) {
-                Some(target_score) => {
-                    // If the score on the song we want to merge into is lower, we delete that score
-                    // then, we add our song's score to the merge target song
-                    if target_score.score < own_score.score {
-                        target_score.delete(conn, redis_conn).await?;
-                        own_score.song_id = target.id;
-                        own_score.play_count += target_score.play_count;
-                        own_score.save_changes::<Score>(conn).await?;
-                    } else {
-                        target_score.play_count += own_score.play_count;
-                        target_score.save_changes::<Score>(conn).await?;
-                        own_score.delete(conn, redis_conn).await?;
+                    debug!("Merging song {} into {}", self.id, target.id);
+
+                    let mut manifest = MergeManifest::default();
+
+                    for own_score in own_scores {
+                        // Find score with same player and league in the target song
+                        let target_score = target_scores.iter().find(|found_score| {
+                            found_score.player_id == own_score.player_id
+                                && found_score.league == own_score.league
+                        });
+                        manifest.scores.push(
+                            own_score
+                                .move_to_song(target.id, target_score, conn, &mut deferred)
+                                .await?,
+                        );
                     }
-                }
-                None => {
-                    diesel::update(&own_score)
-                        .set(song_id.eq(target.id))
-                        .execute(conn)
+
+                    if should_alias {
+                        //This doesn't merge our own aliases into the target's!
+                        //*Only our artist and title fields* are added to the target's aliases.
+                        manifest.added_title_alias = SongAlias::add(
+                            target.id,
+                            AliasKind::Title,
+                            &self.title,
+                            MetadataSource::MergeAlias,
+                            conn,
+                        )
                         .await?;
-                }
-            }
-        }
+                        manifest.added_artist_alias = SongAlias::add(
+                            target.id,
+                            AliasKind::Artist,
+                            &self.artist,
+                            MetadataSource::MergeAlias,
+                            conn,
+                        )
+                        .await?;
+                    }
 
-        if should_alias {
-            let target_extra_info: Option<ExtraSongInfo> = ExtraSongInfo::belonging_to(&target)
-                .select(ExtraSongInfo::as_select())
-                .first::<ExtraSongInfo>(conn)
-                .await
-                .optional()?;
-
-            if let Some(target_extra_info) = target_extra_info {
-                //This doesn't merge our own alias list into the target's!
-                //*Only our artist and title fields* are added to the target's aliases.
-                target_extra_info
-                    .aliases_artist
-                    .clone()
-                    .unwrap_or_default()
-                    .push(Some(self.artist.clone()));
-                target_extra_info
-                    .aliases_title
-                    .clone()
-                    .unwrap_or_default()
-                    .push(Some(self.title.clone()));
-
-                target_extra_info
-                    .save_changes::<ExtraSongInfo>(conn)
-                    .await?;
-            } else {
-                let new_extra_info = NewExtraSongInfo {
-                    song_id: target.id,
-                    aliases_artist: Some(vec![self.artist.clone()]),
-                    aliases_title: Some(vec![self.title.clone()]),
-                    ..Default::default()
-                };
-                new_extra_info.insert(conn).await?;
-            }
-        }
+                    //Delete this song!
+                    self.delete(conn, &mut deferred).await?;
 
-        //Delete this song!
-        self.delete(conn, redis_conn).await?;
+                    let merge = NewMergeLog::new(self.id, target.id, &manifest)?
+                        .insert(conn)
+                        .await?;
+                    Ok((merge, deferred))
+                }
+                .scope_boxed()
+            })
+            .await?;
+        deferred.apply(store).await?;
 
-        Ok(())
+        Ok(merge)
     }
 
     #[allow(clippy::doc_markdown)]
     /// Automatically adds extra metadata from [MusicBrainz](https://musicbrainz.org) to the song if it doesn't have any.
     ///
     /// This function doesn't check if an existing `ExtraSongInfo` struct lacks info.
-    /// It bails if it finds an existing struct *at all.*
+    /// It bails if it finds an existing struct *at all,* or if the song is locked.
     ///
     /// # Errors
     /// Fails on database error or if the MusicBrainz lookup fails.
@@ -165,9 +334,14 @@ impl Song {
         &self,
         duration: i32,
         conn: &mut AsyncPgConnection,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), WavebreakerError> {
         use crate::util::musicbrainz::lookup_metadata;
 
+        if self.locked {
+            debug!("Song {} is locked, not adding metadata", self.id);
+            return Ok(());
+        }
+
         let extra_info = ExtraSongInfo::belonging_to(self)
             .select(ExtraSongInfo::as_select())
             .first::<ExtraSongInfo>(conn)
@@ -175,12 +349,21 @@ impl Song {
             .optional()?;
 
         if extra_info.is_none() {
-            let metadata = lookup_metadata(self, duration).await?;
+            let metadata = lookup_metadata(self, duration)
+                .await
+                .map_err(WavebreakerError::MusicBrainz)?;
 
             diesel::insert_into(extra_song_info::table)
                 .values((metadata, extra_song_info::song_id.eq(self.id)))
                 .execute(conn)
                 .await?;
+            MetadataProvenance::record(
+                self.id,
+                &MUSICBRAINZ_FIELDS,
+                MetadataSource::AutoMatch,
+                conn,
+            )
+            .await?;
         }
 
         Ok(())
@@ -191,14 +374,20 @@ impl Song {
     /// It updates all relevant fields on the `ExtraSongInfo` struct, if there is one already.
     /// If there isn't, it creates a new one.
     ///
+    /// Fields that came from a source with a higher precedence than `source` are kept as they are, unless `force` is
+    /// set, see [`MetadataSource::precedence`]. If that's all of them, MusicBrainz isn't even asked.
+    ///
     /// # Errors
     /// Fails on database error or if the MusicBrainz lookup fails.
     pub async fn add_metadata_mbid(
         &self,
         mbid: &str,
         release_mbid: Option<&str>,
+        source: MetadataSource,
+        force: bool,
         conn: &mut AsyncPgConnection,
-    ) -> anyhow::Result<()> {
+        store: &mut impl RankingStore,
+    ) -> Result<(), WavebreakerError> {
         use crate::util::musicbrainz::lookup_mbid;
 
         let existing_info = ExtraSongInfo::belonging_to(self)
@@ -206,24 +395,221 @@ impl Song {
             .first::<ExtraSongInfo>(conn)
             .await
             .optional()?;
+        let kept = if existing_info.is_some() && !force {
+            MetadataProvenance::outranking(self.id, source, conn).await?
+        } else {
+            Vec::new()
+        };
+        let updated: Vec<&str> = MUSICBRAINZ_FIELDS
+            .into_iter()
+            .filter(|field| !kept.iter().any(|kept_field| kept_field == field))
+            .collect();
+        if updated.is_empty() {
+            debug!(
+                "Song {} has curated metadata, not replacing it with MBID {mbid} ({})",
+                self.id,
+                source.as_str()
+            );
+            return Ok(());
+        }
 
-        let mb_info = lookup_mbid(mbid, release_mbid).await?;
+        let mut mb_info = lookup_mbid(mbid, release_mbid)
+            .await
+            .map_err(WavebreakerError::MusicBrainz)?;
 
         if let Some(existing_info) = existing_info {
+            mb_info.keep(&kept, &existing_info);
             diesel::update(&existing_info)
                 .set(mb_info)
                 .execute(conn)
                 .await?;
+            // The old title and artist might not match anymore
+            Self::invalidate_lookups(self.id, store).await?;
         } else {
             diesel::insert_into(extra_song_info::table)
                 .values((mb_info, extra_song_info::song_id.eq(self.id)))
                 .execute(conn)
                 .await?;
         }
+        MetadataProvenance::record(self.id, &updated, source, conn).await?;
 
         Ok(())
     }
 
+    /// Changes the song's metadata by hand, creating its `ExtraSongInfo` if it doesn't have one yet.
+    /// The changed fields count as curated, so automatic lookups don't overwrite them anymore.
+    ///
+    /// # Errors
+    /// Fails if the edit doesn't change anything or something is wrong with the database or Redis.
+    pub async fn edit_metadata(
+        &self,
+        edit: &MetadataEdit,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<ExtraSongInfo, WavebreakerError> {
+        let edited = conn
+            .transaction::<_, WavebreakerError, _>(|conn| {
+                async move {
+                    let extra_info = match ExtraSongInfo::belonging_to(self)
+                        .select(ExtraSongInfo::as_select())
+                        .first::<ExtraSongInfo>(conn)
+                        .await
+                        .optional()?
+                    {
+                        Some(extra_info) => extra_info,
+                        None => {
+                            NewExtraSongInfo {
+                                song_id: self.id,
+                                ..Default::default()
+                            }
+                            .insert(conn)
+                            .await?
+                        }
+                    };
+
+                    let edited = diesel::update(&extra_info)
+                        .set(edit)
+                        .get_result::<ExtraSongInfo>(conn)
+                        .await?;
+                    MetadataProvenance::record(
+                        self.id,
+                        &edit.fields(),
+                        MetadataSource::AdminEdit,
+                        conn,
+                    )
+                    .await?;
+                    Ok(edited)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        // The title and artist lookups match against might have changed
+        Self::invalidate_lookups(self.id, redis_conn).await?;
+
+        Ok(edited)
+    }
+
+    /// Credits the player with discovering the song, unless someone else already was.
+    ///
+    /// # Returns
+    /// Whether the player got the credit.
+    pub async fn credit_first_rider(
+        &self,
+        player_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<bool> {
+        use crate::schema::songs::dsl::*;
+
+        // Checked in the query too, two players can submit their first score at the same time
+        let updated = diesel::update(self)
+            .filter(first_rider_id.is_null())
+            .set(first_rider_id.eq(player_id))
+            .execute(conn)
+            .await?;
+        if updated > 0 {
+            debug!("Player {player_id} is the first to ride song {}", self.id);
+        }
+        Ok(updated > 0)
+    }
+
+    /// Locks or unlocks the song and replaces its moderator notes.
+    pub async fn set_moderation(
+        &self,
+        lock: bool,
+        notes: Option<&str>,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::songs::dsl::*;
+
+        diesel::update(self)
+            .set((locked.eq(lock), moderator_notes.eq(notes)))
+            .get_result(conn)
+            .await
+    }
+
+    /// Gets the built-in song with a reserved ID, creating it with the given tags if it doesn't exist yet.
+    /// Built-in songs always belong to the main realm, see [`crate::util::reserved_songs`].
+    ///
+    /// # Returns
+    /// `None` if the song can't be created with that ID, because the tags belong to another song already,
+    /// or if it was deleted.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn find_or_create_reserved(
+        reserved_id: i32,
+        song_title: &str,
+        song_artist: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::songs::dsl::*;
+
+        diesel::insert_into(songs)
+            .values((
+                id.eq(reserved_id),
+                title.eq(song_title),
+                artist.eq(song_artist),
+                realm.eq(MAIN_REALM),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+
+        Self::all()
+            .find(reserved_id)
+            .first::<Self>(conn)
+            .await
+            .optional()
+    }
+
+    /// Changes the song's title and artist, e.g. to fix a typo in its tags.
+    /// The old title and artist are kept as aliases, so the game still finds the song with them.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database or Redis,
+    /// or if the realm already has a song with the new title, artist and modifiers (merge them instead).
+    pub async fn rename(
+        &self,
+        new_title: &str,
+        new_artist: &str,
+        conn: &mut AsyncPgConnection,
+        store: &mut impl RankingStore,
+    ) -> Result<Self, WavebreakerError> {
+        let renamed = conn
+            .transaction::<_, WavebreakerError, _>(|conn| {
+                async move {
+                    SongAlias::add(
+                        self.id,
+                        AliasKind::Title,
+                        &self.title,
+                        MetadataSource::RenameAlias,
+                        conn,
+                    )
+                    .await?;
+                    SongAlias::add(
+                        self.id,
+                        AliasKind::Artist,
+                        &self.artist,
+                        MetadataSource::RenameAlias,
+                        conn,
+                    )
+                    .await?;
+
+                    Ok(diesel::update(self)
+                        .set((songs::title.eq(new_title), songs::artist.eq(new_artist)))
+                        .get_result::<Self>(conn)
+                        .await?)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        Self::invalidate_lookups(self.id, store).await?;
+
+        Ok(renamed)
+    }
+
     /// Checks if a user is allowed to edit a song's metadata.
     /// This is allowed if the user is a moderator/Wavebreaker team member, or if they set the first score on the song.
     ///
@@ -237,21 +623,25 @@ impl Song {
         &self,
         player_id: i32,
         conn: &mut AsyncPgConnection,
-    ) -> anyhow::Result<bool> {
+    ) -> Result<bool, WavebreakerError> {
         use crate::schema::{
             players::dsl::players,
-            scores::dsl::{scores, song_id, submitted_at},
+            scores::dsl::{song_id, submitted_at},
         };
 
-        let player = players.find(player_id).first::<Player>(conn).await?;
+        let player = players
+            .find(player_id)
+            .first::<Player>(conn)
+            .await
+            .optional()?
+            .ok_or(WavebreakerError::NotFound("Player"))?;
 
-        if player.account_type == AccountType::Moderator || player.account_type == AccountType::Team
-        {
+        if player.is_staff() {
             return Ok(true);
         }
 
         //Get first score of song
-        let first_score = scores
+        let first_score = Score::all()
             .filter(song_id.eq(self.id))
             .order(submitted_at.asc())
             .first::<Score>(conn)
@@ -273,6 +663,7 @@ pub struct NewSong<'a> {
     pub title: &'a str,
     pub artist: &'a str,
     pub modifiers: Option<Vec<&'a str>>,
+    pub realm: &'a str,
 }
 
 impl<'a> NewSong<'a> {
@@ -281,20 +672,31 @@ impl<'a> NewSong<'a> {
     /// # Arguments
     /// * `title` - The title of the song.
     /// * `artist` - The artist of the song.
+    /// * `modifiers` - The modifiers parsed from the title.
+    /// * `realm` - The realm the song is looked up/created in.
     ///
     /// # Returns
     /// A new `NewSong` instance.
     #[must_use]
-    pub const fn new(title: &'a str, artist: &'a str, modifiers: Option<Vec<&'a str>>) -> Self {
+    pub const fn new(
+        title: &'a str,
+        artist: &'a str,
+        modifiers: Option<Vec<&'a str>>,
+        realm: &'a str,
+    ) -> Self {
         Self {
             title,
             artist,
             modifiers,
+            realm,
         }
     }
 
     /// Finds or creates a song in the database.
     ///
+    /// Exact title/artist matches are looked up first, since they're by far the most common.
+    /// Only if that fails, the extra song info (metadata) and the aliases are checked.
+    ///
     /// # Arguments
     /// * `conn` - The mutable reference to the database connection.
     ///
@@ -304,45 +706,185 @@ impl<'a> NewSong<'a> {
     /// # Errors
     /// This fails if the query or DB connection fail.
     pub async fn find_or_create(&self, conn: &mut AsyncPgConnection) -> QueryResult<Song> {
-        use diesel::sql_types::{Nullable, Text};
+        Ok(self.find_or_insert(conn).await?.0)
+    }
+
+    /// Does the work of [`NewSong::find_or_create`], and also tells whether the song was just created.
+    async fn find_or_insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<(Song, bool)> {
+        if let Some(song) = self.find(conn).await? {
+            return Ok((song, false));
+        }
+        Ok((
+            diesel::insert_into(songs::table)
+                .values(self)
+                .get_result(conn)
+                .await?,
+            true,
+        ))
+    }
+
+    /// Finds the song like [`NewSong::find_or_create`], without creating it if it doesn't exist.
+    async fn find(&self, conn: &mut AsyncPgConnection) -> QueryResult<Option<Song>> {
+        use diesel::dsl::exists;
 
         use crate::schema::{
-            extra_song_info::dsl::{
-                aliases_artist, aliases_title, musicbrainz_artist, musicbrainz_title,
-            },
-            songs::dsl::{artist, title},
+            extra_song_info::dsl::{lookup_artist, lookup_title},
+            song_aliases::dsl::{alias, kind, song_aliases, song_id},
+            songs::dsl::{artist, id, realm, title},
         };
 
-        // diesel doesn't have support for the lower function out of the box
-        define_sql_function!(fn lower(x: Nullable<Text> ) -> Nullable<Text>);
-
-        // the alias arrays and the musicbrainz data have to play by the game's rules
-        // or else we can never match them with what the game sends!
-        // for arrays: lowercase (the lower function wont work on arrays)
-        // for all of them: "&" replaced with "and", potentially other changes by the client too!
-        // can we fix this in the hook? what do we do?!
-        let title_predicate = title.eq(self.title).or(lower(musicbrainz_title)
-            .eq(self.title)
-            .or(aliases_title.contains(vec![self.title])));
-        let artist_predicate = artist.eq(self.artist).or(lower(musicbrainz_artist)
-            .eq(self.artist)
-            .or(aliases_artist.contains(vec![self.artist])));
-
-        match songs::table
-            .left_join(extra_song_info::table)
-            .select((Song::as_select(), Option::<ExtraSongInfo>::as_select()))
-            .filter(title_predicate.and(artist_predicate))
-            .first::<(Song, Option<ExtraSongInfo>)>(conn)
+        // Covered by songs_unique_data
+        if let Some(song) = Song::all()
+            .filter(realm.eq(self.realm))
+            .filter(title.eq(self.title))
+            .filter(artist.eq(self.artist))
+            .first::<Song>(conn)
             .await
             .optional()?
         {
-            Some(song_extended) => Ok(song_extended.0),
-            None => {
-                diesel::insert_into(songs::table)
-                    .values(self)
-                    .get_result(conn)
-                    .await
-            }
+            return Ok(Some(song));
+        }
+
+        // The game mangles tags before sending them, MusicBrainz data and aliases are stored normalized
+        // so they can be compared with the game's tags after normalizing those the same way
+        let normalized_title = normalize_tag(self.title);
+        let normalized_artist = normalize_tag(self.artist);
+        let title_alias = exists(
+            song_aliases
+                .filter(song_id.eq(id))
+                .filter(kind.eq(AliasKind::Title.as_str()))
+                .filter(alias.eq(&normalized_title)),
+        );
+        let artist_alias = exists(
+            song_aliases
+                .filter(song_id.eq(id))
+                .filter(kind.eq(AliasKind::Artist.as_str()))
+                .filter(alias.eq(&normalized_artist)),
+        );
+        let extra_title_predicate = lookup_title.eq(&normalized_title).or(title_alias);
+        let extra_artist_predicate = lookup_artist.eq(&normalized_artist).or(artist_alias);
+        let title_predicate = title.eq(self.title).or(extra_title_predicate);
+        let artist_predicate = artist.eq(self.artist).or(extra_artist_predicate);
+
+        // No exact match, so at least one side has to match the extra info or an alias.
+        // Filtering on that explicitly lets Postgres find candidates through the indexes on extra_song_info
+        // and song_aliases instead of evaluating the whole predicate for every song.
+        Song::all()
+            .left_join(extra_song_info::table)
+            .select(Song::as_select())
+            .filter(realm.eq(self.realm))
+            .filter(extra_title_predicate.or(extra_artist_predicate))
+            .filter(title_predicate.and(artist_predicate))
+            .first::<Song>(conn)
+            .await
+            .optional()
+    }
+
+    /// Like [`NewSong::find_or_create`], but the result is cached in Redis.
+    /// Every player looks up the same few popular songs, so this saves a lot of lookups.
+    ///
+    /// The cache is keyed by the normalized title and artist. Cached lookups are dropped by [`Song::invalidate_lookups`]
+    /// and expire after a day either way.
+    ///
+    /// # Returns
+    /// The song, and whether it was just created.
+    ///
+    /// # Errors
+    /// This fails if something is wrong with the DB or with Redis.
+    pub async fn find_or_create_cached(
+        &self,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<(Song, bool), WavebreakerError> {
+        if let Some(song) = self.cached(conn, redis_conn).await? {
+            return Ok((song, false));
+        }
+
+        let (song, created) = self.find_or_insert(conn).await?;
+        self.cache(&song, redis_conn).await?;
+
+        Ok((song, created))
+    }
+
+    /// Like [`NewSong::find_or_create_cached`], but the song isn't created if it doesn't exist.
+    /// For players who aren't allowed to create songs, see [`crate::util::song_creation`].
+    ///
+    /// # Errors
+    /// This fails if something is wrong with the DB or with Redis.
+    pub async fn find_cached(
+        &self,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<Option<Song>, WavebreakerError> {
+        if let Some(song) = self.cached(conn, redis_conn).await? {
+            return Ok(Some(song));
+        }
+
+        let Some(song) = self.find(conn).await? else {
+            return Ok(None);
+        };
+        self.cache(&song, redis_conn).await?;
+
+        Ok(Some(song))
+    }
+
+    /// The song the lookup is cached as, if it's cached and the song still exists.
+    async fn cached(
+        &self,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<Option<Song>, WavebreakerError> {
+        let lookup_key = redis_keys::song_lookup(&self.lookup_hash());
+
+        let Some(cached_id) = redis_conn.get::<_, Option<i32>>(&lookup_key).await? else {
+            return Ok(None);
+        };
+        // Deleted songs should've been invalidated already, but better safe than sorry
+        Ok(Song::all()
+            .find(cached_id)
+            .first::<Song>(conn)
+            .await
+            .optional()?)
+    }
+
+    /// Caches the lookup as the song.
+    async fn cache(
+        &self,
+        song: &Song,
+        redis_conn: &mut deadpool_redis::Connection,
+    ) -> Result<(), WavebreakerError> {
+        let lookup_key = redis_keys::song_lookup(&self.lookup_hash());
+        let song_lookups_key = redis_keys::song_lookups(song.id);
+        redis::pipe()
+            .atomic()
+            .set_ex(&lookup_key, song.id, LOOKUP_CACHE_TTL)
+            .ignore()
+            .sadd(&song_lookups_key, &lookup_key)
+            .ignore()
+            .expire(
+                &song_lookups_key,
+                LOOKUP_CACHE_TTL.try_into().unwrap_or(i64::MAX),
+            )
+            .ignore()
+            .query_async::<()>(redis_conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Identifies the lookup for [`NewSong::find_or_create_cached`].
+    /// Tags are normalized like everywhere else in song lookups, see [`crate::util::normalize`].
+    fn lookup_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.realm.as_bytes());
+        hasher.update([0]);
+        hasher.update(normalize_tag(self.title).as_bytes());
+        hasher.update([0]);
+        hasher.update(normalize_tag(self.artist).as_bytes());
+        for modifier in self.modifiers.iter().flatten() {
+            hasher.update([0]);
+            hasher.update(modifier.as_bytes());
         }
+        format!("{:x}", hasher.finalize())
     }
 }
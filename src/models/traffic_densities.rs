@@ -0,0 +1,84 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{
+    schema::traffic_densities,
+    util::game_types::{Character, League},
+};
+
+/// Densities reported fewer times than this aren't trusted enough to check submissions against.
+const MIN_TRUSTED_SUBMISSIONS: i32 = 3;
+
+/// A traffic density the game reported for a song, league and character, and how often it did.
+/// The game generates the traffic from the song, so everyone riding the same song should report the same one.
+#[derive(Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = traffic_densities, check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficDensity {
+    #[serde(skip_serializing)]
+    pub song_id: i32,
+    pub league: League,
+    pub vehicle: Character,
+    pub density: i32,
+    pub submissions: i32,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub last_submitted_at: OffsetDateTime,
+}
+
+impl TrafficDensity {
+    /// Counts a density reported by the game.
+    pub async fn record(
+        song: i32,
+        ridden_league: League,
+        character: Character,
+        reported: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::traffic_densities::dsl::*;
+
+        diesel::insert_into(traffic_densities)
+            .values((
+                song_id.eq(song),
+                league.eq(ridden_league),
+                vehicle.eq(character),
+                density.eq(reported),
+            ))
+            .on_conflict((song_id, league, vehicle, density))
+            .do_update()
+            .set((
+                submissions.eq(submissions + 1),
+                last_submitted_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gets the consensus density of the song for one league and character, which is the one reported most often.
+    pub async fn consensus(
+        song: i32,
+        ridden_league: League,
+        character: Character,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::traffic_densities::dsl::*;
+
+        traffic_densities
+            .filter(song_id.eq(song))
+            .filter(league.eq(ridden_league))
+            .filter(vehicle.eq(character))
+            .order_by((submissions.desc(), last_submitted_at.desc()))
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Whether the consensus is backed by enough submissions to check rides against.
+    #[must_use]
+    pub const fn is_trusted(&self) -> bool {
+        self.submissions >= MIN_TRUSTED_SUBMISSIONS
+    }
+}
@@ -0,0 +1,150 @@
+use diesel::{prelude::*, upsert};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{
+    schema::traffic_flags,
+    util::{
+        game_types::{Character, League},
+        traffic::TrafficProblem,
+    },
+};
+
+/// A ride whose traffic didn't add up, see [`crate::util::traffic`].
+///
+/// A player has one flag per leaderboard. While it's active, more rides there that don't add up only update it.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = traffic_flags, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficFlag {
+    pub id: i32,
+    pub player_id: i32,
+    pub song_id: i32,
+    pub league: League,
+    pub vehicle: Character,
+    /// See [`TrafficProblem::as_str`]
+    pub reason: String,
+    /// What the ride claimed
+    pub density: i32,
+    pub xstats: Vec<Option<i32>>,
+    /// In centiseconds
+    pub song_length: i32,
+    /// The consensus density of the song when the ride was flagged, if there was a trusted one
+    pub expected_density: Option<i32>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub flagged_at: OffsetDateTime,
+    /// When a moderator decided the ride is fine, `None` while the flag is active
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub cleared_at: Option<OffsetDateTime>,
+    /// The moderator who cleared the flag. `None` if they don't exist anymore.
+    pub cleared_by: Option<i32>,
+}
+
+/// A ride to flag.
+pub struct FlaggedRide<'a> {
+    pub player_id: i32,
+    pub song_id: i32,
+    pub league: League,
+    pub vehicle: Character,
+    pub density: i32,
+    pub xstats: &'a [i32],
+    pub song_length: i32,
+    pub expected_density: Option<i32>,
+}
+
+impl TrafficFlag {
+    /// Flags the ride, replacing the player's flag on the leaderboard, active or not.
+    pub async fn flag(
+        ride: &FlaggedRide<'_>,
+        problem: TrafficProblem,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        use crate::schema::traffic_flags::dsl::*;
+
+        diesel::insert_into(traffic_flags)
+            .values((
+                player_id.eq(ride.player_id),
+                song_id.eq(ride.song_id),
+                league.eq(ride.league),
+                vehicle.eq(ride.vehicle),
+                reason.eq(problem.as_str()),
+                density.eq(ride.density),
+                xstats.eq(ride.xstats),
+                song_length.eq(ride.song_length),
+                expected_density.eq(ride.expected_density),
+            ))
+            .on_conflict((player_id, song_id, league))
+            .do_update()
+            .set((
+                vehicle.eq(upsert::excluded(vehicle)),
+                reason.eq(upsert::excluded(reason)),
+                density.eq(upsert::excluded(density)),
+                xstats.eq(upsert::excluded(xstats)),
+                song_length.eq(upsert::excluded(song_length)),
+                expected_density.eq(upsert::excluded(expected_density)),
+                flagged_at.eq(OffsetDateTime::now_utc()),
+                cleared_at.eq(None::<OffsetDateTime>),
+                cleared_by.eq(None::<i32>),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// Flags no moderator cleared yet, the newest first.
+    pub async fn active(limit: i64, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::traffic_flags::dsl::*;
+
+        traffic_flags
+            .filter(cleared_at.is_null())
+            .order(flagged_at.desc())
+            .limit(limit)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Whether the player has an active flag on the leaderboard.
+    pub async fn is_active_on(
+        player: i32,
+        song: i32,
+        in_league: League,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<bool> {
+        use crate::schema::traffic_flags::dsl::*;
+
+        diesel::select(diesel::dsl::exists(
+            traffic_flags
+                .filter(player_id.eq(player))
+                .filter(song_id.eq(song))
+                .filter(league.eq(in_league))
+                .filter(cleared_at.is_null()),
+        ))
+        .get_result(conn)
+        .await
+    }
+
+    /// Clears an active flag.
+    ///
+    /// # Returns
+    /// `None` if there's no active flag with that ID.
+    pub async fn clear(
+        flag: i32,
+        moderator_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::traffic_flags::dsl::*;
+
+        diesel::update(traffic_flags.find(flag).filter(cleared_at.is_null()))
+            .set((
+                cleared_at.eq(OffsetDateTime::now_utc()),
+                cleared_by.eq(moderator_id),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+            .optional()
+    }
+}
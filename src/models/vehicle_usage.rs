@@ -0,0 +1,101 @@
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, SmallInt},
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{schema::vehicle_usage, util::game_types::Character};
+
+/// How often a player rode with a character, counted on every submission.
+#[derive(Selectable, Queryable, Debug, Serialize)]
+#[diesel(table_name = vehicle_usage, check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleUsage {
+    #[serde(skip_serializing)]
+    pub player_id: i32,
+    pub vehicle: Character,
+    pub rides: i32,
+    /// Sum of the scores of all rides
+    pub total_score: i64,
+    pub best_score: i32,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub last_ridden_at: OffsetDateTime,
+}
+
+/// How popular a character is across the whole server.
+#[derive(QueryableByName, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleMeta {
+    #[diesel(sql_type = SmallInt)]
+    pub vehicle: Character,
+    /// How many players rode with the character at least once
+    #[diesel(sql_type = BigInt)]
+    pub players: i64,
+    #[diesel(sql_type = BigInt)]
+    pub rides: i64,
+    #[diesel(sql_type = BigInt)]
+    pub average_score: i64,
+}
+
+impl VehicleUsage {
+    /// Counts a ride of the player with the character.
+    pub async fn record(
+        player: i32,
+        character: Character,
+        score: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::vehicle_usage::dsl::*;
+
+        diesel::insert_into(vehicle_usage)
+            .values((
+                player_id.eq(player),
+                vehicle.eq(character),
+                rides.eq(1),
+                total_score.eq(i64::from(score)),
+                best_score.eq(score),
+            ))
+            .on_conflict((player_id, vehicle))
+            .do_update()
+            .set((
+                rides.eq(rides + 1),
+                total_score.eq(total_score + i64::from(score)),
+                best_score.eq(diesel::dsl::sql::<diesel::sql_types::Integer>(
+                    "GREATEST(vehicle_usage.best_score, EXCLUDED.best_score)",
+                )),
+                last_ridden_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gets every character the player rode with, most ridden first.
+    pub async fn for_player(player: i32, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::vehicle_usage::dsl::*;
+
+        vehicle_usage
+            .filter(player_id.eq(player))
+            .order_by((rides.desc(), vehicle))
+            .load(conn)
+            .await
+    }
+}
+
+impl VehicleMeta {
+    /// Gets the popularity of every character anyone rode with, most ridden first.
+    pub async fn load(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        // Sums of big integers are numerics in Postgres, they're cast back since averages of scores fit easily
+        sql_query(
+            "SELECT vehicle, COUNT(*) AS players, SUM(rides)::BIGINT AS rides, \
+             (SUM(total_score) / GREATEST(SUM(rides), 1))::BIGINT AS average_score \
+             FROM vehicle_usage GROUP BY vehicle ORDER BY rides DESC, vehicle",
+        )
+        .load(conn)
+        .await
+    }
+}
@@ -0,0 +1,288 @@
+//! Server-wide records, like the highest score anyone ever submitted. See [`RecordKind`] for all of them.
+//!
+//! Every realm has its own records. They're checked with every score submission by [`check_submission`],
+//! so they're only as old as this module: nothing from before it is counted.
+//! Reigns are counted from when the beaten score was submitted, like the reign the game shows when dethroning.
+//!
+//! Records in the main realm that beat a previous one are announced on Discord, if `records.discord_webhook_url`
+//! is set. The first record of a kind isn't announced, it didn't beat anybody.
+
+use std::time::Duration;
+
+use axum::http::header::CONTENT_TYPE;
+use diesel::{
+    sql_query,
+    sql_types::{BigInt, Integer, Text},
+    QueryableByName,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde_json::json;
+use time::OffsetDateTime;
+use tracing::{error, info, Instrument};
+use url::Url;
+
+use crate::{
+    models::{
+        players::Player,
+        scores::Score,
+        server_records::{NewServerRecord, RecordKind},
+        songs::Song,
+    },
+    util::{realm::MAIN_REALM, redis_keys, redis_ops},
+    AppState,
+};
+
+/// How long the plays of a day are counted for, a day and some slack for the last submissions of it.
+const PLAYS_PER_DAY_TTL_SECS: u64 = 2 * 86_400;
+
+/// A score submission, as far as the records are concerned.
+pub struct Submission<'a> {
+    pub player: &'a Player,
+    pub song: &'a Song,
+    /// The player's score on the song and league after the submission
+    pub score: &'a Score,
+    /// Whether the submitted score is the best one on its leaderboard now
+    pub on_top: bool,
+    /// The player whose score was beaten for the top spot, and how long it was on top in seconds
+    pub dethroned: Option<(&'a str, i32, i64)>,
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+/// Checks whether the submission broke any records, and announces the ones that did.
+///
+/// # Errors
+/// Fails if something is wrong with the DB or Redis.
+pub async fn check_submission(
+    state: &AppState,
+    submission: &Submission<'_>,
+    conn: &mut AsyncPgConnection,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> anyhow::Result<()> {
+    let Submission {
+        player,
+        song,
+        score,
+        ..
+    } = submission;
+    let attempt = |kind: RecordKind, player_id: i32, on_song: bool, value: i64| {
+        let new_record = NewServerRecord {
+            realm: &song.realm,
+            kind: kind.as_str(),
+            player_id,
+            song_id: on_song.then_some(song.id),
+            league: on_song.then_some(score.league),
+            value,
+        };
+        (kind, new_record)
+    };
+
+    let today = state.config.time_zone.today(OffsetDateTime::now_utc());
+    let plays_key = redis_keys::plays_per_day(&song.realm, player.id, today);
+    let plays = redis_ops::incr_in_window(&plays_key, PLAYS_PER_DAY_TTL_SECS, redis_conn)
+        .await?
+        .count;
+
+    // Along with the name of who'd hold the record
+    let mut attempts = vec![
+        (
+            attempt(RecordKind::MostPlaysInADay, player.id, false, plays),
+            player.username.as_str(),
+        ),
+        (
+            attempt(
+                RecordKind::HighestScore,
+                player.id,
+                true,
+                i64::from(score.score),
+            ),
+            player.username.as_str(),
+        ),
+    ];
+    if submission.on_top {
+        let number_ones = number_ones(player.id, &song.realm, conn).await?;
+        attempts.push((
+            attempt(RecordKind::MostNumberOnes, player.id, false, number_ones),
+            player.username.as_str(),
+        ));
+    }
+    if let Some((dethroned_name, dethroned_id, reign_seconds)) = submission.dethroned {
+        attempts.push((
+            attempt(RecordKind::LongestReign, dethroned_id, true, reign_seconds),
+            dethroned_name,
+        ));
+    }
+
+    for ((kind, attempt), holder) in attempts {
+        let Some((record, previous)) = attempt.try_break(conn).await? else {
+            continue;
+        };
+        info!(
+            "Player {} set a new {} record of {} in realm {}",
+            record.player_id, record.kind, record.value, record.realm
+        );
+
+        let Some(webhook_url) = &state.config.records.discord_webhook_url else {
+            continue;
+        };
+        if let Some(previous) = previous.filter(|_| record.realm == MAIN_REALM) {
+            let message = announcement(kind, record.value, previous.value, holder, song);
+            let webhook_url = webhook_url.clone();
+            // Discord being slow isn't worth holding up the submission
+            tokio::spawn(
+                async move {
+                    if let Err(e) = announce(&webhook_url, &message).await {
+                        error!("Failed to announce record on Discord: {e:?}");
+                    }
+                }
+                .in_current_span(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts the leaderboards of the realm the player is on top of. Shared top spots count, too.
+async fn number_ones(
+    player: i32,
+    realm: &str,
+    conn: &mut AsyncPgConnection,
+) -> diesel::QueryResult<i64> {
+    let count: Count = sql_query(
+        "SELECT COUNT(*) AS count FROM scores mine \
+         WHERE mine.player_id = $1 AND mine.realm = $2 AND mine.deleted_at IS NULL \
+         AND NOT EXISTS ( \
+             SELECT 1 FROM scores better \
+             WHERE better.song_id = mine.song_id AND better.league = mine.league \
+             AND better.deleted_at IS NULL AND better.score > mine.score \
+         )",
+    )
+    .bind::<Integer, _>(player)
+    .bind::<Text, _>(realm)
+    .get_result(conn)
+    .await?;
+
+    Ok(count.count)
+}
+
+/// Describes a broken record for people reading it on Discord.
+/// `holder` is the name of the player holding the record now.
+fn announcement(kind: RecordKind, value: i64, previous: i64, holder: &str, song: &Song) -> String {
+    let on_song = format!("{} by {}", song.title, song.artist);
+    match kind {
+        RecordKind::HighestScore => format!(
+            "{holder} scored {value} on {on_song}, the highest score ever! The old record was {previous}."
+        ),
+        RecordKind::MostNumberOnes => format!(
+            "{holder} is on top of {value} leaderboards now, more than anyone ever! The old record was {previous}."
+        ),
+        RecordKind::LongestReign => format!(
+            "{holder} was on top of {on_song} for {} before being dethroned, the longest reign ever! The old record was {}.",
+            format_duration(value),
+            format_duration(previous)
+        ),
+        RecordKind::MostPlaysInADay => format!(
+            "{holder} submitted {value} scores today, the most anyone ever did in a day! The old record was {previous}."
+        ),
+    }
+}
+
+/// Formats seconds as the largest unit that fits, like `3 days`.
+fn format_duration(seconds: i64) -> String {
+    let (amount, unit) = if seconds < 3600 {
+        (seconds / 60, "minute")
+    } else if seconds < 86_400 {
+        (seconds / 3600, "hour")
+    } else {
+        (seconds / 86_400, "day")
+    };
+    if amount == 1 {
+        format!("1 {unit}")
+    } else {
+        format!("{amount} {unit}s")
+    }
+}
+
+/// How long a webhook gets to take a message, so a slow one doesn't hold up the job worker
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Hosts Discord serves webhooks on
+const DISCORD_HOSTS: [&str; 4] = [
+    "discord.com",
+    "discordapp.com",
+    "ptb.discord.com",
+    "canary.discord.com",
+];
+
+/// Whether the URL is a Discord webhook. Webhooks players set can only be those, since the server sends requests to
+/// them on its own: anything else could point it at hosts on its own network.
+#[must_use]
+pub fn is_discord_webhook(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| {
+        url.scheme() == "https"
+            && url.port().is_none()
+            && url.username().is_empty()
+            && url
+                .host_str()
+                .is_some_and(|host| DISCORD_HOSTS.contains(&host))
+            && url.path().starts_with("/api/webhooks/")
+    })
+}
+
+/// Posts a message to a Discord webhook, or anything else taking the same JSON.
+pub async fn announce(webhook_url: &str, message: &str) -> anyhow::Result<()> {
+    reqwest::Client::new()
+        .post(webhook_url)
+        .timeout(WEBHOOK_TIMEOUT)
+        .header(CONTENT_TYPE, "application/json")
+        .body(json!({ "content": message }).to_string())
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(59), "0 minutes");
+        assert_eq!(format_duration(60), "1 minute");
+        assert_eq!(format_duration(7200), "2 hours");
+        assert_eq!(format_duration(86_400 * 3 + 5), "3 days");
+    }
+
+    #[test]
+    fn test_is_discord_webhook() {
+        assert!(is_discord_webhook(
+            "https://discord.com/api/webhooks/123/token"
+        ));
+        assert!(is_discord_webhook(
+            "https://canary.discord.com/api/webhooks/123/token"
+        ));
+        assert!(!is_discord_webhook(
+            "http://discord.com/api/webhooks/123/token"
+        ));
+        assert!(!is_discord_webhook("https://discord.com/channels/123"));
+        assert!(!is_discord_webhook(
+            "https://discord.com:8443/api/webhooks/123/token"
+        ));
+        assert!(!is_discord_webhook(
+            "https://discord.com.evil.example/api/webhooks/1/t"
+        ));
+        assert!(!is_discord_webhook(
+            "https://discord.com@10.0.0.1/api/webhooks/1/t"
+        ));
+        assert!(!is_discord_webhook(
+            "https://169.254.169.254/api/webhooks/1/t"
+        ));
+        assert!(!is_discord_webhook("https://localhost/api/webhooks/1/t"));
+    }
+}
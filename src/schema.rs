@@ -1,5 +1,57 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Int4,
+        name -> Text,
+        key_hash -> Text,
+        requests_per_day -> Int4,
+        burst_per_minute -> Int4,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    daily_player_points (player_id, day) {
+        player_id -> Int4,
+        day -> Date,
+        skill_points -> Int8,
+    }
+}
+
+diesel::table! {
+    daily_song_plays (day, song_id) {
+        day -> Date,
+        song_id -> Int4,
+        plays -> Int4,
+    }
+}
+
+diesel::table! {
+    daily_stats (day) {
+        day -> Date,
+        new_players -> Int4,
+        active_players -> Int4,
+        plays -> Int4,
+        casual_plays -> Int4,
+        pro_plays -> Int4,
+        elite_plays -> Int4,
+        rolled_up_at -> Timestamptz,
+        new_songs -> Int4,
+    }
+}
+
+diesel::table! {
+    events (id) {
+        id -> Int8,
+        kind -> Text,
+        payload -> Jsonb,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     extra_song_info (id) {
         id -> Int4,
@@ -11,8 +63,193 @@ diesel::table! {
         musicbrainz_artist -> Nullable<Text>,
         musicbrainz_length -> Nullable<Int4>,
         mistag_lock -> Bool,
-        aliases_artist -> Nullable<Array<Nullable<Text>>>,
-        aliases_title -> Nullable<Array<Nullable<Text>>>,
+        lookup_title -> Nullable<Text>,
+        lookup_artist -> Nullable<Text>,
+        release_mbid -> Nullable<Text>,
+        release_group_mbid -> Nullable<Text>,
+        artist_mbids -> Array<Nullable<Text>>,
+        release_group_title -> Nullable<Text>,
+        release_track_count -> Nullable<Int4>,
+        cover_colors -> Array<Nullable<Text>>,
+        cover_colors_source -> Nullable<Text>,
+        preview_checked_at -> Nullable<Timestamptz>,
+        deezer_track_id -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    gold_thresholds (song_id, league, vehicle, gold_threshold) {
+        song_id -> Int4,
+        league -> Int2,
+        vehicle -> Int2,
+        gold_threshold -> Int4,
+        submissions -> Int4,
+        last_submitted_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    impersonations (id) {
+        id -> Int4,
+        staff_id -> Int4,
+        player_id -> Int4,
+        reason -> Text,
+        started_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        ended_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    jobs (id) {
+        id -> Int4,
+        payload -> Jsonb,
+        attempts -> Int4,
+        max_attempts -> Int4,
+        run_at -> Timestamptz,
+        locked_at -> Nullable<Timestamptz>,
+        finished_at -> Nullable<Timestamptz>,
+        failed_at -> Nullable<Timestamptz>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    leaderboard_snapshots (id) {
+        id -> Int4,
+        song_id -> Int4,
+        league -> Int2,
+        taken_at -> Timestamptz,
+        placement -> Int4,
+        player_id -> Nullable<Int4>,
+        score -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    merge_log (id) {
+        id -> Int4,
+        source_song_id -> Int4,
+        target_song_id -> Int4,
+        manifest -> Jsonb,
+        merged_at -> Timestamptz,
+        undone_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    metadata_provenance (song_id, field) {
+        song_id -> Int4,
+        field -> Text,
+        source -> Text,
+        recorded_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    metadata_suggestions (id) {
+        id -> Int4,
+        song_id -> Int4,
+        player_id -> Int4,
+        mbid -> Nullable<Text>,
+        release_mbid -> Nullable<Text>,
+        title -> Nullable<Text>,
+        artist -> Nullable<Text>,
+        status -> Text,
+        suggested_at -> Timestamptz,
+        resolved_at -> Nullable<Timestamptz>,
+        resolved_by -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    news_items (id) {
+        id -> Int4,
+        kind -> Text,
+        #[max_length = 500]
+        text -> Varchar,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    notification_links (player_id, channel) {
+        player_id -> Int4,
+        #[max_length = 16]
+        channel -> Varchar,
+        #[max_length = 320]
+        address -> Varchar,
+        #[max_length = 64]
+        code_hash -> Nullable<Varchar>,
+        code_expires_at -> Nullable<Timestamptz>,
+        code_attempts -> Int4,
+        verified_at -> Nullable<Timestamptz>,
+        notify_dethrones -> Bool,
+        notify_moderation -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    pending_songs (id) {
+        id -> Int4,
+        realm -> Text,
+        title -> Text,
+        artist -> Text,
+        mbid -> Nullable<Text>,
+        release_mbid -> Nullable<Text>,
+        first_player_id -> Nullable<Int4>,
+        times_seen -> Int4,
+        first_seen_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        lookup_title -> Text,
+        lookup_artist -> Text,
+    }
+}
+
+diesel::table! {
+    player_messages (id) {
+        id -> Int4,
+        player_id -> Int4,
+        #[max_length = 500]
+        text -> Varchar,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        delivered_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    player_names (id) {
+        id -> Int4,
+        player_id -> Int4,
+        #[max_length = 32]
+        username -> Varchar,
+        used_until -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    player_redirects (player_id) {
+        player_id -> Int4,
+        steam_id -> Text,
+        #[max_length = 32]
+        username -> Varchar,
+        target_id -> Int4,
+        merged_by -> Nullable<Int4>,
+        merged_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    player_slugs (slug) {
+        #[max_length = 32]
+        slug -> Varchar,
+        player_id -> Int4,
+        used_until -> Timestamptz,
     }
 }
 
@@ -27,6 +264,38 @@ diesel::table! {
         account_type -> Int2,
         joined_at -> Timestamptz,
         avatar_url -> Text,
+        share_activity -> Bool,
+        #[max_length = 16]
+        locale -> Nullable<Varchar>,
+        #[max_length = 64]
+        time_zone -> Nullable<Varchar>,
+        #[max_length = 32]
+        slug -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    ride_sources (id) {
+        id -> Int4,
+        score_id -> Int4,
+        #[max_length = 256]
+        user_agent -> Nullable<Varchar>,
+        #[max_length = 16]
+        fingerprint -> Varchar,
+        #[max_length = 8]
+        http_version -> Varchar,
+        #[max_length = 8]
+        region -> Nullable<Varchar>,
+        submitted_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    rival_digests (player_id) {
+        player_id -> Int4,
+        seen_at -> Nullable<Timestamptz>,
+        webhook_url -> Nullable<Text>,
+        sent_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -38,6 +307,53 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    sandbagging_flags (id) {
+        id -> Int4,
+        player_id -> Int4,
+        realm -> Text,
+        elite_points -> Int8,
+        recent_scores -> Int4,
+        recent_casual_scores -> Int4,
+        casual_tops -> Int4,
+        excluded -> Bool,
+        flagged_at -> Timestamptz,
+        cleared_at -> Nullable<Timestamptz>,
+        cleared_by -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    score_appeals (id) {
+        id -> Int4,
+        score_id -> Nullable<Int4>,
+        player_id -> Int4,
+        #[max_length = 1000]
+        comment -> Varchar,
+        evidence_url -> Nullable<Text>,
+        appealed_at -> Timestamptz,
+        resolved_at -> Nullable<Timestamptz>,
+        resolution -> Nullable<Text>,
+        resolution_note -> Nullable<Text>,
+        resolved_by -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    score_removals (id) {
+        id -> Int4,
+        score_id -> Nullable<Int4>,
+        player_id -> Int4,
+        song_id -> Int4,
+        league -> Int2,
+        score -> Int4,
+        #[max_length = 16]
+        action -> Varchar,
+        created_at -> Timestamptz,
+        moderator_id -> Nullable<Int4>,
+    }
+}
+
 diesel::table! {
     scores (id) {
         id -> Int4,
@@ -56,6 +372,44 @@ diesel::table! {
         gold_threshold -> Int4,
         iss -> Int4,
         isj -> Int4,
+        deleted_at -> Nullable<Timestamptz>,
+        realm -> Text,
+    }
+}
+
+diesel::table! {
+    scores_archive (id) {
+        id -> Int4,
+        song_id -> Int4,
+        player_id -> Int4,
+        league -> Int2,
+        submitted_at -> Timestamptz,
+        play_count -> Int4,
+        score -> Int4,
+        track_shape -> Array<Nullable<Int4>>,
+        xstats -> Array<Nullable<Int4>>,
+        density -> Int4,
+        vehicle -> Int2,
+        feats -> Array<Nullable<Text>>,
+        song_length -> Int4,
+        gold_threshold -> Int4,
+        iss -> Int4,
+        isj -> Int4,
+        deleted_at -> Nullable<Timestamptz>,
+        realm -> Text,
+        archived_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    server_records (realm, kind) {
+        realm -> Text,
+        kind -> Text,
+        player_id -> Int4,
+        song_id -> Nullable<Int4>,
+        league -> Nullable<Int2>,
+        value -> Int8,
+        set_at -> Timestamptz,
     }
 }
 
@@ -67,6 +421,99 @@ diesel::table! {
         posted_at -> Timestamptz,
         #[max_length = 240]
         content -> Varchar,
+        hidden_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    shout_reports (id) {
+        id -> Int4,
+        shout_id -> Nullable<Int4>,
+        reporter_id -> Int4,
+        #[max_length = 240]
+        reason -> Varchar,
+        reported_at -> Timestamptz,
+        resolved_at -> Nullable<Timestamptz>,
+        resolution -> Nullable<Text>,
+        resolved_by -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    skill_point_ledger (id) {
+        id -> Int8,
+        player_id -> Int4,
+        realm -> Text,
+        score_id -> Nullable<Int4>,
+        #[max_length = 16]
+        reason -> Varchar,
+        delta -> Int4,
+        note -> Nullable<Text>,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    song_aliases (id) {
+        id -> Int4,
+        song_id -> Int4,
+        kind -> Text,
+        alias -> Text,
+        created_at -> Timestamptz,
+        source -> Text,
+    }
+}
+
+diesel::table! {
+    song_links (id) {
+        id -> Int4,
+        song_id -> Int4,
+        #[max_length = 16]
+        kind -> Varchar,
+        url -> Text,
+        added_by -> Nullable<Int4>,
+        added_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    song_quarantine (id) {
+        id -> Int4,
+        realm -> Text,
+        title -> Text,
+        artist -> Text,
+        reason -> Text,
+        first_player_id -> Nullable<Int4>,
+        times_seen -> Int4,
+        first_seen_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    song_request_votes (request_id, player_id) {
+        request_id -> Int4,
+        player_id -> Int4,
+        voted_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    song_requests (id) {
+        id -> Int4,
+        title -> Text,
+        artist -> Text,
+        #[max_length = 240]
+        note -> Nullable<Varchar>,
+        requested_by -> Nullable<Int4>,
+        votes -> Int4,
+        status -> Text,
+        requested_at -> Timestamptz,
+        resolved_at -> Nullable<Timestamptz>,
+        resolved_by -> Nullable<Int4>,
+        #[max_length = 240]
+        admin_note -> Nullable<Varchar>,
     }
 }
 
@@ -77,20 +524,143 @@ diesel::table! {
         artist -> Text,
         created_at -> Timestamptz,
         modifiers -> Nullable<Array<Nullable<Text>>>,
+        deleted_at -> Nullable<Timestamptz>,
+        realm -> Text,
+        first_rider_id -> Nullable<Int4>,
+        locked -> Bool,
+        moderator_notes -> Nullable<Text>,
+        scores_archived_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    traffic_densities (song_id, league, vehicle, density) {
+        song_id -> Int4,
+        league -> Int2,
+        vehicle -> Int2,
+        density -> Int4,
+        submissions -> Int4,
+        last_submitted_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    traffic_flags (id) {
+        id -> Int4,
+        player_id -> Int4,
+        song_id -> Int4,
+        league -> Int2,
+        vehicle -> Int2,
+        #[max_length = 16]
+        reason -> Varchar,
+        density -> Int4,
+        xstats -> Array<Nullable<Int4>>,
+        song_length -> Int4,
+        expected_density -> Nullable<Int4>,
+        flagged_at -> Timestamptz,
+        cleared_at -> Nullable<Timestamptz>,
+        cleared_by -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    vehicle_usage (player_id, vehicle) {
+        player_id -> Int4,
+        vehicle -> Int2,
+        rides -> Int4,
+        total_score -> Int8,
+        best_score -> Int4,
+        last_ridden_at -> Timestamptz,
     }
 }
 
+diesel::joinable!(api_keys -> players (created_by));
+diesel::joinable!(daily_player_points -> players (player_id));
+diesel::joinable!(daily_song_plays -> songs (song_id));
 diesel::joinable!(extra_song_info -> songs (song_id));
+diesel::joinable!(gold_thresholds -> songs (song_id));
+diesel::joinable!(leaderboard_snapshots -> players (player_id));
+diesel::joinable!(leaderboard_snapshots -> songs (song_id));
+diesel::joinable!(metadata_provenance -> songs (song_id));
+diesel::joinable!(metadata_suggestions -> players (player_id));
+diesel::joinable!(metadata_suggestions -> songs (song_id));
+diesel::joinable!(news_items -> players (created_by));
+diesel::joinable!(notification_links -> players (player_id));
+diesel::joinable!(pending_songs -> players (first_player_id));
+diesel::joinable!(player_messages -> players (player_id));
+diesel::joinable!(player_names -> players (player_id));
+diesel::joinable!(player_slugs -> players (player_id));
+diesel::joinable!(ride_sources -> scores (score_id));
+diesel::joinable!(rival_digests -> players (player_id));
+diesel::joinable!(sandbagging_flags -> players (player_id));
+diesel::joinable!(score_appeals -> players (player_id));
+diesel::joinable!(score_appeals -> scores (score_id));
+diesel::joinable!(score_removals -> players (player_id));
+diesel::joinable!(score_removals -> scores (score_id));
 diesel::joinable!(scores -> players (player_id));
 diesel::joinable!(scores -> songs (song_id));
+diesel::joinable!(scores_archive -> players (player_id));
+diesel::joinable!(scores_archive -> songs (song_id));
+diesel::joinable!(server_records -> players (player_id));
+diesel::joinable!(server_records -> songs (song_id));
+diesel::joinable!(shout_reports -> players (reporter_id));
+diesel::joinable!(shout_reports -> shouts (shout_id));
 diesel::joinable!(shouts -> players (author_id));
 diesel::joinable!(shouts -> songs (song_id));
+diesel::joinable!(skill_point_ledger -> players (player_id));
+diesel::joinable!(skill_point_ledger -> scores (score_id));
+diesel::joinable!(song_aliases -> songs (song_id));
+diesel::joinable!(song_links -> players (added_by));
+diesel::joinable!(song_links -> songs (song_id));
+diesel::joinable!(song_quarantine -> players (first_player_id));
+diesel::joinable!(song_request_votes -> players (player_id));
+diesel::joinable!(song_request_votes -> song_requests (request_id));
+diesel::joinable!(songs -> players (first_rider_id));
+diesel::joinable!(traffic_densities -> songs (song_id));
+diesel::joinable!(traffic_flags -> songs (song_id));
+diesel::joinable!(vehicle_usage -> players (player_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    daily_player_points,
+    daily_song_plays,
+    daily_stats,
+    events,
     extra_song_info,
+    gold_thresholds,
+    impersonations,
+    jobs,
+    leaderboard_snapshots,
+    merge_log,
+    metadata_provenance,
+    metadata_suggestions,
+    news_items,
+    notification_links,
+    pending_songs,
+    player_messages,
+    player_names,
+    player_redirects,
+    player_slugs,
     players,
+    ride_sources,
+    rival_digests,
     rivalries,
+    sandbagging_flags,
+    score_appeals,
+    score_removals,
     scores,
+    scores_archive,
+    server_records,
+    shout_reports,
     shouts,
+    skill_point_ledger,
+    song_aliases,
+    song_links,
+    song_quarantine,
+    song_request_votes,
+    song_requests,
     songs,
+    traffic_densities,
+    traffic_flags,
+    vehicle_usage,
 );
@@ -0,0 +1,157 @@
+//! Where large blobs are kept, like backups.
+//!
+//! Blobs are addressed by keys like `backups/20240917T031500Z/scores.jsonl`. Levels are separated by `/`,
+//! so keys work as paths on the local filesystem and as object keys in S3 alike.
+//! Which backend is used is set in the `storage` section of the config, S3 needs the `s3` feature.
+
+#[cfg(feature = "s3")]
+mod s3;
+
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context};
+use tokio::fs;
+
+use crate::{Storage, StorageBackend};
+
+/// The configured storage backend.
+pub enum BlobStorage {
+    /// Keys are paths below the directory
+    Local(PathBuf),
+    #[cfg(feature = "s3")]
+    S3(s3::S3Storage),
+}
+
+impl BlobStorage {
+    /// Sets up the backend that's configured.
+    ///
+    /// # Errors
+    /// Fails if S3 is configured without a bucket, or Wavebreaker was built without the `s3` feature.
+    // Only setting up S3 needs to wait for anything
+    #[cfg_attr(not(feature = "s3"), allow(clippy::unused_async))]
+    pub(crate) async fn from_config(config: &Storage) -> anyhow::Result<Self> {
+        match config.backend {
+            StorageBackend::Local => Ok(Self::Local(PathBuf::from(&config.directory))),
+            #[cfg(feature = "s3")]
+            StorageBackend::S3 => {
+                let Some(bucket) = &config.s3_bucket else {
+                    bail!("The S3 storage backend needs s3_bucket to be set");
+                };
+                Ok(Self::S3(
+                    s3::S3Storage::new(bucket, &config.s3_prefix).await,
+                ))
+            }
+            #[cfg(not(feature = "s3"))]
+            StorageBackend::S3 => {
+                bail!("The S3 storage backend is configured, but Wavebreaker was built without the s3 feature")
+            }
+        }
+    }
+
+    /// Where blobs end up, for logging.
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Local(directory) => format!("directory {}", directory.display()),
+            #[cfg(feature = "s3")]
+            Self::S3(s3) => s3.describe(),
+        }
+    }
+
+    /// Stores the contents of a local file under the key, replacing what was there.
+    ///
+    /// # Errors
+    /// Fails if the file can't be read or the backend can't store it.
+    pub async fn put_file(&self, key: &str, path: &Path) -> anyhow::Result<()> {
+        match self {
+            Self::Local(directory) => {
+                let target = local_path(directory, key)?;
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                fs::copy(path, &target).await.with_context(|| {
+                    format!("Failed to copy {} to {}", path.display(), target.display())
+                })?;
+                Ok(())
+            }
+            #[cfg(feature = "s3")]
+            Self::S3(s3) => s3.put_file(key, path).await,
+        }
+    }
+
+    /// Lists the levels directly below `prefix`, like the entries of a directory.
+    /// `prefix` has to end with a `/`, the names are returned without it.
+    ///
+    /// # Errors
+    /// Fails if the backend can't be read.
+    pub async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        match self {
+            Self::Local(directory) => {
+                let mut entries = match fs::read_dir(local_path(directory, prefix)?).await {
+                    Ok(entries) => entries,
+                    // Nothing was ever stored there
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                    Err(e) => return Err(e.into()),
+                };
+                let mut names = Vec::new();
+                while let Some(entry) = entries.next_entry().await? {
+                    if let Ok(name) = entry.file_name().into_string() {
+                        names.push(name);
+                    }
+                }
+                Ok(names)
+            }
+            #[cfg(feature = "s3")]
+            Self::S3(s3) => s3.list(prefix).await,
+        }
+    }
+
+    /// Deletes everything stored under the prefix, which has to end with a `/`.
+    ///
+    /// # Errors
+    /// Fails if the backend can't delete it.
+    pub async fn delete_all(&self, prefix: &str) -> anyhow::Result<()> {
+        match self {
+            Self::Local(directory) => {
+                match fs::remove_dir_all(local_path(directory, prefix)?).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                    _ => Ok(()),
+                }
+            }
+            #[cfg(feature = "s3")]
+            Self::S3(s3) => s3.delete_all(prefix).await,
+        }
+    }
+}
+
+/// Turns a key into a path below the directory.
+/// Keys only ever come from Wavebreaker itself, but one escaping the directory would still be bad news.
+fn local_path(directory: &Path, key: &str) -> anyhow::Result<PathBuf> {
+    let relative = Path::new(key);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        bail!("Invalid storage key {key}");
+    }
+    Ok(directory.join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_path() {
+        let directory = Path::new("/srv/wavebreaker");
+        assert_eq!(
+            local_path(directory, "backups/20240917T031500Z/scores.jsonl").unwrap(),
+            Path::new("/srv/wavebreaker/backups/20240917T031500Z/scores.jsonl")
+        );
+        assert_eq!(
+            local_path(directory, "backups/").unwrap(),
+            Path::new("/srv/wavebreaker/backups")
+        );
+        assert!(local_path(directory, "../etc/passwd").is_err());
+        assert!(local_path(directory, "/etc/passwd").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+//! Storing blobs in S3, or anything that speaks its API.
+//! Credentials and the region come from the usual AWS environment variables and config files.
+
+use std::path::Path;
+
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::{primitives::ByteStream, Client};
+
+pub struct S3Storage {
+    client: Client,
+    bucket: String,
+    /// Prepended to every key
+    prefix: String,
+}
+
+impl S3Storage {
+    pub async fn new(bucket: &str, prefix: &str) -> Self {
+        let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        Self {
+            client: Client::new(&config),
+            bucket: bucket.to_owned(),
+            prefix: prefix.to_owned(),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!("bucket {} (prefix {:?})", self.bucket, self.prefix)
+    }
+
+    pub async fn put_file(&self, key: &str, path: &Path) -> anyhow::Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(format!("{}{key}", self.prefix))
+            .body(ByteStream::from_path(path).await?)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Lists the "directories" and objects directly below the prefix, see [`super::BlobStorage::list`].
+    pub async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let full_prefix = format!("{}{prefix}", self.prefix);
+        let mut names = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&full_prefix)
+            .delimiter("/")
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page?;
+            let directories = page
+                .common_prefixes()
+                .iter()
+                .filter_map(|common_prefix| common_prefix.prefix())
+                .filter_map(|p| p.strip_prefix(&full_prefix))
+                .filter_map(|p| p.strip_suffix('/'));
+            let objects = page
+                .contents()
+                .iter()
+                .filter_map(|object| object.key())
+                .filter_map(|key| key.strip_prefix(&full_prefix));
+            names.extend(directories.chain(objects).map(str::to_owned));
+        }
+
+        Ok(names)
+    }
+
+    /// Deletes every object below the prefix.
+    pub async fn delete_all(&self, prefix: &str) -> anyhow::Result<()> {
+        let mut keys = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(format!("{}{prefix}", self.prefix))
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            keys.extend(
+                page?
+                    .contents()
+                    .iter()
+                    .filter_map(|object| object.key())
+                    .map(str::to_owned),
+            );
+        }
+
+        for key in keys {
+            self.client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .send()
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,333 @@
+//! What players are riding right now, for things like Discord bots showing "X is riding Y".
+//!
+//! Only players who opted in with `share_activity` are recorded.
+//! Rides are kept in Redis for [`RECENT_RIDE_WINDOW`] seconds, older ones aren't interesting to anyone.
+//! The functions take any [`ActivityStore`], which is the Redis connection outside of unit tests.
+
+use async_trait::async_trait;
+use redis::{AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::util::{game_types::League, redis_keys};
+
+/// How long (in seconds) a ride counts as recent.
+pub const RECENT_RIDE_WINDOW: u64 = 60 * 60;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentRide {
+    pub player_id: i32,
+    pub username: String,
+    pub realm: String,
+    pub song_id: i32,
+    pub title: String,
+    pub artist: String,
+    pub league: League,
+    pub score: i32,
+    #[serde(with = "time::serde::iso8601")]
+    pub ridden_at: OffsetDateTime,
+}
+
+/// Where recent rides are kept, see the module documentation. Times are Unix timestamps.
+#[async_trait]
+pub trait ActivityStore: Send {
+    /// Stores the player's ride (as JSON) for [`RECENT_RIDE_WINDOW`] seconds, as the one they rode at `ridden_at`,
+    /// and forgets every player whose last ride was at `cutoff` or before.
+    async fn store_ride(
+        &mut self,
+        player_id: i32,
+        ride: String,
+        ridden_at: i64,
+        cutoff: i64,
+    ) -> RedisResult<()>;
+
+    /// Up to `limit` players whose last ride was after `cutoff`, the latest first.
+    async fn recent_players(&mut self, cutoff: i64, limit: usize) -> RedisResult<Vec<i32>>;
+
+    /// The stored rides of the players in the same order, `None` for ones that expired.
+    async fn rides(&mut self, player_ids: &[i32]) -> RedisResult<Vec<Option<String>>>;
+
+    /// Forgets the player's ride.
+    async fn forget(&mut self, player_id: i32) -> RedisResult<()>;
+}
+
+#[async_trait]
+impl ActivityStore for deadpool_redis::Connection {
+    async fn store_ride(
+        &mut self,
+        player_id: i32,
+        ride: String,
+        ridden_at: i64,
+        cutoff: i64,
+    ) -> RedisResult<()> {
+        redis::pipe()
+            .atomic()
+            .set_ex(redis_keys::recent_ride(player_id), ride, RECENT_RIDE_WINDOW)
+            .ignore()
+            .zadd(redis_keys::RECENT_RIDES, player_id, ridden_at)
+            .ignore()
+            // Nobody else cleans these up
+            .zrembyscore(redis_keys::RECENT_RIDES, "-inf", cutoff)
+            .ignore()
+            .query_async::<()>(self)
+            .await
+    }
+
+    async fn recent_players(&mut self, cutoff: i64, limit: usize) -> RedisResult<Vec<i32>> {
+        self.zrevrangebyscore_limit(
+            redis_keys::RECENT_RIDES,
+            "+inf",
+            format!("({cutoff}"),
+            0,
+            isize::try_from(limit).unwrap_or(isize::MAX),
+        )
+        .await
+    }
+
+    async fn rides(&mut self, player_ids: &[i32]) -> RedisResult<Vec<Option<String>>> {
+        let keys: Vec<String> = player_ids
+            .iter()
+            .map(|id| redis_keys::recent_ride(*id))
+            .collect();
+        self.mget(keys).await
+    }
+
+    async fn forget(&mut self, player_id: i32) -> RedisResult<()> {
+        redis::pipe()
+            .atomic()
+            .del(redis_keys::recent_ride(player_id))
+            .ignore()
+            .zrem(redis_keys::RECENT_RIDES, player_id)
+            .ignore()
+            .query_async::<()>(self)
+            .await
+    }
+}
+
+/// Keeps the rides in memory, for unit tests. Rides expire by [`MemoryActivityStore::now`].
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemoryActivityStore {
+    /// The current time, set by the tests
+    pub now: i64,
+    /// The last ride of every player, with when it expires
+    pub rides: std::collections::HashMap<i32, (String, i64)>,
+    /// When every player last rode
+    pub ridden_at: std::collections::HashMap<i32, i64>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl ActivityStore for MemoryActivityStore {
+    async fn store_ride(
+        &mut self,
+        player_id: i32,
+        ride: String,
+        ridden_at: i64,
+        cutoff: i64,
+    ) -> RedisResult<()> {
+        self.rides
+            .insert(player_id, (ride, self.now + window_secs()));
+        self.ridden_at.insert(player_id, ridden_at);
+        self.ridden_at.retain(|_, ridden_at| *ridden_at > cutoff);
+        Ok(())
+    }
+
+    async fn recent_players(&mut self, cutoff: i64, limit: usize) -> RedisResult<Vec<i32>> {
+        let mut players: Vec<(i32, i64)> = self
+            .ridden_at
+            .iter()
+            .filter(|(_, ridden_at)| **ridden_at > cutoff)
+            .map(|(player, ridden_at)| (*player, *ridden_at))
+            .collect();
+        // Redis orders ties by member, descending too
+        players.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+        Ok(players
+            .into_iter()
+            .take(limit)
+            .map(|(player, _)| player)
+            .collect())
+    }
+
+    async fn rides(&mut self, player_ids: &[i32]) -> RedisResult<Vec<Option<String>>> {
+        Ok(player_ids
+            .iter()
+            .map(|id| {
+                self.rides
+                    .get(id)
+                    .filter(|(_, expires_at)| *expires_at > self.now)
+                    .map(|(ride, _)| ride.clone())
+            })
+            .collect())
+    }
+
+    async fn forget(&mut self, player_id: i32) -> RedisResult<()> {
+        self.rides.remove(&player_id);
+        self.ridden_at.remove(&player_id);
+        Ok(())
+    }
+}
+
+/// Remembers the ride as the player's most recent one.
+///
+/// # Errors
+/// Fails if something is wrong with Redis or the ride can't be serialized.
+pub async fn record_ride(ride: &RecentRide, store: &mut impl ActivityStore) -> anyhow::Result<()> {
+    let ridden_at = ride.ridden_at.unix_timestamp();
+    store
+        .store_ride(
+            ride.player_id,
+            serde_json::to_string(ride)?,
+            ridden_at,
+            ridden_at - window_secs(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Gets the most recent ride of up to `limit` players who rode something in the last [`RECENT_RIDE_WINDOW`] seconds,
+/// newest first.
+///
+/// # Errors
+/// Fails if something is wrong with Redis or a stored ride can't be deserialized.
+pub async fn recent_rides(
+    limit: usize,
+    store: &mut impl ActivityStore,
+) -> anyhow::Result<Vec<RecentRide>> {
+    recent_rides_at(OffsetDateTime::now_utc().unix_timestamp(), limit, store).await
+}
+
+async fn recent_rides_at(
+    now: i64,
+    limit: usize,
+    store: &mut impl ActivityStore,
+) -> anyhow::Result<Vec<RecentRide>> {
+    let player_ids = store.recent_players(now - window_secs(), limit).await?;
+    if player_ids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Rides can expire between the two requests, those are just skipped
+    store
+        .rides(&player_ids)
+        .await?
+        .into_iter()
+        .flatten()
+        .map(|ride| Ok(serde_json::from_str(&ride)?))
+        .collect()
+}
+
+/// Removes the player's recent ride, for when they stop sharing their activity.
+///
+/// # Errors
+/// Fails if something is wrong with Redis.
+pub async fn forget_player(player_id: i32, store: &mut impl ActivityStore) -> anyhow::Result<()> {
+    store.forget(player_id).await?;
+    Ok(())
+}
+
+fn window_secs() -> i64 {
+    i64::try_from(RECENT_RIDE_WINDOW).unwrap_or(i64::MAX)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ride(player_id: i32, ridden_at: i64) -> RecentRide {
+        RecentRide {
+            player_id,
+            username: format!("Player {player_id}"),
+            realm: "main".to_owned(),
+            song_id: 7,
+            title: "Title".to_owned(),
+            artist: "Artist".to_owned(),
+            league: League::Pro,
+            score: 1000 + player_id,
+            ridden_at: OffsetDateTime::from_unix_timestamp(ridden_at).unwrap(),
+        }
+    }
+
+    fn players(rides: &[RecentRide]) -> Vec<i32> {
+        rides.iter().map(|ride| ride.player_id).collect()
+    }
+
+    #[tokio::test]
+    async fn test_recent_rides() {
+        let start = 1_700_000_000;
+        let mut store = MemoryActivityStore {
+            now: start,
+            ..Default::default()
+        };
+        assert!(recent_rides_at(start, 10, &mut store)
+            .await
+            .unwrap()
+            .is_empty());
+
+        record_ride(&ride(1, start), &mut store).await.unwrap();
+        store.now = start + 60;
+        record_ride(&ride(2, start + 60), &mut store).await.unwrap();
+        store.now = start + 120;
+        record_ride(&ride(3, start + 120), &mut store)
+            .await
+            .unwrap();
+        // Only the last ride of a player counts
+        store.now = start + 180;
+        record_ride(&ride(1, start + 180), &mut store)
+            .await
+            .unwrap();
+
+        let rides = recent_rides_at(start + 180, 10, &mut store).await.unwrap();
+        assert_eq!(players(&rides), vec![1, 3, 2]);
+        assert_eq!(rides[0].score, 1001);
+        assert_eq!(rides[0].ridden_at.unix_timestamp(), start + 180);
+        // The newest ones are kept
+        assert_eq!(
+            players(&recent_rides_at(start + 180, 2, &mut store).await.unwrap()),
+            vec![1, 3]
+        );
+
+        // Player 2 rode exactly a window ago, that's too long
+        let later = start + 60 + window_secs();
+        assert_eq!(
+            players(&recent_rides_at(later, 10, &mut store).await.unwrap()),
+            vec![1, 3]
+        );
+
+        // A ride expiring before its player is dropped from the set is skipped
+        store.now = start + 120 + window_secs();
+        assert_eq!(
+            players(&recent_rides_at(start + 120, 10, &mut store).await.unwrap()),
+            vec![1]
+        );
+
+        // Recording cleans up the players who didn't ride in a while
+        store.now = later;
+        record_ride(&ride(4, later), &mut store).await.unwrap();
+        assert!(!store.ridden_at.contains_key(&2));
+        assert!(store.ridden_at.contains_key(&3));
+    }
+
+    #[tokio::test]
+    async fn test_forget_player() {
+        let now = 1_700_000_000;
+        let mut store = MemoryActivityStore {
+            now,
+            ..Default::default()
+        };
+        record_ride(&ride(1, now), &mut store).await.unwrap();
+        record_ride(&ride(2, now), &mut store).await.unwrap();
+
+        forget_player(1, &mut store).await.unwrap();
+        assert_eq!(
+            players(&recent_rides_at(now, 10, &mut store).await.unwrap()),
+            vec![2]
+        );
+        assert!(!store.rides.contains_key(&1));
+        // Forgetting someone who isn't there is fine
+        forget_player(1, &mut store).await.unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+//! Quotas for API keys, see [`crate::models::api_keys`].
+//!
+//! Requests to `/api` with an `X-Api-Key` header count against two quotas of that key:
+//! a daily one that resets at midnight in the server's time zone (see [`crate::util::time_zone`]), and a burst one
+//! per minute. Like the shout rate limit, the minute starts with the first request in it, so it's a fixed window and
+//! not a sliding one.
+//! Both are counted in Redis, so they hold across restarts and multiple instances.
+//! Requests with an unknown or revoked key are rejected.
+//!
+//! Requests without a key count against `limits.anonymous_api_per_minute` of their client's IP address (IPv6 addresses
+//! by their /64, which is usually one household), in the same kind of window as the burst quota. The address is the
+//! connection's, or the last one in `limits.client_ip_header` behind a proxy, which is the one the proxy added.
+//!
+//! Every response to a request with a key tells how much of its quotas is left, resets are in seconds:
+//! - `X-RateLimit-Limit`, `X-RateLimit-Remaining`, `X-RateLimit-Reset` for the daily quota
+//! - `X-RateLimit-Burst-Limit`, `X-RateLimit-Burst-Remaining`, `X-RateLimit-Burst-Reset` for the burst quota
+//!
+//! Requests over a quota are answered with 429 and a `Retry-After` header. They still count.
+
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{
+        header::{HeaderName, RETRY_AFTER},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use time::OffsetDateTime;
+
+use crate::{
+    models::api_keys::ApiKey,
+    util::{errors::RouteError, redis_keys, redis_ops},
+    AppState,
+};
+
+/// Header the API key is sent in.
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// Length of the burst window, in seconds.
+const BURST_WINDOW_SECS: u64 = 60;
+
+/// How much of one quota has been used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub limit: u32,
+    /// Including the current request
+    pub used: u32,
+    /// Seconds until the quota resets
+    pub reset_secs: u64,
+}
+
+impl Window {
+    #[must_use]
+    pub const fn remaining(self) -> u32 {
+        self.limit.saturating_sub(self.used)
+    }
+
+    #[must_use]
+    pub const fn exceeded(self) -> bool {
+        self.used > self.limit
+    }
+}
+
+/// How much of both quotas of a key has been used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub day: Window,
+    pub burst: Window,
+}
+
+impl Usage {
+    /// Seconds until the request can be retried, if it went over a quota.
+    /// With both quotas exceeded, that's when the later one resets.
+    #[must_use]
+    pub fn retry_after(self) -> Option<u64> {
+        [self.day, self.burst]
+            .into_iter()
+            .filter(|window| window.exceeded())
+            .map(|window| window.reset_secs)
+            .max()
+    }
+
+    fn insert_headers(self, headers: &mut HeaderMap) {
+        for (prefix, window) in [
+            ("x-ratelimit-", self.day),
+            ("x-ratelimit-burst-", self.burst),
+        ] {
+            for (name, value) in [
+                ("limit", u64::from(window.limit)),
+                ("remaining", u64::from(window.remaining())),
+                ("reset", window.reset_secs),
+            ] {
+                if let Ok(name) = HeaderName::try_from(format!("{prefix}{name}")) {
+                    headers.insert(name, HeaderValue::from(value));
+                }
+            }
+        }
+    }
+}
+
+/// Counts requests with an API key against its quotas and adds the rate limit headers to the response.
+pub async fn enforce(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(plain) = request.headers().get(API_KEY_HEADER) else {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| peer.ip());
+        let client = client_ip(
+            request.headers(),
+            state.config.limits.client_ip_header.as_deref(),
+            peer,
+        );
+        return match count_anonymous_request(&state, client).await {
+            Ok(Some(retry_after)) => too_many_requests(retry_after),
+            Ok(None) => next.run(request).await,
+            Err(e) => e.into_response(),
+        };
+    };
+    // Keys are plain ASCII, anything else can't be a key
+    let plain = plain.to_str().unwrap_or_default().to_owned();
+
+    let usage = match count_request(&state, &plain).await {
+        Ok(Some(usage)) => usage,
+        Ok(None) => {
+            return RouteError::new_unauthorized()
+                .set_public_error_message("Unknown or revoked API key")
+                .into_response()
+        }
+        Err(e) => return e.into_response(),
+    };
+
+    let mut response = if let Some(retry_after) = usage.retry_after() {
+        too_many_requests(retry_after)
+    } else {
+        next.run(request).await
+    };
+    usage.insert_headers(response.headers_mut());
+
+    response
+}
+
+fn too_many_requests(retry_after: u64) -> Response {
+    let mut response = RouteError::from_status(StatusCode::TOO_MANY_REQUESTS)
+        .set_public_error_message("API quota exceeded")
+        .into_response();
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+/// Counts a request without a key against the limit of its client.
+///
+/// # Returns
+/// Seconds until the request can be retried, if it went over the limit.
+async fn count_anonymous_request(
+    state: &AppState,
+    client: Option<IpAddr>,
+) -> Result<Option<u64>, RouteError> {
+    let limit = state.config.limits.anonymous_api_per_minute;
+    // Without an address, like in tests, there's nobody to count against
+    let Some(client) = client.filter(|_| limit > 0) else {
+        return Ok(None);
+    };
+
+    let mut redis_conn = state.redis.get().await?;
+    let count = redis_ops::incr_in_window(
+        &redis_keys::api_quota_anonymous(&client_key(client)),
+        BURST_WINDOW_SECS,
+        &mut redis_conn,
+    )
+    .await?;
+    Ok((count.count > i64::from(limit))
+        .then(|| u64::try_from(count.ttl_secs).unwrap_or(BURST_WINDOW_SECS)))
+}
+
+/// The client's IP address: the last one in `header` if it's set, otherwise the connection's.
+fn client_ip(headers: &HeaderMap, header: Option<&str>, peer: Option<IpAddr>) -> Option<IpAddr> {
+    match header {
+        // A proxy appends the address it saw to whatever the client sent, so only the last entry can be trusted.
+        // Everything before it could be made up to get a new quota with every request.
+        Some(header) => headers
+            .get_all(header)
+            .iter()
+            .last()?
+            .to_str()
+            .ok()?
+            .rsplit(',')
+            .next()?
+            .trim()
+            .parse()
+            .ok(),
+        None => peer,
+    }
+}
+
+/// What requests without a key are counted by, see the module documentation.
+fn client_key(client: IpAddr) -> String {
+    match client {
+        IpAddr::V4(address) => address.to_string(),
+        IpAddr::V6(address) => {
+            let segments = address.segments();
+            format!(
+                "{:x}:{:x}:{:x}:{:x}::/64",
+                segments[0], segments[1], segments[2], segments[3]
+            )
+        }
+    }
+}
+
+/// Counts the request against the quotas of the key.
+///
+/// # Returns
+/// `None` if there's no active key with that plain text.
+async fn count_request(state: &AppState, plain: &str) -> Result<Option<Usage>, RouteError> {
+    let mut conn = state.db_read.get().await?;
+    let key = match ApiKey::find_active(plain, &mut conn).await? {
+        Some(key) => key,
+        // A key that was just created might not be on the read replica yet
+        None => {
+            drop(conn);
+            let mut conn = state.db.get().await?;
+            let Some(key) = ApiKey::find_active(plain, &mut conn).await? else {
+                return Ok(None);
+            };
+            key
+        }
+    };
+
+    let mut redis_conn = state.redis.get().await?;
+    let now = OffsetDateTime::now_utc();
+
+    let day_reset = state.config.time_zone.secs_until_midnight(now);
+    let day_key = redis_keys::api_quota_day(key.id, state.config.time_zone.today(now));
+    let burst_key = redis_keys::api_quota_burst(key.id);
+    let counts = redis_ops::incr_in_windows(
+        &[(&day_key, day_reset), (&burst_key, BURST_WINDOW_SECS)],
+        &mut redis_conn,
+    )
+    .await?;
+    let (day, burst) = (counts[0], counts[1]);
+
+    Ok(Some(Usage {
+        day: Window {
+            limit: u32::try_from(key.requests_per_day).unwrap_or_default(),
+            used: u32::try_from(day.count).unwrap_or(u32::MAX),
+            reset_secs: day_reset,
+        },
+        burst: Window {
+            limit: u32::try_from(key.burst_per_minute).unwrap_or_default(),
+            used: u32::try_from(burst.count).unwrap_or(u32::MAX),
+            reset_secs: u64::try_from(burst.ttl_secs).unwrap_or(BURST_WINDOW_SECS),
+        },
+    }))
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(limit: u32, used: u32, reset_secs: u64) -> Window {
+        Window {
+            limit,
+            used,
+            reset_secs,
+        }
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let peer: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        // The client made up the first entry, the proxy appended the one it saw
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("198.18.0.66, 203.0.113.7"),
+        );
+        assert_eq!(client_ip(&headers, None, Some(peer)), Some(peer));
+        assert_eq!(
+            client_ip(&headers, Some("x-forwarded-for"), Some(peer)),
+            Some("203.0.113.7".parse().unwrap())
+        );
+        // Same if the proxy added its own header line instead of appending
+        headers.insert("x-forwarded-for", HeaderValue::from_static("198.18.0.66"));
+        headers.append("x-forwarded-for", HeaderValue::from_static("203.0.113.7"));
+        assert_eq!(
+            client_ip(&headers, Some("x-forwarded-for"), Some(peer)),
+            Some("203.0.113.7".parse().unwrap())
+        );
+        // Not the connection's address, which is the proxy
+        assert_eq!(client_ip(&headers, Some("x-real-ip"), Some(peer)), None);
+        assert_eq!(client_ip(&HeaderMap::new(), None, None), None);
+    }
+
+    #[test]
+    fn test_client_key() {
+        assert_eq!(client_key("203.0.113.7".parse().unwrap()), "203.0.113.7");
+        // The whole /64 counts as one client
+        assert_eq!(
+            client_key("2001:db8:1:2:aaaa::1".parse().unwrap()),
+            client_key("2001:db8:1:2:bbbb::2".parse().unwrap())
+        );
+        assert_eq!(
+            client_key("2001:db8:1:2::1".parse().unwrap()),
+            "2001:db8:1:2::/64"
+        );
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let within = Usage {
+            day: window(100, 100, 5000),
+            burst: window(10, 3, 30),
+        };
+        assert_eq!(within.retry_after(), None);
+        assert_eq!(within.day.remaining(), 0);
+
+        let burst = Usage {
+            day: window(100, 50, 5000),
+            burst: window(10, 11, 30),
+        };
+        assert_eq!(burst.retry_after(), Some(30));
+        assert_eq!(burst.burst.remaining(), 0);
+
+        let both = Usage {
+            day: window(100, 101, 5000),
+            burst: window(10, 11, 30),
+        };
+        assert_eq!(both.retry_after(), Some(5000));
+    }
+}
@@ -0,0 +1,97 @@
+//! Recognizes song tags that clearly aren't a song, so they don't end up in the songs table.
+//! Tags like these are put into quarantine instead, see `models::song_quarantine`.
+
+/// Why a song's tags were refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BogusSongReason {
+    /// Title or artist are empty or only whitespace
+    Blank,
+    /// Looks like a link, probably from a stream or a file with junk in its tags
+    Url,
+    /// Looks like a tag command that isn't configured, see `game::commands`
+    Command,
+}
+
+impl BogusSongReason {
+    /// How the reason is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Blank => "blank",
+            Self::Url => "url",
+            Self::Command => "command",
+        }
+    }
+}
+
+/// Checks whether the title and artist sent by the game look like an actual song.
+///
+/// # Returns
+/// `None` if they do, the reason they don't otherwise.
+#[must_use]
+pub fn check_song_tags(title: &str, artist: &str) -> Option<BogusSongReason> {
+    let (title, artist) = (title.trim(), artist.trim());
+
+    if title.is_empty() || artist.is_empty() {
+        return Some(BogusSongReason::Blank);
+    }
+    if looks_like_url(title) || looks_like_url(artist) {
+        return Some(BogusSongReason::Url);
+    }
+    if looks_like_command(title) || looks_like_command(artist) {
+        return Some(BogusSongReason::Command);
+    }
+
+    None
+}
+
+fn looks_like_url(tag: &str) -> bool {
+    let tag = tag.to_ascii_lowercase();
+    tag.contains("://") || tag.starts_with("www.")
+}
+
+/// Commands start with `/` or `!` followed by a letter, like `/rank` or `!top`.
+/// Requiring the letter keeps artists like "!!!" from being caught.
+fn looks_like_command(tag: &str) -> bool {
+    let mut chars = tag.chars();
+    matches!(chars.next(), Some('/' | '!')) && chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_song_tags() {
+        assert_eq!(check_song_tags("dear music.", "a4."), None);
+        assert_eq!(check_song_tags("chk chk chk", "!!!"), None);
+        assert_eq!(check_song_tags("1/2", "some artist"), None);
+
+        assert_eq!(
+            check_song_tags("   ", "some artist"),
+            Some(BogusSongReason::Blank)
+        );
+        assert_eq!(
+            check_song_tags("some song", ""),
+            Some(BogusSongReason::Blank)
+        );
+
+        assert_eq!(
+            check_song_tags("https://example.com/stream", "radio"),
+            Some(BogusSongReason::Url)
+        );
+        assert_eq!(
+            check_song_tags("some song", "WWW.EXAMPLE.COM"),
+            Some(BogusSongReason::Url)
+        );
+
+        assert_eq!(
+            check_song_tags("/rank", "wavebreaker"),
+            Some(BogusSongReason::Command)
+        );
+        assert_eq!(
+            check_song_tags("top", "!wavebreaker"),
+            Some(BogusSongReason::Command)
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! What the client a ride was submitted from looks like, so protocol oddities can be traced back to specific builds
+//! of the game (or of something pretending to be it) and regions. See [`crate::models::ride_sources`].
+//!
+//! Nothing that identifies the player is kept: no IP address, and of the headers only the user agent
+//! and the ones that differ between HTTP stacks rather than between people.
+
+use axum::http::{
+    header::{ACCEPT, ACCEPT_ENCODING, CONNECTION, CONTENT_TYPE, USER_AGENT},
+    HeaderMap, HeaderName, Version,
+};
+use sha2::{Digest, Sha256};
+
+/// Longest user agent that's kept, the rest is cut off
+const MAX_USER_AGENT_LENGTH: usize = 256;
+/// Longest region that's kept, country codes and the like are a lot shorter
+const MAX_REGION_LENGTH: usize = 8;
+/// Headers whose values go into the fingerprint, as they depend on the client's HTTP stack
+const FINGERPRINT_HEADERS: [HeaderName; 4] = [ACCEPT, ACCEPT_ENCODING, CONNECTION, CONTENT_TYPE];
+
+/// The client a request came from, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientSource {
+    pub user_agent: Option<String>,
+    /// Hash of the HTTP version, the names of the headers in the order they were sent
+    /// and the values of [`FINGERPRINT_HEADERS`]. Tells clients apart that send the same user agent.
+    pub fingerprint: String,
+    pub http_version: &'static str,
+    /// From the header set in `ride_sources.region_header`, like the country code a CDN adds
+    pub region: Option<String>,
+}
+
+impl ClientSource {
+    #[must_use]
+    pub fn from_request(
+        version: Version,
+        headers: &HeaderMap,
+        region_header: Option<&str>,
+    ) -> Self {
+        let user_agent = headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect());
+        let region = region_header
+            .and_then(|name| headers.get(name))
+            .and_then(|value| value.to_str().ok())
+            .map(|region| region.trim().to_ascii_uppercase())
+            .filter(|region| {
+                (1..=MAX_REGION_LENGTH).contains(&region.len())
+                    && region
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+
+        Self {
+            user_agent,
+            fingerprint: fingerprint(version, headers),
+            http_version: version_name(version),
+            region,
+        }
+    }
+}
+
+const fn version_name(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_11 => "1.1",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "other",
+    }
+}
+
+/// See [`ClientSource::fingerprint`]. The first 16 hex digits of the hash are plenty to tell a few clients apart.
+fn fingerprint(version: Version, headers: &HeaderMap) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(version_name(version));
+    // Names that were sent more than once show up once, in the order they were first sent
+    for name in headers.keys() {
+        hasher.update(b"\n");
+        hasher.update(name.as_str());
+    }
+    for name in &FINGERPRINT_HEADERS {
+        hasher.update(b"\n");
+        for value in headers.get_all(name) {
+            hasher.update(value.as_bytes());
+            hasher.update(b",");
+        }
+    }
+
+    hasher.finalize()[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_from_request() {
+        let game = headers(&[
+            ("host", "localhost"),
+            ("user-agent", "Audiosurf/1.0"),
+            ("content-type", "application/x-www-form-urlencoded"),
+            ("cf-ipcountry", "de"),
+        ]);
+        let source = ClientSource::from_request(Version::HTTP_11, &game, Some("CF-IPCountry"));
+        assert_eq!(source.user_agent.as_deref(), Some("Audiosurf/1.0"));
+        assert_eq!(source.http_version, "1.1");
+        assert_eq!(source.region.as_deref(), Some("DE"));
+        assert_eq!(source.fingerprint.len(), 16);
+
+        assert_eq!(
+            ClientSource::from_request(Version::HTTP_11, &game, None).region,
+            None
+        );
+        let bogus_region = headers(&[("cf-ipcountry", "not a region")]);
+        assert_eq!(
+            ClientSource::from_request(Version::HTTP_11, &bogus_region, Some("cf-ipcountry"))
+                .region,
+            None
+        );
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let game = headers(&[
+            ("host", "localhost"),
+            ("user-agent", "Audiosurf/1.0"),
+            ("content-type", "application/x-www-form-urlencoded"),
+        ]);
+        // Only the user agent and host differ, which isn't what the fingerprint is about
+        let same_stack = headers(&[
+            ("host", "wavebreaker.example"),
+            ("user-agent", "Audiosurf/1.1"),
+            ("content-type", "application/x-www-form-urlencoded"),
+        ]);
+        let reordered = headers(&[
+            ("user-agent", "Audiosurf/1.0"),
+            ("host", "localhost"),
+            ("content-type", "application/x-www-form-urlencoded"),
+        ]);
+        let other_encoding = headers(&[
+            ("host", "localhost"),
+            ("user-agent", "Audiosurf/1.0"),
+            ("content-type", "multipart/form-data"),
+        ]);
+
+        assert_eq!(
+            fingerprint(Version::HTTP_11, &game),
+            fingerprint(Version::HTTP_11, &same_stack)
+        );
+        assert_ne!(
+            fingerprint(Version::HTTP_11, &game),
+            fingerprint(Version::HTTP_11, &reordered)
+        );
+        assert_ne!(
+            fingerprint(Version::HTTP_11, &game),
+            fingerprint(Version::HTTP_11, &other_encoding)
+        );
+        assert_ne!(
+            fingerprint(Version::HTTP_11, &game),
+            fingerprint(Version::HTTP_10, &game)
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! The server time the game is sent, for ride ages and event countdowns.
+//!
+//! The game compares the timestamps it gets (like when a ride was submitted) with the server time in the same
+//! response, so both have to be Unix seconds in UTC. The wall clock can be stepped backwards by NTP or an admin,
+//! which would make countdowns jump, so it's only read once. After that the time is advanced with a monotonic clock,
+//! and never runs backwards between two responses.
+
+use std::{sync::OnceLock, time::Instant};
+
+use time::OffsetDateTime;
+
+/// The wall clock time when the clock was first read, and the monotonic time it was read at.
+static ANCHOR: OnceLock<(OffsetDateTime, Instant)> = OnceLock::new();
+
+/// The current server time, see the module documentation.
+#[must_use]
+pub fn now() -> OffsetDateTime {
+    let (wall, monotonic) = ANCHOR.get_or_init(|| (OffsetDateTime::now_utc(), Instant::now()));
+    *wall + monotonic.elapsed()
+}
+
+/// The current server time as Unix seconds, the way the game expects it.
+#[must_use]
+pub fn unix_time() -> i64 {
+    now().unix_timestamp()
+}
+
+/// When a ride happened, the way the game expects it next to `server_time`: Unix seconds, the age being the difference.
+///
+/// Scores from before the database stored time zones were saved in the database server's local time, and were
+/// taken as UTC when converted. Depending on where it ran, that puts them up to a day in the future, so they're
+/// clamped to the server time and never show up with a negative age.
+#[must_use]
+pub fn ride_time(submitted_at: OffsetDateTime, server_time: i64) -> i64 {
+    submitted_at.unix_timestamp().clamp(0, server_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_now() {
+        let first = now();
+        let second = now();
+        assert!(second >= first);
+        assert!((OffsetDateTime::now_utc() - second).abs() < time::Duration::seconds(5));
+    }
+
+    #[test]
+    fn test_ride_time() {
+        let server_time = 1_700_000_000;
+        let an_hour_ago = OffsetDateTime::from_unix_timestamp(server_time - 3600).unwrap();
+        assert_eq!(ride_time(an_hour_ago, server_time), server_time - 3600);
+
+        // Stored in a time zone ahead of UTC before time zones were stored
+        let in_the_future = OffsetDateTime::from_unix_timestamp(server_time + 7200).unwrap();
+        assert_eq!(ride_time(in_the_future, server_time), server_time);
+    }
+}
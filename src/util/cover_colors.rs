@@ -0,0 +1,193 @@
+//! Picks the dominant colors of song covers, so the frontend can theme song pages without processing images itself.
+//!
+//! Covers are only linked, usually to the [Cover Art Archive](https://coverartarchive.org), but moderators can point
+//! them at any image when they edit a song's metadata, so nothing about the host is trusted. The job worker downloads
+//! the small one (see [`Job::ExtractCoverColors`]) once the metadata of a song is added or changes, with a timeout and
+//! a size limit, shrinks it off the async threads and counts its colors. The colors are stored on [`ExtraSongInfo`]
+//! along with the cover they're from, so a new cover gets new colors.
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{bail, Context};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use tracing::{debug, error};
+
+use crate::{jobs::Job, models::extra_song_info::ExtraSongInfo};
+
+/// Most colors kept per cover
+pub const MAX_COLORS: usize = 5;
+/// Covers are shrunk to fit this before counting, the colors don't need more
+const SAMPLE_SIZE: u32 = 64;
+/// Biggest cover downloaded, the small ones are around 30 KB
+const MAX_COVER_BYTES: usize = 5 * 1024 * 1024;
+/// How long downloading a cover can take
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(15);
+/// How far apart (squared, in RGB) colors have to be to both be kept, so one color doesn't take every spot
+const MIN_DISTANCE_SQUARED: u32 = 48 * 48;
+
+/// Puts extracting the colors of the song's cover into the job queue, unless it's waiting there already.
+/// Meant for after the metadata changed, which isn't worth failing over this, so errors are only logged.
+pub async fn queue(song_id: i32, conn: &mut AsyncPgConnection) {
+    let job = Job::ExtractCoverColors { song_id };
+    let queued = match job.is_queued(conn).await {
+        Ok(true) => Ok(()),
+        Ok(false) => job.enqueue(conn).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = queued {
+        error!("Failed to queue extracting the cover colors of song {song_id}: {e}");
+    }
+}
+
+/// Extracts and stores the colors of the song's cover, if it has one and they weren't extracted from it already.
+pub async fn extract(song_id: i32, conn: &mut AsyncPgConnection) -> anyhow::Result<()> {
+    use crate::schema::extra_song_info;
+
+    let Some(info) = extra_song_info::table
+        .filter(extra_song_info::song_id.eq(song_id))
+        .select(ExtraSongInfo::as_select())
+        .first::<ExtraSongInfo>(conn)
+        .await
+        .optional()?
+    else {
+        return Ok(());
+    };
+    let Some(cover) = info.cover_url_small.as_ref().or(info.cover_url.as_ref()) else {
+        return Ok(());
+    };
+    if info.cover_colors_source.as_ref() == Some(cover) {
+        debug!("Colors of the cover of song {song_id} are extracted already");
+        return Ok(());
+    }
+
+    let colors: Vec<String> = dominant_colors(&download_pixels(cover).await?, MAX_COLORS)
+        .into_iter()
+        .map(to_hex)
+        .collect();
+    diesel::update(&info)
+        .set((
+            extra_song_info::cover_colors.eq(colors),
+            extra_song_info::cover_colors_source.eq(cover),
+        ))
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+async fn download_pixels(url: &str) -> anyhow::Result<Vec<[u8; 3]>> {
+    let client = reqwest::Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .build()?;
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_COVER_BYTES as u64)
+    {
+        bail!("Cover {url} is too big");
+    }
+    // The length can be left out or be wrong, so the limit is checked while reading too
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > MAX_COVER_BYTES {
+            bail!("Cover {url} is too big");
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    // Decoding takes long enough to hold up other tasks
+    let url = url.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let image = image::load_from_memory(&bytes)
+            .with_context(|| format!("Cover {url} isn't an image"))?
+            .thumbnail(SAMPLE_SIZE, SAMPLE_SIZE)
+            .to_rgb8();
+        Ok(image.pixels().map(|pixel| pixel.0).collect())
+    })
+    .await?
+}
+
+/// Finds the most common colors, most common first.
+///
+/// Similar colors are counted together, and a color too close to one that's more common is left out, so a cover
+/// that's mostly one color with some noise doesn't get five shades of it.
+#[must_use]
+pub fn dominant_colors(pixels: &[[u8; 3]], count: usize) -> Vec<[u8; 3]> {
+    // 16 levels per channel, with the sums to average the colors that end up together
+    let mut buckets: HashMap<[u8; 3], (u32, [u32; 3])> = HashMap::new();
+    for pixel in pixels {
+        let (seen, sums) = buckets
+            .entry(pixel.map(|channel| channel >> 4))
+            .or_default();
+        *seen += 1;
+        for (sum, channel) in sums.iter_mut().zip(pixel) {
+            *sum += u32::from(*channel);
+        }
+    }
+
+    let mut buckets: Vec<(u32, [u32; 3])> = buckets.into_values().collect();
+    // Ties by color, so the result doesn't depend on the order of the map
+    buckets.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    let mut colors: Vec<[u8; 3]> = Vec::with_capacity(count);
+    for (seen, sums) in buckets {
+        if colors.len() == count {
+            break;
+        }
+        #[allow(clippy::cast_possible_truncation)] // An average of bytes fits into a byte
+        let color = sums.map(|sum| (sum / seen) as u8);
+        if colors
+            .iter()
+            .all(|kept| distance_squared(*kept, color) >= MIN_DISTANCE_SQUARED)
+        {
+            colors.push(color);
+        }
+    }
+    colors
+}
+
+fn distance_squared(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| u32::from(a.abs_diff(b)).pow(2))
+        .sum()
+}
+
+/// Colors are stored as `#rrggbb`, like CSS takes them.
+fn to_hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dominant_colors() {
+        let mut pixels = vec![[200, 10, 10]; 100];
+        // Close enough to the red to not get a spot of its own
+        pixels.extend([[220, 30, 20]; 60]);
+        pixels.extend([[10, 10, 200]; 30]);
+        pixels.extend([[10, 200, 10]; 10]);
+
+        assert_eq!(
+            dominant_colors(&pixels, 5),
+            vec![[200, 10, 10], [10, 10, 200], [10, 200, 10]]
+        );
+        assert_eq!(dominant_colors(&pixels, 1), vec![[200, 10, 10]]);
+        assert!(dominant_colors(&[], 5).is_empty());
+    }
+
+    #[test]
+    fn test_similar_colors_are_averaged() {
+        // Both end up in the same bucket
+        let pixels = [[0, 0, 0], [2, 4, 6]];
+        assert_eq!(dominant_colors(&pixels, 5), vec![[1, 2, 3]]);
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex([255, 0, 16]), "#ff0010");
+    }
+}
@@ -0,0 +1,239 @@
+//! Finds data that doesn't add up, like rankings of players that don't exist anymore, and optionally fixes it.
+//!
+//! Foreign keys already prevent most of this, but not everything is covered by them (Redis, soft deletes),
+//! and hand-edited databases or restored backups don't always have them.
+//! Run with the `doctor` command or `POST /api/admin/doctor`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use diesel::{dsl::not, prelude::*};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use redis::AsyncCommands;
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    models::{scores::Score, skill_point_ledger::LedgerEntry},
+    schema::{extra_song_info, players, rivalries, scores, songs},
+    util::{
+        rankings::{self, RankingMode},
+        realm::MAIN_REALM,
+        redis_keys,
+    },
+    AppState,
+};
+
+/// Everything the doctor found. With `fixed` set, all of it has been fixed already.
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    /// Scores that aren't deleted, on songs that are. Fixed by deleting the scores, too.
+    pub scores_of_deleted_songs: Vec<i32>,
+    /// Extra info pointing at songs that don't exist. Fixed by deleting it.
+    pub extra_info_without_song: Vec<i32>,
+    /// Challenger and rival IDs of rivalries with a player that doesn't exist. Fixed by deleting them.
+    pub rivalries_without_player: Vec<(i32, i32)>,
+    /// Player IDs in the rankings of each realm that don't belong to any player.
+    /// Fixed by removing them from the rankings of every mode.
+    pub unknown_ranked_players: BTreeMap<String, Vec<i32>>,
+    /// Player IDs in each realm whose skill points aren't what their ledger adds up to, see [`LedgerEntry`].
+    /// Fixed by computing their rankings again, which records the difference in the ledger.
+    pub ledger_mismatches: BTreeMap<String, Vec<i32>>,
+    pub fixed: bool,
+}
+
+impl Report {
+    #[must_use]
+    pub fn problems(&self) -> usize {
+        self.scores_of_deleted_songs.len()
+            + self.extra_info_without_song.len()
+            + self.rivalries_without_player.len()
+            + self
+                .unknown_ranked_players
+                .values()
+                .map(Vec::len)
+                .sum::<usize>()
+            + self.ledger_mismatches.values().map(Vec::len).sum::<usize>()
+    }
+}
+
+/// Checks everything, fixing what was found if `fix` is set.
+///
+/// # Errors
+/// Fails if something is wrong with the DB or Redis.
+pub async fn run(state: &AppState, fix: bool) -> anyhow::Result<Report> {
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    let mut realms = vec![MAIN_REALM.to_owned()];
+    realms.extend(
+        state
+            .config
+            .main
+            .realms
+            .iter()
+            .map(|realm| realm.name().to_owned()),
+    );
+
+    let report = Report {
+        scores_of_deleted_songs: scores_of_deleted_songs(&mut conn).await?,
+        extra_info_without_song: extra_info_without_song(&mut conn).await?,
+        rivalries_without_player: rivalries_without_player(&mut conn).await?,
+        unknown_ranked_players: unknown_ranked_players(&realms, &mut conn, &mut redis_conn).await?,
+        ledger_mismatches: ledger_mismatches(&realms, &mut conn, &mut redis_conn).await?,
+        fixed: fix,
+    };
+    info!("Doctor found {} problem(s)", report.problems());
+
+    if fix {
+        fix_problems(&report, &mut conn, &mut redis_conn).await?;
+        info!("Doctor fixed {} problem(s)", report.problems());
+    }
+
+    Ok(report)
+}
+
+async fn scores_of_deleted_songs(conn: &mut AsyncPgConnection) -> QueryResult<Vec<i32>> {
+    Score::all()
+        .inner_join(songs::table)
+        .filter(songs::deleted_at.is_not_null())
+        .select(scores::id)
+        .order(scores::id)
+        .load(conn)
+        .await
+}
+
+async fn extra_info_without_song(conn: &mut AsyncPgConnection) -> QueryResult<Vec<i32>> {
+    extra_song_info::table
+        .filter(not(
+            extra_song_info::song_id.eq_any(songs::table.select(songs::id))
+        ))
+        .select(extra_song_info::id)
+        .order(extra_song_info::id)
+        .load(conn)
+        .await
+}
+
+async fn rivalries_without_player(conn: &mut AsyncPgConnection) -> QueryResult<Vec<(i32, i32)>> {
+    rivalries::table
+        .filter(
+            not(rivalries::challenger_id.eq_any(players::table.select(players::id))).or(not(
+                rivalries::rival_id.eq_any(players::table.select(players::id)),
+            )),
+        )
+        .select((rivalries::challenger_id, rivalries::rival_id))
+        .load(conn)
+        .await
+}
+
+async fn unknown_ranked_players(
+    realms: &[String],
+    conn: &mut AsyncPgConnection,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> anyhow::Result<BTreeMap<String, Vec<i32>>> {
+    let mut unknown = BTreeMap::new();
+    for realm in realms {
+        let ranked: Vec<i32> = redis_conn
+            .zrange(redis_keys::skill_points(realm), 0, -1)
+            .await?;
+        let existing: HashSet<i32> = players::table
+            .filter(players::id.eq_any(&ranked))
+            .select(players::id)
+            .load::<i32>(conn)
+            .await?
+            .into_iter()
+            .collect();
+
+        let missing: Vec<i32> = ranked
+            .into_iter()
+            .filter(|player| !existing.contains(player))
+            .collect();
+        if !missing.is_empty() {
+            unknown.insert(realm.clone(), missing);
+        }
+    }
+
+    Ok(unknown)
+}
+
+async fn ledger_mismatches(
+    realms: &[String],
+    conn: &mut AsyncPgConnection,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> anyhow::Result<BTreeMap<String, Vec<i32>>> {
+    let mut mismatches = BTreeMap::new();
+    for realm in realms {
+        let ranked: HashMap<i32, i64> = redis_conn
+            .zrange_withscores::<_, Vec<(i32, i64)>>(redis_keys::skill_points(realm), 0, -1)
+            .await?
+            .into_iter()
+            .collect();
+        let recorded = LedgerEntry::totals(realm, conn).await?;
+
+        let mut mismatched: Vec<i32> = ranked
+            .keys()
+            .chain(recorded.keys())
+            .copied()
+            .collect::<HashSet<i32>>()
+            .into_iter()
+            .filter(|player| {
+                ranked.get(player).copied().unwrap_or_default()
+                    != recorded.get(player).copied().unwrap_or_default()
+            })
+            .collect();
+        if !mismatched.is_empty() {
+            mismatched.sort_unstable();
+            mismatches.insert(realm.clone(), mismatched);
+        }
+    }
+
+    Ok(mismatches)
+}
+
+async fn fix_problems(
+    report: &Report,
+    conn: &mut AsyncPgConnection,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> anyhow::Result<()> {
+    // Deleted like any other score, so their skill points are taken away too
+    let orphaned_scores: Vec<Score> = Score::all()
+        .filter(scores::id.eq_any(&report.scores_of_deleted_songs))
+        .load(conn)
+        .await?;
+    for score in orphaned_scores {
+        score.delete(conn, redis_conn).await?;
+    }
+
+    diesel::delete(
+        extra_song_info::table.filter(extra_song_info::id.eq_any(&report.extra_info_without_song)),
+    )
+    .execute(conn)
+    .await?;
+
+    for (challenger, rival) in &report.rivalries_without_player {
+        diesel::delete(rivalries::table.find((challenger, rival)))
+            .execute(conn)
+            .await?;
+    }
+
+    for (realm, player_ids) in &report.unknown_ranked_players {
+        for mode in RankingMode::ALL {
+            redis_conn
+                .zrem::<_, _, ()>(mode.key(realm), player_ids)
+                .await?;
+        }
+    }
+
+    for (realm, player_ids) in &report.ledger_mismatches {
+        let unknown = report.unknown_ranked_players.get(realm);
+        for player in player_ids {
+            // Just removed from the rankings, there's nothing left to compute again
+            if unknown.is_some_and(|unknown| unknown.contains(player)) {
+                continue;
+            }
+            rankings::refresh_player(*player, realm, conn, redis_conn).await?;
+        }
+    }
+
+    Ok(())
+}
@@ -14,8 +14,90 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use serde::{Deserialize, Serialize};
 
+/// Errors returned by the model layer.
+///
+/// Unlike a plain `anyhow::Error`, these let routes tell "not found" apart from "the database is down".
+/// `RouteError` picks the right status code and message for them automatically.
+#[derive(Debug, thiserror::Error)]
+pub enum WavebreakerError {
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("Merge {0} has already been undone")]
+    MergeAlreadyUndone(i32),
+    #[error("Songs from different realms can't be merged")]
+    CrossRealmMerge,
+    #[error("A player can't be merged into themselves")]
+    SelfMerge,
+    #[error("You can't be your own rival")]
+    SelfRivalry,
+    #[error("You can't have more than {0} rivals")]
+    TooManyRivals(i64),
+    #[error("This rival was added or removed too recently, try again later")]
+    RivalryCooldown,
+    #[error("Song {0} is locked")]
+    SongLocked(i32),
+    #[error("The slug {0} is taken")]
+    SlugTaken(String),
+    #[error("You changed your slug too recently, try again later")]
+    SlugCooldown,
+    #[error("The skill points of player {0} changed by more than a ledger entry can hold")]
+    LedgerOverflow(i32),
+    #[error("MusicBrainz lookup failed: {0:#}")]
+    MusicBrainz(anyhow::Error),
+    #[error("Failed to (de)serialize data: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error(transparent)]
+    Database(#[from] DieselError),
+    #[error(transparent)]
+    Redis(#[from] redis::RedisError),
+}
+
+impl WavebreakerError {
+    /// The status code this error should be answered with.
+    #[must_use]
+    pub const fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) | Self::Database(DieselError::NotFound) => StatusCode::NOT_FOUND,
+            Self::CrossRealmMerge | Self::SelfMerge | Self::SelfRivalry => StatusCode::BAD_REQUEST,
+            Self::RivalryCooldown | Self::SlugCooldown => StatusCode::TOO_MANY_REQUESTS,
+            Self::MergeAlreadyUndone(_)
+            | Self::SongLocked(_)
+            | Self::SlugTaken(_)
+            | Self::TooManyRivals(_)
+            | Self::Database(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                StatusCode::CONFLICT
+            }
+            Self::MusicBrainz(_) => StatusCode::BAD_GATEWAY,
+            Self::LedgerOverflow(_)
+            | Self::Serialization(_)
+            | Self::Database(_)
+            | Self::Redis(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The message that's shown to users, if it's safe to show them the error itself.
+    /// Internal errors get the generic message for their status code instead.
+    #[must_use]
+    pub fn public_message(&self) -> Option<String> {
+        match self {
+            Self::NotFound(_)
+            | Self::MergeAlreadyUndone(_)
+            | Self::CrossRealmMerge
+            | Self::SelfMerge
+            | Self::SelfRivalry
+            | Self::TooManyRivals(_)
+            | Self::RivalryCooldown
+            | Self::SongLocked(_)
+            | Self::SlugTaken(_)
+            | Self::SlugCooldown => Some(self.to_string()),
+            _ => None,
+        }
+    }
+}
+
 /// This is for **exposing internal errors publicly.**
 /// It is desirable for internal services, where you do want to expose
 /// what has gone wrong as a part of the return.
@@ -49,6 +131,7 @@ impl<S> Default for RouteErrorOutput<S> {
 }
 
 /// This Rust module provides a standard error type for routes.
+///
 /// It encapsulates information about errors that occur while handling requests.
 /// It includes a status code, error details, any extra data,
 /// and a public error message.
@@ -142,7 +225,7 @@ where
     ///
     /// # Example Code
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use ::axum_route_error::RouteError;
     /// use ::serde::Deserialize;
     /// use ::serde::Serialize;
@@ -281,6 +364,17 @@ where
 {
     fn from(error: FE) -> Self {
         let anyhow_error: AnyhowError = error.into();
+
+        // Errors from the model layer know what they should look like to the user
+        if let Some(wavebreaker_error) = anyhow_error.downcast_ref::<WavebreakerError>() {
+            return Self {
+                status_code: wavebreaker_error.status_code(),
+                public_error_message: wavebreaker_error.public_message(),
+                error: Some(anyhow_error),
+                ..Self::default()
+            };
+        }
+
         Self {
             status_code: StatusCode::INTERNAL_SERVER_ERROR,
             error: Some(anyhow_error),
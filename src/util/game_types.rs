@@ -1,6 +1,8 @@
 use diesel::{deserialize::FromSqlRow, expression::AsExpression};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use validator::ValidationError;
 
 /// Represents the three skill levels represented on the leaderboard.
 #[derive(
@@ -11,6 +13,7 @@ use serde_repr::{Deserialize_repr, Serialize_repr};
     Debug,
     Eq,
     PartialEq,
+    Hash,
     Clone,
     Copy,
     TryFromPrimitive,
@@ -24,6 +27,10 @@ pub enum League {
     Elite,
 }
 
+impl League {
+    pub const ALL: [Self; 3] = [Self::Casual, Self::Pro, Self::Elite];
+}
+
 /// Represents a character/vehicle in the game.
 #[derive(
     AsExpression,
@@ -58,6 +65,34 @@ pub enum Character {
     Mono = 17,
 }
 
+/// A feat the game awards for how a song was ridden. Scores store them by their name in the game, see
+/// [`Feat::as_str`], which keeps feats that aren't known here.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Hash, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Feat {
+    CleanFinish,
+    Stealth,
+}
+
+impl Feat {
+    pub const ALL: [Self; 2] = [Self::CleanFinish, Self::Stealth];
+
+    /// The feat's name in the game, which is how it's stored.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::CleanFinish => "Clean Finish",
+            Self::Stealth => "Stealth",
+        }
+    }
+
+    /// Gets the feat by its name in the game, `None` for feats that aren't known.
+    #[must_use]
+    pub fn from_game(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|feat| feat.as_str() == name)
+    }
+}
+
 /// Represents the three kinds of leaderboards available in the game.
 #[derive(Deserialize_repr, Serialize_repr, Debug, Eq, PartialEq, TryFromPrimitive)]
 #[repr(u8)]
@@ -65,6 +100,8 @@ pub enum Leaderboard {
     Friend,
     Global,
     Nearby,
+    /// Every player's best score across all leagues. Wavebreaker-specific, only sent to clients asking for it.
+    Combined,
 }
 
 /// Split a string with values separated by 'x' into a vector of the values.
@@ -83,6 +120,73 @@ where
         .collect::<Result<Vec<T>, T::Err>>()
 }
 
+/// Why a list of numbers couldn't be parsed by [`parse_separated_i32`].
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ParseListError {
+    #[error("entry {0} is not a valid number")]
+    InvalidNumber(usize),
+    #[error("list has more than {0} entries")]
+    TooManyEntries(usize),
+}
+
+/// Parses a list of integers separated by `separator` directly into a `Vec`.
+///
+/// Unlike [`split_x_separated`], this doesn't go through `str::parse` for every entry
+/// and allocates the `Vec` once with the right capacity.
+/// A trailing separator is allowed, since the game sends track shapes like that. An empty string is an empty list.
+///
+/// # Errors
+/// Fails if an entry isn't a valid `i32` (including empty entries) or if there are more than `max_entries` entries.
+/// The list is rejected before anything is allocated if it's too long.
+pub fn parse_separated_i32(
+    s: &str,
+    separator: u8,
+    max_entries: usize,
+) -> Result<Vec<i32>, ParseListError> {
+    let bytes = s.as_bytes();
+    let bytes = bytes.strip_suffix(&[separator]).unwrap_or(bytes);
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let entries = memchr::memchr_iter(separator, bytes).count() + 1;
+    if entries > max_entries {
+        return Err(ParseListError::TooManyEntries(max_entries));
+    }
+
+    let mut result = Vec::with_capacity(entries);
+    for (index, entry) in bytes.split(|&b| b == separator).enumerate() {
+        result.push(parse_i32_bytes(entry).ok_or(ParseListError::InvalidNumber(index))?);
+    }
+    Ok(result)
+}
+
+/// Parses a decimal `i32` with an optional leading minus. `None` on anything else, or on overflow.
+fn parse_i32_bytes(bytes: &[u8]) -> Option<i32> {
+    let (negative, digits) = match bytes.split_first() {
+        Some((b'-', digits)) => (true, digits),
+        _ => (false, bytes),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+
+    // Accumulate negatively, so i32::MIN parses without overflowing
+    let mut value: i32 = 0;
+    for &b in digits {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        value = value.checked_mul(10)?.checked_sub(i32::from(b - b'0'))?;
+    }
+
+    if negative {
+        Some(value)
+    } else {
+        value.checked_neg()
+    }
+}
+
 pub fn join_x_separated<T>(v: &[T]) -> String
 where
     T: std::fmt::Display,
@@ -96,6 +200,47 @@ where
     result
 }
 
+/// The most entries a track shape may have. Far more than even the longest songs produce.
+pub const MAX_TRACK_SHAPE_ENTRIES: usize = 20_000;
+/// The most entries the `xstats` field may have. The game sends a couple dozen at most.
+pub const MAX_XSTATS_ENTRIES: usize = 64;
+
+/// Checks that a separated list doesn't have more than `max` entries.
+fn validate_entry_count(
+    s: &str,
+    separator: char,
+    max: usize,
+    code: &'static str,
+) -> Result<(), ValidationError> {
+    let entries = s
+        .strip_suffix(separator)
+        .unwrap_or(s)
+        .split(separator)
+        .count();
+    if entries > max {
+        let mut error = ValidationError::new(code);
+        error.add_param("entries".into(), &entries);
+        error.add_param("max".into(), &max);
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Validator for track shapes, which are 'x' separated.
+pub fn validate_track_shape(track_shape: &str) -> Result<(), ValidationError> {
+    validate_entry_count(
+        track_shape,
+        'x',
+        MAX_TRACK_SHAPE_ENTRIES,
+        "track_shape_too_long",
+    )
+}
+
+/// Validator for `xstats`, which are comma separated.
+pub fn validate_xstats(xstats: &str) -> Result<(), ValidationError> {
+    validate_entry_count(xstats, ',', MAX_XSTATS_ENTRIES, "too_many_xstats")
+}
+
 #[allow(clippy::unwrap_used)]
 #[cfg(test)]
 mod tests {
@@ -130,4 +275,79 @@ mod tests {
         let expected2 = "x";
         assert_eq!(join_x_separated(&input2), expected2);
     }
+
+    #[test]
+    fn test_validate_track_shape() {
+        // Test case 1: Normal track shape
+        assert!(validate_track_shape("1x2x3x4x").is_ok());
+
+        // Test case 2: Exactly at the limit, with trailing 'x'
+        let at_limit = "1x".repeat(MAX_TRACK_SHAPE_ENTRIES);
+        assert!(validate_track_shape(&at_limit).is_ok());
+
+        // Test case 3: Over the limit
+        let over_limit = "1x".repeat(MAX_TRACK_SHAPE_ENTRIES + 1);
+        assert!(validate_track_shape(&over_limit).is_err());
+    }
+
+    #[test]
+    fn test_validate_xstats() {
+        assert!(validate_xstats("1,2,3").is_ok());
+        assert!(validate_xstats(&vec!["0"; MAX_XSTATS_ENTRIES + 1].join(",")).is_err());
+    }
+
+    #[test]
+    fn test_parse_separated_i32() {
+        // Test case 1: Valid input, trailing separator
+        assert_eq!(
+            parse_separated_i32("1x2x-3x4x", b'x', 10).unwrap(),
+            vec![1, 2, -3, 4]
+        );
+
+        // Test case 2: Empty input
+        assert_eq!(
+            parse_separated_i32("", b',', 10).unwrap(),
+            Vec::<i32>::new()
+        );
+
+        // Test case 3: Bounds of i32
+        assert_eq!(
+            parse_separated_i32("2147483647,-2147483648", b',', 10).unwrap(),
+            vec![i32::MAX, i32::MIN]
+        );
+
+        // Test case 4: Invalid input
+        assert_eq!(
+            parse_separated_i32("1x2xAAAx", b'x', 10),
+            Err(ParseListError::InvalidNumber(2))
+        );
+        assert_eq!(
+            parse_separated_i32("1xx2", b'x', 10),
+            Err(ParseListError::InvalidNumber(1))
+        );
+        assert_eq!(
+            parse_separated_i32("2147483648", b'x', 10),
+            Err(ParseListError::InvalidNumber(0))
+        );
+        assert_eq!(
+            parse_separated_i32("-", b'x', 10),
+            Err(ParseListError::InvalidNumber(0))
+        );
+
+        // Test case 5: Too many entries
+        assert_eq!(
+            parse_separated_i32("1,2,3", b',', 2),
+            Err(ParseListError::TooManyEntries(2))
+        );
+    }
+
+    #[test]
+    fn test_feat_from_game() {
+        for feat in Feat::ALL {
+            assert_eq!(Feat::from_game(feat.as_str()), Some(feat));
+        }
+        assert_eq!(Feat::from_game("Clean Finish"), Some(Feat::CleanFinish));
+        assert_eq!(Feat::from_game("clean finish"), None);
+        assert_eq!(Feat::from_game(""), None);
+    }
 }
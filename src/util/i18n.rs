@@ -0,0 +1,404 @@
+//! Localized versions of the texts the server writes for players: the news, the shouts placeholder and notices,
+//! messages from moderators and the rival digests.
+//!
+//! English is built in. Other languages are configured in the `i18n` section of the config, one table per locale
+//! with the keys of [`Text`] mapping to templates. Placeholders like `{username}` are filled in when the text is
+//! used, a template missing one just doesn't show it. Keys a locale leaves out fall back to English.
+//!
+//! A player's locale is the one they picked, otherwise the best match for the `Accept-Language` header of the request,
+//! otherwise the configured default. Texts stored for a player (like messages from moderators) are localized
+//! when they're written, in the player's locale at that time.
+
+use std::{
+    collections::HashMap,
+    fmt::{Display, Write},
+};
+
+use anyhow::{bail, ensure};
+use axum::http::{header::ACCEPT_LANGUAGE, HeaderMap};
+use serde::Deserialize;
+
+/// A text the server writes for players, see the module documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Text {
+    /// `{username}`
+    NewsGreeting,
+    /// Shown in the news when there are no news items
+    NewsWelcome,
+    /// `{text}`
+    NewsMaintenance,
+    /// `{text}`
+    NewsChallenge,
+    NoShouts,
+    /// Shown above the shouts when a player's shout was refused for posting too often, `{limit}` per minute
+    ShoutRateLimited,
+    /// Shown above the shouts when a player's shout was refused by the text filter
+    ShoutRefused,
+    /// `{score}`, `{artist}`, `{title}`, `{league}`
+    ScoreRemoved,
+    /// `{reason}`
+    RemovalReason,
+    /// `{score}`, `{artist}`, `{title}`, `{league}`
+    AppealedScore,
+    /// For appeals of scores that are gone for good
+    AppealedScoreGone,
+    /// `{appealed}`, one of the two above
+    AppealAccepted,
+    /// `{appealed}`
+    AppealRejected,
+    DigestHeader,
+    /// `{count}`
+    DigestMore,
+    /// `{rival}`, `{score}`, `{title}`, `{artist}`, `{league}`
+    DigestActivity,
+    DigestNumberOne,
+    /// `{score}`, the player's own score
+    DigestBeatYou,
+    /// `{player}`, `{score}`, `{title}`, `{artist}`, `{league}`
+    NotifyDethroned,
+    /// `{code}`, `{minutes}`
+    NotifyCode,
+}
+
+impl Text {
+    pub const ALL: [Self; 20] = [
+        Self::NewsGreeting,
+        Self::NewsWelcome,
+        Self::NewsMaintenance,
+        Self::NewsChallenge,
+        Self::NoShouts,
+        Self::ShoutRateLimited,
+        Self::ShoutRefused,
+        Self::ScoreRemoved,
+        Self::RemovalReason,
+        Self::AppealedScore,
+        Self::AppealedScoreGone,
+        Self::AppealAccepted,
+        Self::AppealRejected,
+        Self::DigestHeader,
+        Self::DigestMore,
+        Self::DigestActivity,
+        Self::DigestNumberOne,
+        Self::DigestBeatYou,
+        Self::NotifyDethroned,
+        Self::NotifyCode,
+    ];
+
+    /// The key of the text in the config.
+    #[must_use]
+    pub const fn key(self) -> &'static str {
+        match self {
+            Self::NewsGreeting => "news_greeting",
+            Self::NewsWelcome => "news_welcome",
+            Self::NewsMaintenance => "news_maintenance",
+            Self::NewsChallenge => "news_challenge",
+            Self::NoShouts => "no_shouts",
+            Self::ShoutRateLimited => "shout_rate_limited",
+            Self::ShoutRefused => "shout_refused",
+            Self::ScoreRemoved => "score_removed",
+            Self::RemovalReason => "removal_reason",
+            Self::AppealedScore => "appealed_score",
+            Self::AppealedScoreGone => "appealed_score_gone",
+            Self::AppealAccepted => "appeal_accepted",
+            Self::AppealRejected => "appeal_rejected",
+            Self::DigestHeader => "digest_header",
+            Self::DigestMore => "digest_more",
+            Self::DigestActivity => "digest_activity",
+            Self::DigestNumberOne => "digest_number_one",
+            Self::DigestBeatYou => "digest_beat_you",
+            Self::NotifyDethroned => "notify_dethroned",
+            Self::NotifyCode => "notify_code",
+        }
+    }
+
+    /// The built-in English template.
+    #[must_use]
+    pub const fn english(self) -> &'static str {
+        match self {
+            Self::NewsGreeting => "Hi, {username}!",
+            Self::NewsWelcome => "Welcome to wavebreaker-rs,\nthe next generation of Wavebreaker!",
+            Self::NewsMaintenance => "Maintenance: {text}",
+            Self::NewsChallenge => "Challenge: {text}",
+            Self::NoShouts => {
+                "This song has no shouts yet. Let's change that!\n'Cause we're gonna shout it loud!"
+            }
+            Self::ShoutRateLimited => {
+                "Your shout wasn't posted, you can only shout {limit} times a minute. Try again in a bit!"
+            }
+            Self::ShoutRefused => {
+                "Your shout wasn't posted, it looks like spam or has words in it that aren't allowed."
+            }
+            Self::ScoreRemoved => {
+                "Your score of {score} on {artist} - {title} ({league}) was removed by a moderator."
+            }
+            Self::RemovalReason => "Reason: {reason}",
+            Self::AppealedScore => "your score of {score} on {artist} - {title} ({league})",
+            Self::AppealedScoreGone => "your removed score",
+            Self::AppealAccepted => "Your appeal was accepted, {appealed} is back.",
+            Self::AppealRejected => "Your appeal was rejected, {appealed} stays removed.",
+            Self::DigestHeader => "Here's what your rivals did:",
+            Self::DigestMore => "...and {count} more",
+            Self::DigestActivity => "{rival} scored {score} on {title} by {artist} ({league})",
+            Self::DigestNumberOne => ", the best score on it",
+            Self::DigestBeatYou => ", beating your {score}",
+            Self::NotifyDethroned => {
+                "{player} took the top spot on {title} by {artist} ({league}) from you with {score}."
+            }
+            Self::NotifyCode => {
+                "Your code to get Wavebreaker notifications here is {code}. It works for {minutes} minutes."
+            }
+        }
+    }
+}
+
+/// The `i18n` section of the config, see the module documentation.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Localization {
+    /// Used for players who didn't pick a locale and whose requests don't say what they prefer
+    pub default_locale: String,
+    /// Templates per locale (lowercase, like `de` or `pt-br`) and key of [`Text`]
+    pub locales: HashMap<String, HashMap<String, String>>,
+}
+
+impl Default for Localization {
+    fn default() -> Self {
+        Self {
+            default_locale: "en".to_owned(),
+            locales: HashMap::new(),
+        }
+    }
+}
+
+impl Localization {
+    /// Checks what the types can't, called when the config is loaded.
+    ///
+    /// # Errors
+    /// Fails with the first locale that isn't lowercase or has a key that isn't one of [`Text`].
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (locale, texts) in &self.locales {
+            ensure!(
+                *locale == locale.to_ascii_lowercase(),
+                "i18n.locales.{locale} has to be lowercase"
+            );
+            if let Some(key) = texts
+                .keys()
+                .find(|key| Text::ALL.iter().all(|text| text.key() != key.as_str()))
+            {
+                bail!("i18n.locales.{locale}.{key} isn't a known text");
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether texts can be written in the locale, English always can.
+    #[must_use]
+    pub fn is_available(&self, locale: &str) -> bool {
+        let locale = locale.to_ascii_lowercase();
+        locale == "en" || self.locales.contains_key(&locale)
+    }
+
+    /// Picks the locale for a player, see the module documentation.
+    #[must_use]
+    pub fn negotiate(&self, preferred: Option<&str>, accept_language: Option<&str>) -> String {
+        preferred
+            .into_iter()
+            .chain(
+                accept_language
+                    .map(parse_accept_language)
+                    .unwrap_or_default(),
+            )
+            .map(str::to_ascii_lowercase)
+            .find_map(|locale| {
+                if self.is_available(&locale) {
+                    return Some(locale);
+                }
+                // "de-at" is still better served by "de" than by the default
+                let language = locale.split('-').next()?;
+                self.is_available(language).then(|| language.to_owned())
+            })
+            .unwrap_or_else(|| self.default_locale.to_ascii_lowercase())
+    }
+
+    /// Writes a text in the locale, filling in the placeholders.
+    /// Falls back to the locale's language, then English. Locales that aren't available get the default locale.
+    #[must_use]
+    pub fn text(&self, locale: &str, text: Text, args: &[(&str, &dyn Display)]) -> String {
+        let locale = locale.to_ascii_lowercase();
+        let language = locale.split('-').next().unwrap_or_default();
+        let template = if self.is_available(&locale) || self.is_available(language) {
+            [locale.as_str(), language]
+                .into_iter()
+                .find_map(|locale| self.locales.get(locale)?.get(text.key()))
+        } else {
+            self.locales
+                .get(&self.default_locale.to_ascii_lowercase())
+                .and_then(|texts| texts.get(text.key()))
+        };
+        fill(
+            template.map_or_else(|| text.english(), String::as_str),
+            args,
+        )
+    }
+}
+
+/// The `Accept-Language` header of a request, for [`Localization::negotiate`].
+#[must_use]
+pub fn accept_language(headers: &HeaderMap) -> Option<&str> {
+    headers.get(ACCEPT_LANGUAGE)?.to_str().ok()
+}
+
+/// The language tags of an `Accept-Language` header, most preferred first.
+/// Tags with a quality of 0 (not acceptable) and the `*` wildcard are left out.
+fn parse_accept_language(header: &str) -> Vec<&str> {
+    let mut tags: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |quality| quality.trim().parse().ok())?;
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    // Stable, so equally preferred tags keep their order
+    tags.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// Replaces the `{name}` placeholders of the template with the arguments, in one pass, so what's filled in is
+/// never taken for a placeholder itself. Placeholders without an argument are left as they are.
+fn fill(template: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let arg = placeholder.find('}').and_then(|end| {
+            args.iter()
+                .find(|(name, _)| *name == &placeholder[1..end])
+                .map(|(_, value)| (end, value))
+        });
+        if let Some((end, value)) = arg {
+            // Writing to a String can't fail
+            let _ = write!(filled, "{value}");
+            rest = &placeholder[end + 1..];
+        } else {
+            filled.push('{');
+            rest = &placeholder[1..];
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn german() -> Localization {
+        Localization {
+            default_locale: "en".to_owned(),
+            locales: HashMap::from([(
+                "de".to_owned(),
+                HashMap::from([("news_greeting".to_owned(), "Hallo, {username}!".to_owned())]),
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_parse_accept_language() {
+        assert_eq!(
+            parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"),
+            vec!["fr-CH", "fr", "en", "de"]
+        );
+        assert_eq!(parse_accept_language("en;q=0.5, de"), vec!["de", "en"]);
+        assert_eq!(parse_accept_language("de;q=0, en"), vec!["en"]);
+        assert!(parse_accept_language("").is_empty());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let i18n = german();
+        assert_eq!(i18n.negotiate(Some("de"), Some("en")), "de");
+        assert_eq!(i18n.negotiate(None, Some("fr, de-AT;q=0.8")), "de");
+        assert_eq!(i18n.negotiate(Some("fr"), None), "en");
+        assert_eq!(i18n.negotiate(None, None), "en");
+    }
+
+    #[test]
+    fn test_text() {
+        let i18n = german();
+        assert_eq!(
+            i18n.text("de-AT", Text::NewsGreeting, &[("username", &"m1nt_")]),
+            "Hallo, m1nt_!"
+        );
+        // Not translated, so English
+        assert_eq!(
+            i18n.text("de", Text::RemovalReason, &[("reason", &"Cheating")]),
+            "Reason: Cheating"
+        );
+        assert_eq!(
+            i18n.text("en", Text::DigestMore, &[("count", &3)]),
+            "...and 3 more"
+        );
+
+        let german_by_default = Localization {
+            default_locale: "de".to_owned(),
+            ..german()
+        };
+        assert_eq!(
+            german_by_default.text("fr", Text::NewsGreeting, &[("username", &"m1nt_")]),
+            "Hallo, m1nt_!"
+        );
+        assert_eq!(
+            german_by_default.text("en", Text::NewsGreeting, &[("username", &"m1nt_")]),
+            "Hi, m1nt_!"
+        );
+    }
+
+    #[test]
+    fn test_fill() {
+        assert_eq!(
+            fill("{rival} scored {score}", &[("rival", &"A"), ("score", &1)]),
+            "A scored 1"
+        );
+        // Filled in values aren't filled in again
+        assert_eq!(
+            fill(
+                "{rival} scored {score}",
+                &[("rival", &"{score}"), ("score", &1)]
+            ),
+            "{score} scored 1"
+        );
+        assert_eq!(fill("{unknown} {a} {", &[("a", &"b")]), "{unknown} b {");
+        assert_eq!(fill("{{a}}", &[("a", &"b")]), "{b}");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(german().validate().is_ok());
+
+        let mut typo = german();
+        typo.locales
+            .get_mut("de")
+            .unwrap()
+            .insert("news_greting".to_owned(), "Hallo!".to_owned());
+        assert!(typo.validate().is_err());
+
+        let mut uppercase = german();
+        uppercase.locales = HashMap::from([("DE".to_owned(), HashMap::new())]);
+        assert!(uppercase.validate().is_err());
+    }
+
+    #[test]
+    fn test_keys_are_unique() {
+        for (i, text) in Text::ALL.iter().enumerate() {
+            assert!(Text::ALL[i + 1..]
+                .iter()
+                .all(|other| other.key() != text.key()));
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! Running several instances of the server behind a load balancer.
+//!
+//! Everything players and the API see is in Postgres and Redis, so any instance can answer any request:
+//! caches (players, song lookups, news), rate limits and quotas, rankings, recent activity and the latency windows.
+//! What's left in an instance's memory is only ever its own work, and losing it with the instance loses nothing else:
+//! the ride queue, events waiting to be written and the server time the game is sent (see [`crate::util::clock`]).
+//! Jobs are claimed with row locks, so every job is run by one instance. Migrations and queueing the recurring
+//! jobs at startup are done by one instance at a time, see [`STARTUP_LOCK`].
+//!
+//! Log lines of requests and background tasks carry the instance's ID, see [`resolve_id`].
+
+use rand::Rng;
+
+/// Key of the Postgres advisory lock held while an instance migrates the DB or queues the recurring jobs,
+/// so instances starting at the same time don't do it twice.
+pub const STARTUP_LOCK: i64 = 0x7761_7665;
+
+/// The ID of this instance: `main.instance_id` from the config, otherwise the host name (which is unique per container
+/// in most setups), otherwise a random one.
+#[must_use]
+pub fn resolve_id(configured: Option<&str>) -> String {
+    configured
+        .map(str::to_owned)
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|id| id.trim().to_owned())
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| format!("{:08x}", rand::thread_rng().gen::<u32>()))
+}
@@ -3,18 +3,24 @@
 use async_trait::async_trait;
 use axum::{
     extract::{FromRef, FromRequestParts},
-    http::{request::Parts, StatusCode},
+    http::{request::Parts, Method, StatusCode},
     RequestPartsExt,
 };
 use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
 use jsonwebtoken::{decode, DecodingKey, EncodingKey, Validation};
 use serde::{Deserialize, Serialize};
+use tracing::info;
 
 use super::errors::{IntoRouteError, RouteError};
-use crate::{models::players::Player, AppState};
+use crate::{
+    models::{impersonations::Impersonation, players::Player},
+    AppState,
+};
 #[derive(Clone)]
 pub struct Keys {
     pub encoding: EncodingKey,
@@ -49,6 +55,16 @@ impl AuthBody {
 pub struct Claims {
     pub profile: Player,
     pub exp: i64,
+    /// Set if a staff member is using the API as `profile`, see [`Impersonation`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonation: Option<ImpersonationClaim>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImpersonationClaim {
+    /// ID of the [`Impersonation`]
+    pub id: i32,
+    pub staff_id: i32,
 }
 
 #[async_trait]
@@ -74,7 +90,67 @@ where
             &Validation::default(),
         )
         .http_error("Invalid token", StatusCode::UNAUTHORIZED)?;
+        let claims = token_data.claims;
+
+        // Impersonating staff can only look, and only until the impersonation ends
+        if let Some(impersonation) = &claims.impersonation {
+            if !matches!(parts.method, Method::GET | Method::HEAD) {
+                return Err(RouteError::new_forbidden()
+                    .set_public_error_message("Impersonation is read-only"));
+            }
+            let mut conn = state.db.get().await?;
+            if !Impersonation::is_active(impersonation.id, &mut conn).await? {
+                return Err(RouteError::new_unauthorized()
+                    .set_public_error_message("Impersonation has ended"));
+            }
+            info!(
+                "Player {} impersonating player {} (impersonation {}): {} {}",
+                impersonation.staff_id,
+                claims.profile.id,
+                impersonation.id,
+                parts.method,
+                parts.uri.path()
+            );
+        }
+
+        Ok(claims)
+    }
+}
+
+/// Like [`Claims`], but only lets moderators and Wavebreaker team members through.
+/// Use this for anything under the admin API. Impersonation tokens never get through, even for staff.
+#[derive(Debug)]
+pub struct StaffClaims(pub Claims);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for StaffClaims
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = RouteError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        use crate::schema::players;
+
+        let claims = Claims::from_request_parts(parts, state).await?;
+        if claims.impersonation.is_some() {
+            return Err(RouteError::new_forbidden());
+        }
+
+        // The profile in the token can be a month old, so check if they're *still* staff
+        let state = AppState::from_ref(state);
+        let mut conn = state.db.get().await?;
+        let player: Player = players::table
+            .find(claims.profile.id)
+            .first(&mut conn)
+            .await
+            .http_status_error(StatusCode::UNAUTHORIZED)?;
+
+        if !player.is_staff() {
+            return Err(RouteError::new_forbidden());
+        }
 
-        Ok(token_data.claims)
+        Ok(Self(claims))
     }
 }
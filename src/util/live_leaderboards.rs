@@ -0,0 +1,148 @@
+//! Live updates of song leaderboards, for `GET /api/songs/:id/leaderboard/stream`.
+//!
+//! When a ride changed its song's leaderboard, an update is published on a Redis channel (see
+//! [`redis_keys::LEADERBOARD_UPDATES`]) once it's processed, so every instance hears about it, no matter which one the
+//! ride was submitted to. [`run_relay`] hands the updates on to the streams connected to this instance.
+//!
+//! Updates aren't kept anywhere. Whoever isn't connected when one is published misses it, so clients should load the
+//! leaderboard after connecting, and again whenever they're told they fell behind.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::bail;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+use tokio_stream::StreamExt;
+use tracing::{info, warn};
+
+use crate::{
+    util::{game_types::League, redis_keys},
+    AppState,
+};
+
+/// How many updates a stream can fall behind before it misses some
+const CHANNEL_CAPACITY: usize = 256;
+
+/// How long to wait before subscribing again after losing the connection to Redis
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A score that changed a song's leaderboard.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardUpdate {
+    pub song_id: i32,
+    pub league: League,
+    pub score_id: i32,
+    pub player_id: i32,
+    pub username: String,
+    pub score: i32,
+    /// Where the score is on the song's leaderboard in the league now, 1 is the top score
+    pub rank: i64,
+    /// ID of the player who lost the top spot to the score
+    pub dethroned: Option<i32>,
+    #[serde(with = "time::serde::iso8601")]
+    pub ridden_at: OffsetDateTime,
+}
+
+/// Where the streams connected to this instance get their updates from.
+#[derive(Clone)]
+pub struct LiveLeaderboards {
+    sender: broadcast::Sender<Arc<LeaderboardUpdate>>,
+}
+
+impl Default for LiveLeaderboards {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self { sender }
+    }
+}
+
+impl LiveLeaderboards {
+    /// Gets every update relayed from now on, for all songs.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<LeaderboardUpdate>> {
+        self.sender.subscribe()
+    }
+}
+
+/// Publishes the update to every instance.
+///
+/// # Errors
+/// Fails if something is wrong with Redis.
+pub async fn publish(
+    update: &LeaderboardUpdate,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> anyhow::Result<()> {
+    redis_conn
+        .publish::<_, _, ()>(
+            redis_keys::LEADERBOARD_UPDATES,
+            serde_json::to_string(update)?,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Relays published updates to this instance's streams until the server stops, subscribing again whenever the
+/// connection to Redis is lost. Meant to be spawned as a task next to the server.
+pub async fn run_relay(state: AppState) {
+    info!("Leaderboard update relay started");
+    loop {
+        if let Err(e) = relay(&state).await {
+            warn!("Lost the leaderboard update channel, subscribing again: {e:#}");
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn relay(state: &AppState) -> anyhow::Result<()> {
+    // Subscribing takes over the connection, so it can't come from the pool
+    let mut pubsub = redis::Client::open(state.config.main.redis.as_str())?
+        .get_async_pubsub()
+        .await?;
+    pubsub.subscribe(redis_keys::LEADERBOARD_UPDATES).await?;
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let update: LeaderboardUpdate = match serde_json::from_slice(message.get_payload_bytes()) {
+            Ok(update) => update,
+            Err(e) => {
+                warn!("Ignoring a malformed leaderboard update: {e}");
+                continue;
+            }
+        };
+        // Only fails if no stream is connected, which is fine
+        let _ = state.live_leaderboards.sender.send(Arc::new(update));
+    }
+
+    bail!("the connection was closed")
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_round_trip() {
+        // Updates are read back by every instance
+        let update = LeaderboardUpdate {
+            song_id: 12,
+            league: League::Elite,
+            score_id: 345,
+            player_id: 6,
+            username: "Dylan".to_owned(),
+            score: 123_456,
+            rank: 2,
+            dethroned: None,
+            ridden_at: OffsetDateTime::UNIX_EPOCH,
+        };
+        let json = serde_json::to_string(&update).unwrap();
+        assert!(json.contains("\"songId\":12"));
+        assert_eq!(
+            serde_json::from_str::<LeaderboardUpdate>(&json).unwrap(),
+            update
+        );
+    }
+}
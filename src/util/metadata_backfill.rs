@@ -0,0 +1,260 @@
+//! Looks up MusicBrainz metadata for every song that doesn't have any yet, for `wavebreaker backfill-metadata`.
+//!
+//! New songs get their metadata looked up after their first ride (see [`Job::AddMetadata`]), so this is for songs
+//! from before that, or whose lookup failed back then. Songs are looked up in the order of their IDs, `workers` at
+//! once, but no more than one is started every `interval`: a lookup makes up to three requests to MusicBrainz, which
+//! allows about one a second. The duration to search with is taken from the song's latest score, songs without any
+//! are skipped. Locked songs are left alone, like they are by the job.
+//!
+//! After every batch, the last song looked up and the counts so far are saved in Redis (see
+//! [`redis_keys::METADATA_BACKFILL`]), so a backfill that was stopped picks up where it left off when run again.
+//!
+//! [`Job::AddMetadata`]: crate::jobs::Job::AddMetadata
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use diesel::{
+    dsl::{exists, not},
+    prelude::*,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use redis::AsyncCommands;
+use tokio::{
+    sync::{Mutex, Semaphore},
+    task::JoinSet,
+    time::MissedTickBehavior,
+};
+use tracing::{info, Instrument};
+
+use crate::{
+    models::{scores::Score, songs::Song},
+    schema::{extra_song_info, scores, songs},
+    util::{cover_colors, errors::WavebreakerError, redis_keys},
+    AppState,
+};
+
+/// Songs looked up between two checkpoints
+const BATCH_SIZE: i64 = 50;
+
+/// How many songs were looked up, including the ones before the backfill was resumed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub matched: i64,
+    pub unmatched: i64,
+    /// Songs without scores, which don't have a duration to search with
+    pub skipped: i64,
+}
+
+/// Where a backfill left off, stored as the fields of a Redis hash.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Checkpoint {
+    last_song_id: i32,
+    summary: Summary,
+}
+
+impl Checkpoint {
+    /// Missing fields are taken as 0, so no checkpoint at all is a new backfill.
+    fn from_fields(fields: &HashMap<String, i64>) -> anyhow::Result<Self> {
+        let field = |name: &str| fields.get(name).copied().unwrap_or_default();
+        Ok(Self {
+            last_song_id: i32::try_from(field("last_song_id"))?,
+            summary: Summary {
+                matched: field("matched"),
+                unmatched: field("unmatched"),
+                skipped: field("skipped"),
+            },
+        })
+    }
+
+    fn to_fields(&self) -> [(&'static str, i64); 4] {
+        [
+            ("last_song_id", i64::from(self.last_song_id)),
+            ("matched", self.summary.matched),
+            ("unmatched", self.summary.unmatched),
+            ("skipped", self.summary.skipped),
+        ]
+    }
+}
+
+/// Looks up every song without metadata, resuming the last backfill unless `restart` is set.
+///
+/// # Errors
+/// Fails on database or Redis errors, the checkpoint of the last finished batch is kept then.
+/// Songs MusicBrainz has no match for (or that fail to be looked up) only count as unmatched.
+pub async fn run(
+    state: &AppState,
+    workers: usize,
+    interval: Duration,
+    restart: bool,
+) -> anyhow::Result<Summary> {
+    let mut conn = state.db.get().await?;
+    let mut redis_conn = state.redis.get().await?;
+
+    if restart {
+        redis_conn
+            .del::<_, ()>(redis_keys::METADATA_BACKFILL)
+            .await?;
+    }
+    let fields: HashMap<String, i64> = redis_conn.hgetall(redis_keys::METADATA_BACKFILL).await?;
+    let mut checkpoint = Checkpoint::from_fields(&fields)?;
+    if checkpoint.last_song_id > 0 {
+        info!(
+            "Resuming the backfill after song {}",
+            checkpoint.last_song_id
+        );
+    }
+
+    let total: i64 = Song::all()
+        .filter(songs::id.gt(checkpoint.last_song_id))
+        .filter(songs::locked.eq(false))
+        .filter(not(exists(
+            extra_song_info::table.filter(extra_song_info::song_id.eq(songs::id)),
+        )))
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    info!("{total} song(s) to look up");
+
+    let permits = Arc::new(Semaphore::new(workers.max(1)));
+    let mut pace = tokio::time::interval(interval);
+    pace.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let pace = Arc::new(Mutex::new(pace));
+    let mut done = 0;
+
+    loop {
+        let batch: Vec<Song> = Song::all()
+            .filter(songs::id.gt(checkpoint.last_song_id))
+            .filter(songs::locked.eq(false))
+            .filter(not(exists(
+                extra_song_info::table.filter(extra_song_info::song_id.eq(songs::id)),
+            )))
+            .order(songs::id)
+            .limit(BATCH_SIZE)
+            .select(Song::as_select())
+            .load(&mut conn)
+            .await?;
+        let Some(last_song_id) = batch.last().map(|song| song.id) else {
+            break;
+        };
+        let durations = latest_durations(&batch, &mut conn).await?;
+        done += batch.len();
+
+        let mut lookups = JoinSet::new();
+        for song in batch {
+            let Some(&duration) = durations.get(&song.id) else {
+                info!(
+                    "Skipped song {} ({} - {}), it has no scores to take the duration from",
+                    song.id, song.artist, song.title
+                );
+                checkpoint.summary.skipped += 1;
+                continue;
+            };
+            let permit = Arc::clone(&permits).acquire_owned().await?;
+            let state = state.clone();
+            let pace = Arc::clone(&pace);
+            lookups.spawn(
+                async move {
+                    pace.lock().await.tick().await;
+                    let matched = look_up(&state, &song, duration).await;
+                    drop(permit);
+                    matched
+                }
+                .in_current_span(),
+            );
+        }
+        while let Some(matched) = lookups.join_next().await {
+            if matched?? {
+                checkpoint.summary.matched += 1;
+            } else {
+                checkpoint.summary.unmatched += 1;
+            }
+        }
+
+        checkpoint.last_song_id = last_song_id;
+        redis_conn
+            .hset_multiple::<_, _, _, ()>(redis_keys::METADATA_BACKFILL, &checkpoint.to_fields())
+            .await?;
+        info!(
+            "Looked up {done}/{total} song(s), {} matched, {} unmatched and {} skipped so far",
+            checkpoint.summary.matched, checkpoint.summary.unmatched, checkpoint.summary.skipped
+        );
+    }
+
+    redis_conn
+        .del::<_, ()>(redis_keys::METADATA_BACKFILL)
+        .await?;
+    Ok(checkpoint.summary)
+}
+
+/// Looks up the song, and queues extracting its cover colors if it's matched.
+///
+/// # Returns
+/// Whether it was matched.
+async fn look_up(state: &AppState, song: &Song, duration: i32) -> anyhow::Result<bool> {
+    let mut conn = state.db.get().await?;
+
+    match song.auto_add_metadata(duration, &mut conn).await {
+        Ok(()) => {
+            cover_colors::queue(song.id, &mut conn).await;
+            Ok(true)
+        }
+        Err(WavebreakerError::MusicBrainz(e)) => {
+            info!(
+                "No match for song {} ({} - {}): {e:#}",
+                song.id, song.artist, song.title
+            );
+            Ok(false)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Gets the length (in milliseconds, like MusicBrainz has them) the latest score of each song was ridden with.
+/// Songs without scores are left out.
+async fn latest_durations(
+    batch: &[Song],
+    conn: &mut AsyncPgConnection,
+) -> QueryResult<HashMap<i32, i32>> {
+    let ids: Vec<i32> = batch.iter().map(|song| song.id).collect();
+
+    // The game sends song lengths in centiseconds
+    Ok(Score::all()
+        .filter(scores::song_id.eq_any(ids))
+        .distinct_on(scores::song_id)
+        .order((scores::song_id, scores::submitted_at.desc()))
+        .select((scores::song_id, scores::song_length))
+        .load::<(i32, i32)>(conn)
+        .await?
+        .into_iter()
+        .map(|(song_id, song_length)| (song_id, song_length * 10))
+        .collect())
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_fields() {
+        let checkpoint = Checkpoint {
+            last_song_id: 1234,
+            summary: Summary {
+                matched: 40,
+                unmatched: 8,
+                skipped: 2,
+            },
+        };
+        let fields: HashMap<String, i64> = checkpoint
+            .to_fields()
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value))
+            .collect();
+        assert_eq!(Checkpoint::from_fields(&fields).unwrap(), checkpoint);
+
+        assert_eq!(
+            Checkpoint::from_fields(&HashMap::new()).unwrap(),
+            Checkpoint::default()
+        );
+    }
+}
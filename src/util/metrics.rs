@@ -0,0 +1,326 @@
+//! Request latency per route, in fixed windows.
+//!
+//! Latencies are counted into histograms with fixed buckets, so recording is cheap and percentiles are approximate
+//! (they're the upper bound of the bucket they fall into). Every instance counts its requests in memory, and adds
+//! them to the ones in Redis when the window is finished, so the windows cover every instance.
+//! Every `latency_alerts.window_secs` the current window is finished. If `send_ride` got too slow during it,
+//! an alert is logged and sent to the configured webhook, by only one of the instances.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header::CONTENT_TYPE,
+    middleware::Next,
+    response::Response,
+};
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::{error, info, warn};
+
+use crate::{util::redis_keys, AppState, LatencyAlerts};
+
+/// Upper bounds of the histogram buckets, in milliseconds.
+/// Anything slower than the last one is counted into it, those requests have timed out for the game anyway.
+const BUCKETS_MS: [u64; 13] = [
+    5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 20000, 30000,
+];
+/// Windows with fewer `send_ride` requests than this never alert, a few slow requests on a quiet server aren't news.
+const MIN_ALERT_SAMPLES: u64 = 20;
+/// How long (in seconds) after a window ends it's checked for alerts, so every instance could share it.
+const ALERT_DELAY_SECS: u64 = 5;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    counts: [u64; BUCKETS_MS.len()],
+}
+
+impl Histogram {
+    pub fn record(&mut self, latency: Duration) {
+        let millis = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|bound| millis <= *bound)
+            .unwrap_or(BUCKETS_MS.len() - 1);
+        self.counts[bucket] += 1;
+    }
+
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Gets the latency (in milliseconds) that `percent` percent of the requests were at most as slow as.
+    ///
+    /// # Returns
+    /// `None` if nothing was recorded.
+    #[must_use]
+    pub fn percentile(&self, percent: u64) -> Option<u64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = (count * percent.min(100)).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (bound, bucket_count) in BUCKETS_MS.iter().zip(self.counts) {
+            seen += bucket_count;
+            if seen >= rank {
+                return Some(*bound);
+            }
+        }
+        BUCKETS_MS.last().copied()
+    }
+}
+
+/// Latency histograms of every route for the current window, of this instance only.
+/// Finished windows are added up with the other instances' in Redis, see [`last_window`].
+#[derive(Clone, Default)]
+pub struct RouteLatencies(Arc<Mutex<HashMap<String, Histogram>>>);
+
+impl RouteLatencies {
+    pub fn record(&self, route: &str, latency: Duration) {
+        let mut current = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(histogram) = current.get_mut(route) {
+            histogram.record(latency);
+        } else {
+            let mut histogram = Histogram::default();
+            histogram.record(latency);
+            current.insert(route.to_owned(), histogram);
+        }
+    }
+
+    /// Finishes the current window and starts a new one.
+    ///
+    /// # Returns
+    /// The histograms of the window that was just finished.
+    pub fn rotate(&self) -> HashMap<String, Histogram> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// Adds this instance's histograms of a finished window to the shared ones in Redis.
+async fn share_window(
+    window_start: u64,
+    window_secs: u64,
+    window: &HashMap<String, Histogram>,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> redis::RedisResult<()> {
+    let key = redis_keys::latency_window(window_start);
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for (route, histogram) in window {
+        for (bucket, count) in histogram.counts.iter().enumerate() {
+            if *count > 0 {
+                pipe.hincr(&key, format!("{bucket}:{route}"), *count)
+                    .ignore();
+            }
+        }
+    }
+    pipe.expire(&key, i64::try_from(window_secs * 3).unwrap_or(i64::MAX))
+        .ignore();
+    pipe.query_async(redis_conn).await
+}
+
+/// Gets the histograms of a window, added up over every instance.
+async fn load_window(
+    window_start: u64,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> redis::RedisResult<HashMap<String, Histogram>> {
+    let fields: HashMap<String, u64> = redis_conn
+        .hgetall(redis_keys::latency_window(window_start))
+        .await?;
+
+    let mut window: HashMap<String, Histogram> = HashMap::new();
+    for (field, count) in fields {
+        let Some((bucket, route)) = field.split_once(':') else {
+            continue;
+        };
+        let Some(bucket) = bucket
+            .parse::<usize>()
+            .ok()
+            .filter(|bucket| *bucket < BUCKETS_MS.len())
+        else {
+            continue;
+        };
+        window.entry(route.to_owned()).or_default().counts[bucket] += count;
+    }
+    Ok(window)
+}
+
+/// Gets the histograms of the last finished window, added up over every instance.
+///
+/// # Errors
+/// Fails if something is wrong with Redis.
+pub async fn last_window(
+    window_secs: u64,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> redis::RedisResult<HashMap<String, Histogram>> {
+    let window_secs = window_secs.max(1);
+    let now = OffsetDateTime::now_utc().unix_timestamp().unsigned_abs();
+    let window_start = (now / window_secs).saturating_sub(1) * window_secs;
+
+    load_window(window_start, redis_conn).await
+}
+
+/// Middleware recording how long each request took, by the route it matched.
+pub async fn track_latency(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Requests that didn't match anything would only fill the map with garbage
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned());
+
+    let start = std::time::Instant::now();
+    let response = next.run(request).await;
+    if let Some(route) = route {
+        state.latencies.record(&route, start.elapsed());
+    }
+
+    response
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LatencyAlert<'a> {
+    route: &'a str,
+    p99_ms: u64,
+    threshold_ms: u64,
+    requests: u64,
+    window_secs: u64,
+}
+
+/// Finishes a window at every multiple of `latency_alerts.window_secs` in Unix time and shares it in Redis.
+///
+/// Every instance finishes the same windows, then one of them alerts if `send_ride` was too slow during it.
+/// Meant to be spawned as a task next to the server.
+pub async fn run_windows(state: AppState) {
+    let window_secs = state.config.latency_alerts.window_secs.max(1);
+
+    loop {
+        let now = OffsetDateTime::now_utc().unix_timestamp().unsigned_abs();
+        let window_end = (now / window_secs + 1) * window_secs;
+        tokio::time::sleep(Duration::from_secs(window_end - now)).await;
+
+        let window = state.latencies.rotate();
+        if let Err(e) = finish_window(&state, window_end - window_secs, &window).await {
+            error!("Failed to finish latency window: {e:?}");
+        }
+    }
+}
+
+async fn finish_window(
+    state: &AppState,
+    window_start: u64,
+    window: &HashMap<String, Histogram>,
+) -> anyhow::Result<()> {
+    let config = &state.config.latency_alerts;
+    let window_secs = config.window_secs.max(1);
+    let mut redis_conn = state.redis.get().await?;
+    share_window(window_start, window_secs, window, &mut redis_conn).await?;
+
+    let Some(threshold_ms) = config.send_ride_p99_ms else {
+        return Ok(());
+    };
+    drop(redis_conn);
+    // The other instances finish the window at the same time, give them a moment to share theirs
+    tokio::time::sleep(Duration::from_secs(ALERT_DELAY_SECS.min(window_secs / 2))).await;
+
+    let mut redis_conn = state.redis.get().await?;
+    let claimed: bool = redis_conn
+        .set_options(
+            redis_keys::latency_alerts(window_start),
+            &*state.instance_id,
+            SetOptions::default()
+                .conditional_set(ExistenceCheck::NX)
+                .with_expiration(SetExpiry::EX(window_secs * 3)),
+        )
+        .await?;
+    if !claimed {
+        return Ok(());
+    }
+    let window = load_window(window_start, &mut redis_conn).await?;
+    drop(redis_conn);
+
+    alert(config, threshold_ms, &window).await;
+
+    Ok(())
+}
+
+/// Alerts about every `send_ride` route that was too slow during the window.
+async fn alert(config: &LatencyAlerts, threshold_ms: u64, window: &HashMap<String, Histogram>) {
+    let window_secs = config.window_secs.max(1);
+    // Every realm has its own send_ride route, they're checked separately
+    for (route, histogram) in window {
+        if !route.ends_with("/game_SendRideSteamVerified.php") {
+            continue;
+        }
+        let Some(p99_ms) = histogram.percentile(99) else {
+            continue;
+        };
+        if histogram.count() < MIN_ALERT_SAMPLES || p99_ms <= threshold_ms {
+            continue;
+        }
+
+        let alert = LatencyAlert {
+            route,
+            p99_ms,
+            threshold_ms,
+            requests: histogram.count(),
+            window_secs,
+        };
+        warn!(
+            "p99 latency of {route} was {p99_ms} ms over the last {window_secs} seconds, threshold is {threshold_ms} ms"
+        );
+        if let Some(webhook_url) = &config.webhook_url {
+            if let Err(e) = send_alert(webhook_url, &alert).await {
+                error!("Failed to send latency alert to webhook: {e:?}");
+            }
+        }
+    }
+}
+
+async fn send_alert(webhook_url: &str, alert: &LatencyAlert<'_>) -> anyhow::Result<()> {
+    reqwest::Client::new()
+        .post(webhook_url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_string(alert)?)
+        .send()
+        .await?
+        .error_for_status()?;
+    info!("Latency alert for {} sent to webhook", alert.route);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(99), None);
+
+        for _ in 0..98 {
+            histogram.record(Duration::from_millis(3));
+        }
+        histogram.record(Duration::from_millis(80));
+        histogram.record(Duration::from_secs(45));
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.percentile(50), Some(5));
+        assert_eq!(histogram.percentile(98), Some(5));
+        assert_eq!(histogram.percentile(99), Some(100));
+        // Slower than the last bucket still counts into it
+        assert_eq!(histogram.percentile(100), Some(30000));
+    }
+}
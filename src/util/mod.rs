@@ -1,7 +1,41 @@
+pub mod activity;
+pub mod api_quota;
+pub mod bogus_songs;
+pub mod client_source;
+pub mod clock;
+pub mod cover_colors;
+pub mod doctor;
 pub mod errors;
 pub mod game_types;
+pub mod i18n;
+pub mod instance;
 pub mod jwt;
+pub mod live_leaderboards;
+pub mod metadata_backfill;
+pub mod metrics;
 pub mod modifiers;
 pub mod musicbrainz;
+pub mod news;
+pub mod normalize;
+pub mod notify;
+pub mod previews;
 pub mod radio;
+pub mod rank_cache;
+pub mod ranking_store;
+pub mod rankings;
+pub mod realm;
+pub mod redis_keys;
+pub mod redis_ops;
+pub mod reserved_songs;
+pub mod sandbagging;
+pub mod scoring;
+pub mod self_check;
+pub mod slug;
+pub mod song_creation;
 pub mod steam_openid;
+pub mod text_filter;
+pub mod time_zone;
+pub mod timeseries;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod traffic;
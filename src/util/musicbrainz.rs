@@ -5,7 +5,10 @@ use musicbrainz_rs::{
 };
 use tracing::{error, info};
 
-use crate::models::songs::Song;
+use crate::{
+    models::{extra_song_info::ExtraSongInfo, songs::Song},
+    util::normalize::normalize_tag,
+};
 
 #[derive(Debug, AsChangeset, Insertable)]
 #[diesel(table_name = crate::schema::extra_song_info)]
@@ -13,9 +16,74 @@ pub struct MusicBrainzInfo {
     pub cover_url: Option<String>,
     pub cover_url_small: Option<String>,
     pub mbid: String,
+    pub release_mbid: String,
+    pub release_group_mbid: Option<String>,
+    pub release_group_title: Option<String>,
+    pub release_track_count: Option<i32>,
+    pub artist_mbids: Vec<String>,
     pub musicbrainz_title: String,
     pub musicbrainz_artist: String,
     pub musicbrainz_length: i32,
+    pub lookup_title: String,
+    pub lookup_artist: String,
+}
+
+impl MusicBrainzInfo {
+    /// Takes the `fields` (see [`MUSICBRAINZ_FIELDS`]) from the existing metadata instead, so updating the song with
+    /// this doesn't change them.
+    ///
+    /// [`MUSICBRAINZ_FIELDS`]: crate::models::metadata_provenance::MUSICBRAINZ_FIELDS
+    pub fn keep(&mut self, fields: &[String], existing: &ExtraSongInfo) {
+        for field in fields {
+            match field.as_str() {
+                "mbid" => keep_required(&mut self.mbid, existing.mbid.as_deref()),
+                "release_mbid" => {
+                    keep_required(&mut self.release_mbid, existing.release_mbid.as_deref())
+                }
+                "release_group_mbid" => {
+                    self.release_group_mbid
+                        .clone_from(&existing.release_group_mbid);
+                }
+                "release_group_title" => {
+                    self.release_group_title
+                        .clone_from(&existing.release_group_title);
+                }
+                "release_track_count" => self.release_track_count = existing.release_track_count,
+                "artist_mbids" => {
+                    self.artist_mbids = existing.artist_mbids.iter().flatten().cloned().collect();
+                }
+                "musicbrainz_title" => {
+                    keep_required(
+                        &mut self.musicbrainz_title,
+                        existing.musicbrainz_title.as_deref(),
+                    );
+                    self.lookup_title = normalize_tag(&self.musicbrainz_title);
+                }
+                "musicbrainz_artist" => {
+                    keep_required(
+                        &mut self.musicbrainz_artist,
+                        existing.musicbrainz_artist.as_deref(),
+                    );
+                    self.lookup_artist = normalize_tag(&self.musicbrainz_artist);
+                }
+                "musicbrainz_length" => {
+                    if let Some(length) = existing.musicbrainz_length {
+                        self.musicbrainz_length = length;
+                    }
+                }
+                "cover_url" => self.cover_url.clone_from(&existing.cover_url),
+                "cover_url_small" => self.cover_url_small.clone_from(&existing.cover_url_small),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Keeps an existing value of a field MusicBrainz always has, the new one stays if there isn't one.
+fn keep_required(new: &mut String, existing: Option<&str>) {
+    if let Some(existing) = existing {
+        existing.clone_into(new);
+    }
 }
 
 // TODO: Make this code less bad
@@ -46,28 +114,10 @@ pub async fn lookup_metadata(song: &Song, duration: i32) -> anyhow::Result<Music
         None => return Err(anyhow::anyhow!("No release found for recording")),
     };
 
-    let cover_url = match release.get_coverart().front().res_500().execute().await {
-        Ok(cover_resp) => match cover_resp {
-            CoverartResponse::Json(cover) => Some(cover.images[0].image.clone()),
-            CoverartResponse::Url(url) => Some(url),
-        },
-        Err(e) => {
-            error!("Failed to fetch cover of {}: {:?}", release.id, e);
-            None
-        }
-    };
-
-    let cover_url_small = match release.get_coverart().front().res_250().execute().await {
-        Ok(cover_resp) => match cover_resp {
-            CoverartResponse::Json(cover) => Some(cover.images[0].image.clone()),
-            CoverartResponse::Url(url) => Some(url),
-        },
-        Err(e) => {
-            error!("Failed to fetch small cover of {}: {:?}", release.id, e);
-            None
-        }
-    };
+    let (cover_url, cover_url_small) = fetch_covers(&release).await;
+    let (release_group_mbid, release_group_title) = fetch_release_group(&release).await;
 
+    let artist_mbids = artist_mbids(&recording);
     let mbid = recording.id;
     let musicbrainz_title = recording.title;
     let musicbrainz_artist = match recording.artist_credit {
@@ -93,6 +143,13 @@ pub async fn lookup_metadata(song: &Song, duration: i32) -> anyhow::Result<Music
         cover_url,
         cover_url_small,
         mbid,
+        release_group_mbid,
+        release_group_title,
+        release_track_count: track_count(&release),
+        release_mbid: release.id,
+        artist_mbids,
+        lookup_title: normalize_tag(&musicbrainz_title),
+        lookup_artist: normalize_tag(&musicbrainz_artist),
         musicbrainz_title,
         musicbrainz_artist,
         musicbrainz_length: musicbrainz_length.unwrap_or_default(),
@@ -118,7 +175,12 @@ pub async fn lookup_mbid(
     let release = match release_mbid {
         Some(release_mbid) => {
             info!("Fetching release from MBID: {:?}", release_mbid);
-            match Release::fetch().id(release_mbid).execute().await {
+            match Release::fetch()
+                .id(release_mbid)
+                .with_release_groups()
+                .execute()
+                .await
+            {
                 Ok(release_result) => release_result,
                 Err(_) => {
                     return Err(anyhow::anyhow!("Failed to fetch release from MBID"));
@@ -131,28 +193,10 @@ pub async fn lookup_mbid(
         },
     };
 
-    let cover_url = match release.get_coverart().front().res_500().execute().await {
-        Ok(cover_resp) => match cover_resp {
-            CoverartResponse::Json(cover) => Some(cover.images[0].image.clone()),
-            CoverartResponse::Url(url) => Some(url),
-        },
-        Err(e) => {
-            error!("Failed to fetch cover of {}: {:?}", release.id, e);
-            None
-        }
-    };
-
-    let cover_url_small = match release.get_coverart().front().res_250().execute().await {
-        Ok(cover_resp) => match cover_resp {
-            CoverartResponse::Json(cover) => Some(cover.images[0].image.clone()),
-            CoverartResponse::Url(url) => Some(url),
-        },
-        Err(e) => {
-            error!("Failed to fetch small cover of {}: {:?}", release.id, e);
-            None
-        }
-    };
+    let (cover_url, cover_url_small) = fetch_covers(&release).await;
+    let (release_group_mbid, release_group_title) = fetch_release_group(&release).await;
 
+    let artist_mbids = artist_mbids(&recording);
     let mbid = recording.id;
     let musicbrainz_title = recording.title;
     let musicbrainz_artist = match recording.artist_credit {
@@ -178,8 +222,164 @@ pub async fn lookup_mbid(
         cover_url,
         cover_url_small,
         mbid,
+        release_group_mbid,
+        release_group_title,
+        release_track_count: track_count(&release),
+        release_mbid: release.id,
+        artist_mbids,
+        lookup_title: normalize_tag(&musicbrainz_title),
+        lookup_artist: normalize_tag(&musicbrainz_artist),
         musicbrainz_title,
         musicbrainz_artist,
         musicbrainz_length: musicbrainz_length.unwrap_or_default(),
     })
 }
+
+/// Whether the string has the shape of a [MusicBrainz ID](https://musicbrainz.org/doc/MusicBrainz_Identifier),
+/// a lowercase UUID.
+#[allow(clippy::doc_markdown)]
+#[must_use]
+pub fn is_mbid(id: &str) -> bool {
+    id.len() == 36
+        && id.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => matches!(c, '0'..='9' | 'a'..='f'),
+        })
+}
+
+/// Gets the front cover of the release in 500px and 250px, if it has one.
+async fn fetch_covers(release: &Release) -> (Option<String>, Option<String>) {
+    let cover_url = match release.get_coverart().front().res_500().execute().await {
+        Ok(cover_resp) => match cover_resp {
+            CoverartResponse::Json(cover) => Some(cover.images[0].image.clone()),
+            CoverartResponse::Url(url) => Some(url),
+        },
+        Err(e) => {
+            error!("Failed to fetch cover of {}: {:?}", release.id, e);
+            None
+        }
+    };
+
+    let cover_url_small = match release.get_coverart().front().res_250().execute().await {
+        Ok(cover_resp) => match cover_resp {
+            CoverartResponse::Json(cover) => Some(cover.images[0].image.clone()),
+            CoverartResponse::Url(url) => Some(url),
+        },
+        Err(e) => {
+            error!("Failed to fetch small cover of {}: {:?}", release.id, e);
+            None
+        }
+    };
+
+    (cover_url, cover_url_small)
+}
+
+/// Gets the MBID and title of the release group (the album, across its editions) the release is in.
+/// Fetches the release again if it came without it.
+async fn fetch_release_group(release: &Release) -> (Option<String>, Option<String>) {
+    let release_group = match &release.release_group {
+        Some(release_group) => Some(release_group.clone()),
+        None => match Release::fetch()
+            .id(&release.id)
+            .with_release_groups()
+            .execute()
+            .await
+        {
+            Ok(release) => release.release_group,
+            Err(e) => {
+                error!("Failed to fetch release group of {}: {:?}", release.id, e);
+                None
+            }
+        },
+    };
+
+    release_group.map_or((None, None), |release_group| {
+        (Some(release_group.id), Some(release_group.title))
+    })
+}
+
+/// How many tracks the release has on all its media, `None` if MusicBrainz didn't say.
+#[allow(clippy::doc_markdown)]
+fn track_count(release: &Release) -> Option<i32> {
+    let media = release.media.as_ref()?;
+    let tracks: u32 = media.iter().map(|medium| medium.track_count).sum();
+    i32::try_from(tracks).ok()
+}
+
+/// The MBIDs of the credited artists, in the order they're credited and without duplicates.
+fn artist_mbids(recording: &Recording) -> Vec<String> {
+    let mut mbids: Vec<String> = Vec::new();
+    for credit in recording.artist_credit.iter().flatten() {
+        if !mbids.contains(&credit.artist.id) {
+            mbids.push(credit.artist.id.clone());
+        }
+    }
+    mbids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> MusicBrainzInfo {
+        MusicBrainzInfo {
+            cover_url: Some("https://coverartarchive.org/new-500.jpg".to_owned()),
+            cover_url_small: Some("https://coverartarchive.org/new-250.jpg".to_owned()),
+            mbid: "new-mbid".to_owned(),
+            release_mbid: "new-release".to_owned(),
+            release_group_mbid: None,
+            release_group_title: None,
+            release_track_count: Some(12),
+            artist_mbids: vec!["new-artist".to_owned()],
+            musicbrainz_title: "New Title".to_owned(),
+            musicbrainz_artist: "New Artist".to_owned(),
+            musicbrainz_length: 200_000,
+            lookup_title: "new title".to_owned(),
+            lookup_artist: "new artist".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_keep() {
+        let existing = ExtraSongInfo {
+            cover_url: Some("https://example.com/edited.jpg".to_owned()),
+            mbid: Some("old-mbid".to_owned()),
+            musicbrainz_title: Some("Edited Title".to_owned()),
+            ..Default::default()
+        };
+
+        let mut kept = info();
+        kept.keep(
+            &[
+                "musicbrainz_title".to_owned(),
+                "cover_url".to_owned(),
+                "cover_url_small".to_owned(),
+            ],
+            &existing,
+        );
+        assert_eq!(kept.musicbrainz_title, "Edited Title");
+        assert_eq!(kept.lookup_title, normalize_tag("Edited Title"));
+        assert_eq!(
+            kept.cover_url.as_deref(),
+            Some("https://example.com/edited.jpg")
+        );
+        // None isn't written, so the existing (lack of a) small cover stays too
+        assert_eq!(kept.cover_url_small, None);
+        assert_eq!(kept.mbid, "new-mbid");
+        assert_eq!(kept.musicbrainz_artist, "New Artist");
+
+        // Without an existing value, there's nothing to keep
+        let mut artist_kept = info();
+        artist_kept.keep(&["musicbrainz_artist".to_owned()], &existing);
+        assert_eq!(artist_kept.musicbrainz_artist, "New Artist");
+    }
+
+    #[test]
+    fn test_is_mbid() {
+        assert!(is_mbid("a74b1b7f-71a5-4011-9441-d0b5e4122711"));
+        assert!(!is_mbid("A74B1B7F-71A5-4011-9441-D0B5E4122711"));
+        assert!(!is_mbid("a74b1b7f71a540119441d0b5e4122711"));
+        assert!(!is_mbid("radiohead"));
+        assert!(!is_mbid("a74b1b7f-71a5-4011-9441-d0b5e412271g"));
+    }
+}
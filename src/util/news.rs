@@ -0,0 +1,164 @@
+//! The news the game shows before playing a song, see `game_CustomNews.php`.
+//!
+//! The game fetches the news every time, so whatever they say now is what players see next. They're made of:
+//! - messages for the player, like "your score was removed", shown once and then marked as delivered
+//! - news items for everyone (maintenance warnings, challenges, announcements) until they expire
+//!
+//! Everything is written in the player's locale, see [`crate::util::i18n`].
+//! The news items are rendered once per locale and cached in Redis. Anything changing them has to call [`mark_dirty`],
+//! so the next fetch renders them again. The cache also expires on its own, so expired items disappear.
+
+use diesel_async::AsyncPgConnection;
+use redis::AsyncCommands;
+use time::OffsetDateTime;
+
+use crate::{
+    models::news_items::{NewsItem, NewsKind},
+    util::{
+        errors::WavebreakerError,
+        i18n::{Localization, Text},
+        redis_keys,
+    },
+};
+
+/// How long (in seconds) the rendered news items stay cached at most.
+const NEWS_CACHE_TTL: u64 = 60 * 10;
+
+/// Renders the news items, most important kinds first. Newest first within a kind, as they're given.
+/// The items themselves are written by moderators and aren't translated.
+#[must_use]
+pub fn render_items(items: &[NewsItem], i18n: &Localization, locale: &str) -> String {
+    let mut rendered = Vec::with_capacity(items.len());
+    for kind in NewsKind::ALL {
+        for item in items.iter().filter(|item| item.kind == kind.as_str()) {
+            let text: &dyn std::fmt::Display = &item.text;
+            rendered.push(match kind {
+                NewsKind::Maintenance => {
+                    i18n.text(locale, Text::NewsMaintenance, &[("text", text)])
+                }
+                NewsKind::Challenge => i18n.text(locale, Text::NewsChallenge, &[("text", text)]),
+                NewsKind::Announcement => item.text.clone(),
+            });
+        }
+    }
+    rendered.join("\n\n")
+}
+
+/// Puts together the news for a player from their messages and the rendered news items.
+#[must_use]
+pub fn compose(
+    i18n: &Localization,
+    locale: &str,
+    username: &str,
+    messages: &[String],
+    items: &str,
+) -> String {
+    let mut parts = vec![i18n.text(locale, Text::NewsGreeting, &[("username", &username)])];
+    parts.extend(messages.iter().cloned());
+    parts.push(if items.is_empty() {
+        i18n.text(locale, Text::NewsWelcome, &[])
+    } else {
+        items.to_owned()
+    });
+    parts.join("\n\n")
+}
+
+/// Gets the news items rendered in the locale, from the cache if they're in there.
+///
+/// # Errors
+/// Fails if something is wrong with the DB or Redis.
+pub async fn current_items(
+    i18n: &Localization,
+    locale: &str,
+    conn: &mut AsyncPgConnection,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> Result<String, WavebreakerError> {
+    if let Some(cached) = redis_conn
+        .hget::<_, _, Option<String>>(redis_keys::NEWS, locale)
+        .await?
+    {
+        return Ok(cached);
+    }
+
+    let items = NewsItem::active(conn).await?;
+    let rendered = render_items(&items, i18n, locale);
+
+    // Cached until the next item expires at the latest, so it doesn't linger
+    let now = OffsetDateTime::now_utc();
+    let ttl = items
+        .iter()
+        .filter_map(|item| item.expires_at)
+        .map(|expires_at| u64::try_from((expires_at - now).whole_seconds()).unwrap_or_default())
+        .fold(NEWS_CACHE_TTL, u64::min)
+        .max(1);
+    // The other locales are rendered from the same items, so their TTL is the same
+    redis::pipe()
+        .hset(redis_keys::NEWS, locale, &rendered)
+        .ignore()
+        .expire(redis_keys::NEWS, i64::try_from(ttl).unwrap_or(i64::MAX))
+        .ignore()
+        .query_async::<()>(redis_conn)
+        .await?;
+
+    Ok(rendered)
+}
+
+/// Drops the cached news items, so the next fetch renders them again.
+///
+/// # Errors
+/// Fails if something is wrong with Redis.
+pub async fn mark_dirty(redis_conn: &mut deadpool_redis::Connection) -> redis::RedisResult<()> {
+    redis_conn.del(redis_keys::NEWS).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: i32, kind: NewsKind, text: &str) -> NewsItem {
+        NewsItem {
+            id,
+            kind: kind.as_str().to_owned(),
+            text: text.to_owned(),
+            created_by: None,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn test_render_items() {
+        let items = [
+            item(4, NewsKind::Announcement, "New vehicles stats page"),
+            item(3, NewsKind::Challenge, "Song of the week: Dear Music."),
+            item(2, NewsKind::Announcement, "Welcome back!"),
+            item(1, NewsKind::Maintenance, "Down at 20:00 UTC"),
+        ];
+        let i18n = Localization::default();
+        assert_eq!(
+            render_items(&items, &i18n, "en"),
+            "Maintenance: Down at 20:00 UTC\n\nChallenge: Song of the week: Dear Music.\n\n\
+             New vehicles stats page\n\nWelcome back!"
+        );
+        assert_eq!(render_items(&[], &i18n, "en"), "");
+    }
+
+    #[test]
+    fn test_compose() {
+        let i18n = Localization::default();
+        assert_eq!(
+            compose(&i18n, "en", "m1nt_", &[], ""),
+            format!("Hi, m1nt_!\n\n{}", Text::NewsWelcome.english())
+        );
+        assert_eq!(
+            compose(
+                &i18n,
+                "en",
+                "m1nt_",
+                &["Your score was removed.".to_owned()],
+                "Challenge: Beat it"
+            ),
+            "Hi, m1nt_!\n\nYour score was removed.\n\nChallenge: Beat it"
+        );
+    }
+}
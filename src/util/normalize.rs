@@ -0,0 +1,60 @@
+//! Brings song titles and artists into one canonical form, so tags from different sources can be matched.
+//!
+//! The game mangles tags before sending them (lowercase, "&" becomes "and", ...),
+//! while [MusicBrainz](https://musicbrainz.org) has them the way they're printed on the cover.
+//! Both sides are normalized the same way before they're compared:
+//! metadata and aliases when they're stored, the game's tags when a song is looked up.
+//!
+//! Changing the rules here means existing rows have to be normalized again, with the `normalize-tags` command.
+//! The database has its own copy of [`normalize_tag`] for tags that aren't stored normalized (the songs' own),
+//! which has to be changed along with it, in a new migration.
+
+/// Normalizes a title or artist:
+/// - lowercase
+/// - "&" is written out as "and"
+/// - typographic quotes and dashes become their plain ASCII versions
+/// - whitespace is collapsed to single spaces and trimmed
+#[must_use]
+pub fn normalize_tag(tag: &str) -> String {
+    let replaced: String = tag
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' | '`' | '\u{b4}' => '\'',
+            '\u{201c}' | '\u{201d}' => '"',
+            '\u{2010}' | '\u{2011}' | '\u{2013}' | '\u{2014}' => '-',
+            c => c,
+        })
+        .collect::<String>()
+        .replace('&', " and ");
+
+    replaced.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_tag() {
+        assert_eq!(normalize_tag("Simon & Garfunkel"), "simon and garfunkel");
+        assert_eq!(normalize_tag("Simon&Garfunkel"), "simon and garfunkel");
+        assert_eq!(normalize_tag("simon and garfunkel"), "simon and garfunkel");
+        assert_eq!(
+            normalize_tag("Don\u{2019}t Stop Me Now"),
+            "don't stop me now"
+        );
+        assert_eq!(normalize_tag("  Take   Five \t"), "take five");
+        assert_eq!(normalize_tag("Jay\u{2010}Z"), "jay-z");
+        assert_eq!(normalize_tag("ÉMILIE SIMON"), "émilie simon");
+        assert_eq!(normalize_tag(""), "");
+    }
+
+    #[test]
+    fn test_normalize_tag_is_idempotent() {
+        for tag in ["Rock & Roll", "  Don\u{2019}t  ", "AC/DC", "&&"] {
+            let normalized = normalize_tag(tag);
+            assert_eq!(normalize_tag(&normalized), normalized);
+        }
+    }
+}
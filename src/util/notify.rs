@@ -0,0 +1,231 @@
+//! Notifications outside of the game, sent to the email addresses and Discord accounts players linked,
+//! see [`crate::models::notification_links`].
+//!
+//! Nothing is sent while anyone waits: [`queue`] puts a [`Job::Notify`] into the job queue for every channel the
+//! player wants the notification on, and the job worker delivers it with [`deliver`], retrying it if that fails.
+//! Codes confirming a link are queued as a [`Job::SendNotificationCode`], which makes the code when it runs.
+//! Emails go through the SMTP server in the `notifications` section of the config, which needs the `smtp` feature.
+//! Discord notifications are direct messages from a bot, which only reach players sharing a server with it.
+
+use std::time::Duration;
+
+use anyhow::bail;
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use diesel_async::AsyncPgConnection;
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    jobs::Job,
+    models::notification_links::{NotificationChannel, NotificationKind, NotificationLink},
+    Notifications,
+};
+
+/// Longest email address there can be
+const MAX_EMAIL_LENGTH: usize = 320;
+/// Discord doesn't take messages over 2000 characters
+const MAX_DISCORD_LENGTH: usize = 2000;
+const DISCORD_API: &str = "https://discord.com/api/v10";
+/// How long a request to Discord can take before the job is retried
+const DISCORD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether players can link the channel with what's configured.
+#[must_use]
+pub(crate) fn is_available(settings: &Notifications, channel: NotificationChannel) -> bool {
+    match channel {
+        NotificationChannel::Email => {
+            cfg!(feature = "smtp")
+                && settings.smtp_host.is_some()
+                && !settings.email_from.is_empty()
+        }
+        NotificationChannel::Discord => settings.discord_bot_token.is_some(),
+    }
+}
+
+/// Checks that an address looks like it can be sent to on the channel.
+///
+/// # Returns
+/// The address the way it's stored, `None` if it can't be used.
+#[must_use]
+pub fn parse_address(channel: NotificationChannel, address: &str) -> Option<String> {
+    let address = address.trim();
+    let valid = match channel {
+        // Anything more thorough rejects addresses that work, the code tells if it does
+        NotificationChannel::Email => {
+            address.len() <= MAX_EMAIL_LENGTH
+                && !address.chars().any(|c| c.is_whitespace() || c.is_control())
+                && address.split_once('@').is_some_and(|(local, domain)| {
+                    !local.is_empty() && domain.contains('.') && !domain.contains('@')
+                })
+        }
+        // Discord user IDs are snowflakes
+        NotificationChannel::Discord => {
+            (17..=20).contains(&address.len()) && address.bytes().all(|b| b.is_ascii_digit())
+        }
+    };
+    valid.then(|| address.to_owned())
+}
+
+/// A new code to confirm a link with, six digits.
+#[must_use]
+pub fn new_code() -> String {
+    format!("{:06}", rand::thread_rng().gen_range(0..1_000_000))
+}
+
+/// Queues a notification for every channel the player confirmed and wants this kind of notification on.
+///
+/// # Returns
+/// How many were queued.
+pub async fn queue(
+    player_id: i32,
+    kind: NotificationKind,
+    text: &str,
+    conn: &mut AsyncPgConnection,
+) -> anyhow::Result<usize> {
+    let channels = NotificationLink::channels_wanting(player_id, kind, conn).await?;
+    for channel in &channels {
+        Job::Notify {
+            player_id,
+            channel: *channel,
+            text: text.to_owned(),
+        }
+        .enqueue(conn)
+        .await?;
+    }
+    Ok(channels.len())
+}
+
+/// Sends the text to the address on the channel. Called by the job worker, see the module documentation.
+pub(crate) async fn deliver(
+    settings: &Notifications,
+    channel: NotificationChannel,
+    address: &str,
+    text: &str,
+) -> anyhow::Result<()> {
+    match channel {
+        NotificationChannel::Email => send_email(settings, address, text).await,
+        NotificationChannel::Discord => {
+            let Some(token) = &settings.discord_bot_token else {
+                bail!("Can't send Discord notifications without notifications.discord_bot_token");
+            };
+            send_discord(token, address, text).await
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DiscordChannel {
+    id: String,
+}
+
+/// Sends a direct message from the bot to the Discord user.
+async fn send_discord(token: &str, user_id: &str, text: &str) -> anyhow::Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(DISCORD_TIMEOUT)
+        .build()?;
+    let authorization = format!("Bot {token}");
+
+    // Opening the DM channel again just returns the one that exists
+    let dm_channel: DiscordChannel = serde_json::from_str(
+        &client
+            .post(format!("{DISCORD_API}/users/@me/channels"))
+            .header(AUTHORIZATION, &authorization)
+            .header(CONTENT_TYPE, "application/json")
+            .body(json!({ "recipient_id": user_id }).to_string())
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?,
+    )?;
+
+    let content: String = text.chars().take(MAX_DISCORD_LENGTH).collect();
+    client
+        .post(format!("{DISCORD_API}/channels/{}/messages", dm_channel.id))
+        .header(AUTHORIZATION, &authorization)
+        .header(CONTENT_TYPE, "application/json")
+        .body(json!({ "content": content }).to_string())
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(feature = "smtp")]
+async fn send_email(settings: &Notifications, to: &str, text: &str) -> anyhow::Result<()> {
+    use lettre::{
+        transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport, Message,
+        Tokio1Executor,
+    };
+
+    let Some(host) = &settings.smtp_host else {
+        bail!("Can't send email notifications without notifications.smtp_host");
+    };
+
+    let email = Message::builder()
+        .from(settings.email_from.parse()?)
+        .to(to.parse()?)
+        .subject("Wavebreaker")
+        .body(text.to_owned())?;
+    let mut transport =
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?.port(settings.smtp_port);
+    if !settings.smtp_username.is_empty() {
+        transport = transport.credentials(Credentials::new(
+            settings.smtp_username.clone(),
+            settings.smtp_password.clone(),
+        ));
+    }
+    transport.build().send(email).await?;
+    Ok(())
+}
+
+#[cfg(not(feature = "smtp"))]
+#[allow(clippy::unused_async)]
+async fn send_email(_settings: &Notifications, _to: &str, _text: &str) -> anyhow::Result<()> {
+    bail!("Can't send email notifications, Wavebreaker was built without the smtp feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address(NotificationChannel::Email, " duck@example.com "),
+            Some("duck@example.com".to_owned())
+        );
+        assert_eq!(parse_address(NotificationChannel::Email, "duck"), None);
+        assert_eq!(
+            parse_address(NotificationChannel::Email, "@example.com"),
+            None
+        );
+        assert_eq!(
+            parse_address(NotificationChannel::Email, "duck@localhost"),
+            None
+        );
+        assert_eq!(
+            parse_address(NotificationChannel::Email, "du ck@example.com"),
+            None
+        );
+        assert_eq!(
+            parse_address(NotificationChannel::Discord, "80351110224678912"),
+            Some("80351110224678912".to_owned())
+        );
+        assert_eq!(
+            parse_address(NotificationChannel::Discord, "duck#1234"),
+            None
+        );
+        assert_eq!(parse_address(NotificationChannel::Discord, "1234"), None);
+    }
+
+    #[test]
+    fn test_new_code() {
+        for _ in 0..100 {
+            let code = new_code();
+            assert_eq!(code.len(), 6);
+            assert!(code.bytes().all(|b| b.is_ascii_digit()));
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Finds 30 second previews of songs on [Deezer](https://developers.deezer.com/api), so the website can play what a
+//! leaderboard's song sounds like.
+//!
+//! Deezer's API doesn't need a key. Songs are searched for by their MusicBrainz title and artist (or their tags, if
+//! they don't have any) and a result only counts if it's about as long as the recording. New songs are looked up
+//! along with their metadata, and once a day the job worker looks up the songs that weren't looked up in
+//! `previews.refresh_days`, in case Deezer's catalog changed.
+//!
+//! Only the Deezer track is stored. Its preview links are signed and stop working after a while, so a fresh one is
+//! asked for when a preview is played, see [`preview_url`]. Links are cached in Redis for a few minutes, but never
+//! longer than they work, so playing the same preview over and over doesn't use up the requests Deezer allows.
+
+use std::time::Duration as StdDuration;
+
+use anyhow::bail;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use redis::AsyncCommands;
+use serde::{de::DeserializeOwned, Deserialize};
+use time::{Duration, OffsetDateTime};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{
+    models::{extra_song_info::ExtraSongInfo, songs::Song},
+    schema::extra_song_info,
+    util::redis_keys,
+};
+
+const DEEZER_API: &str = "https://api.deezer.com";
+/// How many results are looked at
+const SEARCH_LIMIT: &str = "10";
+/// How far off (in seconds) a result's length can be from the recording's
+const MAX_LENGTH_DIFFERENCE: i32 = 5;
+/// Deezer allows 50 requests in 5 seconds, this stays well below that
+const REQUEST_INTERVAL: StdDuration = StdDuration::from_millis(200);
+/// How long a request to Deezer can take, previews are also looked up while someone waits
+const REQUEST_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+/// How long (in seconds) a preview link is cached at most
+const PREVIEW_CACHE_SECS: u64 = 5 * 60;
+/// How long (in seconds) a cached preview link has to keep working, so it doesn't run out while it's loading
+const PREVIEW_EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// What Deezer answers with when something's wrong, with a status of 200.
+#[derive(Deserialize, Debug)]
+struct DeezerError {
+    #[serde(default)]
+    code: i64,
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResponse {
+    #[serde(default)]
+    data: Vec<Track>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Track {
+    id: i64,
+    /// In seconds
+    duration: i32,
+    /// Empty if the track doesn't have one
+    #[serde(default)]
+    preview: String,
+}
+
+/// Reads a response of Deezer's API, which reports errors in the body instead of the status.
+fn parse_response<T: DeserializeOwned>(body: &str) -> anyhow::Result<T> {
+    #[derive(Deserialize)]
+    struct Envelope {
+        error: Option<DeezerError>,
+    }
+
+    if let Some(error) = serde_json::from_str::<Envelope>(body)?.error {
+        bail!("Deezer error {}: {}", error.code, error.message);
+    }
+    Ok(serde_json::from_str(body)?)
+}
+
+async fn get<T: DeserializeOwned>(url: Url) -> anyhow::Result<T> {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    let body = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    parse_response(&body)
+}
+
+/// Quotes a value for Deezer's advanced search, like `artist:"..."`.
+fn quoted(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Looks up the Deezer track with the preview of the song and stores it, or that there isn't any.
+///
+/// # Returns
+/// Whether a preview was found.
+///
+/// # Errors
+/// Fails if Deezer can't be reached or answers with an error, in which case nothing is stored and the song is looked
+/// up again the next time.
+pub async fn find(song: &Song, conn: &mut AsyncPgConnection) -> anyhow::Result<bool> {
+    let Some(info) = ExtraSongInfo::belonging_to(song)
+        .select(ExtraSongInfo::as_select())
+        .first::<ExtraSongInfo>(conn)
+        .await
+        .optional()?
+    else {
+        return Ok(false);
+    };
+
+    let title = info.musicbrainz_title.as_deref().unwrap_or(&song.title);
+    let artist = info.musicbrainz_artist.as_deref().unwrap_or(&song.artist);
+    let query = format!("artist:{} track:{}", quoted(artist), quoted(title));
+    let url = Url::parse_with_params(
+        &format!("{DEEZER_API}/search"),
+        &[("q", query.as_str()), ("limit", SEARCH_LIMIT)],
+    )?;
+    let response: SearchResponse = get(url).await?;
+
+    // MusicBrainz lengths are in milliseconds
+    let track = pick_track(
+        &response.data,
+        info.musicbrainz_length.map(|length| length / 1000),
+    );
+    debug!("Deezer track of song {}: {track:?}", song.id);
+    diesel::update(&info)
+        .set((
+            extra_song_info::deezer_track_id.eq(track),
+            extra_song_info::preview_checked_at.eq(OffsetDateTime::now_utc()),
+        ))
+        .execute(conn)
+        .await?;
+
+    Ok(track.is_some())
+}
+
+/// Asks Deezer for a link to the preview of the track, which works for a while.
+///
+/// # Returns
+/// `None` if the track doesn't have a preview (anymore).
+///
+/// # Errors
+/// Fails if Deezer can't be reached or answers with an error.
+pub async fn preview_url(track_id: i64) -> anyhow::Result<Option<String>> {
+    let track: Track = get(Url::parse(&format!("{DEEZER_API}/track/{track_id}"))?).await?;
+    Ok((!track.preview.is_empty()).then_some(track.preview))
+}
+
+/// Like [`preview_url`], but the link is cached for a while, see [`cache_secs`].
+///
+/// # Errors
+/// Fails if Deezer can't be reached or answers with an error, or something is wrong with Redis.
+pub async fn cached_preview_url(
+    track_id: i64,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> anyhow::Result<Option<String>> {
+    let cache_key = redis_keys::preview(track_id);
+    if let Some(cached) = redis_conn.get::<_, Option<String>>(&cache_key).await? {
+        return Ok((!cached.is_empty()).then_some(cached));
+    }
+
+    let url = preview_url(track_id).await?;
+    let secs = url.as_deref().map_or(PREVIEW_CACHE_SECS, |url| {
+        cache_secs(url, OffsetDateTime::now_utc().unix_timestamp())
+    });
+    if secs > 0 {
+        redis_conn
+            .set_ex::<_, _, ()>(&cache_key, url.as_deref().unwrap_or_default(), secs)
+            .await?;
+    }
+    Ok(url)
+}
+
+/// How long (in seconds) the preview link can be cached: [`PREVIEW_CACHE_SECS`], or less if it stops working before
+/// that. Deezer signs its links with an `exp=` Unix timestamp, links without one are expected to work long enough.
+fn cache_secs(url: &str, now: i64) -> u64 {
+    let expires_at = url
+        .split(['?', '&', '~'])
+        .find_map(|part| {
+            part.strip_prefix("hdnea=")
+                .unwrap_or(part)
+                .strip_prefix("exp=")
+        })
+        .and_then(|exp| exp.parse::<i64>().ok());
+    expires_at.map_or(PREVIEW_CACHE_SECS, |expires_at| {
+        u64::try_from(expires_at - now - PREVIEW_EXPIRY_MARGIN_SECS)
+            .unwrap_or(0)
+            .min(PREVIEW_CACHE_SECS)
+    })
+}
+
+/// Looks up the previews of songs that weren't looked up in `refresh_days`, up to `limit` of them, the ones never
+/// looked up first. Songs whose lookup fails are logged and tried again the next time, the rest are still looked up.
+///
+/// # Returns
+/// How many songs were looked up, how many previews were found and how many lookups failed.
+pub async fn refresh(
+    refresh_days: i64,
+    limit: i64,
+    conn: &mut AsyncPgConnection,
+) -> anyhow::Result<(usize, usize, usize)> {
+    let stale = OffsetDateTime::now_utc() - Duration::days(refresh_days);
+    let songs: Vec<Song> = Song::all()
+        .inner_join(extra_song_info::table)
+        .filter(
+            extra_song_info::preview_checked_at
+                .is_null()
+                .or(extra_song_info::preview_checked_at.lt(stale)),
+        )
+        .order(extra_song_info::preview_checked_at.asc().nulls_first())
+        .limit(limit)
+        .select(Song::as_select())
+        .load(conn)
+        .await?;
+
+    let (mut found, mut failed) = (0, 0);
+    for song in &songs {
+        match find(song, conn).await {
+            Ok(true) => found += 1,
+            Ok(false) => {}
+            Err(e) => {
+                warn!("Failed to look up the preview of song {}: {e:#}", song.id);
+                failed += 1;
+            }
+        }
+        tokio::time::sleep(REQUEST_INTERVAL).await;
+    }
+    Ok((songs.len(), found, failed))
+}
+
+/// Picks the first track with a preview that's about as long as the recording, or just the first one with a preview
+/// if the recording's length (in seconds) isn't known.
+fn pick_track(tracks: &[Track], length: Option<i32>) -> Option<i64> {
+    tracks
+        .iter()
+        .filter(|track| !track.preview.is_empty())
+        .find(|track| {
+            length.map_or(true, |length| {
+                (track.duration - length).abs() <= MAX_LENGTH_DIFFERENCE
+            })
+        })
+        .map(|track| track.id)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: i64, duration: i32, preview: &str) -> Track {
+        Track {
+            id,
+            duration,
+            preview: preview.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_pick_track() {
+        let tracks = [
+            track(1, 200, ""),
+            track(2, 90, "https://cdn.example/edit.mp3"),
+            track(3, 203, "https://cdn.example/album.mp3"),
+        ];
+
+        assert_eq!(pick_track(&tracks, Some(200)), Some(3));
+        assert_eq!(pick_track(&tracks, None), Some(2));
+        assert_eq!(pick_track(&tracks, Some(300)), None);
+        assert_eq!(pick_track(&[], None), None);
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response: SearchResponse = parse_response(
+            r#"{"data": [{"id": 3135556, "title": "Harder, Better, Faster, Stronger", "duration": 224,
+                "preview": "https://cdn.example/preview.mp3", "artist": {"name": "Daft Punk"}}], "total": 1}"#,
+        )
+        .unwrap();
+        assert_eq!(response.data.len(), 1);
+        assert_eq!(response.data[0].id, 3_135_556);
+        assert_eq!(response.data[0].duration, 224);
+
+        let empty: SearchResponse = parse_response(r#"{"data": [], "total": 0}"#).unwrap();
+        assert!(empty.data.is_empty());
+
+        // Errors come with a 200, they mustn't look like there's no preview
+        let error = parse_response::<SearchResponse>(
+            r#"{"error": {"type": "Exception", "message": "Quota limit exceeded", "code": 4}}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("Quota limit exceeded"));
+        assert!(
+            parse_response::<Track>(r#"{"error": {"code": 800, "message": "no data"}}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_cache_secs() {
+        let now = 1_700_000_000;
+        let signed = |exp: i64| {
+            format!("https://cdnt-preview.dzcdn.net/api/1/1/a/b/c/0/abc.mp3?hdnea=exp={exp}~acl=/api/1/1/a/b/c/0/abc.mp3*~data=user_id=0~hmac=ff")
+        };
+        // Links working for longer are still only cached for a few minutes
+        assert_eq!(cache_secs(&signed(now + 3600), now), PREVIEW_CACHE_SECS);
+        // Not until the very end
+        assert_eq!(cache_secs(&signed(now + 150), now), 90);
+        // Links that are about to run out aren't cached at all
+        assert_eq!(cache_secs(&signed(now + 30), now), 0);
+        assert_eq!(cache_secs(&signed(now - 30), now), 0);
+        assert_eq!(
+            cache_secs("https://cdn.example/preview.mp3", now),
+            PREVIEW_CACHE_SECS
+        );
+        assert_eq!(
+            cache_secs("https://cdn.example/preview.mp3?exp=soon", now),
+            PREVIEW_CACHE_SECS
+        );
+    }
+
+    #[test]
+    fn test_quoted() {
+        assert_eq!(quoted("Daft Punk"), r#""Daft Punk""#);
+        assert_eq!(quoted(r#"12" Mix"#), r#""12\" Mix""#);
+        assert_eq!(quoted(r"AC\DC"), r#""AC\\DC""#);
+    }
+}
@@ -1,7 +1,17 @@
-use std::fs;
+use std::{
+    fs,
+    io::ErrorKind,
+    sync::{Arc, Mutex, PoisonError},
+    time::SystemTime,
+};
 
 use serde::Deserialize;
 
+const RADIO_CONFIG_PATH: &str = "WavebreakerRadio.toml";
+
+/// The radio list as last read by [`cached_radio_songs`], with when the file was modified then
+static CACHE: Mutex<Option<(SystemTime, Option<Arc<Vec<RadioSong>>>)>> = Mutex::new(None);
+
 #[derive(Deserialize, Clone)]
 struct RadioConfig {
     radio_songs: Option<Vec<RadioSong>>,
@@ -18,7 +28,32 @@ pub struct RadioSong {
 }
 
 pub fn get_radio_songs() -> anyhow::Result<Option<Vec<RadioSong>>> {
-    let config_string = fs::read_to_string("WavebreakerRadio.toml")?;
+    let config_string = fs::read_to_string(RADIO_CONFIG_PATH)?;
     let radio_config: RadioConfig = toml::from_str(&config_string)?;
     Ok(radio_config.radio_songs)
 }
+
+/// Same as [`get_radio_songs`], but only reads the file again once it was modified, for lookups that happen on every
+/// ride. Servers without radio have no file, that's `None` as well.
+///
+/// # Errors
+/// Fails if the file can't be read or parsed.
+pub async fn cached_radio_songs() -> anyhow::Result<Option<Arc<Vec<RadioSong>>>> {
+    let modified = match tokio::fs::metadata(RADIO_CONFIG_PATH).await {
+        Ok(metadata) => metadata.modified()?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let cached = CACHE.lock().unwrap_or_else(PoisonError::into_inner).clone();
+    if let Some((cached_at, songs)) = cached {
+        if cached_at == modified {
+            return Ok(songs);
+        }
+    }
+
+    let config_string = tokio::fs::read_to_string(RADIO_CONFIG_PATH).await?;
+    let radio_config: RadioConfig = toml::from_str(&config_string)?;
+    let songs = radio_config.radio_songs.map(Arc::new);
+    *CACHE.lock().unwrap_or_else(PoisonError::into_inner) = Some((modified, songs.clone()));
+    Ok(songs)
+}
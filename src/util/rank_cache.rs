@@ -0,0 +1,247 @@
+//! Read-through cache of where scores stand on their leaderboards, for ranks and percentiles.
+//!
+//! Counting the scores above one takes a query over the whole leaderboard, which gets slow on popular songs.
+//! Instead, the scores of a song's leaderboard in a league are kept in Redis as a sorted set (see
+//! [`redis_keys::rank_index`]), which answers it without going through them. The index is built from the database
+//! the first time it's needed, and deleted whenever scores on the leaderboard change (see [`invalidate`] and
+//! [`RankingStore::invalidate_rank_index`]), so it's built again on the next lookup. That includes archiving and
+//! restoring a song's scores and merging players.
+//!
+//! Deleting the index also bumps its version (see [`redis_keys::rank_index_version`]). An index is only stored if
+//! the version is still the one from before its scores were read, so a lookup that read the scores just before they
+//! changed can't put the old ones back.
+//!
+//! If Redis fails, the ranks are counted in the database instead.
+//!
+//! [`redis_keys::rank_index`]: crate::util::redis_keys::rank_index
+//! [`redis_keys::rank_index_version`]: crate::util::redis_keys::rank_index_version
+
+use std::future::Future;
+
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use tracing::warn;
+
+use crate::{
+    models::scores::Score,
+    schema::scores,
+    util::{errors::WavebreakerError, game_types::League, ranking_store::RankingStore},
+};
+
+/// How long (in seconds) an index is kept
+pub const INDEX_TTL: i64 = 60 * 10;
+
+/// How long (in seconds) the version of an index is kept after it was bumped, longer than any lookup takes
+pub const VERSION_TTL: i64 = 60 * 60;
+
+/// Gets how a score of `points` stands on the song's leaderboard in the league.
+///
+/// # Returns
+/// How many scores are better and how many there are in total. The score itself counts if it's on the leaderboard.
+///
+/// # Errors
+/// Fails if something is wrong with the DB, Redis failing only makes it go to the DB.
+pub async fn standing(
+    song_id: i32,
+    league: League,
+    points: i32,
+    conn: &mut AsyncPgConnection,
+    store: &mut impl RankingStore,
+) -> QueryResult<(i64, i64)> {
+    let loading_conn = &mut *conn;
+    let read = read_through(song_id, league, points, store, move || async move {
+        Score::all()
+            .filter(scores::song_id.eq(song_id))
+            .filter(scores::league.eq(league))
+            .select((scores::id, scores::score))
+            .load(loading_conn)
+            .await
+    })
+    .await;
+    match read {
+        Ok(standing) => Ok(standing),
+        Err(WavebreakerError::Database(e)) => Err(e),
+        Err(e) => {
+            warn!("Failed to look up the rank index of song {song_id} ({league:?}): {e}");
+            database_standing(song_id, league, points, conn).await
+        }
+    }
+}
+
+/// Drops the indexes of the songs' leaderboards in every league, after their scores changed in bulk.
+///
+/// # Errors
+/// Fails if something is wrong with Redis.
+pub async fn invalidate(song_ids: &[i32], store: &mut impl RankingStore) -> redis::RedisResult<()> {
+    for song_id in song_ids {
+        for league in League::ALL {
+            store.invalidate_rank_index(*song_id, league).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Answers from the index, or builds it with the scores `load` gets if there isn't one.
+async fn read_through<F, Fut>(
+    song_id: i32,
+    league: League,
+    points: i32,
+    store: &mut impl RankingStore,
+    load: F,
+) -> Result<(i64, i64), WavebreakerError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = QueryResult<Vec<(i32, i32)>>>,
+{
+    if let Some(standing) = store.rank_index_standing(song_id, league, points).await? {
+        return Ok(standing);
+    }
+
+    // Read before the scores, so a change in between shows
+    let version = store.rank_index_version(song_id, league).await?;
+    let entries = load().await?;
+    // An empty leaderboard is quick to count, so there's nothing to store
+    if !entries.is_empty() {
+        match store
+            .store_rank_index(song_id, league, &entries, version)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("Not storing the rank index of song {song_id} ({league:?}), it changed while it was built");
+            }
+            Err(e) => {
+                warn!("Failed to store the rank index of song {song_id} ({league:?}): {e}");
+            }
+        }
+    }
+
+    Ok(standing_among(
+        entries.iter().map(|(_, entry_points)| *entry_points),
+        points,
+    ))
+}
+
+/// Same as [`standing`], straight from the database.
+async fn database_standing(
+    song_id: i32,
+    league: League,
+    points: i32,
+    conn: &mut AsyncPgConnection,
+) -> QueryResult<(i64, i64)> {
+    let on_leaderboard = || {
+        Score::all()
+            .filter(scores::song_id.eq(song_id))
+            .filter(scores::league.eq(league))
+    };
+    let better: i64 = on_leaderboard()
+        .filter(scores::score.gt(points))
+        .count()
+        .get_result(conn)
+        .await?;
+    let total: i64 = on_leaderboard().count().get_result(conn).await?;
+
+    Ok((better, total))
+}
+
+/// Counts the scores better than `points` and all of them.
+pub(crate) fn standing_among(entries: impl IntoIterator<Item = i32>, points: i32) -> (i64, i64) {
+    entries
+        .into_iter()
+        .fold((0, 0), |(better, total), entry_points| {
+            (better + i64::from(entry_points > points), total + 1)
+        })
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::ranking_store::MemoryRankingStore;
+
+    #[test]
+    fn test_standing_among() {
+        assert_eq!(standing_among([500, 300, 300, 100], 300), (1, 4));
+        // Ties share the better rank
+        assert_eq!(standing_among([300, 300], 300), (0, 2));
+        assert_eq!(standing_among([500], 600), (0, 1));
+        assert_eq!(standing_among(std::iter::empty(), 100), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_read_through_hit() {
+        let mut store = MemoryRankingStore::default();
+        let entries = vec![(1, 500), (2, 300), (3, 100)];
+
+        let built = read_through(7, League::Pro, 300, &mut store, move || async move {
+            Ok(entries)
+        })
+        .await
+        .unwrap();
+        assert_eq!(built, (1, 3));
+
+        // Answered from the index now, without loading the scores
+        let cached = read_through(7, League::Pro, 50, &mut store, || async {
+            Err(diesel::result::Error::NotFound)
+        })
+        .await
+        .unwrap();
+        assert_eq!(cached, (3, 3));
+        // Other leaderboards aren't
+        assert!(read_through(7, League::Elite, 50, &mut store, || async {
+            Err(diesel::result::Error::NotFound)
+        })
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_through_after_invalidate() {
+        let mut store = MemoryRankingStore::default();
+        read_through(7, League::Pro, 300, &mut store, || async move {
+            Ok(vec![(1, 500), (2, 300)])
+        })
+        .await
+        .unwrap();
+
+        invalidate(&[7], &mut store).await.unwrap();
+        assert_eq!(
+            store.invalidated_rank_indexes,
+            vec![(7, League::Casual), (7, League::Pro), (7, League::Elite)]
+        );
+        let rebuilt = read_through(7, League::Pro, 300, &mut store, || async move {
+            Ok(vec![(1, 500), (2, 300), (3, 400)])
+        })
+        .await
+        .unwrap();
+        assert_eq!(rebuilt, (2, 3));
+    }
+
+    #[tokio::test]
+    async fn test_stale_index_not_stored() {
+        let mut store = MemoryRankingStore::default();
+        let entries = [(1, 500), (2, 300)];
+
+        // The scores changed after they were read for the index
+        let version = store.rank_index_version(7, League::Pro).await.unwrap();
+        store.invalidate_rank_index(7, League::Pro).await.unwrap();
+        let stored = store
+            .store_rank_index(7, League::Pro, &entries, version)
+            .await
+            .unwrap();
+        assert!(!stored);
+        assert_eq!(
+            store
+                .rank_index_standing(7, League::Pro, 300)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let version = store.rank_index_version(7, League::Pro).await.unwrap();
+        assert!(store
+            .store_rank_index(7, League::Pro, &entries, version)
+            .await
+            .unwrap());
+    }
+}
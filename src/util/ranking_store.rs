@@ -0,0 +1,413 @@
+//! The Redis side of changing scores and songs, behind [`RankingStore`].
+//!
+//! Deleting, restoring, merging and submitting scores (and deleting songs) only ever do a few things in Redis: change
+//! the players' points in the rankings (see [`crate::util::rankings`]), drop rank indexes (see
+//! [`crate::util::rank_cache`]) and drop cached song lookups (see [`Song::invalidate_lookups`]). Looking up and
+//! building the rank indexes goes through it too. Those functions take any [`RankingStore`], so callers pass their
+//! Redis connection, or a [`DeferredRankingStore`] inside a database transaction. The unit tests of [`crate::util::rankings`] and [`crate::util::rank_cache`] pass a
+//! [`MemoryRankingStore`] instead of needing a live Redis; the model functions using it need a database as well, so
+//! they aren't unit tested.
+//!
+//! Reading the rankings and everything else in Redis still goes through the connection directly, including player
+//! merges, rivalries, song lookups and metadata changes in [`crate::models`].
+//!
+//! [`Song::invalidate_lookups`]: crate::models::songs::Song::invalidate_lookups
+
+use async_trait::async_trait;
+use redis::{AsyncCommands, RedisResult};
+
+use crate::util::{
+    game_types::League,
+    rank_cache::{INDEX_TTL, VERSION_TTL},
+    redis_keys, redis_ops,
+};
+
+/// Stores a rank index (`KEYS[1]`, the entries from `ARGV[3]` on as score and member) unless its version
+/// (`KEYS[2]`) isn't `ARGV[1]` anymore, see [`RankingStore::store_rank_index`].
+const STORE_RANK_INDEX_SCRIPT: &str = r"
+if (redis.call('GET', KEYS[2]) or '') ~= ARGV[1] then
+    return 0
+end
+redis.call('DEL', KEYS[1])
+for i = 3, #ARGV, 2000 do
+    redis.call('ZADD', KEYS[1], unpack(ARGV, i, math.min(i + 1999, #ARGV)))
+end
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+return 1
+";
+
+/// Where the changes of the rankings and the caches depending on scores and songs go, see the module documentation.
+#[async_trait]
+pub trait RankingStore: Send {
+    /// Changes a player's points in several rankings (keys of sorted sets) at once.
+    /// Rankings whose delta is 0 are left alone.
+    async fn add_points(&mut self, player: i32, deltas: Vec<(String, i64)>) -> RedisResult<()>;
+
+    /// Sets a player's points in several rankings at once.
+    async fn set_points(&mut self, player: i32, points: Vec<(String, i64)>) -> RedisResult<()>;
+
+    /// Drops the rank index of a song's leaderboard in a league and bumps its version.
+    async fn invalidate_rank_index(&mut self, song_id: i32, league: League) -> RedisResult<()>;
+
+    /// Looks up how many scores in a rank index are better than `points`, and how many there are.
+    /// `None` if there's no index.
+    async fn rank_index_standing(
+        &mut self,
+        song_id: i32,
+        league: League,
+        points: i32,
+    ) -> RedisResult<Option<(i64, i64)>>;
+
+    /// The version of a rank index, `None` if it wasn't dropped in a while.
+    async fn rank_index_version(
+        &mut self,
+        song_id: i32,
+        league: League,
+    ) -> RedisResult<Option<i64>>;
+
+    /// Stores a rank index built from `(score ID, points)` entries, unless its version isn't `version` anymore.
+    ///
+    /// # Returns
+    /// Whether it was stored.
+    async fn store_rank_index(
+        &mut self,
+        song_id: i32,
+        league: League,
+        entries: &[(i32, i32)],
+        version: Option<i64>,
+    ) -> RedisResult<bool>;
+
+    /// Drops every cached lookup that resolved to the song.
+    async fn forget_song_lookups(&mut self, song_id: i32) -> RedisResult<()>;
+}
+
+#[async_trait]
+impl RankingStore for deadpool_redis::Connection {
+    async fn add_points(&mut self, player: i32, deltas: Vec<(String, i64)>) -> RedisResult<()> {
+        redis_ops::zincr_many(player, deltas, self).await
+    }
+
+    async fn set_points(&mut self, player: i32, points: Vec<(String, i64)>) -> RedisResult<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (key, points) in points {
+            pipe.zadd(key, player, points).ignore();
+        }
+        pipe.query_async::<()>(self).await
+    }
+
+    async fn invalidate_rank_index(&mut self, song_id: i32, league: League) -> RedisResult<()> {
+        let version_key = redis_keys::rank_index_version(song_id, league);
+        redis::pipe()
+            .atomic()
+            .incr(&version_key, 1)
+            .ignore()
+            .expire(&version_key, VERSION_TTL)
+            .ignore()
+            .del(redis_keys::rank_index(song_id, league))
+            .ignore()
+            .query_async::<()>(self)
+            .await
+    }
+
+    async fn rank_index_standing(
+        &mut self,
+        song_id: i32,
+        league: League,
+        points: i32,
+    ) -> RedisResult<Option<(i64, i64)>> {
+        let key = redis_keys::rank_index(song_id, league);
+        let (better, total): (i64, i64) = redis::pipe()
+            .zcount(&key, format!("({points}"), "+inf")
+            .zcard(&key)
+            .query_async(self)
+            .await?;
+        // Leaderboards without scores aren't stored
+        Ok((total > 0).then_some((better, total)))
+    }
+
+    async fn rank_index_version(
+        &mut self,
+        song_id: i32,
+        league: League,
+    ) -> RedisResult<Option<i64>> {
+        self.get(redis_keys::rank_index_version(song_id, league))
+            .await
+    }
+
+    async fn store_rank_index(
+        &mut self,
+        song_id: i32,
+        league: League,
+        entries: &[(i32, i32)],
+        version: Option<i64>,
+    ) -> RedisResult<bool> {
+        let script = redis::Script::new(STORE_RANK_INDEX_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(redis_keys::rank_index(song_id, league))
+            .key(redis_keys::rank_index_version(song_id, league))
+            .arg(version.map_or_else(String::new, |version| version.to_string()))
+            .arg(INDEX_TTL);
+        for (score_id, points) in entries {
+            invocation.arg(points).arg(score_id);
+        }
+        invocation.invoke_async(self).await
+    }
+
+    async fn forget_song_lookups(&mut self, song_id: i32) -> RedisResult<()> {
+        let song_lookups_key = redis_keys::song_lookups(song_id);
+        let lookup_keys: Vec<String> = self.smembers(&song_lookups_key).await?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for lookup_key in &lookup_keys {
+            pipe.del(lookup_key).ignore();
+        }
+        pipe.del(&song_lookups_key).ignore();
+        pipe.query_async::<()>(self).await
+    }
+}
+
+/// A change held back by [`DeferredRankingStore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DeferredChange {
+    AddPoints(i32, Vec<(String, i64)>),
+    SetPoints(i32, Vec<(String, i64)>),
+    InvalidateRankIndex(i32, League),
+    ForgetSongLookups(i32),
+}
+
+/// Holds the changes back until [`DeferredRankingStore::apply`], for changing scores in a database transaction:
+/// the changes are applied once it's committed, so a rollback doesn't leave Redis ahead of the database.
+///
+/// Rank indexes look missing to it, so nothing is read from or stored in them in the meantime.
+#[derive(Debug, Default)]
+pub struct DeferredRankingStore {
+    changes: Vec<DeferredChange>,
+}
+
+impl DeferredRankingStore {
+    /// Applies the changes to the store, in the order they were made.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the store, the changes after the failing one aren't applied.
+    pub async fn apply(self, store: &mut impl RankingStore) -> RedisResult<()> {
+        for change in self.changes {
+            match change {
+                DeferredChange::AddPoints(player, deltas) => {
+                    store.add_points(player, deltas).await?
+                }
+                DeferredChange::SetPoints(player, points) => {
+                    store.set_points(player, points).await?
+                }
+                DeferredChange::InvalidateRankIndex(song_id, league) => {
+                    store.invalidate_rank_index(song_id, league).await?;
+                }
+                DeferredChange::ForgetSongLookups(song_id) => {
+                    store.forget_song_lookups(song_id).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RankingStore for DeferredRankingStore {
+    async fn add_points(&mut self, player: i32, deltas: Vec<(String, i64)>) -> RedisResult<()> {
+        self.changes.push(DeferredChange::AddPoints(player, deltas));
+        Ok(())
+    }
+
+    async fn set_points(&mut self, player: i32, points: Vec<(String, i64)>) -> RedisResult<()> {
+        self.changes.push(DeferredChange::SetPoints(player, points));
+        Ok(())
+    }
+
+    async fn invalidate_rank_index(&mut self, song_id: i32, league: League) -> RedisResult<()> {
+        self.changes
+            .push(DeferredChange::InvalidateRankIndex(song_id, league));
+        Ok(())
+    }
+
+    async fn rank_index_standing(
+        &mut self,
+        _song_id: i32,
+        _league: League,
+        _points: i32,
+    ) -> RedisResult<Option<(i64, i64)>> {
+        Ok(None)
+    }
+
+    async fn rank_index_version(
+        &mut self,
+        _song_id: i32,
+        _league: League,
+    ) -> RedisResult<Option<i64>> {
+        Ok(None)
+    }
+
+    async fn store_rank_index(
+        &mut self,
+        _song_id: i32,
+        _league: League,
+        _entries: &[(i32, i32)],
+        _version: Option<i64>,
+    ) -> RedisResult<bool> {
+        Ok(false)
+    }
+
+    async fn forget_song_lookups(&mut self, song_id: i32) -> RedisResult<()> {
+        self.changes
+            .push(DeferredChange::ForgetSongLookups(song_id));
+        Ok(())
+    }
+}
+
+/// Keeps the rankings in memory and remembers what was dropped, for unit tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemoryRankingStore {
+    /// Points per ranking and player
+    pub points: std::collections::HashMap<String, std::collections::HashMap<i32, i64>>,
+    /// Leaderboards whose rank index was dropped, in order
+    pub invalidated_rank_indexes: Vec<(i32, League)>,
+    /// Points of the scores in the rank index of every leaderboard that has one
+    pub rank_indexes: std::collections::HashMap<(i32, League), Vec<i32>>,
+    /// How often the rank index of every leaderboard was dropped
+    pub rank_index_versions: std::collections::HashMap<(i32, League), i64>,
+    /// Songs whose lookups were dropped, in order
+    pub forgotten_lookups: Vec<i32>,
+}
+
+#[cfg(test)]
+impl MemoryRankingStore {
+    /// The player's points in the ranking, `None` if they aren't in it.
+    #[must_use]
+    pub fn points_of(&self, key: &str, player: i32) -> Option<i64> {
+        self.points.get(key)?.get(&player).copied()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl RankingStore for MemoryRankingStore {
+    async fn add_points(&mut self, player: i32, deltas: Vec<(String, i64)>) -> RedisResult<()> {
+        for (key, delta) in deltas.into_iter().filter(|(_, delta)| *delta != 0) {
+            *self
+                .points
+                .entry(key)
+                .or_default()
+                .entry(player)
+                .or_default() += delta;
+        }
+        Ok(())
+    }
+
+    async fn set_points(&mut self, player: i32, points: Vec<(String, i64)>) -> RedisResult<()> {
+        for (key, points) in points {
+            self.points.entry(key).or_default().insert(player, points);
+        }
+        Ok(())
+    }
+
+    async fn invalidate_rank_index(&mut self, song_id: i32, league: League) -> RedisResult<()> {
+        self.invalidated_rank_indexes.push((song_id, league));
+        self.rank_indexes.remove(&(song_id, league));
+        *self
+            .rank_index_versions
+            .entry((song_id, league))
+            .or_default() += 1;
+        Ok(())
+    }
+
+    async fn rank_index_standing(
+        &mut self,
+        song_id: i32,
+        league: League,
+        points: i32,
+    ) -> RedisResult<Option<(i64, i64)>> {
+        Ok(self
+            .rank_indexes
+            .get(&(song_id, league))
+            .map(|index| crate::util::rank_cache::standing_among(index.iter().copied(), points)))
+    }
+
+    async fn rank_index_version(
+        &mut self,
+        song_id: i32,
+        league: League,
+    ) -> RedisResult<Option<i64>> {
+        Ok(self.rank_index_versions.get(&(song_id, league)).copied())
+    }
+
+    async fn store_rank_index(
+        &mut self,
+        song_id: i32,
+        league: League,
+        entries: &[(i32, i32)],
+        version: Option<i64>,
+    ) -> RedisResult<bool> {
+        if self.rank_index_versions.get(&(song_id, league)).copied() != version {
+            return Ok(false);
+        }
+        self.rank_indexes.insert(
+            (song_id, league),
+            entries.iter().map(|(_, points)| *points).collect(),
+        );
+        Ok(true)
+    }
+
+    async fn forget_song_lookups(&mut self, song_id: i32) -> RedisResult<()> {
+        self.forgotten_lookups.push(song_id);
+        Ok(())
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_deferred_store() {
+        let mut deferred = DeferredRankingStore::default();
+        deferred
+            .add_points(1, vec![("a".to_owned(), 100), ("b".to_owned(), 0)])
+            .await
+            .unwrap();
+        deferred
+            .set_points(2, vec![("a".to_owned(), 50)])
+            .await
+            .unwrap();
+        deferred
+            .add_points(1, vec![("a".to_owned(), -30)])
+            .await
+            .unwrap();
+        deferred
+            .invalidate_rank_index(3, League::Pro)
+            .await
+            .unwrap();
+        deferred.forget_song_lookups(3).await.unwrap();
+
+        // Rank indexes aren't built from uncommitted scores
+        assert_eq!(
+            deferred.rank_index_version(3, League::Pro).await.unwrap(),
+            None
+        );
+        assert!(!deferred
+            .store_rank_index(3, League::Pro, &[(1, 100)], None)
+            .await
+            .unwrap());
+
+        let mut store = MemoryRankingStore::default();
+
+        deferred.apply(&mut store).await.unwrap();
+        assert_eq!(store.points_of("a", 1), Some(70));
+        assert_eq!(store.points_of("b", 1), None);
+        assert_eq!(store.points_of("a", 2), Some(50));
+        assert_eq!(store.invalidated_rank_indexes, vec![(3, League::Pro)]);
+        assert_eq!(store.forgotten_lookups, vec![3]);
+    }
+}
@@ -0,0 +1,395 @@
+//! The global rankings, in every mode they can be viewed in.
+//!
+//! - `skillPoints`: the total skill points of all scores, the combined view of the league pools below.
+//!   These are the rankings the game shows.
+//! - `casual`, `pro` and `elite`: the league pools, only scores in that league count
+//! - `bestLeague`: only the best score of each song counts, whichever league it's in
+//! - `weighted`: all scores count, weighted by their league with the `league_weights` of the scoring policy
+//!
+//! Every mode is its own sorted set per realm in Redis, see [`RankingMode::key`]. They're kept up to date
+//! on every submission, deletion and restore with [`record_change`] (through a [`RankingStore`]), and rebuilt by
+//! [`crate::util::scoring::recalculate_rankings`]. Moving scores between songs changes which score is the best
+//! of a song, so merges recompute the affected players with [`refresh_player`].
+//!
+//! The Casual scores of players flagged for sandbagging can be kept out of every mode, see
+//! [`crate::util::sandbagging`].
+//!
+//! Every change to the skill points is recorded in the ledger, see [`LedgerEntry`]. Moderators can adjust them by
+//! hand there, which counts towards `skillPoints` only, on top of the scores.
+
+use std::collections::HashMap;
+
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::{
+        sandbagging_flags::SandbaggingFlag,
+        scores::Score,
+        skill_point_ledger::{LedgerEntry, LedgerReason},
+    },
+    schema::scores,
+    util::{
+        errors::WavebreakerError,
+        game_types::League,
+        ranking_store::RankingStore,
+        redis_keys,
+        scoring::{self, ScoringPolicy},
+    },
+};
+
+/// Which scores count towards the rankings, and how much.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum RankingMode {
+    #[default]
+    SkillPoints,
+    Elite,
+    BestLeague,
+    Weighted,
+    Casual,
+    Pro,
+}
+
+impl RankingMode {
+    pub const ALL: [Self; 6] = [
+        Self::SkillPoints,
+        Self::Elite,
+        Self::BestLeague,
+        Self::Weighted,
+        Self::Casual,
+        Self::Pro,
+    ];
+    /// The pools of every league, in the order of [`League`]. Their points add up to [`RankingMode::SkillPoints`].
+    pub const LEAGUE_POOLS: [Self; 3] = [Self::Casual, Self::Pro, Self::Elite];
+
+    /// How the mode is named in the API and in Redis keys.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::SkillPoints => "skillPoints",
+            Self::Elite => "elite",
+            Self::BestLeague => "bestLeague",
+            Self::Weighted => "weighted",
+            Self::Casual => "casual",
+            Self::Pro => "pro",
+        }
+    }
+
+    /// The pool only scores in the league count towards.
+    #[must_use]
+    pub const fn pool_of(league: League) -> Self {
+        Self::LEAGUE_POOLS[league as usize]
+    }
+
+    /// The sorted set holding the rankings of the realm in this mode.
+    /// The skill point rankings keep their original key, everything else relies on it.
+    #[must_use]
+    pub fn key(self, realm: &str) -> String {
+        match self {
+            Self::SkillPoints => redis_keys::skill_points(realm),
+            other => redis_keys::ranking(other.as_str(), realm),
+        }
+    }
+}
+
+/// Adds up the points of one player's scores in one realm, for every mode.
+/// The scores are given as `(song ID, league, skill points)`.
+///
+/// # Returns
+/// The points, in the order of [`RankingMode::ALL`].
+#[must_use]
+pub fn totals(
+    policy: &ScoringPolicy,
+    player_scores: impl IntoIterator<Item = (i32, League, i32)>,
+) -> [i32; RankingMode::ALL.len()] {
+    let mut totals = [0; RankingMode::ALL.len()];
+    let mut best_per_song: HashMap<i32, i32> = HashMap::new();
+    for (song, league, skill_points) in player_scores {
+        totals[RankingMode::SkillPoints as usize] += skill_points;
+        totals[RankingMode::pool_of(league) as usize] += skill_points;
+        totals[RankingMode::Weighted as usize] += policy.weighted_points(league, skill_points);
+        let best = best_per_song.entry(song).or_default();
+        *best = (*best).max(skill_points);
+    }
+    totals[RankingMode::BestLeague as usize] = best_per_song.into_values().sum();
+
+    totals
+}
+
+/// How one score of a player changed, along with their best score on the song in the other leagues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScoreChange {
+    pub league: League,
+    /// Skill points of the score before the change, 0 if it didn't count
+    pub before: i32,
+    /// Skill points of the score after the change, 0 if it doesn't count anymore
+    pub after: i32,
+    /// Skill points of the player's best score on the song in the other leagues, 0 if there are none
+    pub best_of_others: i32,
+}
+
+impl ScoreChange {
+    /// How much the points of the player change in every mode, in the order of [`RankingMode::ALL`].
+    #[must_use]
+    pub fn deltas(&self, policy: &ScoringPolicy) -> [i32; RankingMode::ALL.len()] {
+        let delta = self.after - self.before;
+        let pool = |mode: RankingMode| {
+            if RankingMode::pool_of(self.league) == mode {
+                delta
+            } else {
+                0
+            }
+        };
+        [
+            delta,
+            pool(RankingMode::Elite),
+            self.best_of_others.max(self.after) - self.best_of_others.max(self.before),
+            policy.weighted_points(self.league, self.after)
+                - policy.weighted_points(self.league, self.before),
+            pool(RankingMode::Casual),
+            pool(RankingMode::Pro),
+        ]
+    }
+}
+
+/// Updates the rankings of every mode after a score was submitted, deleted or restored, and records it in the
+/// ledger. `before` and `after` are the skill points of the score, 0 when it didn't or doesn't count.
+///
+/// # Errors
+/// Fails if something is wrong with the DB or Redis.
+pub async fn record_change(
+    score: &Score,
+    reason: LedgerReason,
+    before: i32,
+    after: i32,
+    conn: &mut AsyncPgConnection,
+    store: &mut impl RankingStore,
+) -> Result<(), WavebreakerError> {
+    let casual_excluded = SandbaggingFlag::is_excluded(score.player_id, &score.realm, conn).await?;
+    if casual_excluded && score.league == League::Casual {
+        return Ok(());
+    }
+
+    let others: Vec<(League, i32, i32)> = Score::all()
+        .filter(scores::player_id.eq(score.player_id))
+        .filter(scores::song_id.eq(score.song_id))
+        .filter(scores::league.ne(score.league))
+        .select((scores::league, scores::score, scores::gold_threshold))
+        .load(conn)
+        .await?;
+    let best_of_others = others
+        .into_iter()
+        .filter(|(other_league, _, _)| !(casual_excluded && *other_league == League::Casual))
+        .map(|(other_league, points, gold)| Score::skill_points_for(other_league, points, gold))
+        .max()
+        .unwrap_or_default();
+
+    let change = ScoreChange {
+        league: score.league,
+        before,
+        after,
+        best_of_others,
+    };
+    let deltas = change.deltas(scoring::policy());
+    store
+        .add_points(score.player_id, keyed_deltas(&score.realm, deltas))
+        .await?;
+    LedgerEntry::record_score(
+        score,
+        reason,
+        deltas[RankingMode::SkillPoints as usize],
+        conn,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Computes the points of a player in one realm again from their scores, in every mode.
+/// Adjustments by moderators are added to their skill points, and the ledger gets an entry so it adds up to them.
+///
+/// # Errors
+/// Fails if something is wrong with the DB or Redis.
+pub async fn refresh_player(
+    player: i32,
+    realm: &str,
+    conn: &mut AsyncPgConnection,
+    store: &mut impl RankingStore,
+) -> Result<(), WavebreakerError> {
+    let casual_excluded = SandbaggingFlag::is_excluded(player, realm, conn).await?;
+    let mut player_scores: Vec<Score> = Score::all()
+        .filter(scores::player_id.eq(player))
+        .filter(scores::realm.eq(realm))
+        .load(conn)
+        .await?;
+    // Archived scores keep counting, see `crate::models::scores_archive`
+    player_scores.extend(Score::archived_of(player, realm, conn).await?);
+
+    let totals = totals(
+        scoring::policy(),
+        player_scores
+            .iter()
+            .filter(|score| !(casual_excluded && score.league == League::Casual))
+            .map(|score| (score.song_id, score.league, score.get_skill_points())),
+    );
+    let adjustments = LedgerEntry::adjustments_of(player, realm, conn).await?;
+    let recorded = LedgerEntry::total_of(player, realm, conn).await?;
+
+    store
+        .set_points(player, keyed_totals(realm, totals, adjustments))
+        .await?;
+
+    let skill_points = i64::from(totals[RankingMode::SkillPoints as usize]) + adjustments;
+    let correction = i32::try_from(skill_points - recorded)
+        .map_err(|_| WavebreakerError::LedgerOverflow(player))?;
+    LedgerEntry::record_corrections(realm, LedgerReason::Refresh, &[(player, correction)], conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Pairs the changes of a player's points with the keys of their rankings in the realm.
+fn keyed_deltas(realm: &str, deltas: [i32; RankingMode::ALL.len()]) -> Vec<(String, i64)> {
+    RankingMode::ALL
+        .into_iter()
+        .zip(deltas)
+        .map(|(mode, delta)| (mode.key(realm), i64::from(delta)))
+        .collect()
+}
+
+/// Pairs a player's points with the keys of their rankings in the realm, with the adjustments added to their
+/// skill points.
+fn keyed_totals(
+    realm: &str,
+    totals: [i32; RankingMode::ALL.len()],
+    adjustments: i64,
+) -> Vec<(String, i64)> {
+    RankingMode::ALL
+        .into_iter()
+        .zip(totals)
+        .map(|(mode, points)| {
+            let adjustment = if mode == RankingMode::SkillPoints {
+                adjustments
+            } else {
+                0
+            };
+            (mode.key(realm), i64::from(points) + adjustment)
+        })
+        .collect()
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::ranking_store::MemoryRankingStore;
+
+    #[test]
+    fn test_totals() {
+        let policy = ScoringPolicy::default();
+        let totals = totals(
+            &policy,
+            [
+                (1, League::Casual, 100),
+                (1, League::Elite, 300),
+                (2, League::Pro, 200),
+            ],
+        );
+        assert_eq!(totals, [600, 300, 500, 50 + 450 + 200, 100, 200]);
+        // The league pools add up to the combined skill points
+        let pooled: i32 = RankingMode::LEAGUE_POOLS
+            .iter()
+            .map(|pool| totals[*pool as usize])
+            .sum();
+        assert_eq!(pooled, totals[RankingMode::SkillPoints as usize]);
+    }
+
+    #[test]
+    fn test_deltas() {
+        let policy = ScoringPolicy::default();
+
+        // A new Casual score that's worse than the Elite score on the same song
+        let below_best = ScoreChange {
+            league: League::Casual,
+            before: 0,
+            after: 100,
+            best_of_others: 300,
+        };
+        assert_eq!(below_best.deltas(&policy), [100, 0, 0, 50, 100, 0]);
+
+        // An improved Elite score that's now the best of the song
+        let new_best = ScoreChange {
+            league: League::Elite,
+            before: 150,
+            after: 400,
+            best_of_others: 200,
+        };
+        assert_eq!(new_best.deltas(&policy), [250, 250, 200, 375, 0, 0]);
+
+        // Deleting the best score falls back to the best of the other leagues
+        let deleted = ScoreChange {
+            league: League::Pro,
+            before: 200,
+            after: 0,
+            best_of_others: 100,
+        };
+        assert_eq!(deleted.deltas(&policy), [-200, 0, -100, -200, 0, -200]);
+    }
+
+    #[tokio::test]
+    async fn test_keyed_deltas_in_store() {
+        let policy = ScoringPolicy::default();
+        let mut store = MemoryRankingStore::default();
+        let submitted = ScoreChange {
+            league: League::Pro,
+            before: 0,
+            after: 200,
+            best_of_others: 0,
+        };
+        store
+            .add_points(1, keyed_deltas("main", submitted.deltas(&policy)))
+            .await
+            .unwrap();
+        assert_eq!(store.points_of(&RankingMode::Pro.key("main"), 1), Some(200));
+        // Rankings the change doesn't touch aren't written
+        assert_eq!(store.points_of(&RankingMode::Elite.key("main"), 1), None);
+
+        let deleted = ScoreChange {
+            before: 200,
+            after: 0,
+            ..submitted
+        };
+        store
+            .add_points(1, keyed_deltas("main", deleted.deltas(&policy)))
+            .await
+            .unwrap();
+        for mode in [
+            RankingMode::SkillPoints,
+            RankingMode::Pro,
+            RankingMode::BestLeague,
+        ] {
+            assert_eq!(store.points_of(&mode.key("main"), 1), Some(0));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keyed_totals_in_store() {
+        let mut store = MemoryRankingStore::default();
+        store
+            .set_points(2, keyed_totals("main", [600, 300, 500, 700, 100, 200], -50))
+            .await
+            .unwrap();
+        // Only the skill points get the adjustments
+        assert_eq!(
+            store.points_of(&RankingMode::SkillPoints.key("main"), 2),
+            Some(550)
+        );
+        assert_eq!(
+            store.points_of(&RankingMode::Elite.key("main"), 2),
+            Some(300)
+        );
+        assert_eq!(store.points_of(&RankingMode::Pro.key("main"), 2), Some(200));
+    }
+}
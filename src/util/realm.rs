@@ -0,0 +1,95 @@
+//! Realms are isolated instances of Wavebreaker sharing one deployment and database.
+//!
+//! They're useful for things like a testing instance or one for modded clients.
+//! Every realm has its own songs, scores and rankings. Players are shared between all of them.
+//!
+//! The main realm is served at the usual paths, other realms under `/realms/{name}/`.
+
+use std::sync::Arc;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// The realm everything belongs to when no realm prefix is used.
+pub const MAIN_REALM: &str = "main";
+/// Realm names end up in URLs and Redis keys, so they're kept short.
+const MAX_REALM_NAME_LENGTH: usize = 32;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum RealmNameError {
+    #[error("Realm name must be between 1 and {MAX_REALM_NAME_LENGTH} characters long")]
+    Length,
+    #[error("Realm name can only contain lowercase letters, digits, '-' and '_'")]
+    InvalidCharacter,
+    #[error("The main realm always exists, it doesn't have to be configured")]
+    Main,
+}
+
+/// The realm a request is for. Added to the game routes as an extension.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Realm(Arc<str>);
+
+impl Realm {
+    #[must_use]
+    pub fn main() -> Self {
+        Self(MAIN_REALM.into())
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Realm {
+    type Error = RealmNameError;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        if name.is_empty() || name.len() > MAX_REALM_NAME_LENGTH {
+            return Err(RealmNameError::Length);
+        }
+        if !name
+            .bytes()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'-' || c == b'_')
+        {
+            return Err(RealmNameError::InvalidCharacter);
+        }
+        if name == MAIN_REALM {
+            return Err(RealmNameError::Main);
+        }
+
+        Ok(Self(name.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_realm_names() {
+        assert_eq!(
+            Realm::try_from("testing".to_owned()).map(|r| r.name().to_owned()),
+            Ok("testing".to_owned())
+        );
+        assert_eq!(
+            Realm::try_from("modded-2_x".to_owned()).map(|r| r.name().to_owned()),
+            Ok("modded-2_x".to_owned())
+        );
+        assert_eq!(Realm::try_from(String::new()), Err(RealmNameError::Length));
+        assert_eq!(Realm::try_from("a".repeat(33)), Err(RealmNameError::Length));
+        assert_eq!(
+            Realm::try_from("Testing".to_owned()),
+            Err(RealmNameError::InvalidCharacter)
+        );
+        assert_eq!(
+            Realm::try_from("../main".to_owned()),
+            Err(RealmNameError::InvalidCharacter)
+        );
+        assert_eq!(
+            Realm::try_from(MAIN_REALM.to_owned()),
+            Err(RealmNameError::Main)
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! Every key Wavebreaker stores in Redis, in one place.
+//!
+//! Keys are prefixed with `wavebreaker:v{SCHEMA_VERSION}:`, so it's obvious which layout they belong to.
+//! The version of the layout the data is currently in lives in [`SCHEMA_VERSION_KEY`].
+//!
+//! Current layout (version 2):
+//! - `wavebreaker:v2:skill_points` - Sorted set, member is the player ID, score is their total skill points
+//!   in the main realm.
+//! - `wavebreaker:v2:realm:{realm}:skill_points` - Same as above, for the other realms.
+//! - `wavebreaker:v2:rankings:{mode}` - Sorted set, member is the player ID, score is their points in an alternative
+//!   ranking mode in the main realm, see `util::rankings`.
+//! - `wavebreaker:v2:realm:{realm}:rankings:{mode}` - Same as above, for the other realms.
+//! - `wavebreaker:v2:scoring_version` - Integer, the version of the skill point formula the rankings were computed with,
+//!   see `util::scoring`. Missing means version 1.
+//! - `wavebreaker:v2:ride_submission:{hash}` - String, `pending` or the JSON response of a score submission.
+//!   Expires after a few minutes, used to recognize the game retrying a submission.
+//! - `wavebreaker:v2:song_lookup:{hash}` - String, the ID of the song a title/artist lookup resolved to.
+//! - `wavebreaker:v2:song_lookups:{song_id}` - Set of the `song_lookup` keys resolving to that song,
+//!   so they can be dropped when the song changes.
+//! - `wavebreaker:v2:recent_rides` - Sorted set, member is the player ID, score is the Unix timestamp of their last ride.
+//!   Only contains players sharing their activity.
+//! - `wavebreaker:v2:recent_ride:{player_id}` - String, JSON of the player's last ride. Expires after a while.
+//! - `wavebreaker:v2:realm:{realm}:plays:{player_id}:{date}` - Integer, how many scores the player submitted
+//!   in the realm on that day (in the server's time zone), for the records. Expires after two days.
+//! - `wavebreaker:v2:rivalry_change:{challenger_id}:{rival_id}` - String, set when the rivalry was added or removed.
+//!   Expires when it may be changed again.
+//! - `wavebreaker:v2:shout_rate:{player_id}` - Integer, how many shouts the player posted this minute. Expires after a minute.
+//! - `wavebreaker:v2:pending_songs:{player_id}` - Integer, how many lookups of the player were queued as pending songs
+//!   this hour, see `util::song_creation`. Expires after an hour.
+//! - `wavebreaker:v2:notification_codes:{player_id}` - Integer, how many codes to confirm a notification link were
+//!   sent to the player this hour. Expires after an hour.
+//! - `wavebreaker:v2:score_removals:{player_id}` - Integer, how often the player hid, unhid or deleted their own
+//!   scores since the first time today. Expires a day after that.
+//! - `wavebreaker:v2:api_quota:{key_id}:day:{date}` - Integer, how many requests were made with the API key
+//!   on that day (in the server's time zone). Expires at the end of the day.
+//! - `wavebreaker:v2:api_quota:{key_id}:burst` - Integer, how many requests were made with the API key this minute.
+//!   Expires after a minute.
+//! - `wavebreaker:v2:api_quota:anonymous:{client}` - Integer, how many requests without an API key were made from the
+//!   IP address (or IPv6 /64) this minute. Expires after a minute.
+//! - `wavebreaker:v2:localized_news` - Hash, field is the locale, value the news items rendered in it.
+//!   Expires after a while, deleted when they change. Replaced `wavebreaker:v2:news`, which expires on its own.
+//! - `wavebreaker:v2:player:{steam_id}` - String, JSON of the player with that Steam ID. Expires after a while.
+//! - `wavebreaker:v2:latency:{window_start}` - Hash, field is `{bucket}:{route}`, value how many requests of every
+//!   instance fell into that bucket of the latency histogram during the window, see `util::metrics`.
+//!   Expires after a few windows.
+//! - `wavebreaker:v2:latency_alerts:{window_start}` - String, the instance that checked the window for alerts.
+//!   Expires after a few windows.
+//! - `wavebreaker:v2:metadata_backfill` - Hash with the checkpoint of `wavebreaker backfill-metadata`: the last song
+//!   looked up (`last_song_id`) and how many were `matched`, `unmatched` and `skipped`, see `util::metadata_backfill`.
+//!   Deleted once the backfill is done.
+//! - `wavebreaker:v2:rank_index:{song_id}:{league}` - Sorted set, member is the score ID, score is the score, of
+//!   every score on a song's leaderboard in a league (the league's number), see `util::rank_cache`. Expires after a
+//!   while, deleted when a score on the leaderboard changes.
+//! - `wavebreaker:v2:rank_index_version:{song_id}:{league}` - Integer, bumped whenever the rank index is deleted, so
+//!   an index built from scores read before that isn't stored. Expires after a while.
+//! - `wavebreaker:v2:preview:{track_id}` - String, the signed link to the preview of a Deezer track, empty if it
+//!   doesn't have one, see `util::previews`. Expires before the link stops working.
+//! - `wavebreaker:v2:leaderboard_updates` - Pub/sub channel, JSON of every score that changed a song's leaderboard,
+//!   see `util::live_leaderboards`. Nothing is stored under it.
+//!
+//! Older layouts:
+//! - Version 1 (unversioned, before this module existed): the skill points were in the `leaderboard` sorted set.
+//!
+//! When changing the layout, bump [`SCHEMA_VERSION`], add a step to [`migrate`]
+//! and document the new layout above. Adding new keys doesn't need a new version.
+
+use anyhow::bail;
+use redis::AsyncCommands;
+use steam_rs::steam_id::SteamId;
+use time::Date;
+use tracing::info;
+
+use crate::util::{game_types::League, realm::MAIN_REALM};
+
+/// The Redis layout version this build of Wavebreaker expects.
+pub const SCHEMA_VERSION: i32 = 2;
+/// Key holding the version of the layout the data in Redis is in.
+/// Deliberately not versioned itself, since it's needed to find out what the version is.
+pub const SCHEMA_VERSION_KEY: &str = "wavebreaker:schema_version";
+
+/// Sorted set of every player's total skill points in the main realm, used for rankings.
+pub const SKILL_POINTS: &str = "wavebreaker:v2:skill_points";
+
+/// Version of the skill point formula the rankings were computed with, see `util::scoring`.
+pub const SCORING_VERSION: &str = "wavebreaker:v2:scoring_version";
+
+/// Sorted set of every player's total skill points in a realm.
+/// The main realm keeps using [`SKILL_POINTS`], so its rankings didn't have to be migrated when realms were added.
+#[must_use]
+pub fn skill_points(realm: &str) -> String {
+    if realm == MAIN_REALM {
+        SKILL_POINTS.to_owned()
+    } else {
+        format!("wavebreaker:v2:realm:{realm}:skill_points")
+    }
+}
+
+/// Sorted set of every player's points in an alternative ranking mode in a realm, see `util::rankings`.
+#[must_use]
+pub fn ranking(mode: &str, realm: &str) -> String {
+    if realm == MAIN_REALM {
+        format!("wavebreaker:v2:rankings:{mode}")
+    } else {
+        format!("wavebreaker:v2:realm:{realm}:rankings:{mode}")
+    }
+}
+
+/// Marker for a score submission, see `send_ride`.
+/// `hash` identifies the submission, so retries of it end up with the same key.
+#[must_use]
+pub fn ride_submission(hash: &str) -> String {
+    format!("wavebreaker:v2:ride_submission:{hash}")
+}
+
+/// Latency histograms of every instance, added up per window, see `util::metrics`.
+#[must_use]
+pub fn latency_window(window_start: u64) -> String {
+    format!("wavebreaker:v2:latency:{window_start}")
+}
+
+/// Claimed by the instance checking a latency window for alerts, see `util::metrics`.
+#[must_use]
+pub fn latency_alerts(window_start: u64) -> String {
+    format!("wavebreaker:v2:latency_alerts:{window_start}")
+}
+
+/// Cached player, see `Player::find_by_steam_id_cached`.
+#[must_use]
+pub fn player(steam_id: SteamId) -> String {
+    format!("wavebreaker:v2:player:{steam_id}")
+}
+
+/// Cached result of a song lookup, see `NewSong::find_or_create_cached`.
+/// `hash` identifies the normalized title, artist and modifiers.
+#[must_use]
+pub fn song_lookup(hash: &str) -> String {
+    format!("wavebreaker:v2:song_lookup:{hash}")
+}
+
+/// Set of all cached lookups that resolved to a song, see `Song::invalidate_lookups`.
+#[must_use]
+pub fn song_lookups(song_id: i32) -> String {
+    format!("wavebreaker:v2:song_lookups:{song_id}")
+}
+
+/// Channel scores that changed a song's leaderboard are published on, see `util::live_leaderboards`.
+pub const LEADERBOARD_UPDATES: &str = "wavebreaker:v2:leaderboard_updates";
+
+/// Players who recently rode a song, see `util::activity`.
+pub const RECENT_RIDES: &str = "wavebreaker:v2:recent_rides";
+
+/// The last ride of a player, see `util::activity`.
+#[must_use]
+pub fn recent_ride(player_id: i32) -> String {
+    format!("wavebreaker:v2:recent_ride:{player_id}")
+}
+
+/// Counter of the scores a player submitted in a realm on a day, see `records`.
+#[must_use]
+pub fn plays_per_day(realm: &str, player_id: i32, date: Date) -> String {
+    format!("wavebreaker:v2:realm:{realm}:plays:{player_id}:{date}")
+}
+
+/// Set while a rivalry can't be changed again, see `RivalryLimits` in `models::rivalries`.
+#[must_use]
+pub fn rivalry_change(challenger_id: i32, rival_id: i32) -> String {
+    format!("wavebreaker:v2:rivalry_change:{challenger_id}:{rival_id}")
+}
+
+/// Counter of the player's lookups queued as pending songs this hour, see `util::song_creation`.
+#[must_use]
+pub fn pending_songs(player_id: i32) -> String {
+    format!("wavebreaker:v2:pending_songs:{player_id}")
+}
+
+/// Counter of the shouts a player posted recently, see `util::text_filter`.
+#[must_use]
+pub fn shout_rate(player_id: i32) -> String {
+    format!("wavebreaker:v2:shout_rate:{player_id}")
+}
+
+/// Counter of the codes sent to a player to confirm notification links, see `util::notify`.
+#[must_use]
+pub fn notification_codes(player_id: i32) -> String {
+    format!("wavebreaker:v2:notification_codes:{player_id}")
+}
+
+/// Counter of the times a player hid, unhid or deleted their own scores recently, see `api::scores`.
+#[must_use]
+pub fn score_removals(player_id: i32) -> String {
+    format!("wavebreaker:v2:score_removals:{player_id}")
+}
+
+/// Hash of the rendered news items per locale, see `util::news`.
+pub const NEWS: &str = "wavebreaker:v2:localized_news";
+
+/// Counter of the requests made with an API key on a day, see `util::api_quota`.
+#[must_use]
+pub fn api_quota_day(key_id: i32, date: Date) -> String {
+    format!("wavebreaker:v2:api_quota:{key_id}:day:{date}")
+}
+
+/// Counter of the requests made with an API key recently, see `util::api_quota`.
+#[must_use]
+pub fn api_quota_burst(key_id: i32) -> String {
+    format!("wavebreaker:v2:api_quota:{key_id}:burst")
+}
+
+/// Counter of the requests made without an API key by a client recently, see `util::api_quota`.
+#[must_use]
+pub fn api_quota_anonymous(client: &str) -> String {
+    format!("wavebreaker:v2:api_quota:anonymous:{client}")
+}
+
+/// Hash with the checkpoint of the MusicBrainz backfill, see `util::metadata_backfill`.
+pub const METADATA_BACKFILL: &str = "wavebreaker:v2:metadata_backfill";
+
+/// Index of the scores on a song's leaderboard in a league, see `util::rank_cache`.
+#[must_use]
+pub fn rank_index(song_id: i32, league: League) -> String {
+    format!("wavebreaker:v2:rank_index:{song_id}:{}", league as i16)
+}
+
+/// How often the index of a song's leaderboard in a league was deleted, see `util::rank_cache`.
+#[must_use]
+pub fn rank_index_version(song_id: i32, league: League) -> String {
+    format!(
+        "wavebreaker:v2:rank_index_version:{song_id}:{}",
+        league as i16
+    )
+}
+
+/// Link to the preview of a Deezer track, see `util::previews`.
+#[must_use]
+pub fn preview(track_id: i64) -> String {
+    format!("wavebreaker:v2:preview:{track_id}")
+}
+
+/// Where the skill points were stored in version 1.
+const V1_SKILL_POINTS: &str = "leaderboard";
+
+/// Finds out which layout version the data in Redis is in.
+///
+/// Data from before the version was tracked is recognized as version 1.
+/// An empty Redis gets the current version written into it right away, since there's nothing to migrate.
+pub async fn current_version(redis_conn: &mut deadpool_redis::Connection) -> anyhow::Result<i32> {
+    if let Some(version) = redis_conn.get::<_, Option<i32>>(SCHEMA_VERSION_KEY).await? {
+        return Ok(version);
+    }
+
+    if redis_conn.exists::<_, bool>(V1_SKILL_POINTS).await? {
+        return Ok(1);
+    }
+
+    redis_conn
+        .set::<_, _, ()>(SCHEMA_VERSION_KEY, SCHEMA_VERSION)
+        .await?;
+    Ok(SCHEMA_VERSION)
+}
+
+/// Rewrites the keys in Redis, one version at a time, until they're in the current layout.
+///
+/// # Returns
+/// The version the data was in before migrating.
+///
+/// # Errors
+/// Fails if the data is in a newer layout than this build knows about, or if something is wrong with Redis.
+pub async fn migrate(redis_conn: &mut deadpool_redis::Connection) -> anyhow::Result<i32> {
+    let initial_version = current_version(redis_conn).await?;
+    if initial_version > SCHEMA_VERSION {
+        bail!("Redis data is in schema version {initial_version}, but this build only knows up to version {SCHEMA_VERSION}");
+    }
+
+    let mut version = initial_version;
+    while version < SCHEMA_VERSION {
+        match version {
+            1 => migrate_v1_to_v2(redis_conn).await?,
+            _ => bail!("Don't know how to migrate Redis data from schema version {version}"),
+        }
+        version += 1;
+        info!("Migrated Redis data to schema version {version}");
+    }
+
+    Ok(initial_version)
+}
+
+/// Moves the skill points from `leaderboard` to their versioned key.
+async fn migrate_v1_to_v2(redis_conn: &mut deadpool_redis::Connection) -> anyhow::Result<()> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    if redis_conn.exists::<_, bool>(V1_SKILL_POINTS).await? {
+        pipe.rename(V1_SKILL_POINTS, SKILL_POINTS).ignore();
+    }
+    pipe.set(SCHEMA_VERSION_KEY, 2).ignore();
+    pipe.query_async::<()>(redis_conn).await?;
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+//! Pipelined versions of the Redis patterns that come up on every submission, so each is one round trip.
+//!
+//! - Counters in fixed windows (plays per day, shout rate limits, API key quotas): the key is created with its expiry
+//!   before it's incremented, in the same transaction. A crash between the two can't leave a counter that never
+//!   expires anymore, which incrementing first and setting the expiry on the first count could.
+//! - Skill point changes: all sorted sets of a player are changed at once, see [`crate::util::rankings`].
+
+use redis::{ExistenceCheck, SetExpiry, SetOptions};
+
+/// A counter's value after it was incremented, see [`incr_in_windows`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowCount {
+    pub count: i64,
+    /// Seconds until the window ends and the counter starts over
+    pub ttl_secs: i64,
+}
+
+/// Increments counters that start over after a fixed window, all in one transaction.
+/// Every window starts with the first increment in it, so they're fixed windows and not sliding ones.
+///
+/// # Arguments
+/// * `windows` - The keys of the counters and the length of their windows, in seconds
+///
+/// # Errors
+/// Fails if something is wrong with Redis.
+pub async fn incr_in_windows(
+    windows: &[(&str, u64)],
+    redis_conn: &mut deadpool_redis::Connection,
+) -> redis::RedisResult<Vec<WindowCount>> {
+    if windows.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for (key, window_secs) in windows {
+        pipe.set_options(
+            key,
+            0,
+            SetOptions::default()
+                .conditional_set(ExistenceCheck::NX)
+                .with_expiration(SetExpiry::EX((*window_secs).max(1))),
+        )
+        .ignore()
+        .incr(key, 1)
+        .ttl(key);
+    }
+    let flat: Vec<i64> = pipe.query_async(redis_conn).await?;
+
+    Ok(flat
+        .chunks_exact(2)
+        .map(|pair| WindowCount {
+            count: pair[0],
+            ttl_secs: pair[1],
+        })
+        .collect())
+}
+
+/// Increments one counter that starts over after a fixed window, see [`incr_in_windows`].
+///
+/// # Errors
+/// Fails if something is wrong with Redis.
+pub async fn incr_in_window(
+    key: &str,
+    window_secs: u64,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> redis::RedisResult<WindowCount> {
+    let counts = incr_in_windows(&[(key, window_secs)], redis_conn).await?;
+    Ok(counts[0])
+}
+
+/// Changes the score of a member in several sorted sets in one transaction, like a player's points in every
+/// ranking mode. Sets whose delta is 0 are left alone, nothing is sent if all of them are.
+///
+/// # Errors
+/// Fails if something is wrong with Redis.
+pub async fn zincr_many(
+    member: i32,
+    deltas: impl IntoIterator<Item = (String, i64)>,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> redis::RedisResult<()> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    let mut changed = false;
+    for (key, delta) in deltas {
+        if delta != 0 {
+            pipe.zincr(key, member, delta).ignore();
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(());
+    }
+
+    pipe.query_async::<()>(redis_conn).await
+}
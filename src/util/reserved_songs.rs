@@ -0,0 +1,77 @@
+//! Song IDs reserved for built-in entries, like the songs of Audiosurf Radio.
+//!
+//! The game only knows songs by their ID, so built-in entries need IDs that are the same on every server
+//! and never taken by a song the server created. The `songs` ID sequence stops below [`RESERVED_SONG_IDS`],
+//! anything in there is only ever inserted explicitly, see [`crate::models::songs::Song::find_or_create_reserved`].
+
+use std::ops::RangeInclusive;
+
+use crate::util::{normalize::normalize_tag, radio::RadioSong};
+
+/// IDs the server never hands out to songs it creates on its own.
+pub const RESERVED_SONG_IDS: RangeInclusive<i32> = 2_000_000_000..=i32::MAX;
+
+#[must_use]
+pub fn is_reserved(song_id: i32) -> bool {
+    RESERVED_SONG_IDS.contains(&song_id)
+}
+
+/// Finds the radio song with a reserved ID the title and artist sent by the game belong to, if any.
+/// Radio songs with a regular ID are songs the server knows already, their lookups aren't special.
+///
+/// # Returns
+/// The reserved ID and the radio song.
+#[must_use]
+pub fn find_reserved_radio_song<'a>(
+    radio_songs: &'a [RadioSong],
+    title: &str,
+    artist: &str,
+) -> Option<(i32, &'a RadioSong)> {
+    let (title, artist) = (normalize_tag(title), normalize_tag(artist));
+    radio_songs.iter().find_map(|song| {
+        let id = i32::try_from(song.id).ok().filter(|id| is_reserved(*id))?;
+        (normalize_tag(&song.title) == title && normalize_tag(&song.artist) == artist)
+            .then_some((id, song))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn radio_song(id: u32, title: &str, artist: &str) -> RadioSong {
+        RadioSong {
+            id,
+            title: title.to_owned(),
+            artist: artist.to_owned(),
+            external_url: String::new(),
+            cgr_url: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_is_reserved() {
+        assert!(!is_reserved(1));
+        assert!(!is_reserved(1_999_999_999));
+        assert!(is_reserved(2_000_000_000));
+        assert!(is_reserved(i32::MAX));
+    }
+
+    #[test]
+    fn test_find_reserved_radio_song() {
+        let radio_songs = [
+            radio_song(1, "Known Song", "Someone"),
+            radio_song(2_000_000_001, "Dear Music.", "A4."),
+            // Doesn't fit into an i32, so it can't be reserved either
+            radio_song(u32::MAX, "Too Big", "Someone"),
+        ];
+
+        assert_eq!(
+            find_reserved_radio_song(&radio_songs, "dear music.", "a4.").map(|(id, _)| id),
+            Some(2_000_000_001)
+        );
+        assert!(find_reserved_radio_song(&radio_songs, "known song", "someone").is_none());
+        assert!(find_reserved_radio_song(&radio_songs, "too big", "someone").is_none());
+        assert!(find_reserved_radio_song(&radio_songs, "dear music.", "someone").is_none());
+    }
+}
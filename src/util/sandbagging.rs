@@ -0,0 +1,358 @@
+//! Detects league-sandbagging: elite-level players riding in Casual to farm easy top spots.
+//!
+//! The rules come from the `sandbagging` section of the config. Once a day, every player with at least
+//! `min_elite_points` in a realm's `elite` rankings (see [`crate::util::rankings`]) is looked at. They're flagged
+//! when, of the scores they submitted in the last `window_days`, at least `min_casual_tops` are on top of their
+//! Casual leaderboard and at least `min_casual_share` of them are in Casual. Playing Casual now and then doesn't
+//! get anyone flagged, mostly playing it while being able to do well in Elite does.
+//!
+//! What happens then depends on `action`: the flag is only stored for moderators to look at, the player's Casual
+//! scores stop counting towards the rankings as well, or moderators are told about it on a webhook as well.
+//! A moderator clearing the flag undoes that, and the player is only flagged again for what they do after.
+
+use std::collections::HashMap;
+
+use anyhow::ensure;
+use diesel::{dsl::max, prelude::*};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use redis::AsyncCommands;
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+use tracing::{info, warn};
+
+use crate::{
+    models::{sandbagging_flags::SandbaggingFlag, scores::Score},
+    records,
+    schema::{players, scores},
+    util::{
+        game_types::League,
+        rankings::{self, RankingMode},
+        realm::MAIN_REALM,
+    },
+    AppState,
+};
+
+/// What's done about a player who looks like they're sandbagging.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SandbaggingAction {
+    /// Only store the flag, for moderators to look at under `/api/admin/sandbagging`
+    #[default]
+    Flag,
+    /// Also keep the player's Casual scores out of the rankings until the flag is cleared
+    ExcludeCasual,
+    /// Also post about it to `webhook_url`
+    NotifyModerators,
+}
+
+/// Rules for the detection, configured in the `sandbagging` section of the config.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SandbaggingRules {
+    /// Whether the job worker looks for sandbagging every day
+    pub enabled: bool,
+    /// Points in the `elite` rankings that make a player elite-level
+    pub min_elite_points: i64,
+    /// How far back the submissions are looked at
+    pub window_days: i64,
+    /// Casual scores on top of their leaderboard it takes to be flagged
+    pub min_casual_tops: i32,
+    /// Share of the submissions that have to be in Casual to be flagged, from 0 to 1
+    pub min_casual_share: f64,
+    pub action: SandbaggingAction,
+    /// Discord webhook (or anything taking the same JSON) moderators are told on, for `notifyModerators`
+    pub webhook_url: Option<String>,
+}
+
+impl Default for SandbaggingRules {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            // About a hundred Elite scores at the gold threshold
+            min_elite_points: 30_000,
+            window_days: 7,
+            min_casual_tops: 10,
+            min_casual_share: 0.5,
+            action: SandbaggingAction::Flag,
+            webhook_url: None,
+        }
+    }
+}
+
+impl SandbaggingRules {
+    /// Checks what the types can't, called when the config is loaded.
+    ///
+    /// # Errors
+    /// Fails with what's wrong with the rules.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.window_days > 0,
+            "sandbagging.window_days has to be at least 1"
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.min_casual_share),
+            "sandbagging.min_casual_share has to be between 0 and 1"
+        );
+        Ok(())
+    }
+
+    /// Whether what an elite-level player submitted recently looks like sandbagging.
+    #[must_use]
+    pub fn is_sandbagging(&self, activity: &LeagueActivity) -> bool {
+        activity.scores > 0
+            && activity.casual_tops >= self.min_casual_tops
+            && f64::from(activity.casual_scores) / f64::from(activity.scores)
+                >= self.min_casual_share
+    }
+}
+
+/// What a player submitted during the detection window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LeagueActivity {
+    pub scores: i32,
+    pub casual_scores: i32,
+    /// Casual scores nobody else beat on their leaderboard
+    pub casual_tops: i32,
+}
+
+impl LeagueActivity {
+    /// Sums up the player's recent scores, given as `(song ID, league, score)`, along with the best Casual score
+    /// of anyone else on those songs.
+    #[must_use]
+    pub fn from_scores(
+        recent: &[(i32, League, i32)],
+        best_casual_of_others: &HashMap<i32, i32>,
+    ) -> Self {
+        let mut activity = Self::default();
+        for (song, league, score) in recent {
+            activity.scores += 1;
+            if *league != League::Casual {
+                continue;
+            }
+            activity.casual_scores += 1;
+            if !best_casual_of_others
+                .get(song)
+                .is_some_and(|best| best > score)
+            {
+                activity.casual_tops += 1;
+            }
+        }
+        activity
+    }
+
+    /// Looks up what the player submitted in the realm since `since`.
+    pub async fn of(
+        player: i32,
+        realm: &str,
+        since: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        let recent: Vec<(i32, League, i32)> = Score::all()
+            .filter(scores::player_id.eq(player))
+            .filter(scores::realm.eq(realm))
+            .filter(scores::submitted_at.ge(since))
+            .select((scores::song_id, scores::league, scores::score))
+            .load(conn)
+            .await?;
+
+        let casual_songs: Vec<i32> = recent
+            .iter()
+            .filter(|(_, league, _)| *league == League::Casual)
+            .map(|(song, _, _)| *song)
+            .collect();
+        let best_casual_of_others: HashMap<i32, i32> = Score::all()
+            .filter(scores::song_id.eq_any(&casual_songs))
+            .filter(scores::realm.eq(realm))
+            .filter(scores::league.eq(League::Casual))
+            .filter(scores::player_id.ne(player))
+            .group_by(scores::song_id)
+            .select((scores::song_id, max(scores::score)))
+            .load::<(i32, Option<i32>)>(conn)
+            .await?
+            .into_iter()
+            .filter_map(|(song, best)| Some((song, best?)))
+            .collect();
+
+        Ok(Self::from_scores(&recent, &best_casual_of_others))
+    }
+}
+
+/// Looks for sandbagging in every realm and does what the rules say about it.
+///
+/// # Returns
+/// How many players were newly flagged.
+///
+/// # Errors
+/// Fails if something is wrong with the DB or Redis. A webhook that doesn't work is only logged.
+pub async fn detect(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> anyhow::Result<usize> {
+    let rules = &state.config.sandbagging;
+    let window_start = OffsetDateTime::now_utc() - Duration::days(rules.window_days);
+    let realms = std::iter::once(MAIN_REALM)
+        .chain(state.config.main.realms.iter().map(|realm| realm.name()));
+
+    let mut flagged = 0;
+    for realm in realms {
+        let candidates: Vec<(i32, i64)> = redis_conn
+            .zrangebyscore_withscores(
+                RankingMode::Elite.key(realm),
+                rules.min_elite_points,
+                "+inf",
+            )
+            .await?;
+        for (player, elite_points) in candidates {
+            let since = SandbaggingFlag::cleared_at_of(player, realm, conn)
+                .await?
+                .map_or(window_start, |cleared_at| cleared_at.max(window_start));
+            let activity = LeagueActivity::of(player, realm, since, conn).await?;
+            if !rules.is_sandbagging(&activity) {
+                continue;
+            }
+
+            let exclude = rules.action == SandbaggingAction::ExcludeCasual;
+            let Some(flag) =
+                SandbaggingFlag::flag(player, realm, elite_points, &activity, exclude, conn)
+                    .await?
+            else {
+                continue;
+            };
+            flagged += 1;
+            warn!(
+                "Player {player} flagged for sandbagging in realm {realm}: {} Casual top(s) out of {} score(s)",
+                flag.casual_tops, flag.recent_scores
+            );
+
+            if exclude {
+                rankings::refresh_player(player, realm, conn, redis_conn).await?;
+            }
+            if rules.action == SandbaggingAction::NotifyModerators {
+                if let Some(webhook_url) = &rules.webhook_url {
+                    notify_moderators(webhook_url, &flag, conn).await;
+                }
+            }
+        }
+    }
+
+    info!("Flagged {flagged} player(s) for sandbagging");
+    Ok(flagged)
+}
+
+/// Tells moderators about a new flag. Failing to do so is only logged, the flag is stored either way.
+async fn notify_moderators(
+    webhook_url: &str,
+    flag: &SandbaggingFlag,
+    conn: &mut AsyncPgConnection,
+) {
+    let username = players::table
+        .find(flag.player_id)
+        .select(players::username)
+        .first::<String>(conn)
+        .await
+        .unwrap_or_else(|_| format!("Player {}", flag.player_id));
+    let message = format!(
+        "{username} (ID {}) might be sandbagging in realm {}: {} of their {} recent score(s) are in Casual, {} of them on top of the leaderboard. Flag {} can be cleared under /api/admin/sandbagging.",
+        flag.player_id,
+        flag.realm,
+        flag.recent_casual_scores,
+        flag.recent_scores,
+        flag.casual_tops,
+        flag.id
+    );
+    if let Err(e) = records::announce(webhook_url, &message).await {
+        warn!(
+            "Failed to tell moderators about sandbagging flag {}: {e:?}",
+            flag.id
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_scores() {
+        let best_of_others = HashMap::from([(1, 500), (2, 900)]);
+        let activity = LeagueActivity::from_scores(
+            &[
+                // Beats the best of the others
+                (1, League::Casual, 600),
+                // Doesn't
+                (2, League::Casual, 800),
+                // Nobody else rode it
+                (3, League::Casual, 100),
+                (4, League::Elite, 1000),
+            ],
+            &best_of_others,
+        );
+        assert_eq!(
+            activity,
+            LeagueActivity {
+                scores: 4,
+                casual_scores: 3,
+                casual_tops: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_is_sandbagging() {
+        let rules = SandbaggingRules {
+            min_casual_tops: 2,
+            min_casual_share: 0.5,
+            ..Default::default()
+        };
+        let farming = LeagueActivity {
+            scores: 4,
+            casual_scores: 3,
+            casual_tops: 2,
+        };
+        assert!(rules.is_sandbagging(&farming));
+
+        // Mostly Elite, with a few Casual tops on the side
+        let mostly_elite = LeagueActivity {
+            scores: 20,
+            casual_scores: 3,
+            casual_tops: 3,
+        };
+        assert!(!rules.is_sandbagging(&mostly_elite));
+
+        // Mostly Casual, but not on top
+        let not_on_top = LeagueActivity {
+            scores: 4,
+            casual_scores: 4,
+            casual_tops: 1,
+        };
+        assert!(!rules.is_sandbagging(&not_on_top));
+
+        assert!(!rules.is_sandbagging(&LeagueActivity::default()));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(SandbaggingRules::default().validate().is_ok());
+        for rules in [
+            SandbaggingRules {
+                window_days: 0,
+                ..Default::default()
+            },
+            SandbaggingRules {
+                min_casual_share: 1.5,
+                ..Default::default()
+            },
+            SandbaggingRules {
+                min_casual_share: -0.1,
+                ..Default::default()
+            },
+            SandbaggingRules {
+                min_casual_share: f64::NAN,
+                ..Default::default()
+            },
+        ] {
+            assert!(rules.validate().is_err());
+        }
+    }
+}
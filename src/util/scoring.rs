@@ -0,0 +1,273 @@
+//! How scores translate into skill points.
+//!
+//! The formula is a policy from the `scoring` section of the config, installed once on startup with [`install`].
+//! A score is worth the points of its league times how far it got towards the gold threshold,
+//! optionally capped at some multiple of the threshold. The rank on the leaderboard deliberately isn't part of it:
+//! every score that's beaten would change the skill points of its player, which the rankings can't keep up with.
+//!
+//! The rankings in Redis are kept up to date incrementally, so they're only right for the formula they were
+//! computed with. That's recorded as the policy's `version` in [`redis_keys::SCORING_VERSION`]. Operators changing
+//! the formula bump the version and run `recalculate-skill-points`, the self-check warns until they do.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::OnceLock,
+};
+
+use anyhow::Context;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use redis::AsyncCommands;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{
+    models::{
+        sandbagging_flags::SandbaggingFlag,
+        scores::Score,
+        skill_point_ledger::{LedgerEntry, LedgerReason},
+    },
+    schema::{scores, scores_archive},
+    util::{
+        game_types::League,
+        rankings::{self, RankingMode},
+        realm::MAIN_REALM,
+        redis_keys,
+    },
+};
+
+/// Song ID, league and skill points of a score, see [`rankings::totals`].
+type RankedScore = (i32, League, i32);
+
+static POLICY: OnceLock<ScoringPolicy> = OnceLock::new();
+
+/// The skill point formula, configured in the `scoring` section of the config.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ScoringPolicy {
+    /// Identifies the formula. Has to be bumped whenever anything else here changes.
+    pub version: u32,
+    /// Skill points for a score right at the gold threshold, for Casual, Pro and Elite
+    pub league_points: [u32; 3],
+    /// Scores count as at most this many times the gold threshold. Unlimited if unset.
+    pub max_gold_ratio: Option<f64>,
+    /// How much the skill points of a Casual, Pro and Elite score count in the weighted rankings,
+    /// see [`crate::util::rankings`]
+    pub league_weights: [f64; 3],
+}
+
+impl Default for ScoringPolicy {
+    /// The formula Wavebreaker has always used, as version 1.
+    fn default() -> Self {
+        Self {
+            version: 1,
+            league_points: [100, 200, 300],
+            max_gold_ratio: None,
+            league_weights: [0.5, 1.0, 1.5],
+        }
+    }
+}
+
+impl ScoringPolicy {
+    /// Calculates the skill points a score is worth.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn skill_points(&self, league: League, score: i32, gold_threshold: i32) -> i32 {
+        let mut ratio = f64::from(score) / f64::from(gold_threshold);
+        if let Some(max_gold_ratio) = self.max_gold_ratio {
+            ratio = ratio.min(max_gold_ratio);
+        }
+        let league_points = self.league_points[league as usize];
+        (ratio * f64::from(league_points)).round() as i32
+    }
+
+    /// Calculates what the skill points of a score count in the weighted rankings.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn weighted_points(&self, league: League, skill_points: i32) -> i32 {
+        (f64::from(skill_points) * self.league_weights[league as usize]).round() as i32
+    }
+}
+
+/// Makes the policy the one [`policy`] returns. Only the first call has an effect.
+pub fn install(policy: ScoringPolicy) {
+    if POLICY.set(policy).is_err() {
+        warn!("Scoring policy was installed twice, keeping the first one");
+    }
+}
+
+/// The installed policy, or the default one if none was installed (like in benchmarks).
+pub fn policy() -> &'static ScoringPolicy {
+    POLICY.get_or_init(ScoringPolicy::default)
+}
+
+/// Finds out which version of the formula the rankings were computed with.
+/// Rankings from before versions were tracked are assumed to be version 1, that's the only formula there was.
+///
+/// # Errors
+/// Fails if something is wrong with Redis.
+pub async fn rankings_version(
+    redis_conn: &mut deadpool_redis::Connection,
+) -> redis::RedisResult<u32> {
+    let version: Option<u32> = redis_conn.get(redis_keys::SCORING_VERSION).await?;
+    Ok(version.unwrap_or(1))
+}
+
+/// Computes the rankings of every realm in every mode again from the scores, with the installed policy.
+///
+/// Submissions while this runs can get lost from the rankings, so it's best done while the server is stopped.
+///
+/// # Returns
+/// How many players are ranked in each realm.
+///
+/// # Errors
+/// Fails if something is wrong with the DB or Redis.
+pub async fn recalculate_rankings(
+    realms: &[String],
+    conn: &mut AsyncPgConnection,
+    redis_conn: &mut deadpool_redis::Connection,
+) -> anyhow::Result<BTreeMap<String, usize>> {
+    let policy = policy();
+
+    let mut all_scores: Vec<(i32, String, i32, League, i32, i32)> = Score::all()
+        .select((
+            scores::player_id,
+            scores::realm,
+            scores::song_id,
+            scores::league,
+            scores::score,
+            scores::gold_threshold,
+        ))
+        .load(conn)
+        .await?;
+    // Archived scores keep counting, see `crate::models::scores_archive`
+    let archived_scores: Vec<(i32, String, i32, League, i32, i32)> = scores_archive::table
+        .select((
+            scores_archive::player_id,
+            scores_archive::realm,
+            scores_archive::song_id,
+            scores_archive::league,
+            scores_archive::score,
+            scores_archive::gold_threshold,
+        ))
+        .load(conn)
+        .await?;
+    all_scores.extend(archived_scores);
+
+    // Per realm and player: song ID, league and skill points of every score
+    let mut player_scores: HashMap<String, HashMap<i32, Vec<RankedScore>>> = realms
+        .iter()
+        .map(|realm| (realm.clone(), HashMap::new()))
+        .collect();
+    player_scores.entry(MAIN_REALM.to_owned()).or_default();
+    let casual_excluded: HashSet<(i32, String)> = SandbaggingFlag::all_excluded(conn)
+        .await?
+        .into_iter()
+        .collect();
+    for (player, realm, song, league, score, gold_threshold) in all_scores {
+        if league == League::Casual && casual_excluded.contains(&(player, realm.clone())) {
+            continue;
+        }
+        player_scores
+            .entry(realm)
+            .or_default()
+            .entry(player)
+            .or_default()
+            .push((
+                song,
+                league,
+                policy.skill_points(league, score, gold_threshold),
+            ));
+    }
+
+    let mut ranked = BTreeMap::new();
+    for (realm, mut realm_scores) in player_scores {
+        // Players without scores are ranked too, with 0 skill points (or what moderators gave them)
+        let previous: HashMap<i32, i64> = redis_conn
+            .zrange_withscores::<_, Vec<(i32, i64)>>(redis_keys::skill_points(&realm), 0, -1)
+            .await?
+            .into_iter()
+            .collect();
+        let adjustments = LedgerEntry::all_adjustments(&realm, conn).await?;
+        let recorded = LedgerEntry::totals(&realm, conn).await?;
+        for player in previous
+            .keys()
+            .chain(adjustments.keys())
+            .chain(recorded.keys())
+        {
+            realm_scores.entry(*player).or_default();
+        }
+
+        let mut items: [Vec<(i64, i32)>; RankingMode::ALL.len()] = Default::default();
+        let mut corrections = Vec::with_capacity(realm_scores.len());
+        for (player, scores) in &realm_scores {
+            let totals = rankings::totals(policy, scores.iter().copied());
+            for ((mode, mode_items), points) in
+                RankingMode::ALL.into_iter().zip(&mut items).zip(totals)
+            {
+                let mut points = i64::from(points);
+                if mode == RankingMode::SkillPoints {
+                    points += adjustments.get(player).copied().unwrap_or_default();
+                    let correction = points - recorded.get(player).copied().unwrap_or_default();
+                    let correction = i32::try_from(correction).with_context(|| {
+                        format!(
+                            "The skill points of player {player} in realm {realm} changed by more than a ledger entry can hold"
+                        )
+                    })?;
+                    corrections.push((*player, correction));
+                }
+                mode_items.push((points, *player));
+            }
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (mode, mode_items) in RankingMode::ALL.into_iter().zip(&items) {
+            let key = mode.key(&realm);
+            pipe.del(&key);
+            if !mode_items.is_empty() {
+                pipe.zadd_multiple(&key, mode_items);
+            }
+        }
+        pipe.query_async::<()>(redis_conn).await?;
+        LedgerEntry::record_corrections(&realm, LedgerReason::Recalculation, &corrections, conn)
+            .await?;
+        ranked.insert(realm, realm_scores.len());
+    }
+
+    redis_conn
+        .set::<_, _, ()>(redis_keys::SCORING_VERSION, policy.version)
+        .await?;
+    info!(
+        "Rankings recalculated with scoring version {}",
+        policy.version
+    );
+
+    Ok(ranked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy() {
+        let policy = ScoringPolicy::default();
+        assert_eq!(policy.skill_points(League::Casual, 1000, 1000), 100);
+        assert_eq!(policy.skill_points(League::Pro, 1500, 1000), 300);
+        assert_eq!(policy.skill_points(League::Elite, 500, 1000), 150);
+    }
+
+    #[test]
+    fn test_max_gold_ratio() {
+        let policy = ScoringPolicy {
+            version: 2,
+            league_points: [10, 20, 30],
+            max_gold_ratio: Some(1.5),
+            ..ScoringPolicy::default()
+        };
+        assert_eq!(policy.skill_points(League::Casual, 1000, 1000), 10);
+        assert_eq!(policy.skill_points(League::Elite, 1200, 1000), 36);
+        assert_eq!(policy.skill_points(League::Elite, 5000, 1000), 45);
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::{bail, Context};
+use diesel::{sql_query, sql_types::Text, QueryableByName};
+use diesel_async::RunQueryDsl;
+use steam_rs::steam_id::SteamId;
+use tracing::{info, warn};
+
+use crate::{
+    util::{redis_keys, scoring},
+    AppState,
+};
+
+/// Postgres extensions the migrations rely on.
+const REQUIRED_EXTENSIONS: &[&str] = &["plpgsql"];
+/// Indexes that queries depend on for correctness, not just speed.
+/// Score submissions upsert on `scores_unique_compound`, for example.
+const REQUIRED_INDEXES: &[&str] = &[
+    "scores_unique_compound",
+    "songs_unique_data",
+    "jobs_pending",
+];
+
+/// Any Steam ID works for checking the API key, this one is Gabe Newell's.
+const STEAM_CHECK_ID: u64 = 76_561_197_960_287_930;
+
+#[derive(QueryableByName)]
+struct Name {
+    #[diesel(sql_type = Text)]
+    name: String,
+}
+
+/// Checks that Postgres, Redis and the Steam API are set up the way Wavebreaker expects,
+/// so misconfiguration shows up on startup instead of in the middle of a player's request.
+///
+/// # Errors
+/// Fails with a description of what's wrong and how to fix it, if any of the checks fail.
+pub async fn run(state: &AppState) -> anyhow::Result<()> {
+    check_postgres(state).await?;
+    check_redis(state).await?;
+    check_steam(state).await?;
+
+    info!("Self-check passed");
+    Ok(())
+}
+
+async fn check_postgres(state: &AppState) -> anyhow::Result<()> {
+    let mut conn = state
+        .db
+        .get()
+        .await
+        .context("Can't connect to Postgres, check main.database in the config")?;
+
+    let extensions: Vec<String> = sql_query("SELECT extname AS name FROM pg_extension")
+        .load::<Name>(&mut conn)
+        .await?
+        .into_iter()
+        .map(|row| row.name)
+        .collect();
+    for extension in REQUIRED_EXTENSIONS {
+        if !extensions.iter().any(|e| e == extension) {
+            bail!("Postgres extension {extension} is missing, install it with CREATE EXTENSION {extension}");
+        }
+    }
+
+    let indexes: Vec<String> =
+        sql_query("SELECT indexname AS name FROM pg_indexes WHERE schemaname = current_schema()")
+            .load::<Name>(&mut conn)
+            .await?
+            .into_iter()
+            .map(|row| row.name)
+            .collect();
+    for index in REQUIRED_INDEXES {
+        if !indexes.iter().any(|i| i == index) {
+            bail!("Postgres index {index} is missing, the database doesn't match the migrations. Did someone drop it by hand? Re-run the migration that creates it.");
+        }
+    }
+
+    Ok(())
+}
+
+async fn check_redis(state: &AppState) -> anyhow::Result<()> {
+    let mut redis_conn = state
+        .redis
+        .get()
+        .await
+        .context("Can't connect to Redis, check main.redis in the config")?;
+
+    let version = redis_keys::current_version(&mut redis_conn)
+        .await
+        .context("Failed to read the Redis schema version")?;
+    if version > redis_keys::SCHEMA_VERSION {
+        bail!(
+            "Redis data is in schema version {version}, but this build only understands up to version {}. Did you downgrade Wavebreaker?",
+            redis_keys::SCHEMA_VERSION
+        );
+    } else if version < redis_keys::SCHEMA_VERSION {
+        bail!(
+            "Redis data is in schema version {version}, but this build expects version {}. Run `wavebreaker migrate-redis` to migrate it.",
+            redis_keys::SCHEMA_VERSION
+        );
+    }
+
+    // The server works fine with outdated rankings, they're just not fair until they're recalculated
+    let scoring_version = scoring::rankings_version(&mut redis_conn).await?;
+    if scoring_version != scoring::policy().version {
+        warn!(
+            "Rankings were computed with scoring version {scoring_version}, but scoring.version is {}. Run `wavebreaker recalculate-skill-points` to recalculate them.",
+            scoring::policy().version
+        );
+    }
+
+    Ok(())
+}
+
+async fn check_steam(state: &AppState) -> anyhow::Result<()> {
+    state
+        .steam_api
+        .get_player_summaries(vec![SteamId::new(STEAM_CHECK_ID)])
+        .await
+        .context("Steam API check failed, make sure external.steam_key in the config is a valid Steam Web API key")?;
+
+    Ok(())
+}
@@ -0,0 +1,49 @@
+//! Slugs players pick for their profile links, like `/api/players/by-slug/m1nt` instead of a numeric ID.
+//! See [`crate::models::player_slugs`] for how they're claimed and changed.
+
+/// Shortest slug that can be claimed
+pub const MIN_LENGTH: usize = 3;
+/// Longest slug that can be claimed, the column has the same limit
+pub const MAX_LENGTH: usize = 32;
+
+/// Brings a slug into its stored form (trimmed and lowercase) and checks that it can be claimed:
+/// - between [`MIN_LENGTH`] and [`MAX_LENGTH`] characters
+/// - only ASCII letters, digits, `-` and `_`, starting and ending with a letter or digit
+/// - not only digits, so it can't be mistaken for a player ID
+///
+/// # Returns
+/// `None` if the slug can't be claimed.
+#[must_use]
+pub fn parse_slug(slug: &str) -> Option<String> {
+    let slug = slug.trim().to_ascii_lowercase();
+    let valid = (MIN_LENGTH..=MAX_LENGTH).contains(&slug.len())
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        && slug.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && slug.ends_with(|c: char| c.is_ascii_alphanumeric())
+        && !slug.chars().all(|c| c.is_ascii_digit());
+
+    valid.then_some(slug)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_slug() {
+        assert_eq!(parse_slug("m1nt"), Some("m1nt".to_owned()));
+        assert_eq!(
+            parse_slug(" Rubber_Duck-Shobe "),
+            Some("rubber_duck-shobe".to_owned())
+        );
+        assert_eq!(parse_slug("ab"), None);
+        assert_eq!(parse_slug(&"a".repeat(33)), None);
+        assert_eq!(parse_slug("12345"), None);
+        assert_eq!(parse_slug("-m1nt"), None);
+        assert_eq!(parse_slug("m1nt_"), None);
+        assert_eq!(parse_slug("m1nt/admin"), None);
+        assert_eq!(parse_slug("mïnt"), None);
+    }
+}
@@ -0,0 +1,91 @@
+//! Decides who may create new songs by looking up tags the server doesn't know yet, see
+//! [`crate::models::songs::NewSong::find_cached`].
+//!
+//! The policy comes from the `song_creation` section of the config. On open servers anyone can, which is how it
+//! always was. Small servers can keep their songs tidy by only letting players with some scores create them, or
+//! nobody at all. Lookups that aren't allowed to create a song fail in the game and are queued as pending songs
+//! (see [`crate::models::pending_songs`]) for moderators to approve, as long as the player exists and didn't queue
+//! too many this hour. Moderators can always create songs.
+
+use serde::Deserialize;
+
+/// Who may create new songs.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SongCreationMode {
+    /// Anyone
+    #[default]
+    Open,
+    /// Only players with at least `min_scores` scores
+    MinScores,
+    /// Nobody, every new song has to be approved by a moderator
+    Approval,
+}
+
+/// The policy, configured in the `song_creation` section of the config.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SongCreationPolicy {
+    pub mode: SongCreationMode,
+    /// Scores a player needs to create songs, for `minScores`. Deleted ones don't count.
+    pub min_scores: i64,
+    /// Lookups a player can queue as pending songs per hour, the ones after that are dropped
+    pub pending_songs_per_hour: u32,
+}
+
+impl Default for SongCreationPolicy {
+    fn default() -> Self {
+        Self {
+            mode: SongCreationMode::Open,
+            min_scores: 10,
+            pending_songs_per_hour: 20,
+        }
+    }
+}
+
+impl SongCreationPolicy {
+    /// Whether anyone may create songs, so players don't have to be looked at.
+    #[must_use]
+    pub fn is_open(&self) -> bool {
+        self.mode == SongCreationMode::Open
+    }
+
+    /// Whether a player with that many scores may create songs.
+    #[must_use]
+    pub fn allows(&self, scores: i64, is_staff: bool) -> bool {
+        match self.mode {
+            SongCreationMode::Open => true,
+            _ if is_staff => true,
+            SongCreationMode::MinScores => scores >= self.min_scores,
+            SongCreationMode::Approval => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows() {
+        let open = SongCreationPolicy::default();
+        assert!(open.is_open());
+        assert!(open.allows(0, false));
+
+        let min_scores = SongCreationPolicy {
+            mode: SongCreationMode::MinScores,
+            ..SongCreationPolicy::default()
+        };
+        assert!(!min_scores.is_open());
+        assert!(!min_scores.allows(9, false));
+        assert!(min_scores.allows(10, false));
+        assert!(min_scores.allows(0, true));
+
+        let approval = SongCreationPolicy {
+            mode: SongCreationMode::Approval,
+            ..SongCreationPolicy::default()
+        };
+        assert!(!approval.allows(1000, false));
+        assert!(approval.allows(0, true));
+    }
+}
@@ -0,0 +1,173 @@
+//! Filters text players write before it's stored, like shouts and display names.
+//!
+//! The rules come from the `text_filter` section of the config, nothing is hardcoded.
+//! Words are matched as whole words and case-insensitively, so "class" doesn't trip over "ass".
+
+use serde::Deserialize;
+
+/// Rules for user text, configured in the `text_filter` section of the config.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct TextFilterRules {
+    /// Shouts containing these are refused, display names get them masked
+    pub blocked_words: Vec<String>,
+    /// Refuse shouts containing links
+    pub block_links: bool,
+    /// Refuse shouts repeating the same character more often than this in a row, like "aaaaaaaaaaaa"
+    pub max_repeated_chars: Option<usize>,
+    /// How many shouts a player can post per minute
+    pub shouts_per_minute: Option<u32>,
+}
+
+/// Why a text was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterReason {
+    BlockedWord,
+    Link,
+    RepeatedCharacters,
+    /// The same player posted this exact text last time
+    Duplicate,
+    /// The player posts too quickly
+    RateLimited,
+}
+
+impl TextFilterRules {
+    /// Checks the content of a shout. Rate limits and duplicates are checked by the caller,
+    /// since they need to know what the player posted before.
+    ///
+    /// # Returns
+    /// `None` if the shout is fine, the reason it isn't otherwise.
+    #[must_use]
+    pub fn check(&self, text: &str) -> Option<FilterReason> {
+        if words(text).any(|word| self.is_blocked(word)) {
+            return Some(FilterReason::BlockedWord);
+        }
+        if self.block_links && contains_link(text) {
+            return Some(FilterReason::Link);
+        }
+        if self
+            .max_repeated_chars
+            .is_some_and(|max| longest_run(text) > max)
+        {
+            return Some(FilterReason::RepeatedCharacters);
+        }
+
+        None
+    }
+
+    /// Replaces blocked words with asterisks, for text that can't just be refused like display names.
+    #[must_use]
+    pub fn mask(&self, text: &str) -> String {
+        let mut masked = String::with_capacity(text.len());
+        let mut word_start = None;
+
+        for (i, c) in text
+            .char_indices()
+            .chain(std::iter::once((text.len(), ' ')))
+        {
+            if c.is_alphanumeric() {
+                word_start.get_or_insert(i);
+                continue;
+            }
+            if let Some(start) = word_start.take() {
+                let word = &text[start..i];
+                if self.is_blocked(word) {
+                    masked.push_str(&"*".repeat(word.chars().count()));
+                } else {
+                    masked.push_str(word);
+                }
+            }
+            if i < text.len() {
+                masked.push(c);
+            }
+        }
+
+        masked
+    }
+
+    fn is_blocked(&self, word: &str) -> bool {
+        let word = word.to_lowercase();
+        self.blocked_words
+            .iter()
+            .any(|blocked| blocked.to_lowercase() == word)
+    }
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+}
+
+fn contains_link(text: &str) -> bool {
+    let text = text.to_lowercase();
+    text.contains("://") || text.contains("www.")
+}
+
+/// Length of the longest run of the same character, ignoring spaces.
+fn longest_run(text: &str) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    let mut previous = None;
+
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        if previous == Some(c) {
+            current += 1;
+        } else {
+            current = 1;
+            previous = Some(c);
+        }
+        longest = longest.max(current);
+    }
+
+    longest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> TextFilterRules {
+        TextFilterRules {
+            blocked_words: vec!["Heck".to_owned(), "darn".to_owned()],
+            block_links: true,
+            max_repeated_chars: Some(5),
+            shouts_per_minute: None,
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let rules = rules();
+        assert_eq!(rules.check("what a great song"), None);
+        assert_eq!(rules.check("checking out this track"), None);
+        assert_eq!(rules.check("aaaaa"), None);
+
+        assert_eq!(
+            rules.check("what the HECK"),
+            Some(FilterReason::BlockedWord)
+        );
+        assert_eq!(rules.check("darn!"), Some(FilterReason::BlockedWord));
+        assert_eq!(
+            rules.check("listen at https://example.com"),
+            Some(FilterReason::Link)
+        );
+        assert_eq!(
+            rules.check("aaaaaa"),
+            Some(FilterReason::RepeatedCharacters)
+        );
+
+        assert_eq!(
+            TextFilterRules::default().check("heck aaaaaaaaaa www.x"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_mask() {
+        let rules = rules();
+        assert_eq!(rules.mask("xX_Heck_Xx"), "xX_****_Xx");
+        assert_eq!(rules.mask("darned heck"), "darned ****");
+        assert_eq!(rules.mask("m1nt_"), "m1nt_");
+        assert_eq!(rules.mask(""), "");
+    }
+}
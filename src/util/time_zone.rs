@@ -0,0 +1,275 @@
+//! Where days start and end: for the plays per day record, the daily API key quotas, the daily jobs and the daily
+//! stats.
+//!
+//! Timestamps are stored and sent in UTC, only the day boundaries follow the `time_zone` section of the config.
+//! That's either a zone from the IANA time zone database, which follows daylight saving time, or a fixed offset.
+//!
+//! Players can store their own time zone for clients to show times in, see [`is_time_zone_name`].
+//! The server doesn't use it for anything.
+
+use std::fmt;
+
+use serde::{Deserialize, Deserializer};
+use time::{Date, Duration, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+use time_tz::{
+    timezones, Offset, OffsetDateTimeExt, OffsetResult, PrimitiveDateTimeExt, TimeZone as _, Tz,
+};
+
+/// The `time_zone` section of the config, see the module documentation.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct TimeZone {
+    /// Like `Europe/Berlin`, takes precedence over `utc_offset`
+    #[serde(deserialize_with = "deserialize_zone")]
+    pub name: Option<&'static Tz>,
+    /// Like `+02:00` or `-05:30`, UTC if neither this nor `name` is set
+    #[serde(deserialize_with = "deserialize_utc_offset")]
+    pub utc_offset: UtcOffset,
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        Self {
+            name: None,
+            utc_offset: UtcOffset::UTC,
+        }
+    }
+}
+
+impl fmt::Debug for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeZone")
+            .field("name", &self.name.map(|zone| zone.name()))
+            .field("utc_offset", &self.utc_offset)
+            .finish()
+    }
+}
+
+impl TimeZone {
+    /// The day it is in the server's time zone.
+    #[must_use]
+    pub fn today(self, now: OffsetDateTime) -> Date {
+        match self.name {
+            Some(zone) => now.to_timezone(zone).date(),
+            None => now.to_offset(self.utc_offset).date(),
+        }
+    }
+
+    /// When the day started in the server's time zone.
+    #[must_use]
+    pub fn start_of(self, day: Date) -> OffsetDateTime {
+        let midnight = day.with_time(Time::MIDNIGHT);
+        match self.name {
+            Some(zone) => start_in_zone(midnight, zone),
+            None => midnight.assume_offset(self.utc_offset),
+        }
+    }
+
+    /// When the day ends in the server's time zone, which is when the next one starts.
+    /// Days are 23 or 25 hours long when daylight saving time starts or ends.
+    #[must_use]
+    pub fn end_of(self, day: Date) -> OffsetDateTime {
+        day.next_day()
+            .map_or(PrimitiveDateTime::MAX.assume_utc(), |next| {
+                self.start_of(next)
+            })
+    }
+
+    /// When the current day started in the server's time zone.
+    #[must_use]
+    pub fn start_of_today(self, now: OffsetDateTime) -> OffsetDateTime {
+        self.start_of(self.today(now))
+    }
+
+    /// When the next day starts in the server's time zone.
+    #[must_use]
+    pub fn next_midnight(self, now: OffsetDateTime) -> OffsetDateTime {
+        self.end_of(self.today(now))
+    }
+
+    /// Seconds until the next day starts in the server's time zone, at least 1.
+    #[must_use]
+    pub fn secs_until_midnight(self, now: OffsetDateTime) -> u64 {
+        u64::try_from((self.next_midnight(now) - now).whole_seconds()).map_or(1, |secs| secs.max(1))
+    }
+}
+
+/// When local midnight is in the zone. If the clocks are turned back over it, that's the first of the two,
+/// and if they skip it, it's the moment they skip to.
+fn start_in_zone(midnight: PrimitiveDateTime, zone: &Tz) -> OffsetDateTime {
+    match midnight.assume_timezone(zone) {
+        OffsetResult::Some(start) | OffsetResult::Ambiguous(start, _) => start,
+        // Midnight didn't happen there, the offset from before the skip gets to the right moment.
+        // A day earlier in UTC is before local midnight in every zone.
+        OffsetResult::None => {
+            let before = zone
+                .get_offset_utc(&(midnight.assume_utc() - Duration::days(1)))
+                .to_utc();
+            midnight.assume_offset(before)
+        }
+    }
+}
+
+/// Parses a UTC offset like `+02:00`, `-0530`, `+2` or `Z`.
+fn parse_utc_offset(offset: &str) -> Option<UtcOffset> {
+    let offset = offset.trim();
+    if offset.eq_ignore_ascii_case("z") || offset.eq_ignore_ascii_case("utc") {
+        return Some(UtcOffset::UTC);
+    }
+    let (sign, rest) = if let Some(rest) = offset.strip_prefix('+') {
+        (1, rest)
+    } else {
+        (-1, offset.strip_prefix('-')?)
+    };
+    let (hours, minutes) = rest
+        .split_once(':')
+        .or_else(|| (rest.len() == 4).then(|| rest.split_at(2)))
+        .unwrap_or((rest, "0"));
+    if hours.is_empty() || hours.len() > 2 || minutes.len() > 2 {
+        return None;
+    }
+    let hours: i8 = hours.parse().ok()?;
+    let minutes: i8 = minutes.parse().ok()?;
+    // The offsets in use today are between -12:00 and +14:00
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+fn deserialize_utc_offset<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<UtcOffset, D::Error> {
+    let offset = String::deserialize(deserializer)?;
+    parse_utc_offset(&offset)
+        .ok_or_else(|| serde::de::Error::custom(format!("{offset} isn't a UTC offset like +02:00")))
+}
+
+fn deserialize_zone<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<&'static Tz>, D::Error> {
+    let Some(name) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    timezones::get_by_name(&name).map(Some).ok_or_else(|| {
+        serde::de::Error::custom(format!("{name} isn't a time zone like Europe/Berlin"))
+    })
+}
+
+/// Whether the name looks like one from the IANA time zone database, like `Europe/Berlin` or `UTC`.
+/// Clients only know the actual zones, so this just keeps out anything that can't be one.
+#[must_use]
+pub fn is_time_zone_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && name.split('/').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Month;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_utc_offset() {
+        let offset = |hours, minutes| UtcOffset::from_hms(hours, minutes, 0).ok();
+        assert_eq!(parse_utc_offset("+02:00"), offset(2, 0));
+        assert_eq!(parse_utc_offset("-05:30"), offset(-5, -30));
+        assert_eq!(parse_utc_offset("+0545"), offset(5, 45));
+        assert_eq!(parse_utc_offset("+9"), offset(9, 0));
+        assert_eq!(parse_utc_offset("Z"), Some(UtcOffset::UTC));
+        assert_eq!(parse_utc_offset("02:00"), None);
+        assert_eq!(parse_utc_offset("+15:00"), None);
+        assert_eq!(parse_utc_offset("+01:60"), None);
+        assert_eq!(parse_utc_offset("+"), None);
+    }
+
+    #[test]
+    fn test_day_boundaries() {
+        let berlin = TimeZone {
+            name: None,
+            utc_offset: UtcOffset::from_hms(2, 0, 0).unwrap(),
+        };
+        // 23:00 UTC is already the next day in Berlin
+        let late = OffsetDateTime::UNIX_EPOCH + Duration::hours(23);
+        assert_eq!(
+            TimeZone::default().today(late),
+            OffsetDateTime::UNIX_EPOCH.date()
+        );
+        assert_eq!(
+            berlin.today(late),
+            OffsetDateTime::UNIX_EPOCH.date().next_day().unwrap()
+        );
+        assert_eq!(
+            berlin.start_of_today(late),
+            OffsetDateTime::UNIX_EPOCH + Duration::hours(22)
+        );
+        assert_eq!(
+            berlin.next_midnight(late),
+            OffsetDateTime::UNIX_EPOCH + Duration::hours(46)
+        );
+        assert_eq!(berlin.secs_until_midnight(late), 23 * 3600);
+        assert_eq!(
+            berlin.start_of(OffsetDateTime::UNIX_EPOCH.date()),
+            OffsetDateTime::UNIX_EPOCH - Duration::hours(2)
+        );
+
+        let midnight = OffsetDateTime::UNIX_EPOCH;
+        let utc = TimeZone::default();
+        assert_eq!(utc.secs_until_midnight(midnight), 86_400);
+        assert_eq!(
+            utc.secs_until_midnight(midnight + Duration::seconds(1)),
+            86_399
+        );
+        assert_eq!(
+            utc.secs_until_midnight(midnight + Duration::seconds(86_399)),
+            1
+        );
+    }
+
+    #[test]
+    fn test_zone_day_boundaries() {
+        let berlin = TimeZone {
+            name: timezones::get_by_name("Europe/Berlin"),
+            utc_offset: UtcOffset::UTC,
+        };
+        let date = |month, day| Date::from_calendar_date(2024, month, day).unwrap();
+        let utc = |month, day, hour| date(month, day).with_hms(hour, 0, 0).unwrap().assume_utc();
+
+        // Summer time is +02:00, winter time +01:00
+        assert_eq!(
+            berlin.start_of(date(Month::July, 1)),
+            utc(Month::June, 30, 22)
+        );
+        assert_eq!(
+            berlin.start_of(date(Month::February, 1)),
+            utc(Month::January, 31, 23)
+        );
+        assert_eq!(berlin.today(utc(Month::July, 1, 22)), date(Month::July, 2));
+        // The clocks go forward on March 31st and back on October 27th
+        let length = |day| berlin.end_of(day) - berlin.start_of(day);
+        assert_eq!(length(date(Month::March, 31)), Duration::hours(23));
+        assert_eq!(length(date(Month::October, 27)), Duration::hours(25));
+        assert_eq!(
+            berlin.next_midnight(utc(Month::October, 27, 12)),
+            utc(Month::October, 27, 23)
+        );
+    }
+
+    #[test]
+    fn test_is_time_zone_name() {
+        assert!(is_time_zone_name("Europe/Berlin"));
+        assert!(is_time_zone_name("America/Argentina/Buenos_Aires"));
+        assert!(is_time_zone_name("Etc/GMT+5"));
+        assert!(is_time_zone_name("UTC"));
+        assert!(!is_time_zone_name(""));
+        assert!(!is_time_zone_name("Europe//Berlin"));
+        assert!(!is_time_zone_name("Europe/Berlin "));
+        assert!(!is_time_zone_name("../etc/passwd"));
+    }
+}
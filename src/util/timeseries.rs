@@ -0,0 +1,206 @@
+//! Series for charts, bucketed by the server so clients can plot them as they are.
+//!
+//! Every bucket between the first and the last one is there, even if nothing happened in it: counts are 0 then,
+//! and levels (like skill points) stay what they were in the bucket before.
+
+use serde::{Serialize, Serializer};
+use time::{Date, Duration};
+
+/// How long one bucket of a [`Series`] is.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Bucket {
+    Day,
+    /// Weeks start on Monday
+    Week,
+}
+
+/// One bucket of a [`Series`].
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SeriesPoint {
+    /// The first day in the bucket, as `YYYY-MM-DD`
+    #[serde(serialize_with = "serialize_day")]
+    pub start: Date,
+    pub value: i64,
+}
+
+/// Values over time, the oldest bucket first.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Series {
+    pub bucket: Bucket,
+    pub points: Vec<SeriesPoint>,
+}
+
+/// Days are sent as `YYYY-MM-DD`.
+pub fn serialize_day<S: Serializer>(day: &Date, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(day)
+}
+
+/// The first day of the bucket the day is in.
+#[must_use]
+pub fn bucket_start(bucket: Bucket, day: Date) -> Date {
+    match bucket {
+        Bucket::Day => day,
+        Bucket::Week => day - Duration::days(i64::from(day.weekday().number_days_from_monday())),
+    }
+}
+
+/// Sums up counts per day, given oldest first, into every bucket from the one `since` is in to the one `until` is
+/// in. Days outside of that are left out.
+#[must_use]
+pub fn sum_counts(bucket: Bucket, since: Date, until: Date, counts: &[(Date, i64)]) -> Series {
+    let points = bucket_starts(bucket, since, until)
+        .map(|start| {
+            let end = start + bucket_length(bucket);
+            SeriesPoint {
+                start,
+                value: counts
+                    .iter()
+                    .filter(|(day, _)| *day >= start.max(since) && *day < end && *day <= until)
+                    .map(|(_, count)| count)
+                    .sum(),
+            }
+        })
+        .collect();
+    Series { bucket, points }
+}
+
+/// Turns the days a level changed on, given oldest first, into its level at the end of every bucket from the one
+/// `since` is in to the one `until` is in. Changes before `since` set where it starts from, 0 without any.
+#[must_use]
+pub fn carry_levels(bucket: Bucket, since: Date, until: Date, changes: &[(Date, i64)]) -> Series {
+    let points = bucket_starts(bucket, since, until)
+        .map(|start| {
+            let last_day = (start + bucket_length(bucket) - Duration::days(1)).min(until);
+            SeriesPoint {
+                start,
+                value: changes
+                    .iter()
+                    .take_while(|(day, _)| *day <= last_day)
+                    .last()
+                    .map_or(0, |(_, level)| *level),
+            }
+        })
+        .collect();
+    Series { bucket, points }
+}
+
+/// The days from `since` to the one before `until` that aren't in `done`, oldest first. For finding the days that
+/// still have to be rolled up, gaps included.
+#[must_use]
+pub fn missing_days(since: Date, until: Date, done: &[Date]) -> Vec<Date> {
+    std::iter::successors(Some(since), |day| day.next_day())
+        .take_while(|day| *day < until)
+        .filter(|day| !done.contains(day))
+        .collect()
+}
+
+fn bucket_length(bucket: Bucket) -> Duration {
+    match bucket {
+        Bucket::Day => Duration::days(1),
+        Bucket::Week => Duration::weeks(1),
+    }
+}
+
+fn bucket_starts(bucket: Bucket, since: Date, until: Date) -> impl Iterator<Item = Date> {
+    let step = bucket_length(bucket);
+    std::iter::successors(Some(bucket_start(bucket, since)), move |start| {
+        Some(*start + step)
+    })
+    .take_while(move |start| *start <= until)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use time::{Month, Weekday};
+
+    use super::*;
+
+    fn day(day: u8) -> Date {
+        Date::from_calendar_date(2024, Month::October, day).unwrap()
+    }
+
+    #[test]
+    fn test_bucket_start() {
+        // The 7th is a Monday
+        assert_eq!(day(7).weekday(), Weekday::Monday);
+        assert_eq!(bucket_start(Bucket::Week, day(7)), day(7));
+        assert_eq!(bucket_start(Bucket::Week, day(13)), day(7));
+        assert_eq!(bucket_start(Bucket::Week, day(14)), day(14));
+        assert_eq!(bucket_start(Bucket::Day, day(13)), day(13));
+    }
+
+    #[test]
+    fn test_sum_counts() {
+        let counts = [
+            (day(6), 100),
+            (day(7), 1),
+            (day(8), 2),
+            (day(9), 3),
+            (day(15), 4),
+        ];
+
+        let daily = sum_counts(Bucket::Day, day(7), day(10), &counts);
+        let values: Vec<i64> = daily.points.iter().map(|point| point.value).collect();
+        assert_eq!(values, vec![1, 2, 3, 0]);
+        assert_eq!(daily.points[0].start, day(7));
+
+        let weekly = sum_counts(Bucket::Week, day(1), day(16), &counts);
+        assert_eq!(
+            weekly.points,
+            vec![
+                SeriesPoint {
+                    start: day(7) - Duration::weeks(1),
+                    value: 100,
+                },
+                SeriesPoint {
+                    start: day(7),
+                    value: 6,
+                },
+                SeriesPoint {
+                    start: day(14),
+                    value: 4,
+                },
+            ]
+        );
+        // The count before `since` is left out, even though it's in the same week
+        let from_tuesday = sum_counts(Bucket::Week, day(8), day(16), &counts);
+        assert_eq!(from_tuesday.points[0].start, day(7));
+        assert_eq!(from_tuesday.points[0].value, 5);
+    }
+
+    #[test]
+    fn test_missing_days() {
+        assert_eq!(
+            missing_days(day(7), day(10), &[]),
+            vec![day(7), day(8), day(9)]
+        );
+        // Gaps are filled, days outside the window and the one that isn't over yet are left alone
+        assert_eq!(
+            missing_days(day(7), day(12), &[day(5), day(7), day(9), day(12)]),
+            vec![day(8), day(10), day(11)]
+        );
+        assert_eq!(missing_days(day(7), day(9), &[day(7), day(8)]), vec![]);
+        assert_eq!(missing_days(day(9), day(7), &[]), vec![]);
+    }
+
+    #[test]
+    fn test_carry_levels() {
+        let changes = [(day(5), 10), (day(8), 20), (day(9), 25)];
+
+        let daily = carry_levels(Bucket::Day, day(7), day(10), &changes);
+        let values: Vec<i64> = daily.points.iter().map(|point| point.value).collect();
+        assert_eq!(values, vec![10, 20, 25, 25]);
+
+        let weekly = carry_levels(Bucket::Week, day(7), day(16), &changes);
+        let values: Vec<i64> = weekly.points.iter().map(|point| point.value).collect();
+        assert_eq!(values, vec![25, 25]);
+
+        let nothing_yet = carry_levels(Bucket::Day, day(1), day(2), &changes);
+        let values: Vec<i64> = nothing_yet.points.iter().map(|point| point.value).collect();
+        assert_eq!(values, vec![0, 0]);
+    }
+}
@@ -0,0 +1,181 @@
+//! Serving HTTPS without a reverse proxy in front, for small servers.
+//!
+//! When `tls.address` is set, the same routes are served there over TLS, with HTTP/2 for clients that offer it.
+//! The plain HTTP/1.1 listener on `main.address` stays, as the game can't talk to anything else.
+//! So the game keeps using plain HTTP, while browsers and API clients can use HTTPS.
+//!
+//! The certificate and key are read from PEM files, and read again when they change (checked every
+//! `tls.reload_interval_secs`), so renewing a certificate doesn't need a restart. New connections get the new
+//! certificate, open ones keep theirs. If the new files can't be used, the old certificate stays.
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use axum::{extract::ConnectInfo, Extension, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use tokio::net::TcpListener;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tracing::{debug, info, warn, Instrument};
+
+use crate::Tls;
+
+/// How long a client has for the TLS handshake before the connection is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Hands out the certificate that was loaded last.
+#[derive(Debug)]
+struct ReloadingCert {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(
+            &self.current.read().unwrap_or_else(PoisonError::into_inner),
+        ))
+    }
+}
+
+/// Reads the certificate chain and private key.
+fn load(config: &Tls) -> anyhow::Result<CertifiedKey> {
+    let open = |path: &str| {
+        File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("Failed to open {path}"))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(&config.cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to read the certificates in {}", config.cert_path))?;
+    if certs.is_empty() {
+        bail!("{} has no certificates", config.cert_path);
+    }
+    let key = rustls_pemfile::private_key(&mut open(&config.key_path)?)
+        .with_context(|| format!("Failed to read the private key in {}", config.key_path))?
+        .with_context(|| format!("{} has no private key", config.key_path))?;
+    let key = ring::sign::any_supported_type(&key)
+        .with_context(|| format!("The private key in {} isn't supported", config.key_path))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// When the certificate or key file was last changed, whichever is later.
+fn modified(config: &Tls) -> Option<SystemTime> {
+    [&config.cert_path, &config.key_path]
+        .into_iter()
+        .filter_map(|path| Path::new(path).metadata().ok()?.modified().ok())
+        .max()
+}
+
+/// Loads the certificate again whenever its files change, until the server stops.
+async fn reload(config: Tls, cert: Arc<ReloadingCert>) {
+    let mut loaded = modified(&config);
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(config.reload_interval_secs.max(1)));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        let changed = modified(&config);
+        if changed == loaded {
+            continue;
+        }
+        match load(&config) {
+            Ok(new) => {
+                *cert.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(new);
+                loaded = changed;
+                info!("Reloaded the TLS certificate from {}", config.cert_path);
+            }
+            // Likely caught halfway through being replaced, it's tried again next time
+            Err(e) => warn!("Failed to reload the TLS certificate, keeping the old one: {e:?}"),
+        }
+    }
+}
+
+/// Serves the routes over HTTPS on `tls.address` until the server stops.
+///
+/// # Errors
+/// Fails if the certificate can't be loaded or the address can't be bound.
+pub(crate) async fn serve(config: Tls, app: Router) -> anyhow::Result<()> {
+    let Some(address) = config.address.clone() else {
+        return Ok(());
+    };
+
+    let cert = Arc::new(ReloadingCert {
+        current: RwLock::new(Arc::new(load(&config)?)),
+    });
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .context("The TLS defaults should be supported by ring")?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::clone(&cert) as Arc<dyn ResolvesServerCert>);
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+    let listener = TcpListener::bind(&address)
+        .await
+        .with_context(|| format!("Failed to listen for HTTPS on {address}"))?;
+    info!("Serving HTTPS on {address}");
+    tokio::spawn(reload(config, cert).in_current_span());
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // Usually out of file descriptors, which takes a moment to get better
+                warn!("Failed to accept an HTTPS connection: {e}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        // Like `into_make_service_with_connect_info` does for the plain listener
+        let app = app.clone().layer(Extension(ConnectInfo(peer)));
+
+        tokio::spawn(
+            async move {
+                let stream =
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(e)) => {
+                            debug!("TLS handshake with {peer} failed: {e}");
+                            return;
+                        }
+                        Err(_) => {
+                            debug!("TLS handshake with {peer} timed out");
+                            return;
+                        }
+                    };
+                if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection_with_upgrades(
+                        TokioIo::new(stream),
+                        TowerToHyperService::new(app),
+                    )
+                    .await
+                {
+                    debug!("HTTPS connection with {peer} failed: {e}");
+                }
+            }
+            .in_current_span(),
+        );
+    }
+}
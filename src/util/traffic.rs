@@ -0,0 +1,184 @@
+//! Checks that the traffic a ride claims to have had adds up, like the original server's "force piece count"
+//! checks did.
+//!
+//! The game generates a song's traffic from the song itself, so every ride of a song in the same league and with the
+//! same character reports the same `density`, the number of blocks on the track. Rides are checked against the
+//! density reported most often (see [`TrafficDensity`]), once enough rides reported it, and against how many blocks a
+//! song of that length can have at all. What the `xstats` entries mean depends on the character, so only the ones
+//! configured as block counts (see [`TrafficRules::block_count_xstats`]) are compared to the density, since blocks the
+//! player did something with can't be more than there are on the track.
+//!
+//! Rides that don't add up are flagged (see [`crate::models::traffic_flags`]) for moderators to look at under
+//! `/api/admin/traffic`, their scores are saved anyway. The rules come from the `traffic_checks` section of the config.
+
+use serde::Deserialize;
+
+use crate::models::traffic_densities::TrafficDensity;
+
+/// How far (in percent) a ride's traffic may be off, since the same song can be analyzed slightly differently
+/// depending on the file.
+const TOLERANCE_PERCENT: i64 = 10;
+
+/// What's wrong with a ride's traffic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrafficProblem {
+    /// More blocks than fit into a song of that length
+    TooDense,
+    /// An `xstats` entry that counts blocks counts more than there are
+    TooManyBlocks,
+    /// The density is far off from what others reported for the song
+    OffConsensus,
+}
+
+impl TrafficProblem {
+    /// How the problem is stored in the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::TooDense => "tooDense",
+            Self::TooManyBlocks => "tooManyBlocks",
+            Self::OffConsensus => "offConsensus",
+        }
+    }
+}
+
+/// Rules for the checks, configured in the `traffic_checks` section of the config.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TrafficRules {
+    /// Whether rides are checked and densities recorded
+    pub enabled: bool,
+    /// Most blocks a song can have per second of its length
+    pub max_blocks_per_second: f64,
+    /// Indexes of the `xstats` entries that count blocks, none are compared by default
+    pub block_count_xstats: Vec<usize>,
+}
+
+impl Default for TrafficRules {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            // Even the busiest songs stay well below this
+            max_blocks_per_second: 10.0,
+            block_count_xstats: Vec::new(),
+        }
+    }
+}
+
+impl TrafficRules {
+    /// Checks the traffic of a ride against these rules and the song's consensus, if there is one.
+    ///
+    /// # Arguments
+    /// * `song_length` - In centiseconds, like the game sends it.
+    ///
+    /// # Returns
+    /// The first problem found, `None` if the traffic adds up.
+    #[must_use]
+    pub fn check(
+        &self,
+        density: i32,
+        xstats: &[i32],
+        song_length: i32,
+        consensus: Option<&TrafficDensity>,
+    ) -> Option<TrafficProblem> {
+        let seconds = f64::from(song_length.max(1)) / 100.0;
+        if f64::from(density) / seconds > self.max_blocks_per_second {
+            return Some(TrafficProblem::TooDense);
+        }
+
+        if self
+            .block_count_xstats
+            .iter()
+            .filter_map(|&index| xstats.get(index))
+            .any(|&blocks| exceeds(i64::from(blocks), i64::from(density)))
+        {
+            return Some(TrafficProblem::TooManyBlocks);
+        }
+
+        let expected = consensus.filter(|consensus| consensus.is_trusted())?;
+        let difference = (i64::from(density) - i64::from(expected.density)).abs();
+        if difference * 100 > i64::from(expected.density) * TOLERANCE_PERCENT {
+            return Some(TrafficProblem::OffConsensus);
+        }
+
+        None
+    }
+}
+
+/// Whether `value` is more than the tolerance above `limit`.
+const fn exceeds(value: i64, limit: i64) -> bool {
+    value * 100 > limit * (100 + TOLERANCE_PERCENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::util::game_types::{Character, League};
+
+    fn consensus(density: i32, submissions: i32) -> TrafficDensity {
+        TrafficDensity {
+            song_id: 1,
+            league: League::Elite,
+            vehicle: Character::Eraser,
+            density,
+            submissions,
+            last_submitted_at: OffsetDateTime::UNIX_EPOCH,
+        }
+    }
+
+    #[test]
+    fn test_check_density_for_length() {
+        let rules = TrafficRules::default();
+        // 600 blocks in a minute
+        assert_eq!(rules.check(600, &[], 6000, None), None);
+        assert_eq!(
+            rules.check(601, &[], 6000, None),
+            Some(TrafficProblem::TooDense)
+        );
+    }
+
+    #[test]
+    fn test_check_block_counts() {
+        let rules = TrafficRules {
+            block_count_xstats: vec![0, 1, 5],
+            ..TrafficRules::default()
+        };
+        assert_eq!(rules.check(500, &[400, 100, 0], 18_000, None), None);
+        // Within the tolerance
+        assert_eq!(rules.check(500, &[550], 18_000, None), None);
+        assert_eq!(
+            rules.check(500, &[400, 551], 18_000, None),
+            Some(TrafficProblem::TooManyBlocks)
+        );
+        // Entries that don't count blocks aren't compared
+        assert_eq!(rules.check(500, &[400, 100, 9000], 18_000, None), None);
+        assert_eq!(
+            TrafficRules::default().check(500, &[400, 551], 18_000, None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_check_consensus() {
+        let rules = TrafficRules::default();
+        assert_eq!(
+            rules.check(550, &[], 18_000, Some(&consensus(500, 3))),
+            None
+        );
+        assert_eq!(
+            rules.check(551, &[], 18_000, Some(&consensus(500, 3))),
+            Some(TrafficProblem::OffConsensus)
+        );
+        assert_eq!(
+            rules.check(449, &[], 18_000, Some(&consensus(500, 3))),
+            Some(TrafficProblem::OffConsensus)
+        );
+        // Not reported often enough to go by
+        assert_eq!(
+            rules.check(900, &[], 18_000, Some(&consensus(500, 2))),
+            None
+        );
+    }
+}